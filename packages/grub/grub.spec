@@ -122,7 +122,8 @@ grub2-mkimage \
 %else
   efi_gop \
 %endif
-  configfile echo ext2 gptprio linux normal part_gpt reboot sleep
+  configfile echo ext2 gptprio linux loadenv normal part_gpt reboot regexp \
+  search_part_label sleep test
 
 %if %{_cross_arch} == x86_64
 install -m 0644 ./grub-core/boot.img \
@@ -12,6 +12,7 @@ Requires: %{_cross_os}containerd
 Source10: host-containerd.service
 Source11: host-containerd-tmpfiles.conf
 Source12: host-containerd-config.toml
+Source13: host-containers-registry-toml
 
 %description
 %{summary}.
@@ -37,6 +38,9 @@ install -p -m 0644 %{S:11} %{buildroot}%{_cross_tmpfilesdir}/host-containerd.con
 install -d %{buildroot}%{_cross_factorydir}%{_cross_sysconfdir}/host-containerd
 install -p -m 0644 %{S:12} %{buildroot}%{_cross_factorydir}%{_cross_sysconfdir}/host-containerd/config.toml
 
+install -d %{buildroot}%{_cross_templatedir}
+install -p -m 0644 %{S:13} %{buildroot}%{_cross_templatedir}
+
 %cross_scan_attribution go-vendor vendor
 
 %files
@@ -45,5 +49,6 @@ install -p -m 0644 %{S:12} %{buildroot}%{_cross_factorydir}%{_cross_sysconfdir}/
 %{_cross_unitdir}/host-containerd.service
 %{_cross_tmpfilesdir}/host-containerd.conf
 %{_cross_factorydir}%{_cross_sysconfdir}/host-containerd/config.toml
+%{_cross_templatedir}/host-containers-registry-toml
 
 %changelog
@@ -9,6 +9,8 @@ URL: https://www.kernel.org/
 # Use latest-srpm-url.sh to get this.
 Source0: https://cdn.amazonlinux.com/blobstore/2e1a86879ed805e227d81f815fcc9f7575ae98a2fd6573ca71c9be2776c0637d/kernel-5.4.20-12.75.amzn2.src.rpm
 Source100: config-bottlerocket
+# Variant-specific options, merged if the file for the variant being built exists.
+Source101: config-aws-dev
 Patch0001: 0001-lustrefsx-Disable-Werror-stringop-overflow.patch
 BuildRequires: bc
 BuildRequires: elfutils-devel
@@ -51,9 +53,11 @@ for patch in ../*.patch; do
 done
 # Patches listed in this spec (Patch0001...)
 %autopatch -p1
+variant_config="%{_sourcedir}/config-${VARIANT}"
+[ -f "${variant_config}" ] || variant_config=""
 KCONFIG_CONFIG="arch/%{_cross_karch}/configs/%{_cross_vendor}_defconfig" \
     ARCH="%{_cross_karch}" \
-    scripts/kconfig/merge_config.sh ../config-%{_cross_arch} %{SOURCE100}
+    scripts/kconfig/merge_config.sh ../config-%{_cross_arch} %{SOURCE100} ${variant_config}
 rm -f ../config-%{_cross_arch} ../*.patch
 
 %global kmake \
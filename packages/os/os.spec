@@ -14,6 +14,13 @@ Source3: eni-max-pods
 
 Source4: root.json
 Source5: updog-toml
+Source6: pki-trusted-certs
+Source7: proxy-env
+Source8: update-reporter-toml
+Source9: updater-toml
+Source10: admin-ssh-authorized-keys
+Source11: oci-defaults-json
+Source12: static-pods-json
 
 # 1xx sources: systemd units
 Source100: apiserver.service
@@ -24,6 +31,11 @@ Source105: settings-applier.service
 Source106: migrator.service
 Source107: host-containers@.service
 Source110: mark-successful-boot.service
+Source111: generate-net-config.service
+Source112: update-reporter.service
+Source113: updog-auto.service
+Source114: updog-auto.timer
+Source115: bootstrap-commands.service
 
 # 2xx sources: tmpfilesd configs
 Source200: migration-tmpfiles.conf
@@ -99,6 +111,42 @@ Requires: %{_cross_os}host-ctr
 %description -n %{_cross_os}host-containers
 %{summary}.
 
+%package -n %{_cross_os}bootstrap-commands
+Summary: Runs commands from settings once before the orchestrator agent starts
+Requires: %{_cross_os}apiserver = %{version}-%{release}
+Requires: %{_cross_os}host-ctr
+%description -n %{_cross_os}bootstrap-commands
+%{summary}.
+
+%package -n %{_cross_os}certdog
+Summary: Builds the system trust store from settings
+Requires: %{_cross_os}ca-certificates
+%description -n %{_cross_os}certdog
+%{summary}.
+
+%package -n %{_cross_os}corndog
+Summary: Applies kernel settings from the API
+Requires: %{_cross_os}apiserver = %{version}-%{release}
+%description -n %{_cross_os}corndog
+%{summary}.
+
+%package -n %{_cross_os}kennel
+Summary: Writes static pods from settings to kubelet's static pod directory
+Requires: %{_cross_os}apiserver = %{version}-%{release}
+%description -n %{_cross_os}kennel
+%{summary}.
+
+%package -n %{_cross_os}cert-rotator
+Summary: Removes kubelet's serving certificates so it gets new ones
+%description -n %{_cross_os}cert-rotator
+%{summary}.
+
+%package -n %{_cross_os}shimpei
+Summary: Adds the OCI defaults from settings to containers created by containerd
+Requires: %{_cross_os}runc
+%description -n %{_cross_os}shimpei
+%{summary}.
+
 %package -n %{_cross_os}storewolf
 Summary: Data store creator
 Requires: %{_cross_os}apiserver = %{version}-%{release}
@@ -130,6 +178,11 @@ Summary: Bottlerocket updater CLI
 %description -n %{_cross_os}updog
 not much what's up with you
 
+%package -n %{_cross_os}update-reporter
+Summary: Bottlerocket opt-in update health reporter
+%description -n %{_cross_os}update-reporter
+%{summary}.
+
 %package -n %{_cross_os}logdog
 Summary: Bottlerocket log extractor
 %description -n %{_cross_os}logdog
@@ -162,11 +215,18 @@ mkdir bin
     -p thar-be-settings \
     -p servicedog \
     -p host-containers \
+    -p bootstrap-commands \
+    -p certdog \
+    -p corndog \
+    -p kennel \
+    -p cert-rotator \
+    -p shimpei \
     -p storewolf \
     -p settings-committer \
     -p migrator \
     -p signpost \
     -p updog \
+    -p update-reporter \
     -p logdog \
     -p growpart \
     -p laika \
@@ -186,10 +246,11 @@ install -d %{buildroot}%{_cross_bindir}
 for p in \
   apiserver \
   early-boot-config netdog sundog schnauzer pluto bork \
-  thar-be-settings servicedog host-containers \
+  thar-be-settings servicedog host-containers bootstrap-commands \
+  certdog corndog kennel cert-rotator shimpei \
   storewolf settings-committer \
   migrator \
-  signpost updog logdog;
+  signpost updog update-reporter logdog;
 do
   install -p -m 0755 ${HOME}/.cache/%{__cargo_target}/release/${p} %{buildroot}%{_cross_bindir}
 done
@@ -231,17 +292,20 @@ install -d %{buildroot}%{_cross_datadir}/updog
 install -p -m 0644 %{S:4} %{buildroot}%{_cross_datadir}/updog
 
 install -d %{buildroot}%{_cross_templatedir}
-install -p -m 0644 %{S:5} %{buildroot}%{_cross_templatedir}
+install -p -m 0644 %{S:5} %{S:6} %{S:7} %{S:8} %{S:9} %{S:10} %{S:11} %{S:12} %{buildroot}%{_cross_templatedir}
 
 install -d %{buildroot}%{_cross_unitdir}
 install -p -m 0644 \
   %{S:100} %{S:101} %{S:102} %{S:103} %{S:105} \
-  %{S:106} %{S:107} %{S:110} \
+  %{S:106} %{S:107} %{S:110} %{S:111} %{S:112} \
+  %{S:113} %{S:114} %{S:115} \
   %{buildroot}%{_cross_unitdir}
 
 install -d %{buildroot}%{_cross_tmpfilesdir}
 install -p -m 0644 %{S:200} %{buildroot}%{_cross_tmpfilesdir}/migration.conf
 install -p -m 0644 %{S:201} %{buildroot}%{_cross_tmpfilesdir}/host-containers.conf
+%dir %{_cross_templatedir}
+%{_cross_templatedir}/admin-ssh-authorized-keys
 
 %cross_scan_attribution --clarify %{_builddir}/sources/clarify.toml \
     cargo --offline --locked %{_builddir}/sources/Cargo.toml
@@ -264,6 +328,9 @@ install -p -m 0644 %{S:201} %{buildroot}%{_cross_tmpfilesdir}/host-containers.co
 
 %files -n %{_cross_os}netdog
 %{_cross_bindir}/netdog
+%{_cross_unitdir}/generate-net-config.service
+%dir %{_cross_templatedir}
+%{_cross_templatedir}/proxy-env
 
 %files -n %{_cross_os}sundog
 %{_cross_bindir}/sundog
@@ -292,6 +359,31 @@ install -p -m 0644 %{S:201} %{buildroot}%{_cross_tmpfilesdir}/host-containers.co
 %{_cross_unitdir}/host-containers@.service
 %{_cross_tmpfilesdir}/host-containers.conf
 
+%files -n %{_cross_os}bootstrap-commands
+%{_cross_bindir}/bootstrap-commands
+%{_cross_unitdir}/bootstrap-commands.service
+
+%files -n %{_cross_os}certdog
+%{_cross_bindir}/certdog
+%dir %{_cross_templatedir}
+%{_cross_templatedir}/pki-trusted-certs
+
+%files -n %{_cross_os}corndog
+%{_cross_bindir}/corndog
+
+%files -n %{_cross_os}kennel
+%{_cross_bindir}/kennel
+%dir %{_cross_templatedir}
+%{_cross_templatedir}/static-pods-json
+
+%files -n %{_cross_os}cert-rotator
+%{_cross_bindir}/cert-rotator
+
+%files -n %{_cross_os}shimpei
+%{_cross_bindir}/shimpei
+%dir %{_cross_templatedir}
+%{_cross_templatedir}/oci-defaults-json
+
 %files -n %{_cross_os}storewolf
 %{_cross_bindir}/storewolf
 %{_cross_unitdir}/storewolf.service
@@ -317,8 +409,17 @@ install -p -m 0644 %{S:201} %{buildroot}%{_cross_tmpfilesdir}/host-containers.co
 %files -n %{_cross_os}updog
 %{_cross_bindir}/updog
 %{_cross_datadir}/updog
+%{_cross_unitdir}/updog-auto.service
+%{_cross_unitdir}/updog-auto.timer
 %dir %{_cross_templatedir}
 %{_cross_templatedir}/updog-toml
+%{_cross_templatedir}/updater-toml
+
+%files -n %{_cross_os}update-reporter
+%{_cross_bindir}/update-reporter
+%{_cross_unitdir}/update-reporter.service
+%dir %{_cross_templatedir}
+%{_cross_templatedir}/update-reporter-toml
 
 %files -n %{_cross_os}logdog
 %{_cross_bindir}/logdog
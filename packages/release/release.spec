@@ -52,11 +52,18 @@ Requires: %{_cross_os}sundog
 Requires: %{_cross_os}pluto
 Requires: %{_cross_os}storewolf
 Requires: %{_cross_os}host-containers
+Requires: %{_cross_os}bootstrap-commands
+Requires: %{_cross_os}certdog
+Requires: %{_cross_os}corndog
+Requires: %{_cross_os}kennel
+Requires: %{_cross_os}cert-rotator
+Requires: %{_cross_os}shimpei
 Requires: %{_cross_os}settings-committer
 Requires: %{_cross_os}systemd
 Requires: %{_cross_os}thar-be-settings
 Requires: %{_cross_os}migration
 Requires: %{_cross_os}updog
+Requires: %{_cross_os}update-reporter
 Requires: %{_cross_os}logdog
 Requires: %{_cross_os}util-linux
 Requires: %{_cross_os}preinit
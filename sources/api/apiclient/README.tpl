@@ -19,6 +19,11 @@ Specify the data after `-d` or `--data`.
 
 To see verbose response data, including the HTTP status code, use `-v` or `--verbose`.
 
+If a commit changes settings that only take effect after a reboot, apiclient prints a note saying so to stderr.
+
+`apiclient logs` shows the recent logs of a host container, with `--host-container NAME`, or a system service, with `--service NAME`, through the `/logs` API.
+`-n` or `--lines` says how many lines to show, and `-f` or `--follow` keeps showing new ones as they're logged until apiclient is interrupted.
+
 ### Example usage
 
 Getting settings:
@@ -42,6 +47,12 @@ apiclient -m GET -u /tx
 
 (You can group changes into transactions by adding a parameter like `?tx=FOO` to the calls above.)
 
+Following the admin container's logs:
+
+```
+apiclient logs --host-container admin --follow
+```
+
 ## apiclient library
 
 {{readme}}
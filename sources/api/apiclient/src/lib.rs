@@ -5,7 +5,9 @@
 //!
 //! The `raw_request` method takes care of the basics of making an HTTP request on a Unix-domain
 //! socket, and requires you to specify the socket path, the URI (including query string), the
-//! HTTP method, and any request body data.
+//! HTTP method, and any request body data.  `raw_request_with_headers` does the same, and also
+//! returns the response headers.  `stream_request` writes the response body out as it arrives,
+//! for responses that keep going, like followed logs.
 //!
 //! In the future, we intend to add methods that understand the Bottlerocket API and help more with common
 //! types of requests.
@@ -18,6 +20,7 @@ use futures::TryStreamExt;
 use hyper::{header, Body, Client, Request};
 use hyper_unix_connector::{UnixClient, Uri};
 use snafu::{ensure, ResultExt};
+use std::io::Write;
 use std::path::Path;
 use tokio::runtime::Runtime;
 
@@ -50,11 +53,18 @@ mod error {
 
         #[snafu(display("Response was not UTF-8: {}", source))]
         NonUtf8Response { source: std::string::FromUtf8Error },
+
+        #[snafu(display("Failed to write response: {}", source))]
+        ResponseWrite { source: io::Error },
     }
 }
 pub use error::Error;
 pub type Result<T> = std::result::Result<T, error::Error>;
 
+/// The API server sets this response header to "true" when a commit changed settings that only
+/// take effect after a reboot.
+pub const REBOOT_REQUIRED_HEADER: &str = "Bottlerocket-Reboot-Required";
+
 /// Makes an HTTP request to a Unix-domain socket.
 ///
 /// The socket is specified as a path, for example "/tmp/api.sock".
@@ -80,9 +90,101 @@ where
     P: AsRef<Path>,
     S1: AsRef<str>,
     S2: AsRef<str>,
+{
+    let (status, _headers, body) = raw_request_with_headers(socket_path, uri, method, data)?;
+    Ok((status, body))
+}
+
+/// Makes an HTTP request to a Unix-domain socket, like `raw_request`, but also returns the
+/// headers of the response, for example to check `REBOOT_REQUIRED_HEADER`.
+pub fn raw_request_with_headers<P, S1, S2>(
+    socket_path: P,
+    uri: S1,
+    method: S2,
+    data: Option<String>,
+) -> Result<(http::StatusCode, http::HeaderMap, String)>
+where
+    P: AsRef<Path>,
+    S1: AsRef<str>,
+    S2: AsRef<str>,
+{
+    let method = method.as_ref();
+    let (mut runtime, uri, head, body_stream) = send_request(socket_path, uri, method, data)?;
+    let body = read_body(&mut runtime, body_stream)?;
+
+    // Error if the response status is in not in the 2xx range.
+    ensure!(
+        head.status.is_success(),
+        error::ResponseStatus {
+            method,
+            code: head.status,
+            uri,
+            body,
+        }
+    );
+
+    Ok((head.status, head.headers, body))
+}
+
+/// Makes an HTTP request to a Unix-domain socket, like `raw_request`, but writes the response body
+/// to `output` as each piece arrives instead of returning it, so a response that keeps streaming,
+/// like followed logs, can be shown as it goes.  The body is written as it is, so it doesn't have
+/// to be UTF-8.
+///
+/// Returns the status code of the response.  Unlike `raw_request`, a response outside the 2xx
+/// range is an error, with its body in the error rather than written to `output`.
+pub fn stream_request<P, S1, S2, W>(
+    socket_path: P,
+    uri: S1,
+    method: S2,
+    data: Option<String>,
+    output: &mut W,
+) -> Result<http::StatusCode>
+where
+    P: AsRef<Path>,
+    S1: AsRef<str>,
+    S2: AsRef<str>,
+    W: Write,
 {
     let method = method.as_ref();
+    let (mut runtime, uri, head, mut body_stream) = send_request(socket_path, uri, method, data)?;
 
+    if !head.status.is_success() {
+        let body = read_body(&mut runtime, body_stream)?;
+        return error::ResponseStatus {
+            method,
+            code: head.status,
+            uri,
+            body,
+        }
+        .fail();
+    }
+
+    runtime.block_on(async {
+        while let Some(bytes) = body_stream
+            .try_next()
+            .await
+            .context(error::ResponseBodyRead)?
+        {
+            output.write_all(&bytes).context(error::ResponseWrite)?;
+            output.flush().context(error::ResponseWrite)?;
+        }
+        Ok(head.status)
+    })
+}
+
+/// Sends a request, returning the runtime it was sent with, the full URI, and the head of the
+/// response, with the body still to be read from the returned stream.
+fn send_request<P, S>(
+    socket_path: P,
+    uri: S,
+    method: &str,
+    data: Option<String>,
+) -> Result<(Runtime, hyper::Uri, http::response::Parts, Body)>
+where
+    P: AsRef<Path>,
+    S: AsRef<str>,
+{
     let request_data = if let Some(data) = data {
         Body::from(data)
     } else {
@@ -111,25 +213,15 @@ where
         .block_on(client.request(request))
         .context(error::RequestSend)?
         .into_parts();
+    Ok((runtime, uri, head, body_stream))
+}
 
-    // Wait on the second future (the streaming body) and concatenate all the pieces together so we
-    // have a single response body.  We make sure the result is a string; we assume that we're not
-    // handling binary data.
+/// Waits on the streaming body and concatenates all the pieces together so we have a single
+/// response body.  We make sure the result is a string; we assume that we're not handling binary
+/// data.
+fn read_body(runtime: &mut Runtime, body_stream: Body) -> Result<String> {
     let body_bytes: Vec<u8> = runtime
         .block_on(body_stream.map_ok(|bytes| bytes.to_vec()).try_concat())
         .context(error::ResponseBodyRead)?;
-    let body = String::from_utf8(body_bytes).context(error::NonUtf8Response)?;
-
-    // Error if the response status is in not in the 2xx range.
-    ensure!(
-        head.status.is_success(),
-        error::ResponseStatus {
-            method,
-            code: head.status,
-            uri,
-            body,
-        }
-    );
-
-    Ok((head.status, body))
+    String::from_utf8(body_bytes).context(error::NonUtf8Response)
 }
@@ -1,4 +1,5 @@
 use std::env;
+use std::io;
 use std::process;
 
 const DEFAULT_API_SOCKET: &str = "/run/api.sock";
@@ -10,6 +11,8 @@ struct Args {
     method: String,
     uri: String,
     data: Option<String>,
+    // Write the response as it arrives, rather than all at once, for responses that keep going.
+    stream: bool,
 }
 
 /// Informs the user about proper usage of the program and exits.
@@ -23,9 +26,17 @@ fn usage() -> ! {
             [ (-s | --socket-path) PATH ]
             [ -v | --verbose ... ]
 
+       {} logs
+            (--host-container NAME | --service NAME)
+            [ (-n | --lines) LINES ]
+            [ (-f | --follow) ]
+            [ (-s | --socket-path) PATH ]
+
     Method defaults to GET
-    Socket path defaults to {}",
-        program_name, DEFAULT_API_SOCKET
+    Socket path defaults to {}
+    logs shows a host container's or system service's recent logs, and with
+    --follow, keeps showing new ones until interrupted",
+        program_name, program_name, DEFAULT_API_SOCKET
     );
     process::exit(2);
 }
@@ -44,7 +55,11 @@ fn parse_args(args: env::Args) -> Args {
     let mut uri = None;
     let mut data = None;
 
-    let mut iter = args.skip(1);
+    let mut iter = args.skip(1).peekable();
+    if iter.peek().map(String::as_str) == Some("logs") {
+        iter.next();
+        return parse_logs_args(iter);
+    }
     while let Some(arg) = iter.next() {
         match arg.as_ref() {
             "-v" | "--verbose" => verbosity += 1,
@@ -87,14 +102,89 @@ fn parse_args(args: env::Args) -> Args {
         method: method.unwrap_or_else(|| "GET".to_string()),
         uri: uri.unwrap_or_else(|| usage()),
         data,
+        stream: false,
+    }
+}
+
+/// Parses the arguments of the logs subcommand into a request for the /logs API.
+fn parse_logs_args(mut iter: impl Iterator<Item = String>) -> Args {
+    let mut socket_path = None;
+    let mut source = None;
+    let mut lines = None;
+    let mut follow = false;
+
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--socket-path" | "-s" => {
+                socket_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --socket-path")),
+                )
+            }
+
+            "--host-container" | "--service" => {
+                if source.is_some() {
+                    usage_msg("Only one of --host-container and --service can be given");
+                }
+                let name = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg(format!("Did not give argument to {}", arg)));
+                source = Some((arg.trim_start_matches('-').to_string(), name));
+            }
+
+            "-n" | "--lines" => {
+                let value = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to -n | --lines"));
+                if value.parse::<u32>().is_err() {
+                    usage_msg(format!("Invalid number of lines '{}'", value));
+                }
+                lines = Some(value);
+            }
+
+            "-f" | "--follow" => follow = true,
+
+            _ => usage(),
+        }
+    }
+
+    let (kind, name) =
+        source.unwrap_or_else(|| usage_msg("One of --host-container and --service is required"));
+    let mut uri = format!("/logs?{}={}", kind, name);
+    if let Some(lines) = lines {
+        uri.push_str(&format!("&lines={}", lines));
+    }
+    if follow {
+        uri.push_str("&follow=true");
+    }
+
+    Args {
+        verbosity: 3,
+        socket_path: socket_path.unwrap_or_else(|| DEFAULT_API_SOCKET.to_string()),
+        method: "GET".to_string(),
+        uri,
+        data: None,
+        stream: true,
     }
 }
 
 fn run() -> Result<(), Box<dyn std::error::Error>> {
     let args = parse_args(env::args());
 
-    let (status, body) =
-        apiclient::raw_request(args.socket_path, args.uri, args.method, args.data)?;
+    if args.stream {
+        let stdout = io::stdout();
+        apiclient::stream_request(
+            args.socket_path,
+            args.uri,
+            args.method,
+            args.data,
+            &mut stdout.lock(),
+        )?;
+        return Ok(());
+    }
+
+    let (status, headers, body) =
+        apiclient::raw_request_with_headers(args.socket_path, args.uri, args.method, args.data)?;
 
     if args.verbosity > 3 {
         eprintln!("{}", status);
@@ -102,6 +192,12 @@ fn run() -> Result<(), Box<dyn std::error::Error>> {
     if !body.is_empty() {
         println!("{}", body);
     }
+    let reboot_required = headers
+        .get(apiclient::REBOOT_REQUIRED_HEADER)
+        .and_then(|v| v.to_str().ok());
+    if reboot_required == Some("true") {
+        eprintln!("Some of the committed settings take effect after the next reboot");
+    }
     Ok(())
 }
 
@@ -24,11 +24,31 @@ Pending settings can be retrieved from `/tx` to see what will change.
 Upon making a `/tx/commit` POST call, the pending transaction is made live.
 Upon making an `/tx/apply` POST call, an external settings applier tool is called to apply the changes to the system and restart services as necessary.
 There's also `/tx/commit_and_apply` to do both, which is the most common case.
+If any committed setting only takes effect after a reboot, as marked by its `reboot-required` metadata, the commit response includes a `Bottlerocket-Reboot-Required: true` header.
 
 If you don't specify a transaction, the "default" transaction is used, so you usually don't have to think about it.
 If you want to group changes into transactions yourself, you can add a `tx` parameter to the APIs mentioned above.
 For example, if you want the name "FOO", you can `PATCH` to `/settings?tx=FOO` and `POST` to `/tx/commit_and_apply?tx=FOO`.
 
+`/os` returns the running version, variant, architecture, and data store version.
+`/os/update-status` returns the partition sets, the version in each if it's known, and any update that updog has staged on the inactive set.
+A `POST` to `/os/cancel-update` asks the updog run that's writing or activating an update to cancel, like SIGTERM does; updog stops at the next point it can stop cleanly and records the run as `cancelled`.
+`/os/selinux` returns the SELinux mode the host is running in, the mode from `settings.kernel.selinux` that it boots in next, and whether the variant allows permissive mode; on variants that don't, setting it to `permissive` is refused.
+If no run holds updog's lock, it returns 422.
+
+`/logs` returns the recent logs of a host container, with `host-container=NAME`, or of a system service, with `service=NAME` and the name of its systemd unit, like `/logs?service=containerd`.
+`lines` says how many of the most recent lines to return, and defaults to 100.
+With `follow=true`, new lines are streamed as they're logged until the client disconnects, so operators can debug a host container without getting a shell on the host.
+
+`/bootstrap-commands` returns the status of each bootstrap command that has run, by name: whether it succeeded, its exit code, and when it finished.
+The status is recorded by bootstrap-commands with a `PATCH` to the same path; it's kept in the data store, outside of settings, so it's written directly rather than through a transaction.
+
+A `POST` to `/actions/rotate-certificates` rotates the host's certificates, like kubelet's serving certificates, without replacing the host.
+It runs thar-be-settings in the background for the services named with a `-certificates` suffix, whose restart commands remove the certificates they manage and restart the services that use them, so those services get new ones.
+If no services on the host rotate certificates, it returns 422.
+
+Commits, applies, cancelled updates, and certificate rotations are recorded in the journal as structured audit entries, with the transaction or keys they were for and whether they succeeded; see the [audit](../../audit) library for the fields.
+
 Requests are directed by `server::router`.
 `server::controller` maps requests into our data model.
 
@@ -1,20 +1,37 @@
 //! The controller module maps between the datastore and the API interface, similar to the
 //! controller in the MVC model.
 
-use bottlerocket_release::BottlerocketRelease;
+use bottlerocket_release::{VersionInfo, DEFAULT_RELEASE_FILE};
+use chrono::{DateTime, Utc};
+use futures::channel::mpsc;
+use futures::stream::{Stream, StreamExt};
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use semver::Version;
 use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use signpost::{PartitionSet, State};
 use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::{HashMap, HashSet};
-use std::io::Write;
-use std::process::{Command, Stdio};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::{Child, Command, Stdio};
+use std::task::{Context, Poll};
+use std::thread;
 
 use crate::datastore::deserialization::{from_map, from_map_with_prefix};
-use crate::datastore::serialization::to_pairs;
+use crate::datastore::serialization::{to_pairs, to_pairs_with_prefix};
 use crate::datastore::{
     deserialize_scalar, Committed, DataStore, Key, KeyType, ScalarError, Value,
 };
 use crate::server::error::{self, Result};
-use model::{ConfigurationFiles, Services, Settings};
+use model::modeled_types::SelinuxMode;
+use model::{BootstrapCommandsStatus, ConfigurationFiles, Services, Settings};
 
 /// List the open transactions from the data store.
 pub(crate) fn list_transactions<D>(datastore: &D) -> Result<HashSet<String>>
@@ -74,10 +91,354 @@ pub(crate) fn get_settings_prefix<D: DataStore, S: AsRef<str>>(
         .unwrap_or_else(|| Ok(Settings::default()))
 }
 
-// The "os" APIs don't deal with the data store at all, they just read a release field.
-/// Build a BottlerocketRelease using the bottlerocket-release library.
-pub(crate) fn get_os_info() -> Result<BottlerocketRelease> {
-    BottlerocketRelease::new().context(error::ReleaseData)
+// The "os" APIs don't deal with the data store's contents, they just read release fields and the
+// version the data store is at.
+/// Build a VersionInfo using the bottlerocket-release library.  `datastore_dir` is the directory
+/// holding the versioned data stores, not the data store itself.
+pub(crate) fn get_os_info<P: AsRef<Path>>(datastore_dir: P) -> Result<VersionInfo> {
+    VersionInfo::from_paths(DEFAULT_RELEASE_FILE, datastore_dir).context(error::ReleaseData)
+}
+
+/// Where updog records the update it has written to the inactive partition set.
+const PREPARED_UPDATE_FILE: &str = "/var/lib/updog/prepared.json";
+
+/// The state of the partition sets and of any update staged on the inactive one.
+#[derive(Debug, Serialize)]
+pub(crate) struct UpdateStatus {
+    active_partition: PartitionStatus,
+    inactive_partition: PartitionStatus,
+    /// The update written to the inactive partition set, if it's still there.
+    staged_update: Option<StagedUpdate>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct PartitionStatus {
+    boot: PathBuf,
+    root: PathBuf,
+    hash: PathBuf,
+    /// The version of Bottlerocket in the partition set, if it's known.
+    version: Option<Version>,
+}
+
+impl PartitionStatus {
+    fn new(set: &PartitionSet, version: Option<Version>) -> Self {
+        Self {
+            boot: set.boot.clone(),
+            root: set.root.clone(),
+            hash: set.hash.clone(),
+            version,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct StagedUpdate {
+    variant: String,
+    version: Version,
+    /// When the update was written.
+    time: DateTime<Utc>,
+    /// The update won't be activated before this time.
+    not_before: Option<DateTime<Utc>>,
+    /// Whether the update is set to boot next, i.e. it's waiting for a reboot.
+    activated: bool,
+}
+
+/// The parts of updog's record of a prepared update that we report.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct PreparedUpdate {
+    variant: String,
+    version: Version,
+    root: PathBuf,
+    time: DateTime<Utc>,
+    #[serde(default)]
+    not_before: Option<DateTime<Utc>>,
+}
+
+/// Build an UpdateStatus from the partition table, as signpost sees it, and updog's record of the
+/// update it prepared.  `running` is the version of the active partition set.
+pub(crate) fn get_update_status(running: Version) -> Result<UpdateStatus> {
+    let state = State::load().context(error::PartitionState)?;
+    let prepared = read_prepared_update(PREPARED_UPDATE_FILE)?;
+    let staged_update = staged_update(
+        prepared,
+        &state.inactive_set().root,
+        state.inactive_valid(),
+        state.upgrade_pending(),
+    );
+    let inactive_version = staged_update.as_ref().map(|update| update.version.clone());
+    Ok(UpdateStatus {
+        active_partition: PartitionStatus::new(state.active_set(), Some(running)),
+        inactive_partition: PartitionStatus::new(state.inactive_set(), inactive_version),
+        staged_update,
+    })
+}
+
+/// The lock updog holds while it writes or activates an update, which holds its process ID.
+const UPDOG_LOCK_FILE: &str = "/run/updog/lock";
+
+/// Asks the updog run that's writing or activating an update to cancel, the way SIGTERM does.
+/// updog stops at the next point it can stop cleanly and records the run as cancelled; this
+/// doesn't wait for it to stop.
+pub(crate) fn cancel_update() -> Result<()> {
+    signal_update_run(UPDOG_LOCK_FILE)
+}
+
+/// Sends SIGTERM to the process holding the updog lock at `path`.  The process ID in the file only
+/// counts while the lock is held, since it's left behind when updog exits.
+fn signal_update_run<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return error::NoUpdateRunning.fail(),
+        Err(e) => return Err(e).context(error::UpdateLockRead { path }),
+    };
+    match flock(file.as_raw_fd(), FlockArg::LockSharedNonblock) {
+        Ok(()) => return error::NoUpdateRunning.fail(),
+        Err(nix::Error::Sys(Errno::EAGAIN)) => {}
+        Err(source) => return Err(source).context(error::UpdateLock { path }),
+    }
+    let pid = fs::read_to_string(path).context(error::UpdateLockRead { path })?;
+    let pid = pid
+        .trim()
+        .parse()
+        .ok()
+        .context(error::UpdateLockPid { path })?;
+    kill(Pid::from_raw(pid), Signal::SIGTERM).context(error::UpdateSignal { pid })
+}
+
+/// Reads updog's record of a prepared update, if there is one.
+fn read_prepared_update<P: AsRef<Path>>(path: P) -> Result<Option<PreparedUpdate>> {
+    let path = path.as_ref();
+    match fs::read(path) {
+        Ok(data) => serde_json::from_slice(&data)
+            .map(Some)
+            .context(error::PreparedUpdateParse { path }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).context(error::PreparedUpdateRead { path }),
+    }
+}
+
+/// Checks whether the prepared update is still on the inactive partition set.  The record isn't
+/// removed when the partition sets swap or the update is cancelled, so it only counts if it was
+/// written to the current inactive root partition and that set is marked valid or to boot next.
+fn staged_update(
+    prepared: Option<PreparedUpdate>,
+    inactive_root: &Path,
+    inactive_valid: bool,
+    upgrade_pending: bool,
+) -> Option<StagedUpdate> {
+    let prepared = prepared?;
+    if prepared.root != inactive_root || !(inactive_valid || upgrade_pending) {
+        return None;
+    }
+    Some(StagedUpdate {
+        variant: prepared.variant,
+        version: prepared.version,
+        time: prepared.time,
+        not_before: prepared.not_before,
+        activated: upgrade_pending,
+    })
+}
+
+/// Where the kernel shows whether SELinux is enforcing, as "1", or permissive, as "0".
+const SELINUX_ENFORCE_FILE: &str = "/sys/fs/selinux/enforce";
+
+/// The SELinux mode the host is running in, and the one it boots in next.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct SelinuxStatus {
+    current: String,
+    /// The mode from settings.kernel.selinux, which takes effect on the next boot.
+    next_boot: String,
+    /// Whether this variant allows settings.kernel.selinux to be permissive.
+    permissive_allowed: bool,
+}
+
+/// Build a SelinuxStatus from the running kernel and the live settings.
+pub(crate) fn get_selinux_status<D: DataStore>(datastore: &D) -> Result<SelinuxStatus> {
+    let next_boot = get_settings(datastore, &Committed::Live)?
+        .kernel
+        .and_then(|kernel| kernel.selinux);
+    selinux_status(
+        SELINUX_ENFORCE_FILE,
+        next_boot,
+        model::SELINUX_PERMISSIVE_ALLOWED,
+    )
+}
+
+fn selinux_status<P: AsRef<Path>>(
+    enforce_file: P,
+    next_boot: Option<SelinuxMode>,
+    permissive_allowed: bool,
+) -> Result<SelinuxStatus> {
+    let path = enforce_file.as_ref();
+    let enforce = fs::read_to_string(path).context(error::SelinuxStatusRead { path })?;
+    let current = match enforce.trim() {
+        "0" => "permissive",
+        _ => "enforcing",
+    };
+    Ok(SelinuxStatus {
+        current: current.to_string(),
+        // Hosts enforce unless told otherwise.
+        next_boot: next_boot.map_or_else(|| "enforcing".to_string(), |mode| mode.to_string()),
+        permissive_allowed,
+    })
+}
+
+/// Refuses permissive SELinux on variants that don't allow it.  Any mode is valid for the model,
+/// so this is checked here, where the variant is known.
+fn check_selinux_mode(settings: &Settings, permissive_allowed: bool) -> Result<()> {
+    let mode = settings
+        .kernel
+        .as_ref()
+        .and_then(|kernel| kernel.selinux.as_ref());
+    if let Some(mode) = mode {
+        ensure!(
+            permissive_allowed || !mode.is_permissive(),
+            error::SelinuxPermissiveDisallowed
+        );
+    }
+    Ok(())
+}
+
+/// The journal reader, used to show the logs of host containers and system services.
+const JOURNALCTL: &str = "/usr/bin/journalctl";
+
+/// How many lines of logs are returned if the request doesn't say.
+pub(crate) const DEFAULT_LOG_LINES: u32 = 100;
+
+/// Whose logs to show: a host container, by its name in settings, or a system service, by its
+/// systemd unit name, like "containerd" or "kubelet.service".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum LogSource<'a> {
+    HostContainer(&'a str),
+    Service(&'a str),
+}
+
+impl LogSource<'_> {
+    /// The systemd unit whose journal has the logs.  Names are limited to the characters unit
+    /// names use, so they can't be taken as journalctl options or unit patterns.
+    fn unit(&self) -> Result<String> {
+        let name = match self {
+            LogSource::HostContainer(name) | LogSource::Service(name) => *name,
+        };
+        let valid = |c: char| c.is_ascii_alphanumeric() || "-_.@:".contains(c);
+        ensure!(
+            !name.is_empty() && !name.starts_with('-') && name.chars().all(valid),
+            error::InvalidLogName { name }
+        );
+        Ok(match self {
+            LogSource::HostContainer(name) => format!("host-containers@{}.service", name),
+            LogSource::Service(name) => (*name).to_string(),
+        })
+    }
+}
+
+/// Checks that a host container is in the live settings, so asking for the logs of one that
+/// doesn't exist is an error rather than an empty response.
+pub(crate) fn check_host_container<D: DataStore>(datastore: &D, name: &str) -> Result<()> {
+    let prefix = format!("settings.host-containers.{}.", name);
+    let keys = datastore
+        .list_populated_keys(&prefix, &Committed::Live)
+        .context(error::DataStore {
+            op: "list_populated_keys",
+        })?;
+    ensure!(!keys.is_empty(), error::MissingData { prefix });
+    Ok(())
+}
+
+/// The journalctl arguments to show the last `lines` lines of a unit's logs, and with `follow`,
+/// keep showing new ones.
+fn journalctl_args(unit: &str, lines: u32, follow: bool) -> Vec<String> {
+    let mut args = vec![
+        "--unit".to_string(),
+        unit.to_string(),
+        "--lines".to_string(),
+        lines.to_string(),
+        "--output".to_string(),
+        "short-iso".to_string(),
+        "--no-pager".to_string(),
+    ];
+    if follow {
+        args.push("--follow".to_string());
+    }
+    args
+}
+
+/// Builds the journalctl command that shows a host container's or service's logs.
+pub(crate) fn log_command(source: &LogSource<'_>, lines: u32, follow: bool) -> Result<Command> {
+    let mut command = Command::new(JOURNALCTL);
+    command.args(journalctl_args(&source.unit()?, lines, follow));
+    Ok(command)
+}
+
+/// Runs a log command and returns its output.  Log lines aren't always UTF-8, so anything else is
+/// replaced rather than failing the request.
+pub(crate) fn get_logs(mut command: Command) -> Result<String> {
+    let output = command.output().context(error::LogCommandStart)?;
+    ensure!(
+        output.status.success(),
+        error::LogCommandStatus {
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string()
+        }
+    );
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The output of a log command that keeps running, a line at a time.  Dropping it, like when the
+/// client following the logs goes away, stops the command right away, even if nothing new is
+/// logged.
+pub(crate) struct LogStream {
+    lines: mpsc::UnboundedReceiver<Vec<u8>>,
+    child: Child,
+}
+
+impl Stream for LogStream {
+    type Item = Vec<u8>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.lines.poll_next_unpin(cx)
+    }
+}
+
+impl Drop for LogStream {
+    fn drop(&mut self) {
+        // The command may have already exited, in which case there's nothing to stop.  Once it's
+        // stopped, its output closes, which ends the thread reading it.
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Starts a log command that keeps running, like one with `--follow`, and returns a stream of
+/// each line of its output.  The command runs until the stream is dropped.
+pub(crate) fn follow_logs(mut command: Command) -> Result<LogStream> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .context(error::LogCommandStart)?;
+    let stdout = child.stdout.take().context(error::LogCommandStdout)?;
+
+    let (sender, receiver) = mpsc::unbounded();
+    thread::spawn(move || {
+        let mut stdout = BufReader::new(stdout);
+        loop {
+            let mut line = Vec::new();
+            match stdout.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    if sender.unbounded_send(line).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    Ok(LogStream {
+        lines: receiver,
+        child,
+    })
 }
 
 /// Build a Services based on the data in the datastore.
@@ -108,6 +469,36 @@ pub(crate) fn get_configuration_files<D: DataStore>(datastore: &D) -> Result<Con
     })?
 }
 
+/// Build a BootstrapCommandsStatus based on the data in the datastore.  It's empty until
+/// bootstrap-commands has recorded the status of some command.
+pub(crate) fn get_bootstrap_commands_status<D: DataStore>(
+    datastore: &D,
+) -> Result<BootstrapCommandsStatus> {
+    Ok(get_prefix(
+        datastore,
+        &Committed::Live,
+        "bootstrap-commands.",
+        Some("bootstrap-commands".to_string()),
+    )?
+    .unwrap_or_default())
+}
+
+/// Records the status of bootstrap commands in the datastore.  Status isn't a setting, so it's
+/// written live rather than through a transaction.
+pub(crate) fn set_bootstrap_commands_status<D: DataStore>(
+    datastore: &mut D,
+    status: &BootstrapCommandsStatus,
+) -> Result<()> {
+    let pairs = to_pairs_with_prefix("bootstrap-commands", status).context(
+        error::DataStoreSerialization {
+            given: "BootstrapCommandsStatus",
+        },
+    )?;
+    datastore
+        .set_keys(&pairs, &Committed::Live)
+        .context(error::DataStore { op: "set_keys" })
+}
+
 /// Helper to get data from the datastore, starting with the given find_prefix, and deserialize it
 /// into the desired type.  map_prefix should be the prefix to remove if you're deserializing into
 /// a map; see docs on from_map_with_prefix.  Returns Err if we couldn't pull expected data;
@@ -235,6 +626,7 @@ pub(crate) fn set_settings<D: DataStore>(
     settings: &Settings,
     transaction: &str,
 ) -> Result<()> {
+    check_selinux_mode(settings, model::SELINUX_PERMISSIVE_ALLOWED)?;
     trace!("Serializing Settings to write to data store");
     let pairs = to_pairs(settings).context(error::DataStoreSerialization { given: "Settings" })?;
     let pending = Committed::Pending {
@@ -284,6 +676,17 @@ pub(crate) fn get_metadata_for_data_keys<D: DataStore, S: AsRef<str>>(
     Ok(result)
 }
 
+/// Checks whether any of the given data keys is marked with "reboot-required" metadata, meaning
+/// changes to it only take effect after the next boot.
+pub(crate) fn reboot_required<D: DataStore>(
+    datastore: &D,
+    data_keys: &HashSet<Key>,
+) -> Result<bool> {
+    let data_key_strs = data_keys.iter().map(|k| k.name().as_str()).collect();
+    let metadata = get_metadata_for_data_keys(datastore, "reboot-required", &data_key_strs)?;
+    Ok(metadata.values().any(|v| v.as_bool() == Some(true)))
+}
+
 /// Gets the value of a metadata key everywhere it's found in the data store.  Returns a mapping
 /// of data key to the metadata value associated with the requested key.
 pub(crate) fn get_metadata_for_all_data_keys<D: DataStore, S: AsRef<str>>(
@@ -317,11 +720,55 @@ pub(crate) fn commit_transaction<D>(datastore: &mut D, transaction: &str) -> Res
 where
     D: DataStore,
 {
+    check_image_gc_thresholds(datastore, transaction)?;
     datastore
         .commit_transaction(transaction)
         .context(error::DataStore { op: "commit" })
 }
 
+/// Image garbage collection removes images down to the low threshold once disk usage passes the
+/// high one.  Either can be changed on its own, so they're checked against each other at commit.
+const IMAGE_GC_HIGH_THRESHOLD: &str = "settings.container-runtime.gc.high-threshold-percent";
+const IMAGE_GC_LOW_THRESHOLD: &str = "settings.container-runtime.gc.low-threshold-percent";
+
+/// Refuses a commit that would leave the image garbage collection thresholds with the low one at or
+/// above the high one, which kubelet won't start with.  Variants without the settings never have
+/// the keys, so there's nothing to check.
+fn check_image_gc_thresholds<D: DataStore>(datastore: &D, transaction: &str) -> Result<()> {
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    // The value a key will have once the transaction is committed.
+    let committed_value = |name: &str| -> Result<Option<u64>> {
+        let key = Key::new(KeyType::Data, name).context(error::NewKey {
+            key_type: "data",
+            name,
+        })?;
+        let value = match datastore
+            .get_key(&key, &pending)
+            .context(error::DataStore { op: "get_key" })?
+        {
+            Some(value) => Some(value),
+            None => datastore
+                .get_key(&key, &Committed::Live)
+                .context(error::DataStore { op: "get_key" })?,
+        };
+        value
+            .map(|value| {
+                deserialize_scalar::<_, ScalarError>(&value)
+                    .context(error::InvalidData { key: name })
+            })
+            .transpose()
+    };
+
+    let high = committed_value(IMAGE_GC_HIGH_THRESHOLD)?;
+    let low = committed_value(IMAGE_GC_LOW_THRESHOLD)?;
+    if let (Some(high), Some(low)) = (high, low) {
+        ensure!(low < high, error::ImageGcThresholds { high, low });
+    }
+    Ok(())
+}
+
 /// Launches the config applier to make appropriate changes to the system based on any settings
 /// that have been committed.  Can be called after a commit, with the keys that changed in that
 /// commit, or called on its own to reset configuration state with all known keys.
@@ -374,14 +821,51 @@ where
     Ok(())
 }
 
+/// Services whose restart commands rotate certificates are named with this suffix, like
+/// "kubelet-certificates".
+const CERTIFICATE_SERVICE_SUFFIX: &str = "-certificates";
+
+/// Returns the names of the services whose restart commands rotate certificates.  It's an error if
+/// there aren't any, so a request to rotate certificates doesn't silently do nothing.
+pub(crate) fn certificate_services<D: DataStore>(datastore: &D) -> Result<HashSet<String>> {
+    let names: HashSet<String> = get_services(datastore)?
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|name| name.ends_with(CERTIFICATE_SERVICE_SUFFIX))
+        .collect();
+    ensure!(!names.is_empty(), error::NoCertificateServices);
+    Ok(names)
+}
+
+/// Launches the config applier to rewrite the configuration files of the given services and run
+/// their restart commands, which remove the certificates they manage and restart the services
+/// that use them, so those services get new certificates.
+pub(crate) fn rotate_certificates(services: &HashSet<String>) -> Result<()> {
+    let mut names: Vec<&str> = services.iter().map(|s| s.as_str()).collect();
+    names.sort();
+
+    debug!(
+        "Launching thar-be-settings to rotate certificates of {:?}",
+        names
+    );
+    Command::new("/usr/bin/thar-be-settings")
+        .arg("--services")
+        .arg(names.join(","))
+        .spawn()
+        .context(error::ConfigApplierStart)?;
+
+    // Leave config applier to run in the background; restarting services can take a while
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::datastore::memory::MemoryDataStore;
     use crate::datastore::{Committed, DataStore, Key, KeyType};
     use maplit::{hashmap, hashset};
-    use model::Service;
-    use std::convert::TryInto;
+    use model::{BootstrapCommandStatus, Service};
+    use std::convert::{TryFrom, TryInto};
 
     #[test]
     fn get_settings_works() {
@@ -475,6 +959,43 @@ mod test {
         );
     }
 
+    #[test]
+    fn certificate_services_works() {
+        let mut ds = MemoryDataStore::new();
+        // No services rotate certificates yet
+        for (key, value) in &[
+            ("services.foo.configuration-files", "[]"),
+            ("services.foo.restart-commands", "[\"echo hi\"]"),
+        ] {
+            ds.set_key(
+                &Key::new(KeyType::Data, key).unwrap(),
+                value,
+                &Committed::Live,
+            )
+            .unwrap();
+        }
+        certificate_services(&ds).unwrap_err();
+
+        for (key, value) in &[
+            ("services.foo-certificates.configuration-files", "[]"),
+            (
+                "services.foo-certificates.restart-commands",
+                "[\"echo rotated\"]",
+            ),
+        ] {
+            ds.set_key(
+                &Key::new(KeyType::Data, key).unwrap(),
+                value,
+                &Committed::Live,
+            )
+            .unwrap();
+        }
+        assert_eq!(
+            certificate_services(&ds).unwrap(),
+            hashset!("foo-certificates".to_string())
+        );
+    }
+
     #[test]
     fn set_settings_works() {
         let mut settings = Settings::default();
@@ -541,6 +1062,29 @@ mod test {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn bootstrap_commands_status_works() {
+        let mut ds = MemoryDataStore::new();
+        assert!(get_bootstrap_commands_status(&ds).unwrap().is_empty());
+
+        let status = BootstrapCommandStatus {
+            succeeded: false,
+            exit_code: 3,
+            finished: "2020-06-01T00:00:00Z".try_into().unwrap(),
+        };
+        set_bootstrap_commands_status(&mut ds, &hashmap!("10-wipe".to_string() => status)).unwrap();
+        let key = Key::new(KeyType::Data, "bootstrap-commands.10-wipe.exit-code").unwrap();
+        assert_eq!(
+            ds.get_key(&key, &Committed::Live).unwrap(),
+            Some("3".to_string())
+        );
+
+        let all = get_bootstrap_commands_status(&ds).unwrap();
+        assert_eq!(all.len(), 1);
+        assert!(!all["10-wipe"].succeeded);
+        assert_eq!(all["10-wipe"].exit_code, 3);
+    }
+
     #[test]
     fn commit_works() {
         // Set directly with data store
@@ -569,4 +1113,198 @@ mod test {
         let settings = get_settings(&ds, &Committed::Live).unwrap();
         assert_eq!(settings.motd, Some("json string".try_into().unwrap()));
     }
+
+    #[test]
+    fn reboot_required_works() {
+        let mut ds = MemoryDataStore::new();
+        ds.set_metadata(
+            &Key::new(KeyType::Meta, "reboot-required").unwrap(),
+            &Key::new(KeyType::Data, "settings.boot").unwrap(),
+            "true",
+        )
+        .unwrap();
+
+        let boot = Key::new(KeyType::Data, "settings.boot.hugepages").unwrap();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        assert!(reboot_required(&ds, &hashset!(boot, motd.clone())).unwrap());
+        assert!(!reboot_required(&ds, &hashset!(motd)).unwrap());
+    }
+
+    #[test]
+    fn staged_update_works() {
+        let inactive_root = Path::new("/dev/nvme0n1p5");
+        let prepared = || {
+            Some(PreparedUpdate {
+                variant: "aws-k8s-1.15".to_string(),
+                version: Version::parse("0.3.4").unwrap(),
+                root: inactive_root.to_path_buf(),
+                time: Utc::now(),
+                not_before: None,
+            })
+        };
+
+        let staged = staged_update(prepared(), inactive_root, true, false).unwrap();
+        assert_eq!(staged.version, Version::parse("0.3.4").unwrap());
+        assert!(!staged.activated);
+        assert!(
+            staged_update(prepared(), inactive_root, false, true)
+                .unwrap()
+                .activated
+        );
+
+        // Cancelled, or written before the partition sets swapped.
+        assert_eq!(staged_update(prepared(), inactive_root, false, false), None);
+        assert_eq!(
+            staged_update(prepared(), Path::new("/dev/nvme0n1p3"), true, false),
+            None
+        );
+        assert_eq!(staged_update(None, inactive_root, true, false), None);
+    }
+
+    #[test]
+    fn cancel_update_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lock");
+        assert!(matches!(
+            signal_update_run(&path),
+            Err(error::Error::NoUpdateRunning)
+        ));
+
+        // Stand in for updog with a process that waits to be signalled.
+        let mut updog = Command::new("sleep").arg("60").spawn().unwrap();
+        fs::write(&path, updog.id().to_string()).unwrap();
+        let lock = File::open(&path).unwrap();
+        flock(lock.as_raw_fd(), FlockArg::LockExclusiveNonblock).unwrap();
+        signal_update_run(&path).unwrap();
+        let status = updog.wait().unwrap();
+        assert_eq!(
+            std::os::unix::process::ExitStatusExt::signal(&status),
+            Some(15)
+        );
+
+        // Once updog exits, its process ID is stale.
+        drop(lock);
+        assert!(matches!(
+            signal_update_run(&path),
+            Err(error::Error::NoUpdateRunning)
+        ));
+    }
+
+    #[test]
+    fn selinux_status_works() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("enforce");
+        let permissive = SelinuxMode::try_from("permissive").unwrap();
+
+        fs::write(&path, "1").unwrap();
+        let status = selinux_status(&path, Some(permissive), true).unwrap();
+        assert_eq!(status.current, "enforcing");
+        assert_eq!(status.next_boot, "permissive");
+        assert!(status.permissive_allowed);
+
+        fs::write(&path, "0").unwrap();
+        let status = selinux_status(&path, None, false).unwrap();
+        assert_eq!(status.current, "permissive");
+        assert_eq!(status.next_boot, "enforcing");
+    }
+
+    #[test]
+    fn permissive_refused() {
+        let settings: Settings =
+            serde_json::from_str(r#"{"kernel": {"selinux": "permissive"}}"#).unwrap();
+        check_selinux_mode(&settings, true).unwrap();
+        assert!(matches!(
+            check_selinux_mode(&settings, false),
+            Err(error::Error::SelinuxPermissiveDisallowed)
+        ));
+
+        let settings: Settings =
+            serde_json::from_str(r#"{"kernel": {"selinux": "enforcing"}}"#).unwrap();
+        check_selinux_mode(&settings, false).unwrap();
+        check_selinux_mode(&Settings::default(), false).unwrap();
+    }
+
+    #[test]
+    fn image_gc_thresholds() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let set = |ds: &mut MemoryDataStore, name: &str, value: &str, committed: &Committed| {
+            ds.set_key(&Key::new(KeyType::Data, name).unwrap(), value, committed)
+                .unwrap()
+        };
+        let pending = Committed::Pending { tx: tx.into() };
+
+        // Nothing to check without the settings.
+        commit_transaction(&mut ds, tx).unwrap();
+
+        set(&mut ds, IMAGE_GC_HIGH_THRESHOLD, "85", &Committed::Live);
+        set(&mut ds, IMAGE_GC_LOW_THRESHOLD, "80", &Committed::Live);
+
+        // A pending change is checked against the live value of the other threshold.
+        set(&mut ds, IMAGE_GC_HIGH_THRESHOLD, "80", &pending);
+        assert!(matches!(
+            commit_transaction(&mut ds, tx),
+            Err(error::Error::ImageGcThresholds { high: 80, low: 80 })
+        ));
+
+        // Changing both together is fine, as long as low stays below high.
+        set(&mut ds, IMAGE_GC_LOW_THRESHOLD, "0", &pending);
+        let changes = commit_transaction(&mut ds, tx).unwrap();
+        assert_eq!(changes.len(), 2);
+    }
+
+    #[test]
+    fn log_sources() {
+        assert_eq!(
+            LogSource::HostContainer("admin").unit().unwrap(),
+            "host-containers@admin.service"
+        );
+        assert_eq!(LogSource::Service("kubelet").unit().unwrap(), "kubelet");
+        // Names can't be taken as options, unit patterns, or more than one argument.
+        for name in &["", "--all", "*", "kubelet containerd"] {
+            assert!(LogSource::Service(name).unit().is_err(), "{}", name);
+        }
+        assert_eq!(
+            journalctl_args("kubelet", 20, true)[..4],
+            ["--unit", "kubelet", "--lines", "20"]
+        );
+        assert_eq!(
+            journalctl_args("kubelet", 20, true).last().unwrap(),
+            "--follow"
+        );
+
+        let mut ds = MemoryDataStore::new();
+        ds.set_key(
+            &Key::new(KeyType::Data, "settings.host-containers.admin.enabled").unwrap(),
+            "true",
+            &Committed::Live,
+        )
+        .unwrap();
+        check_host_container(&ds, "admin").unwrap();
+        assert!(matches!(
+            check_host_container(&ds, "control"),
+            Err(error::Error::MissingData { .. })
+        ));
+    }
+
+    #[test]
+    fn follow_logs_lines() {
+        let mut command = Command::new("printf");
+        command.arg("one\ntwo\n");
+        let lines = follow_logs(command).unwrap();
+        let lines: Vec<_> = actix_rt::System::new("test").block_on(lines.collect());
+        assert_eq!(lines, vec![b"one\n".to_vec(), b"two\n".to_vec()]);
+    }
+
+    #[test]
+    fn follow_logs_stops() {
+        // A command that never logs anything is stopped as soon as the stream goes away.
+        let mut command = Command::new("sleep");
+        command.arg("1000");
+        let lines = follow_logs(command).unwrap();
+        let pid = lines.child.id();
+        assert!(Path::new(&format!("/proc/{}", pid)).exists());
+        drop(lines);
+        assert!(!Path::new(&format!("/proc/{}", pid)).exists());
+    }
 }
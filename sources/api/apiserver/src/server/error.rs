@@ -38,6 +38,9 @@ pub enum Error {
     #[snafu(display("Input '{}' cannot be empty", input))]
     EmptyInput { input: String },
 
+    #[snafu(display("Invalid value '{}' for input '{}'", value, input))]
+    InvalidInput { input: String, value: String },
+
     #[snafu(display("Another thread poisoned the data store lock by panicking"))]
     DataStoreLock,
 
@@ -58,6 +61,64 @@ pub enum Error {
         source: bottlerocket_release::Error,
     },
 
+    #[snafu(display("Unable to read the partition table: {}", source))]
+    PartitionState { source: signpost::Error },
+
+    #[snafu(display("Unable to read prepared update from '{}': {}", path.display(), source))]
+    PreparedUpdateRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to parse prepared update from '{}': {}", path.display(), source))]
+    PreparedUpdateParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("No update is being written or activated"))]
+    NoUpdateRunning,
+
+    #[snafu(display("No services rotate certificates on this host"))]
+    NoCertificateServices,
+
+    #[snafu(display("SELinux can't be made permissive on this variant"))]
+    SelinuxPermissiveDisallowed,
+
+    #[snafu(display(
+        "Image garbage collection's low threshold ({}%) must be below its high threshold ({}%)",
+        low,
+        high
+    ))]
+    ImageGcThresholds { high: u64, low: u64 },
+
+    #[snafu(display("Unable to read SELinux status from '{}': {}", path.display(), source))]
+    SelinuxStatusRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to read updog's lock file '{}': {}", path.display(), source))]
+    UpdateLockRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to check updog's lock file '{}': {}", path.display(), source))]
+    UpdateLock { path: PathBuf, source: nix::Error },
+
+    #[snafu(display("Unable to find updog's process ID in '{}'", path.display()))]
+    UpdateLockPid { path: PathBuf },
+
+    #[snafu(display("Unable to signal updog process {}: {}", pid, source))]
+    UpdateSignal { pid: i32, source: nix::Error },
+
+    #[snafu(display("Only one of 'host-container' and 'service' can be given"))]
+    LogSourceConflict,
+
+    #[snafu(display("Invalid host container or service name '{}'", name))]
+    InvalidLogName { name: String },
+
+    #[snafu(display("Unable to start journalctl: {}", source))]
+    LogCommandStart { source: io::Error },
+
+    #[snafu(display("journalctl failed: {}", stderr))]
+    LogCommandStatus { stderr: String },
+
+    #[snafu(display("Unable to read logs, couldn't get journalctl's output"))]
+    LogCommandStdout {},
+
     // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
     // Controller errors
@@ -107,6 +168,12 @@ pub enum Error {
         source: serde_json::Error,
     },
 
+    #[snafu(display("Data '{}' is not a valid number: {}", key, source))]
+    InvalidData {
+        key: String,
+        source: serde_json::Error,
+    },
+
     #[snafu(display("Unable to start config applier: {} ", source))]
     ConfigApplierStart { source: io::Error },
 
@@ -9,11 +9,12 @@ use crate::datastore::{Committed, FilesystemDataStore, Key, Value};
 use actix_web::{
     error::ResponseError, web, App, FromRequest, HttpRequest, HttpResponse, HttpServer, Responder,
 };
-use bottlerocket_release::BottlerocketRelease;
+use audit::{Action, Actor, Event};
+use bottlerocket_release::VersionInfo;
 use error::Result;
-use futures::future;
+use futures::{future, StreamExt};
 use log::info;
-use model::{ConfigurationFiles, Model, Services, Settings};
+use model::{BootstrapCommandsStatus, ConfigurationFiles, Model, Services, Settings};
 use nix::unistd::{chown, Gid};
 use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::{HashMap, HashSet};
@@ -21,10 +22,13 @@ use std::env;
 use std::fs::set_permissions;
 use std::fs::Permissions;
 use std::os::unix::fs::PermissionsExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync;
 
+/// Response header set on commits that changed settings that only take effect after a reboot.
+const REBOOT_REQUIRED_HEADER: &str = "Bottlerocket-Reboot-Required";
+
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
 // sd_notify helper
@@ -60,8 +64,16 @@ where
     P1: AsRef<Path>,
     P2: AsRef<Path>,
 {
+    // The data store path is normally the "current" link in the directory of versioned data
+    // stores, which is where its version is found.
+    let datastore_dir = datastore_path
+        .as_ref()
+        .parent()
+        .unwrap_or_else(|| datastore_path.as_ref())
+        .to_path_buf();
     let shared_datastore = web::Data::new(SharedDataStore {
         ds: sync::RwLock::new(FilesystemDataStore::new(datastore_path)),
+        datastore_dir,
     });
 
     let http_server = HttpServer::new(move || {
@@ -103,6 +115,9 @@ where
             .service(
                 web::scope("/os")
                     .route("", web::get().to(get_os_info))
+                    .route("/update-status", web::get().to(get_update_status))
+                    .route("/selinux", web::get().to(get_selinux_status))
+                    .route("/cancel-update", web::post().to(cancel_update)),
             )
             .service(
                 web::scope("/metadata")
@@ -111,6 +126,16 @@ where
                     .route("/templates", web::get().to(get_templates)),
             )
             .service(web::scope("/services").route("", web::get().to(get_services)))
+            .service(web::scope("/logs").route("", web::get().to(get_logs)))
+            .service(
+                web::scope("/bootstrap-commands")
+                    .route("", web::get().to(get_bootstrap_commands_status))
+                    .route("", web::patch().to(patch_bootstrap_commands_status)),
+            )
+            .service(
+                web::scope("/actions")
+                    .route("/rotate-certificates", web::post().to(rotate_certificates)),
+            )
             .service(
                 web::scope("/configuration-files")
                     .route("", web::get().to(get_configuration_files)),
@@ -149,7 +174,7 @@ async fn get_model(data: web::Data<SharedDataStore>) -> Result<ModelResponse> {
     let settings = Some(controller::get_settings(&*datastore, &Committed::Live)?);
     let services = Some(controller::get_services(&*datastore)?);
     let configuration_files = Some(controller::get_configuration_files(&*datastore)?);
-    let os = Some(controller::get_os_info()?);
+    let os = Some(controller::get_os_info(&data.datastore_dir)?);
 
     let model = Model {settings, services, configuration_files, os};
     Ok(ModelResponse(model))
@@ -226,17 +251,21 @@ async fn delete_transaction(
 async fn commit_transaction(
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
-) -> Result<ChangedKeysResponse> {
+) -> Result<CommitResponse> {
     let transaction = transaction_name(&query);
     let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
 
-    let changes = controller::commit_transaction(&mut *datastore, transaction)?;
+    let changes = commit(&mut *datastore, transaction)?;
 
     if changes.is_empty() {
         return error::CommitWithNoPending.fail();
     }
 
-    Ok(ChangedKeysResponse(changes))
+    let reboot_required = controller::reboot_required(&*datastore, &changes)?;
+    Ok(CommitResponse {
+        changes,
+        reboot_required,
+    })
 }
 
 /// Starts settings appliers for any changes that have been committed to the data store.  This
@@ -244,9 +273,9 @@ async fn commit_transaction(
 async fn apply_changes(query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
     if let Some(keys_str) = query.get("keys") {
         let keys = comma_separated("keys", keys_str)?;
-        controller::apply_changes(Some(&keys))?;
+        apply(Some(&keys))?;
     } else {
-        controller::apply_changes(None as Option<&HashSet<&str>>)?;
+        apply(None as Option<&HashSet<&str>>)?;
     }
 
     Ok(HttpResponse::NoContent().json(()))
@@ -258,24 +287,93 @@ async fn apply_changes(query: web::Query<HashMap<String, String>>) -> Result<Htt
 async fn commit_transaction_and_apply(
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
-) -> Result<ChangedKeysResponse> {
+) -> Result<CommitResponse> {
     let transaction = transaction_name(&query);
     let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
 
-    let changes = controller::commit_transaction(&mut *datastore, transaction)?;
+    let changes = commit(&mut *datastore, transaction)?;
 
     if changes.is_empty() {
         return error::CommitWithNoPending.fail();
     }
 
     let key_names = changes.iter().map(|k| k.name()).collect();
-    controller::apply_changes(Some(&key_names))?;
+    apply(Some(&key_names))?;
+
+    let reboot_required = controller::reboot_required(&*datastore, &changes)?;
+    Ok(CommitResponse {
+        changes,
+        reboot_required,
+    })
+}
+
+async fn get_os_info(data: web::Data<SharedDataStore>) -> Result<VersionInfoResponse> {
+    Ok(VersionInfoResponse(controller::get_os_info(
+        &data.datastore_dir,
+    )?))
+}
+
+async fn get_update_status(data: web::Data<SharedDataStore>) -> Result<UpdateStatusResponse> {
+    let os = controller::get_os_info(&data.datastore_dir)?;
+    Ok(UpdateStatusResponse(controller::get_update_status(
+        os.release.version_id,
+    )?))
+}
 
-    Ok(ChangedKeysResponse(changes))
+/// Returns the SELinux mode the host is running in, and the one it boots in next.
+async fn get_selinux_status(data: web::Data<SharedDataStore>) -> Result<SelinuxStatusResponse> {
+    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+    Ok(SelinuxStatusResponse(controller::get_selinux_status(
+        &*datastore,
+    )?))
+}
+
+/// Asks the updog run that's writing or activating an update to stop.
+async fn cancel_update() -> Result<HttpResponse> {
+    let result = controller::cancel_update();
+    audit::record(
+        Actor::Apiserver,
+        Action::UpdateCancel,
+        "update run",
+        &result,
+    );
+    result?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Rotates the certificates of the services that manage them, by running their restart commands
+/// through the config applier.  The rotation runs in the background.
+async fn rotate_certificates(data: web::Data<SharedDataStore>) -> Result<HttpResponse> {
+    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+    let services = controller::certificate_services(&*datastore)?;
+    let result = controller::rotate_certificates(&services);
+    audit::record(
+        Actor::Apiserver,
+        Action::CertificatesRotate,
+        sorted_list(&services),
+        &result,
+    );
+    result?;
+    Ok(HttpResponse::NoContent().finish()) // 204
 }
 
-async fn get_os_info() -> Result<BottlerocketReleaseResponse> {
-    Ok(BottlerocketReleaseResponse(controller::get_os_info()?))
+/// Get the status of the bootstrap commands that have run.
+async fn get_bootstrap_commands_status(
+    data: web::Data<SharedDataStore>,
+) -> Result<BootstrapCommandsStatusResponse> {
+    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+    let resp = controller::get_bootstrap_commands_status(&*datastore)?;
+    Ok(BootstrapCommandsStatusResponse(resp))
+}
+
+/// Record the status of bootstrap commands as they finish; used by bootstrap-commands.
+async fn patch_bootstrap_commands_status(
+    status: web::Json<BootstrapCommandsStatus>,
+    data: web::Data<SharedDataStore>,
+) -> Result<HttpResponse> {
+    let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
+    controller::set_bootstrap_commands_status(&mut *datastore, &status)?;
+    Ok(HttpResponse::NoContent().finish()) // 204
 }
 
 /// Get the affected services for a list of data keys
@@ -352,6 +450,55 @@ async fn get_configuration_files(
     Ok(ConfigurationFilesResponse(resp))
 }
 
+/// Get the recent logs of a host container, given with 'host-container', or a system service,
+/// given with 'service'.  'lines' says how many, and with 'follow=true', new lines are streamed as
+/// they're logged until the client disconnects.
+async fn get_logs(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<HttpResponse> {
+    let source = match (query.get("host-container"), query.get("service")) {
+        (Some(name), None) => controller::LogSource::HostContainer(name),
+        (None, Some(name)) => controller::LogSource::Service(name),
+        (Some(_), Some(_)) => return error::LogSourceConflict.fail(),
+        (None, None) => {
+            return error::MissingInput {
+                input: "host-container or service",
+            }
+            .fail()
+        }
+    };
+    let lines = match query.get("lines") {
+        Some(lines) => lines.parse().ok().context(error::InvalidInput {
+            input: "lines",
+            value: lines,
+        })?,
+        None => controller::DEFAULT_LOG_LINES,
+    };
+    let follow = match query.get("follow") {
+        Some(follow) => follow.parse().ok().context(error::InvalidInput {
+            input: "follow",
+            value: follow,
+        })?,
+        None => false,
+    };
+
+    if let controller::LogSource::HostContainer(name) = source {
+        let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+        controller::check_host_container(&*datastore, name)?;
+    }
+    let command = controller::log_command(&source, lines, follow)?;
+
+    let mut response = HttpResponse::Ok();
+    response.content_type("text/plain; charset=utf-8");
+    if follow {
+        let lines = controller::follow_logs(command)?;
+        Ok(response.streaming(lines.map(|line| Ok::<_, error::Error>(web::Bytes::from(line)))))
+    } else {
+        Ok(response.body(controller::get_logs(command)?))
+    }
+}
+
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
 // Helpers for handler methods called by the router
@@ -363,6 +510,40 @@ fn comma_separated<'a>(key_name: &'static str, input: &'a str) -> Result<HashSet
     Ok(input.split(',').collect())
 }
 
+/// Commits a transaction, recording the commit in the audit trail with the keys it changed.  A
+/// commit with nothing to change fails the request, and isn't recorded.
+fn commit(datastore: &mut FilesystemDataStore, transaction: &str) -> Result<HashSet<Key>> {
+    let changes = controller::commit_transaction(datastore, transaction);
+    let event = Event::new(Actor::Apiserver, Action::SettingsCommit, transaction).outcome(&changes);
+    match &changes {
+        Ok(changes) if changes.is_empty() => {}
+        Ok(changes) => event
+            .detail(sorted_list(changes.iter().map(|k| k.name())))
+            .record(),
+        Err(_) => event.record(),
+    }
+    changes
+}
+
+/// Starts the settings applier, recording it in the audit trail with the keys it was given.
+fn apply<S: AsRef<str>>(keys: Option<&HashSet<S>>) -> Result<()> {
+    let result = controller::apply_changes(keys);
+    let object = keys.map_or_else(|| "all".to_string(), sorted_list);
+    audit::record(Actor::Apiserver, Action::SettingsApply, object, &result);
+    result
+}
+
+/// Lists names in order, separated by commas, for the audit trail.
+fn sorted_list<I, S>(names: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut names: Vec<String> = names.into_iter().map(|s| s.as_ref().to_string()).collect();
+    names.sort();
+    names.join(",")
+}
+
 fn transaction_name(query: &web::Query<HashMap<String, String>>) -> &str {
     if let Some(name_str) = query.get("tx") {
         name_str
@@ -380,7 +561,12 @@ impl ResponseError for error::Error {
             // 400 Bad Request
             MissingInput { .. } => HttpResponse::BadRequest(),
             EmptyInput { .. } => HttpResponse::BadRequest(),
+            InvalidInput { .. } => HttpResponse::BadRequest(),
             NewKey { .. } => HttpResponse::BadRequest(),
+            LogSourceConflict => HttpResponse::BadRequest(),
+            InvalidLogName { .. } => HttpResponse::BadRequest(),
+            SelinuxPermissiveDisallowed => HttpResponse::BadRequest(),
+            ImageGcThresholds { .. } => HttpResponse::BadRequest(),
 
             // 404 Not Found
             MissingData { .. } => HttpResponse::NotFound(),
@@ -388,6 +574,8 @@ impl ResponseError for error::Error {
 
             // 422 Unprocessable Entity
             CommitWithNoPending => HttpResponse::UnprocessableEntity(),
+            NoUpdateRunning => HttpResponse::UnprocessableEntity(),
+            NoCertificateServices => HttpResponse::UnprocessableEntity(),
 
             // 500 Internal Server Error
             DataStoreLock => HttpResponse::InternalServerError(),
@@ -400,6 +588,7 @@ impl ResponseError for error::Error {
             DataStoreSerialization { .. } => HttpResponse::InternalServerError(),
             CommandSerialization { .. } => HttpResponse::InternalServerError(),
             InvalidMetadata { .. } => HttpResponse::InternalServerError(),
+            InvalidData { .. } => HttpResponse::InternalServerError(),
             ConfigApplierStart { .. } => HttpResponse::InternalServerError(),
             ConfigApplierStdin {} => HttpResponse::InternalServerError(),
             ConfigApplierWrite { .. } => HttpResponse::InternalServerError(),
@@ -408,6 +597,17 @@ impl ResponseError for error::Error {
             SetPermissions { .. } => HttpResponse::InternalServerError(),
             SetGroup { .. } => HttpResponse::InternalServerError(),
             ReleaseData { .. } => HttpResponse::InternalServerError(),
+            PartitionState { .. } => HttpResponse::InternalServerError(),
+            PreparedUpdateRead { .. } => HttpResponse::InternalServerError(),
+            PreparedUpdateParse { .. } => HttpResponse::InternalServerError(),
+            UpdateLockRead { .. } => HttpResponse::InternalServerError(),
+            UpdateLock { .. } => HttpResponse::InternalServerError(),
+            UpdateLockPid { .. } => HttpResponse::InternalServerError(),
+            UpdateSignal { .. } => HttpResponse::InternalServerError(),
+            SelinuxStatusRead { .. } => HttpResponse::InternalServerError(),
+            LogCommandStart { .. } => HttpResponse::InternalServerError(),
+            LogCommandStatus { .. } => HttpResponse::InternalServerError(),
+            LogCommandStdout {} => HttpResponse::InternalServerError(),
         }
         // Include the error message in the response, and for all error types.  The Bottlerocket
         // API is only exposed locally, and only on the host filesystem and to authorized
@@ -418,6 +618,7 @@ impl ResponseError for error::Error {
 
 struct SharedDataStore {
     ds: sync::RwLock<FilesystemDataStore>,
+    datastore_dir: PathBuf,
 }
 
 /// Helper macro for implementing the actix-web Responder trait for a type.
@@ -452,14 +653,27 @@ impl_responder_for!(ModelResponse, self, self.0);
 struct SettingsResponse(Settings);
 impl_responder_for!(SettingsResponse, self, self.0);
 
-/// This lets us respond from our handler methods with a BottlerocketRelease (or Result<BottlerocketRelease>)
-struct BottlerocketReleaseResponse(BottlerocketRelease);
-impl_responder_for!(BottlerocketReleaseResponse, self, self.0);
+/// This lets us respond from our handler methods with a VersionInfo (or Result<VersionInfo>)
+struct VersionInfoResponse(VersionInfo);
+impl_responder_for!(VersionInfoResponse, self, self.0);
+
+/// This lets us respond from our handler methods with an UpdateStatus (or Result<UpdateStatus>)
+struct UpdateStatusResponse(controller::UpdateStatus);
+impl_responder_for!(UpdateStatusResponse, self, self.0);
+
+/// This lets us respond from our handler methods with a SelinuxStatus (or Result<SelinuxStatus>)
+struct SelinuxStatusResponse(controller::SelinuxStatus);
+impl_responder_for!(SelinuxStatusResponse, self, self.0);
 
 /// This lets us respond from our handler methods with a HashMap (or Result<HashMap>) for metadata
 struct MetadataResponse(HashMap<String, Value>);
 impl_responder_for!(MetadataResponse, self, self.0);
 
+/// This lets us respond from our handler methods with a BootstrapCommandsStatus (or
+/// Result<BootstrapCommandsStatus>)
+struct BootstrapCommandsStatusResponse(BootstrapCommandsStatus);
+impl_responder_for!(BootstrapCommandsStatusResponse, self, self.0);
+
 /// This lets us respond from our handler methods with a Services (or Result<Services>)
 struct ServicesResponse(Services);
 impl_responder_for!(ServicesResponse, self, self.0);
@@ -472,5 +686,30 @@ impl_responder_for!(ConfigurationFilesResponse, self, self.0);
 struct ChangedKeysResponse(HashSet<Key>);
 impl_responder_for!(ChangedKeysResponse, self, self.0);
 
+/// This lets us respond to a commit with the changed keys, and tell the client with a header if
+/// any of those changes only take effect after a reboot.
+struct CommitResponse {
+    changes: HashSet<Key>,
+    reboot_required: bool,
+}
+
+impl Responder for CommitResponse {
+    type Error = error::Error;
+    type Future = future::Ready<Result<HttpResponse>>;
+
+    fn respond_to(self, _req: &HttpRequest) -> Self::Future {
+        let body = match serde_json::to_string(&self.changes) {
+            Ok(s) => s,
+            Err(e) => return future::ready(Err(e).context(error::ResponseSerialization)),
+        };
+        let mut response = HttpResponse::Ok();
+        response.content_type("application/json");
+        if self.reboot_required {
+            response.header(REBOOT_REQUIRED_HEADER, "true");
+        }
+        future::ready(Ok(response.body(body)))
+    }
+}
+
 struct TransactionListResponse(HashSet<String>);
 impl_responder_for!(TransactionListResponse, self, self.0);
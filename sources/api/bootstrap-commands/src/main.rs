@@ -0,0 +1,468 @@
+/*!
+# Introduction
+
+bootstrap-commands runs the tasks in `settings.bootstrap-commands` once, after the host is
+configured and before the orchestrator agent starts, so they can prepare the host, for example by
+loading kernel modules or formatting local disks.
+
+Commands run one at a time, in the order of their names.  Each is either:
+
+* a container, run from the image at `source` with host-ctr, optionally `superpowered`, with
+  `arguments` in place of the image's command; or
+* a `command`, one of `apiclient`, `modprobe`, `wipefs`, or `blkdiscard`, run on the host with
+  `arguments`.
+
+When a command finishes, its result is recorded in the API under `/bootstrap-commands`, and it
+isn't run again on later boots if it succeeded.  Failed commands aren't retried unless they're
+`essential`; if an essential command fails, bootstrap-commands stops and exits with an error, so
+the commands after it and the orchestrator agent wait until it succeeds on a later boot.
+*/
+
+#![deny(rust_2018_idioms)]
+
+#[macro_use]
+extern crate log;
+
+use chrono::{SecondsFormat, Utc};
+use model::modeled_types::{Identifier, Timestamp};
+use model::{BootstrapCommand, BootstrapCommandStatus, BootstrapCommandsStatus};
+use simplelog::{Config as LogConfig, LevelFilter, TermLogger, TerminalMode};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::str::FromStr;
+
+// FIXME Get from configuration in the future
+const DEFAULT_API_SOCKET: &str = "/run/api.sock";
+const API_SETTINGS_URI: &str = "/settings";
+const API_STATUS_URI: &str = "/bootstrap-commands";
+const ARGS_FILE_DIR: &str = "/etc/bootstrap-commands";
+// host-ctr bind-mounts each container's directory here as its persistent storage.
+const STORAGE_DIR: &str = "/local/host-containers";
+
+const HOST_CTR_BIN: &str = "/usr/bin/host-ctr";
+
+/// The host programs bootstrap commands may run, and where to find them.
+const PROGRAMS: &[(&str, &str)] = &[
+    ("apiclient", "/usr/bin/apiclient"),
+    ("modprobe", "/usr/bin/modprobe"),
+    ("wipefs", "/usr/sbin/wipefs"),
+    ("blkdiscard", "/usr/sbin/blkdiscard"),
+];
+
+mod error {
+    use http::StatusCode;
+    use snafu::Snafu;
+    use std::io;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Error sending {} to {}: {}", method, uri, source))]
+        APIRequest {
+            method: String,
+            uri: String,
+            source: apiclient::Error,
+        },
+
+        #[snafu(display("Error {} when sending {} to {}: {}", code, method, uri, response_body))]
+        APIResponse {
+            method: String,
+            uri: String,
+            code: StatusCode,
+            response_body: String,
+        },
+
+        #[snafu(display(
+            "Error deserializing response as JSON from {} to {}: {}",
+            method,
+            uri,
+            source
+        ))]
+        ResponseJson {
+            method: &'static str,
+            uri: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Error serializing status of bootstrap command '{}': {}", name, source))]
+        SerializeStatus {
+            name: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Bootstrap command '{}' needs a 'source' or a 'command'", name))]
+        MissingTask { name: String },
+
+        #[snafu(display("Bootstrap command '{}' has both a 'source' and a 'command'", name))]
+        ConflictingTask { name: String },
+
+        #[snafu(display("Bootstrap command '{}' has unknown command '{}'", name, command))]
+        UnknownProgram { name: String, command: String },
+
+        #[snafu(display("Unable to create bootstrap-commands config dir {}: {}", path.display(), source))]
+        ArgsFileDirCreate { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Unable to create container storage dir {}: {}", path.display(), source))]
+        StorageDirCreate { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Failed to write arguments file to {}: {}", path.display(), source))]
+        ArgsFileWriteFailed { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Invalid finish time '{}': {}", time, source))]
+        InvalidTime {
+            time: String,
+            source: model::modeled_types::error::Error,
+        },
+
+        #[snafu(display(
+            "Essential bootstrap command '{}' failed with exit code {}",
+            name,
+            exit_code
+        ))]
+        EssentialFailed { name: String, exit_code: i32 },
+
+        #[snafu(display("Logger setup error: {}", source))]
+        Logger { source: simplelog::TermLogError },
+    }
+}
+
+type Result<T> = std::result::Result<T, error::Error>;
+
+/// Sends a request to the API and returns the response body, if the request succeeded.
+fn api_request<P>(
+    socket_path: P,
+    method: &'static str,
+    uri: &str,
+    data: Option<String>,
+) -> Result<String>
+where
+    P: AsRef<Path>,
+{
+    let (code, response_body) = apiclient::raw_request(&socket_path, uri, method, data)
+        .context(error::APIRequest { method, uri })?;
+    ensure!(
+        code.is_success(),
+        error::APIResponse {
+            method,
+            uri,
+            code,
+            response_body,
+        }
+    );
+    Ok(response_body)
+}
+
+/// Query the API for the bootstrap commands and the results of the ones that already ran.
+fn get_bootstrap_commands<P>(
+    socket_path: P,
+) -> Result<(
+    HashMap<Identifier, BootstrapCommand>,
+    BootstrapCommandsStatus,
+)>
+where
+    P: AsRef<Path>,
+{
+    debug!("Querying the API for settings");
+    let method = "GET";
+    let uri = API_SETTINGS_URI;
+    let response_body = api_request(&socket_path, method, uri, None)?;
+    let settings: model::Settings =
+        serde_json::from_str(&response_body).context(error::ResponseJson { method, uri })?;
+
+    debug!("Querying the API for bootstrap command status");
+    let uri = API_STATUS_URI;
+    let response_body = api_request(&socket_path, method, uri, None)?;
+    let status: BootstrapCommandsStatus =
+        serde_json::from_str(&response_body).context(error::ResponseJson { method, uri })?;
+
+    Ok((settings.bootstrap_commands.unwrap_or_default(), status))
+}
+
+/// Records the result of a bootstrap command in the API.
+fn set_status<P>(socket_path: P, name: &str, status: BootstrapCommandStatus) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let mut statuses = BootstrapCommandsStatus::new();
+    statuses.insert(name.to_string(), status);
+    let body = serde_json::to_string(&statuses).context(error::SerializeStatus { name })?;
+    api_request(socket_path, "PATCH", API_STATUS_URI, Some(body))?;
+    Ok(())
+}
+
+/// Returns the bootstrap commands that should run this boot, in order of their names.  Commands
+/// that succeeded are done; commands that failed are only tried again if they're essential.
+fn pending<'a>(
+    commands: &'a HashMap<Identifier, BootstrapCommand>,
+    status: &BootstrapCommandsStatus,
+) -> Vec<(&'a str, &'a BootstrapCommand)> {
+    let mut pending: Vec<(&str, &BootstrapCommand)> = commands
+        .iter()
+        .map(|(name, command)| (name.as_ref(), command))
+        .filter(|(name, command)| match status.get(*name) {
+            None => true,
+            Some(result) => !result.succeeded && command.essential.unwrap_or(false),
+        })
+        .collect();
+    pending.sort_by_key(|(name, _)| *name);
+    pending
+}
+
+/// Builds the command that runs a bootstrap command.  For a container, this writes the arguments
+/// file host-ctr needs and creates the container's persistent storage.
+fn build_command(
+    name: &str,
+    command: &BootstrapCommand,
+    args_dir: &Path,
+    storage_dir: &Path,
+) -> Result<Command> {
+    let arguments: Vec<&str> = command
+        .arguments
+        .iter()
+        .flatten()
+        .map(|arg| arg.as_ref())
+        .collect();
+
+    match (&command.source, &command.command) {
+        (Some(source), None) => {
+            fs::create_dir_all(args_dir).context(error::ArgsFileDirCreate { path: args_dir })?;
+            let path = args_dir.join(format!("{}.args", name));
+            let mut output = String::new();
+            for arg in &arguments {
+                output.push_str(arg);
+                output.push('\n');
+            }
+            fs::write(&path, output).context(error::ArgsFileWriteFailed { path: &path })?;
+
+            let ctr_id = format!("bootstrap-{}", name);
+            let storage = storage_dir.join(&ctr_id);
+            fs::create_dir_all(&storage).context(error::StorageDirCreate { path: &storage })?;
+
+            let mut cmd = Command::new(HOST_CTR_BIN);
+            cmd.arg("-ctr-id")
+                .arg(ctr_id)
+                .arg("-source")
+                .arg(AsRef::<str>::as_ref(source))
+                .arg(format!(
+                    "-superpowered={}",
+                    command.superpowered.unwrap_or(false)
+                ))
+                .arg("-args-file")
+                .arg(&path);
+            Ok(cmd)
+        }
+        (None, Some(program)) => {
+            let program: &str = program.as_ref();
+            let (_, path) = PROGRAMS
+                .iter()
+                .find(|(known, _)| *known == program)
+                .context(error::UnknownProgram {
+                    name,
+                    command: program,
+                })?;
+            let mut cmd = Command::new(path);
+            cmd.args(&arguments);
+            Ok(cmd)
+        }
+        (None, None) => error::MissingTask { name }.fail(),
+        (Some(_), Some(_)) => error::ConflictingTask { name }.fail(),
+    }
+}
+
+/// Runs a bootstrap command and returns its exit code, or -1 if it couldn't be run or was killed.
+fn run_command(name: &str, command: &BootstrapCommand) -> Result<i32> {
+    let mut cmd = build_command(
+        name,
+        command,
+        Path::new(ARGS_FILE_DIR),
+        Path::new(STORAGE_DIR),
+    )?;
+    debug!("Running {:?}", cmd);
+    match cmd.status() {
+        Ok(status) => Ok(status.code().unwrap_or(-1)),
+        Err(e) => {
+            error!("Failed to run bootstrap command '{}': {}", name, e);
+            Ok(-1)
+        }
+    }
+}
+
+/// Store the args we receive on the command line
+struct Args {
+    log_level: LevelFilter,
+    socket_path: PathBuf,
+}
+
+/// Print a usage message in the event a bad arg is passed
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            [ --socket-path PATH ]
+            [ --log-level trace|debug|info|warn|error ]
+
+    Socket path defaults to {}",
+        program_name, DEFAULT_API_SOCKET,
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parse the args to the program and return an Args struct
+fn parse_args(args: env::Args) -> Args {
+    let mut log_level = None;
+    let mut socket_path = None;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--log-level" => {
+                let log_level_str = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --log-level"));
+                log_level = Some(LevelFilter::from_str(&log_level_str).unwrap_or_else(|_| {
+                    usage_msg(format!("Invalid log level '{}'", log_level_str))
+                }));
+            }
+
+            "--socket-path" => {
+                socket_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --socket-path"))
+                        .into(),
+                )
+            }
+
+            _ => usage(),
+        }
+    }
+
+    Args {
+        log_level: log_level.unwrap_or_else(|| LevelFilter::Info),
+        socket_path: socket_path.unwrap_or_else(|| DEFAULT_API_SOCKET.into()),
+    }
+}
+
+fn run() -> Result<()> {
+    let args = parse_args(env::args());
+
+    // TerminalMode::Mixed will send errors to stderr and anything less to stdout.
+    TermLogger::init(args.log_level, LogConfig::default(), TerminalMode::Mixed)
+        .context(error::Logger)?;
+
+    info!("bootstrap-commands started");
+
+    let (commands, status) = get_bootstrap_commands(&args.socket_path)?;
+    for (name, command) in pending(&commands, &status) {
+        info!("Running bootstrap command '{}'", name);
+        let exit_code = run_command(name, command)?;
+
+        let time = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        let finished = Timestamp::try_from(time.as_str()).context(error::InvalidTime { time })?;
+        let succeeded = exit_code == 0;
+        set_status(
+            &args.socket_path,
+            name,
+            BootstrapCommandStatus {
+                succeeded,
+                exit_code,
+                finished,
+            },
+        )?;
+
+        let essential = command.essential.unwrap_or(false);
+        if succeeded {
+            info!("Bootstrap command '{}' succeeded", name);
+        } else {
+            ensure!(!essential, error::EssentialFailed { name, exit_code });
+            warn!(
+                "Bootstrap command '{}' failed with exit code {}, continuing",
+                name, exit_code
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn commands(json: &str) -> HashMap<Identifier, BootstrapCommand> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn status(succeeded: bool) -> BootstrapCommandStatus {
+        BootstrapCommandStatus {
+            succeeded,
+            exit_code: if succeeded { 0 } else { 1 },
+            finished: Timestamp::try_from("2020-03-27T02:00:00Z").unwrap(),
+        }
+    }
+
+    #[test]
+    fn pending_in_name_order() {
+        let commands = commands(
+            r#"{"b-new": {"command": "modprobe"},
+                "a-done": {"command": "modprobe"},
+                "c-failed": {"command": "modprobe"},
+                "d-failed-essential": {"command": "modprobe", "essential": true}}"#,
+        );
+        let mut statuses = BootstrapCommandsStatus::new();
+        statuses.insert("a-done".to_string(), status(true));
+        statuses.insert("c-failed".to_string(), status(false));
+        statuses.insert("d-failed-essential".to_string(), status(false));
+
+        let names: Vec<_> = pending(&commands, &statuses)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, vec!["b-new", "d-failed-essential"]);
+    }
+
+    #[test]
+    fn host_command() {
+        let commands =
+            commands(r#"{"br": {"command": "modprobe", "arguments": ["br_netfilter"]}}"#);
+        let dir = Path::new("/nonexistent");
+        let cmd = build_command("br", commands.values().next().unwrap(), dir, dir).unwrap();
+        assert_eq!(
+            format!("{:?}", cmd),
+            r#""/usr/bin/modprobe" "br_netfilter""#
+        );
+    }
+
+    #[test]
+    fn needs_one_task() {
+        let commands = commands(
+            r#"{"none": {"arguments": ["x"]},
+                "both": {"command": "wipefs", "source": "example.com/setup:v1"}}"#,
+        );
+        let dir = Path::new("/nonexistent");
+        for (name, command) in &commands {
+            build_command(name.as_ref(), command, dir, dir).unwrap_err();
+        }
+    }
+}
@@ -1,14 +1,192 @@
 #![deny(rust_2018_idioms)]
 
+use model::modeled_types::{FriendlyVersion, Identifier};
+use model::UpdatesSettings;
 use rand::{thread_rng, Rng};
+use snafu::ResultExt;
+use std::convert::TryFrom;
+use std::{env, process};
 
-fn main() {
+const DEFAULT_VERSION_LOCK: &str = "latest";
+const DEFAULT_CHANNEL: &str = "stable";
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Invalid version lock '{}': {}", input, source))]
+        VersionLock {
+            input: String,
+            source: model::modeled_types::error::Error,
+        },
+
+        #[snafu(display("Invalid update channel '{}': {}", input, source))]
+        Channel {
+            input: String,
+            source: model::modeled_types::error::Error,
+        },
+
+        #[snafu(display("Error serializing output to JSON: {}", source))]
+        SerializeOutput { source: serde_json::error::Error },
+    }
+}
+type Result<T> = std::result::Result<T, error::Error>;
+
+/// The settings bork can generate.
+enum Generate {
+    /// Just the random seed.
+    Seed,
+    /// The whole updates block; the contained values are used as defaults for anything the user
+    /// hasn't set.
+    Updates {
+        version_lock: String,
+        ignore_waves: bool,
+        channel: String,
+    },
+}
+
+/// Print usage message.
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {} seed
+       {} updates
+            [ --version-lock latest|VERSION ]
+            [ --ignore-waves true|false ]
+            [ --channel CHANNEL ]
+
+    Version lock defaults to {}, ignore-waves to false, and channel to {}",
+        program_name, program_name, DEFAULT_VERSION_LOCK, DEFAULT_CHANNEL,
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parses the subcommand and its options.
+fn parse_args(args: env::Args) -> Generate {
+    let mut args = args.skip(1);
+    match args.next().as_deref() {
+        Some("seed") => {
+            if args.next().is_some() {
+                usage_msg("The seed subcommand takes no options");
+            }
+            Generate::Seed
+        }
+        Some("updates") => {
+            let mut version_lock = DEFAULT_VERSION_LOCK.to_string();
+            let mut ignore_waves = false;
+            let mut channel = DEFAULT_CHANNEL.to_string();
+
+            while let Some(arg) = args.next() {
+                match arg.as_ref() {
+                    "--version-lock" => {
+                        version_lock = args
+                            .next()
+                            .unwrap_or_else(|| usage_msg("Did not give argument to --version-lock"))
+                    }
+                    "--ignore-waves" => {
+                        let value = args.next().unwrap_or_else(|| {
+                            usage_msg("Did not give argument to --ignore-waves")
+                        });
+                        ignore_waves = value.parse().unwrap_or_else(|_| {
+                            usage_msg(format!("Invalid value for --ignore-waves: '{}'", value))
+                        });
+                    }
+                    "--channel" => {
+                        channel = args
+                            .next()
+                            .unwrap_or_else(|| usage_msg("Did not give argument to --channel"))
+                    }
+                    _ => usage(),
+                }
+            }
+
+            Generate::Updates {
+                version_lock,
+                ignore_waves,
+                channel,
+            }
+        }
+        _ => usage(),
+    }
+}
+
+/// Generates a random seed, which determines where the host falls in the update order.
+fn seed() -> u32 {
     let mut rng = thread_rng();
-    let val = rng.gen_range(0, 2048);
+    rng.gen_range(0, 2048)
+}
+
+/// Builds the updates block.  The URLs come from elsewhere, and the other settings are only set if
+/// the user sets them, so we leave them out.  Values go through the model's types so we don't
+/// generate anything the API would reject.
+fn updates(version_lock: &str, ignore_waves: bool, channel: &str) -> Result<UpdatesSettings> {
+    Ok(UpdatesSettings {
+        seed: Some(seed()),
+        version_lock: Some(FriendlyVersion::try_from(version_lock).context(
+            error::VersionLock {
+                input: version_lock,
+            },
+        )?),
+        ignore_waves: Some(ignore_waves),
+        channel: Some(Identifier::try_from(channel).context(error::Channel { input: channel })?),
+        ..Default::default()
+    })
+}
 
+fn run() -> Result<()> {
     // sundog expects JSON-serialized output so that many types can be represented, allowing the
-    // API model to use more accurate types.
-    let output = serde_json::to_string(&val).expect("Unable to serialize val '{}' to JSON");
+    // API model to use more accurate types.  sundog only uses the parts of the updates block that
+    // the user hasn't already set.
+    let output = match parse_args(env::args()) {
+        Generate::Seed => serde_json::to_string(&seed()),
+        Generate::Updates {
+            version_lock,
+            ignore_waves,
+            channel,
+        } => serde_json::to_string(&updates(&version_lock, ignore_waves, &channel)?),
+    }
+    .context(error::SerializeOutput)?;
 
     println!("{}", output);
+    Ok(())
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn default_updates() {
+        let settings = updates(DEFAULT_VERSION_LOCK, false, DEFAULT_CHANNEL).unwrap();
+        let value = serde_json::to_value(&settings).unwrap();
+        assert!(value["seed"].as_u64().unwrap() < 2048);
+        assert_eq!(value["version-lock"], "latest");
+        assert_eq!(value["ignore-waves"], false);
+        assert_eq!(value["channel"], "stable");
+        assert!(value.get("metadata-base-url").is_none());
+    }
+
+    #[test]
+    fn invalid_updates() {
+        updates("0.3", false, DEFAULT_CHANNEL).unwrap_err();
+        updates(DEFAULT_VERSION_LOCK, false, "not/a/channel").unwrap_err();
+    }
 }
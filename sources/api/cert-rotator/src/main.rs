@@ -0,0 +1,212 @@
+/*!
+# Introduction
+
+cert-rotator removes kubelet's serving certificates, so kubelet gets new ones when it's restarted.
+
+It's run by thar-be-settings as a restart command of the `kubelet-certificates` service, when
+certificates are rotated through the API with `POST /actions/rotate-certificates`.  The next
+restart command restarts kubelet, which then:
+
+* requests a new serving certificate from the cluster, since `serverTLSBootstrap` is on; or
+* makes a new self-signed serving certificate, if the cluster doesn't sign one.
+
+These files are removed from kubelet's certificate directory:
+
+* `kubelet-server-*.pem`, the serving certificates signed by the cluster, and the
+  `kubelet-server-current.pem` link to the one in use;
+* `kubelet.crt` and `kubelet.key`, the self-signed serving certificate.
+
+kubelet's client certificates, `kubelet-client-*.pem`, are left alone, since kubelet may need them
+to reach the cluster and request the new serving certificate.
+*/
+
+#![deny(rust_2018_idioms)]
+
+use snafu::ResultExt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::{env, process};
+
+const DEFAULT_DIR: &str = "/var/lib/kubelet/pki";
+
+mod error {
+    use snafu::Snafu;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Failed to list certificate directory '{}': {}", path.display(), source))]
+        ListDir {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to remove certificate '{}': {}", path.display(), source))]
+        RemoveCertificate {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+    }
+}
+type Result<T> = std::result::Result<T, error::Error>;
+
+/// Store the args we receive on the command line
+struct Args {
+    dir: PathBuf,
+}
+
+/// Print a usage message in the event a bad arg is passed
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            [ --dir PATH ]
+
+    Dir defaults to {}",
+        program_name, DEFAULT_DIR,
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parse the args to the program and return an Args struct
+fn parse_args(args: env::Args) -> Args {
+    let mut dir = None;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--dir" => {
+                dir = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --dir")),
+                )
+            }
+            _ => usage(),
+        }
+    }
+
+    Args {
+        dir: dir.unwrap_or_else(|| DEFAULT_DIR.to_string()).into(),
+    }
+}
+
+/// Whether a file in kubelet's certificate directory is part of a serving certificate.
+fn is_serving_certificate(name: &str) -> bool {
+    name == "kubelet.crt"
+        || name == "kubelet.key"
+        || (name.starts_with("kubelet-server-") && name.ends_with(".pem"))
+}
+
+/// Removes the serving certificates in `dir`, returning the names of the files removed.  A
+/// missing directory has nothing to remove, like before kubelet has first started.
+fn remove_serving_certificates(dir: &Path) -> Result<Vec<String>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context(error::ListDir { path: dir }),
+    };
+
+    let mut removed = Vec::new();
+    for entry in entries {
+        let path = entry.context(error::ListDir { path: dir })?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        if is_serving_certificate(&name) {
+            // remove_file removes a link, not what it points to.
+            fs::remove_file(&path).context(error::RemoveCertificate { path: &path })?;
+            removed.push(name);
+        }
+    }
+    removed.sort();
+    Ok(removed)
+}
+
+fn run() -> Result<()> {
+    let args = parse_args(env::args());
+    let removed = remove_serving_certificates(&args.dir)?;
+    if removed.is_empty() {
+        println!(
+            "No serving certificates in '{}' to remove",
+            args.dir.display()
+        );
+    }
+    for name in removed {
+        println!("Removed '{}'", args.dir.join(name).display());
+    }
+    Ok(())
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn removes_only_serving_certificates() {
+        let dir = TempDir::new().unwrap();
+        for name in &[
+            "kubelet.crt",
+            "kubelet.key",
+            "kubelet-server-2020-06-01-10-00-00.pem",
+            "kubelet-client-2020-06-01-10-00-00.pem",
+            "kubelet-client-current.pem",
+        ] {
+            fs::write(dir.path().join(name), "cert").unwrap();
+        }
+        std::os::unix::fs::symlink(
+            "kubelet-server-2020-06-01-10-00-00.pem",
+            dir.path().join("kubelet-server-current.pem"),
+        )
+        .unwrap();
+
+        let removed = remove_serving_certificates(dir.path()).unwrap();
+        assert_eq!(
+            removed,
+            vec![
+                "kubelet-server-2020-06-01-10-00-00.pem",
+                "kubelet-server-current.pem",
+                "kubelet.crt",
+                "kubelet.key",
+            ]
+        );
+        let mut left: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            vec![
+                "kubelet-client-2020-06-01-10-00-00.pem",
+                "kubelet-client-current.pem"
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_dir() {
+        let dir = TempDir::new().unwrap();
+        assert!(remove_serving_certificates(&dir.path().join("pki"))
+            .unwrap()
+            .is_empty());
+    }
+}
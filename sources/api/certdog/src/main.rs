@@ -0,0 +1,231 @@
+/*!
+# Introduction
+
+certdog builds the system trust store from the CA bundle shipped with the OS and any additional
+certificates the user marked as trusted in `settings.pki`.
+
+thar-be-settings renders the trusted certificates from `settings.pki` into a PEM file, and runs
+certdog as the restart command of the `pki` service.  certdog then writes the shipped bundle
+followed by the trusted certificates to the system bundle, replacing it atomically so that
+programs never see a partial bundle.
+
+Programs read the system trust store when they start, so running services like containerd and
+kubelet pick up changes when they're next started.
+*/
+
+#![deny(rust_2018_idioms)]
+
+use snafu::ResultExt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::{env, process};
+
+const DEFAULT_SOURCE: &str = "/usr/share/factory/etc/pki/tls/certs/ca-bundle.crt";
+const DEFAULT_TRUSTED: &str = "/etc/pki/bottlerocket/trusted-certs.pem";
+const DEFAULT_OUTPUT: &str = "/etc/pki/tls/certs/ca-bundle.crt";
+
+mod error {
+    use snafu::Snafu;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Failed to read CA bundle '{}': {}", path.display(), source))]
+        ReadBundle {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to write CA bundle '{}': {}", path.display(), source))]
+        WriteBundle {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to move '{}' into place at '{}': {}", from.display(), to.display(), source))]
+        Rename {
+            from: PathBuf,
+            to: PathBuf,
+            source: std::io::Error,
+        },
+    }
+}
+type Result<T> = std::result::Result<T, error::Error>;
+
+/// Store the args we receive on the command line
+struct Args {
+    source: PathBuf,
+    trusted: PathBuf,
+    output: PathBuf,
+}
+
+/// Print a usage message in the event a bad arg is passed
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            [ --source PATH ]
+            [ --trusted PATH ]
+            [ --output PATH ]
+
+    Source defaults to {}
+    Trusted defaults to {}
+    Output defaults to {}",
+        program_name, DEFAULT_SOURCE, DEFAULT_TRUSTED, DEFAULT_OUTPUT,
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parse the args to the program and return an Args struct
+fn parse_args(args: env::Args) -> Args {
+    let mut source = None;
+    let mut trusted = None;
+    let mut output = None;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--source" => {
+                source = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --source")),
+                )
+            }
+            "--trusted" => {
+                trusted = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --trusted")),
+                )
+            }
+            "--output" => {
+                output = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --output")),
+                )
+            }
+            _ => usage(),
+        }
+    }
+
+    Args {
+        source: source.unwrap_or_else(|| DEFAULT_SOURCE.to_string()).into(),
+        trusted: trusted
+            .unwrap_or_else(|| DEFAULT_TRUSTED.to_string())
+            .into(),
+        output: output.unwrap_or_else(|| DEFAULT_OUTPUT.to_string()).into(),
+    }
+}
+
+/// Combines the shipped bundle with the trusted certificates, making sure each part ends with a
+/// newline so certificates don't run together.
+fn combine(source: &str, trusted: &str) -> String {
+    let mut bundle = String::with_capacity(source.len() + trusted.len() + 2);
+    for part in &[source, trusted] {
+        if part.trim().is_empty() {
+            continue;
+        }
+        bundle.push_str(part);
+        if !part.ends_with('\n') {
+            bundle.push('\n');
+        }
+    }
+    bundle
+}
+
+/// Reads a file, treating a missing file as empty; the trusted certificates aren't written until
+/// thar-be-settings first runs.
+fn read_optional(path: &Path) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(s) => Ok(s),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e).context(error::ReadBundle { path }),
+    }
+}
+
+/// Writes the combined bundle to `output`, through a temporary file in the same directory so the
+/// rename is atomic.
+fn write_bundle(args: &Args) -> Result<()> {
+    let source =
+        fs::read_to_string(&args.source).context(error::ReadBundle { path: &args.source })?;
+    let trusted = read_optional(&args.trusted)?;
+    let bundle = combine(&source, &trusted);
+
+    let mut temp = args.output.clone().into_os_string();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    fs::write(&temp, bundle).context(error::WriteBundle { path: &temp })?;
+    fs::rename(&temp, &args.output).context(error::Rename {
+        from: &temp,
+        to: &args.output,
+    })?;
+    Ok(())
+}
+
+fn run() -> Result<()> {
+    let args = parse_args(env::args());
+    write_bundle(&args)?;
+    println!("Wrote CA bundle to '{}'", args.output.display());
+    Ok(())
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn combine_parts() {
+        assert_eq!(combine("a\n", "b\n"), "a\nb\n");
+        assert_eq!(combine("a", "b"), "a\nb\n");
+        assert_eq!(combine("a\n", ""), "a\n");
+        assert_eq!(combine("a\n", "\n\n"), "a\n");
+    }
+
+    #[test]
+    fn write_with_and_without_trusted() {
+        let dir = TempDir::new().unwrap();
+        let args = Args {
+            source: dir.path().join("source.crt"),
+            trusted: dir.path().join("trusted.pem"),
+            output: dir.path().join("ca-bundle.crt"),
+        };
+        fs::write(&args.source, "shipped\n").unwrap();
+
+        write_bundle(&args).unwrap();
+        assert_eq!(fs::read_to_string(&args.output).unwrap(), "shipped\n");
+
+        fs::write(&args.trusted, "mine\n").unwrap();
+        write_bundle(&args).unwrap();
+        assert_eq!(fs::read_to_string(&args.output).unwrap(), "shipped\nmine\n");
+        assert!(!dir.path().join("ca-bundle.crt.tmp").exists());
+    }
+
+    #[test]
+    fn missing_source() {
+        let dir = TempDir::new().unwrap();
+        let args = Args {
+            source: dir.path().join("source.crt"),
+            trusted: dir.path().join("trusted.pem"),
+            output: dir.path().join("ca-bundle.crt"),
+        };
+        write_bundle(&args).unwrap_err();
+    }
+}
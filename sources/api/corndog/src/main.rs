@@ -0,0 +1,428 @@
+/*!
+# Introduction
+
+corndog applies kernel settings from the API to the running system.
+
+`corndog sysctl` writes each value in `settings.kernel.sysctl` to the matching file under
+`/proc/sys`, so `net.ipv4.ip_forward` is written to `/proc/sys/net/ipv4/ip_forward`.
+
+thar-be-settings runs corndog as the restart command of the `sysctl` service, so settings are
+applied when they're committed, and again at boot.  If a setting can't be applied, for example
+because the kernel doesn't have that parameter, corndog still applies the others, then reports
+the failure.
+
+`corndog boot-config` writes `settings.boot` to the GRUB environment block in the private
+partition, `/var/lib/bottlerocket/grubenv`, with one variable per kernel parameter, like
+`bottlerocket_hugepages=64`.  The boot menu loads only those variables, checks that each value
+has the form its parameter expects, and builds the kernel parameters from them, like
+`hugepages=64`, so changes take effect on the next boot.  Anything else in the file is ignored.
+
+`settings.kernel.selinux` is written the same way: `permissive` sets `bottlerocket_selinux`, and
+the boot menu adds `enforcing=0`, which overrides the `enforcing=1` the kernel command line
+starts with.  On variants that don't allow permissive mode, corndog refuses to write it and
+leaves the boot configuration as it was.
+*/
+
+#![deny(rust_2018_idioms)]
+
+use model::modeled_types::{SelinuxMode, SingleLineString, SysctlKey};
+use model::BootSettings;
+use snafu::{ensure, ResultExt};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::{env, process};
+
+const DEFAULT_API_SOCKET: &str = "/run/api.sock";
+const API_SETTINGS_URI: &str = "/settings";
+const SYSCTL_PATH_PREFIX: &str = "/proc/sys";
+const GRUBENV_PATH: &str = "/var/lib/bottlerocket/grubenv";
+
+// GRUB requires its environment block to be exactly this size, padded with '#'.
+const GRUBENV_SIZE: usize = 1024;
+const GRUBENV_HEADER: &str = "# GRUB Environment Block\n";
+
+// The GRUB environment variables for each kernel parameter.  The boot menu in rpm2img loads only
+// these, and only uses values with the form it expects.
+const GRUBENV_HUGEPAGES_VAR: &str = "bottlerocket_hugepages";
+const GRUBENV_ISOLCPUS_VAR: &str = "bottlerocket_isolcpus";
+const GRUBENV_LOCKDOWN_VAR: &str = "bottlerocket_lockdown";
+const GRUBENV_SELINUX_VAR: &str = "bottlerocket_selinux";
+
+mod error {
+    use http::StatusCode;
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Error sending {} to {}: {}", method, uri, source))]
+        APIRequest {
+            method: String,
+            uri: String,
+            source: apiclient::Error,
+        },
+
+        #[snafu(display("Error {} when sending {} to {}: {}", code, method, uri, response_body))]
+        APIResponse {
+            method: String,
+            uri: String,
+            code: StatusCode,
+            response_body: String,
+        },
+
+        #[snafu(display(
+            "Error deserializing response as JSON from {} to {}: {}",
+            method,
+            uri,
+            source
+        ))]
+        ResponseJson {
+            method: &'static str,
+            uri: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Failed to apply {} of {} sysctls", failed, tried))]
+        ApplySysctls { failed: usize, tried: usize },
+
+        #[snafu(display(
+            "Boot settings '{}' don't fit in the {} byte GRUB environment block",
+            vars,
+            size
+        ))]
+        GrubenvSize { vars: String, size: usize },
+
+        #[snafu(display(
+            "SELinux can't be permissive on this variant, so the boot configuration wasn't changed"
+        ))]
+        PermissiveDisallowed,
+
+        #[snafu(display("Failed to write GRUB environment block to '{}': {}", path.display(), source))]
+        WriteGrubenv {
+            path: std::path::PathBuf,
+            source: std::io::Error,
+        },
+    }
+}
+type Result<T> = std::result::Result<T, error::Error>;
+
+/// The subcommands corndog supports.
+enum Subcommand {
+    Sysctl,
+    BootConfig,
+}
+
+/// Store the args we receive on the command line
+struct Args {
+    subcommand: Subcommand,
+    socket_path: PathBuf,
+}
+
+/// Print a usage message in the event a bad arg is passed
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            ( sysctl | boot-config )
+            [ --socket-path PATH ]
+
+    Socket path defaults to {}",
+        program_name, DEFAULT_API_SOCKET,
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parse the args to the program and return an Args struct
+fn parse_args(args: env::Args) -> Args {
+    let mut subcommand = None;
+    let mut socket_path = None;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--socket-path" => {
+                socket_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --socket-path")),
+                )
+            }
+            "sysctl" => subcommand = Some(Subcommand::Sysctl),
+            "boot-config" => subcommand = Some(Subcommand::BootConfig),
+            x => usage_msg(format!("Unknown argument '{}'", x)),
+        }
+    }
+
+    Args {
+        subcommand: subcommand.unwrap_or_else(|| usage_msg("Did not specify subcommand")),
+        socket_path: socket_path
+            .unwrap_or_else(|| DEFAULT_API_SOCKET.to_string())
+            .into(),
+    }
+}
+
+/// Query the API for the current settings.
+fn get_settings<P>(socket_path: P) -> Result<model::Settings>
+where
+    P: AsRef<Path>,
+{
+    let method = "GET";
+    let uri = API_SETTINGS_URI;
+    let (code, response_body) = apiclient::raw_request(&socket_path, uri, method, None)
+        .context(error::APIRequest { method, uri })?;
+    ensure!(
+        code.is_success(),
+        error::APIResponse {
+            method,
+            uri,
+            code,
+            response_body,
+        }
+    );
+
+    serde_json::from_str(&response_body).context(error::ResponseJson { method, uri })
+}
+
+/// Returns the path of the file under `prefix` that holds the parameter named by `key`.
+fn sysctl_path<P: AsRef<Path>>(prefix: P, key: &SysctlKey) -> PathBuf {
+    key.split('.')
+        .fold(prefix.as_ref().to_path_buf(), |path, component| {
+            path.join(component)
+        })
+}
+
+/// Writes each value to the file for its key under `prefix`, returning the number of failures.
+fn apply_sysctls<P: AsRef<Path>>(
+    prefix: P,
+    sysctls: &HashMap<SysctlKey, SingleLineString>,
+) -> usize {
+    let mut failed = 0;
+    for (key, value) in sysctls {
+        let path = sysctl_path(&prefix, key);
+        match fs::write(&path, value.as_bytes()) {
+            Ok(()) => println!("Set {} to '{}'", key, value),
+            Err(e) => {
+                eprintln!("Failed to set {} via '{}': {}", key, path.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
+
+/// Builds the GRUB environment variables for the given boot settings, then the SELinux variable
+/// if there is one, in a stable order.
+fn grubenv_vars(
+    boot: Option<&BootSettings>,
+    selinux: Option<&'static str>,
+) -> Vec<(&'static str, String)> {
+    let mut vars = Vec::new();
+    if let Some(boot) = boot {
+        if let Some(hugepages) = boot.hugepages {
+            vars.push((GRUBENV_HUGEPAGES_VAR, hugepages.to_string()));
+        }
+        if let Some(isolcpus) = &boot.isolcpus {
+            vars.push((GRUBENV_ISOLCPUS_VAR, isolcpus.to_string()));
+        }
+        if let Some(lockdown) = &boot.lockdown {
+            vars.push((GRUBENV_LOCKDOWN_VAR, lockdown.to_string()));
+        }
+    }
+    vars.extend(selinux.map(|mode| (GRUBENV_SELINUX_VAR, mode.to_string())));
+    vars
+}
+
+/// Returns the value of the SELinux variable for the mode, if it needs one.  The kernel command
+/// line already has `enforcing=1`, and the last value given wins, so only permissive mode is
+/// written; the boot menu turns it into `enforcing=0`.
+fn selinux_var(mode: &SelinuxMode, permissive_allowed: bool) -> Result<Option<&'static str>> {
+    if !mode.is_permissive() {
+        return Ok(None);
+    }
+    ensure!(permissive_allowed, error::PermissiveDisallowed);
+    Ok(Some("permissive"))
+}
+
+/// Returns a GRUB environment block that sets the given variables.  Our values are modeled types
+/// that can't contain the characters GRUB would need us to escape.
+fn grubenv(vars: &[(&str, String)]) -> Result<Vec<u8>> {
+    let mut block = GRUBENV_HEADER.to_string();
+    for (name, value) in vars {
+        block.push_str(&format!("{}={}\n", name, value));
+    }
+    let mut block = block.into_bytes();
+    ensure!(
+        block.len() <= GRUBENV_SIZE,
+        error::GrubenvSize {
+            vars: display_vars(vars),
+            size: GRUBENV_SIZE,
+        }
+    );
+    block.resize(GRUBENV_SIZE, b'#');
+    Ok(block)
+}
+
+/// Formats the variables for messages, like "bottlerocket_hugepages=64 bottlerocket_lockdown=none".
+fn display_vars(vars: &[(&str, String)]) -> String {
+    vars.iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Writes the GRUB environment block to `path`, going through a temporary file so GRUB never
+/// sees a partial block.
+fn write_grubenv<P: AsRef<Path>>(path: P, block: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, block)
+        .and_then(|()| fs::rename(&tmp, path))
+        .context(error::WriteGrubenv { path })
+}
+
+fn run() -> Result<()> {
+    let args = parse_args(env::args());
+    match args.subcommand {
+        Subcommand::Sysctl => {
+            let sysctls = get_settings(&args.socket_path)?
+                .kernel
+                .and_then(|kernel| kernel.sysctl)
+                .unwrap_or_default();
+            let failed = apply_sysctls(SYSCTL_PATH_PREFIX, &sysctls);
+            ensure!(
+                failed == 0,
+                error::ApplySysctls {
+                    failed,
+                    tried: sysctls.len(),
+                }
+            );
+        }
+        Subcommand::BootConfig => {
+            let settings = get_settings(&args.socket_path)?;
+            let selinux = match settings.kernel.and_then(|kernel| kernel.selinux) {
+                Some(mode) => selinux_var(&mode, model::SELINUX_PERMISSIVE_ALLOWED)?,
+                None => None,
+            };
+            let vars = grubenv_vars(settings.boot.as_ref(), selinux);
+            write_grubenv(GRUBENV_PATH, &grubenv(&vars)?)?;
+            println!(
+                "Set boot configuration for next boot to '{}'",
+                display_vars(&vars)
+            );
+        }
+    }
+    Ok(())
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryFrom;
+    use tempfile::TempDir;
+
+    #[test]
+    fn path_from_key() {
+        let key = SysctlKey::try_from("net.ipv4.ip_forward").unwrap();
+        assert_eq!(
+            sysctl_path("/proc/sys", &key),
+            PathBuf::from("/proc/sys/net/ipv4/ip_forward")
+        );
+    }
+
+    #[test]
+    fn apply_continues_after_failure() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("vm")).unwrap();
+        fs::write(dir.path().join("vm/max_map_count"), "65530").unwrap();
+
+        let mut sysctls = HashMap::new();
+        sysctls.insert(
+            SysctlKey::try_from("vm.max_map_count").unwrap(),
+            SingleLineString::try_from("262144").unwrap(),
+        );
+        sysctls.insert(
+            SysctlKey::try_from("net.missing.param").unwrap(),
+            SingleLineString::try_from("1").unwrap(),
+        );
+
+        assert_eq!(apply_sysctls(dir.path(), &sysctls), 1);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("vm/max_map_count")).unwrap(),
+            "262144"
+        );
+    }
+
+    #[test]
+    fn vars_from_settings() {
+        let boot: BootSettings = serde_json::from_str(
+            r#"{"hugepages": 64, "isolcpus": "2-3", "lockdown": "integrity"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            display_vars(&grubenv_vars(Some(&boot), None)),
+            "bottlerocket_hugepages=64 bottlerocket_isolcpus=2-3 bottlerocket_lockdown=integrity"
+        );
+
+        let boot: BootSettings = serde_json::from_str(r#"{"isolcpus": "1,3"}"#).unwrap();
+        assert_eq!(
+            display_vars(&grubenv_vars(Some(&boot), None)),
+            "bottlerocket_isolcpus=1,3"
+        );
+        assert_eq!(
+            display_vars(&grubenv_vars(Some(&boot), Some("permissive"))),
+            "bottlerocket_isolcpus=1,3 bottlerocket_selinux=permissive"
+        );
+        assert_eq!(
+            display_vars(&grubenv_vars(None, Some("permissive"))),
+            "bottlerocket_selinux=permissive"
+        );
+    }
+
+    #[test]
+    fn selinux_mode() {
+        let enforcing = SelinuxMode::try_from("enforcing").unwrap();
+        let permissive = SelinuxMode::try_from("permissive").unwrap();
+        assert_eq!(selinux_var(&enforcing, false).unwrap(), None);
+        assert_eq!(selinux_var(&enforcing, true).unwrap(), None);
+        assert_eq!(selinux_var(&permissive, true).unwrap(), Some("permissive"));
+        // Variants that don't allow permissive mode refuse it rather than writing anything.
+        assert!(selinux_var(&permissive, false).is_err());
+    }
+
+    #[test]
+    fn grubenv_block() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("grubenv");
+        let vars = vec![
+            (GRUBENV_HUGEPAGES_VAR, "64".to_string()),
+            (GRUBENV_SELINUX_VAR, "permissive".to_string()),
+        ];
+        write_grubenv(&path, &grubenv(&vars).unwrap()).unwrap();
+
+        let block = fs::read_to_string(&path).unwrap();
+        assert_eq!(block.len(), GRUBENV_SIZE);
+        assert!(block.starts_with(
+            "# GRUB Environment Block\nbottlerocket_hugepages=64\nbottlerocket_selinux=permissive\n#"
+        ));
+        assert!(block.trim_end_matches('#').ends_with('\n'));
+        assert!(!dir.path().join("grubenv.tmp").exists());
+
+        assert!(grubenv(&[(GRUBENV_ISOLCPUS_VAR, "1,".repeat(GRUBENV_SIZE))]).is_err());
+    }
+}
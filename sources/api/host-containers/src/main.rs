@@ -4,6 +4,12 @@
 host-containers is a tool that queries the API for the currently enabled host containers and
 ensures the relevant systemd service is enabled/started or disabled/stopped for each one depending
 on its 'enabled' flag.
+
+Each host container can also be given environment variables, extra bind mounts from the host,
+memory and CPU limits, and a systemd restart policy.  The environment variables are written to a
+file that host-ctr passes to the container; the mounts and limits are passed to host-ctr through
+the service's EnvironmentFile; and the restart policy is written to a drop-in for the container's
+systemd unit, which is reloaded if the policy changed.
 */
 
 #![deny(rust_2018_idioms)]
@@ -22,12 +28,14 @@ use std::path::{Path, PathBuf};
 use std::process::{self, Command};
 use std::str::FromStr;
 
-use model::modeled_types::Identifier;
+use model::modeled_types::{EnvironmentVariableName, Identifier, SingleLineString};
 
 // FIXME Get from configuration in the future
 const DEFAULT_API_SOCKET: &str = "/run/api.sock";
 const API_SETTINGS_URI: &str = "/settings";
 const ENV_FILE_DIR: &str = "/etc/host-containers";
+const UNIT_DROP_IN_DIR: &str = "/etc/systemd/system";
+const DEFAULT_RESTART_POLICY: &str = "always";
 
 const SYSTEMCTL_BIN: &str = "/bin/systemctl";
 
@@ -84,6 +92,18 @@ mod error {
         #[snafu(display("Failed to write EnvironmentFile to {}: {}", path.display(), source))]
         EnvFileWriteFailed { path: PathBuf, source: io::Error },
 
+        #[snafu(display("Failed to build container environment for {}: {}", name, source))]
+        CtrEnvFileBuildFailed { name: String, source: fmt::Error },
+
+        #[snafu(display("Failed to write container environment to {}: {}", path.display(), source))]
+        CtrEnvFileWriteFailed { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Unable to create systemd drop-in dir {}: {}", path.display(), source))]
+        DropInDirCreate { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Failed to write systemd drop-in to {}: {}", path.display(), source))]
+        DropInWriteFailed { path: PathBuf, source: io::Error },
+
         #[snafu(display("Failed to execute '{:?}': {}", command, source))]
         ExecutionFailure {
             command: Command,
@@ -177,7 +197,15 @@ where
 }
 
 /// Write out the EnvironmentFile that systemd uses to fill in arguments to host-ctr
-fn write_env_file<S1, S2>(name: S1, source: S2, enabled: bool, superpowered: bool) -> Result<()>
+fn write_env_file<S1, S2>(
+    name: S1,
+    source: S2,
+    enabled: bool,
+    superpowered: bool,
+    mounts: &str,
+    memory_limit_mib: u64,
+    cpu_quota_percent: u32,
+) -> Result<()>
 where
     S1: AsRef<str>,
     S2: AsRef<str>,
@@ -191,6 +219,11 @@ where
         .context(error::EnvFileBuildFailed { name })?;
     writeln!(output, "CTR_SOURCE={}", source.as_ref())
         .context(error::EnvFileBuildFailed { name })?;
+    writeln!(output, "CTR_MOUNTS={}", mounts).context(error::EnvFileBuildFailed { name })?;
+    writeln!(output, "CTR_MEMORY_LIMIT_MIB={}", memory_limit_mib)
+        .context(error::EnvFileBuildFailed { name })?;
+    writeln!(output, "CTR_CPU_QUOTA_PERCENT={}", cpu_quota_percent)
+        .context(error::EnvFileBuildFailed { name })?;
 
     writeln!(
         output,
@@ -204,6 +237,82 @@ where
     Ok(())
 }
 
+/// Write out the environment variables that host-ctr sets in the container, one per line, in
+/// the form NAME=VALUE.  The file is read by host-ctr rather than systemd, so the values don't
+/// need quoting.
+fn write_ctr_env_file<S>(
+    name: S,
+    environment: &HashMap<EnvironmentVariableName, SingleLineString>,
+) -> Result<()>
+where
+    S: AsRef<str>,
+{
+    let name = name.as_ref();
+    let filename = format!("{}.ctr-env", name);
+    let path = Path::new(ENV_FILE_DIR).join(filename);
+
+    // Sort for a stable file that's easy to compare across runs.
+    let mut sorted: Vec<_> = environment.iter().collect();
+    sorted.sort_by(|(a, _), (b, _)| a.as_ref().cmp(b.as_ref()));
+
+    let mut output = String::new();
+    for (key, value) in sorted {
+        writeln!(output, "{}={}", key, value).context(error::CtrEnvFileBuildFailed { name })?;
+    }
+
+    fs::write(&path, output).context(error::CtrEnvFileWriteFailed { path })?;
+
+    Ok(())
+}
+
+/// Build the list of extra mounts in the form host-ctr expects: comma-separated entries of
+/// `source:destination:ro` or `source:destination:rw`.  The mount path type guarantees there are
+/// no separators inside the paths.
+fn format_mounts<S>(name: S, mounts: &[model::ContainerMount]) -> Result<String>
+where
+    S: AsRef<str>,
+{
+    let name = name.as_ref();
+    let mut formatted = Vec::with_capacity(mounts.len());
+    for mount in mounts {
+        let source = mount.source.as_ref().context(error::MissingField {
+            name,
+            field: "mounts.source",
+        })?;
+        let destination = mount.destination.as_ref().context(error::MissingField {
+            name,
+            field: "mounts.destination",
+        })?;
+        let mode = if mount.read_only.unwrap_or(false) {
+            "ro"
+        } else {
+            "rw"
+        };
+        formatted.push(format!("{}:{}:{}", source, destination, mode));
+    }
+    Ok(formatted.join(","))
+}
+
+/// Write out the drop-in that sets the restart policy of the container's systemd unit.  Returns
+/// whether the drop-in changed, in which case systemd needs to reload its units.
+fn write_restart_drop_in<S1, S2>(unit_name: S1, restart_policy: S2) -> Result<bool>
+where
+    S1: AsRef<str>,
+    S2: AsRef<str>,
+{
+    let dir = Path::new(UNIT_DROP_IN_DIR).join(format!("{}.d", unit_name.as_ref()));
+    let path = dir.join("restart.conf");
+    let output = format!("[Service]\nRestart={}\n", restart_policy.as_ref());
+
+    if fs::read_to_string(&path).ok().as_deref() == Some(output.as_str()) {
+        return Ok(false);
+    }
+
+    fs::create_dir_all(&dir).context(error::DropInDirCreate { path: &dir })?;
+    fs::write(&path, output).context(error::DropInWriteFailed { path })?;
+    Ok(true)
+}
+
 /// Store the args we receive on the command line
 struct Args {
     log_level: LevelFilter,
@@ -283,6 +392,17 @@ where
         field: "superpowered",
     })?;
 
+    // The remaining settings are optional; without them, the container gets no extra
+    // environment or mounts, no resource limits, and is always restarted.
+    let mounts = format_mounts(name, image_details.mounts.as_deref().unwrap_or_default())?;
+    let memory_limit_mib = image_details.memory_limit_mib.unwrap_or(0);
+    let cpu_quota_percent = image_details.cpu_quota_percent.unwrap_or(0);
+    let restart_policy = image_details
+        .restart_policy
+        .as_ref()
+        .map(|p| p.as_ref())
+        .unwrap_or(DEFAULT_RESTART_POLICY);
+
     info!(
         "Handling host container '{}' which is enabled: {}",
         name, enabled
@@ -290,10 +410,30 @@ where
 
     // Write the environment file needed for the systemd service to have details about this
     // specific host container
-    write_env_file(name, source, enabled, superpowered)?;
+    write_env_file(
+        name,
+        source,
+        enabled,
+        superpowered,
+        &mounts,
+        memory_limit_mib,
+        cpu_quota_percent,
+    )?;
+    write_ctr_env_file(
+        name,
+        image_details
+            .environment
+            .as_ref()
+            .unwrap_or(&HashMap::new()),
+    )?;
 
-    // Now start/stop the container according to the 'enabled' setting
     let unit_name = format!("host-containers@{}.service", name);
+    if write_restart_drop_in(&unit_name, restart_policy)? {
+        debug!("Restart policy of '{}' changed, reloading systemd", name);
+        systemctl(["daemon-reload"])?;
+    }
+
+    // Now start/stop the container according to the 'enabled' setting
     let systemd_unit = SystemdUnit::new(&unit_name);
 
     if enabled {
@@ -0,0 +1,303 @@
+/*!
+# Introduction
+
+kennel writes the static pods in `settings.kubernetes.static-pods` into kubelet's static pod
+directory, so kubelet runs them on the node without the API server.
+
+thar-be-settings renders the static pod settings to a JSON file, and runs kennel as the restart
+command of the `static-pods` service.  kennel then makes the directory match the settings:
+
+* Each enabled pod's manifest is decoded and written to `<name>.yaml`, through a hidden temporary
+  file that kubelet ignores, so kubelet never reads a partial manifest.
+* Manifests that haven't changed aren't written again.
+* Manifests for pods that were removed or disabled are deleted, and kubelet stops those pods.
+
+kubelet watches the directory, so changes take effect without restarting it.  The manifests are
+checked when they're set; kubelet reports problems with the pods themselves in its own log.
+*/
+
+#![deny(rust_2018_idioms)]
+
+use model::modeled_types::{Identifier, StaticPodManifest};
+use model::StaticPod;
+use snafu::ResultExt;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::{env, process};
+
+const DEFAULT_PODS: &str = "/etc/kubernetes/static-pods.json";
+const DEFAULT_DIR: &str = "/etc/kubernetes/static-pods";
+const MANIFEST_EXTENSION: &str = "yaml";
+
+mod error {
+    use snafu::Snafu;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Failed to read '{}': {}", path.display(), source))]
+        ReadFile {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to parse static pods from '{}': {}", path.display(), source))]
+        ParsePods {
+            path: PathBuf,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Invalid manifest for static pod '{}': {}", name, source))]
+        DecodeManifest {
+            name: String,
+            source: base64::DecodeError,
+        },
+
+        #[snafu(display("Failed to create static pod directory '{}': {}", path.display(), source))]
+        CreateDir {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to list static pod directory '{}': {}", path.display(), source))]
+        ListDir {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to write static pod manifest '{}': {}", path.display(), source))]
+        WriteManifest {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to remove static pod manifest '{}': {}", path.display(), source))]
+        RemoveManifest {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+    }
+}
+type Result<T> = std::result::Result<T, error::Error>;
+
+/// Store the args we receive on the command line
+struct Args {
+    pods: PathBuf,
+    dir: PathBuf,
+}
+
+/// Print a usage message in the event a bad arg is passed
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            [ --pods PATH ]
+            [ --dir PATH ]
+
+    Pods defaults to {}
+    Dir defaults to {}",
+        program_name, DEFAULT_PODS, DEFAULT_DIR,
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parse the args to the program and return an Args struct
+fn parse_args(args: env::Args) -> Args {
+    let mut pods = None;
+    let mut dir = None;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--pods" => {
+                pods = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --pods")),
+                )
+            }
+            "--dir" => {
+                dir = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --dir")),
+                )
+            }
+            _ => usage(),
+        }
+    }
+
+    Args {
+        pods: pods.unwrap_or_else(|| DEFAULT_PODS.to_string()).into(),
+        dir: dir.unwrap_or_else(|| DEFAULT_DIR.to_string()).into(),
+    }
+}
+
+/// Reads the static pod settings.  A missing file, or "null", which the template renders if the
+/// settings have never been set, means there are no static pods.
+fn load_pods(path: &Path) -> Result<HashMap<Identifier, StaticPod>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e).context(error::ReadFile { path }),
+    };
+    let pods: Option<HashMap<Identifier, StaticPod>> =
+        serde_json::from_slice(&data).context(error::ParsePods { path })?;
+    Ok(pods.unwrap_or_default())
+}
+
+/// Returns the decoded manifests of the enabled pods, by file name.
+fn manifests(pods: &HashMap<Identifier, StaticPod>) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut manifests = BTreeMap::new();
+    for (name, pod) in pods {
+        let manifest: &StaticPodManifest = match (&pod.manifest, pod.enabled) {
+            (Some(manifest), Some(true)) => manifest,
+            _ => continue,
+        };
+        let decoded = base64::decode(manifest.as_bytes()).context(error::DecodeManifest {
+            name: name.to_string(),
+        })?;
+        manifests.insert(format!("{}.{}", name, MANIFEST_EXTENSION), decoded);
+    }
+    Ok(manifests)
+}
+
+/// Makes the manifests in `dir` match `manifests`, returning the names of the files written and
+/// removed.  Only files with the manifest extension are removed, so hidden temporary files and
+/// anything else in the directory are left alone.
+fn sync_dir(
+    dir: &Path,
+    manifests: &BTreeMap<String, Vec<u8>>,
+) -> Result<(Vec<String>, Vec<String>)> {
+    fs::create_dir_all(dir).context(error::CreateDir { path: dir })?;
+
+    let mut removed = Vec::new();
+    for entry in fs::read_dir(dir).context(error::ListDir { path: dir })? {
+        let path = entry.context(error::ListDir { path: dir })?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        if path
+            .extension()
+            .map_or(false, |ext| ext == MANIFEST_EXTENSION)
+            && !name.starts_with('.')
+            && !manifests.contains_key(&name)
+        {
+            fs::remove_file(&path).context(error::RemoveManifest { path: &path })?;
+            removed.push(name);
+        }
+    }
+
+    let mut written = Vec::new();
+    for (name, manifest) in manifests {
+        let path = dir.join(name);
+        match fs::read(&path) {
+            Ok(current) if current == *manifest => continue,
+            _ => {}
+        }
+        // kubelet ignores hidden files, so it never sees the manifest half-written.
+        let tmp = dir.join(format!(".{}.tmp", name));
+        fs::write(&tmp, manifest)
+            .and_then(|()| fs::rename(&tmp, &path))
+            .context(error::WriteManifest { path: &path })?;
+        written.push(name.clone());
+    }
+    Ok((written, removed))
+}
+
+fn run() -> Result<()> {
+    let args = parse_args(env::args());
+    let pods = load_pods(&args.pods)?;
+    let (written, removed) = sync_dir(&args.dir, &manifests(&pods)?)?;
+    for name in written {
+        println!(
+            "Wrote static pod manifest '{}'",
+            args.dir.join(name).display()
+        );
+    }
+    for name in removed {
+        println!(
+            "Removed static pod manifest '{}'",
+            args.dir.join(name).display()
+        );
+    }
+    Ok(())
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn pods(json: &str) -> HashMap<Identifier, StaticPod> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn only_enabled_pods() {
+        let manifest = base64::encode("kind: Pod\n");
+        let pods = pods(&format!(
+            r#"{{"agent": {{"manifest": "{0}", "enabled": true}},
+                "off": {{"manifest": "{0}", "enabled": false}},
+                "unset": {{"manifest": "{0}"}}}}"#,
+            manifest
+        ));
+        let manifests = manifests(&pods).unwrap();
+        assert_eq!(manifests.keys().collect::<Vec<_>>(), vec!["agent.yaml"]);
+        assert_eq!(manifests["agent.yaml"], b"kind: Pod\n");
+    }
+
+    #[test]
+    fn missing_and_null_settings() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("static-pods.json");
+        assert!(load_pods(&path).unwrap().is_empty());
+        fs::write(&path, "null").unwrap();
+        assert!(load_pods(&path).unwrap().is_empty());
+        fs::write(&path, r#"{"agent": {"manifest": "not base64"}}"#).unwrap();
+        load_pods(&path).unwrap_err();
+    }
+
+    #[test]
+    fn sync() {
+        let dir = TempDir::new().unwrap();
+        let pods_dir = dir.path().join("static-pods");
+        let mut manifests = BTreeMap::new();
+        manifests.insert("a.yaml".to_string(), b"a".to_vec());
+        manifests.insert("b.yaml".to_string(), b"b".to_vec());
+
+        let (written, removed) = sync_dir(&pods_dir, &manifests).unwrap();
+        assert_eq!(written, vec!["a.yaml", "b.yaml"]);
+        assert!(removed.is_empty());
+
+        // Unchanged manifests aren't written again; ones no longer set are removed, but other
+        // files are left alone.
+        fs::write(pods_dir.join("notes.txt"), "mine").unwrap();
+        manifests.remove("b.yaml");
+        let (written, removed) = sync_dir(&pods_dir, &manifests).unwrap();
+        assert!(written.is_empty());
+        assert_eq!(removed, vec!["b.yaml"]);
+        assert_eq!(fs::read(pods_dir.join("a.yaml")).unwrap(), b"a");
+        assert!(!pods_dir.join("b.yaml").exists());
+        assert!(pods_dir.join("notes.txt").exists());
+        assert!(!pods_dir.join(".a.yaml.tmp").exists());
+    }
+}
@@ -31,6 +31,94 @@ impl Migration for AddSettingMigration {
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
+/// We use this migration when we add several settings at once and want to make sure they're all
+/// removed before we go back to old versions that don't understand them.
+pub struct AddSettingsMigration(pub &'static [&'static str]);
+
+impl Migration for AddSettingsMigration {
+    /// New versions must either have a default for the settings or generate them; we don't need
+    /// to do anything.
+    fn forward(&mut self, input: MigrationData) -> Result<MigrationData> {
+        println!(
+            "AddSettingsMigration({}) has no work to do on upgrade.",
+            self.0.join(", ")
+        );
+        Ok(input)
+    }
+
+    /// Older versions don't know about the settings; we remove them so that old versions don't
+    /// see them and fail deserialization.  (The settings must be defaulted or generated in new
+    /// versions, and safe to remove.)
+    fn backward(&mut self, input: MigrationData) -> Result<MigrationData> {
+        let mut migrated = input;
+        for setting in self.0 {
+            migrated = AddSettingMigration(setting).backward(migrated)?;
+        }
+        Ok(migrated)
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// We use this migration when we add a subtree of settings whose keys we can't list ahead of time,
+/// like a map keyed by user-chosen names, and want to make sure it's all removed before we go back
+/// to old versions that don't understand it.  Each prefix is a dotted key; the key itself and any
+/// key under it are removed, along with their metadata.
+pub struct AddPrefixesMigration(pub &'static [&'static str]);
+
+impl AddPrefixesMigration {
+    /// Returns whether `key` is one of our prefixes or is under one of them.
+    fn matches(&self, key: &str) -> bool {
+        self.0.iter().any(|prefix| {
+            key == *prefix || (key.starts_with(prefix) && key[prefix.len()..].starts_with('.'))
+        })
+    }
+}
+
+impl Migration for AddPrefixesMigration {
+    /// New versions must either have a default for the settings or generate them; we don't need
+    /// to do anything.
+    fn forward(&mut self, input: MigrationData) -> Result<MigrationData> {
+        println!(
+            "AddPrefixesMigration({}) has no work to do on upgrade.",
+            self.0.join(", ")
+        );
+        Ok(input)
+    }
+
+    /// Older versions don't know about the settings; we remove them so that old versions don't
+    /// see them and fail deserialization.
+    fn backward(&mut self, mut input: MigrationData) -> Result<MigrationData> {
+        let data_keys: Vec<String> = input
+            .data
+            .keys()
+            .filter(|key| self.matches(key))
+            .cloned()
+            .collect();
+        if data_keys.is_empty() {
+            println!("Found no data under {} to remove", self.0.join(", "));
+        }
+        for key in data_keys {
+            if let Some(data) = input.data.remove(&key) {
+                println!("Removed {}, which was set to '{}'", key, data);
+            }
+        }
+
+        let metadata_keys: Vec<String> = input
+            .metadata
+            .keys()
+            .filter(|key| self.matches(key))
+            .cloned()
+            .collect();
+        for key in metadata_keys {
+            if input.metadata.remove(&key).is_some() {
+                println!("Removed metadata for {}", key);
+            }
+        }
+        Ok(input)
+    }
+}
+
 /// We use this migration when we remove a setting from the model, so the new version doesn't see
 /// it and error.
 pub struct RemoveSettingMigration(pub &'static str);
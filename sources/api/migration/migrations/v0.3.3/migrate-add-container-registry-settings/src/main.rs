@@ -0,0 +1,27 @@
+#![deny(rust_2018_idioms)]
+
+use migration_helpers::common_migrations::AddSettingsMigration;
+use migration_helpers::{migrate, Result};
+use std::process;
+
+/// We added settings for container registry mirrors and credentials.  Both are lists, which are
+/// stored as a single key each.  The configuration files the credentials are written to were
+/// given a mode, so only root can read them, which older versions don't understand either.
+fn run() -> Result<()> {
+    migrate(AddSettingsMigration(&[
+        "settings.container-registry.mirrors",
+        "settings.container-registry.credentials",
+        "configuration-files.containerd-config-toml.mode",
+        "configuration-files.host-containers-registry-toml.mode",
+    ]))
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,27 @@
+#![deny(rust_2018_idioms)]
+
+use migration_helpers::common_migrations::AddPrefixesMigration;
+use migration_helpers::{migrate, Result};
+use std::process;
+
+/// We added settings for static network configuration, which are keyed by interface name, along
+/// with the service that persists them for use at boot.  We also added proxy settings, with the
+/// service and configuration file that pass them to other services.
+fn run() -> Result<()> {
+    migrate(AddPrefixesMigration(&[
+        "settings.network",
+        "services.network",
+        "services.proxy",
+        "configuration-files.proxy-env",
+    ]))
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,58 @@
+#![deny(rust_2018_idioms)]
+
+use migration_helpers::common_migrations::AddSettingMigration;
+use migration_helpers::{migrate, Migration, MigrationData, Result};
+use serde_json::json;
+use std::process;
+
+const NTP_PREFIX: &str = "settings.ntp";
+const AFFECTED_SERVICES: &str = "affected-services";
+
+/// We added a setting for the options given to each NTP server.  At the same time, we fixed the
+/// metadata that tells the settings applier which service to update when NTP settings change; it
+/// named "chronyd" rather than the "ntp" service, so changes weren't applied until reboot.
+struct AddNtpOptions;
+
+impl Migration for AddNtpOptions {
+    /// The new setting has a default, but storewolf doesn't replace existing metadata, so we fix
+    /// the affected service here.
+    fn forward(&mut self, mut input: MigrationData) -> Result<MigrationData> {
+        if let Some(metadata) = input.metadata.get_mut(NTP_PREFIX) {
+            if let Some(services) = metadata.get_mut(AFFECTED_SERVICES) {
+                if *services == json!(["chronyd"]) {
+                    *services = json!(["ntp"]);
+                    println!(
+                        "Changed {} of {} from 'chronyd' to 'ntp'",
+                        AFFECTED_SERVICES, NTP_PREFIX
+                    );
+                } else {
+                    println!(
+                        "{} of {} is set to '{}', leaving alone",
+                        AFFECTED_SERVICES, NTP_PREFIX, services
+                    );
+                }
+            }
+        }
+        Ok(input)
+    }
+
+    /// Older versions don't know about the new setting, so we remove it.  The fixed metadata
+    /// works for older versions too, so we leave it.
+    fn backward(&mut self, input: MigrationData) -> Result<MigrationData> {
+        AddSettingMigration("settings.ntp.options").backward(input)
+    }
+}
+
+fn run() -> Result<()> {
+    migrate(AddNtpOptions)
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,25 @@
+#![deny(rust_2018_idioms)]
+
+use migration_helpers::common_migrations::AddPrefixesMigration;
+use migration_helpers::{migrate, Result};
+use std::process;
+
+/// We added settings for additional CA certificates, which are keyed by user-chosen names, along
+/// with the service and configuration file that write them to the system trust store.
+fn run() -> Result<()> {
+    migrate(AddPrefixesMigration(&[
+        "settings.pki",
+        "services.pki",
+        "configuration-files.pki-trusted-certs",
+    ]))
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
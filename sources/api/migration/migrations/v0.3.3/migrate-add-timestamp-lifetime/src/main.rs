@@ -0,0 +1,23 @@
+#![deny(rust_2018_idioms)]
+
+use migration_helpers::common_migrations::AddPrefixesMigration;
+use migration_helpers::{migrate, Result};
+use std::process;
+
+/// We added a setting for how long the update repository's timestamp metadata is good for, so
+/// updog can check waves against it, settings.updates.timestamp-lifetime-hours.
+fn run() -> Result<()> {
+    migrate(AddPrefixesMigration(&[
+        "settings.updates.timestamp-lifetime-hours",
+    ]))
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
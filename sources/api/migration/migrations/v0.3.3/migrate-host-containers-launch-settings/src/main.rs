@@ -0,0 +1,78 @@
+#![deny(rust_2018_idioms)]
+
+use migration_helpers::{migrate, Migration, MigrationData, Result};
+use std::process;
+
+const PREFIX: &str = "settings.host-containers.";
+
+/// The settings we added to each host container.  "environment" is a map, so any key under it is
+/// included.
+const ADDED_SETTINGS: &[&str] = &[
+    "environment",
+    "mounts",
+    "memory-limit-mib",
+    "cpu-quota-percent",
+    "restart-policy",
+];
+
+/// We added settings for the environment, mounts, resource limits, and restart policy of host
+/// containers.  Host containers can have any name, so we can't list the keys individually as in
+/// AddSettingMigration; instead we match the setting name after the container name.
+struct HostContainersLaunchSettings;
+
+/// Returns whether `key` is one of the added settings for some host container.
+fn is_added_setting(key: &str) -> bool {
+    if !key.starts_with(PREFIX) {
+        return false;
+    }
+    // The container name is an Identifier, so it can't contain the separator; the next
+    // segment is the setting name.
+    let mut segments = key[PREFIX.len()..].splitn(3, '.');
+    let _name = segments.next();
+    match segments.next() {
+        Some(setting) => ADDED_SETTINGS.contains(&setting),
+        None => false,
+    }
+}
+
+impl Migration for HostContainersLaunchSettings {
+    /// New versions treat the settings as optional; we don't need to do anything.
+    fn forward(&mut self, input: MigrationData) -> Result<MigrationData> {
+        println!("HostContainersLaunchSettings has no work to do on upgrade.");
+        Ok(input)
+    }
+
+    /// Older versions don't know about the settings; we remove them so that old versions don't
+    /// see them and fail deserialization.
+    fn backward(&mut self, mut input: MigrationData) -> Result<MigrationData> {
+        let keys: Vec<String> = input
+            .data
+            .keys()
+            .filter(|key| is_added_setting(key))
+            .cloned()
+            .collect();
+        if keys.is_empty() {
+            println!("Found no host container launch settings to remove");
+        }
+        for key in keys {
+            if let Some(data) = input.data.remove(&key) {
+                println!("Removed {}, which was set to '{}'", key, data);
+            }
+        }
+        Ok(input)
+    }
+}
+
+fn run() -> Result<()> {
+    migrate(HostContainersLaunchSettings)
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
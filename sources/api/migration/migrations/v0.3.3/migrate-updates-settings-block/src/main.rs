@@ -0,0 +1,76 @@
+#![deny(rust_2018_idioms)]
+
+use migration_helpers::{migrate, Migration, MigrationData, Result};
+use serde_json::Value;
+use std::process;
+
+const SEED_SETTING: &str = "settings.updates.seed";
+const UPDATES_SETTING: &str = "settings.updates";
+const GENERATOR_KEY: &str = "setting-generator";
+const OLD_GENERATOR: &str = "bork seed";
+const NEW_GENERATOR: &str = "bork updates";
+
+/// The settings we added to the updates block.
+const ADDED_SETTINGS: &[&str] = &[
+    "settings.updates.version-lock",
+    "settings.updates.ignore-waves",
+    "settings.updates.channel",
+];
+
+/// We added settings for the update version lock, ignoring waves, and channel, and bork now
+/// generates the whole updates block rather than just the seed.  The generator moves from the seed
+/// setting to the updates block, and back again on downgrade.
+struct UpdatesSettingsBlock;
+
+/// Removes the given generator from the metadata of `from`, if it's set, and sets `generator` on
+/// `to`.
+fn move_generator(input: &mut MigrationData, from: &str, to: &str, generator: &str) {
+    if let Some(metadata) = input.metadata.get_mut(from) {
+        if let Some(old) = metadata.remove(GENERATOR_KEY) {
+            println!(
+                "Removed {} from '{}', which was '{}'",
+                GENERATOR_KEY, from, old
+            );
+        }
+    }
+    input.metadata.entry(to.to_string()).or_default().insert(
+        GENERATOR_KEY.to_string(),
+        Value::String(generator.to_string()),
+    );
+    println!("Set {} of '{}' to '{}'", GENERATOR_KEY, to, generator);
+}
+
+impl Migration for UpdatesSettingsBlock {
+    fn forward(&mut self, mut input: MigrationData) -> Result<MigrationData> {
+        move_generator(&mut input, SEED_SETTING, UPDATES_SETTING, NEW_GENERATOR);
+        Ok(input)
+    }
+
+    /// Older versions don't know about the new settings, and their bork only generates a seed, so
+    /// we remove the settings and point the generator back at the seed.
+    fn backward(&mut self, mut input: MigrationData) -> Result<MigrationData> {
+        for setting in ADDED_SETTINGS {
+            if let Some(data) = input.data.remove(*setting) {
+                println!("Removed {}, which was set to '{}'", setting, data);
+            } else {
+                println!("Found no {} to remove", setting);
+            }
+        }
+        move_generator(&mut input, UPDATES_SETTING, SEED_SETTING, OLD_GENERATOR);
+        Ok(input)
+    }
+}
+
+fn run() -> Result<()> {
+    migrate(UpdatesSettingsBlock)
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
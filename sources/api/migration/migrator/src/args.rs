@@ -8,6 +8,7 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::process;
 use std::str::FromStr;
+use updater_config::{Layer, SETTINGS_FILE};
 
 /// Informs the user about proper usage of the program and exits.
 fn usage() -> ! {
@@ -17,8 +18,10 @@ fn usage() -> ! {
             --datastore-path PATH
             --migration-directories PATH[:PATH:PATH...]
             (--migrate-to-version x.y | --migrate-to-version-from-os-release)
+            [ --status-path PATH ]
             [ --no-color ]
-            [ --log-level trace|debug|info|warn|error ]",
+            [ --log-level trace|debug|info|warn|error ]
+            [ --show-config ]",
         program_name
     );
     process::exit(2);
@@ -36,6 +39,7 @@ pub(crate) struct Args {
     pub(crate) log_level: LevelFilter,
     pub(crate) migration_directories: Vec<PathBuf>,
     pub(crate) migrate_to_version: Version,
+    pub(crate) status_path: Option<PathBuf>,
 }
 
 impl Args {
@@ -46,6 +50,8 @@ impl Args {
         let mut log_level = None;
         let mut migration_directories = None;
         let mut migrate_to_version = None;
+        let mut status_path = None;
+        let mut show_config = false;
 
         let mut iter = args.skip(1);
         while let Some(arg) = iter.next() {
@@ -114,15 +120,51 @@ impl Args {
                     migrate_to_version = Some(br.version_id)
                 }
 
+                "--status-path" => {
+                    let path_str = iter
+                        .next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --status-path"));
+                    status_path = Some(PathBuf::from(path_str));
+                }
+
+                "--show-config" => show_config = true,
+
                 _ => usage(),
             }
         }
 
+        // The log level can also be set in the settings shared by the updater's components.
+        let command_line = Layer {
+            log_level,
+            ..Layer::default()
+        };
+        let shared = updater_config::load(None, Path::new(SETTINGS_FILE), command_line);
+        if show_config {
+            match shared {
+                Ok(config) => println!("{}", config),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+            process::exit(0);
+        }
+        // Migrations have to run for the host to boot, so a problem with the shared config isn't
+        // worth stopping for.
+        let log_level = match shared {
+            Ok(config) => config.log_level.value,
+            Err(e) => {
+                eprintln!("Ignoring shared config: {}", e);
+                log_level.unwrap_or(LevelFilter::Info)
+            }
+        };
+
         Self {
             datastore_path: datastore_path.unwrap_or_else(|| usage()),
-            log_level: log_level.unwrap_or_else(|| LevelFilter::Info),
+            log_level,
             migration_directories: migration_directories.unwrap_or_else(|| usage()),
             migrate_to_version: migrate_to_version.unwrap_or_else(|| usage()),
+            status_path,
         }
     }
 }
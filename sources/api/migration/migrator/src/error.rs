@@ -13,20 +13,14 @@ pub(crate) enum Error {
     #[snafu(display("Internal error: {}", msg))]
     Internal { msg: String },
 
-    #[snafu(display("Data store path '{}' contains invalid UTF-8", path.display()))]
-    DataStorePathNotUTF8 { path: PathBuf },
-
     #[snafu(display("Unable to open data store directory '{}': {}", path.display(), source))]
     DataStoreDirOpen { path: PathBuf, source: nix::Error },
 
     #[snafu(display("Data store link '{}' points to /", path.display()))]
     DataStoreLinkToRoot { path: PathBuf },
 
-    #[snafu(display("Data store path '{}' contains invalid version: {}", path.display(), source))]
-    InvalidDataStoreVersion {
-        path: PathBuf,
-        source: semver::SemVerError,
-    },
+    #[snafu(display("Unable to find version of data store: {}", source))]
+    DataStoreVersion { source: bottlerocket_release::Error },
 
     #[snafu(display("Migration '{}' contains invalid version: {}", path.display(), source))]
     InvalidMigrationVersion {
@@ -53,9 +47,6 @@ pub(crate) enum Error {
     #[snafu(display("Failed to swap symlink at {} to new version: {}", link.display(), source))]
     LinkSwap { link: PathBuf, source: io::Error },
 
-    #[snafu(display("Failed to read symlink at {} to find version: {}", link.display(), source))]
-    LinkRead { link: PathBuf, source: io::Error },
-
     #[snafu(display("Failed listing migration directory '{}': {}", dir.display(), source))]
     ListMigrations { dir: PathBuf, source: io::Error },
 
@@ -15,6 +15,26 @@
 //!   * just symlink to the old data store
 //! * do symlink flips so the new version takes the place of the original
 //!
+//! If given `--status-path`, it saves what it did there as JSON, like updog's status file.  A
+//! failure is saved as a broad class, `disk`, `migration`, or `other`, with a short hint at what to
+//! check, rather than the full error:
+//!
+//! ```json
+//! {"time":"2020-03-26T20:00:00Z","from-version":"0.3.2","to-version":"0.3.3","result":"failed","failure":"migration","remediation":"Check the failed migration's output with `journalctl -u migrator`, and that the update's migrations were all downloaded"}
+//! ```
+//!
+//! `result` is `current` if the data store was already at the requested version, `migrated`, or
+//! `failed`.
+//!
+//! The log level is resolved like updog's and signpost's, from `/etc/updater.toml`, then
+//! `UPDATER_LOG_LEVEL`, then `--log-level`; `--show-config` prints what it would be, and where it
+//! was set, and exits.  The settings file isn't there yet when the migrator runs at boot, so it
+//! usually comes down to the command line.
+//!
+//! Moving the data store to another version, and each migration run along the way, are recorded
+//! in the journal as structured audit entries, `datastore-migrate` and `migration-run`, with whether
+//! they succeeded; see the audit library for the fields.
+//!
 //! To understand motivation and more about the overall process, look at the migration system
 //! documentation, one level up.
 
@@ -23,6 +43,7 @@
 #[macro_use]
 extern crate log;
 
+use audit::{Action, Actor, Outcome};
 use nix::{dir::Dir, fcntl::OFlag, sys::stat::Mode, unistd::fsync};
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use semver::Version;
@@ -40,24 +61,33 @@ use migrator::MIGRATION_FILENAME_RE;
 mod args;
 mod direction;
 mod error;
+mod status;
 
 use args::Args;
 use direction::Direction;
 use error::Result;
+use status::Status;
 
 // Returning a Result from main makes it print a Debug representation of the error, but with Snafu
 // we have nice Display representations of the error, so we wrap "main" (run) and print any error.
 // https://github.com/shepmaster/snafu/issues/110
 fn main() {
-    if let Err(e) = run() {
+    let args = Args::from_env(env::args());
+    let mut status = Status::new(&args.migrate_to_version);
+    let result = run(&args, &mut status);
+    if let Some(path) = &args.status_path {
+        status.finish(result.as_ref().map(|_| ()));
+        if let Err(e) = status.write(path) {
+            eprintln!("Failed to save status to {}: {}", path.display(), e);
+        }
+    }
+    if let Err(e) = result {
         eprintln!("{}", e);
         process::exit(1);
     }
 }
 
-fn run() -> Result<()> {
-    let args = Args::from_env(env::args());
-
+fn run(args: &Args, status: &mut Status) -> Result<()> {
     // TerminalMode::Mixed will send errors to stderr and anything less to stdout.
     TermLogger::init(args.log_level, LogConfig::default(), TerminalMode::Mixed)
         .context(error::Logger)?;
@@ -70,21 +100,38 @@ fn run() -> Result<()> {
             path: &args.datastore_path,
         })?;
 
-    let current_version = get_current_version(&datastore_dir)?;
+    let current_version =
+        bottlerocket_release::datastore_version(datastore_dir).context(error::DataStoreVersion)?;
+    status.from_version = Some(current_version.clone());
 
-    let direction = Direction::from_versions(&current_version, &args.migrate_to_version)
-        .unwrap_or_else(|| {
+    let direction = match Direction::from_versions(&current_version, &args.migrate_to_version) {
+        Some(direction) => direction,
+        None => {
             info!(
                 "Requested version {} matches version of given datastore at '{}'; nothing to do",
                 args.migrate_to_version,
                 args.datastore_path.display()
             );
-            process::exit(0);
-        });
+            return Ok(());
+        }
+    };
+
+    let result = migrate(args, direction, &current_version);
+    audit::record(
+        Actor::Migrator,
+        Action::DatastoreMigrate,
+        format!("{} to {}", current_version, args.migrate_to_version),
+        &result,
+    );
+    result
+}
 
+/// Runs the migrations from `current_version` to the requested version, if there are any, and
+/// flips the data store links so the result is live.
+fn migrate(args: &Args, direction: Direction, current_version: &Version) -> Result<()> {
     let migrations = find_migrations(
         &args.migration_directories,
-        &current_version,
+        current_version,
         &args.migrate_to_version,
     )?;
 
@@ -109,35 +156,6 @@ fn run() -> Result<()> {
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
-fn get_current_version<P>(datastore_dir: P) -> Result<Version>
-where
-    P: AsRef<Path>,
-{
-    let datastore_dir = datastore_dir.as_ref();
-
-    // Find the current patch version link, which contains our full version number
-    let current = datastore_dir.join("current");
-    let major =
-        datastore_dir.join(fs::read_link(&current).context(error::LinkRead { link: current })?);
-    let minor = datastore_dir.join(fs::read_link(&major).context(error::LinkRead { link: major })?);
-    let patch = datastore_dir.join(fs::read_link(&minor).context(error::LinkRead { link: minor })?);
-
-    // Pull out the basename of the path, which contains the version
-    let version_os_str = patch
-        .file_name()
-        .context(error::DataStoreLinkToRoot { path: &patch })?;
-    let mut version_str = version_os_str
-        .to_str()
-        .context(error::DataStorePathNotUTF8 { path: &patch })?;
-
-    // Allow 'v' at the start so the links have clearer names for humans
-    if version_str.starts_with('v') {
-        version_str = &version_str[1..];
-    }
-
-    Version::parse(version_str).context(error::InvalidDataStoreVersion { path: &patch })
-}
-
 /// Returns a list of all migrations found on disk.
 ///
 /// TODO: This does not yet handle migrations that have been replaced by newer versions - we only
@@ -396,6 +414,17 @@ where
             debug!("No migration stderr");
         }
 
+        let outcome = if output.status.success() {
+            Outcome::Success
+        } else {
+            Outcome::Failure(output.status.to_string())
+        };
+        audit::record(
+            Actor::Migrator,
+            Action::MigrationRun,
+            migration.as_ref().display().to_string(),
+            outcome,
+        );
         ensure!(output.status.success(), error::MigrationFailure { output });
 
         source_datastore = &target_datastore;
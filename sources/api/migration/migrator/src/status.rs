@@ -0,0 +1,160 @@
+//! This module saves what the migrator did at boot, so a failed migration can be seen without
+//! reading the console.  Like updog's status file, failures are saved as a broad class with a
+//! short hint, never as the full error, and the classes are the ones updog uses.
+
+use crate::error::Error;
+use chrono::{DateTime, Utc};
+use semver::Version;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum MigrationResult {
+    /// The data store was already at the requested version.
+    Current,
+    /// The data store was migrated, or linked, to the requested version.
+    Migrated,
+    Failed,
+}
+
+/// The broad reason the migrator failed, named the same as updog's failure classes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum FailureClass {
+    /// The data store or migrations couldn't be read or changed on disk.
+    Disk,
+    /// A migration was invalid, couldn't be started, or failed.
+    Migration,
+    Other,
+}
+
+impl From<&Error> for FailureClass {
+    fn from(err: &Error) -> Self {
+        match err {
+            Error::DataStoreDirOpen { .. }
+            | Error::DataStoreLinkToRoot { .. }
+            | Error::DataStoreVersion { .. }
+            | Error::LinkCreate { .. }
+            | Error::LinkSwap { .. }
+            | Error::ListMigrations { .. }
+            | Error::ReadMigrationEntry { .. }
+            | Error::PathMetadata { .. }
+            | Error::SetPermissions { .. } => Self::Disk,
+
+            Error::InvalidMigrationVersion { .. }
+            | Error::MigrationNameNotUTF8 { .. }
+            | Error::NewVersionAlreadyExists { .. }
+            | Error::StartMigration { .. }
+            | Error::MigrationFailure { .. } => Self::Migration,
+
+            Error::Internal { .. } | Error::Logger { .. } => Self::Other,
+        }
+    }
+}
+
+impl FailureClass {
+    /// A short hint at what to check for this class of failure.
+    pub(crate) fn remediation(self) -> &'static str {
+        match self {
+            Self::Disk => "Check the journal for I/O errors from the host's disk",
+            Self::Migration => "Check the failed migration's output with `journalctl -u migrator`, and that the update's migrations were all downloaded",
+            Self::Other => "Check the migrator's full error with `journalctl -u migrator`",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Status {
+    time: DateTime<Utc>,
+    /// The data store's version before migrating, if the migrator got far enough to find it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) from_version: Option<Version>,
+    to_version: Version,
+    result: MigrationResult,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failure: Option<FailureClass>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remediation: Option<&'static str>,
+}
+
+impl Status {
+    /// Starts the status for a migration to `to_version`; until `finish` is called, it reads as
+    /// failed.
+    pub(crate) fn new(to_version: &Version) -> Self {
+        Self {
+            time: Utc::now(),
+            from_version: None,
+            to_version: to_version.clone(),
+            result: MigrationResult::Failed,
+            failure: Some(FailureClass::Other),
+            remediation: Some(FailureClass::Other.remediation()),
+        }
+    }
+
+    /// Records how the migration ended.
+    pub(crate) fn finish(&mut self, run: Result<(), &Error>) {
+        self.time = Utc::now();
+        let (result, failure) = match run {
+            Ok(()) if self.from_version.as_ref() == Some(&self.to_version) => {
+                (MigrationResult::Current, None)
+            }
+            Ok(()) => (MigrationResult::Migrated, None),
+            Err(e) => (MigrationResult::Failed, Some(FailureClass::from(e))),
+        };
+        self.result = result;
+        self.failure = failure;
+        self.remediation = failure.map(FailureClass::remediation);
+    }
+
+    /// Writes the status to `path`, replacing the file all at once so readers never see part of
+    /// it.
+    pub(crate) fn write(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let data = serde_json::to_vec(self).map_err(io::Error::from)?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn status_json() {
+        let from = Version::parse("0.3.2").unwrap();
+        let to = Version::parse("0.3.3").unwrap();
+
+        let mut status = Status::new(&to);
+        status.from_version = Some(from);
+        status.finish(Ok(()));
+        let value = serde_json::to_value(&status).unwrap();
+        assert_eq!(value["from-version"], "0.3.2");
+        assert_eq!(value["to-version"], "0.3.3");
+        assert_eq!(value["result"], "migrated");
+        assert!(value.get("failure").is_none());
+
+        let err = Error::ListMigrations {
+            dir: PathBuf::from("/var/lib/bottlerocket-migrations"),
+            source: io::Error::from(io::ErrorKind::PermissionDenied),
+        };
+        status.finish(Err(&err));
+        let value = serde_json::to_value(&status).unwrap();
+        assert_eq!(value["result"], "failed");
+        assert_eq!(value["failure"], "disk");
+        assert_eq!(value["remediation"], FailureClass::Disk.remediation());
+
+        let mut status = Status::new(&to);
+        status.from_version = Some(to.clone());
+        status.finish(Ok(()));
+        assert_eq!(status.result, MigrationResult::Current);
+    }
+}
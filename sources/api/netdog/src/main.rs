@@ -1,9 +1,35 @@
 /*!
 # Introduction
 
-netdog is a small helper program for wicked, to apply network settings received from DHCP.
-
-It generates `/etc/resolv.conf` and sets the hostname.
+netdog is a small helper program for wicked, to apply network settings received from DHCP, and to
+configure network interfaces from `settings.network`.
+
+When called by wicked with a DHCP lease for the primary interface, it generates `/etc/resolv.conf`
+and sets the hostname.  If static name servers are configured, they're used instead of the ones
+from the lease.  If the primary interface only has static addresses, the hostname is built from
+its first address, like `ip-10-0-0-10`.
+
+`netdog write-net-config` reads `settings.network` from the API and persists it to
+`/var/lib/bottlerocket/net.toml`.  Network settings have to be available before the network (and
+the API) are up, so at boot, `netdog generate-net-config` reads that file and writes the wicked
+interface configuration and static resolver configuration.  If no interfaces are configured, the
+primary interface (eth0) uses DHCP.
+
+Interfaces are configured like this:
+
+```toml
+[settings.network]
+name-servers = ["10.0.0.2"]
+search-list = ["example.com"]
+
+[settings.network.interfaces.eth0]
+primary = true
+addresses = ["10.0.0.10/24"]
+routes = [{ to = "0.0.0.0/0", via = "10.0.0.1" }]
+
+[settings.network.interfaces.eth1]
+dhcp4 = true
+```
 */
 
 // TODO:
@@ -15,15 +41,19 @@ It generates `/etc/resolv.conf` and sets the hostname.
 
 #![deny(rust_2018_idioms)]
 
+mod net_config;
+mod wicked;
+
 use dns_lookup::lookup_addr;
 use envy;
 use ipnet::IpNet;
 use lazy_static::lazy_static;
+use net_config::NetConfig;
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 use regex::Regex;
 use serde::Deserialize;
-use snafu::ResultExt;
+use snafu::{ensure, ResultExt};
 use std::collections::BTreeSet;
 use std::fmt::Write;
 use std::fs::{self, File};
@@ -34,6 +64,11 @@ use std::{env, process};
 
 static RESOLV_CONF: &str = "/etc/resolv.conf";
 static KERNEL_HOSTNAME: &str = "/proc/sys/kernel/hostname";
+static NET_CONFIG: &str = "/var/lib/bottlerocket/net.toml";
+static WICKED_IFCONFIG_DIR: &str = "/etc/wicked/ifconfig";
+static FACTORY_IFCONFIG_DIR: &str = "/usr/share/factory/etc/wicked/ifconfig";
+static DEFAULT_API_SOCKET: &str = "/run/api.sock";
+static API_SETTINGS_URI: &str = "/settings";
 
 // Matches wicked's shell-like syntax for DHCP lease variables:
 //     FOO='BAR' -> key=FOO, val=BAR
@@ -44,6 +79,8 @@ lazy_static! {
 /// Potential errors during netdog execution
 mod error {
     use envy;
+    use http::StatusCode;
+    use ipnet::IpNet;
     use snafu::Snafu;
     use std::io;
     use std::net::IpAddr;
@@ -70,6 +107,75 @@ mod error {
 
         #[snafu(display("Failed to write hostname to '{}': {}", path.display(), source))]
         HostnameWriteFailed { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Error sending {} to {}: {}", method, uri, source))]
+        APIRequest {
+            method: String,
+            uri: String,
+            source: apiclient::Error,
+        },
+
+        #[snafu(display("Error {} when sending {} to {}: {}", code, method, uri, response_body))]
+        APIResponse {
+            method: String,
+            uri: String,
+            code: StatusCode,
+            response_body: String,
+        },
+
+        #[snafu(display(
+            "Error deserializing response as JSON from {} to {}: {}",
+            method,
+            uri,
+            source
+        ))]
+        ResponseJson {
+            method: &'static str,
+            uri: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Failed to read network configuration from '{}': {}", path.display(), source))]
+        NetConfigReadFailed { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Failed to parse network configuration from '{}': {}", path.display(), source))]
+        NetConfigParseFailed {
+            path: PathBuf,
+            source: toml::de::Error,
+        },
+
+        #[snafu(display("Failed to serialize network configuration: {}", source))]
+        NetConfigSerializeFailed { source: toml::ser::Error },
+
+        #[snafu(display("Failed to write network configuration to '{}': {}", path.display(), source))]
+        NetConfigWriteFailed { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Interface '{}' has no addresses and doesn't use DHCP", interface))]
+        InterfaceUnaddressed { interface: String },
+
+        #[snafu(display(
+            "Route to {} on interface '{}' uses gateway {} from a different address family",
+            to,
+            interface,
+            via
+        ))]
+        RouteFamilyMismatch {
+            interface: String,
+            to: IpNet,
+            via: IpAddr,
+        },
+
+        #[snafu(display(
+            "Exactly one interface must be primary when several are configured, found {}",
+            count
+        ))]
+        PrimaryInterfaceAmbiguous { count: usize },
+
+        #[snafu(display("Failed to build wicked configuration: {}", source))]
+        WickedConfigBuildFailed { source: std::fmt::Error },
+
+        #[snafu(display("Failed to write wicked configuration to '{}': {}", path.display(), source))]
+        WickedConfigWriteFailed { path: PathBuf, source: io::Error },
     }
 }
 
@@ -80,12 +186,8 @@ type Result<T> = std::result::Result<T, error::Error>;
 enum SubCommand {
     Install,
     Remove,
-}
-
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "kebab-case")]
-enum InterfaceName {
-    Eth0,
+    GenerateNetConfig,
+    WriteNetConfig,
 }
 
 #[derive(Debug, Deserialize)]
@@ -103,9 +205,17 @@ enum InterfaceFamily {
 
 /// Stores user-supplied arguments.
 #[derive(Debug)]
-struct Args {
-    sub_command: SubCommand,
-    interface_name: InterfaceName,
+enum Args {
+    Install(WickedArgs),
+    Remove(WickedArgs),
+    GenerateNetConfig,
+    WriteNetConfig { socket_path: PathBuf },
+}
+
+/// Stores the arguments wicked passes to its system updater.
+#[derive(Debug)]
+struct WickedArgs {
+    interface_name: String,
     interface_type: InterfaceType,
     interface_family: InterfaceFamily,
     data_file: PathBuf,
@@ -128,13 +238,20 @@ struct LeaseInfo {
 fn usage() -> ! {
     let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
     eprintln!(
-        r"Usage: {}
-            COMMAND
+        r"Usage: {0}
+            (install | remove)
               -i INTERFACE_NAME
               -t INTERFACE_TYPE
               -f INTERFACE_FAMILY
-              DATA_FILE",
-        program_name
+              DATA_FILE
+
+       {0} generate-net-config
+
+       {0} write-net-config
+            [ --socket-path PATH ]
+
+    Socket path defaults to {1}",
+        program_name, DEFAULT_API_SOCKET,
     );
     process::exit(2);
 }
@@ -151,11 +268,41 @@ fn parse_args(args: env::Args) -> Result<Args> {
     let value = iter
         .next()
         .unwrap_or_else(|| usage_msg("Did not specify command"));
-    let sub_command = Some(
-        serde_plain::from_str::<SubCommand>(&value)
-            .unwrap_or_else(|_| usage_msg(format!("Unknown command {}", value))),
-    );
+    let sub_command = serde_plain::from_str::<SubCommand>(&value)
+        .unwrap_or_else(|_| usage_msg(format!("Unknown command {}", value)));
+
+    match sub_command {
+        SubCommand::Install => Ok(Args::Install(parse_wicked_args(iter)?)),
+        SubCommand::Remove => Ok(Args::Remove(parse_wicked_args(iter)?)),
+        SubCommand::GenerateNetConfig => Ok(Args::GenerateNetConfig),
+        SubCommand::WriteNetConfig => {
+            let mut socket_path = None;
+            while let Some(arg) = iter.next() {
+                match arg.as_ref() {
+                    "--socket-path" => {
+                        socket_path = Some(
+                            iter.next()
+                                .unwrap_or_else(|| {
+                                    usage_msg("Did not give argument to --socket-path")
+                                })
+                                .into(),
+                        )
+                    }
+                    x => usage_msg(format!("Unexpected argument '{}'", x)),
+                }
+            }
+            Ok(Args::WriteNetConfig {
+                socket_path: socket_path.unwrap_or_else(|| DEFAULT_API_SOCKET.into()),
+            })
+        }
+    }
+}
 
+/// Parses the arguments wicked passes to its system updater.
+fn parse_wicked_args<I>(mut iter: I) -> Result<WickedArgs>
+where
+    I: Iterator<Item = String>,
+{
     let mut interface_name = None;
     let mut interface_type = None;
     let mut interface_family = None;
@@ -167,10 +314,7 @@ fn parse_args(args: env::Args) -> Result<Args> {
                 let value = iter
                     .next()
                     .unwrap_or_else(|| usage_msg("Did not give argument to -i"));
-                interface_name = Some(
-                    serde_plain::from_str::<InterfaceName>(&value)
-                        .unwrap_or_else(|_| usage_msg(format!("Unknown interface name {}", value))),
-                );
+                interface_name = Some(value);
             }
 
             "-t" => {
@@ -203,8 +347,7 @@ fn parse_args(args: env::Args) -> Result<Args> {
         }
     }
 
-    Ok(Args {
-        sub_command: sub_command.unwrap_or_else(|| usage()),
+    Ok(WickedArgs {
         interface_name: interface_name.unwrap_or_else(|| usage()),
         interface_type: interface_type.unwrap_or_else(|| usage()),
         interface_family: interface_family.unwrap_or_else(|| usage()),
@@ -268,19 +411,21 @@ fn update_hostname(ip: &IpNet) -> Result<()> {
     Ok(())
 }
 
-fn install(args: &Args) -> Result<()> {
-    match (
-        &args.interface_name,
-        &args.interface_type,
-        &args.interface_family,
-    ) {
-        (InterfaceName::Eth0, InterfaceType::Dhcp, InterfaceFamily::Ipv4) => {
+fn install(args: &WickedArgs) -> Result<()> {
+    let config = NetConfig::load(NET_CONFIG)?;
+    let primary = args.interface_name == config.primary_interface();
+    match (primary, &args.interface_type, &args.interface_family) {
+        (true, InterfaceType::Dhcp, InterfaceFamily::Ipv4) => {
             let info = parse_lease_info(&args.data_file)?;
-            // Randomize name server order, for libc implementations like musl that send
-            // queries to the first N servers.
-            let mut dns_servers: Vec<_> = info.dns_servers.iter().collect();
-            dns_servers.shuffle(&mut thread_rng());
-            write_resolv_conf(&dns_servers, &info.dns_search)?;
+            // Statically configured name servers take precedence over the lease; they're written
+            // by generate-net-config.
+            if config.name_servers.is_empty() {
+                // Randomize name server order, for libc implementations like musl that send
+                // queries to the first N servers.
+                let mut dns_servers: Vec<_> = info.dns_servers.iter().collect();
+                dns_servers.shuffle(&mut thread_rng());
+                write_resolv_conf(&dns_servers, &info.dns_search)?;
+            }
             update_hostname(&info.ip_address)?;
         }
         _ => eprintln!("Unhandled 'install' command: {:?}", &args),
@@ -288,7 +433,7 @@ fn install(args: &Args) -> Result<()> {
     Ok(())
 }
 
-fn remove(args: &Args) -> Result<()> {
+fn remove(args: &WickedArgs) -> Result<()> {
     match (
         &args.interface_name,
         &args.interface_type,
@@ -299,11 +444,66 @@ fn remove(args: &Args) -> Result<()> {
     Ok(())
 }
 
+/// Write the wicked interface configuration, the resolver configuration if name servers are set
+/// statically, and the hostname if the primary interface is static, from the persisted network
+/// configuration.
+fn generate_net_config() -> Result<()> {
+    let config = NetConfig::load(NET_CONFIG)?;
+    wicked::write_interfaces(&config, WICKED_IFCONFIG_DIR, FACTORY_IFCONFIG_DIR)?;
+
+    if !config.name_servers.is_empty() {
+        let dns_servers: Vec<_> = config.name_servers.iter().collect();
+        let dns_search = if config.search_list.is_empty() {
+            None
+        } else {
+            Some(config.search_list.join(" "))
+        };
+        write_resolv_conf(&dns_servers, &dns_search)?;
+    }
+
+    // Without a DHCP lease on the primary interface, nothing else sets the hostname.
+    if let Some(host) = config.static_hostname() {
+        fs::write(KERNEL_HOSTNAME, host).context(error::HostnameWriteFailed {
+            path: KERNEL_HOSTNAME,
+        })?;
+    }
+    Ok(())
+}
+
+/// Query the API for the current network settings and persist them for use at boot.
+fn write_net_config<P>(socket_path: P) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let method = "GET";
+    let uri = API_SETTINGS_URI;
+    let (code, response_body) = apiclient::raw_request(&socket_path, uri, method, None)
+        .context(error::APIRequest { method, uri })?;
+    ensure!(
+        code.is_success(),
+        error::APIResponse {
+            method,
+            uri,
+            code,
+            response_body,
+        }
+    );
+
+    let settings: model::Settings =
+        serde_json::from_str(&response_body).context(error::ResponseJson { method, uri })?;
+    let config = settings.network.map(NetConfig::from).unwrap_or_default();
+    config.validate()?;
+    config.save(NET_CONFIG)?;
+
+    generate_net_config()
+}
+
 fn run() -> Result<()> {
-    let args = parse_args(env::args())?;
-    match args.sub_command {
-        SubCommand::Install => install(&args)?,
-        SubCommand::Remove => remove(&args)?,
+    match parse_args(env::args())? {
+        Args::Install(args) => install(&args)?,
+        Args::Remove(args) => remove(&args)?,
+        Args::GenerateNetConfig => generate_net_config()?,
+        Args::WriteNetConfig { socket_path } => write_net_config(socket_path)?,
     }
     Ok(())
 }
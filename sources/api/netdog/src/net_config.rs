@@ -0,0 +1,251 @@
+/*!
+This module defines the network configuration netdog persists on the data partition, so that it
+can configure interfaces at boot before the network (and the API) is available.  The file is
+written from `settings.network` whenever those settings change.
+*/
+
+use crate::{error, Result};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, ResultExt};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// The interface we treat as primary when none are configured.
+pub(crate) const DEFAULT_PRIMARY_INTERFACE: &str = "eth0";
+
+/// Network configuration for the host.  Values come before tables so that TOML serialization
+/// succeeds.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct NetConfig {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) name_servers: Vec<IpAddr>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) search_list: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) interfaces: BTreeMap<String, InterfaceConfig>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct InterfaceConfig {
+    #[serde(default)]
+    pub(crate) primary: bool,
+    #[serde(default)]
+    pub(crate) dhcp4: bool,
+    #[serde(default)]
+    pub(crate) dhcp6: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) addresses: Vec<IpNet>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) routes: Vec<RouteConfig>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct RouteConfig {
+    pub(crate) to: IpNet,
+    pub(crate) via: IpAddr,
+}
+
+impl From<model::NetworkSettings> for NetConfig {
+    fn from(settings: model::NetworkSettings) -> Self {
+        let interfaces = settings
+            .interfaces
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, interface)| {
+                let config = InterfaceConfig {
+                    primary: interface.primary.unwrap_or(false),
+                    dhcp4: interface.dhcp4.unwrap_or(false),
+                    dhcp6: interface.dhcp6.unwrap_or(false),
+                    addresses: interface.addresses.unwrap_or_default(),
+                    routes: interface
+                        .routes
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(|route| match (route.to, route.via) {
+                            (Some(to), Some(via)) => Some(RouteConfig { to, via }),
+                            _ => None,
+                        })
+                        .collect(),
+                };
+                (name.to_string(), config)
+            })
+            .collect();
+
+        NetConfig {
+            name_servers: settings.name_servers.unwrap_or_default(),
+            search_list: settings
+                .search_list
+                .unwrap_or_default()
+                .into_iter()
+                .map(|domain| domain.to_string())
+                .collect(),
+            interfaces,
+        }
+    }
+}
+
+impl NetConfig {
+    /// Loads the configuration from `path`.  If there's no file, nothing has been configured, and
+    /// we return the default configuration.
+    pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).context(error::NetConfigReadFailed { path }),
+        };
+        let config: Self = toml::from_str(&data).context(error::NetConfigParseFailed { path })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Saves the configuration to `path`, replacing it through a temporary file so that a
+    /// partially written file is never read at boot.
+    pub(crate) fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let data = toml::to_string(self).context(error::NetConfigSerializeFailed)?;
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, data).context(error::NetConfigWriteFailed { path: &temp })?;
+        fs::rename(&temp, path).context(error::NetConfigWriteFailed { path })?;
+        Ok(())
+    }
+
+    /// Makes sure each interface gets an address somehow, that routes make sense, and that we can
+    /// tell which interface is primary.
+    pub(crate) fn validate(&self) -> Result<()> {
+        for (name, interface) in &self.interfaces {
+            ensure!(
+                interface.dhcp4 || interface.dhcp6 || !interface.addresses.is_empty(),
+                error::InterfaceUnaddressed { interface: name }
+            );
+            for route in &interface.routes {
+                ensure!(
+                    route.to.addr().is_ipv4() == route.via.is_ipv4(),
+                    error::RouteFamilyMismatch {
+                        interface: name,
+                        to: route.to,
+                        via: route.via,
+                    }
+                );
+            }
+        }
+
+        let primaries = self.interfaces.values().filter(|i| i.primary).count();
+        ensure!(
+            primaries == 1 || (primaries == 0 && self.interfaces.len() <= 1),
+            error::PrimaryInterfaceAmbiguous { count: primaries }
+        );
+        Ok(())
+    }
+
+    /// Returns the name of the primary interface: the one marked primary, the only one
+    /// configured, or eth0 if none are configured.
+    pub(crate) fn primary_interface(&self) -> &str {
+        self.interfaces
+            .iter()
+            .find(|(_, i)| i.primary)
+            .or_else(|| match self.interfaces.len() {
+                1 => self.interfaces.iter().next(),
+                _ => None,
+            })
+            .map(|(name, _)| name.as_str())
+            .unwrap_or(DEFAULT_PRIMARY_INTERFACE)
+    }
+
+    /// Returns a hostname for hosts whose primary interface only has static addresses, since
+    /// there's no DHCP lease to give us one.  It's built from the first static address, preferring
+    /// IPv4, like "ip-10-0-0-10".
+    pub(crate) fn static_hostname(&self) -> Option<String> {
+        let interface = self.interfaces.get(self.primary_interface())?;
+        if interface.dhcp4 {
+            return None;
+        }
+        let address = interface
+            .addresses
+            .iter()
+            .find(|a| a.addr().is_ipv4())
+            .or_else(|| interface.addresses.first())?;
+        Some(format!("ip-{}", address.addr()).replace(|c| c == '.' || c == ':', "-"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(s: &str) -> NetConfig {
+        let config: NetConfig = toml::from_str(s).unwrap();
+        config
+    }
+
+    #[test]
+    fn empty() {
+        let config = parse("");
+        config.validate().unwrap();
+        assert_eq!(config.primary_interface(), "eth0");
+    }
+
+    #[test]
+    fn static_and_dhcp() {
+        let config = parse(
+            r#"
+            name-servers = ["10.0.0.2"]
+            search-list = ["example.com"]
+
+            [interfaces.eth0]
+            dhcp4 = true
+
+            [interfaces.eth1]
+            primary = true
+            addresses = ["10.0.0.10/24", "fd00::10/64"]
+            routes = [{ to = "0.0.0.0/0", via = "10.0.0.1" }]
+            "#,
+        );
+        config.validate().unwrap();
+        assert_eq!(config.primary_interface(), "eth1");
+        assert_eq!(config.interfaces["eth1"].addresses.len(), 2);
+        assert_eq!(config.static_hostname(), Some("ip-10-0-0-10".to_string()));
+
+        // What we save is what we load.
+        let saved = toml::to_string(&config).unwrap();
+        assert_eq!(parse(&saved), config);
+    }
+
+    #[test]
+    fn only_interface_is_primary() {
+        let config = parse("[interfaces.ens5]\ndhcp6 = true\n");
+        config.validate().unwrap();
+        assert_eq!(config.primary_interface(), "ens5");
+        assert_eq!(config.static_hostname(), None);
+    }
+
+    #[test]
+    fn static_ipv6_hostname() {
+        let config = parse("[interfaces.eth0]\naddresses = [\"fd00::10/64\"]\n");
+        config.validate().unwrap();
+        assert_eq!(config.static_hostname(), Some("ip-fd00--10".to_string()));
+    }
+
+    #[test]
+    fn invalid() {
+        for bad in &[
+            // No way to get an address
+            "[interfaces.eth0]\nprimary = true\n",
+            // Two interfaces, neither primary
+            "[interfaces.eth0]\ndhcp4 = true\n[interfaces.eth1]\ndhcp4 = true\n",
+            // Two primaries
+            "[interfaces.eth0]\ndhcp4 = true\nprimary = true\n[interfaces.eth1]\ndhcp4 = true\nprimary = true\n",
+            // IPv4 route through an IPv6 gateway
+            "[interfaces.eth0]\naddresses = [\"10.0.0.10/24\"]\nroutes = [{ to = \"0.0.0.0/0\", via = \"fd00::1\" }]\n",
+        ] {
+            parse(bad).validate().unwrap_err();
+        }
+    }
+}
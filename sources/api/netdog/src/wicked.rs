@@ -0,0 +1,194 @@
+/*!
+This module writes wicked's interface configuration files from our network configuration.
+*/
+
+use crate::net_config::{InterfaceConfig, NetConfig};
+use crate::{error, Result};
+use snafu::ResultExt;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Render the wicked configuration for one interface.
+pub(crate) fn interface_xml(name: &str, interface: &InterfaceConfig) -> Result<String> {
+    let mut xml = String::new();
+    render_interface(&mut xml, name, interface).context(error::WickedConfigBuildFailed)?;
+    Ok(xml)
+}
+
+fn render_interface(xml: &mut String, name: &str, interface: &InterfaceConfig) -> std::fmt::Result {
+    writeln!(xml, "<interface>")?;
+    writeln!(xml, "  <name>{}</name>", name)?;
+    writeln!(xml, "  <control>")?;
+    writeln!(xml, "    <mode>boot</mode>")?;
+    writeln!(xml, "    <link-detection>")?;
+    writeln!(xml, "      <require-link />")?;
+    writeln!(xml, "    </link-detection>")?;
+    writeln!(xml, "  </control>")?;
+
+    writeln!(xml, "  <ipv4>")?;
+    writeln!(xml, "    <arp-verify>false</arp-verify>")?;
+    writeln!(xml, "    <arp-notify>false</arp-notify>")?;
+    writeln!(xml, "  </ipv4>")?;
+
+    for (family, is_ipv4) in &[("ipv4", true), ("ipv6", false)] {
+        let addresses: Vec<_> = interface
+            .addresses
+            .iter()
+            .filter(|a| a.addr().is_ipv4() == *is_ipv4)
+            .collect();
+        let routes: Vec<_> = interface
+            .routes
+            .iter()
+            .filter(|r| r.to.addr().is_ipv4() == *is_ipv4)
+            .collect();
+        if addresses.is_empty() && routes.is_empty() {
+            continue;
+        }
+        writeln!(xml, "  <{}:static>", family)?;
+        for address in addresses {
+            writeln!(xml, "    <address>")?;
+            writeln!(xml, "      <local>{}</local>", address)?;
+            writeln!(xml, "    </address>")?;
+        }
+        for route in routes {
+            writeln!(xml, "    <route>")?;
+            writeln!(xml, "      <destination>{}</destination>", route.to)?;
+            writeln!(xml, "      <nexthop>")?;
+            writeln!(xml, "        <gateway>{}</gateway>", route.via)?;
+            writeln!(xml, "      </nexthop>")?;
+            writeln!(xml, "    </route>")?;
+        }
+        writeln!(xml, "  </{}:static>", family)?;
+    }
+
+    if interface.dhcp4 {
+        writeln!(xml, "  <ipv4:dhcp>")?;
+        writeln!(xml, "    <enabled>true</enabled>")?;
+        writeln!(xml, "  </ipv4:dhcp>")?;
+    }
+    if interface.dhcp6 {
+        writeln!(xml, "  <ipv6:dhcp>")?;
+        writeln!(xml, "    <enabled>true</enabled>")?;
+        writeln!(xml, "    <defer-timeout>1</defer-timeout>")?;
+        writeln!(xml, "  </ipv6:dhcp>")?;
+    }
+    writeln!(xml, "</interface>")?;
+    Ok(())
+}
+
+/// Replace the interface configuration files in `dir`.  If no interfaces are configured, we
+/// restore the files shipped in `factory_dir`, which configure the primary interface with DHCP.
+pub(crate) fn write_interfaces<P1, P2>(config: &NetConfig, dir: P1, factory_dir: P2) -> Result<()>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let dir = dir.as_ref();
+    let factory_dir = factory_dir.as_ref();
+    fs::create_dir_all(dir).context(error::WickedConfigWriteFailed { path: dir })?;
+
+    // Remove existing files first so that interfaces that are no longer configured are dropped.
+    for entry in fs::read_dir(dir).context(error::WickedConfigWriteFailed { path: dir })? {
+        let path = entry
+            .context(error::WickedConfigWriteFailed { path: dir })?
+            .path();
+        if path.extension() == Some("xml".as_ref()) {
+            fs::remove_file(&path).context(error::WickedConfigWriteFailed { path: &path })?;
+        }
+    }
+
+    if config.interfaces.is_empty() {
+        for entry in fs::read_dir(factory_dir)
+            .context(error::WickedConfigWriteFailed { path: factory_dir })?
+        {
+            let from = entry
+                .context(error::WickedConfigWriteFailed { path: factory_dir })?
+                .path();
+            if let Some(file_name) = from.file_name() {
+                let to = dir.join(file_name);
+                fs::copy(&from, &to).context(error::WickedConfigWriteFailed { path: &to })?;
+            }
+        }
+        return Ok(());
+    }
+
+    for (name, interface) in &config.interfaces {
+        let path = dir.join(format!("{}.xml", name));
+        fs::write(&path, interface_xml(name, interface)?)
+            .context(error::WickedConfigWriteFailed { path: &path })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net_config::RouteConfig;
+    use tempfile::TempDir;
+
+    #[test]
+    fn dhcp_matches_factory() {
+        let interface = InterfaceConfig {
+            dhcp4: true,
+            dhcp6: true,
+            ..Default::default()
+        };
+        let xml = interface_xml("eth0", &interface).unwrap();
+        // A copy of packages/release/eth0.xml, which this has to stay in step with.
+        let factory = include_str!("../tests/data/eth0.xml");
+        let normalize = |s: &str| -> Vec<String> {
+            s.lines()
+                .map(|l| l.trim().to_string())
+                .filter(|l| !l.is_empty() && !l.starts_with("<description>"))
+                .collect()
+        };
+        assert_eq!(normalize(&xml), normalize(factory));
+    }
+
+    #[test]
+    fn static_addresses() {
+        let interface = InterfaceConfig {
+            addresses: vec!["10.0.0.10/24".parse().unwrap()],
+            routes: vec![RouteConfig {
+                to: "0.0.0.0/0".parse().unwrap(),
+                via: "10.0.0.1".parse().unwrap(),
+            }],
+            ..Default::default()
+        };
+        let xml = interface_xml("eth1", &interface).unwrap();
+        assert!(xml.contains("<ipv4:static>"));
+        assert!(xml.contains("<local>10.0.0.10/24</local>"));
+        assert!(xml.contains("<destination>0.0.0.0/0</destination>"));
+        assert!(xml.contains("<gateway>10.0.0.1</gateway>"));
+        assert!(!xml.contains("ipv6:static"));
+        assert!(!xml.contains("dhcp"));
+    }
+
+    #[test]
+    fn write_and_restore() {
+        let factory = TempDir::new().unwrap();
+        fs::write(factory.path().join("eth0.xml"), "factory").unwrap();
+        let dir = TempDir::new().unwrap();
+
+        let mut config = NetConfig::default();
+        config.interfaces.insert(
+            "eth1".to_string(),
+            InterfaceConfig {
+                dhcp4: true,
+                ..Default::default()
+            },
+        );
+        fs::write(dir.path().join("eth0.xml"), "factory").unwrap();
+        write_interfaces(&config, dir.path(), factory.path()).unwrap();
+        assert!(!dir.path().join("eth0.xml").exists());
+        assert!(dir.path().join("eth1.xml").exists());
+
+        write_interfaces(&NetConfig::default(), dir.path(), factory.path()).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("eth0.xml")).unwrap(),
+            "factory"
+        );
+        assert!(!dir.path().join("eth1.xml").exists());
+    }
+}
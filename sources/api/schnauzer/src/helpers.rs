@@ -422,6 +422,53 @@ pub fn default(
     Ok(())
 }
 
+/// `to_json` writes a value as JSON, like a whole settings subtree, so a template can hand it to a
+/// program without escaping each field by hand.  A value that isn't set is written as `null`.
+/// Like other helpers, its output isn't HTML-escaped:
+/// {{to_json settings.foo}}
+pub fn to_json(
+    helper: &Helper<'_, '_>,
+    _: &Handlebars,
+    _: &Context,
+    renderctx: &mut RenderContext<'_, '_>,
+    out: &mut dyn Output,
+) -> Result<(), RenderError> {
+    trace!("Starting to_json helper");
+    let template_name = renderctx
+        .get_root_template_name()
+        .map(|i| i.to_string())
+        .unwrap_or_else(|| "dynamic template".to_string());
+    trace!("Template name: {}", &template_name);
+
+    trace!("Number of params: {}", helper.params().len());
+    if helper.params().len() != 1 {
+        return Err(RenderError::from(
+            error::TemplateHelperError::IncorrectNumberOfParams {
+                expected: 1,
+                received: helper.params().len(),
+                helper: helper.name().to_string(),
+                template: template_name,
+            },
+        ));
+    }
+
+    let value = helper
+        .param(0)
+        .map(|v| v.value())
+        .context(error::Internal {
+            msg: "Missing param after confirming there are enough",
+        })?;
+    // Serializing a JSON value can't fail; its map keys are always strings.
+    let json = serde_json::to_string(value).map_err(|e| error::TemplateHelperError::Internal {
+        msg: format!("Failed to serialize value as JSON: {}", e),
+    })?;
+
+    out.write(&json).context(error::TemplateWrite {
+        template: template_name.to_owned(),
+    })?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod test_base64_decode {
     use super::*;
@@ -662,3 +709,45 @@ mod test_default {
         assert_eq!(result, "true")
     }
 }
+
+#[cfg(test)]
+mod test_to_json {
+    use super::*;
+    use handlebars::TemplateRenderError;
+    use serde::Serialize;
+    use serde_json::json;
+
+    // A thin wrapper around the handlebars render_template method that includes
+    // setup and registration of helpers
+    fn setup_and_render_template<T>(tmpl: &str, data: &T) -> Result<String, TemplateRenderError>
+    where
+        T: Serialize,
+    {
+        let mut registry = Handlebars::new();
+        registry.register_helper("to_json", Box::new(to_json));
+
+        registry.render_template(tmpl, data)
+    }
+
+    #[test]
+    fn subtree() {
+        let data = json!({"settings": {"foo": {"args": ["--name=\"x\"", "a\\b"], "n": 5}}});
+        let result = setup_and_render_template("{{to_json settings.foo}}", &data).unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed, data["settings"]["foo"]);
+    }
+
+    #[test]
+    fn missing() {
+        let result =
+            setup_and_render_template("{{to_json settings.foo}}", &json!({"settings": {}}))
+                .unwrap();
+        assert_eq!(result, "null");
+    }
+
+    #[test]
+    fn wrong_number_of_params() {
+        assert!(setup_and_render_template("{{to_json}}", &json!({})).is_err());
+        assert!(setup_and_render_template("{{to_json a b}}", &json!({"a": 1, "b": 2})).is_err());
+    }
+}
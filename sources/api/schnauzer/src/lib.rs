@@ -115,6 +115,7 @@ pub fn build_template_registry() -> Result<handlebars::Handlebars<'static>> {
     template_registry.register_helper("base64_decode", Box::new(helpers::base64_decode));
     template_registry.register_helper("join_map", Box::new(helpers::join_map));
     template_registry.register_helper("default", Box::new(helpers::default));
+    template_registry.register_helper("to_json", Box::new(helpers::to_json));
 
     Ok(template_registry)
 }
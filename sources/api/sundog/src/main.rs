@@ -5,6 +5,9 @@ sundog is a small program to handle settings that must be generated at OS runtim
 
 It requests settings generators from the API and runs them.
 The output is collected and sent to a known Bottlerocket API server endpoint.
+
+Generators output JSON.
+A generator can output a scalar for a single setting, or an object to fill in a whole block of settings; either way, settings that are already populated are not overwritten.
 */
 
 #![deny(rust_2018_idioms)]
@@ -134,8 +137,14 @@ mod error {
         #[snafu(display("Error serializing Settings: {} ", source))]
         SerializeSettings { source: serialization::Error },
 
-        #[snafu(display("Error serializing command output '{}': {}", value, source))]
-        SerializeScalar {
+        #[snafu(display(
+            "Error serializing output '{}' of generator '{}': {}",
+            value,
+            generator,
+            source
+        ))]
+        SerializeOutput {
+            generator: String,
             value: serde_json::Value,
             source: datastore::ScalarError,
         },
@@ -183,7 +192,9 @@ where
     Ok(generators)
 }
 
-/// Given a list of settings, query the API for any that are currently set.
+/// Given a list of settings, query the API for any that are currently set.  A setting can name a
+/// whole block, like "settings.updates", so each is queried as a prefix, and every populated key
+/// under it is returned.
 fn get_populated_settings<P>(socket_path: P, to_query: Vec<&str>) -> Result<HashSet<Key>>
 where
     P: AsRef<Path>,
@@ -192,34 +203,36 @@ where
 
     let mut populated_settings = HashSet::new();
 
-    // Build the query string and the URI containing that query.
-    let query = to_query.join(",");
-    let uri = &format!("{}?keys={}", API_SETTINGS_URI, query);
-
-    let (code, response_body) = apiclient::raw_request(socket_path.as_ref(), uri, "GET", None)
-        .context(error::APIRequest { method: "GET", uri })?;
-    ensure!(
-        code.is_success(),
-        error::APIResponse {
-            method: "GET",
-            uri,
-            code,
-            response_body,
-        }
-    );
+    for setting in to_query {
+        // The API's prefix doesn't include "settings."
+        let prefix = setting.trim_start_matches("settings.");
+        let uri = &format!("{}?prefix={}", API_SETTINGS_URI, prefix);
+
+        let (code, response_body) = apiclient::raw_request(socket_path.as_ref(), uri, "GET", None)
+            .context(error::APIRequest { method: "GET", uri })?;
+        ensure!(
+            code.is_success(),
+            error::APIResponse {
+                method: "GET",
+                uri,
+                code,
+                response_body,
+            }
+        );
 
-    // Build a Settings struct from the response.
-    let settings: model::Settings =
-        serde_json::from_str(&response_body).context(error::ResponseJson { method: "GET", uri })?;
+        // Build a Settings struct from the response.
+        let settings: model::Settings = serde_json::from_str(&response_body)
+            .context(error::ResponseJson { method: "GET", uri })?;
 
-    // Serialize the Settings struct into key/value pairs. This builds the dotted
-    // string representation of the setting
-    let settings_keypairs =
-        to_pairs_with_prefix("settings", &settings).context(error::SerializeSettings)?;
+        // Serialize the Settings struct into key/value pairs. This builds the dotted
+        // string representation of the setting
+        let settings_keypairs =
+            to_pairs_with_prefix("settings", &settings).context(error::SerializeSettings)?;
 
-    // Put the setting into our hashset of populated keys
-    for (k, _) in settings_keypairs {
-        populated_settings.insert(k);
+        // Put the setting into our hashset of populated keys
+        for (k, _) in settings_keypairs {
+            populated_settings.insert(k);
+        }
     }
 
     trace!("Found populated settings: {:#?}", &populated_settings);
@@ -335,13 +348,17 @@ where
         //
         // We have to go through the round-trip of serialization because the data store
         // serialization format may not be the same as the format we choose for sundog.
-        let serialized_output =
-            datastore::serialize_scalar(&output_value).context(error::SerializeScalar {
-                value: output_value,
-            })?;
-        trace!("Serialized output: {}", &serialized_output);
-
-        settings.insert(setting, serialized_output);
+        //
+        // Generators can output an object to fill in a whole block of settings at once, so we
+        // serialize to individual keys under the generator's setting.  Keys the user already set
+        // are left alone, so they can override any part of the block.
+        unpopulated_output(
+            &setting,
+            &generator,
+            &output_value,
+            &populated_settings,
+            &mut settings,
+        )?;
     }
 
     // The API takes a properly nested Settings struct, so deserialize our map to a Settings
@@ -352,6 +369,42 @@ where
     Ok(settings_struct)
 }
 
+/// Serializes a generator's output to individual keys under its setting, leaving out any that are
+/// already populated.  An object fills in the settings under it, so a generator can give a whole
+/// block at once; anything else is the value of a single setting.
+fn unpopulated_output(
+    setting: &Key,
+    generator: &str,
+    output_value: &serde_json::Value,
+    populated_settings: &HashSet<Key>,
+    settings: &mut HashMap<Key, String>,
+) -> Result<()> {
+    if let serde_json::Value::Object(fields) = output_value {
+        for (name, value) in fields {
+            let mut segments = setting.segments().clone();
+            segments.push(name.clone());
+            let key = Key::from_segments(KeyType::Data, &segments).context(error::InvalidKey {
+                key_type: KeyType::Data,
+                key: segments.join("."),
+            })?;
+            unpopulated_output(&key, generator, value, populated_settings, settings)?;
+        }
+        return Ok(());
+    }
+
+    if populated_settings.contains(setting) {
+        debug!("Setting '{}' is already populated, skipping", setting);
+        return Ok(());
+    }
+    let serialized = datastore::serialize_scalar(output_value).context(error::SerializeOutput {
+        generator,
+        value: output_value.clone(),
+    })?;
+    trace!("Serialized output for '{}': {}", setting, &serialized);
+    settings.insert(setting.clone(), serialized);
+    Ok(())
+}
+
 /// Send the settings to the datastore through the API
 fn set_settings<S>(socket_path: S, settings: model::Settings) -> Result<()>
 where
@@ -474,3 +527,73 @@ fn main() {
         process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn key(name: &str) -> Key {
+        Key::new(KeyType::Data, name).unwrap()
+    }
+
+    fn keys(names: &[&str]) -> HashSet<Key> {
+        names.iter().map(|name| key(name)).collect()
+    }
+
+    fn generate(
+        setting: &str,
+        output: serde_json::Value,
+        populated: &HashSet<Key>,
+    ) -> HashMap<Key, String> {
+        let mut settings = HashMap::new();
+        unpopulated_output(
+            &key(setting),
+            "generator",
+            &output,
+            populated,
+            &mut settings,
+        )
+        .unwrap();
+        settings
+    }
+
+    #[test]
+    fn populated_block_keys_kept() {
+        // The user gave a seed and version lock; only the rest of the block is generated.
+        let populated = keys(&["settings.updates.seed", "settings.updates.version-lock"]);
+        let output = json!({"seed": 1234, "version-lock": "latest", "ignore-waves": false});
+        assert_eq!(
+            generate("settings.updates", output, &populated),
+            hashmap(&[("settings.updates.ignore-waves", "false")])
+        );
+
+        // Nothing set yet, so the whole block is generated, numbers included.
+        let output = json!({"seed": 1234, "version-lock": "latest"});
+        assert_eq!(
+            generate("settings.updates", output, &HashSet::new()),
+            hashmap(&[
+                ("settings.updates.seed", "1234"),
+                ("settings.updates.version-lock", "\"latest\""),
+            ])
+        );
+    }
+
+    #[test]
+    fn populated_scalar_kept() {
+        let populated = keys(&["settings.kubernetes.node-ip"]);
+        let output = json!("10.0.0.1");
+        assert!(generate("settings.kubernetes.node-ip", output.clone(), &populated).is_empty());
+        assert_eq!(
+            generate("settings.kubernetes.node-ip", output, &HashSet::new()),
+            hashmap(&[("settings.kubernetes.node-ip", "\"10.0.0.1\"")])
+        );
+    }
+
+    fn hashmap(pairs: &[(&str, &str)]) -> HashMap<Key, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (key(name), value.to_string()))
+            .collect()
+    }
+}
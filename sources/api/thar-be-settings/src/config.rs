@@ -3,6 +3,8 @@ use itertools::join;
 use snafu::ResultExt;
 use std::collections::HashSet;
 use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
 use std::path::{Path, PathBuf};
 
 /// Query the API for ConfigurationFile data
@@ -58,12 +60,10 @@ pub fn render_config_files(
         let try_rendered = registry.render(&name, &settings);
         if strict {
             let rendered = try_rendered.context(error::TemplateRender { template: name })?;
-            rendered_configs.push(RenderedConfigFile::new(&metadata.path, rendered));
+            rendered_configs.push(RenderedConfigFile::new(&metadata, rendered));
         } else {
             match try_rendered {
-                Ok(rendered) => {
-                    rendered_configs.push(RenderedConfigFile::new(&metadata.path, rendered))
-                }
+                Ok(rendered) => rendered_configs.push(RenderedConfigFile::new(&metadata, rendered)),
                 Err(err) => warn!("Unable to render template '{}': {}", &name, err),
             }
         }
@@ -82,17 +82,19 @@ pub fn write_config_files(rendered_config: Vec<RenderedConfigFile>) -> Result<()
 }
 
 /// RenderedConfigFile contains both the path to the config file
-/// and the rendered data to write.
+/// and the rendered data to write, along with the mode to write it with, if any.
 #[derive(Debug)]
 pub struct RenderedConfigFile {
     path: PathBuf,
+    mode: Option<u32>,
     rendered: String,
 }
 
 impl RenderedConfigFile {
-    fn new(path: &str, rendered: String) -> RenderedConfigFile {
+    fn new(metadata: &model::ConfigurationFile, rendered: String) -> RenderedConfigFile {
         RenderedConfigFile {
-            path: PathBuf::from(&path),
+            path: PathBuf::from(metadata.path.as_ref() as &str),
+            mode: metadata.mode.as_ref().map(|mode| mode.bits()),
             rendered,
         }
     }
@@ -106,7 +108,22 @@ impl RenderedConfigFile {
             })?;
         };
 
-        fs::write(&self.path, self.rendered.as_bytes()).context(error::TemplateWrite {
+        match self.mode {
+            None => fs::write(&self.path, self.rendered.as_bytes()),
+            // The mode is only used when the file is created, so it's set again before writing,
+            // in case the file was already there with looser permissions.
+            Some(mode) => fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(mode)
+                .open(&self.path)
+                .and_then(|mut file| {
+                    file.set_permissions(fs::Permissions::from_mode(mode))?;
+                    file.write_all(self.rendered.as_bytes())
+                }),
+        }
+        .context(error::TemplateWrite {
             path: &self.path,
             pathtype: "file",
         })
@@ -136,4 +153,22 @@ mod test {
 
         assert_eq!(get_config_file_names(&input_map), expected_output)
     }
+
+    #[test]
+    fn write_with_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("registry.toml");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let cfg = RenderedConfigFile {
+            path: path.clone(),
+            mode: Some(0o600),
+            rendered: "secret".to_string(),
+        };
+        cfg.write_to_disk().unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o600);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "secret");
+    }
 }
@@ -12,6 +12,10 @@ It then renders the templates and rewrites the affected configuration files.
 Service data from the API includes any commands needed to restart services affected by configuration file changes, which are run here.
 
 In the standalone ("all keys") mode, it queries the API for all services and configuration files, then renders and rewrites all configuration files and restarts all services.
+
+In the "specific services" mode, it's given the names of services, and renders and rewrites only their configuration files and restarts only them.
+The API server uses this to rotate certificates, by running the restart commands of the services that manage them, which are named with a `-certificates` suffix.
+Those services are left out of the standalone mode, so certificates are only rotated when asked.
 */
 
 #![deny(rust_2018_idioms)]
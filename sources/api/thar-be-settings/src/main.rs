@@ -33,12 +33,13 @@ mod error {
 }
 
 /// RunMode represents how thar-be-settings was requested to be run, either handling all
-/// configuration files and services, or handling configuration files and services based on
-/// specific keys given by the user.
+/// configuration files and services, handling configuration files and services based on
+/// specific keys given by the user, or handling specific services named by the user.
 #[derive(Debug)]
 enum RunMode {
     All,
     SpecificKeys,
+    SpecificServices(HashSet<String>),
 }
 
 /// Store the args we receive on the command line
@@ -53,14 +54,16 @@ fn usage() -> ! {
     let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
     eprintln!(
         r"Usage: {}
-            [ --all ]
+            [ --all | --services NAME[,NAME...] ]
             [ --socket-path PATH ]
             [ --log-level trace|debug|info|warn|error ]
 
     If --all is given, all configuration files will be written and all
     services will have their restart-commands run.  Otherwise, settings keys
     will be read from stdin; only files related to those keys will be written,
-    and only services related to those keys will be restarted.
+    and only services related to those keys will be restarted.  If --services
+    is given, only the configuration files of the named services will be
+    written, and only those services will have their restart-commands run.
 
     Socket path defaults to {}",
         program_name, DEFAULT_API_SOCKET,
@@ -85,6 +88,21 @@ fn parse_args(args: env::Args) -> Args {
         match arg.as_ref() {
             "--all" => mode = RunMode::All,
 
+            "--services" => {
+                let services_str = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --services"));
+                let services: HashSet<String> = services_str
+                    .split(',')
+                    .filter(|s| !s.is_empty())
+                    .map(String::from)
+                    .collect();
+                if services.is_empty() {
+                    usage_msg("Did not give any service names to --services");
+                }
+                mode = RunMode::SpecificServices(services);
+            }
+
             "--log-level" => {
                 let log_level_str = iter
                     .next()
@@ -146,7 +164,7 @@ fn write_config_files(
     // Ensure all files render properly
     info!("Rendering config files...");
     let strict = match &args.mode {
-        RunMode::SpecificKeys => true,
+        RunMode::SpecificKeys | RunMode::SpecificServices(_) => true,
         RunMode::All => false,
     };
     let rendered = config::render_config_files(&template_registry, config_files, settings, strict)?;
@@ -168,7 +186,7 @@ fn run() -> Result<(), Box<dyn std::error::Error>> {
 
     info!("thar-be-settings started");
 
-    match args.mode {
+    match &args.mode {
         RunMode::SpecificKeys => {
             // Get the settings that changed via stdin
             info!("Parsing stdin for updated settings");
@@ -198,11 +216,26 @@ fn run() -> Result<(), Box<dyn std::error::Error>> {
             info!("Restarting affected services...");
             service::restart_services(services)?;
         }
+        RunMode::SpecificServices(names) => {
+            info!("Requesting services: {:?}", names);
+            let services = service::get_services(&args.socket_path, names.clone())?;
+            trace!("Found services: {:?}", services);
+
+            let config_file_names = config::get_config_file_names(&services);
+            if !config_file_names.is_empty() {
+                write_config_files(&args, Some(config_file_names))?;
+            }
+
+            info!("Restarting requested services...");
+            service::restart_services(services)?;
+        }
         RunMode::All => {
             write_config_files(&args, None)?;
 
             info!("Restarting all services...");
-            let services = service::get_affected_services(&args.socket_path, None)?;
+            let mut services = service::get_affected_services(&args.socket_path, None)?;
+            // Certificates are only rotated when asked, not every time settings are applied.
+            services.retain(|name, _| !service::rotates_certificates(name));
             trace!("Found services: {:?}", services);
             service::restart_services(services)?;
         }
@@ -37,6 +37,26 @@ where
     Ok(services)
 }
 
+/// Services whose restart commands rotate certificates are named with this suffix, like
+/// "kubelet-certificates".
+const CERTIFICATE_SERVICE_SUFFIX: &str = "-certificates";
+
+/// Whether the restart commands of the named service rotate certificates.  These services are
+/// only restarted when they're named, so certificates are only rotated when asked.
+pub fn rotates_certificates(name: &str) -> bool {
+    name.ends_with(CERTIFICATE_SERVICE_SUFFIX)
+}
+
+/// Gather the metadata for the services with the given names.  It's an error if any of them
+/// don't exist.
+#[allow(clippy::implicit_hasher)]
+pub fn get_services<P>(socket_path: P, names: HashSet<String>) -> Result<model::Services>
+where
+    P: AsRef<Path>,
+{
+    get_service_metadata(socket_path.as_ref(), Some(names))
+}
+
 /// Gather the services affected for each setting into a map, or if `settings_limit` is None, all
 /// services
 #[allow(clippy::implicit_hasher)]
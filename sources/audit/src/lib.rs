@@ -0,0 +1,361 @@
+/*!
+# Introduction
+
+audit records the privileged actions Bottlerocket's system components take, as structured
+entries in the journal, so tooling like a SIEM can build rules on them without parsing log text.
+
+The API server records settings commits and applies, cancelled updates, and certificate
+rotations; updog records writing an update's images, activating them, and rebooting into them;
+signpost records each change to the partition table's boot flags; and the migrator records each
+data store migration and each migration it runs.
+
+Each entry is sent over journald's native protocol, with these fields:
+
+* `MESSAGE_ID`: an ID for the kind of action, which never changes once released; see `Action`
+* `BOTTLEROCKET_ACTOR`: the component that took the action, like `updog`
+* `BOTTLEROCKET_ACTION`: the action's name, like `image-write`
+* `BOTTLEROCKET_OBJECT`: what was acted on, like a settings transaction, an update's version, or
+  a partition set
+* `BOTTLEROCKET_OUTCOME`: `success` or `failure`
+* `BOTTLEROCKET_DETAIL`: what changed, or why the action failed, if there's more to say
+* `MESSAGE`, `PRIORITY`, and `SYSLOG_IDENTIFIER`, so the entry reads like any other
+
+journald adds the sender's process, user, and unit as trusted fields, like `_PID`, `_UID`, and
+`_SYSTEMD_UNIT`, which the sender can't set, so they can be relied on to say who made the entry.
+The API server doesn't know which client sent a request; its socket is only open to root and the
+`api` group.
+
+To see the entries:
+
+```bash
+# Every entry
+journalctl BOTTLEROCKET_OUTCOME=success BOTTLEROCKET_OUTCOME=failure
+# Cancelled updates
+journalctl MESSAGE_ID=c4f9fb367021456d92bd74ad99426ef8
+```
+
+Recording an entry never fails the action: if the journal can't be reached, the entry is written
+to stderr as a line instead, which the journal usually picks up anyway.
+*/
+
+#![deny(rust_2018_idioms)]
+
+use std::fmt;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::process;
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// The syslog severity of a successful action, "notice".
+const NOTICE: u8 = 5;
+/// The syslog severity of a failed action, "warning".
+const WARNING: u8 = 4;
+
+/// A component that takes privileged actions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Actor {
+    Apiserver,
+    Updog,
+    Signpost,
+    Migrator,
+}
+
+impl Actor {
+    /// The component's name, which is also its program's name.
+    pub fn name(self) -> &'static str {
+        match self {
+            Actor::Apiserver => "apiserver",
+            Actor::Updog => "updog",
+            Actor::Signpost => "signpost",
+            Actor::Migrator => "migrator",
+        }
+    }
+}
+
+impl fmt::Display for Actor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.name().fmt(f)
+    }
+}
+
+macro_rules! actions {
+    ($($(#[$attr:meta])* $name:ident = $id:literal, $message_id:literal => $text:literal,)*) => {
+        /// A kind of privileged action.  Its name and MESSAGE_ID are never changed or reused once
+        /// released, so rules can key off them; the text can be reworded.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Action {
+            $($(#[$attr])* $name,)*
+        }
+
+        impl Action {
+            pub const ALL: &'static [Action] = &[$(Action::$name,)*];
+
+            /// The action's name, like `image-write`.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Action::$name => $id,)*
+                }
+            }
+
+            /// The action's MESSAGE_ID in the journal: 128 bits, in lowercase hex.
+            pub fn message_id(self) -> &'static str {
+                match self {
+                    $(Action::$name => $message_id,)*
+                }
+            }
+
+            /// What the action does, in English.
+            pub fn text(self) -> &'static str {
+                match self {
+                    $(Action::$name => $text,)*
+                }
+            }
+        }
+    };
+}
+
+actions! {
+    // The API server.
+    /// Committing a settings transaction to the live data store.
+    SettingsCommit = "settings-commit", "7764d2aa0d6949a59bfea0a8409b9463"
+        => "Commit settings transaction",
+    /// Starting the settings applier, to write config files and restart services.
+    SettingsApply = "settings-apply", "f9cfb8b893be4a579a9d7b65b4d09bd0"
+        => "Apply settings",
+    /// Asking a running update to stop.
+    UpdateCancel = "update-cancel", "c4f9fb367021456d92bd74ad99426ef8"
+        => "Cancel update",
+    /// Rotating the certificates of the services that manage them.
+    CertificatesRotate = "certificates-rotate", "e8c84916b92949aba25acf9355780ec4"
+        => "Rotate certificates",
+
+    // updog.
+    /// Writing an update's images to the inactive partition set.
+    ImageWrite = "image-write", "a8a59d68dc3549a491d6938500e4dd41"
+        => "Write update images",
+    /// Setting the inactive partition set, with an update on it, to boot next.
+    UpdateActivate = "update-activate", "68f9af20a3114184a64069383c7bcbaa"
+        => "Activate update",
+    /// Rebooting the host into an update.
+    Reboot = "reboot", "14460a05fcee433580ec7a29327c0f66"
+        => "Reboot",
+
+    // signpost; each is named after its command.
+    PartitionMarkSuccessfulBoot = "mark-successful-boot", "e1615e3f26c34d72b05a773ce55c76dd"
+        => "Mark partition set successfully booted",
+    PartitionClearInactive = "clear-inactive", "2e15f93f46414fcdbd7a434dae4bc7f1"
+        => "Clear inactive partition set",
+    PartitionMarkInactiveValid = "mark-inactive-valid", "52cf8badedbd4401b5c62d9520d75086"
+        => "Mark inactive partition set valid",
+    PartitionUpgradeToInactive = "upgrade-to-inactive", "de8bd87ab05b428797d72bc865283c54"
+        => "Set inactive partition set to boot next",
+    PartitionCancelUpgrade = "cancel-upgrade", "ff23e33253594f7697468acfb78c440b"
+        => "Cancel upgrade to inactive partition set",
+    PartitionRollbackToInactive = "rollback-to-inactive", "e219f01ab33140f3a0ce499f0633e64c"
+        => "Roll back to inactive partition set",
+    PartitionRewriteTable = "rewrite-table", "9e92e7f858b646edb731e56d76405a96"
+        => "Rewrite partition table",
+
+    // The migrator.
+    /// Moving the data store to another version, whether or not there are migrations to run.
+    DatastoreMigrate = "datastore-migrate", "cde6c193a17c4b12b52660deab2b246d"
+        => "Migrate data store",
+    /// Running one migration.
+    MigrationRun = "migration-run", "ba8d6588d3e445b2b097f6520e9a5160"
+        => "Run migration",
+}
+
+/// Whether an action succeeded, and if not, why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    Failure(String),
+}
+
+impl<T, E: fmt::Display> From<&Result<T, E>> for Outcome {
+    fn from(result: &Result<T, E>) -> Self {
+        match result {
+            Ok(_) => Outcome::Success,
+            Err(e) => Outcome::Failure(e.to_string()),
+        }
+    }
+}
+
+/// An entry in the audit trail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    actor: Actor,
+    action: Action,
+    object: String,
+    outcome: Outcome,
+    detail: Option<String>,
+}
+
+impl Event {
+    /// An action that succeeded, unless given another outcome.
+    pub fn new<S: Into<String>>(actor: Actor, action: Action, object: S) -> Self {
+        Self {
+            actor,
+            action,
+            object: object.into(),
+            outcome: Outcome::Success,
+            detail: None,
+        }
+    }
+
+    /// Sets the action's outcome.  A failure's reason is its detail, unless one is set after.
+    pub fn outcome<O: Into<Outcome>>(mut self, outcome: O) -> Self {
+        self.outcome = outcome.into();
+        if let Outcome::Failure(reason) = &self.outcome {
+            self.detail = Some(reason.clone());
+        }
+        self
+    }
+
+    /// Sets what else there is to say about the action, like what it changed.
+    pub fn detail<S: Into<String>>(mut self, detail: S) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Records the event in the journal, or on stderr if the journal can't be reached.
+    pub fn record(&self) {
+        if self.send_to(Path::new(JOURNALD_SOCKET)).is_err() {
+            eprintln!("{}", self.message());
+        }
+    }
+
+    fn send_to(&self, path: &Path) -> io::Result<()> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        socket.send(&self.entry()).map(|_| ())
+    }
+
+    /// The event's message, for people reading the journal.
+    fn message(&self) -> String {
+        let mut message = format!("{}: {} '{}'", self.actor, self.action.text(), self.object);
+        match &self.outcome {
+            Outcome::Success => message.push_str(" succeeded"),
+            Outcome::Failure(_) => message.push_str(" failed"),
+        }
+        if let Some(detail) = &self.detail {
+            message.push_str(": ");
+            message.push_str(detail);
+        }
+        message
+    }
+
+    /// The event as a journal entry.
+    fn entry(&self) -> Vec<u8> {
+        let (outcome, priority) = match self.outcome {
+            Outcome::Success => ("success", NOTICE),
+            Outcome::Failure(_) => ("failure", WARNING),
+        };
+        let mut entry = Vec::new();
+        add_field(&mut entry, "MESSAGE", &self.message());
+        add_field(&mut entry, "MESSAGE_ID", self.action.message_id());
+        add_field(&mut entry, "PRIORITY", &priority.to_string());
+        add_field(&mut entry, "SYSLOG_IDENTIFIER", self.actor.name());
+        add_field(&mut entry, "SYSLOG_PID", &process::id().to_string());
+        add_field(&mut entry, "BOTTLEROCKET_ACTOR", self.actor.name());
+        add_field(&mut entry, "BOTTLEROCKET_ACTION", self.action.name());
+        add_field(&mut entry, "BOTTLEROCKET_OBJECT", &self.object);
+        add_field(&mut entry, "BOTTLEROCKET_OUTCOME", outcome);
+        if let Some(detail) = &self.detail {
+            add_field(&mut entry, "BOTTLEROCKET_DETAIL", detail);
+        }
+        entry
+    }
+}
+
+/// Adds a field to a journal entry.  Values with newlines in them are sent with their length
+/// rather than ending at a newline.
+fn add_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+/// Records that `actor` took `action` on `object`, with the given outcome, like the `Result` of
+/// the action.
+pub fn record<S, O>(actor: Actor, action: Action, object: S, outcome: O)
+where
+    S: Into<String>,
+    O: Into<Outcome>,
+{
+    Event::new(actor, action, object).outcome(outcome).record()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn stable_ids() {
+        let mut names = HashSet::new();
+        let mut ids = HashSet::new();
+        for action in Action::ALL {
+            assert!(names.insert(action.name()), "{} repeated", action.name());
+            assert!(ids.insert(action.message_id()), "{:?}'s ID repeated", action);
+            // journald takes IDs of 128 bits in lowercase hex.
+            assert_eq!(action.message_id().len(), 32);
+            assert!(action
+                .message_id()
+                .chars()
+                .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c)));
+        }
+    }
+
+    #[test]
+    fn journal_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("socket");
+        let journal = UnixDatagram::bind(&path).unwrap();
+        let mut buf = [0; 4096];
+
+        Event::new(Actor::Updog, Action::ImageWrite, "1.2.0")
+            .detail("boot=/dev/xvda4")
+            .send_to(&path)
+            .unwrap();
+        let len = journal.recv(&mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buf[..len]),
+            format!(
+                "MESSAGE=updog: Write update images '1.2.0' succeeded: boot=/dev/xvda4\n\
+                 MESSAGE_ID=a8a59d68dc3549a491d6938500e4dd41\n\
+                 PRIORITY=5\n\
+                 SYSLOG_IDENTIFIER=updog\n\
+                 SYSLOG_PID={}\n\
+                 BOTTLEROCKET_ACTOR=updog\n\
+                 BOTTLEROCKET_ACTION=image-write\n\
+                 BOTTLEROCKET_OBJECT=1.2.0\n\
+                 BOTTLEROCKET_OUTCOME=success\n\
+                 BOTTLEROCKET_DETAIL=boot=/dev/xvda4\n",
+                process::id()
+            )
+        );
+
+        // A failure's reason is its detail.
+        let result: Result<(), String> = Err("disk full".to_string());
+        Event::new(Actor::Migrator, Action::MigrationRun, "migrate_v1.1.0_a")
+            .outcome(&result)
+            .send_to(&path)
+            .unwrap();
+        let len = journal.recv(&mut buf).unwrap();
+        let text = String::from_utf8_lossy(&buf[..len]);
+        assert!(text.starts_with(
+            "MESSAGE=migrator: Run migration 'migrate_v1.1.0_a' failed: disk full\n"
+        ));
+        assert!(text.contains("\nPRIORITY=4\n"));
+        assert!(text.ends_with("\nBOTTLEROCKET_OUTCOME=failure\nBOTTLEROCKET_DETAIL=disk full\n"));
+    }
+}
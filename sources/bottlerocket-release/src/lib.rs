@@ -5,16 +5,24 @@ This library lets you get a BottlerocketRelease struct that represents the data
 The VERSION_ID is returned as a semver::Version for convenience.
 
 The information is pulled at runtime because build_id changes frequently and would cause unnecessary rebuilds.
+
+# Version information
+
+Components that need to know what a host is running, like the API server's `/os` endpoint, updog, and logdog, should use a VersionInfo, which adds the version of the datastore to the release data.
+The datastore version is found by following the `current` link in the datastore directory, the same way the migrator does; it can lag behind the OS version if migrations haven't run yet, and it's missing before the datastore is created on first boot.
 */
 
-const DEFAULT_RELEASE_FILE: &str = "/usr/lib/os-release";
+/// The standard os-release file.
+pub const DEFAULT_RELEASE_FILE: &str = "/usr/lib/os-release";
+/// The directory holding the versioned datastores and the `current` link to the one in use.
+pub const DEFAULT_DATASTORE_DIR: &str = "/var/lib/bottlerocket/datastore";
 
 include!(concat!(env!("OUT_DIR"), "/constants.rs"));
 
 use log::debug;
 use semver::Version;
 use serde::{Deserialize, Serialize};
-use snafu::ResultExt;
+use snafu::{OptionExt, ResultExt};
 use std::fs;
 use std::path::Path;
 
@@ -44,6 +52,21 @@ mod error {
 
         #[snafu(display("Unable to load release data from file '{}': {}", path.display(), source))]
         LoadReleaseData { path: PathBuf, source: envy::Error },
+
+        #[snafu(display("Failed to read symlink at {} to find version: {}", link.display(), source))]
+        LinkRead { link: PathBuf, source: io::Error },
+
+        #[snafu(display("Data store link '{}' points to /", path.display()))]
+        DataStoreLinkToRoot { path: PathBuf },
+
+        #[snafu(display("Data store path '{}' contains invalid UTF-8", path.display()))]
+        DataStorePathNotUTF8 { path: PathBuf },
+
+        #[snafu(display("Data store path '{}' contains invalid version: {}", path.display(), source))]
+        InvalidDataStoreVersion {
+            path: PathBuf,
+            source: semver::SemVerError,
+        },
     }
 }
 pub use error::Error;
@@ -54,7 +77,8 @@ impl BottlerocketRelease {
         Self::from_file(DEFAULT_RELEASE_FILE)
     }
 
-    fn from_file<P>(path: P) -> Result<Self>
+    /// Reads release data from the given file rather than the standard os-release file.
+    pub fn from_file<P>(path: P) -> Result<Self>
     where
         P: AsRef<Path>,
     {
@@ -98,3 +122,77 @@ impl BottlerocketRelease {
         envy::from_iter(pairs).context(error::LoadReleaseData { path })
     }
 }
+
+/// Returns the version of the datastore that the `current` link in `datastore_dir` points to.
+///
+/// The links look like this, and the patch version link holds the full version:
+///    /path/to/datastore/current
+///    -> /path/to/datastore/v1
+///    -> /path/to/datastore/v1.5
+///    -> /path/to/datastore/v1.5.2
+///    -> /path/to/datastore/v1.5.2_0123456789abcdef
+pub fn datastore_version<P>(datastore_dir: P) -> Result<Version>
+where
+    P: AsRef<Path>,
+{
+    let datastore_dir = datastore_dir.as_ref();
+
+    // Find the current patch version link, which contains our full version number
+    let current = datastore_dir.join("current");
+    let major =
+        datastore_dir.join(fs::read_link(&current).context(error::LinkRead { link: current })?);
+    let minor = datastore_dir.join(fs::read_link(&major).context(error::LinkRead { link: major })?);
+    let patch = datastore_dir.join(fs::read_link(&minor).context(error::LinkRead { link: minor })?);
+
+    // Pull out the basename of the path, which contains the version
+    let version_os_str = patch
+        .file_name()
+        .context(error::DataStoreLinkToRoot { path: &patch })?;
+    let mut version_str = version_os_str
+        .to_str()
+        .context(error::DataStorePathNotUTF8 { path: &patch })?;
+
+    // Allow 'v' at the start so the links have clearer names for humans
+    if version_str.starts_with('v') {
+        version_str = &version_str[1..];
+    }
+
+    Version::parse(version_str).context(error::InvalidDataStoreVersion { path: &patch })
+}
+
+/// VersionInfo is what a host is running: its release data, and the version of its datastore.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct VersionInfo {
+    #[serde(flatten)]
+    pub release: BottlerocketRelease,
+
+    /// None if the datastore hasn't been created yet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datastore_version: Option<Version>,
+}
+
+impl VersionInfo {
+    pub fn new() -> Result<Self> {
+        Self::from_paths(DEFAULT_RELEASE_FILE, DEFAULT_DATASTORE_DIR)
+    }
+
+    /// Reads version information from the given release file and datastore directory rather
+    /// than the standard ones.
+    pub fn from_paths<P1, P2>(release_file: P1, datastore_dir: P2) -> Result<Self>
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        let release = BottlerocketRelease::from_file(release_file)?;
+        let datastore_dir = datastore_dir.as_ref();
+        let datastore_version = if datastore_dir.join("current").exists() {
+            Some(datastore_version(datastore_dir)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            release,
+            datastore_version,
+        })
+    }
+}
@@ -7,7 +7,7 @@ pub(crate) mod error;
 use error::Result;
 
 use block_party::BlockDevice;
-use gptman::{GPTPartitionEntry, GPT};
+use gptman::GPT;
 use snafu::{ensure, OptionExt, ResultExt};
 use std::fs;
 use std::os::linux::fs::MetadataExt;
@@ -16,67 +16,56 @@ use std::path::{Path, PathBuf};
 pub struct DiskPart {
     gpt: GPT,
     device: PathBuf,
+    part: u32,
 }
 
 impl DiskPart {
     /// Given a path to a partition, find the underlying disk and load the GPT label.
-    pub(crate) fn new<P>(path: P) -> Result<Self>
+    ///
+    /// If a label is given, the partition with that label is selected for growing, and the path
+    /// may also refer to the disk itself. Otherwise, the disk must hold exactly one partition.
+    pub(crate) fn new<P>(path: P, label: Option<String>) -> Result<Self>
     where
         P: AsRef<Path>,
     {
         let path = path.as_ref();
-        let disk = Self::find_disk(path)?;
+        let disk = Self::find_disk(path, label.is_some())?;
         let device = disk.path();
         let gpt = Self::load_gpt(&device)?;
-        let used_partitions = gpt.iter().filter(|(_num, part)| part.is_used()).count();
-        ensure!(
-            used_partitions == 1,
-            error::MultiplePartitions {
-                path,
-                count: used_partitions
-            }
-        );
-        Ok(Self { device, gpt })
-    }
-
-    /// Grow a single partition to fill the available capacity on the device.
-    pub(crate) fn grow(&mut self) -> Result<()> {
-        let gpt = &mut self.gpt;
-        let part = 1;
-        let current = &gpt[part];
-        let partition_name = current.partition_name.clone();
-        let partition_type_guid = current.partition_type_guid;
-        let unique_parition_guid = current.unique_parition_guid;
-
-        let path = &self.device;
 
-        // Remove all existing partitions so that the space shows up as free.
-        gpt.remove(part)
-            .context(error::RemovePartition { part, path })?;
-
-        // First usable LBA is just after the primary label. We want partitions aligned on 1 MB
-        // boundaries, so the first one occurs at 2048 sectors.
-        let starting_lba = 2048;
-
-        // Max size gives us the sector count between starting and ending LBA, but doesn't give
-        // us the last LBA, which we must solve for next.
-        let max_size: u64 = gpt
-            .get_maximum_partition_size()
-            .context(error::FindMaxSize { path })?;
-
-        // We know the first LBA, and we know the sector count, so we can calculate the last LBA.
-        let ending_lba = starting_lba + max_size - 1;
-
-        gpt[part] = GPTPartitionEntry {
-            starting_lba,
-            ending_lba,
-            attribute_bits: 0,
-            partition_name,
-            partition_type_guid,
-            unique_parition_guid,
+        let part = match label {
+            Some(label) => Self::find_partition(&gpt, &label).context(error::LabelNotFound {
+                label,
+                path: &device,
+            })?,
+            None => {
+                let used: Vec<u32> = gpt
+                    .iter()
+                    .filter(|(_num, part)| part.is_used())
+                    .map(|(num, _part)| num)
+                    .collect();
+                ensure!(
+                    used.len() == 1,
+                    error::MultiplePartitions {
+                        path,
+                        count: used.len()
+                    }
+                );
+                used[0]
+            }
         };
 
-        Ok(())
+        Ok(Self { device, gpt, part })
+    }
+
+    /// Grow the selected partition to fill the free space that follows it, leaving the requested
+    /// number of mebibytes unallocated after it.
+    pub(crate) fn grow(&mut self, reserve_mib: u64) -> Result<()> {
+        let reserve_sectors = reserve_mib
+            .checked_mul(1024 * 1024)
+            .context(error::ReserveTooLarge { reserve_mib })?
+            / self.gpt.sector_size;
+        grow_partition(&mut self.gpt, self.part, reserve_sectors, &self.device)
     }
 
     /// Write the GPT label back to the device. If this is a block device, tell
@@ -103,8 +92,9 @@ impl DiskPart {
         Ok(())
     }
 
-    /// Find the block device that holds the specified partition.
-    fn find_disk<P>(path: P) -> Result<BlockDevice>
+    /// Find the block device that holds the specified partition. If `allow_disk` is set, the
+    /// path may also refer to the disk itself.
+    fn find_disk<P>(path: P, allow_disk: bool) -> Result<BlockDevice>
     where
         P: AsRef<Path>,
     {
@@ -117,26 +107,159 @@ impl DiskPart {
                 path: &partition_path,
             })?;
 
-        let disk = partition_device
-            .disk()
-            .context(error::FindDisk {
-                path: &partition_path,
-            })?
-            .context(error::NotPartition {
-                path: &partition_path,
-            })?;
+        let disk = partition_device.disk().context(error::FindDisk {
+            path: &partition_path,
+        })?;
 
-        Ok(disk)
+        match disk {
+            Some(disk) => Ok(disk),
+            None if allow_disk => Ok(partition_device),
+            None => error::NotPartition {
+                path: &partition_path,
+            }
+            .fail(),
+        }
     }
 
-    /// Load the GPT disk label from the device.
+    /// Load the GPT disk label from the device, moving the secondary header and the last usable
+    /// sector to the end of the disk in case the disk has grown.
     fn load_gpt<P>(path: P) -> Result<GPT>
     where
         P: AsRef<Path>,
     {
         let path = path.as_ref();
         let mut f = fs::File::open(path).context(error::DeviceOpen { path })?;
-        let gpt = GPT::find_from(&mut f).context(error::ReadPartitionTable { path })?;
+        let mut gpt = GPT::find_from(&mut f).context(error::ReadPartitionTable { path })?;
+        gpt.header
+            .update_from(&mut f, gpt.sector_size)
+            .context(error::RepairPartitionTable { path })?;
         Ok(gpt)
     }
+
+    /// Find the number of the used partition with the given label.
+    fn find_partition(gpt: &GPT, label: &str) -> Option<u32> {
+        gpt.iter()
+            .find(|(_num, part)| part.is_used() && part.partition_name.as_str() == label)
+            .map(|(num, _part)| num)
+    }
+}
+
+/// Grow partition `part` into the free space that follows it, up to the next partition or the
+/// last usable sector, minus `reserve_sectors`. The starting sector is left unchanged, and the
+/// size is kept aligned to the label's partition alignment.
+fn grow_partition(gpt: &mut GPT, part: u32, reserve_sectors: u64, path: &Path) -> Result<()> {
+    let starting_lba = gpt[part].starting_lba;
+    let current_ending_lba = gpt[part].ending_lba;
+
+    // The space available to us ends just before the next partition, or at the end of the
+    // usable area if this is the last partition on the disk.
+    let limit = gpt
+        .iter()
+        .filter(|(_num, other)| other.is_used() && other.starting_lba > starting_lba)
+        .map(|(_num, other)| other.starting_lba)
+        .min()
+        .unwrap_or(gpt.header.last_usable_lba + 1);
+
+    let available = limit
+        .checked_sub(reserve_sectors)
+        .and_then(|end| end.checked_sub(starting_lba))
+        .unwrap_or(0);
+    let size = available / gpt.align * gpt.align;
+
+    // We know the first LBA, and we know the sector count, so we can calculate the last LBA.
+    // Never shrink the partition; if the reserved space doesn't fit, that's an error.
+    let ending_lba = (starting_lba + size).saturating_sub(1);
+    ensure!(
+        size > 0 && ending_lba >= current_ending_lba,
+        error::NoSpace {
+            part,
+            path,
+            reserve_sectors
+        }
+    );
+
+    gpt[part].ending_lba = ending_lba;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gptman::GPTPartitionEntry;
+    use std::io::Cursor;
+
+    const SECTOR_SIZE: u64 = 512;
+    const MIB: u64 = 1024 * 1024 / SECTOR_SIZE;
+
+    /// Create a label on a disk image of `disk_mib` size with partitions matching the given
+    /// labels and (start, end) sectors.
+    fn make_gpt(disk_mib: u64, parts: &[(&str, u64, u64)]) -> GPT {
+        let mut cur = Cursor::new(vec![0; (disk_mib * MIB * SECTOR_SIZE) as usize]);
+        let mut gpt = GPT::new_from(&mut cur, SECTOR_SIZE, [0xff; 16]).unwrap();
+        for (i, (label, start, end)) in parts.iter().enumerate() {
+            gpt[i as u32 + 1] = GPTPartitionEntry {
+                partition_type_guid: [0xff; 16],
+                unique_parition_guid: [i as u8 + 1; 16],
+                starting_lba: *start,
+                ending_lba: *end,
+                attribute_bits: 0,
+                partition_name: (*label).into(),
+            };
+        }
+        gpt
+    }
+
+    #[test]
+    fn grow_last_partition() {
+        let mut gpt = make_gpt(16, &[("DATA", MIB, 2 * MIB - 1)]);
+        let last_usable = gpt.header.last_usable_lba;
+        grow_partition(&mut gpt, 1, 0, Path::new("test")).unwrap();
+        assert_eq!(gpt[1].starting_lba, MIB);
+        // Aligned to 1 MiB, so the partial MiB holding the secondary label is left alone.
+        assert_eq!(gpt[1].ending_lba, 15 * MIB - 1);
+        assert!(gpt[1].ending_lba <= last_usable);
+    }
+
+    #[test]
+    fn grow_by_label_stops_at_next_partition() {
+        let mut gpt = make_gpt(
+            16,
+            &[("DATA", MIB, 2 * MIB - 1), ("LOG", 8 * MIB, 10 * MIB - 1)],
+        );
+        let part = DiskPart::find_partition(&gpt, "DATA").unwrap();
+        grow_partition(&mut gpt, part, 0, Path::new("test")).unwrap();
+        assert_eq!(gpt[part].ending_lba, 8 * MIB - 1);
+
+        let part = DiskPart::find_partition(&gpt, "LOG").unwrap();
+        grow_partition(&mut gpt, part, 0, Path::new("test")).unwrap();
+        assert_eq!(gpt[part].starting_lba, 8 * MIB);
+        assert_eq!(gpt[part].ending_lba, 15 * MIB - 1);
+
+        assert!(DiskPart::find_partition(&gpt, "MISSING").is_none());
+    }
+
+    #[test]
+    fn grow_with_reserve() {
+        let mut gpt = make_gpt(16, &[("DATA", MIB, 2 * MIB - 1)]);
+        grow_partition(&mut gpt, 1, 4 * MIB, Path::new("test")).unwrap();
+        assert_eq!(gpt[1].ending_lba, 11 * MIB - 1);
+    }
+
+    #[test]
+    fn reserve_too_large() {
+        let mut gpt = make_gpt(16, &[("DATA", MIB, 8 * MIB - 1)]);
+        assert!(grow_partition(&mut gpt, 1, 12 * MIB, Path::new("test")).is_err());
+        assert_eq!(gpt[1].ending_lba, 8 * MIB - 1);
+    }
+
+    #[test]
+    fn reserve_overflow() {
+        let mut diskpart = DiskPart {
+            gpt: make_gpt(16, &[("DATA", MIB, 8 * MIB - 1)]),
+            device: PathBuf::from("test"),
+            part: 1,
+        };
+        assert!(diskpart.grow(u64::MAX / 1024).is_err());
+        assert_eq!(diskpart.gpt[1].ending_lba, 8 * MIB - 1);
+    }
 }
@@ -36,42 +36,85 @@ pub enum Error {
         source: std::io::Error,
     },
 
+    #[snafu(display("Failed to find size of '{}': {}", path.display(), source))]
+    DeviceSize {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to stat filesystem at '{}': {}", path.display(), source))]
+    StatFilesystem {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Unsupported filesystem at '{}' with magic {:#x}", path.display(), magic))]
+    UnsupportedFilesystem {
+        path: std::path::PathBuf,
+        magic: u64,
+    },
+
+    #[snafu(display("Failed to resize {} filesystem at '{}': {}", filesystem, path.display(), source))]
+    ResizeFilesystem {
+        path: std::path::PathBuf,
+        filesystem: String,
+        source: std::io::Error,
+    },
+
     #[snafu(display("Failed to read partition table from '{}': {}", path.display(), source))]
     ReadPartitionTable {
         path: std::path::PathBuf,
         source: gptman::Error,
     },
 
-    #[snafu(display("Found {} partitions on '{}', can only resize with 1", count, path.display()))]
+    #[snafu(display(
+        "Found {} partitions on '{}', can only resize with 1 unless --label is given",
+        count,
+        path.display()
+    ))]
     MultiplePartitions {
         path: std::path::PathBuf,
         count: usize,
     },
 
-    #[snafu(display("Failed to write partition table to '{}': {}", path.display(), source))]
-    WritePartitionTable {
+    #[snafu(display("Failed to find partition labeled '{}' on '{}'", label, path.display()))]
+    LabelNotFound {
+        label: String,
         path: std::path::PathBuf,
-        source: gptman::Error,
     },
 
-    #[snafu(display("Failed to reload partition table from '{}': {}", path.display(), source))]
-    ReloadPartitionTable {
+    #[snafu(display(
+        "Not enough free space to grow partition {} on '{}' while reserving {} sectors",
+        part,
+        path.display(),
+        reserve_sectors
+    ))]
+    NoSpace {
+        part: u32,
         path: std::path::PathBuf,
-        source: gptman::linux::BlockError,
+        reserve_sectors: u64,
     },
 
-    #[snafu(display("Failed to remove partition {} from '{}': {}", part, path.display(), source))]
-    RemovePartition {
-        part: u32,
+    #[snafu(display("Reserved size of {} MiB is too large to count in bytes", reserve_mib))]
+    ReserveTooLarge { reserve_mib: u64 },
+
+    #[snafu(display("Failed to move secondary partition table on '{}': {}", path.display(), source))]
+    RepairPartitionTable {
         path: std::path::PathBuf,
         source: gptman::Error,
     },
 
-    #[snafu(display("Failed to find maximum partition size for '{}': {}", path.display(), source))]
-    FindMaxSize {
+    #[snafu(display("Failed to write partition table to '{}': {}", path.display(), source))]
+    WritePartitionTable {
         path: std::path::PathBuf,
         source: gptman::Error,
     },
+
+    #[snafu(display("Failed to reload partition table from '{}': {}", path.display(), source))]
+    ReloadPartitionTable {
+        path: std::path::PathBuf,
+        source: gptman::linux::BlockError,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
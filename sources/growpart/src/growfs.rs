@@ -0,0 +1,210 @@
+/*!
+This module grows a mounted filesystem to fill the block device that holds it, using the same
+online resize requests as `resize2fs` and `xfs_growfs`.  ext4 and xfs are supported.
+*/
+
+use crate::diskpart::error::{self, Result};
+use block_party::BlockDevice;
+use snafu::ResultExt;
+use std::ffi::CString;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+// Filesystem magic numbers, as reported by statfs(2).
+const EXT4_SUPER_MAGIC: u64 = 0xef53;
+const XFS_SUPER_MAGIC: u64 = 0x5846_5342;
+
+// ioctl request numbers from linux/fs.h, fs/ext4/ext4.h, and xfs/xfs_fs.h.
+const BLKGETSIZE64: u64 = 0x8008_1272;
+const EXT4_IOC_RESIZE_FS: u64 = 0x4008_6610;
+const XFS_IOC_FSGEOMETRY_V1: u64 = 0x8070_5864;
+const XFS_IOC_FSGROWFSDATA: u64 = 0x4010_586e;
+
+/// The filesystems we know how to grow.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Filesystem {
+    Ext4,
+    Xfs,
+}
+
+impl fmt::Display for Filesystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filesystem::Ext4 => write!(f, "ext4"),
+            Filesystem::Xfs => write!(f, "xfs"),
+        }
+    }
+}
+
+/// Matches `struct xfs_fsop_geom_v1`, the oldest and most widely supported geometry request.
+#[repr(C)]
+#[derive(Debug, Default)]
+struct XfsGeometry {
+    blocksize: u32,
+    rtextsize: u32,
+    agblocks: u32,
+    agcount: u32,
+    logblocks: u32,
+    sectsize: u32,
+    inodesize: u32,
+    imaxpct: u32,
+    datablocks: u64,
+    rtblocks: u64,
+    rtextents: u64,
+    logstart: u64,
+    uuid: [u8; 16],
+    sunit: u32,
+    swidth: u32,
+    version: i32,
+    flags: u32,
+    logsectsize: u32,
+    rtsectsize: u32,
+    dirblocksize: u32,
+}
+
+/// Matches `struct xfs_growfs_data`.
+#[repr(C)]
+#[derive(Debug)]
+struct XfsGrowData {
+    newblocks: u64,
+    imaxpct: u32,
+}
+
+/// Grow the filesystem mounted at `mountpoint` to fill its block device.
+pub(crate) fn grow_filesystem<P>(mountpoint: P) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let path = mountpoint.as_ref();
+    let (filesystem, block_size) = filesystem_info(path)?;
+
+    let device = BlockDevice::from_device_path(path)
+        .context(error::FindBlockDevice { path })?
+        .path();
+    let device_size = device_size(&device)?;
+
+    // The resize requests are issued against any open file on the mounted filesystem.
+    let mount = File::open(path).context(error::DeviceOpen { path })?;
+
+    match filesystem {
+        Filesystem::Ext4 => {
+            let mut blocks = device_size / block_size;
+            println!(
+                "Resizing {} filesystem at '{}' to {} blocks of {} bytes",
+                filesystem,
+                path.display(),
+                blocks,
+                block_size
+            );
+            // The kernel treats a request for the current size as a successful no-op.
+            ioctl(&mount, EXT4_IOC_RESIZE_FS, &mut blocks).context(error::ResizeFilesystem {
+                path,
+                filesystem: filesystem.to_string(),
+            })?;
+        }
+
+        Filesystem::Xfs => {
+            let mut geometry = XfsGeometry::default();
+            ioctl(&mount, XFS_IOC_FSGEOMETRY_V1, &mut geometry).context(
+                error::ResizeFilesystem {
+                    path,
+                    filesystem: filesystem.to_string(),
+                },
+            )?;
+
+            let blocks = device_size / u64::from(geometry.blocksize);
+            if blocks <= geometry.datablocks {
+                println!(
+                    "{} filesystem at '{}' already fills its device ({} blocks)",
+                    filesystem,
+                    path.display(),
+                    geometry.datablocks
+                );
+                return Ok(());
+            }
+
+            println!(
+                "Resizing {} filesystem at '{}' from {} to {} blocks of {} bytes",
+                filesystem,
+                path.display(),
+                geometry.datablocks,
+                blocks,
+                geometry.blocksize
+            );
+            let mut request = XfsGrowData {
+                newblocks: blocks,
+                imaxpct: geometry.imaxpct,
+            };
+            ioctl(&mount, XFS_IOC_FSGROWFSDATA, &mut request).context(error::ResizeFilesystem {
+                path,
+                filesystem: filesystem.to_string(),
+            })?;
+        }
+    }
+
+    println!("Resized {} filesystem at '{}'", filesystem, path.display());
+    Ok(())
+}
+
+/// Find the type and block size of the filesystem mounted at `path`.
+fn filesystem_info(path: &Path) -> Result<(Filesystem, u64)> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a null byte"));
+    let c_path = c_path.context(error::StatFilesystem { path })?;
+
+    let mut buf: libc::statfs = unsafe { mem::zeroed() };
+    // Safe because the path is null-terminated and the buffer is a properly sized statfs.
+    let rc = unsafe { libc::statfs(c_path.as_ptr(), &mut buf) };
+    if rc != 0 {
+        return Err(io::Error::last_os_error()).context(error::StatFilesystem { path });
+    }
+
+    // The width and signedness of these fields vary between libc implementations.
+    let (magic, block_size) = (buf.f_type as u64, buf.f_bsize as u64);
+    let filesystem = match magic {
+        EXT4_SUPER_MAGIC => Filesystem::Ext4,
+        XFS_SUPER_MAGIC => Filesystem::Xfs,
+        _ => return error::UnsupportedFilesystem { path, magic }.fail(),
+    };
+    Ok((filesystem, block_size))
+}
+
+/// Find the size of a block device in bytes.
+fn device_size(path: &Path) -> Result<u64> {
+    let f = File::open(path).context(error::DeviceOpen { path })?;
+    let mut size: u64 = 0;
+    ioctl(&f, BLKGETSIZE64, &mut size).context(error::DeviceSize { path })?;
+    Ok(size)
+}
+
+/// Issue an ioctl request against an open file, with a pointer to `arg` as the argument.
+fn ioctl<T>(f: &File, request: u64, arg: &mut T) -> io::Result<()> {
+    // Safe because each request number above is paired with a matching `repr(C)` argument, so
+    // the kernel reads or writes at most `size_of::<T>()` bytes.
+    let rc = unsafe { libc::ioctl(f.as_raw_fd(), request as _, arg as *mut T) };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn request_sizes() {
+        // The ioctl request numbers above encode these sizes.
+        assert_eq!(mem::size_of::<XfsGeometry>(), 112);
+        assert_eq!(mem::size_of::<XfsGrowData>(), 16);
+        assert_eq!((XFS_IOC_FSGEOMETRY_V1 >> 16) & 0x3fff, 112);
+        assert_eq!((XFS_IOC_FSGROWFSDATA >> 16) & 0x3fff, 16);
+        assert_eq!((EXT4_IOC_RESIZE_FS >> 16) & 0x3fff, 8);
+        assert_eq!((BLKGETSIZE64 >> 16) & 0x3fff, 8);
+    }
+}
@@ -3,24 +3,65 @@
 
 growpart is a helper program to expand a partition to fill all available sectors on the
 underlying block device.
+
+By default, the disk must contain exactly one partition, which is grown to the end of the disk.
+For custom disk layouts with more than one partition, the partition to grow can be chosen by its
+GPT label with `--label`; it then grows into the free space that follows it, up to the next
+partition or the end of the disk.
+
+The `--reserve-mib` option leaves the given amount of space unallocated after the grown
+partition, for example to add a separate partition later.
+
+If the disk has been enlarged since the partition table was written, the secondary GPT header
+is moved to the new end of the disk, so the partition can use the new sectors in a single pass.
+
+After the grown partition is mounted, `--resize-fs` grows the ext4 or xfs filesystem mounted at
+the given path to fill the partition, with the same online resize requests used by `resize2fs`
+and `xfs_growfs`.
 */
 
 mod diskpart;
+mod growfs;
 use diskpart::error::Result;
 use diskpart::DiskPart;
 use std::env;
 use std::path::PathBuf;
 
+/// The action requested by the user.
+#[derive(Debug)]
+enum Action {
+    GrowPartition {
+        partition: PathBuf,
+        label: Option<String>,
+        reserve_mib: u64,
+    },
+    ResizeFilesystem {
+        mountpoint: PathBuf,
+    },
+}
+
 /// Stores user-supplied arguments.
 #[derive(Debug)]
 struct Args {
-    partition: PathBuf,
+    action: Action,
 }
 
 /// Informs the user about proper usage of the program and exits.
 fn usage() -> ! {
     let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
-    eprintln!(r"Usage: {} PARTITION", program_name);
+    eprintln!(
+        r"Usage: {0} [ --label LABEL ] [ --reserve-mib MIB ] PARTITION
+       {0} --resize-fs MOUNTPOINT
+
+    PARTITION is the partition to grow, or with --label, any partition on the
+    disk (or the disk itself).
+
+    --label LABEL           Grow the partition with this GPT label
+    --reserve-mib MIB       Leave MIB mebibytes unallocated after the partition
+    --resize-fs MOUNTPOINT  Grow the ext4 or xfs filesystem mounted at MOUNTPOINT
+                            to fill its partition",
+        program_name
+    );
     std::process::exit(2);
 }
 
@@ -31,19 +72,75 @@ fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
 }
 
 /// Parses user arguments into an Args structure.
-fn parse_args() -> Result<Args> {
-    let partition = env::args()
-        .nth(1)
-        .unwrap_or_else(|| usage_msg("Did not specify partition"));
-    let partition = PathBuf::from(partition);
-    Ok(Args { partition })
+fn parse_args(args: env::Args) -> Result<Args> {
+    let mut partition = None;
+    let mut label = None;
+    let mut reserve_mib = 0;
+    let mut mountpoint = None;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--label" => {
+                label = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --label")),
+                )
+            }
+
+            "--reserve-mib" => {
+                let reserve_str = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --reserve-mib"));
+                reserve_mib = reserve_str.parse().unwrap_or_else(|_| {
+                    usage_msg(format!("Invalid reserved size '{}'", reserve_str))
+                });
+            }
+
+            "--resize-fs" => {
+                mountpoint =
+                    Some(PathBuf::from(iter.next().unwrap_or_else(|| {
+                        usage_msg("Did not give argument to --resize-fs")
+                    })))
+            }
+
+            s if !s.starts_with('-') && partition.is_none() => {
+                partition = Some(PathBuf::from(s));
+            }
+
+            _ => usage(),
+        }
+    }
+
+    let action = match (partition, mountpoint) {
+        (Some(partition), None) => Action::GrowPartition {
+            partition,
+            label,
+            reserve_mib,
+        },
+        (None, Some(mountpoint)) if label.is_none() && reserve_mib == 0 => {
+            Action::ResizeFilesystem { mountpoint }
+        }
+        (None, None) => usage_msg("Did not specify partition"),
+        _ => usage_msg("--resize-fs cannot be combined with partition options"),
+    };
+    Ok(Args { action })
 }
 
 fn run() -> Result<()> {
-    let args = parse_args()?;
-    let mut diskpart = DiskPart::new(args.partition)?;
-    diskpart.grow()?;
-    diskpart.write()?;
+    let args = parse_args(env::args())?;
+    match args.action {
+        Action::GrowPartition {
+            partition,
+            label,
+            reserve_mib,
+        } => {
+            let mut diskpart = DiskPart::new(partition, label)?;
+            diskpart.grow(reserve_mib)?;
+            diskpart.write()?;
+        }
+        Action::ResizeFilesystem { mountpoint } => growfs::grow_filesystem(mountpoint)?,
+    }
     Ok(())
 }
 
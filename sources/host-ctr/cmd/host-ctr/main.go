@@ -1,10 +1,14 @@
 package main
 
 import (
+	"bufio"
 	"context"
+	"encoding/base64"
 	"flag"
+	"io"
 	"io/ioutil"
 	"math/rand"
+	"net/url"
 	"os"
 	"os/signal"
 	"regexp"
@@ -12,15 +16,19 @@ import (
 	"syscall"
 	"time"
 
+	"github.com/BurntSushi/toml"
 	"github.com/aws/aws-sdk-go/aws/arn"
 	"github.com/awslabs/amazon-ecr-containerd-resolver/ecr"
 	"github.com/containerd/containerd"
 	"github.com/containerd/containerd/cio"
+	"github.com/containerd/containerd/containers"
 	"github.com/containerd/containerd/contrib/seccomp"
 	"github.com/containerd/containerd/errdefs"
 	"github.com/containerd/containerd/log"
 	"github.com/containerd/containerd/namespaces"
 	"github.com/containerd/containerd/oci"
+	"github.com/containerd/containerd/reference"
+	"github.com/containerd/containerd/remotes/docker"
 	"github.com/opencontainers/runc/libcontainer/cgroups"
 	runtimespec "github.com/opencontainers/runtime-spec/specs-go"
 	"github.com/pkg/errors"
@@ -47,6 +55,12 @@ func _main() int {
 		namespace        string
 		superpowered     bool
 		pullImageOnly    bool
+		envFile          string
+		argsFile         string
+		mounts           string
+		memoryLimitMiB   uint64
+		cpuQuotaPercent  uint64
+		registryConfig   string
 	)
 	flag.StringVar(&targetCtr, "ctr-id", "", "The ID of the container to be started")
 	flag.StringVar(&source, "source", "", "The image to be pulled")
@@ -54,6 +68,12 @@ func _main() int {
 	flag.BoolVar(&pullImageOnly, "pull-image-only", false, "Only pull and unpack the container image, do not start any container task")
 	flag.StringVar(&containerdSocket, "containerd-socket", "/run/host-containerd/containerd.sock", "Specifies the path to the containerd socket. Defaults to `/run/host-containerd/containerd.sock`")
 	flag.StringVar(&namespace, "namespace", "default", "Specifies the containerd namespace")
+	flag.StringVar(&envFile, "env-file", "", "File of NAME=VALUE lines to set in the container's environment")
+	flag.StringVar(&argsFile, "args-file", "", "File of arguments, one per line, to run in place of the image's command")
+	flag.StringVar(&mounts, "mounts", "", "Comma-separated extra bind mounts, each of the form `source:destination:ro|rw`")
+	flag.Uint64Var(&memoryLimitMiB, "memory-limit-mib", 0, "Memory limit for the container in MiB; 0 means unlimited")
+	flag.Uint64Var(&cpuQuotaPercent, "cpu-quota-percent", 0, "CPU limit for the container as a percentage of one CPU; 0 means unlimited")
+	flag.StringVar(&registryConfig, "registry-config", "/etc/host-containers/registry.toml", "Specifies the path to the registry mirror and credential configuration")
 	flag.Parse()
 
 	if source == "" || (targetCtr == "" && !pullImageOnly) {
@@ -88,9 +108,16 @@ func _main() int {
 	}
 	defer client.Close()
 
-	// Check if the image is from ECR, if it is, convert the image name into a resolvable reference
+	registries, err := loadRegistryConfig(registryConfig)
+	if err != nil {
+		log.G(ctx).WithError(err).WithField("registry-config", registryConfig).Error("Failed to load registry configuration")
+		return 1
+	}
+
+	// Check if the image is from ECR, if it is, convert the image name into a resolvable reference.
+	// If the ECR registry is mirrored, we pull from the mirror with the original name instead.
 	ref := source
-	match := ecrRegex.MatchString(source)
+	match := ecrRegex.MatchString(source) && !registries.hasMirror(source)
 	if match {
 		var err error
 		ref, err = ecrImageNameToRef(source)
@@ -100,7 +127,7 @@ func _main() int {
 		}
 	}
 
-	img, err := pullImage(ctx, ref, client)
+	img, err := pullImage(ctx, ref, client, registries)
 	if err != nil {
 		log.G(ctx).WithField("ref", ref).Error(err)
 		return 1
@@ -123,6 +150,23 @@ func _main() int {
 		return 0
 	}
 
+	// Read the extra settings for the container before touching any existing container
+	env, err := readEnvFile(envFile)
+	if err != nil {
+		log.G(ctx).WithError(err).WithField("env-file", envFile).Error("Failed to read container environment")
+		return 1
+	}
+	args, err := readArgsFile(argsFile)
+	if err != nil {
+		log.G(ctx).WithError(err).WithField("args-file", argsFile).Error("Failed to read container arguments")
+		return 1
+	}
+	extraMounts, err := parseMounts(mounts)
+	if err != nil {
+		log.G(ctx).WithError(err).WithField("mounts", mounts).Error("Failed to parse container mounts")
+		return 1
+	}
+
 	// Clean up target container if it already exists before starting container task
 	if err := deleteCtrIfExists(ctx, client, targetCtr); err != nil {
 		return 1
@@ -137,7 +181,8 @@ func _main() int {
 
 	// Set up the container specifications depending on the type of container and whether it's superpowered or not
 	ctrOpts := containerd.WithNewSpec(
-		oci.WithImageConfig(img),
+		// Without arguments, the image's command is run as it is
+		oci.WithImageConfigArgs(img, args),
 		oci.WithHostNamespace(runtimespec.NetworkNamespace),
 		oci.WithHostHostsFile,
 		oci.WithHostResolvconf,
@@ -163,6 +208,10 @@ func _main() int {
 				Source:      "/local/host-containers/" + targetCtr,
 			}}),
 		withSuperpowered(superpowered),
+		// Apply the user's settings last, so they take precedence over the image's environment
+		oci.WithEnv(env),
+		oci.WithMounts(extraMounts),
+		withResourceLimits(memoryLimitMiB, cpuQuotaPercent),
 	)
 
 	// Create and start the container via containerd
@@ -325,13 +374,137 @@ func withSuperpowered(superpowered bool) oci.SpecOpts {
 	)
 }
 
+// Read the NAME=VALUE lines of an environment file; a missing or unset file means no extra environment
+func readEnvFile(path string) ([]string, error) {
+	if path == "" {
+		return nil, nil
+	}
+	f, err := os.Open(path)
+	if err != nil {
+		if os.IsNotExist(err) {
+			return nil, nil
+		}
+		return nil, err
+	}
+	defer f.Close()
+	return parseEnv(f)
+}
+
+// Parse NAME=VALUE lines, skipping blank lines; values are taken literally, without quoting
+func parseEnv(r io.Reader) ([]string, error) {
+	var env []string
+	scanner := bufio.NewScanner(r)
+	for scanner.Scan() {
+		line := scanner.Text()
+		if line == "" {
+			continue
+		}
+		if !strings.Contains(line, "=") || strings.HasPrefix(line, "=") {
+			return nil, errors.Errorf("invalid environment line %q", line)
+		}
+		env = append(env, line)
+	}
+	if err := scanner.Err(); err != nil {
+		return nil, err
+	}
+	return env, nil
+}
+
+// Read the arguments to run in place of the image's command, if there's a file of them
+func readArgsFile(path string) ([]string, error) {
+	if path == "" {
+		return nil, nil
+	}
+	f, err := os.Open(path)
+	if err != nil {
+		return nil, err
+	}
+	defer f.Close()
+	return parseArgs(f)
+}
+
+// Parse one argument per line; every line counts, so an empty line is an empty argument
+func parseArgs(r io.Reader) ([]string, error) {
+	var args []string
+	scanner := bufio.NewScanner(r)
+	for scanner.Scan() {
+		args = append(args, scanner.Text())
+	}
+	if err := scanner.Err(); err != nil {
+		return nil, err
+	}
+	return args, nil
+}
+
+// Parse a comma-separated list of `source:destination:ro|rw` bind mounts
+func parseMounts(input string) ([]runtimespec.Mount, error) {
+	var mounts []runtimespec.Mount
+	if input == "" {
+		return mounts, nil
+	}
+	for _, entry := range strings.Split(input, ",") {
+		fields := strings.Split(entry, ":")
+		if len(fields) != 3 {
+			return nil, errors.Errorf("invalid mount %q, expected source:destination:ro|rw", entry)
+		}
+		source, destination, mode := fields[0], fields[1], fields[2]
+		if !strings.HasPrefix(source, "/") || !strings.HasPrefix(destination, "/") {
+			return nil, errors.Errorf("invalid mount %q, paths must be absolute", entry)
+		}
+		if mode != "ro" && mode != "rw" {
+			return nil, errors.Errorf("invalid mount %q, mode must be ro or rw", entry)
+		}
+		mounts = append(mounts, runtimespec.Mount{
+			Options:     []string{"rbind", mode},
+			Destination: destination,
+			Source:      source,
+		})
+	}
+	return mounts, nil
+}
+
+// The CFS period used for CPU limits, in microseconds; the kernel's default
+const cfsPeriod = 100000
+
+// Add memory and CPU limits to the container; zero means unlimited
+func withResourceLimits(memoryLimitMiB uint64, cpuQuotaPercent uint64) oci.SpecOpts {
+	return func(_ context.Context, _ oci.Client, _ *containers.Container, s *runtimespec.Spec) error {
+		if memoryLimitMiB == 0 && cpuQuotaPercent == 0 {
+			return nil
+		}
+		if s.Linux == nil {
+			s.Linux = &runtimespec.Linux{}
+		}
+		if s.Linux.Resources == nil {
+			s.Linux.Resources = &runtimespec.LinuxResources{}
+		}
+		if memoryLimitMiB > 0 {
+			limit := int64(memoryLimitMiB * 1024 * 1024)
+			if s.Linux.Resources.Memory == nil {
+				s.Linux.Resources.Memory = &runtimespec.LinuxMemory{}
+			}
+			s.Linux.Resources.Memory.Limit = &limit
+		}
+		if cpuQuotaPercent > 0 {
+			period := uint64(cfsPeriod)
+			quota := int64(cpuQuotaPercent * cfsPeriod / 100)
+			if s.Linux.Resources.CPU == nil {
+				s.Linux.Resources.CPU = &runtimespec.LinuxCPU{}
+			}
+			s.Linux.Resources.CPU.Period = &period
+			s.Linux.Resources.CPU.Quota = &quota
+		}
+		return nil
+	}
+}
+
 // Expecting to match ECR image names of the form:
 // Example 1: 777777777777.dkr.ecr.us-west-2.amazonaws.com/my_image:latest
 // Example 2: 777777777777.dkr.ecr.cn-north-1.amazonaws.com.cn/my_image:latest
 var ecrRegex = regexp.MustCompile(`(^[a-zA-Z0-9][a-zA-Z0-9-_]*)\.dkr\.ecr\.([a-zA-Z0-9][a-zA-Z0-9-_]*)\.amazonaws\.com(\.cn)?.*`)
 
 // Pulls image from specified source
-func pullImage(ctx context.Context, source string, client *containerd.Client) (containerd.Image, error) {
+func pullImage(ctx context.Context, source string, client *containerd.Client, registries *registryConfig) (containerd.Image, error) {
 	// Pull the image
 	// Retry with exponential backoff when failures occur, maximum retry duration will not exceed 31 seconds
 	const maxRetryAttempts = 5
@@ -345,7 +518,7 @@ func pullImage(ctx context.Context, source string, client *containerd.Client) (c
 	for {
 		var err error
 		img, err = client.Pull(ctx, source,
-			withDynamicResolver(ctx, source),
+			withDynamicResolver(ctx, source, registries),
 			containerd.WithSchema1Conversion)
 		if err == nil {
 			break
@@ -406,10 +579,9 @@ func tagImage(ctx context.Context, imageName string, newImageName string, client
 }
 
 // Return the resolver appropriate for the specified image reference
-func withDynamicResolver(ctx context.Context, ref string) containerd.RemoteOpt {
+func withDynamicResolver(ctx context.Context, ref string, registries *registryConfig) containerd.RemoteOpt {
 	if !strings.HasPrefix(ref, "ecr.aws/") {
-		// not handled here
-		return func(_ *containerd.Client, _ *containerd.RemoteContext) error { return nil }
+		return withRegistryResolver(ctx, ref, registries)
 	}
 	return func(_ *containerd.Client, c *containerd.RemoteContext) error {
 		// Create the ECR resolver
@@ -423,6 +595,110 @@ func withDynamicResolver(ctx context.Context, ref string) containerd.RemoteOpt {
 	}
 }
 
+// Registry mirrors and credentials, in the same form as containerd's CRI registry configuration
+type registryConfig struct {
+	Mirrors map[string]registryMirror     `toml:"mirrors"`
+	Configs map[string]registryHostConfig `toml:"configs"`
+}
+
+type registryMirror struct {
+	Endpoints []string `toml:"endpoint"`
+}
+
+type registryHostConfig struct {
+	Auth registryAuth `toml:"auth"`
+}
+
+type registryAuth struct {
+	// The base64 encoding of "username:password"
+	Auth string `toml:"auth"`
+}
+
+// Load the registry configuration; a missing file means no mirrors or credentials
+func loadRegistryConfig(path string) (*registryConfig, error) {
+	config := &registryConfig{}
+	if _, err := toml.DecodeFile(path, config); err != nil && !os.IsNotExist(err) {
+		return nil, err
+	}
+	return config, nil
+}
+
+// Check whether a mirror is configured for the registry of the given image reference
+func (r *registryConfig) hasMirror(ref string) bool {
+	spec, err := reference.Parse(ref)
+	if err != nil {
+		return false
+	}
+	_, ok := r.Mirrors[spec.Hostname()]
+	return ok
+}
+
+// Find the host to pull from for the given registry, and whether to use plain HTTP.  Only the
+// first mirror endpoint is used; without a mirror, we use the registry itself.
+func (r *registryConfig) mirrorHost(registry string) (string, bool, error) {
+	mirror, ok := r.Mirrors[registry]
+	if !ok || len(mirror.Endpoints) == 0 {
+		host, err := docker.DefaultHost(registry)
+		return host, false, err
+	}
+	endpoint := mirror.Endpoints[0]
+	if !strings.Contains(endpoint, "://") {
+		endpoint = "https://" + endpoint
+	}
+	u, err := url.Parse(endpoint)
+	if err != nil {
+		return "", false, errors.Wrapf(err, "invalid mirror endpoint %q for %s", mirror.Endpoints[0], registry)
+	}
+	return u.Host, u.Scheme == "http", nil
+}
+
+// Find the username and secret to use for a host, if any were given
+func (r *registryConfig) credentials(host string) (string, string, error) {
+	config, ok := r.Configs[host]
+	if !ok || config.Auth.Auth == "" {
+		return "", "", nil
+	}
+	decoded, err := base64.StdEncoding.DecodeString(config.Auth.Auth)
+	if err != nil {
+		return "", "", errors.Wrapf(err, "invalid auth for %s", host)
+	}
+	parts := strings.SplitN(string(decoded), ":", 2)
+	if len(parts) != 2 {
+		return "", "", errors.Errorf("invalid auth for %s, expected base64 of username:password", host)
+	}
+	return parts[0], parts[1], nil
+}
+
+// Return a resolver that uses any configured mirror and credentials for the image's registry
+func withRegistryResolver(ctx context.Context, ref string, registries *registryConfig) containerd.RemoteOpt {
+	spec, err := reference.Parse(ref)
+	if err != nil {
+		// Let the default resolver report the invalid reference
+		return func(_ *containerd.Client, _ *containerd.RemoteContext) error { return nil }
+	}
+	registry := spec.Hostname()
+	_, hasConfig := registries.Configs[registry]
+	if !registries.hasMirror(ref) && !hasConfig {
+		// not handled here
+		return func(_ *containerd.Client, _ *containerd.RemoteContext) error { return nil }
+	}
+	return func(_ *containerd.Client, c *containerd.RemoteContext) error {
+		host, plainHTTP, err := registries.mirrorHost(registry)
+		if err != nil {
+			return err
+		}
+		log.G(ctx).WithFields(map[string]interface{}{"ref": ref, "host": host}).Info("Pulling with configured registry")
+		c.Resolver = docker.NewResolver(docker.ResolverOptions{
+			Host: func(string) (string, error) {
+				return host, nil
+			},
+			Credentials: registries.credentials,
+			PlainHTTP:   plainHTTP,
+		})
+		return nil
+	}
+}
+
 // Transform an ECR image name into a reference resolvable by the Amazon ECR containerd Resolver
 // e.g. ecr.aws/arn:<partition>:ecr:<region>:<account>:repository/<name>:<tag>
 func ecrImageNameToRef(input string) (string, error) {
@@ -1,8 +1,13 @@
 package main
 
 import (
-	"github.com/stretchr/testify/assert"
+	"context"
+	"strings"
 	"testing"
+
+	"github.com/BurntSushi/toml"
+	runtimespec "github.com/opencontainers/runtime-spec/specs-go"
+	"github.com/stretchr/testify/assert"
 )
 
 // Test ecrImageNameToRef with a valid ECR image URI
@@ -47,3 +52,95 @@ func TestECRImageNameToRefInvalid(t *testing.T) {
 		})
 	}
 }
+
+func TestParseEnv(t *testing.T) {
+	env, err := parseEnv(strings.NewReader("A=1\n\nB=two words\nC=x=y\nD=\n"))
+	assert.NoError(t, err)
+	assert.Equal(t, []string{"A=1", "B=two words", "C=x=y", "D="}, env)
+
+	for _, bad := range []string{"NOEQUALS\n", "=value\n"} {
+		_, err := parseEnv(strings.NewReader(bad))
+		assert.Error(t, err, bad)
+	}
+}
+
+func TestParseArgs(t *testing.T) {
+	args, err := parseArgs(strings.NewReader("--device\n/dev/nvme1n1\n\nlast arg\n"))
+	assert.NoError(t, err)
+	assert.Equal(t, []string{"--device", "/dev/nvme1n1", "", "last arg"}, args)
+
+	args, err = parseArgs(strings.NewReader(""))
+	assert.NoError(t, err)
+	assert.Empty(t, args)
+}
+
+func TestParseMounts(t *testing.T) {
+	mounts, err := parseMounts("")
+	assert.NoError(t, err)
+	assert.Empty(t, mounts)
+
+	mounts, err = parseMounts("/var/log:/host/log:ro,/opt/data:/data:rw")
+	assert.NoError(t, err)
+	assert.Equal(t, []runtimespec.Mount{
+		{Options: []string{"rbind", "ro"}, Destination: "/host/log", Source: "/var/log"},
+		{Options: []string{"rbind", "rw"}, Destination: "/data", Source: "/opt/data"},
+	}, mounts)
+
+	for _, bad := range []string{"/a:/b", "/a:/b:rx", "a:/b:ro", "/a:b:ro", "/a:/b:ro,"} {
+		_, err := parseMounts(bad)
+		assert.Error(t, err, bad)
+	}
+}
+
+func TestWithResourceLimits(t *testing.T) {
+	s := runtimespec.Spec{}
+	assert.NoError(t, withResourceLimits(0, 0)(context.Background(), nil, nil, &s))
+	assert.Nil(t, s.Linux)
+
+	assert.NoError(t, withResourceLimits(512, 150)(context.Background(), nil, nil, &s))
+	assert.Equal(t, int64(512*1024*1024), *s.Linux.Resources.Memory.Limit)
+	assert.Equal(t, uint64(100000), *s.Linux.Resources.CPU.Period)
+	assert.Equal(t, int64(150000), *s.Linux.Resources.CPU.Quota)
+}
+
+func TestRegistryConfig(t *testing.T) {
+	config := &registryConfig{}
+	_, err := toml.Decode(`
+[mirrors."docker.io"]
+endpoint = ["https://mirror.example.com:5000", "https://unused.example.com"]
+
+[mirrors."quay.io"]
+endpoint = ["http://insecure.example.com"]
+
+[configs."mirror.example.com:5000".auth]
+auth = "dXNlcjpwYXNzOndvcmQ="
+`, config)
+	assert.NoError(t, err)
+
+	assert.True(t, config.hasMirror("docker.io/library/alpine:latest"))
+	assert.False(t, config.hasMirror("gcr.io/project/image:latest"))
+
+	host, plainHTTP, err := config.mirrorHost("docker.io")
+	assert.NoError(t, err)
+	assert.Equal(t, "mirror.example.com:5000", host)
+	assert.False(t, plainHTTP)
+
+	host, plainHTTP, err = config.mirrorHost("quay.io")
+	assert.NoError(t, err)
+	assert.Equal(t, "insecure.example.com", host)
+	assert.True(t, plainHTTP)
+
+	host, _, err = config.mirrorHost("gcr.io")
+	assert.NoError(t, err)
+	assert.Equal(t, "gcr.io", host)
+
+	user, secret, err := config.credentials("mirror.example.com:5000")
+	assert.NoError(t, err)
+	assert.Equal(t, "user", user)
+	assert.Equal(t, "pass:word", secret)
+
+	user, secret, err = config.credentials("gcr.io")
+	assert.NoError(t, err)
+	assert.Empty(t, user)
+	assert.Empty(t, secret)
+}
@@ -3,6 +3,7 @@ module host-ctr
 go 1.12
 
 require (
+	github.com/BurntSushi/toml v0.3.1
 	github.com/aws/aws-sdk-go v1.28.9
 	github.com/awslabs/amazon-ecr-containerd-resolver v0.0.0-20200131205711-bda55ee680cd
 	github.com/containerd/containerd v1.2.9
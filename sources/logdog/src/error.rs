@@ -84,6 +84,22 @@ pub(crate) enum Error {
     },
     #[snafu(display("Cannot write to / as a file."))]
     RootAsFile { backtrace: Backtrace },
+    #[snafu(display("Error reading version information: {}", source))]
+    VersionInfo {
+        source: bottlerocket_release::Error,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Error serializing version information: {}", source))]
+    VersionInfoSerialize {
+        source: serde_json::Error,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Error writing version information to '{}': {}", path.display(), source))]
+    VersionInfoWrite {
+        source: io::Error,
+        path: PathBuf,
+        backtrace: Backtrace,
+    },
     #[snafu(display("Error creating tempdir: {}", source))]
     TempDirCreate {
         source: io::Error,
@@ -9,10 +9,13 @@ pub(crate) struct LogRequest<'a> {
 /// Returns the standard list of `logdog` commands.
 pub(crate) fn log_requests<'a>() -> impl Iterator<Item = LogRequest<'static>> {
     [
-        ("os-release", "cat /etc/os-release"),
         ("journalctl-boots", "journalctl --list-boots --no-pager"),
         ("journalctl.errors", "journalctl -p err -a --no-pager"),
         ("journalctl.log", "journalctl -a --no-pager"),
+        (
+            "audit.json",
+            "journalctl -a --no-pager -o json BOTTLEROCKET_OUTCOME=success BOTTLEROCKET_OUTCOME=failure",
+        ),
         ("signpost", "signpost status"),
         ("settings.json", "apiclient --method GET --uri /"),
         ("wicked", "wicked show all"),
@@ -14,6 +14,8 @@ logs are at: /tmp/bottlerocket-logs.tar.gz
 
 For the commands used to gather logs, please see [log_request](src/log_request.rs).
 
+The running version, variant, architecture, and data store version are saved in `version.json`.
+
 */
 
 #![deny(rust_2018_idioms)]
@@ -23,12 +25,13 @@ mod error;
 mod log_request;
 
 use std::collections::VecDeque;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::{env, process};
 
+use bottlerocket_release::VersionInfo;
 use create_tarball::create_tarball;
 use error::Result;
 use log_request::{log_requests, LogRequest};
@@ -36,6 +39,7 @@ use snafu::{ErrorCompat, OptionExt, ResultExt};
 use tempfile::TempDir;
 
 const ERROR_FILENAME: &str = "logdog.errors";
+const VERSION_FILENAME: &str = "version.json";
 const OUTPUT_FILENAME: &str = "bottlerocket-logs.tar.gz";
 const TARBALL_DIRNAME: &str = "bottlerocket-logs";
 
@@ -121,6 +125,15 @@ pub(crate) fn collect_logs<P: AsRef<Path>>(
         path: error_path.clone(),
     })?;
 
+    if let Err(e) = write_version_info(outdir.join(VERSION_FILENAME)) {
+        // ignore the error, but make note of it in the error file.
+        writeln!(&mut error_file, "Error saving version information: '{}'", e).context(
+            error::ErrorWrite {
+                path: error_path.clone(),
+            },
+        )?;
+    }
+
     for log_request in log_requests {
         // show the user what command we are running
         println!("Running: {}", log_request.command);
@@ -139,6 +152,14 @@ pub(crate) fn collect_logs<P: AsRef<Path>>(
     Ok(())
 }
 
+/// Saves the version information of the running OS and its data store to `path`.
+fn write_version_info<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    let info = VersionInfo::new().context(error::VersionInfo)?;
+    let json = serde_json::to_string_pretty(&info).context(error::VersionInfoSerialize)?;
+    fs::write(path, json).context(error::VersionInfoWrite { path })
+}
+
 /// Runs the bulk of the program's logic, main wraps this.
 fn run(log_requests: impl Iterator<Item = LogRequest<'static>>, output: &PathBuf) -> Result<()> {
     let temp_dir = TempDir::new().context(error::TempDirCreate)?;
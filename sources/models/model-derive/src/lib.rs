@@ -130,7 +130,7 @@ impl VisitMut for ModelHelper {
             } else {
                 parse_quote!(#[derive(Debug, PartialEq, Serialize, Deserialize)])
             };
-            node.attrs.push(attr);
+            node.attrs.insert(0, attr);
         }
 
         // Let the default implementation do its thing, recursively.
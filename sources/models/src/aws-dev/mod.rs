@@ -3,7 +3,14 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 use crate::modeled_types::Identifier;
-use crate::{AwsSettings, ContainerImage, NtpSettings, UpdatesSettings};
+use crate::{
+    AwsSettings, BootSettings, BootstrapCommand, ContainerImage, KernelSettings, NetworkSettings,
+    NtpSettings, PemCertificate, RegistrySettings, SshSettings, UpdatesSettings,
+};
+
+/// Development hosts can make SELinux permissive, to find what a denial is blocking.  The kernel
+/// has to allow it too, with packages/kernel/config-aws-dev.
+pub const SELINUX_PERMISSIVE_ALLOWED: bool = true;
 
 // Note: we have to use 'rename' here because the top-level Settings structure is the only one
 // that uses its name in serialization; internal structures use the field name that points to it
@@ -12,6 +19,13 @@ struct Settings {
     motd: String,
     updates: UpdatesSettings,
     host_containers: HashMap<Identifier, ContainerImage>,
+    bootstrap_commands: HashMap<Identifier, BootstrapCommand>,
+    ssh: SshSettings,
+    container_registry: RegistrySettings,
+    pki: HashMap<Identifier, PemCertificate>,
     ntp: NtpSettings,
+    network: NetworkSettings,
+    kernel: KernelSettings,
+    boot: BootSettings,
     aws: AwsSettings,
 }
@@ -3,7 +3,14 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 use crate::modeled_types::Identifier;
-use crate::{AwsSettings, ContainerImage, KubernetesSettings, NtpSettings, UpdatesSettings};
+use crate::{
+    AwsSettings, BootSettings, BootstrapCommand, ContainerImage, ContainerRuntimeSettings,
+    KernelSettings, KubernetesSettings, NetworkSettings, NtpSettings, OciDefaults, PemCertificate,
+    RegistrySettings, SshSettings, UpdatesSettings,
+};
+
+/// Production hosts always enforce SELinux; settings.kernel.selinux can only be "enforcing".
+pub const SELINUX_PERMISSIVE_ALLOWED: bool = false;
 
 // Note: we have to use 'rename' here because the top-level Settings structure is the only one
 // that uses its name in serialization; internal structures use the field name that points to it
@@ -11,8 +18,17 @@ use crate::{AwsSettings, ContainerImage, KubernetesSettings, NtpSettings, Update
 struct Settings {
     motd: String,
     kubernetes: KubernetesSettings,
+    container_runtime: ContainerRuntimeSettings,
+    oci_defaults: OciDefaults,
     updates: UpdatesSettings,
     host_containers: HashMap<Identifier, ContainerImage>,
+    bootstrap_commands: HashMap<Identifier, BootstrapCommand>,
+    ssh: SshSettings,
+    container_registry: RegistrySettings,
+    pki: HashMap<Identifier, PemCertificate>,
     ntp: NtpSettings,
+    network: NetworkSettings,
+    kernel: KernelSettings,
+    boot: BootSettings,
     aws: AwsSettings,
 }
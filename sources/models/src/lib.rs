@@ -60,14 +60,20 @@ pub use variant::*;
 // structure based on these, and that's what gets exposed via the API.  (Specific variants' models
 // are in subdirectories and linked into place by build.rs at variant/current.)
 
+use ipnet::IpNet;
 use model_derive::model;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
 
 use crate::modeled_types::{
-    KubernetesClusterName, KubernetesLabelKey, KubernetesLabelValue, KubernetesTaintValue,
-    SingleLineString, Url, ValidBase64,
+    BootstrapCommandProgram, ContainerMountPath, CpuList, DeviceAccess, DevicePath, DnsDomain,
+    EnvironmentVariableName, FileMode, FriendlyVersion, HostIdentity, Identifier, InterfaceName,
+    KubernetesClusterName, KubernetesLabelKey, KubernetesLabelValue, KubernetesQuantityValue,
+    KubernetesReservedResource, KubernetesTaintValue, LockdownMode, LogTarget, NtpServerOption,
+    OciHookPath, OciHookStage, PemCertificateString, Percentage, RebootWindow, RegistryHost,
+    RestartPolicy, RlimitType, SelinuxMode, SingleLineString, SshPublicKey, SshUserName,
+    StaticPodManifest, SysctlKey, Timestamp, UpdatePolicy, Url, ValidBase64,
 };
 
 // Kubernetes related settings. The dynamic settings are retrieved from
@@ -80,6 +86,9 @@ struct KubernetesSettings {
     api_server: Url,
     node_labels: HashMap<KubernetesLabelKey, KubernetesLabelValue>,
     node_taints: HashMap<KubernetesLabelKey, KubernetesTaintValue>,
+    kube_reserved: HashMap<KubernetesReservedResource, KubernetesQuantityValue>,
+    system_reserved: HashMap<KubernetesReservedResource, KubernetesQuantityValue>,
+    static_pods: HashMap<Identifier, StaticPod>,
 
     // Dynamic settings.
     max_pods: u32,
@@ -88,13 +97,139 @@ struct KubernetesSettings {
     pod_infra_container_image: SingleLineString,
 }
 
-// Updog settings. Taken from userdata. The 'seed' setting is generated
-// by the "Bork" settings generator at runtime.
+// Garbage collection of unused container images, so image sprawl doesn't fill the disk.  kubelet
+// removes images once disk usage passes the high threshold, until it's back under the low one.
 #[model]
+struct ContainerRuntimeSettings {
+    gc: ImageGcSettings,
+}
+
+// Thresholds are percentages of the disk holding images; the API checks that low is below high
+// when they're committed.  Images newer than `minimum_age_minutes` are never removed.
+#[model]
+struct ImageGcSettings {
+    high_threshold_percent: Percentage,
+    low_threshold_percent: Percentage,
+    minimum_age_minutes: u32,
+}
+
+// A pod kubelet runs on this node without the API server, from the base64-encoded `manifest`.
+// Only pods that are `enabled` are written to kubelet's static pod directory.
+#[model]
+struct StaticPod {
+    manifest: StaticPodManifest,
+    enabled: bool,
+}
+
+// Defaults for every orchestrated container, added to its OCI runtime spec by shimpei as it's
+// created.  Hooks and devices are named by the user, so each can be changed on its own.
+#[model]
+struct OciDefaults {
+    hooks: HashMap<Identifier, OciHook>,
+    devices: HashMap<Identifier, OciDevice>,
+    rlimits: HashMap<RlimitType, Rlimit>,
+}
+
+// A program run at a stage of each container's life.  `args` follow the path, which is passed as
+// the first argument; a `timeout_seconds` of 0, or none, means no timeout.
+#[model]
+struct OciHook {
+    stage: OciHookStage,
+    path: OciHookPath,
+    args: Vec<SingleLineString>,
+    environment: HashMap<EnvironmentVariableName, SingleLineString>,
+    timeout_seconds: u32,
+}
+
+// A host device containers can use, like "/dev/kvm".  `access` defaults to "rwm".
+#[model]
+struct OciDevice {
+    path: DevicePath,
+    access: DeviceAccess,
+}
+
+// A resource limit for container processes, replacing the runtime's default for the same limit.
+#[model]
+struct Rlimit {
+    soft: u64,
+    hard: u64,
+}
+
+// Updog settings. Taken from userdata. Any of 'seed', 'version-lock', 'ignore-waves', and
+// 'channel' that the user doesn't give are generated by the "Bork" settings generator at runtime.
+#[model(impl_default = true)]
 struct UpdatesSettings {
     metadata_base_url: Url,
     targets_base_url: Url,
     seed: u32,
+    version_lock: FriendlyVersion,
+    ignore_waves: bool,
+    channel: Identifier,
+    // Labels for this host, which updates can require to restrict them to part of the fleet.
+    labels: HashMap<Identifier, Identifier>,
+    // An in-cluster cache to fetch update images from before the targets URL.
+    target_cache_url: Url,
+    // About how many hosts are in the fleet, to estimate how many fetch an update together.
+    fleet_size_hint: u32,
+    // What updog does on its own: "off", "check-only", "download", "apply", or
+    // "apply-and-reboot-in-window".
+    auto: UpdatePolicy,
+    // When automatic updates may reboot the host, in UTC, like "02:00-04:00".
+    reboot_window: RebootWindow,
+    // Endpoints to tell about update events, by name.
+    webhooks: HashMap<Identifier, UpdateWebhook>,
+    // Whether updog refuses updates without provenance that matches their images.
+    require_provenance: bool,
+    // How long a prepared update stays staged before updog activates it.
+    min_soak_minutes: u32,
+    // Conditions registered on the host, by name, that hold back activating and rebooting.
+    vetoes: HashMap<Identifier, UpdateVeto>,
+    // Public keys that bundles have to be signed with before updog applies them, if any.
+    bundle_keys: Vec<ValidBase64>,
+    // How long the repository's timestamp metadata is good for after it's signed.
+    timestamp_lifetime_hours: u32,
+    // The most memory updog should use, in MiB.
+    memory_limit_mib: u32,
+    // Where updog logs; see updog's log targets.
+    log_target: LogTarget,
+    // Sent to private repositories that only serve hosts they know.
+    host_identity: HostIdentity,
+    // A policy endpoint to ask before activating an update.
+    approval: UpdateApprovalSettings,
+    reporting: UpdateReportingSettings,
+}
+
+// An HTTPS endpoint that updog sends signed events to as an update moves along.  Events are only
+// sent to webhooks with both a URL and a secret.
+#[model]
+struct UpdateWebhook {
+    url: Url,
+    // The base64-encoded key that events are signed with.
+    secret: ValidBase64,
+}
+
+// Something on the host, like a critical batch job, that updog shouldn't activate an update or
+// reboot during.  Vetoes are only honored until they expire, so one left behind can't hold back
+// updates forever.
+#[model]
+struct UpdateVeto {
+    reason: SingleLineString,
+    expires: Timestamp,
+}
+
+// An operator's policy endpoint, which updog asks whether it may activate an update.  If the
+// endpoint can't be reached, activating fails unless `fail_open` is set.
+#[model]
+struct UpdateApprovalSettings {
+    url: Url,
+    fail_open: bool,
+}
+
+// Opt-in reports of update health, sent by update-reporter.  Nothing is sent unless `url` is set,
+// and it must use HTTPS.
+#[model]
+struct UpdateReportingSettings {
+    url: Url,
 }
 
 #[model]
@@ -102,12 +237,135 @@ struct ContainerImage {
     source: Url,
     enabled: bool,
     superpowered: bool,
+    environment: HashMap<EnvironmentVariableName, SingleLineString>,
+    mounts: Vec<ContainerMount>,
+    // A limit of 0 means unlimited.
+    memory_limit_mib: u64,
+    // Percentage of one CPU's time; 150 allows a CPU and a half.  0 means unlimited.
+    cpu_quota_percent: u32,
+    restart_policy: RestartPolicy,
+}
+
+// A task run once, before the orchestrator agent starts, to prepare the host.  Bootstrap commands
+// run in the order of their names.  A command either runs the container image at `source`, with
+// `arguments` in place of the image's command, or runs `command`, one of a few host programs, with
+// `arguments`.  If an `essential` command fails, the ones after it wait, and the orchestrator
+// agent doesn't start, until it succeeds on a later boot.
+#[model]
+struct BootstrapCommand {
+    source: Url,
+    superpowered: bool,
+    command: BootstrapCommandProgram,
+    arguments: Vec<SingleLineString>,
+    essential: bool,
+}
+
+// An extra bind mount from the host into a host container.
+#[model]
+struct ContainerMount {
+    source: ContainerMountPath,
+    destination: ContainerMountPath,
+    read_only: bool,
+}
+
+// SSH access to the admin container, by user name.  Users can log in with any of their public keys,
+// which are written to the admin container's persistent storage, so rotating them doesn't need a
+// new admin container image.
+#[model]
+struct SshSettings {
+    authorized_keys: HashMap<SshUserName, Vec<SshPublicKey>>,
+}
+
+// Container registry settings.  Registries are named by host, with an optional port, like
+// "docker.io" or "registry.example.com:5000".
+#[model]
+struct RegistryMirror {
+    registry: RegistryHost,
+    endpoints: Vec<Url>,
+}
+
+// Credentials for a registry or mirror; `auth` is the base64 encoding of "username:password", as
+// in a Docker config file.
+#[model]
+struct RegistryCredential {
+    registry: RegistryHost,
+    auth: ValidBase64,
+}
+
+#[model]
+struct RegistrySettings {
+    mirrors: Vec<RegistryMirror>,
+    credentials: Vec<RegistryCredential>,
+}
+
+// Additional CA certificates, named by the user.  `data` is the base64 encoding of one or more
+// PEM certificates; `trusted` ones are added to the system trust store.
+#[model]
+struct PemCertificate {
+    data: PemCertificateString,
+    trusted: bool,
 }
 
 // NTP settings
 #[model]
 struct NtpSettings {
     time_servers: Vec<Url>,
+    // Applied to each of the time servers.
+    options: Vec<NtpServerOption>,
+}
+
+// Network settings.  Interfaces that aren't configured here are left alone; if no interfaces are
+// configured, the primary interface (eth0) uses DHCP.  The HTTPS proxy is used by system services
+// that reach the network, except for hosts listed in `no_proxy`.
+#[model]
+struct NetworkSettings {
+    interfaces: HashMap<InterfaceName, NetworkInterface>,
+    name_servers: Vec<IpAddr>,
+    search_list: Vec<DnsDomain>,
+    https_proxy: Url,
+    no_proxy: Vec<SingleLineString>,
+}
+
+// An interface can use DHCP, static addresses, or both.  The primary interface is the one whose
+// DHCP lease provides DNS servers and the hostname.  Interfaces are checked as they're set, so
+// each has to be given whole; see NetworkInterfaceFields.
+#[model]
+#[serde(
+    deny_unknown_fields,
+    rename_all = "kebab-case",
+    try_from = "modeled_types::NetworkInterfaceFields"
+)]
+struct NetworkInterface {
+    primary: bool,
+    dhcp4: bool,
+    dhcp6: bool,
+    addresses: Vec<IpNet>,
+    routes: Vec<StaticRoute>,
+}
+
+// A route to the `to` network, like "0.0.0.0/0" for the default route, through the `via` gateway.
+#[model]
+struct StaticRoute {
+    to: IpNet,
+    via: IpAddr,
+}
+
+// Kernel settings.  Sysctl keys use dots as separators, like "net.ipv4.ip_forward".  The SELinux
+// mode is written to the boot configuration and takes effect on the next boot; variants that
+// don't allow permissive mode set SELINUX_PERMISSIVE_ALLOWED to false.
+#[model]
+struct KernelSettings {
+    sysctl: HashMap<SysctlKey, SingleLineString>,
+    selinux: SelinuxMode,
+}
+
+// Kernel parameters set on the kernel command line.  Changes are written to the boot
+// configuration and take effect on the next boot.
+#[model]
+struct BootSettings {
+    hugepages: u32,
+    isolcpus: CpuList,
+    lockdown: LockdownMode,
 }
 
 // Platform-specific settings
@@ -135,10 +393,27 @@ struct Service {
 
 pub type ConfigurationFiles = HashMap<String, ConfigurationFile>;
 
+// Files are written with the default mode unless `mode` is given; files that hold secrets, like
+// registry credentials, give one that only root can read.
 #[model(add_option = false, rename = "")]
 struct ConfigurationFile {
     path: SingleLineString,
     template_path: SingleLineString,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<FileMode>,
+}
+
+///// Bootstrap command status
+
+// Recorded by bootstrap-commands as each bootstrap command finishes, by name.  Commands that
+// succeeded aren't run again.  `exit_code` is -1 if the command couldn't be run or was killed.
+pub type BootstrapCommandsStatus = HashMap<String, BootstrapCommandStatus>;
+
+#[model(add_option = false, rename = "")]
+struct BootstrapCommandStatus {
+    succeeded: bool,
+    exit_code: i32,
+    finished: Timestamp,
 }
 
 ///// Metadata
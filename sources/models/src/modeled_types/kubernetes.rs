@@ -3,7 +3,7 @@ use regex::Regex;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 // Just need serde's Error in scope to get its trait methods
 use serde::de::Error as _;
-use snafu::ensure;
+use snafu::{ensure, ResultExt};
 use std::borrow::Borrow;
 use std::convert::TryFrom;
 use std::fmt;
@@ -337,3 +337,216 @@ mod test_kubernetes_cluster_name {
         }
     }
 }
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// KubernetesReservedResource represents the name of a resource that can be reserved for system
+/// or Kubernetes daemons, using kubelet's kubeReserved and systemReserved settings.  It stores
+/// the original string and makes it accessible through standard traits.
+// https://kubernetes.io/docs/tasks/administer-cluster/reserve-compute-resources/
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct KubernetesReservedResource {
+    inner: String,
+}
+
+impl TryFrom<&str> for KubernetesReservedResource {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            ["cpu", "memory", "ephemeral-storage", "pid"].contains(&input),
+            error::InvalidKubernetesReservedResource { input }
+        );
+        Ok(KubernetesReservedResource {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(KubernetesReservedResource, "KubernetesReservedResource");
+
+#[cfg(test)]
+mod test_kubernetes_reserved_resource {
+    use super::KubernetesReservedResource;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_resources() {
+        for ok in &["cpu", "memory", "ephemeral-storage", "pid"] {
+            KubernetesReservedResource::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_resources() {
+        for err in &["", "CPU", "storage", "nvidia.com/gpu"] {
+            KubernetesReservedResource::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// KubernetesQuantityValue represents a string that contains a valid, non-negative Kubernetes
+/// resource quantity, like "100m" or "1Gi".  It stores the original string and makes it accessible
+/// through standard traits.
+// https://kubernetes.io/docs/reference/kubernetes-api/common-definitions/quantity/
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct KubernetesQuantityValue {
+    inner: String,
+}
+
+lazy_static! {
+    pub(crate) static ref KUBERNETES_QUANTITY: Regex = Regex::new(
+        r"^\+?([0-9]+(\.[0-9]*)?|\.[0-9]+)([KMGTPE]i|[numkMGTPE]|[eE][+-]?[0-9]+)?$"
+    )
+    .unwrap();
+}
+
+impl TryFrom<&str> for KubernetesQuantityValue {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            KUBERNETES_QUANTITY.is_match(input),
+            error::Pattern {
+                thing: "Kubernetes quantity",
+                pattern: KUBERNETES_QUANTITY.clone(),
+                input
+            }
+        );
+        Ok(KubernetesQuantityValue {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(KubernetesQuantityValue, "KubernetesQuantityValue");
+
+#[cfg(test)]
+mod test_kubernetes_quantity_value {
+    use super::KubernetesQuantityValue;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_quantities() {
+        for ok in &["1", "100m", "1.5", ".5", "1Gi", "512Mi", "2k", "1e3", "+1"] {
+            KubernetesQuantityValue::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_quantities() {
+        for err in &["", "-1", "1GB", "Gi", "1 Gi", "1.2.3", "\"1Gi\""] {
+            KubernetesQuantityValue::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// StaticPodManifest can only be created by deserializing from the base64 encoding of a Kubernetes
+/// pod manifest, in YAML or JSON, of at most 64 KiB once decoded.  The manifest has to be a
+/// `kind: Pod` object without privileged containers.  It stores the original base64 text, not the
+/// decoded form.  Its purpose is to keep what's written into kubelet's static pod directory to
+/// pods of a reasonable size that can't take over the host; kubelet checks the rest of the
+/// manifest itself.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct StaticPodManifest {
+    inner: String,
+}
+
+impl StaticPodManifest {
+    pub const MAX_LEN: usize = 64 * 1024;
+}
+
+impl TryFrom<&str> for StaticPodManifest {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let decoded = base64::decode(input).context(error::InvalidBase64)?;
+        let invalid = |msg: &str| error::Error::InvalidStaticPodManifest {
+            msg: msg.to_string(),
+        };
+        let manifest =
+            std::str::from_utf8(&decoded).map_err(|_| invalid("manifest is not valid UTF-8"))?;
+        if manifest.trim().is_empty() {
+            return Err(invalid("manifest is empty"));
+        }
+        if manifest.len() > Self::MAX_LEN {
+            return Err(invalid("manifest is larger than 64 KiB"));
+        }
+
+        let pod: serde_yaml::Value = serde_yaml::from_str(manifest)
+            .map_err(|e| invalid(&format!("manifest is not valid YAML: {}", e)))?;
+        if pod["kind"].as_str() != Some("Pod") {
+            return Err(invalid("manifest must have 'kind: Pod'"));
+        }
+        // Static pods run outside the API server's admission control, so this is the only place
+        // to stop one from getting full access to the host.
+        for list in &["initContainers", "containers", "ephemeralContainers"] {
+            let containers = pod["spec"][*list].as_sequence().map_or(&[][..], |c| &c[..]);
+            if containers
+                .iter()
+                .any(|c| c["securityContext"]["privileged"].as_bool() == Some(true))
+            {
+                return Err(invalid("privileged containers aren't allowed"));
+            }
+        }
+        Ok(StaticPodManifest {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(StaticPodManifest, "StaticPodManifest");
+
+#[cfg(test)]
+mod test_static_pod_manifest {
+    use super::StaticPodManifest;
+    use std::convert::TryFrom;
+
+    const POD: &str = "apiVersion: v1\nkind: Pod\n";
+
+    /// Returns a pod with one container in `list`, which is privileged if `privileged` is.
+    fn pod_with(list: &str, privileged: bool) -> String {
+        format!(
+            "{}spec:\n  {}:\n  - name: agent\n    securityContext:\n      privileged: {}\n",
+            POD, list, privileged
+        )
+    }
+
+    #[test]
+    fn good_manifests() {
+        for ok in &[
+            POD,
+            r#"{"apiVersion": "v1", "kind": "Pod"}"#,
+            &pod_with("containers", false),
+        ] {
+            StaticPodManifest::try_from(base64::encode(ok).as_str()).unwrap();
+        }
+        let biggest = POD.to_string() + &"#".repeat(StaticPodManifest::MAX_LEN - POD.len());
+        StaticPodManifest::try_from(base64::encode(&biggest).as_str()).unwrap();
+    }
+
+    #[test]
+    fn bad_manifests() {
+        StaticPodManifest::try_from("kind: Pod").unwrap_err();
+        for err in &[
+            base64::encode(""),
+            base64::encode(" \n"),
+            base64::encode(&[0xff, 0xfe]),
+            base64::encode(
+                &(POD.to_string() + &"#".repeat(StaticPodManifest::MAX_LEN + 1 - POD.len())),
+            ),
+            base64::encode("apiVersion: v1\nkind: Deployment\n"),
+            base64::encode("apiVersion: v1\n"),
+            base64::encode("kind: Pod\nspec: [unclosed\n"),
+            base64::encode("- kind: Pod\n"),
+            base64::encode(&pod_with("containers", true)),
+            base64::encode(&pod_with("initContainers", true)),
+        ] {
+            StaticPodManifest::try_from(err.as_str()).unwrap_err();
+        }
+    }
+}
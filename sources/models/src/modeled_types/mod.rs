@@ -28,6 +28,168 @@ pub mod error {
         #[snafu(display("Given invalid URL '{}'", input))]
         InvalidUrl { input: String },
 
+        #[snafu(display(
+            "Environment variable names may only contain ASCII alphanumerics and underscores, and may not start with a digit, received '{}'",
+            input
+        ))]
+        InvalidEnvironmentVariableName { input: String },
+
+        #[snafu(display(
+            "Mount paths must be absolute, without '..', whitespace, commas, or colons, received '{}'",
+            input
+        ))]
+        InvalidContainerMountPath { input: String },
+
+        #[snafu(display(
+            "Restart policy must be 'always', 'on-failure', or 'no', received '{}'",
+            input
+        ))]
+        InvalidRestartPolicy { input: String },
+
+        #[snafu(display(
+            "Bootstrap commands must be 'apiclient', 'modprobe', 'wipefs', or 'blkdiscard', received '{}'",
+            input
+        ))]
+        InvalidBootstrapCommandProgram { input: String },
+
+        #[snafu(display(
+            "Registry hosts may only contain ASCII alphanumerics, hyphens, dots, and a port, received '{}'",
+            input
+        ))]
+        InvalidRegistryHost { input: String },
+
+        #[snafu(display(
+            "File modes must be 3 or 4 octal digits, like '0600', received '{}'",
+            input
+        ))]
+        InvalidFileMode { input: String },
+
+        #[snafu(display(
+            "Version must be 'latest' or a version like '1.2.3' or 'v1.2.3', received '{}'",
+            input
+        ))]
+        InvalidVersion { input: String },
+
+        #[snafu(display("Invalid PEM certificate: {}", msg))]
+        InvalidPemCertificate { msg: String },
+
+        #[snafu(display(
+            "Interface names must be 1 to 15 ASCII alphanumerics, hyphens, or underscores, received '{}'",
+            input
+        ))]
+        InvalidInterfaceName { input: String },
+
+        #[snafu(display(
+            "DNS domains must be ASCII alphanumerics, hyphens, and dots, with no empty labels, received '{}'",
+            input
+        ))]
+        InvalidDnsDomain { input: String },
+
+        #[snafu(display("Invalid network interface: {}", msg))]
+        InvalidNetworkInterface { msg: String },
+
+        #[snafu(display(
+            "Sysctl keys must be dot-separated ASCII alphanumerics, hyphens, and underscores, received '{}'",
+            input
+        ))]
+        InvalidSysctlKey { input: String },
+
+        #[snafu(display(
+            "CPU lists must be comma-separated CPU numbers or ranges like '0-3', received '{}'",
+            input
+        ))]
+        InvalidCpuList { input: String },
+
+        #[snafu(display(
+            "Lockdown mode must be 'none', 'integrity', or 'confidentiality', received '{}'",
+            input
+        ))]
+        InvalidLockdownMode { input: String },
+
+        #[snafu(display(
+            "SELinux mode must be 'enforcing' or 'permissive', received '{}'",
+            input
+        ))]
+        InvalidSelinuxMode { input: String },
+
+        #[snafu(display(
+            "Percentages must be whole numbers from 0 to 100, received '{}'",
+            input
+        ))]
+        InvalidPercentage { input: u64 },
+
+        #[snafu(display(
+            "NTP server options must be a chrony source option, optionally followed by a space and a number, received '{}'",
+            input
+        ))]
+        InvalidNtpServerOption { input: String },
+
+        #[snafu(display(
+            "Update policy must be 'off', 'check-only', 'download', 'apply', or 'apply-and-reboot-in-window', received '{}'",
+            input
+        ))]
+        InvalidUpdatePolicy { input: String },
+
+        #[snafu(display(
+            "Reboot window must be two UTC times like '02:00-04:00', received '{}'",
+            input
+        ))]
+        InvalidRebootWindow { input: String },
+
+        #[snafu(display(
+            "Timestamp must be a UTC time like '2020-03-27T02:00:00Z', received '{}'",
+            input
+        ))]
+        InvalidTimestamp { input: String },
+
+        #[snafu(display(
+            "Log target must be 'auto', 'terminal', 'stderr', 'journald', or 'syslog', received '{}'",
+            input
+        ))]
+        InvalidLogTarget { input: String },
+
+        #[snafu(display(
+            "Host identity must be 'aws-instance-identity' or an absolute path, received '{}'",
+            input
+        ))]
+        InvalidHostIdentity { input: String },
+
+        #[snafu(display(
+            "SSH user names must be 1 to 32 lowercase ASCII letters, digits, hyphens, or underscores, not starting with a digit or hyphen, received '{}'",
+            input
+        ))]
+        InvalidSshUserName { input: String },
+
+        #[snafu(display("Invalid SSH public key: {}", msg))]
+        InvalidSshPublicKey { msg: String },
+
+        #[snafu(display(
+            "OCI hook paths must be absolute, without '..' or control characters, received '{}'",
+            input
+        ))]
+        InvalidOciHookPath { input: String },
+
+        #[snafu(display(
+            "OCI hook stage must be 'prestart', 'poststart', or 'poststop', received '{}'",
+            input
+        ))]
+        InvalidOciHookStage { input: String },
+
+        #[snafu(display(
+            "Device paths must be under /dev, without '..' or control characters, received '{}'",
+            input
+        ))]
+        InvalidDevicePath { input: String },
+
+        #[snafu(display(
+            "Device access must be some of 'r', 'w', and 'm', each at most once, received '{}'",
+            input
+        ))]
+        InvalidDeviceAccess { input: String },
+
+        #[snafu(display("Resource limit must be one of {}, received '{}'", names, input))]
+        InvalidRlimitType { input: String, names: String },
+
         #[snafu(display("{} must match '{}', given: {}", thing, pattern, input))]
         Pattern {
             thing: String,
@@ -41,6 +203,15 @@ pub mod error {
 
         #[snafu(display("Given invalid cluster name '{}': {}", name, msg))]
         InvalidClusterName { name: String, msg: String },
+
+        #[snafu(display("Invalid static pod manifest: {}", msg))]
+        InvalidStaticPodManifest { msg: String },
+
+        #[snafu(display(
+            "Reserved resources must be 'cpu', 'memory', 'ephemeral-storage', or 'pid', received '{}'",
+            input
+        ))]
+        InvalidKubernetesReservedResource { input: String },
     }
 }
 
@@ -121,7 +292,9 @@ macro_rules! string_impls_for {
 
 // Must be after macro definition
 mod kubernetes;
+mod oci;
 mod shared;
 
 pub use kubernetes::*;
+pub use oci::*;
 pub use shared::*;
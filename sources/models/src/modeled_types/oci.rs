@@ -0,0 +1,298 @@
+use super::error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+// Just need serde's Error in scope to get its trait methods
+use serde::de::Error as _;
+use snafu::ensure;
+use std::borrow::Borrow;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Deref;
+
+/// Returns whether `input` is an absolute path without '..' components or control characters.
+fn is_clean_absolute_path(input: &str) -> bool {
+    input.starts_with('/')
+        && !input.contains(|c: char| c.is_control())
+        && !input.split('/').any(|segment| segment == "..")
+}
+
+/// OciHookPath represents the absolute path of a program to run as an OCI hook, without '..'
+/// components or control characters.  It stores the original string and makes it accessible
+/// through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct OciHookPath {
+    inner: String,
+}
+
+impl TryFrom<&str> for OciHookPath {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            is_clean_absolute_path(input) && !input.ends_with('/'),
+            error::InvalidOciHookPath { input }
+        );
+        Ok(OciHookPath {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(OciHookPath, "OciHookPath");
+
+#[cfg(test)]
+mod test_oci_hook_path {
+    use super::OciHookPath;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_paths() {
+        for ok in &[
+            "/usr/bin/hook",
+            "/opt/hooks/log-start",
+            "/local/bin/my hook",
+        ] {
+            OciHookPath::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_paths() {
+        for err in &["", "hook", "/usr/bin/", "/opt/../bin/sh", "/usr/bin/hook\n"] {
+            OciHookPath::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// OciHookStage represents when an OCI hook runs: "prestart", after the container is created but
+/// before its process starts; "poststart", after its process starts; or "poststop", after it
+/// stops.  These are the stages our runc supports.  It stores the original string and makes it
+/// accessible through standard traits.
+// https://github.com/opencontainers/runtime-spec/blob/master/config.md#posix-platform-hooks
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct OciHookStage {
+    inner: String,
+}
+
+impl TryFrom<&str> for OciHookStage {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            ["prestart", "poststart", "poststop"].contains(&input),
+            error::InvalidOciHookStage { input }
+        );
+        Ok(OciHookStage {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(OciHookStage, "OciHookStage");
+
+#[cfg(test)]
+mod test_oci_hook_stage {
+    use super::OciHookStage;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_stages() {
+        for ok in &["prestart", "poststart", "poststop"] {
+            OciHookStage::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_stages() {
+        for err in &["", "Prestart", "createRuntime", "start"] {
+            OciHookStage::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// DevicePath represents the path of a device node under /dev, like "/dev/kvm", without '..'
+/// components or control characters.  It stores the original string and makes it accessible
+/// through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct DevicePath {
+    inner: String,
+}
+
+impl TryFrom<&str> for DevicePath {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            input.len() > "/dev/".len()
+                && input.starts_with("/dev/")
+                && !input.ends_with('/')
+                && is_clean_absolute_path(input),
+            error::InvalidDevicePath { input }
+        );
+        Ok(DevicePath {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(DevicePath, "DevicePath");
+
+#[cfg(test)]
+mod test_device_path {
+    use super::DevicePath;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_paths() {
+        for ok in &["/dev/kvm", "/dev/nvidia0", "/dev/dri/renderD128"] {
+            DevicePath::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_paths() {
+        for err in &[
+            "",
+            "/dev/",
+            "/dev",
+            "kvm",
+            "/tmp/kvm",
+            "/dev/../etc/shadow",
+            "/dev/kvm/",
+        ] {
+            DevicePath::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// DeviceAccess represents what containers may do with a device, like a device cgroup rule: any
+/// of "r" (read), "w" (write), and "m" (mknod), each at most once, like "rw".  It stores the
+/// original string and makes it accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct DeviceAccess {
+    inner: String,
+}
+
+impl TryFrom<&str> for DeviceAccess {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            !input.is_empty()
+                && input.chars().all(|c| "rwm".contains(c))
+                && input
+                    .char_indices()
+                    .all(|(i, c)| !input[i + 1..].contains(c)),
+            error::InvalidDeviceAccess { input }
+        );
+        Ok(DeviceAccess {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(DeviceAccess, "DeviceAccess");
+
+#[cfg(test)]
+mod test_device_access {
+    use super::DeviceAccess;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_access() {
+        for ok in &["rwm", "r", "rw", "mr"] {
+            DeviceAccess::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_access() {
+        for err in &["", "rr", "x", "RW", "rw "] {
+            DeviceAccess::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// RlimitType represents a process resource limit, named like the `ulimit` options of container
+/// runtimes, such as "nofile" for RLIMIT_NOFILE.  It stores the original string and makes it
+/// accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct RlimitType {
+    inner: String,
+}
+
+impl RlimitType {
+    const NAMES: &'static [&'static str] = &[
+        "as",
+        "core",
+        "cpu",
+        "data",
+        "fsize",
+        "locks",
+        "memlock",
+        "msgqueue",
+        "nice",
+        "nofile",
+        "nproc",
+        "rss",
+        "rtprio",
+        "rttime",
+        "sigpending",
+        "stack",
+    ];
+
+    /// The limit's name in an OCI runtime spec, like "RLIMIT_NOFILE".
+    pub fn oci_name(&self) -> String {
+        format!("RLIMIT_{}", self.inner.to_uppercase())
+    }
+}
+
+impl TryFrom<&str> for RlimitType {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            Self::NAMES.contains(&input),
+            error::InvalidRlimitType {
+                input,
+                names: Self::NAMES.join(", ")
+            }
+        );
+        Ok(RlimitType {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(RlimitType, "RlimitType");
+
+#[cfg(test)]
+mod test_rlimit_type {
+    use super::RlimitType;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_types() {
+        for ok in &["nofile", "memlock", "nproc", "as"] {
+            RlimitType::try_from(*ok).unwrap();
+        }
+        assert_eq!(
+            RlimitType::try_from("nofile").unwrap().oci_name(),
+            "RLIMIT_NOFILE"
+        );
+    }
+
+    #[test]
+    fn bad_types() {
+        for err in &["", "NOFILE", "RLIMIT_NOFILE", "open-files"] {
+            RlimitType::try_from(*err).unwrap_err();
+        }
+    }
+}
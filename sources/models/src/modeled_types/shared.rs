@@ -7,6 +7,8 @@ use std::convert::TryFrom;
 use std::fmt;
 use std::ops::Deref;
 use super::error;
+use crate::{NetworkInterface, StaticRoute};
+use ipnet::IpNet;
 
 /// ValidBase64 can only be created by deserializing from valid base64 text.  It stores the
 /// original text, not the decoded form.  Its purpose is input validation, namely being used as a
@@ -195,6 +197,13 @@ impl TryFrom<&str> for Url {
     type Error = error::Error;
 
     fn try_from(input: &str) -> Result<Self, Self::Error> {
+        // url::Url quietly drops tabs and newlines, but we store the original string, and it's
+        // often written into configuration files.
+        ensure!(
+            !input.contains(char::is_whitespace),
+            error::InvalidUrl { input }
+        );
+
         if let Ok(_) = input.parse::<url::Url>() {
             return Ok(Url {
                 inner: input.to_string(),
@@ -245,8 +254,1578 @@ mod test_url {
         for err in &[
             "how are you",
             "weird@",
+            "http://proxy:3128\nFOO=bar",
+            " example.com",
         ] {
             Url::try_from(*err).unwrap_err();
         }
     }
 }
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// EnvironmentVariableName can only be created by deserializing from a string that's a valid
+/// POSIX environment variable name: ASCII letters, digits, and underscores, not starting with a
+/// digit.  It stores the original form and makes it accessible through standard traits.  Its
+/// purpose is to validate names of variables we pass to containers.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct EnvironmentVariableName {
+    inner: String,
+}
+
+impl TryFrom<&str> for EnvironmentVariableName {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            !input.is_empty()
+                && !input.starts_with(|c: char| c.is_ascii_digit())
+                && input.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+            error::InvalidEnvironmentVariableName { input }
+        );
+        Ok(EnvironmentVariableName {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(EnvironmentVariableName, "EnvironmentVariableName");
+
+#[cfg(test)]
+mod test_environment_variable_name {
+    use super::EnvironmentVariableName;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_names() {
+        for ok in &["HOME", "http_proxy", "_", "A1", "__X__"] {
+            EnvironmentVariableName::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_names() {
+        for err in &["", "1A", "A-B", "A=B", "A B", "A\nB", "É"] {
+            EnvironmentVariableName::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// ContainerMountPath can only be created by deserializing from an absolute path that doesn't
+/// contain whitespace, commas, or colons, so a list of mounts can be passed to a container
+/// runtime as a single argument.  It stores the original form and makes it accessible through
+/// standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ContainerMountPath {
+    inner: String,
+}
+
+impl TryFrom<&str> for ContainerMountPath {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            input.starts_with('/')
+                && !input
+                    .chars()
+                    .any(|c| c.is_whitespace() || c.is_control() || c == ',' || c == ':')
+                && !input.split('/').any(|segment| segment == ".."),
+            error::InvalidContainerMountPath { input }
+        );
+        Ok(ContainerMountPath {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(ContainerMountPath, "ContainerMountPath");
+
+#[cfg(test)]
+mod test_container_mount_path {
+    use super::ContainerMountPath;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_paths() {
+        for ok in &["/", "/var/log", "/opt/my-app/data_1", "/a/./b"] {
+            ContainerMountPath::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_paths() {
+        for err in &[
+            "",
+            "var/log",
+            "/var/../etc",
+            "/a b",
+            "/a,b",
+            "/a:b",
+            "/a\nb",
+        ] {
+            ContainerMountPath::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// RestartPolicy can only be created by deserializing from one of the systemd restart policies
+/// we support for services: "always", "on-failure", or "no".  It stores the original form and
+/// makes it accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct RestartPolicy {
+    inner: String,
+}
+
+impl TryFrom<&str> for RestartPolicy {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            ["always", "on-failure", "no"].contains(&input),
+            error::InvalidRestartPolicy { input }
+        );
+        Ok(RestartPolicy {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(RestartPolicy, "RestartPolicy");
+
+#[cfg(test)]
+mod test_restart_policy {
+    use super::RestartPolicy;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_policies() {
+        for ok in &["always", "on-failure", "no"] {
+            RestartPolicy::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_policies() {
+        for err in &["", "Always", "never", "on-abort", "always\n"] {
+            RestartPolicy::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// BootstrapCommandProgram can only be created by deserializing from the name of one of the host
+/// programs bootstrap commands may run: "apiclient", "modprobe", "wipefs", or "blkdiscard".  It
+/// stores the original form and makes it accessible through standard traits.  Its purpose is to
+/// keep bootstrap commands to a small set of programs for preparing the host, since they run
+/// with full privileges before anything else on the host can check them.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct BootstrapCommandProgram {
+    inner: String,
+}
+
+impl TryFrom<&str> for BootstrapCommandProgram {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            ["apiclient", "modprobe", "wipefs", "blkdiscard"].contains(&input),
+            error::InvalidBootstrapCommandProgram { input }
+        );
+        Ok(BootstrapCommandProgram {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(BootstrapCommandProgram, "BootstrapCommandProgram");
+
+#[cfg(test)]
+mod test_bootstrap_command_program {
+    use super::BootstrapCommandProgram;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_programs() {
+        for ok in &["apiclient", "modprobe", "wipefs", "blkdiscard"] {
+            BootstrapCommandProgram::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_programs() {
+        for err in &["", "bash", "/usr/bin/apiclient", "apiclient set", "mkfs"] {
+            BootstrapCommandProgram::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// RegistryHost can only be created by deserializing from a container registry host name, with an
+/// optional port, like "docker.io" or "registry.example.com:5000".  It stores the original form
+/// and makes it accessible through standard traits.  Its purpose is to validate registry names
+/// that are written into container runtime configuration.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct RegistryHost {
+    inner: String,
+}
+
+impl TryFrom<&str> for RegistryHost {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let mut parts = input.splitn(2, ':');
+        let host = parts.next().unwrap_or_default();
+        let port_ok = match parts.next() {
+            Some(port) => port.parse::<u16>().is_ok(),
+            None => true,
+        };
+        ensure!(
+            !host.is_empty()
+                && host
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+                && port_ok,
+            error::InvalidRegistryHost { input }
+        );
+        Ok(RegistryHost {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(RegistryHost, "RegistryHost");
+
+#[cfg(test)]
+mod test_registry_host {
+    use super::RegistryHost;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_hosts() {
+        for ok in &[
+            "docker.io",
+            "localhost",
+            "registry.example.com:5000",
+            "777777777777.dkr.ecr.us-west-2.amazonaws.com",
+        ] {
+            RegistryHost::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_hosts() {
+        for err in &[
+            "",
+            ":5000",
+            "example.com:",
+            "example.com:99999",
+            "https://example.com",
+            "example.com/path",
+            "exa\"mple.com",
+        ] {
+            RegistryHost::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// FileMode can only be created by deserializing from a file permission mode written in octal,
+/// with three or four digits, like "0600" or "644".  It stores the original form and makes it
+/// accessible through standard traits.  Its purpose is to validate the mode rendered configuration
+/// files are written with, so files holding secrets aren't readable by every user.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct FileMode {
+    inner: String,
+}
+
+impl FileMode {
+    /// The permission bits, for passing to the filesystem.
+    pub fn bits(&self) -> u32 {
+        // Validated as octal digits on creation.
+        u32::from_str_radix(&self.inner, 8).unwrap_or_default()
+    }
+}
+
+impl TryFrom<&str> for FileMode {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            (3..=4).contains(&input.len()) && input.chars().all(|c| ('0'..='7').contains(&c)),
+            error::InvalidFileMode { input }
+        );
+        Ok(FileMode {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(FileMode, "FileMode");
+
+#[cfg(test)]
+mod test_file_mode {
+    use super::FileMode;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_modes() {
+        for (ok, bits) in &[("0600", 0o600), ("644", 0o644), ("1777", 0o1777)] {
+            assert_eq!(FileMode::try_from(*ok).unwrap().bits(), *bits);
+        }
+    }
+
+    #[test]
+    fn bad_modes() {
+        for err in &["", "6", "60", "0o600", "0800", "06000", "rw-------", "-600"] {
+            FileMode::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// FriendlyVersion can only be created by deserializing from "latest" or from a semver version,
+/// optionally prefixed with "v" the way we name releases, like "0.3.3" or "v0.3.3".  It stores the
+/// original form and makes it accessible through standard traits.  Its purpose is to validate
+/// versions that users pick, like the version updog should lock to.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct FriendlyVersion {
+    inner: String,
+}
+
+impl TryFrom<&str> for FriendlyVersion {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let mut chars = input.chars();
+        let version = match chars.next() {
+            Some('v') => chars.as_str(),
+            _ => input,
+        };
+        // semver allows surrounding whitespace, but we don't want it in config files.
+        ensure!(
+            input == "latest"
+                || (!input.contains(char::is_whitespace) && semver::Version::parse(version).is_ok()),
+            error::InvalidVersion { input }
+        );
+        Ok(FriendlyVersion {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(FriendlyVersion, "FriendlyVersion");
+
+#[cfg(test)]
+mod test_friendly_version {
+    use super::FriendlyVersion;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_versions() {
+        for ok in &["latest", "0.3.3", "v0.3.3", "1.0.0-rc1", "v10.20.30"] {
+            FriendlyVersion::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_versions() {
+        for err in &["", "v", "Latest", "0.3", "vv0.3.3", "0.3.3 ", "version"] {
+            FriendlyVersion::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// PemCertificateString can only be created by deserializing from base64 text that decodes to one
+/// or more PEM-encoded certificates.  Text outside of the certificates, like the comments in
+/// common CA bundles, is allowed.  It stores the original base64 text, not the decoded form.  Its
+/// purpose is to validate certificates before they're written into the system trust store.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct PemCertificateString {
+    inner: String,
+}
+
+const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+/// Checks that the given text holds at least one complete PEM certificate, and that each
+/// certificate's body is valid base64.
+fn check_pem(pem: &str) -> Result<(), &'static str> {
+    let mut certificates = 0;
+    // The base64 body of the certificate we're in, if any.
+    let mut body: Option<String> = None;
+    for line in pem.lines().map(str::trim) {
+        if let Some(current) = body.as_mut() {
+            if line == PEM_END {
+                base64::decode(&current).map_err(|_| "certificate body is not valid base64")?;
+                certificates += 1;
+                body = None;
+            } else if line == PEM_BEGIN {
+                return Err("certificate begins before the previous one ends");
+            } else {
+                current.push_str(line);
+            }
+        } else if line == PEM_BEGIN {
+            body = Some(String::new());
+        }
+    }
+    if body.is_some() {
+        return Err("certificate is missing its END line");
+    }
+    if certificates == 0 {
+        return Err("no certificates found");
+    }
+    Ok(())
+}
+
+impl TryFrom<&str> for PemCertificateString {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let decoded = base64::decode(input).context(error::InvalidBase64)?;
+        let pem = std::str::from_utf8(&decoded).map_err(|_| {
+            error::Error::InvalidPemCertificate {
+                msg: "certificates are not valid UTF-8".to_string(),
+            }
+        })?;
+        check_pem(pem).map_err(|msg| error::Error::InvalidPemCertificate {
+            msg: msg.to_string(),
+        })?;
+        Ok(PemCertificateString {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(PemCertificateString, "PemCertificateString");
+
+#[cfg(test)]
+mod test_pem_certificate_string {
+    use super::PemCertificateString;
+    use std::convert::TryFrom;
+
+    const CERT: &str = "-----BEGIN CERTIFICATE-----\nMIIBszCCAVmgAwIBAgIU\nZm9vYmFy\n-----END CERTIFICATE-----\n";
+
+    fn encode(pem: &str) -> String {
+        base64::encode(pem)
+    }
+
+    #[test]
+    fn good_certificates() {
+        for ok in &[
+            CERT.to_string(),
+            format!("{}{}", CERT, CERT),
+            format!("My private CA\n=============\n{}", CERT),
+            CERT.replace('\n', "\r\n"),
+        ] {
+            PemCertificateString::try_from(encode(ok).as_str()).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_certificates() {
+        for err in &[
+            String::new(),
+            "not a certificate".to_string(),
+            "-----BEGIN CERTIFICATE-----\nZm9vYmFy\n".to_string(),
+            "-----BEGIN CERTIFICATE-----\nnot base64!\n-----END CERTIFICATE-----\n".to_string(),
+            format!("-----BEGIN CERTIFICATE-----\n{}", CERT),
+        ] {
+            PemCertificateString::try_from(encode(err).as_str()).unwrap_err();
+        }
+        PemCertificateString::try_from("not base64").unwrap_err();
+        PemCertificateString::try_from(base64::encode([0xff, 0xfe]).as_str()).unwrap_err();
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// InterfaceName can only be created by deserializing from a network interface name of at most 15
+/// characters (the kernel's limit) made of ASCII alphanumerics, hyphens, and underscores, like
+/// "eth0" or "ens5".  It stores the original form and makes it accessible through standard traits.
+/// Its purpose is to validate interface names that are used in network configuration file names.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct InterfaceName {
+    inner: String,
+}
+
+impl TryFrom<&str> for InterfaceName {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            !input.is_empty()
+                && input.len() <= 15
+                && input
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+            error::InvalidInterfaceName { input }
+        );
+        Ok(InterfaceName {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(InterfaceName, "InterfaceName");
+
+#[cfg(test)]
+mod test_interface_name {
+    use super::InterfaceName;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_names() {
+        for ok in &["eth0", "ens5", "enp0s31f6", "bond_1", "br-lan", "abcdefghijklmno"] {
+            InterfaceName::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_names() {
+        for err in &["", "abcdefghijklmnop", "eth0.100", "eth 0", "../eth0", "eth0\n"] {
+            InterfaceName::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// DnsDomain can only be created by deserializing from a DNS domain name made of dot-separated
+/// labels of ASCII alphanumerics and hyphens, like "example.com".  It stores the original form and
+/// makes it accessible through standard traits.  Its purpose is to validate domains that are
+/// written into resolver configuration.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct DnsDomain {
+    inner: String,
+}
+
+impl TryFrom<&str> for DnsDomain {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            input.len() <= 253
+                && input.split('.').all(|label| {
+                    !label.is_empty()
+                        && label.len() <= 63
+                        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                }),
+            error::InvalidDnsDomain { input }
+        );
+        Ok(DnsDomain {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(DnsDomain, "DnsDomain");
+
+#[cfg(test)]
+mod test_dns_domain {
+    use super::DnsDomain;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_domains() {
+        for ok in &["example.com", "localdomain", "us-west-2.compute.internal", "a.b-c.d"] {
+            DnsDomain::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_domains() {
+        for err in &["", ".", "example..com", "example.com.", "exa mple.com", "example_com"] {
+            DnsDomain::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// NetworkInterfaceFields holds the fields of a NetworkInterface as they're given to the API.
+/// NetworkInterface is deserialized through it, so that the combination of DHCP, addresses, and
+/// routes is checked when the interface is set, rather than when netdog tries to apply it.  An
+/// interface has to use DHCP or have static addresses, and each route needs a network and a
+/// gateway of the same IP family.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct NetworkInterfaceFields {
+    primary: Option<bool>,
+    dhcp4: Option<bool>,
+    dhcp6: Option<bool>,
+    addresses: Option<Vec<IpNet>>,
+    routes: Option<Vec<StaticRoute>>,
+}
+
+impl TryFrom<NetworkInterfaceFields> for NetworkInterface {
+    type Error = error::Error;
+
+    fn try_from(input: NetworkInterfaceFields) -> Result<Self, Self::Error> {
+        ensure!(
+            input.dhcp4 == Some(true)
+                || input.dhcp6 == Some(true)
+                || input.addresses.as_ref().map_or(false, |a| !a.is_empty()),
+            error::InvalidNetworkInterface {
+                msg: "needs 'dhcp4', 'dhcp6', or static 'addresses'"
+            }
+        );
+        for route in input.routes.iter().flatten() {
+            match (&route.to, &route.via) {
+                (Some(to), Some(via)) => ensure!(
+                    to.addr().is_ipv4() == via.is_ipv4(),
+                    error::InvalidNetworkInterface {
+                        msg: format!("route to {} can't go through gateway {}", to, via)
+                    }
+                ),
+                _ => {
+                    return error::InvalidNetworkInterface {
+                        msg: "routes need both 'to' and 'via'",
+                    }
+                    .fail()
+                }
+            }
+        }
+
+        Ok(NetworkInterface {
+            primary: input.primary,
+            dhcp4: input.dhcp4,
+            dhcp6: input.dhcp6,
+            addresses: input.addresses,
+            routes: input.routes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_network_interface {
+    use crate::NetworkInterface;
+
+    #[test]
+    fn good_interfaces() {
+        for ok in &[
+            "dhcp4 = true",
+            "dhcp6 = true\nprimary = true",
+            "addresses = [\"10.0.0.10/24\"]\nroutes = [{ to = \"0.0.0.0/0\", via = \"10.0.0.1\" }]",
+            "dhcp4 = true\naddresses = [\"fd00::10/64\"]\nroutes = [{ to = \"::/0\", via = \"fd00::1\" }]",
+        ] {
+            toml::from_str::<NetworkInterface>(ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_interfaces() {
+        for err in &[
+            "",
+            "primary = true",
+            "dhcp4 = false\naddresses = []",
+            "addresses = [\"10.0.0.10/24\"]\nroutes = [{ to = \"0.0.0.0/0\", via = \"fd00::1\" }]",
+            "addresses = [\"10.0.0.10/24\"]\nroutes = [{ to = \"0.0.0.0/0\" }]",
+            "dhcp4 = true\nmtu = 9001",
+        ] {
+            toml::from_str::<NetworkInterface>(err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// SysctlKey can only be created by deserializing from a kernel parameter name made of
+/// dot-separated components of ASCII alphanumerics, hyphens, and underscores, like
+/// "net.ipv4.ip_forward".  It stores the original form and makes it accessible through standard
+/// traits.  Its purpose is to make sure the key maps to a file under /proc/sys.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct SysctlKey {
+    inner: String,
+}
+
+impl TryFrom<&str> for SysctlKey {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            input.len() <= 200
+                && input.split('.').all(|component| {
+                    !component.is_empty()
+                        && component
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                }),
+            error::InvalidSysctlKey { input }
+        );
+        Ok(SysctlKey {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(SysctlKey, "SysctlKey");
+
+#[cfg(test)]
+mod test_sysctl_key {
+    use super::SysctlKey;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_keys() {
+        for ok in &[
+            "net.ipv4.ip_forward",
+            "vm.max_map_count",
+            "kernel.pid_max",
+            "fs.inotify.max_user_watches",
+            "net.ipv4.conf.eth-1.rp_filter",
+        ] {
+            SysctlKey::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_keys() {
+        for err in &[
+            "",
+            ".",
+            "net..ipv4",
+            "net.ipv4.",
+            "net/ipv4/ip_forward",
+            "../../etc/passwd",
+            "vm.max map",
+            "vm.x\n",
+        ] {
+            SysctlKey::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// CpuList can only be created by deserializing from a comma-separated list of CPU numbers and
+/// ranges, like "2,4-7", in the format the kernel uses for parameters like isolcpus.  It stores
+/// the original form and makes it accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CpuList {
+    inner: String,
+}
+
+impl TryFrom<&str> for CpuList {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let cpu = |s: &str| -> Option<u16> {
+            if s.is_empty() || !s.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            s.parse().ok()
+        };
+        ensure!(
+            input.split(',').all(|item| {
+                let mut bounds = item.splitn(2, '-');
+                match (bounds.next().and_then(cpu), bounds.next()) {
+                    (Some(_), None) => true,
+                    (Some(first), Some(last)) => cpu(last).filter(|&last| first <= last).is_some(),
+                    (None, _) => false,
+                }
+            }),
+            error::InvalidCpuList { input }
+        );
+        Ok(CpuList {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(CpuList, "CpuList");
+
+#[cfg(test)]
+mod test_cpu_list {
+    use super::CpuList;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_lists() {
+        for ok in &["0", "2,3", "4-7", "1,4-7,12", "0-0"] {
+            CpuList::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_lists() {
+        for err in &["", ",", "1,", "7-4", "1-", "-1", "a", "1 2", "nohz,1", "99999"] {
+            CpuList::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// LockdownMode can only be created by deserializing from one of the kernel lockdown modes:
+/// "none", "integrity", or "confidentiality".  It stores the original form and makes it
+/// accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct LockdownMode {
+    inner: String,
+}
+
+impl TryFrom<&str> for LockdownMode {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            ["none", "integrity", "confidentiality"].contains(&input),
+            error::InvalidLockdownMode { input }
+        );
+        Ok(LockdownMode {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(LockdownMode, "LockdownMode");
+
+#[cfg(test)]
+mod test_lockdown_mode {
+    use super::LockdownMode;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_modes() {
+        for ok in &["none", "integrity", "confidentiality"] {
+            LockdownMode::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_modes() {
+        for err in &["", "Integrity", "full", "none integrity"] {
+            LockdownMode::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// SelinuxMode can only be created by deserializing from "enforcing" or "permissive".  SELinux
+/// can't be disabled, only made permissive, and some variants don't allow even that; see
+/// `SELINUX_PERMISSIVE_ALLOWED` in each variant's model.  It stores the original form and makes
+/// it accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct SelinuxMode {
+    inner: String,
+}
+
+impl SelinuxMode {
+    pub fn is_permissive(&self) -> bool {
+        self.inner == "permissive"
+    }
+}
+
+impl TryFrom<&str> for SelinuxMode {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            ["enforcing", "permissive"].contains(&input),
+            error::InvalidSelinuxMode { input }
+        );
+        Ok(SelinuxMode {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(SelinuxMode, "SelinuxMode");
+
+#[cfg(test)]
+mod test_selinux_mode {
+    use super::SelinuxMode;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_modes() {
+        assert!(!SelinuxMode::try_from("enforcing").unwrap().is_permissive());
+        assert!(SelinuxMode::try_from("permissive").unwrap().is_permissive());
+    }
+
+    #[test]
+    fn bad_modes() {
+        for err in &["", "disabled", "Enforcing", "0", "enforcing permissive"] {
+            SelinuxMode::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// Percentage can only be created by deserializing from a whole number from 0 to 100.  Unlike
+/// most modeled types it's a number, not a string, and it's serialized back out as one.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct Percentage {
+    inner: u8,
+}
+
+impl TryFrom<u64> for Percentage {
+    type Error = error::Error;
+
+    fn try_from(input: u64) -> Result<Self, Self::Error> {
+        ensure!(input <= 100, error::InvalidPercentage { input });
+        Ok(Percentage { inner: input as u8 })
+    }
+}
+
+impl<'de> Deserialize<'de> for Percentage {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let original = u64::deserialize(deserializer)?;
+        Self::try_from(original)
+            .map_err(|e| D::Error::custom(format!("Unable to deserialize into Percentage: {}", e)))
+    }
+}
+
+impl Serialize for Percentage {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u8(self.inner)
+    }
+}
+
+impl Deref for Percentage {
+    type Target = u8;
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl fmt::Display for Percentage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.inner)
+    }
+}
+
+#[cfg(test)]
+mod test_percentage {
+    use super::Percentage;
+    use serde::{Deserialize, Serialize};
+    use std::convert::TryFrom;
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct Threshold {
+        percent: Percentage,
+    }
+
+    #[test]
+    fn good_percentages() {
+        for ok in &[0, 1, 85, 100] {
+            assert_eq!(*Percentage::try_from(*ok).unwrap(), *ok as u8);
+        }
+        let parsed: Threshold = toml::from_str("percent = 0").unwrap();
+        assert_eq!(toml::to_string(&parsed).unwrap(), "percent = 0\n");
+    }
+
+    #[test]
+    fn bad_percentages() {
+        for err in &[101, 150, 256, u64::MAX] {
+            Percentage::try_from(*err).unwrap_err();
+        }
+        for err in &["percent = -1", "percent = 50.5", "percent = \"50\""] {
+            toml::from_str::<Threshold>(err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// NtpServerOption can only be created by deserializing from an option chrony accepts for its
+/// time sources, like "iburst" or "minpoll 4".  Options that take a value are given as the option
+/// name, a space, and a number.  It stores the original form and makes it accessible through
+/// standard traits.
+// https://chrony.tuxfamily.org/doc/3.5/chrony.conf.html#server
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct NtpServerOption {
+    inner: String,
+}
+
+impl NtpServerOption {
+    const FLAGS: &'static [&'static str] = &[
+        "auto_offline",
+        "burst",
+        "iburst",
+        "noselect",
+        "offline",
+        "prefer",
+        "require",
+        "trust",
+        "xleave",
+    ];
+
+    const WITH_VALUE: &'static [&'static str] = &[
+        "asymmetry",
+        "filter",
+        "maxdelay",
+        "maxdelaydevratio",
+        "maxdelayratio",
+        "maxpoll",
+        "maxsamples",
+        "maxsources",
+        "mindelay",
+        "minpoll",
+        "minsamples",
+        "minstratum",
+        "offset",
+        "polltarget",
+        "port",
+        "presend",
+        "version",
+    ];
+}
+
+impl TryFrom<&str> for NtpServerOption {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        // Parsing as a float catches misplaced signs and points; the character check rules out
+        // forms chrony doesn't take, like "inf" or "1e3".
+        let is_number = |s: &str| {
+            s.chars()
+                .all(|c| c.is_ascii_digit() || c == '-' || c == '.')
+                && s.parse::<f64>().is_ok()
+        };
+        let mut words = input.splitn(2, ' ');
+        let valid = match (words.next(), words.next()) {
+            (Some(flag), None) => Self::FLAGS.contains(&flag),
+            (Some(name), Some(value)) => Self::WITH_VALUE.contains(&name) && is_number(value),
+            (None, _) => false,
+        };
+        ensure!(valid, error::InvalidNtpServerOption { input });
+        Ok(NtpServerOption {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(NtpServerOption, "NtpServerOption");
+
+#[cfg(test)]
+mod test_ntp_server_option {
+    use super::NtpServerOption;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_options() {
+        for ok in &[
+            "iburst",
+            "prefer",
+            "minpoll 4",
+            "minpoll -6",
+            "maxdelay 0.3",
+        ] {
+            NtpServerOption::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_options() {
+        for err in &[
+            "",
+            "bogus",
+            "iburst 1",
+            "minpoll",
+            "minpoll four",
+            "minpoll  4",
+            "maxdelay 1.2.3",
+            "maxdelay 1e3",
+            "iburst\nserver evil.example.com",
+        ] {
+            NtpServerOption::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// UpdatePolicy can only be created by deserializing from one of the policies updog follows for
+/// automatic updates: "off", "check-only", "download", "apply", or "apply-and-reboot-in-window".
+/// It stores the original form and makes it accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct UpdatePolicy {
+    inner: String,
+}
+
+impl TryFrom<&str> for UpdatePolicy {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            [
+                "off",
+                "check-only",
+                "download",
+                "apply",
+                "apply-and-reboot-in-window"
+            ]
+            .contains(&input),
+            error::InvalidUpdatePolicy { input }
+        );
+        Ok(UpdatePolicy {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(UpdatePolicy, "UpdatePolicy");
+
+#[cfg(test)]
+mod test_update_policy {
+    use super::UpdatePolicy;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_policies() {
+        for ok in &[
+            "off",
+            "check-only",
+            "download",
+            "apply",
+            "apply-and-reboot-in-window",
+        ] {
+            UpdatePolicy::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_policies() {
+        for err in &["", "Off", "on", "apply-and-reboot", "apply\n"] {
+            UpdatePolicy::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// RebootWindow can only be created by deserializing from a daily window of time in UTC, given as
+/// its start and end in 24-hour "HH:MM" form, like "02:00-04:00".  A window can wrap past
+/// midnight, like "22:00-02:00".  It stores the original form and makes it accessible through
+/// standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct RebootWindow {
+    inner: String,
+}
+
+impl TryFrom<&str> for RebootWindow {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        // Checks for "HH:MM" with an hour below 24 and a minute below 60.
+        let valid_time = |time: &str| {
+            let mut parts = time.splitn(2, ':');
+            let hour = parts.next().unwrap_or_default();
+            let minute = parts.next().unwrap_or_default();
+            hour.len() == 2
+                && minute.len() == 2
+                && time.chars().filter(char::is_ascii_digit).count() == 4
+                && hour.parse::<u8>().map(|h| h < 24).unwrap_or(false)
+                && minute.parse::<u8>().map(|m| m < 60).unwrap_or(false)
+        };
+        let mut times = input.splitn(2, '-');
+        let start = times.next().unwrap_or_default();
+        let end = times.next().unwrap_or_default();
+        ensure!(
+            valid_time(start) && valid_time(end) && start != end,
+            error::InvalidRebootWindow { input }
+        );
+        Ok(RebootWindow {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(RebootWindow, "RebootWindow");
+
+#[cfg(test)]
+mod test_reboot_window {
+    use super::RebootWindow;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_windows() {
+        for ok in &["02:00-04:00", "22:00-02:00", "00:00-23:59", "09:30-09:45"] {
+            RebootWindow::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_windows() {
+        for err in &[
+            "",
+            "02:00",
+            "2:00-4:00",
+            "02:00-24:00",
+            "02:60-04:00",
+            "02:00-02:00",
+            "+2:00-04:00",
+            "02:00 - 04:00",
+            "02:00-04:00-06:00",
+        ] {
+            RebootWindow::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// Timestamp can only be created by deserializing from a time in UTC in RFC 3339 form, like
+/// "2020-03-27T02:00:00Z", optionally with fractional seconds.  It stores the original form and
+/// makes it accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Timestamp {
+    inner: String,
+}
+
+impl TryFrom<&str> for Timestamp {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        // Checks for "YYYY-MM-DDTHH:MM:SS", then an optional fraction of a second, then "Z".
+        let valid = input.is_ascii() && input.len() >= 20 && {
+            let (time, rest) = input.split_at(19);
+            let bytes = time.as_bytes();
+            let field = |start: usize, min: u8, max: u8| {
+                time[start..start + 2]
+                    .parse::<u8>()
+                    .map(|n| min <= n && n <= max)
+                    .unwrap_or(false)
+            };
+            let fraction = if rest.starts_with('.') && rest.ends_with('Z') {
+                &rest[1..rest.len() - 1]
+            } else {
+                ""
+            };
+            time.chars().filter(char::is_ascii_digit).count() == 14
+                && bytes[4] == b'-'
+                && bytes[7] == b'-'
+                && bytes[10] == b'T'
+                && bytes[13] == b':'
+                && bytes[16] == b':'
+                && field(5, 1, 12)
+                && field(8, 1, 31)
+                && field(11, 0, 23)
+                && field(14, 0, 59)
+                // Leap seconds are allowed.
+                && field(17, 0, 60)
+                && (rest == "Z"
+                    || (!fraction.is_empty() && fraction.chars().all(|c| c.is_ascii_digit())))
+        };
+        ensure!(valid, error::InvalidTimestamp { input });
+        Ok(Timestamp {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(Timestamp, "Timestamp");
+
+#[cfg(test)]
+mod test_timestamp {
+    use super::Timestamp;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_timestamps() {
+        for ok in &[
+            "2020-03-27T02:00:00Z",
+            "2020-12-31T23:59:60Z",
+            "2020-03-27T02:00:00.123Z",
+        ] {
+            Timestamp::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_timestamps() {
+        for err in &[
+            "",
+            "2020-03-27",
+            "2020-03-27T02:00:00",
+            "2020-03-27 02:00:00Z",
+            "2020-03-27T02:00:00+01:00",
+            "2020-13-27T02:00:00Z",
+            "2020-00-27T02:00:00Z",
+            "2020-03-27T24:00:00Z",
+            "2020-03-27T02:00:00.Z",
+            "2020-03-27T02:00:00Z\n",
+            "+020-03-27T02:00:00Z",
+        ] {
+            Timestamp::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// LogTarget can only be created by deserializing from one of the places updog can log to:
+/// "auto", "terminal", "stderr", "journald", or "syslog".  It stores the original form and makes
+/// it accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct LogTarget {
+    inner: String,
+}
+
+impl TryFrom<&str> for LogTarget {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            ["auto", "terminal", "stderr", "journald", "syslog"].contains(&input),
+            error::InvalidLogTarget { input }
+        );
+        Ok(LogTarget {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(LogTarget, "LogTarget");
+
+#[cfg(test)]
+mod test_log_target {
+    use super::LogTarget;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_targets() {
+        for ok in &["auto", "terminal", "stderr", "journald", "syslog"] {
+            LogTarget::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_targets() {
+        for err in &["", "Auto", "journal", "stdout", "syslog\n"] {
+            LogTarget::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// HostIdentity can only be created by deserializing from "aws-instance-identity" or the absolute
+/// path of a file holding the host's identity, on a single line.  It stores the original form and
+/// makes it accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct HostIdentity {
+    inner: String,
+}
+
+impl TryFrom<&str> for HostIdentity {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            input == "aws-instance-identity"
+                || (input.starts_with('/') && !input.contains(|c: char| c.is_control())),
+            error::InvalidHostIdentity { input }
+        );
+        Ok(HostIdentity {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(HostIdentity, "HostIdentity");
+
+#[cfg(test)]
+mod test_host_identity {
+    use super::HostIdentity;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_identities() {
+        for ok in &["aws-instance-identity", "/run/attestation/quote", "/"] {
+            HostIdentity::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_identities() {
+        for err in &["", "tpm-quote", "run/attestation/quote", "/run/quote\n"] {
+            HostIdentity::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// SshUserName can only be created by deserializing from a login name the admin container can
+/// create: 1 to 32 lowercase ASCII letters, digits, hyphens, or underscores, starting with a
+/// letter or underscore.  It stores the original form and makes it accessible through standard
+/// traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct SshUserName {
+    inner: String,
+}
+
+impl TryFrom<&str> for SshUserName {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let mut chars = input.chars();
+        ensure!(
+            input.len() <= 32
+                && chars
+                    .next()
+                    .map_or(false, |c| c.is_ascii_lowercase() || c == '_')
+                && chars.all(|c| {
+                    c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_'
+                }),
+            error::InvalidSshUserName { input }
+        );
+        Ok(SshUserName {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(SshUserName, "SshUserName");
+
+#[cfg(test)]
+mod test_ssh_user_name {
+    use super::SshUserName;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_user_names() {
+        for ok in &["ec2-user", "_svc", "a", "ops_1"] {
+            SshUserName::try_from(*ok).unwrap();
+        }
+        SshUserName::try_from("a".repeat(32)).unwrap();
+    }
+
+    #[test]
+    fn bad_user_names() {
+        for err in &[
+            "", "Admin", "1user", "-user", "../root", "ec2 user", "user\n",
+        ] {
+            SshUserName::try_from(*err).unwrap_err();
+        }
+        SshUserName::try_from("a".repeat(33)).unwrap_err();
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// SshPublicKey can only be created by deserializing from an SSH public key in the one-line
+/// format of an `authorized_keys` file: the key type, the base64 key, and an optional comment.
+/// The key's own type, encoded at its start, has to match.  Options before the key type, like
+/// `from="..."`, aren't accepted, and neither are quotes or backslashes in the comment, because
+/// the keys are written into a JSON file for the admin container.  It stores the original form and
+/// makes it accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct SshPublicKey {
+    inner: String,
+}
+
+impl SshPublicKey {
+    const KEY_TYPES: &'static [&'static str] = &[
+        "ssh-ed25519",
+        "ssh-rsa",
+        "ecdsa-sha2-nistp256",
+        "ecdsa-sha2-nistp384",
+        "ecdsa-sha2-nistp521",
+        "sk-ssh-ed25519@openssh.com",
+        "sk-ecdsa-sha2-nistp256@openssh.com",
+    ];
+}
+
+impl TryFrom<&str> for SshPublicKey {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            !input.contains(|c: char| c.is_control() || c == '"' || c == '\\'),
+            error::InvalidSshPublicKey {
+                msg: "may not contain control characters, quotes, or backslashes"
+            }
+        );
+        let mut fields = input.splitn(3, ' ');
+        let key_type = fields.next().unwrap_or_default();
+        ensure!(
+            Self::KEY_TYPES.contains(&key_type),
+            error::InvalidSshPublicKey {
+                msg: format!(
+                    "key type must be one of {}, received '{}'",
+                    Self::KEY_TYPES.join(", "),
+                    key_type
+                )
+            }
+        );
+        let key = base64::decode(fields.next().unwrap_or_default()).map_err(|e| {
+            error::Error::InvalidSshPublicKey {
+                msg: format!("key isn't valid base64: {}", e),
+            }
+        })?;
+        // The key starts with its type, as a string with a 4-byte big-endian length.
+        let encoded_type = key
+            .get(..4)
+            .map(|len| u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize)
+            .and_then(|len| key.get(4..4 + len));
+        ensure!(
+            encoded_type == Some(key_type.as_bytes()),
+            error::InvalidSshPublicKey {
+                msg: format!("key isn't a {} key", key_type)
+            }
+        );
+        Ok(SshPublicKey {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(SshPublicKey, "SshPublicKey");
+
+#[cfg(test)]
+mod test_ssh_public_key {
+    use super::SshPublicKey;
+    use std::convert::TryFrom;
+
+    const ED25519: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+
+    #[test]
+    fn good_keys() {
+        for ok in &[
+            format!("ssh-ed25519 {}", ED25519),
+            format!("ssh-ed25519 {} alice@laptop", ED25519),
+            format!("ssh-ed25519 {} rotated 2020-06-01", ED25519),
+        ] {
+            SshPublicKey::try_from(ok.as_str()).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_keys() {
+        for err in &[
+            String::new(),
+            ED25519.to_string(),
+            "ssh-ed25519".to_string(),
+            "ssh-ed25519 not-base64".to_string(),
+            format!("ssh-rsa {}", ED25519),
+            format!("ssh-dss {}", ED25519),
+            format!("from=\"10.0.0.1\" ssh-ed25519 {}", ED25519),
+            format!("ssh-ed25519 {} \"quoted\"", ED25519),
+            format!("ssh-ed25519 {}\nssh-ed25519 {}", ED25519, ED25519),
+            "ssh-ed25519 AAAA".to_string(),
+        ] {
+            SshPublicKey::try_from(err.as_str()).unwrap_err();
+        }
+    }
+}
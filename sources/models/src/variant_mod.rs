@@ -2,7 +2,7 @@
 // directory at variant/ - see README.md.
 
 use crate::{ConfigurationFiles, Services};
-use bottlerocket_release::BottlerocketRelease;
+use bottlerocket_release::VersionInfo;
 use model_derive::model;
 use serde::{Deserialize, Serialize};
 
@@ -18,5 +18,5 @@ struct Model {
     settings: Settings,
     services: Services,
     configuration_files: ConfigurationFiles,
-    os: BottlerocketRelease,
+    os: VersionInfo,
 }
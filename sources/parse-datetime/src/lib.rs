@@ -6,9 +6,16 @@ This library parses a `DateTime<Utc>` from a string.
 The string can be:
 
 * an `RFC3339` formatted date / time
+* a date and time with an explicit timezone, like `"2024-01-01 09:00 PST"`
+   * the date is `YYYY-MM-DD`, and the time `HH:MM` or `HH:MM:SS`, separated by a space or a `T`
+   * the timezone is required, and may be a numeric offset like `-08:00`, `-0800`, or `-08`, or one of these abbreviations: `UTC`, `GMT`, `Z`, `EST`, `EDT`, `CST`, `CDT`, `MST`, `MDT`, `PST`, `PDT`, `AKST`, `AKDT`, `HST`, `CET`, `CEST`, `JST`
+   * abbreviations are fixed offsets, so `PST` is always UTC-8, even in summer; use the one that's in effect on the date you mean
 * a string with the form `"[in] <unsigned integer> <unit(s)>"` where 'in' is optional
    * `<unsigned integer>` may be any unsigned integer and
-   * `<unit(s)>` may be either the singular or plural form of the following: `hour | hours`, `day | days`, `week | weeks`
+   * `<unit(s)>` may be either the singular or plural form of the following: `minute | minutes`, `hour | hours`, `day | days`, `week | weeks`, or the short forms `m`, `h`, `d`, and `w`
+* the same offset from now, written `"+<unsigned integer><unit>"` or `"now+<unsigned integer><unit>"`, or just `"now"`
+
+Nothing depends on the system's locale or timezone; times without a timezone are refused rather than guessed at.
 
 Examples:
 
@@ -18,10 +25,14 @@ Examples:
 * `"in 2 weeks"`
 * `"1 hour"`
 * `"7 days"`
+* `"+2d"`
+* `"now+4h"`
+* `"2024-01-01 09:00 PST"`
+* `"2024-01-01T17:00:00Z"`
 */
 
-use chrono::{DateTime, Duration, FixedOffset, Utc};
-use snafu::{ensure, ResultExt};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDateTime, TimeZone, Utc};
+use snafu::{ensure, OptionExt, ResultExt};
 
 mod error {
     use snafu::Snafu;
@@ -41,69 +52,205 @@ mod error {
             input: String,
             source: std::num::ParseIntError,
         },
+
+        #[snafu(display(
+            "Date argument '{}' has unknown timezone '{}'; use a numeric offset like '-08:00', or UTC",
+            input,
+            zone
+        ))]
+        DateArgTimezone { input: String, zone: String },
     }
 }
 pub use error::Error;
 type Result<T> = std::result::Result<T, error::Error>;
 
-/// Parses a user-specified datetime, either in full RFC 3339 format, or a shorthand like "in 7
-/// days"
+/// The formats accepted for a date and time before the timezone.
+const DATETIME_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+];
+
+/// Timezone abbreviations we accept, and their offsets from UTC in hours.  Only abbreviations
+/// that mean one thing are listed; others, like IST, have to be given as offsets.
+const ZONES: &[(&str, i32)] = &[
+    ("UTC", 0),
+    ("GMT", 0),
+    ("Z", 0),
+    ("EST", -5),
+    ("EDT", -4),
+    ("CST", -6),
+    ("CDT", -5),
+    ("MST", -7),
+    ("MDT", -6),
+    ("PST", -8),
+    ("PDT", -7),
+    ("AKST", -9),
+    ("AKDT", -8),
+    ("HST", -10),
+    ("CET", 1),
+    ("CEST", 2),
+    ("JST", 9),
+];
+
+/// Parses a user-specified datetime, either in full RFC 3339 format, a date and time with a
+/// timezone like "2024-01-01 09:00 PST", or a shorthand like "in 7 days" or "+7d"
 pub fn parse_datetime(input: &str) -> Result<DateTime<Utc>> {
+    parse_datetime_at(input, Utc::now())
+}
+
+/// Parses a user-specified datetime like `parse_datetime`, with shorthands like "in 7 days"
+/// counted from `now`.
+pub fn parse_datetime_at(input: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let text = input.trim();
+
     // If the user gave an absolute date in a standard format, accept it.
-    let try_dt: std::result::Result<DateTime<FixedOffset>, chrono::format::ParseError> =
-        DateTime::parse_from_rfc3339(input);
-    if let Ok(dt) = try_dt {
-        let utc = dt.into();
-        return Ok(utc);
+    if let Ok(dt) = DateTime::parse_from_rfc3339(text) {
+        return Ok(dt.into());
+    }
+
+    // "now", "now+4h", and "+4h" are offsets from now.
+    if text == "now" {
+        return Ok(now);
+    }
+    let offset = if text.starts_with("now") {
+        Some(text["now".len()..].trim_start())
+    } else {
+        Some(text)
+    }
+    .filter(|offset| offset.starts_with('+'));
+    if let Some(offset) = offset {
+        return Ok(now + parse_duration(input, &offset[1..])?);
+    }
+
+    // A date and time, which needs a timezone.
+    if text.starts_with(|c: char| c.is_ascii_digit()) && text.contains('-') && text.contains(':') {
+        return parse_zoned(input, text);
     }
 
     // Otherwise, pull apart a request like "in 5 days" to get an exact datetime.
-    let mut parts: Vec<&str> = input.split_whitespace().collect();
+    let mut words = text.splitn(2, char::is_whitespace);
+    let duration = match (words.next(), words.next()) {
+        (Some("in"), Some(rest)) => parse_duration(input, rest)?,
+        _ => parse_duration(input, text)?,
+    };
+    Ok(now + duration)
+}
+
+/// Parses an amount of time on its own, like "7 days" or "7d", for callers that count it from
+/// some time other than now, or backward.
+pub fn parse_interval(input: &str) -> Result<Duration> {
+    parse_duration(input, input)
+}
+
+/// Parses a count and unit, like "7 days" or "7d".
+fn parse_duration(input: &str, text: &str) -> Result<Duration> {
+    let text = text.trim();
+    let digits = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (count_str, unit_str) = text.split_at(digits);
     ensure!(
-        parts.len() == 3 || parts.len() == 2,
+        !count_str.is_empty(),
         error::DateArgInvalid {
             input,
-            msg: "expected RFC 3339, or something like 'in 7 days' or '7 days'"
+            msg: "expected RFC 3339, a date and time with a timezone, or something like 'in 7 days', '7 days', or '+7d'"
         }
     );
-    let unit_str = parts.pop().unwrap();
-    let count_str = parts.pop().unwrap();
-
-    // the prefix string 'in' is optional
-    if let Some(prefix_str) = parts.pop() {
-        ensure!(
-            prefix_str == "in",
-            error::DateArgInvalid {
-                input,
-                msg: "expected prefix 'in', something like 'in 7 days'",
-            }
-        );
-    }
-
     let count: u32 = count_str.parse().context(error::DateArgCount { input })?;
+    let count = i64::from(count);
 
-    let duration = match unit_str {
-        "hour" | "hours" => Duration::hours(i64::from(count)),
-        "day" | "days" => Duration::days(i64::from(count)),
-        "week" | "weeks" => Duration::weeks(i64::from(count)),
+    let duration = match unit_str.trim_start() {
+        "m" | "min" | "mins" | "minute" | "minutes" => Duration::minutes(count),
+        "h" | "hour" | "hours" => Duration::hours(count),
+        "d" | "day" | "days" => Duration::days(count),
+        "w" | "week" | "weeks" => Duration::weeks(count),
         _ => {
             return error::DateArgInvalid {
                 input,
-                msg: "date argument's unit must be hours/days/weeks",
+                msg: "date argument's unit must be minutes/hours/days/weeks",
             }
             .fail();
         }
     };
+    Ok(duration)
+}
+
+/// Parses a date and time followed by a timezone, like "2024-01-01 09:00 PST".
+fn parse_zoned(input: &str, text: &str) -> Result<DateTime<Utc>> {
+    let mut parts = text.rsplitn(2, char::is_whitespace);
+    let (zone, datetime) = match (parts.next(), parts.next()) {
+        (Some(zone), Some(datetime)) => (zone, datetime.trim_end()),
+        _ => ("", text),
+    };
+    let naive = |s: &str| {
+        DATETIME_FORMATS
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+    };
+    ensure!(
+        naive(text).is_none(),
+        error::DateArgInvalid {
+            input,
+            msg: "a date and time needs a timezone, like '2024-01-01 09:00 UTC'"
+        }
+    );
+    let datetime = naive(datetime).context(error::DateArgInvalid {
+        input,
+        msg: "expected a date and time like '2024-01-01 09:00' before the timezone",
+    })?;
+    let offset = zone_offset(zone).context(error::DateArgTimezone { input, zone })?;
+    let local = offset
+        .from_local_datetime(&datetime)
+        .single()
+        .context(error::DateArgInvalid {
+            input,
+            msg: "date and time doesn't exist in the given timezone",
+        })?;
+    Ok(local.with_timezone(&Utc))
+}
+
+/// Returns the offset for a timezone abbreviation from `ZONES`, or a numeric offset like
+/// "-08:00", "-0800", or "-08".
+fn zone_offset(zone: &str) -> Option<FixedOffset> {
+    if let Some((_, hours)) = ZONES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(zone))
+    {
+        return FixedOffset::east_opt(hours * 3600);
+    }
 
-    let now = Utc::now();
-    let then = now + duration;
-    Ok(then)
+    let sign = match zone.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits: String = zone[1..].chars().filter(|c| *c != ':').collect();
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes) = match digits.len() {
+        2 => (&digits[..], "0"),
+        4 => (&digits[..2], &digits[2..]),
+        _ => return None,
+    };
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().into()
+    }
+
     #[test]
     fn test_acceptable_strings() {
         let inputs = vec![
@@ -119,19 +266,127 @@ mod tests {
             "0 weeks",
             "1 week",
             "5000000 weeks",
+            "in 30 minutes",
+            "+2d",
+            "+4h",
+            "now",
+            "now+4h",
+            "now + 90m",
+            "2024-01-01 09:00 PST",
+            "2024-01-01T09:00:30 -08:00",
         ];
 
         for input in inputs {
-            assert!(parse_datetime(input).is_ok())
+            assert!(parse_datetime(input).is_ok(), "{}", input)
         }
     }
 
     #[test]
     fn test_unacceptable_strings() {
-        let inputs = vec!["in", "0 hou", "hours", "in 1 month"];
+        let inputs = vec![
+            "in",
+            "0 hou",
+            "hours",
+            "in 1 month",
+            "+",
+            "now-4h",
+            "+4x",
+            "2024-01-01 09:00",
+            "2024-01-01T09:00:00",
+            "2024-01-01 09:00 XYZ",
+            "2024-01-01 09:00 +25:00",
+            "2024-13-01 09:00 UTC",
+        ];
 
         for input in inputs {
-            assert!(parse_datetime(input).is_err())
+            assert!(parse_datetime(input).is_err(), "{}", input)
+        }
+    }
+
+    #[test]
+    fn relative_to_now() {
+        let now = utc("2024-01-01T12:00:00Z");
+        for (input, expected) in &[
+            ("now", "2024-01-01T12:00:00Z"),
+            ("now+4h", "2024-01-01T16:00:00Z"),
+            ("now + 4 hours", "2024-01-01T16:00:00Z"),
+            ("+2d", "2024-01-03T12:00:00Z"),
+            ("+90m", "2024-01-01T13:30:00Z"),
+            ("in 1 week", "2024-01-08T12:00:00Z"),
+            ("3 days", "2024-01-04T12:00:00Z"),
+        ] {
+            assert_eq!(
+                parse_datetime_at(input, now).unwrap(),
+                utc(expected),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn intervals() {
+        assert_eq!(parse_interval("90 days").unwrap(), Duration::days(90));
+        assert_eq!(parse_interval("4h").unwrap(), Duration::hours(4));
+        assert!(parse_interval("in 4h").is_err());
+        assert!(parse_interval("2024-01-01 09:00 UTC").is_err());
+    }
+
+    #[test]
+    fn explicit_timezones() {
+        let now = utc("2020-01-01T00:00:00Z");
+        for (input, expected) in &[
+            ("2024-01-01 09:00 PST", "2024-01-01T17:00:00Z"),
+            ("2024-07-01 09:00 PDT", "2024-07-01T16:00:00Z"),
+            // Abbreviations are fixed offsets, whatever the date.
+            ("2024-07-01 09:00 PST", "2024-07-01T17:00:00Z"),
+            ("2024-01-01 09:00 utc", "2024-01-01T09:00:00Z"),
+            ("2024-01-01 09:00:30 JST", "2024-01-01T00:00:30Z"),
+            ("2024-01-01T09:00 -08:00", "2024-01-01T17:00:00Z"),
+            ("2024-01-01 09:00 +0530", "2024-01-01T03:30:00Z"),
+            ("2024-01-01 09:00 -08", "2024-01-01T17:00:00Z"),
+            ("2024-01-01T09:00:00-08:00", "2024-01-01T17:00:00Z"),
+        ] {
+            assert_eq!(
+                parse_datetime_at(input, now).unwrap(),
+                utc(expected),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        // Whatever we parse, formatting it as RFC 3339 and parsing that gives the same time,
+        // in any offset.
+        let now = utc("2024-01-01T12:00:00Z");
+        for input in &[
+            "2024-01-01 09:00 PST",
+            "2024-03-10 02:30 EST",
+            "2024-01-01 09:00 +0530",
+            "now+4h",
+            "in 6 days",
+        ] {
+            let parsed = parse_datetime_at(input, now).unwrap();
+            for offset in &[0, -8 * 3600, 5 * 3600 + 1800] {
+                let formatted = parsed
+                    .with_timezone(&FixedOffset::east(*offset))
+                    .to_rfc3339();
+                assert_eq!(
+                    parse_datetime_at(&formatted, now).unwrap(),
+                    parsed,
+                    "{}",
+                    input
+                );
+            }
+            let formatted = parsed.format("%Y-%m-%d %H:%M:%S UTC").to_string();
+            assert_eq!(
+                parse_datetime_at(&formatted, now).unwrap(),
+                parsed,
+                "{}",
+                input
+            );
         }
     }
 }
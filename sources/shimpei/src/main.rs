@@ -0,0 +1,483 @@
+/*!
+# Introduction
+
+shimpei adds the defaults in `settings.oci-defaults` to the OCI runtime spec of each container
+containerd creates, then hands off to runc.
+
+containerd is configured to run shimpei in place of runc, with the same arguments.  When runc is
+asked to `create` or `run` a container, shimpei first updates the `config.json` in the container's
+bundle:
+
+* Each hook is added to the hooks for its stage, after any the spec already has.
+* Each rlimit replaces the spec's limit of the same type, or is added if it has none.
+* Each device is added to the container and allowed by its device cgroup, with the given access.
+  Devices that don't exist on the host, like GPUs on an instance without any, are skipped.
+
+Items are added in order of their names, so containers get them in a stable order.  Any other
+runc command is passed through untouched.
+
+The defaults are read from `/etc/containerd/oci-defaults.json`, which thar-be-settings renders
+from the API when the settings change, so new containers get new defaults without restarting
+containerd.  If the defaults can't be applied, the container isn't created, rather than starting
+without limits or hooks the operator asked for.
+*/
+
+#![deny(rust_2018_idioms)]
+
+use model::modeled_types::{DeviceAccess, DevicePath, Identifier};
+use model::{OciDefaults, OciDevice, OciHook, Rlimit};
+use serde_json::{json, Map, Value};
+use snafu::{OptionExt, ResultExt};
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+const DEFAULTS_PATH: &str = "/etc/containerd/oci-defaults.json";
+const RUNC_PATH: &str = "/usr/bin/runc";
+
+// runc's global options that take a value, which comes before the command.
+const RUNC_GLOBAL_OPTIONS_WITH_VALUES: &[&str] =
+    &["--root", "--log", "--log-format", "--criu", "--rootless"];
+
+mod error {
+    use snafu::Snafu;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Failed to read '{}': {}", path.display(), source))]
+        ReadFile {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to parse OCI defaults from '{}': {}", path.display(), source))]
+        ParseDefaults {
+            path: PathBuf,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Failed to parse OCI runtime spec '{}': {}", path.display(), source))]
+        ParseSpec {
+            path: PathBuf,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("OCI runtime spec has a '{}' that isn't {}", key, expected))]
+        SpecShape { key: String, expected: &'static str },
+
+        #[snafu(display("Failed to write OCI runtime spec '{}': {}", path.display(), source))]
+        WriteSpec {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to run '{}': {}", path, source))]
+        ExecRunc {
+            path: &'static str,
+            source: std::io::Error,
+        },
+    }
+}
+type Result<T> = std::result::Result<T, error::Error>;
+
+/// Returns the bundle directory if runc's arguments say to create a container, or None for any
+/// other command.
+fn bundle_dir(args: &[String]) -> Option<PathBuf> {
+    let mut iter = args.iter();
+    // Global options come first; the first argument that isn't one is the command.
+    let command = loop {
+        let arg = iter.next()?;
+        if RUNC_GLOBAL_OPTIONS_WITH_VALUES.contains(&arg.as_str()) {
+            iter.next();
+        } else if !arg.starts_with('-') {
+            break arg;
+        }
+    };
+    if command != "create" && command != "run" {
+        return None;
+    }
+
+    while let Some(arg) = iter.next() {
+        if arg == "--bundle" || arg == "-b" {
+            return iter.next().map(PathBuf::from);
+        }
+        if let Some(bundle) = arg.strip_prefix("--bundle=") {
+            return Some(PathBuf::from(bundle));
+        }
+    }
+    // runc uses the current directory if it isn't given a bundle.
+    Some(PathBuf::from("."))
+}
+
+/// Loads the defaults, or None if there aren't any.
+fn load_defaults<P: AsRef<Path>>(path: P) -> Result<Option<OciDefaults>> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data = fs::read(path).context(error::ReadFile { path })?;
+    // The template renders "null" if the settings have never been set.
+    serde_json::from_slice(&data).context(error::ParseDefaults { path })
+}
+
+/// What runc needs to know about a host device to give it to a container.
+#[derive(Debug, Clone, PartialEq)]
+struct DeviceInfo {
+    /// "c" for a character device, "b" for a block device.
+    kind: &'static str,
+    major: u64,
+    minor: u64,
+    file_mode: u32,
+    uid: u32,
+    gid: u32,
+}
+
+/// Looks up a host device, returning None if it doesn't exist or isn't a device.
+fn host_device(path: &DevicePath) -> Option<DeviceInfo> {
+    let metadata = fs::metadata(path.as_ref() as &str).ok()?;
+    let kind = if metadata.file_type().is_char_device() {
+        "c"
+    } else if metadata.file_type().is_block_device() {
+        "b"
+    } else {
+        return None;
+    };
+    // Linux splits the device number the way glibc's major() and minor() read it.
+    let rdev = metadata.rdev();
+    Some(DeviceInfo {
+        kind,
+        major: ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff),
+        minor: (rdev & 0xff) | ((rdev >> 12) & !0xff),
+        file_mode: metadata.mode() & 0o777,
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+    })
+}
+
+/// Returns the array at the end of `keys` in the spec, creating it and any objects on the way if
+/// they're missing.
+fn spec_array<'a>(spec: &'a mut Value, keys: &[&str]) -> Result<&'a mut Vec<Value>> {
+    let mut value = spec;
+    for (i, key) in keys.iter().enumerate() {
+        let last = i == keys.len() - 1;
+        let object = value.as_object_mut().context(error::SpecShape {
+            key: keys[..i].join("."),
+            expected: "an object",
+        })?;
+        value = object.entry(*key).or_insert_with(|| {
+            if last {
+                Value::Array(Vec::new())
+            } else {
+                Value::Object(Map::new())
+            }
+        });
+    }
+    value.as_array_mut().context(error::SpecShape {
+        key: keys.join("."),
+        expected: "an array",
+    })
+}
+
+/// Returns the named items sorted by name, so they're added in a stable order.
+fn sorted<K: AsRef<str>, V>(items: &Option<HashMap<K, V>>) -> Vec<(&K, &V)> {
+    let mut items: Vec<_> = items.iter().flatten().collect();
+    items.sort_by(|a, b| a.0.as_ref().cmp(b.0.as_ref()));
+    items
+}
+
+fn add_hook(spec: &mut Value, name: &Identifier, hook: &OciHook) -> Result<()> {
+    let (stage, path) = match (&hook.stage, &hook.path) {
+        (Some(stage), Some(path)) => (stage, path),
+        _ => {
+            eprintln!("Skipping OCI hook '{}' without a stage and path", name);
+            return Ok(());
+        }
+    };
+    let mut args = vec![path.to_string()];
+    args.extend(hook.args.iter().flatten().map(ToString::to_string));
+    let mut env: Vec<_> = hook
+        .environment
+        .iter()
+        .flatten()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    env.sort();
+
+    let mut entry = json!({ "path": path, "args": args, "env": env });
+    if let Some(timeout) = hook.timeout_seconds.filter(|&timeout| timeout > 0) {
+        entry["timeout"] = json!(timeout);
+    }
+    spec_array(spec, &["hooks", stage.as_ref()])?.push(entry);
+    Ok(())
+}
+
+fn add_rlimit(spec: &mut Value, name: &str, rlimit: &Rlimit) -> Result<()> {
+    // Either limit defaults to the other, since a soft limit can't be above the hard limit.
+    let (soft, hard) = match (rlimit.soft, rlimit.hard) {
+        (Some(soft), Some(hard)) => (soft, hard),
+        (Some(limit), None) | (None, Some(limit)) => (limit, limit),
+        (None, None) => {
+            eprintln!("Skipping rlimit '{}' without a soft or hard limit", name);
+            return Ok(());
+        }
+    };
+    let entry = json!({ "type": name, "soft": soft, "hard": hard });
+    let rlimits = spec_array(spec, &["process", "rlimits"])?;
+    match rlimits
+        .iter_mut()
+        .find(|existing| existing["type"] == json!(name))
+    {
+        Some(existing) => *existing = entry,
+        None => rlimits.push(entry),
+    }
+    Ok(())
+}
+
+fn add_device<F>(spec: &mut Value, name: &Identifier, device: &OciDevice, lookup: F) -> Result<()>
+where
+    F: Fn(&DevicePath) -> Option<DeviceInfo>,
+{
+    let path = match &device.path {
+        Some(path) => path,
+        None => {
+            eprintln!("Skipping device '{}' without a path", name);
+            return Ok(());
+        }
+    };
+    let info = match lookup(path) {
+        Some(info) => info,
+        None => return Ok(()),
+    };
+    let access = device.access.as_ref().map_or("rwm", DeviceAccess::as_ref);
+
+    let devices = spec_array(spec, &["linux", "devices"])?;
+    if !devices
+        .iter()
+        .any(|existing| existing["path"] == json!(path))
+    {
+        devices.push(json!({
+            "path": path,
+            "type": info.kind,
+            "major": info.major,
+            "minor": info.minor,
+            "fileMode": info.file_mode,
+            "uid": info.uid,
+            "gid": info.gid,
+        }));
+    }
+    spec_array(spec, &["linux", "resources", "devices"])?.push(json!({
+        "allow": true,
+        "type": info.kind,
+        "major": info.major,
+        "minor": info.minor,
+        "access": access,
+    }));
+    Ok(())
+}
+
+/// Adds the defaults to the spec, looking up devices with `lookup`.
+fn apply_defaults<F>(spec: &mut Value, defaults: &OciDefaults, lookup: F) -> Result<()>
+where
+    F: Fn(&DevicePath) -> Option<DeviceInfo>,
+{
+    for (name, hook) in sorted(&defaults.hooks) {
+        add_hook(spec, name, hook)?;
+    }
+    for (rlimit_type, rlimit) in sorted(&defaults.rlimits) {
+        add_rlimit(spec, &rlimit_type.oci_name(), rlimit)?;
+    }
+    for (name, device) in sorted(&defaults.devices) {
+        add_device(spec, name, device, &lookup)?;
+    }
+    Ok(())
+}
+
+/// Adds the defaults to the bundle's spec, going through a temporary file so runc never sees a
+/// partial spec.
+fn update_bundle(bundle: &Path, defaults: &OciDefaults) -> Result<()> {
+    let path = bundle.join("config.json");
+    let data = fs::read(&path).context(error::ReadFile { path: &path })?;
+    let mut spec: Value =
+        serde_json::from_slice(&data).context(error::ParseSpec { path: &path })?;
+    apply_defaults(&mut spec, defaults, host_device)?;
+
+    let data = serde_json::to_vec(&spec).expect("spec serialization can't fail");
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, data)
+        .and_then(|()| fs::rename(&tmp, &path))
+        .context(error::WriteSpec { path })
+}
+
+fn run() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(bundle) = bundle_dir(&args) {
+        if let Some(defaults) = load_defaults(DEFAULTS_PATH)? {
+            update_bundle(&bundle, &defaults)?;
+        }
+    }
+    // exec only returns if it fails.
+    let source = Command::new(RUNC_PATH).args(&args).exec();
+    Err(error::Error::ExecRunc {
+        path: RUNC_PATH,
+        source,
+    })
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn args(args: &str) -> Vec<String> {
+        args.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn finds_bundle() {
+        assert_eq!(
+            bundle_dir(&args(
+                "--root /run/containerd/runc/k8s.io --log log.json --log-format json create --bundle /run/bundle --pid-file init.pid abc"
+            )),
+            Some(PathBuf::from("/run/bundle"))
+        );
+        assert_eq!(
+            bundle_dir(&args("--systemd-cgroup run -b=x --bundle=/b abc")),
+            Some(PathBuf::from("/b"))
+        );
+        assert_eq!(bundle_dir(&args("create abc")), Some(PathBuf::from(".")));
+        assert_eq!(bundle_dir(&args("--root create start abc")), None);
+        assert_eq!(bundle_dir(&args("delete --force abc")), None);
+        assert_eq!(bundle_dir(&args("--debug")), None);
+    }
+
+    #[test]
+    fn missing_or_null_defaults() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("oci-defaults.json");
+        assert!(load_defaults(&path).unwrap().is_none());
+        fs::write(&path, "null").unwrap();
+        assert!(load_defaults(&path).unwrap().is_none());
+        fs::write(&path, r#"{"rlimits": {"nofiles": {"hard": 1}}}"#).unwrap();
+        load_defaults(&path).unwrap_err();
+    }
+
+    #[test]
+    fn applies_defaults() {
+        let defaults: OciDefaults = serde_json::from_value(json!({
+            "hooks": {
+                "b-log": {"stage": "poststop", "path": "/opt/bin/log", "args": ["--stopped"]},
+                "a-setup": {
+                    "stage": "prestart",
+                    "path": "/opt/bin/setup",
+                    "environment": {"MODE": "fast", "DEBUG": "1"},
+                    "timeout-seconds": 5,
+                },
+            },
+            "rlimits": {
+                "nofile": {"soft": 1024, "hard": 4096},
+                "memlock": {"hard": 65536},
+            },
+            "devices": {
+                "kvm": {"path": "/dev/kvm"},
+                "fuse": {"path": "/dev/fuse", "access": "rw"},
+                "gpu": {"path": "/dev/nvidia0"},
+            },
+        }))
+        .unwrap();
+        let mut spec = json!({
+            "process": {"rlimits": [{"type": "RLIMIT_NOFILE", "soft": 1, "hard": 1}]},
+            "hooks": {"prestart": [{"path": "/usr/bin/existing"}]},
+            "linux": {
+                "devices": [{"path": "/dev/fuse", "type": "c", "major": 10, "minor": 229}],
+                "resources": {"devices": [{"allow": false, "access": "rwm"}]},
+            },
+        });
+        // There's no GPU on this "host".
+        apply_defaults(&mut spec, &defaults, |path| match path.as_ref() as &str {
+            "/dev/kvm" => Some(DeviceInfo {
+                kind: "c",
+                major: 10,
+                minor: 232,
+                file_mode: 0o660,
+                uid: 0,
+                gid: 36,
+            }),
+            "/dev/fuse" => Some(DeviceInfo {
+                kind: "c",
+                major: 10,
+                minor: 229,
+                file_mode: 0o666,
+                uid: 0,
+                gid: 0,
+            }),
+            _ => None,
+        })
+        .unwrap();
+
+        assert_eq!(
+            spec,
+            json!({
+                "process": {"rlimits": [
+                    {"type": "RLIMIT_NOFILE", "soft": 1024, "hard": 4096},
+                    {"type": "RLIMIT_MEMLOCK", "soft": 65536, "hard": 65536},
+                ]},
+                "hooks": {
+                    "prestart": [
+                        {"path": "/usr/bin/existing"},
+                        {
+                            "path": "/opt/bin/setup",
+                            "args": ["/opt/bin/setup"],
+                            "env": ["DEBUG=1", "MODE=fast"],
+                            "timeout": 5,
+                        },
+                    ],
+                    "poststop": [
+                        {"path": "/opt/bin/log", "args": ["/opt/bin/log", "--stopped"], "env": []},
+                    ],
+                },
+                "linux": {
+                    "devices": [
+                        {"path": "/dev/fuse", "type": "c", "major": 10, "minor": 229},
+                        {
+                            "path": "/dev/kvm",
+                            "type": "c",
+                            "major": 10,
+                            "minor": 232,
+                            "fileMode": 0o660,
+                            "uid": 0,
+                            "gid": 36,
+                        },
+                    ],
+                    "resources": {"devices": [
+                        {"allow": false, "access": "rwm"},
+                        {"allow": true, "type": "c", "major": 10, "minor": 229, "access": "rw"},
+                        {"allow": true, "type": "c", "major": 10, "minor": 232, "access": "rwm"},
+                    ]},
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_spec() {
+        let defaults: OciDefaults =
+            serde_json::from_value(json!({"rlimits": {"nofile": {"hard": 1}}})).unwrap();
+        let mut spec = json!({"process": {"rlimits": {}}});
+        apply_defaults(&mut spec, &defaults, |_| None).unwrap_err();
+    }
+}
@@ -0,0 +1,106 @@
+/*!
+This module reads the watcher's config file, which says where the events and the manifest are,
+and when a rollout should be paused.
+*/
+
+use crate::error::{self, Result};
+use serde::Deserialize;
+use snafu::ResultExt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Config {
+    /// The manifest to pause rollouts in, as updata takes it: a path, or an `s3://` URL.
+    pub(crate) manifest: String,
+    /// The events stored by the webhook endpoint, one JSON object per line.
+    pub(crate) events: PathBuf,
+    /// Where the watcher remembers the rollouts it paused.
+    pub(crate) state: PathBuf,
+    /// How to run updata, split on whitespace.
+    #[serde(default = "default_updata")]
+    pub(crate) updata: String,
+    #[serde(default)]
+    pub(crate) policy: Policy,
+}
+
+/// When to pause an update's rollout.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Policy {
+    /// The share of hosts that tried the update and failed, as a percentage, above which its
+    /// rollout is paused.
+    #[serde(default = "default_max_failure_percentage")]
+    pub(crate) max_failure_percentage: f64,
+    /// How many hosts have to have tried the update before it can be paused, so a couple of
+    /// unlucky hosts early in the first wave don't stop a rollout.
+    #[serde(default = "default_min_hosts")]
+    pub(crate) min_hosts: u64,
+    /// Only events from this many hours back are counted.
+    #[serde(default = "default_window_hours")]
+    pub(crate) window_hours: i64,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            max_failure_percentage: default_max_failure_percentage(),
+            min_hosts: default_min_hosts(),
+            window_hours: default_window_hours(),
+        }
+    }
+}
+
+fn default_updata() -> String {
+    "updata".to_string()
+}
+
+fn default_max_failure_percentage() -> f64 {
+    5.0
+}
+
+fn default_min_hosts() -> u64 {
+    20
+}
+
+fn default_window_hours() -> i64 {
+    24
+}
+
+impl Config {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let data = fs::read_to_string(path).context(error::ConfigRead { path })?;
+        toml::from_str(&data).context(error::ConfigParse { path })
+    }
+
+    /// The updata command, split into the program and its leading arguments.
+    pub(crate) fn updata_command(&self) -> Vec<String> {
+        self.updata.split_whitespace().map(String::from).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        let config: Config = toml::from_str(
+            r#"
+            manifest = "s3://repo/manifest.json"
+            events = "events.jsonl"
+            state = "state.json"
+
+            [policy]
+            min-hosts = 50
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.updata_command(), vec!["updata"]);
+        assert_eq!(config.policy.min_hosts, 50);
+        assert_eq!(config.policy.window_hours, 24);
+
+        assert!(toml::from_str::<Config>("manifest = \"m.json\"\nevents = \"e\"\n").is_err());
+    }
+}
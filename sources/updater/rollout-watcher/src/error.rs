@@ -0,0 +1,56 @@
+use snafu::Snafu;
+use std::path::PathBuf;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub(crate) enum Error {
+    #[snafu(display("Failed to set up logger: {}", source))]
+    Logger { source: simplelog::TermLogError },
+
+    #[snafu(display("Failed to read config file {}: {}", path.display(), source))]
+    ConfigRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to parse config file {}: {}", path.display(), source))]
+    ConfigParse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[snafu(display("Failed to read events from {}: {}", path.display(), source))]
+    EventsRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to read state file {}: {}", path.display(), source))]
+    StateRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to parse state file {}: {}", path.display(), source))]
+    StateParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Failed to write state file {}: {}", path.display(), source))]
+    StateWrite {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to run '{}': {}", command, source))]
+    UpdataStart {
+        command: String,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("'{}' failed: {}", command, stderr))]
+    UpdataFailure { command: String, stderr: String },
+}
+
+pub(crate) type Result<T> = std::result::Result<T, Error>;
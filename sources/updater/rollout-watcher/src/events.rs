@@ -0,0 +1,205 @@
+/*!
+This module reads the events updog sends to webhooks, as stored by the endpoint, and counts how
+many hosts succeeded or failed with each update.  A host that activated an update counts as a
+success; a host whose update failed, or that rolled back after rebooting into it, counts as a
+failure.  Other events, and events that don't say which update they're about, aren't counted.
+*/
+
+use crate::config::Policy;
+use crate::error::{self, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// The parts of a webhook event the watcher needs.  Events from hosts running an updog that's
+/// older or newer than ours may be missing fields or have more, so neither is an error.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Event {
+    event: String,
+    time: DateTime<Utc>,
+    variant: String,
+    #[serde(default)]
+    arch: Option<String>,
+    #[serde(default)]
+    update_version: Option<Version>,
+}
+
+/// An update in the manifest, as updata pause-rollout names it.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub(crate) struct UpdateKey {
+    pub(crate) variant: String,
+    pub(crate) arch: String,
+    pub(crate) version: Version,
+}
+
+impl fmt::Display for UpdateKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}-{}", self.arch, self.variant, self.version)
+    }
+}
+
+/// How many hosts succeeded and failed with an update.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct Tally {
+    pub(crate) succeeded: u64,
+    pub(crate) failed: u64,
+}
+
+impl Tally {
+    pub(crate) fn hosts(self) -> u64 {
+        self.succeeded + self.failed
+    }
+
+    /// The share of hosts that failed, as a percentage.
+    pub(crate) fn failure_percentage(self) -> f64 {
+        if self.hosts() == 0 {
+            return 0.0;
+        }
+        self.failed as f64 * 100.0 / self.hosts() as f64
+    }
+}
+
+/// Reads the events in `path`, one JSON object per line.  Lines that can't be parsed are skipped
+/// with a warning, so one bad request stored by the endpoint doesn't stop the watcher.
+pub(crate) fn load(path: &Path) -> Result<Vec<Event>> {
+    let data = fs::read_to_string(path).context(error::EventsRead { path })?;
+    let mut events = Vec::new();
+    for (i, line) in data.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(event) => events.push(event),
+            Err(e) => warn!("Skipping line {} of {}: {}", i + 1, path.display(), e),
+        }
+    }
+    Ok(events)
+}
+
+/// Counts the hosts that succeeded and failed with each update, from events since the start of
+/// the policy's window.  `since` gives a later start for some updates, like ones already paused.
+pub(crate) fn tally(
+    events: &[Event],
+    policy: &Policy,
+    now: DateTime<Utc>,
+    since: impl Fn(&UpdateKey) -> Option<DateTime<Utc>>,
+) -> BTreeMap<UpdateKey, Tally> {
+    let window_start = now - Duration::hours(policy.window_hours);
+    let mut tallies: BTreeMap<UpdateKey, Tally> = BTreeMap::new();
+    for event in events {
+        let (arch, version) = match (&event.arch, &event.update_version) {
+            (Some(arch), Some(version)) => (arch, version),
+            _ => continue,
+        };
+        let key = UpdateKey {
+            variant: event.variant.clone(),
+            arch: arch.clone(),
+            version: version.clone(),
+        };
+        let start = since(&key).map_or(window_start, |since| since.max(window_start));
+        if event.time < start {
+            continue;
+        }
+        match event.event.as_str() {
+            "update-activated" => tallies.entry(key).or_default().succeeded += 1,
+            "update-failed" | "update-rolled-back" => tallies.entry(key).or_default().failed += 1,
+            _ => {}
+        }
+    }
+    tallies
+}
+
+/// The updates whose tally breaks the policy.
+pub(crate) fn breaches(
+    tallies: &BTreeMap<UpdateKey, Tally>,
+    policy: &Policy,
+) -> Vec<(UpdateKey, Tally)> {
+    tallies
+        .iter()
+        .filter(|(_, tally)| {
+            tally.hosts() >= policy.min_hosts
+                && tally.failure_percentage() > policy.max_failure_percentage
+        })
+        .map(|(key, tally)| (key.clone(), *tally))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn event(kind: &str, hour: u32, version: &str) -> String {
+        format!(
+            r#"{{"event":"{}","time":"2020-03-26T{:02}:00:00Z","variant":"aws-k8s-1.15","arch":"x86_64","version":"0.3.3","update-version":"{}"}}"#,
+            kind, hour, version
+        )
+    }
+
+    #[test]
+    fn count_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let mut lines = vec![
+            event("update-activated", 10, "0.3.4"),
+            event("update-activated", 11, "0.3.4"),
+            event("update-failed", 12, "0.3.4"),
+            event("update-rolled-back", 13, "0.3.4"),
+            event("update-staged", 13, "0.3.4"),
+            event("update-failed", 1, "0.3.4"),
+            event("update-failed", 12, "0.3.5"),
+            // From an updog that doesn't send the architecture.
+            r#"{"event":"update-failed","time":"2020-03-26T12:00:00Z","variant":"aws-k8s-1.15","version":"0.3.3","update-version":"0.3.4"}"#.to_string(),
+            "not json".to_string(),
+        ];
+        lines.push(String::new());
+        fs::write(&path, lines.join("\n")).unwrap();
+        let events = load(&path).unwrap();
+        assert_eq!(events.len(), 8);
+
+        let policy = Policy {
+            max_failure_percentage: 40.0,
+            min_hosts: 2,
+            window_hours: 12,
+        };
+        let now = Utc.ymd(2020, 3, 26).and_hms(14, 0, 0);
+        let tallies = tally(&events, &policy, now, |_| None);
+        let key = |version: &str| UpdateKey {
+            variant: "aws-k8s-1.15".to_string(),
+            arch: "x86_64".to_string(),
+            version: Version::parse(version).unwrap(),
+        };
+        // The failure at 01:00 is outside the window.
+        assert_eq!(
+            tallies[&key("0.3.4")],
+            Tally {
+                succeeded: 2,
+                failed: 2
+            }
+        );
+        // 0.3.4 failed on half its hosts; 0.3.5 failed everywhere, but on too few hosts to tell.
+        let breached: Vec<_> = breaches(&tallies, &policy)
+            .into_iter()
+            .map(|(key, _)| key.to_string())
+            .collect();
+        assert_eq!(breached, vec!["x86_64-aws-k8s-1.15-0.3.4"]);
+
+        // Events from before the update was paused don't count again.
+        let paused = Utc.ymd(2020, 3, 26).and_hms(11, 30, 0);
+        let tallies = tally(&events, &policy, now, |_| Some(paused));
+        assert_eq!(
+            tallies[&key("0.3.4")],
+            Tally {
+                succeeded: 0,
+                failed: 2
+            }
+        );
+    }
+}
@@ -0,0 +1,200 @@
+/*!
+# Introduction
+
+rollout-watcher pauses the rollout of an update that's failing on too many hosts, so a bad
+release stops spreading before an operator has to notice it.
+
+It reads the events updog sends to webhooks, as stored by the webhook endpoint, and counts the
+hosts that activated each update and the hosts that failed to take it or rolled back from it.
+Once enough hosts have tried an update, and too many of them failed, it runs `updata
+pause-rollout` for that update.  The policy, and where the events and manifest are, come from a
+config file.
+
+Events from before the watcher paused an update aren't counted again, so after an operator
+resumes a rollout, it's only paused again if it keeps failing.
+*/
+
+#![deny(rust_2018_idioms)]
+
+mod config;
+mod error;
+mod events;
+mod state;
+mod updata;
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::state::State;
+use crate::updata::Updata;
+use chrono::Utc;
+use log::{debug, info, warn};
+use simplelog::{Config as LogConfig, LevelFilter, TermLogger, TerminalMode};
+use snafu::ResultExt;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+/// Stores user-supplied arguments.
+struct Args {
+    config: PathBuf,
+    interval: Option<Duration>,
+    dry_run: bool,
+    log_level: LevelFilter,
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+fn usage() -> ! {
+    let program_name = std::env::args()
+        .next()
+        .unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            --config PATH
+            [ --interval SECONDS ]
+            [ --dry-run ]
+            [ --log-level trace|debug|info|warn|error ]
+
+    Without --interval, the events are checked once.  With --dry-run, the
+    rollouts that would be paused are logged, but nothing is changed.",
+        program_name
+    );
+    process::exit(2);
+}
+
+/// Parses user arguments into an Args structure.
+fn parse_args(args: std::env::Args) -> Args {
+    let mut config = None;
+    let mut interval = None;
+    let mut dry_run = false;
+    let mut log_level = None;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--config" => {
+                config =
+                    Some(PathBuf::from(iter.next().unwrap_or_else(|| {
+                        usage_msg("Did not give argument to --config")
+                    })));
+            }
+
+            "--interval" => {
+                let value = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --interval"));
+                let seconds = u64::from_str(&value).unwrap_or_else(|_| {
+                    usage_msg(format!("Invalid number of seconds '{}'", value))
+                });
+                interval = Some(Duration::from_secs(seconds));
+            }
+
+            "--dry-run" => dry_run = true,
+
+            "--log-level" => {
+                let log_level_str = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --log-level"));
+                log_level = Some(LevelFilter::from_str(&log_level_str).unwrap_or_else(|_| {
+                    usage_msg(format!("Invalid log level '{}'", log_level_str))
+                }));
+            }
+
+            _ => usage(),
+        }
+    }
+
+    Args {
+        config: config.unwrap_or_else(|| usage_msg("--config is required")),
+        interval,
+        dry_run,
+        log_level: log_level.unwrap_or(LevelFilter::Info),
+    }
+}
+
+/// Checks the events once, pausing the rollouts that break the policy.  Returns how many were
+/// paused, or would have been in a dry run.
+fn check(config: &Config, updata: &Updata, dry_run: bool) -> Result<usize> {
+    let mut state = State::load(&config.state)?;
+    let events = events::load(&config.events)?;
+    let now = Utc::now();
+    let tallies = events::tally(&events, &config.policy, now, |update| {
+        state.paused_at(update)
+    });
+    for (update, tally) in &tallies {
+        debug!(
+            "{}: {} of {} hosts failed",
+            update,
+            tally.failed,
+            tally.hosts()
+        );
+    }
+
+    let breaches = events::breaches(&tallies, &config.policy);
+    for (update, tally) in &breaches {
+        let reason = format!(
+            "{} of {} hosts failed ({:.1}%)",
+            tally.failed,
+            tally.hosts(),
+            tally.failure_percentage()
+        );
+        if dry_run {
+            info!("Would pause the rollout of {}: {}", update, reason);
+            continue;
+        }
+        updata.pause_rollout(&config.manifest, update)?;
+        warn!("Paused the rollout of {}: {}", update, reason);
+        // Saved after each pause, so a later failure doesn't forget the ones already done.
+        state.set_paused(update, now);
+        state.write(&config.state)?;
+    }
+    Ok(breaches.len())
+}
+
+fn run() -> Result<()> {
+    let args = parse_args(std::env::args());
+
+    // TerminalMode::Mixed will send errors to stderr and anything less to stdout.
+    TermLogger::init(args.log_level, LogConfig::default(), TerminalMode::Mixed)
+        .context(error::Logger)?;
+
+    let config = Config::load(&args.config)?;
+    if config.updata_command().is_empty() {
+        usage_msg("updata in the config file must not be empty");
+    }
+    let updata = Updata::new(config.updata_command());
+
+    let interval = match args.interval {
+        Some(interval) => interval,
+        None => {
+            if check(&config, &updata, args.dry_run)? == 0 {
+                info!("No rollouts need pausing");
+            }
+            return Ok(());
+        }
+    };
+    // Running as a service, a check that fails is retried at the next interval rather than
+    // stopping the watcher.
+    loop {
+        if let Err(e) = check(&config, &updata, args.dry_run) {
+            warn!("Failed to check rollouts: {}", e);
+        }
+        thread::sleep(interval);
+    }
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,93 @@
+/*!
+This module keeps track of the rollouts the watcher paused, and when.  Events from before an
+update was paused aren't counted again, so once an operator has looked into a failure and resumed
+the rollout, the watcher only pauses it again if it keeps failing.
+*/
+
+use crate::error::{self, Result};
+use crate::events::UpdateKey;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Paused {
+    #[serde(flatten)]
+    update: UpdateKey,
+    time: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct State {
+    paused: Vec<Paused>,
+}
+
+impl State {
+    /// Loads the state file, or an empty state if there isn't one yet.
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = fs::read(path).context(error::StateRead { path })?;
+        serde_json::from_slice(&data).context(error::StateParse { path })
+    }
+
+    /// Writes the state file, through a temporary file so a crash can't leave half of it.
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self).expect("state serialization can't fail");
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data).context(error::StateWrite { path: &tmp })?;
+        fs::rename(&tmp, path).context(error::StateWrite { path })
+    }
+
+    /// When the watcher last paused the update, if it ever did.
+    pub(crate) fn paused_at(&self, update: &UpdateKey) -> Option<DateTime<Utc>> {
+        self.paused
+            .iter()
+            .find(|paused| paused.update == *update)
+            .map(|paused| paused.time)
+    }
+
+    pub(crate) fn set_paused(&mut self, update: &UpdateKey, time: DateTime<Utc>) {
+        match self
+            .paused
+            .iter_mut()
+            .find(|paused| paused.update == *update)
+        {
+            Some(paused) => paused.time = time,
+            None => self.paused.push(Paused {
+                update: update.clone(),
+                time,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use semver::Version;
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let mut state = State::load(&path).unwrap();
+        let update = UpdateKey {
+            variant: "aws-k8s-1.15".to_string(),
+            arch: "x86_64".to_string(),
+            version: Version::new(0, 3, 4),
+        };
+        assert_eq!(state.paused_at(&update), None);
+
+        let now = Utc::now();
+        state.set_paused(&update, now - chrono::Duration::hours(1));
+        state.set_paused(&update, now);
+        state.write(&path).unwrap();
+        let state = State::load(&path).unwrap();
+        assert_eq!(state.paused.len(), 1);
+        assert_eq!(state.paused_at(&update), Some(now));
+    }
+}
@@ -0,0 +1,57 @@
+/*!
+This module runs updata to pause rollouts, so the manifest is changed the same way an operator
+would change it, wherever it's kept.
+*/
+
+use crate::error::{self, Result};
+use crate::events::UpdateKey;
+use snafu::{ensure, ResultExt};
+use std::process::Command;
+
+/// Runs updata through the given command, which may include a wrapper or leading arguments.
+pub(crate) struct Updata {
+    command: Vec<String>,
+}
+
+impl Updata {
+    pub(crate) fn new(command: Vec<String>) -> Self {
+        Self { command }
+    }
+
+    /// Pauses the rollout of `update` in `manifest`.  Pausing an update that's already paused
+    /// keeps its original pause time, so this is safe to repeat.
+    pub(crate) fn pause_rollout(&self, manifest: &str, update: &UpdateKey) -> Result<()> {
+        let version = update.version.to_string();
+        let args = [
+            "pause-rollout",
+            manifest,
+            "--variant",
+            &update.variant,
+            "--arch",
+            &update.arch,
+            "--version",
+            &version,
+        ];
+        let command = self.describe(&args);
+        let (program, wrapper_args) = self.command.split_first().expect("empty updata command");
+        let output = Command::new(program)
+            .args(wrapper_args)
+            .args(args)
+            .output()
+            .context(error::UpdataStart { command: &command })?;
+        ensure!(
+            output.status.success(),
+            error::UpdataFailure {
+                command,
+                stderr: String::from_utf8_lossy(&output.stderr).trim(),
+            }
+        );
+        Ok(())
+    }
+
+    fn describe(&self, args: &[&str]) -> String {
+        let mut words: Vec<&str> = self.command.iter().map(String::as_str).collect();
+        words.extend(args);
+        words.join(" ")
+    }
+}
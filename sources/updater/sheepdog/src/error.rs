@@ -0,0 +1,84 @@
+use snafu::Snafu;
+use std::path::PathBuf;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub(crate) enum Error {
+    #[snafu(display("Failed to set up logger: {}", source))]
+    Logger { source: simplelog::TermLogError },
+
+    #[snafu(display("updog {} failed: {}", command, source))]
+    Updog {
+        command: updog::Command,
+        source: updog::Error,
+    },
+
+    #[snafu(display("Failed to run '{}': {}", command, source))]
+    RebootStart {
+        command: String,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("'{}' failed: {}", command, status))]
+    RebootFailure {
+        command: String,
+        status: std::process::ExitStatus,
+    },
+
+    #[snafu(display("Environment variable {} must be set: {}", name, source))]
+    MissingEnv {
+        name: &'static str,
+        source: std::env::VarError,
+    },
+
+    #[snafu(display("Failed to read '{}': {}", path.display(), source))]
+    ReadFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Invalid Kubernetes API URL '{}': {}", url, source))]
+    ApiUrl {
+        url: String,
+        source: url::ParseError,
+    },
+
+    #[snafu(display("Invalid cluster CA certificate: {}", source))]
+    ApiCertificate { source: reqwest::Error },
+
+    #[snafu(display("Failed to build Kubernetes client: {}", source))]
+    ApiClient { source: reqwest::Error },
+
+    #[snafu(display("Error sending {} to {}: {}", method, url, source))]
+    ApiRequest {
+        method: String,
+        url: String,
+        source: reqwest::Error,
+    },
+
+    #[snafu(display("Error {} when sending {} to {}: {}", code, method, url, body))]
+    ApiResponse {
+        method: String,
+        url: String,
+        code: reqwest::StatusCode,
+        body: String,
+    },
+
+    #[snafu(display("Failed to parse response from {}: {}", url, source))]
+    ApiResponseJson {
+        url: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display(
+        "Timed out after {}s waiting for {} pods to be evicted",
+        seconds,
+        remaining
+    ))]
+    DrainTimeout { seconds: u64, remaining: usize },
+
+    #[snafu(display("Update lock is held by node '{}'", holder))]
+    LockHeld { holder: String },
+}
+
+pub(crate) type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,599 @@
+/*!
+This module prepares a Kubernetes node for a reboot by cordoning and draining it, and undoes that
+once the node is back.
+
+To make sure only one node in the cluster reboots at a time, nodes take a `Lease` before they're
+cordoned, and release it once they've come back from the reboot.  If a node never comes back, its
+lease expires after `LEASE_DURATION` and another node can take it over.
+
+We talk to the API server directly, using the service account that Kubernetes mounts into our
+pod, and expect the node name in the `NODE_NAME` environment variable.  The RBAC rules we need
+are in the manifest that ships alongside sheepdog.
+*/
+
+use crate::error::{self, Result};
+use crate::orchestrator::Orchestrator;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use log::{debug, info, warn};
+use reqwest::blocking::Client;
+use reqwest::{Certificate, Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use snafu::{ensure, ResultExt};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+use url::Url;
+
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+/// The annotation we put on nodes we cordoned, so we know to uncordon them after the reboot but
+/// leave alone nodes that someone else cordoned.
+const CORDONED_ANNOTATION: &str = "sheepdog.bottlerocket.aws/cordoned";
+
+/// The name of the lease, in our own namespace, that a node holds while it reboots.
+const LEASE_NAME: &str = "sheepdog";
+
+/// How long a node may hold the lease before other nodes assume it isn't coming back.
+const LEASE_DURATION: i64 = 3600;
+
+/// How long to wait between checks on pods we're evicting.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+pub(crate) struct Kubernetes {
+    client: Client,
+    base_url: Url,
+    token: String,
+    namespace: String,
+    node: String,
+    drain_timeout: Duration,
+}
+
+impl Kubernetes {
+    /// Builds a client from the environment Kubernetes gives pods.
+    pub(crate) fn from_env(drain_timeout: Duration) -> Result<Self> {
+        let host = getenv("KUBERNETES_SERVICE_HOST")?;
+        let port = getenv("KUBERNETES_SERVICE_PORT")?;
+        let node = getenv("NODE_NAME")?;
+
+        let url = if host.contains(':') {
+            format!("https://[{}]:{}/", host, port)
+        } else {
+            format!("https://{}:{}/", host, port)
+        };
+        let base_url = Url::parse(&url).context(error::ApiUrl { url })?;
+
+        let ca = read_service_account_file("ca.crt")?;
+        let ca = Certificate::from_pem(ca.as_bytes()).context(error::ApiCertificate)?;
+        let client = Client::builder()
+            .add_root_certificate(ca)
+            .build()
+            .context(error::ApiClient)?;
+
+        Ok(Self {
+            client,
+            base_url,
+            token: read_service_account_file("token")?.trim().to_string(),
+            namespace: read_service_account_file("namespace")?.trim().to_string(),
+            node,
+            drain_timeout,
+        })
+    }
+
+    /// Sends a request to the API server, returning the response code and body.  `body` is sent
+    /// with the given content type, since patches need their own.
+    fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<(&'static str, &Value)>,
+    ) -> Result<(StatusCode, String)> {
+        let url = self
+            .base_url
+            .join(path)
+            .context(error::ApiUrl { url: path })?;
+        let mut request = self
+            .client
+            .request(method.clone(), url.clone())
+            .bearer_auth(&self.token);
+        if let Some((content_type, body)) = body {
+            request = request
+                .header(reqwest::header::CONTENT_TYPE, content_type)
+                .body(body.to_string());
+        }
+        let context = error::ApiRequest {
+            method: method.as_str(),
+            url: url.as_str(),
+        };
+        let response = request.send().context(context)?;
+        let code = response.status();
+        let body = response.text().context(context)?;
+        debug!("{} {} returned {}", method, url, code);
+        Ok((code, body))
+    }
+
+    /// Sends a request that we expect to succeed, returning the parsed response body.
+    fn expect<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<(&'static str, &Value)>,
+    ) -> Result<T> {
+        let (code, body) = self.request(method.clone(), path, body)?;
+        ensure!(
+            code.is_success(),
+            error::ApiResponse {
+                method: method.as_str(),
+                url: path,
+                code,
+                body,
+            }
+        );
+        serde_json::from_str(&body).context(error::ApiResponseJson { url: path })
+    }
+
+    fn node_path(&self) -> String {
+        format!("api/v1/nodes/{}", self.node)
+    }
+
+    fn lease_path(&self) -> String {
+        format!(
+            "apis/coordination.k8s.io/v1/namespaces/{}/leases",
+            self.namespace
+        )
+    }
+
+    /// Takes the reboot lease, failing with `LockHeld` if another node has it.
+    fn acquire_lease(&self) -> Result<()> {
+        let now = micro_time(Utc::now());
+        let mut lease = json!({
+            "apiVersion": "coordination.k8s.io/v1",
+            "kind": "Lease",
+            "metadata": { "name": LEASE_NAME, "namespace": self.namespace },
+            "spec": {
+                "holderIdentity": self.node,
+                "leaseDurationSeconds": LEASE_DURATION,
+                "acquireTime": now,
+                "renewTime": now,
+            },
+        });
+
+        let (code, body) = self.request(
+            Method::POST,
+            &self.lease_path(),
+            Some(("application/json", &lease)),
+        )?;
+        if code.is_success() {
+            return Ok(());
+        }
+        ensure!(
+            code == StatusCode::CONFLICT,
+            error::ApiResponse {
+                method: "POST",
+                url: self.lease_path(),
+                code,
+                body,
+            }
+        );
+
+        // Someone has the lease already; see whether it's us from before a failed attempt, or a
+        // node that's been gone too long.
+        let path = format!("{}/{}", self.lease_path(), LEASE_NAME);
+        let existing: Lease = self.expect(Method::GET, &path, None)?;
+        let holder = existing.spec.holder_identity.clone().unwrap_or_default();
+        if holder != self.node {
+            ensure!(existing.expired(Utc::now()), error::LockHeld { holder });
+            info!("Taking over lease from '{}', which has expired", holder);
+        }
+
+        // Replacing the lease with its last-seen resourceVersion means we'll get a conflict
+        // rather than stealing it, if another node took it over first.
+        lease["metadata"]["resourceVersion"] = json!(existing.metadata.resource_version);
+        let (code, body) = self.request(Method::PUT, &path, Some(("application/json", &lease)))?;
+        if code == StatusCode::CONFLICT {
+            return error::LockHeld {
+                holder: "(unknown)",
+            }
+            .fail();
+        }
+        ensure!(
+            code.is_success(),
+            error::ApiResponse {
+                method: "PUT",
+                url: path,
+                code,
+                body,
+            }
+        );
+        Ok(())
+    }
+
+    /// Releases the reboot lease if we hold it.
+    fn release_lease(&self) -> Result<()> {
+        let path = format!("{}/{}", self.lease_path(), LEASE_NAME);
+        let (code, body) = self.request(Method::GET, &path, None)?;
+        if code == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        ensure!(
+            code.is_success(),
+            error::ApiResponse {
+                method: "GET",
+                url: &path,
+                code,
+                body,
+            }
+        );
+        let lease: Lease =
+            serde_json::from_str(&body).context(error::ApiResponseJson { url: &path })?;
+        if lease.spec.holder_identity.as_ref() != Some(&self.node) {
+            return Ok(());
+        }
+
+        info!("Releasing update lease");
+        // Only delete the lease we looked at, in case it expired and another node took it.
+        let preconditions = json!({
+            "apiVersion": "v1",
+            "kind": "DeleteOptions",
+            "preconditions": { "resourceVersion": lease.metadata.resource_version },
+        });
+        let (code, body) = self.request(
+            Method::DELETE,
+            &path,
+            Some(("application/json", &preconditions)),
+        )?;
+        ensure!(
+            code.is_success() || code == StatusCode::NOT_FOUND || code == StatusCode::CONFLICT,
+            error::ApiResponse {
+                method: "DELETE",
+                url: path,
+                code,
+                body,
+            }
+        );
+        Ok(())
+    }
+
+    /// Marks the node unschedulable.  If it already was, someone else cordoned it and we leave it
+    /// for them to uncordon.
+    fn cordon(&self) -> Result<()> {
+        let node: Node = self.expect(Method::GET, &self.node_path(), None)?;
+        if node.spec.unschedulable {
+            info!("Node is already cordoned");
+            return Ok(());
+        }
+        info!("Cordoning node");
+        let patch = json!({
+            "metadata": { "annotations": { CORDONED_ANNOTATION: "true" } },
+            "spec": { "unschedulable": true },
+        });
+        self.expect::<Value>(
+            Method::PATCH,
+            &self.node_path(),
+            Some(("application/merge-patch+json", &patch)),
+        )?;
+        Ok(())
+    }
+
+    /// Marks the node schedulable again, if we cordoned it.
+    fn uncordon(&self) -> Result<()> {
+        let node: Node = self.expect(Method::GET, &self.node_path(), None)?;
+        if !node.metadata.annotations.contains_key(CORDONED_ANNOTATION) {
+            return Ok(());
+        }
+        info!("Uncordoning node");
+        // A null removes the annotation in a merge patch.
+        let patch = json!({
+            "metadata": { "annotations": { CORDONED_ANNOTATION: null } },
+            "spec": { "unschedulable": false },
+        });
+        self.expect::<Value>(
+            Method::PATCH,
+            &self.node_path(),
+            Some(("application/merge-patch+json", &patch)),
+        )?;
+        Ok(())
+    }
+
+    /// Evicts pods from the node, respecting disruption budgets, until there are none left that
+    /// we're expected to move.
+    fn drain(&self) -> Result<()> {
+        let path = format!("api/v1/pods?fieldSelector=spec.nodeName%3D{}", self.node);
+        let start = Instant::now();
+        loop {
+            let pods: PodList = self.expect(Method::GET, &path, None)?;
+            let pods: Vec<&Pod> = pods.items.iter().filter(|p| p.needs_eviction()).collect();
+            if pods.is_empty() {
+                info!("Node is drained");
+                return Ok(());
+            }
+            ensure!(
+                start.elapsed() < self.drain_timeout,
+                error::DrainTimeout {
+                    seconds: self.drain_timeout.as_secs(),
+                    remaining: pods.len(),
+                }
+            );
+
+            // Pods that are already terminating just need time to go away.
+            for pod in pods
+                .iter()
+                .filter(|p| p.metadata.deletion_timestamp.is_none())
+            {
+                self.evict(pod)?;
+            }
+            thread::sleep(DRAIN_POLL_INTERVAL);
+        }
+    }
+
+    fn evict(&self, pod: &Pod) -> Result<()> {
+        let name = &pod.metadata.name;
+        let namespace = &pod.metadata.namespace;
+        let path = format!("api/v1/namespaces/{}/pods/{}/eviction", namespace, name);
+        let eviction = json!({
+            "apiVersion": "policy/v1beta1",
+            "kind": "Eviction",
+            "metadata": { "name": name, "namespace": namespace },
+        });
+        let (code, body) =
+            self.request(Method::POST, &path, Some(("application/json", &eviction)))?;
+        match code {
+            // The eviction would break a disruption budget; try again next time around.
+            StatusCode::TOO_MANY_REQUESTS => {
+                warn!("Can't evict {}/{} yet: {}", namespace, name, body.trim());
+                Ok(())
+            }
+            // The pod is already gone.
+            StatusCode::NOT_FOUND => Ok(()),
+            code if code.is_success() => {
+                info!("Evicted {}/{}", namespace, name);
+                Ok(())
+            }
+            code => error::ApiResponse {
+                method: "POST",
+                url: path,
+                code,
+                body,
+            }
+            .fail(),
+        }
+    }
+}
+
+impl Orchestrator for Kubernetes {
+    fn prepare(&self) -> Result<()> {
+        self.acquire_lease()?;
+        self.cordon()?;
+        self.drain()
+    }
+
+    fn resume(&self) -> Result<()> {
+        self.uncordon()?;
+        self.release_lease()
+    }
+}
+
+fn getenv(name: &'static str) -> Result<String> {
+    env::var(name).context(error::MissingEnv { name })
+}
+
+fn read_service_account_file(name: &str) -> Result<String> {
+    let path = std::path::Path::new(SERVICE_ACCOUNT_DIR).join(name);
+    fs::read_to_string(&path).context(error::ReadFile { path })
+}
+
+/// Formats a time the way Kubernetes expects for `MicroTime` fields.
+fn micro_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string()
+}
+
+// Just the parts of the Kubernetes objects we use.
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ObjectMeta {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    namespace: String,
+    #[serde(default)]
+    resource_version: Option<String>,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+    #[serde(default)]
+    owner_references: Vec<OwnerReference>,
+    #[serde(default)]
+    deletion_timestamp: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OwnerReference {
+    kind: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Node {
+    #[serde(default)]
+    metadata: ObjectMeta,
+    #[serde(default)]
+    spec: NodeSpec,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct NodeSpec {
+    #[serde(default)]
+    unschedulable: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct PodList {
+    items: Vec<Pod>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Pod {
+    #[serde(default)]
+    metadata: ObjectMeta,
+    #[serde(default)]
+    status: PodStatus,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PodStatus {
+    #[serde(default)]
+    phase: Option<String>,
+}
+
+impl Pod {
+    /// Whether we need to move the pod before rebooting.  Like `kubectl drain`, we leave alone
+    /// pods from DaemonSets, which would just be rescheduled to the node, static pods, which the
+    /// API server can't remove, and pods that have already finished.
+    fn needs_eviction(&self) -> bool {
+        let daemonset = self
+            .metadata
+            .owner_references
+            .iter()
+            .any(|owner| owner.kind == "DaemonSet");
+        let mirror = self
+            .metadata
+            .annotations
+            .contains_key("kubernetes.io/config.mirror");
+        let finished = self
+            .status
+            .phase
+            .as_deref()
+            .filter(|phase| ["Succeeded", "Failed"].contains(phase))
+            .is_some();
+        !(daemonset || mirror || finished)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Lease {
+    #[serde(default)]
+    metadata: ObjectMeta,
+    #[serde(default)]
+    spec: LeaseSpec,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LeaseSpec {
+    #[serde(default)]
+    holder_identity: Option<String>,
+    #[serde(default)]
+    lease_duration_seconds: Option<i64>,
+    #[serde(default)]
+    acquire_time: Option<DateTime<Utc>>,
+    #[serde(default)]
+    renew_time: Option<DateTime<Utc>>,
+}
+
+impl Lease {
+    /// Whether the holder of the lease has had it for longer than it asked for.  A lease that's
+    /// missing the fields we need is treated as expired, so it can't block updates forever.
+    fn expired(&self, now: DateTime<Utc>) -> bool {
+        let renewed = self.spec.renew_time.or(self.spec.acquire_time);
+        match (renewed, self.spec.lease_duration_seconds) {
+            (Some(renewed), Some(seconds)) => renewed + ChronoDuration::seconds(seconds) < now,
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pod(json: Value) -> Pod {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn evict_regular_pod() {
+        let pod = pod(json!({
+            "metadata": {
+                "name": "web-1",
+                "namespace": "default",
+                "ownerReferences": [{ "kind": "ReplicaSet", "name": "web" }],
+            },
+            "status": { "phase": "Running" },
+        }));
+        assert!(pod.needs_eviction());
+    }
+
+    #[test]
+    fn skip_daemonset_pod() {
+        let pod = pod(json!({
+            "metadata": {
+                "name": "aws-node-x",
+                "namespace": "kube-system",
+                "ownerReferences": [{ "kind": "DaemonSet", "name": "aws-node" }],
+            },
+            "status": { "phase": "Running" },
+        }));
+        assert!(!pod.needs_eviction());
+    }
+
+    #[test]
+    fn skip_mirror_pod() {
+        let pod = pod(json!({
+            "metadata": {
+                "name": "static",
+                "namespace": "kube-system",
+                "annotations": { "kubernetes.io/config.mirror": "abc" },
+            },
+        }));
+        assert!(!pod.needs_eviction());
+    }
+
+    #[test]
+    fn skip_finished_pod() {
+        for phase in &["Succeeded", "Failed"] {
+            let pod = pod(json!({
+                "metadata": { "name": "job", "namespace": "default" },
+                "status": { "phase": phase },
+            }));
+            assert!(!pod.needs_eviction());
+        }
+    }
+
+    fn lease(json: Value) -> Lease {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn lease_expiry() {
+        let lease = lease(json!({
+            "metadata": { "name": LEASE_NAME, "resourceVersion": "42" },
+            "spec": {
+                "holderIdentity": "node-a",
+                "leaseDurationSeconds": 3600,
+                "acquireTime": "2020-06-01T11:00:00.000000Z",
+                "renewTime": "2020-06-01T12:00:00.000000Z",
+            },
+        }));
+        let before: DateTime<Utc> = "2020-06-01T12:59:59Z".parse().unwrap();
+        let after: DateTime<Utc> = "2020-06-01T13:00:01Z".parse().unwrap();
+        assert!(!lease.expired(before));
+        assert!(lease.expired(after));
+    }
+
+    #[test]
+    fn incomplete_lease_expired() {
+        let lease = lease(json!({
+            "metadata": { "name": LEASE_NAME },
+            "spec": { "holderIdentity": "node-a" },
+        }));
+        assert!(lease.expired(Utc::now()));
+    }
+
+    #[test]
+    fn micro_time_format() {
+        let time: DateTime<Utc> = "2020-06-01T12:00:00.123456789Z".parse().unwrap();
+        assert_eq!(micro_time(time), "2020-06-01T12:00:00.123456Z");
+    }
+}
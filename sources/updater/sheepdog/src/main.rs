@@ -0,0 +1,248 @@
+/*!
+# Introduction
+
+sheepdog keeps a host up to date without surprising the orchestrator that runs work on it.
+
+It periodically has updog, which it runs as a library against the host's files, write any
+available update to the inactive partition.  Writing the image doesn't disturb running workloads,
+so updog's wave schedule alone decides when that happens.  Once an update is written, sheepdog
+asks the orchestrator to get the host ready to reboot, and only then has updog flip the partition
+flags, and reboots the host.  When sheepdog starts again after the
+reboot, it tells the orchestrator the host is back.
+
+The supported orchestrators are:
+
+* `none`: the default; the host reboots as soon as an update is written.
+* `kubernetes`: sheepdog runs in a DaemonSet pod.  Nodes take turns through a `Lease`, and each
+  node is cordoned and drained before it reboots, then uncordoned once it's back.
+
+If preparing the host or applying the update fails, sheepdog undoes what it can and tries again
+at the next check.
+*/
+
+#![deny(rust_2018_idioms)]
+
+mod error;
+mod kubernetes;
+mod orchestrator;
+mod updog;
+
+use crate::error::Result;
+use crate::kubernetes::Kubernetes;
+use crate::orchestrator::{NoOrchestrator, Orchestrator};
+use crate::updog::{UpdateStatus, Updog};
+use log::{debug, info, warn};
+use simplelog::{Config as LogConfig, LevelFilter, TermLogger, TerminalMode};
+use snafu::ResultExt;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_CHECK_INTERVAL: u64 = 6 * 60 * 60;
+const DEFAULT_DRAIN_TIMEOUT: u64 = 10 * 60;
+const DEFAULT_HOST_ROOT: &str = "/";
+const DEFAULT_REBOOT: &str = "shutdown -r";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OrchestratorKind {
+    None,
+    Kubernetes,
+}
+
+/// Stores user-supplied arguments.
+struct Args {
+    orchestrator: OrchestratorKind,
+    check_interval: Duration,
+    drain_timeout: Duration,
+    host_root: PathBuf,
+    reboot: Vec<String>,
+    log_level: LevelFilter,
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+fn usage() -> ! {
+    let program_name = std::env::args()
+        .next()
+        .unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            [ --orchestrator none|kubernetes ]
+            [ --check-interval SECONDS ]
+            [ --drain-timeout SECONDS ]
+            [ --host-root PATH ]
+            [ --reboot COMMAND ]
+            [ --log-level trace|debug|info|warn|error ]
+
+    Orchestrator default: none
+    Check interval default: {}
+    Drain timeout default: {}
+    Host root default: {}
+    Reboot default: {}
+
+    The host root is where the host's root filesystem is, like '/proc/1/root' when
+    running in a container that shares the host's PID namespace.  The reboot command
+    is split on whitespace, so it can include a wrapper like 'nsenter -t 1 -a'.",
+        program_name,
+        DEFAULT_CHECK_INTERVAL,
+        DEFAULT_DRAIN_TIMEOUT,
+        DEFAULT_HOST_ROOT,
+        DEFAULT_REBOOT
+    );
+    process::exit(2);
+}
+
+/// Parses a number of seconds for the given argument.
+fn parse_seconds(arg: &str, value: Option<String>) -> Duration {
+    let value = value.unwrap_or_else(|| usage_msg(format!("Did not give argument to {}", arg)));
+    let seconds = u64::from_str(&value)
+        .unwrap_or_else(|_| usage_msg(format!("Invalid number of seconds '{}'", value)));
+    Duration::from_secs(seconds)
+}
+
+/// Parses user arguments into an Args structure.
+fn parse_args(args: std::env::Args) -> Args {
+    let mut orchestrator = None;
+    let mut check_interval = None;
+    let mut drain_timeout = None;
+    let mut host_root = None;
+    let mut reboot = None;
+    let mut log_level = None;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--orchestrator" => {
+                let kind = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --orchestrator"));
+                orchestrator = Some(match kind.as_ref() {
+                    "none" => OrchestratorKind::None,
+                    "kubernetes" => OrchestratorKind::Kubernetes,
+                    "ecs" => usage_msg("The ECS orchestrator is not supported yet"),
+                    _ => usage_msg(format!("Unknown orchestrator '{}'", kind)),
+                });
+            }
+
+            "--check-interval" => check_interval = Some(parse_seconds(&arg, iter.next())),
+
+            "--drain-timeout" => drain_timeout = Some(parse_seconds(&arg, iter.next())),
+
+            "--host-root" => {
+                let path = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --host-root"));
+                host_root = Some(PathBuf::from(path));
+            }
+
+            "--reboot" => {
+                let command = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --reboot"));
+                let command: Vec<String> = command.split_whitespace().map(String::from).collect();
+                if command.is_empty() {
+                    usage_msg("--reboot must not be empty");
+                }
+                reboot = Some(command);
+            }
+
+            "--log-level" => {
+                let log_level_str = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --log-level"));
+                log_level = Some(LevelFilter::from_str(&log_level_str).unwrap_or_else(|_| {
+                    usage_msg(format!("Invalid log level '{}'", log_level_str))
+                }));
+            }
+
+            _ => usage(),
+        }
+    }
+
+    Args {
+        orchestrator: orchestrator.unwrap_or(OrchestratorKind::None),
+        check_interval: check_interval
+            .unwrap_or_else(|| Duration::from_secs(DEFAULT_CHECK_INTERVAL)),
+        drain_timeout: drain_timeout.unwrap_or_else(|| Duration::from_secs(DEFAULT_DRAIN_TIMEOUT)),
+        host_root: host_root.unwrap_or_else(|| PathBuf::from(DEFAULT_HOST_ROOT)),
+        reboot: reboot.unwrap_or_else(|| {
+            DEFAULT_REBOOT
+                .split_whitespace()
+                .map(String::from)
+                .collect()
+        }),
+        log_level: log_level.unwrap_or(LevelFilter::Info),
+    }
+}
+
+fn run() -> Result<()> {
+    let args = parse_args(std::env::args());
+
+    // TerminalMode::Mixed will send errors to stderr and anything less to stdout.
+    TermLogger::init(args.log_level, LogConfig::default(), TerminalMode::Mixed)
+        .context(error::Logger)?;
+
+    let orchestrator: Box<dyn Orchestrator> = match args.orchestrator {
+        OrchestratorKind::None => Box::new(NoOrchestrator),
+        OrchestratorKind::Kubernetes => Box::new(Kubernetes::from_env(args.drain_timeout)?),
+    };
+    let updog = Updog::new(&args.host_root, args.reboot);
+
+    // We normally start after rebooting into an update, so let the orchestrator know we're back
+    // before doing anything else.
+    orchestrator.resume()?;
+
+    // Once an image is written we keep trying to apply it, rather than writing it again.
+    let mut ready = None;
+    loop {
+        if ready.is_none() {
+            match updog.update_image() {
+                Ok(UpdateStatus::Ready(update)) => {
+                    info!("Wrote update to {}", update.version);
+                    ready = Some(update);
+                }
+                Ok(UpdateStatus::Later(time)) => info!("Update available at {}", time),
+                Ok(UpdateStatus::None) => debug!("No update available"),
+                Err(e) => warn!("Failed to check for update: {}", e),
+            }
+        }
+
+        if let Some(update) = &ready {
+            info!("Preparing to reboot into {}", update.version);
+            match orchestrator.prepare().and_then(|()| updog.update_apply()) {
+                Ok(()) => {
+                    info!("Rebooting into {}", update.version);
+                    // The reboot will stop us; until then, make sure we don't resume or write
+                    // another image to the partition we're about to boot from.
+                    loop {
+                        thread::sleep(args.check_interval);
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to apply update, will retry: {}", e);
+                    if let Err(e) = orchestrator.resume() {
+                        warn!("Failed to resume after failed update: {}", e);
+                    }
+                }
+            }
+        }
+
+        thread::sleep(args.check_interval);
+    }
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,30 @@
+/*!
+This module defines how sheepdog works with the orchestrator that schedules work onto the host.
+*/
+
+use crate::error::Result;
+
+/// An orchestrator that needs to know before a host reboots into an update.
+pub(crate) trait Orchestrator {
+    /// Get the host ready to reboot, for example by moving its workloads elsewhere.  This should
+    /// fail rather than wait indefinitely if another host is already rebooting.
+    fn prepare(&self) -> Result<()>;
+
+    /// Undo anything `prepare` did.  This is called when sheepdog starts, which is normally after
+    /// the reboot, and whenever preparing or applying the update fails.  It must be safe to call
+    /// even if `prepare` didn't run, or only partly succeeded.
+    fn resume(&self) -> Result<()>;
+}
+
+/// Used when the host isn't managed by an orchestrator, so updates can reboot at any time.
+pub(crate) struct NoOrchestrator;
+
+impl Orchestrator for NoOrchestrator {
+    fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+/*!
+This module runs updog's library to find, write, and apply updates on the host whose root
+filesystem is at the given path, and reboots the host into them.
+*/
+
+use crate::error::{self, Result};
+use chrono::{DateTime, Utc};
+use snafu::{ensure, ResultExt};
+use std::path::Path;
+use std::process;
+use update_metadata::Update;
+use updog::{Arguments, Command, Output, Paths, Report};
+
+/// What updog did when asked to write an update image.
+#[derive(Debug)]
+pub(crate) enum UpdateStatus {
+    /// There's no update for this host, or its wave hasn't been scheduled yet.
+    None,
+    /// There's an update, but this host's wave doesn't start until the given time.
+    Later(DateTime<Utc>),
+    /// The update was written to the inactive partition and can be applied.
+    Ready(Box<Update>),
+}
+
+/// Runs updog against the host's files, and reboots the host through the given command, which
+/// may include a wrapper like `nsenter` when sheepdog doesn't run in the host's namespaces.
+pub(crate) struct Updog {
+    paths: Paths,
+    reboot: Vec<String>,
+}
+
+impl Updog {
+    pub(crate) fn new(host_root: &Path, reboot: Vec<String>) -> Self {
+        Self {
+            paths: Paths::under(host_root),
+            reboot,
+        }
+    }
+
+    /// Download and write any available update to the inactive partition, without changing the
+    /// partition flags.
+    pub(crate) fn update_image(&self) -> Result<UpdateStatus> {
+        let output = self.run(Command::UpdateImage)?;
+        Ok(match output.report {
+            Report::Update { update, .. } => UpdateStatus::Ready(Box::new(update)),
+            Report::Scheduled(time) => UpdateStatus::Later(time),
+            _ => UpdateStatus::None,
+        })
+    }
+
+    /// Flip the partition flags to the update written by `update_image`, and reboot into it.
+    pub(crate) fn update_apply(&self) -> Result<()> {
+        self.run(Command::UpdateApply)?;
+        self.reboot()
+    }
+
+    /// Runs an updog command, saving its status and history on the host like the `updog` binary
+    /// does.
+    fn run(&self, command: Command) -> Result<Output> {
+        let mut arguments = Arguments::new(command);
+        arguments.paths = self.paths.clone();
+        let run = updog::run(&arguments);
+        updog::record(&arguments, &run);
+        run.context(error::Updog { command })
+    }
+
+    fn reboot(&self) -> Result<()> {
+        let command = self.reboot.join(" ");
+        let (program, args) = self.reboot.split_first().expect("empty reboot command");
+        let status = process::Command::new(program)
+            .args(args)
+            .status()
+            .context(error::RebootStart { command: &command })?;
+        ensure!(status.success(), error::RebootFailure { command, status });
+        Ok(())
+    }
+}
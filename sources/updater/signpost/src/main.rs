@@ -1,10 +1,16 @@
 #![deny(rust_2018_idioms)]
 #![warn(clippy::pedantic)]
 
+use audit::{Action, Actor};
+use log::{info, LevelFilter};
 use serde::Deserialize;
-use signpost::State;
+use signpost::{Error, State};
+use simplelog::{Config as LogConfig, TermLogger, TerminalMode};
+use std::path::Path;
+use std::str::FromStr;
+use updater_config::{Layer, SETTINGS_FILE};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 enum Command {
     Status,
@@ -17,10 +23,27 @@ enum Command {
     RewriteTable,
 }
 
+impl Command {
+    /// The action a command that changes the partition table is recorded as in the audit trail.
+    fn action(self) -> Option<Action> {
+        match self {
+            Command::Status => None,
+            Command::MarkSuccessfulBoot => Some(Action::PartitionMarkSuccessfulBoot),
+            Command::MarkInactiveValid => Some(Action::PartitionMarkInactiveValid),
+            Command::ClearInactive => Some(Action::PartitionClearInactive),
+            Command::UpgradeToInactive => Some(Action::PartitionUpgradeToInactive),
+            Command::CancelUpgrade => Some(Action::PartitionCancelUpgrade),
+            Command::RollbackToInactive => Some(Action::PartitionRollbackToInactive),
+            Command::RewriteTable => Some(Action::PartitionRewriteTable),
+        }
+    }
+}
+
 fn usage() -> ! {
     eprintln!("\
 USAGE:
-    signpost <SUBCOMMAND>
+    signpost [ --log-level trace|debug|info|warn|error ] <SUBCOMMAND>
+    signpost --show-config
 
 SUBCOMMANDS:
     status                  Show partition sets and priority status
@@ -34,42 +57,108 @@ SUBCOMMANDS:
     std::process::exit(1)
 }
 
+/// Prints the log level signpost would use, resolved like the other updater components do, and
+/// exits.
+fn show_config(command_line: Layer) -> ! {
+    match updater_config::load(None, Path::new(SETTINGS_FILE), command_line) {
+        Ok(config) => {
+            println!("{}", config);
+            std::process::exit(0)
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1)
+        }
+    }
+}
+
 fn main() {
-    let command_str = std::env::args().nth(1).unwrap_or_else(|| usage());
+    let mut command_line = Layer::default();
+    let mut command_str = None;
+    let mut show = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--log-level" => {
+                let level = args.next().unwrap_or_else(|| usage());
+                command_line.log_level =
+                    Some(LevelFilter::from_str(&level).unwrap_or_else(|_| usage()));
+            }
+            "--show-config" => show = true,
+            _ if command_str.is_none() => command_str = Some(arg),
+            _ => usage(),
+        }
+    }
+    if show {
+        show_config(command_line);
+    }
+    let command_str = command_str.unwrap_or_else(|| usage());
     let command = serde_plain::from_str::<Command>(&command_str).unwrap_or_else(|_| usage());
 
+    // A problem with the shared config shouldn't keep the host from marking a successful boot.
+    let level = updater_config::load(None, Path::new(SETTINGS_FILE), command_line.clone())
+        .map_or_else(
+            |_| command_line.log_level.unwrap_or(LevelFilter::Info),
+            |config| config.log_level.value,
+        );
+    // Logging is only a convenience here; the result of each command is its exit status.
+    let _ = TermLogger::init(level, LogConfig::default(), TerminalMode::Mixed);
+
     if let Err(err) = State::load().and_then(|mut state| {
-        match command {
-            Command::Status => println!("{}", state),
-            Command::ClearInactive => {
-                state.clear_inactive();
-                state.write()?;
-            }
-            Command::MarkSuccessfulBoot => {
-                state.mark_successful_boot();
-                state.write()?;
+        let action = match command.action() {
+            Some(action) => action,
+            None => {
+                println!("{}", state);
+                return Ok(());
             }
-            Command::MarkInactiveValid => {
-                state.mark_inactive_valid();
-                state.write()?;
-            }
-            Command::UpgradeToInactive => {
-                state.upgrade_to_inactive()?;
-                state.write()?;
-            }
-            Command::CancelUpgrade => {
-                state.cancel_upgrade();
-                state.write()?;
-            }
-            Command::RollbackToInactive => {
-                state.rollback_to_inactive()?;
-                state.write()?;
-            }
-            Command::RewriteTable => state.write()?,
-        }
+        };
+        // What changes: the partition set the command is about, or for rewrite-table, only the
+        // table.
+        let object = match command {
+            Command::MarkSuccessfulBoot => state.active_set().to_string(),
+            Command::RewriteTable => "partition table".to_string(),
+            _ => state.inactive_set().to_string(),
+        };
+        let result = change(command, &mut state);
+        audit::record(Actor::Signpost, action, object, &result);
+        result?;
+        info!("Updated the partition table for {}", command_str);
         Ok(())
     }) {
         eprintln!("{}", err);
         std::process::exit(1)
     }
 }
+
+/// Makes the change to the partition table that `command` is for, and writes it.
+fn change(command: Command, state: &mut State) -> Result<(), Error> {
+    match command {
+        Command::Status => {}
+        Command::ClearInactive => {
+            state.clear_inactive();
+            state.write()?;
+        }
+        Command::MarkSuccessfulBoot => {
+            state.mark_successful_boot();
+            state.write()?;
+        }
+        Command::MarkInactiveValid => {
+            state.mark_inactive_valid();
+            state.write()?;
+        }
+        Command::UpgradeToInactive => {
+            state.upgrade_to_inactive()?;
+            state.write()?;
+        }
+        Command::CancelUpgrade => {
+            state.cancel_upgrade();
+            state.write()?;
+        }
+        Command::RollbackToInactive => {
+            state.rollback_to_inactive()?;
+            state.write()?;
+        }
+        Command::RewriteTable => state.write()?,
+    }
+    Ok(())
+}
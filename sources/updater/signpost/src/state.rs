@@ -40,10 +40,17 @@ impl State {
     /// * Determine which partition set is active by finding which one contains the partition we
     ///   found from our root filesystem earlier.
     pub fn load() -> Result<Self, Error> {
+        Self::load_from_root("/")
+    }
+
+    /// Like `load`, but looks up the root filesystem mounted at `root`, like `/proc/1/root` from
+    /// a container that shares the host's PID namespace.
+    pub fn load_from_root<P: AsRef<Path>>(root: P) -> Result<Self, Error> {
+        let root = root.as_ref();
         // The root filesystem is a dm-verity device. We want to determine what disk and partition
         // the backing data is part of. Look up the device major and minor via stat(2):
-        let root_fs = BlockDevice::from_device_path("/")
-            .context(error::BlockDeviceFromPath { device: "/" })?;
+        let root_fs = BlockDevice::from_device_path(root)
+            .context(error::BlockDeviceFromPath { device: root })?;
         // Get the first lower device from this one, and determine what disk it belongs to.
         let active_partition = root_fs
             .lower_devices()
@@ -54,13 +61,27 @@ impl State {
             .context(error::RootHasNoLowerDevices {
                 root: root_fs.path(),
             })?;
+        Self::from_active_partition(active_partition)
+    }
+
+    /// Like `load`, but treats the given partition device node as the one backing the root
+    /// filesystem, rather than looking it up from `/`.  This lets tests work with partition sets
+    /// on a disk other than the one we booted from.
+    pub fn load_from_partition<P: AsRef<Path>>(active_partition: P) -> Result<Self, Error> {
+        let device = active_partition.as_ref();
+        let active_partition =
+            BlockDevice::from_device_node(device).context(error::BlockDeviceFromPath { device })?;
+        Self::from_active_partition(active_partition)
+    }
+
+    fn from_active_partition(active_partition: BlockDevice) -> Result<Self, Error> {
         let os_disk = active_partition
             .disk()
             .context(error::DiskFromPartition {
-                device: root_fs.path(),
+                device: active_partition.path(),
             })?
             .context(error::RootNotPartition {
-                device: root_fs.path(),
+                device: active_partition.path(),
             })?;
         let active_partition = active_partition.path();
 
@@ -100,7 +121,10 @@ impl State {
                 .path())
         };
 
-        let boot_partition_nums = [nth_guid(BOTTLEROCKET_BOOT, 0)?, nth_guid(BOTTLEROCKET_BOOT, 1)?];
+        let boot_partition_nums = [
+            nth_guid(BOTTLEROCKET_BOOT, 0)?,
+            nth_guid(BOTTLEROCKET_BOOT, 1)?,
+        ];
         let sets = [
             PartitionSet {
                 boot: device_from_part_num(boot_partition_nums[0])?,
@@ -183,6 +207,20 @@ impl State {
         }
     }
 
+    /// Whether the inactive partition set has been marked to boot next, i.e. an update has been
+    /// applied and is waiting for a reboot.
+    pub fn upgrade_pending(&self) -> bool {
+        self.next()
+            .map_or(false, |next| next.idx() == self.inactive().idx())
+    }
+
+    /// Whether the inactive partition set has been marked as potentially valid, but not yet marked
+    /// to boot, i.e. an update has been written and can be applied.
+    pub fn inactive_valid(&self) -> bool {
+        let flags = self.gptprio(self.inactive());
+        flags.tries_left() > 0 && flags.priority() == 0 && !flags.successful()
+    }
+
     /// Sets the active partition as successfully booted, but **does not write to the disk**.
     pub fn mark_successful_boot(&mut self) {
         let mut flags = self.gptprio(self.active());
@@ -0,0 +1,22 @@
+//! Parsing a manifest, in full as updata does, and for one host as updog does.
+
+use update_bench::{Bench, Fleet};
+use update_metadata::Manifest;
+
+fn main() {
+    let mut bench = Bench::from_args("manifest");
+    let (variant, arch) = Fleet::HOST;
+    for fleet in &Fleet::ALL {
+        let data = fleet.manifest_json();
+        bench.run(&format!("full/{}", fleet.name), || {
+            serde_json::from_slice::<Manifest>(&data).unwrap()
+        });
+        bench.run(&format!("host/{}", fleet.name), || {
+            update_metadata::from_slice_for(&data, variant, arch).unwrap()
+        });
+        bench.run(&format!("host-stream/{}", fleet.name), || {
+            update_metadata::from_reader_for(data.as_slice(), variant, arch).unwrap()
+        });
+    }
+    bench.finish();
+}
@@ -0,0 +1,31 @@
+//! Finding the updates a host could apply, and the newest one that's ready for it, as updog does
+//! on every check.
+
+use chrono::Duration;
+use update_bench::{Bench, Fleet};
+use update_metadata::MockClock;
+
+fn main() {
+    let mut bench = Bench::from_args("selection");
+    let (variant, arch) = Fleet::HOST;
+    for fleet in &Fleet::ALL {
+        let manifest = fleet.manifest();
+        // Partway through the rollout of the newest update, so hosts have waves to wait for.
+        let clock = MockClock::new(
+            Fleet::start() + Duration::weeks(fleet.versions as i64 - 1) + Duration::hours(36),
+        );
+        let seed = 1200;
+
+        bench.run(&format!("applicable/{}", fleet.name), || {
+            manifest.applicable_updates(variant, arch).len()
+        });
+        bench.run(&format!("ready/{}", fleet.name), || {
+            manifest
+                .applicable_updates(variant, arch)
+                .into_iter()
+                .find(|u| u.update_ready(seed, &clock))
+                .map(|u| u.version.clone())
+        });
+    }
+    bench.finish();
+}
@@ -0,0 +1,40 @@
+//! Wave math for the updates a host could apply, for every seed in the fleet: which wave the host
+//! is in, whether it's started, and when the host should fetch the update.
+
+use chrono::Duration;
+use update_bench::{Bench, Fleet};
+use update_metadata::{MockClock, MAX_SEED};
+
+fn main() {
+    let mut bench = Bench::from_args("waves");
+    let (variant, arch) = Fleet::HOST;
+    for fleet in &Fleet::ALL {
+        let manifest = fleet.manifest();
+        let updates = manifest.applicable_updates(variant, arch);
+        let now = Fleet::start() + Duration::hours(36);
+        let clock = MockClock::new(now);
+
+        bench.run(&format!("wave/{}", fleet.name), || {
+            updates
+                .iter()
+                .flat_map(|u| (0..MAX_SEED).map(move |seed| u.update_wave(seed)))
+                .filter(Option::is_some)
+                .count()
+        });
+        bench.run(&format!("ready/{}", fleet.name), || {
+            updates
+                .iter()
+                .flat_map(|u| (0..MAX_SEED).map(move |seed| (u, seed)))
+                .filter(|(u, seed)| u.update_ready(*seed, &clock))
+                .count()
+        });
+        bench.run(&format!("fetch-window/{}", fleet.name), || {
+            updates
+                .iter()
+                .flat_map(|u| (0..MAX_SEED).map(move |seed| u.fetch_window(seed, now)))
+                .filter(Option::is_some)
+                .count()
+        });
+    }
+    bench.finish();
+}
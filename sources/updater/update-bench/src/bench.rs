@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+
+/// How long to run a benchmark before timing it, to warm caches and estimate how many
+/// iterations fit in a sample.
+const WARM_UP: Duration = Duration::from_millis(200);
+
+/// How long each sample should take, and how many to take; the median sample is reported, so
+/// one disturbed by something else on the machine doesn't skew the result.
+const SAMPLE_TIME: Duration = Duration::from_millis(100);
+const SAMPLES: usize = 10;
+
+/// How much slower than its baseline, in percent, a benchmark can get before it's a regression.
+const DEFAULT_THRESHOLD: f64 = 25.0;
+
+/// Keeps the compiler from optimizing away a value a benchmark computes, or the work to
+/// compute it.
+pub fn black_box<T>(value: T) -> T {
+    // Safe because we read a valid value, and forget the original so it's only dropped once.
+    unsafe {
+        let copy = std::ptr::read_volatile(&value);
+        std::mem::forget(value);
+        copy
+    }
+}
+
+/// Runs a group of benchmarks, prints their timings, and compares them to a saved baseline.
+/// Options are taken from the command line, as described in the crate docs.
+#[derive(Debug)]
+pub struct Bench {
+    group: &'static str,
+    filter: Option<String>,
+    save_baseline: Option<PathBuf>,
+    baseline: Option<BTreeMap<String, u64>>,
+    threshold: f64,
+    results: BTreeMap<String, u64>,
+    regressions: Vec<String>,
+}
+
+impl Bench {
+    /// Makes a runner for the benchmarks in `group`, with options from the command line.
+    pub fn from_args(group: &'static str) -> Self {
+        let mut bench = Self {
+            group,
+            filter: None,
+            save_baseline: None,
+            baseline: None,
+            threshold: DEFAULT_THRESHOLD,
+            results: BTreeMap::new(),
+            regressions: Vec::new(),
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .unwrap_or_else(|| panic!("{} needs a value", arg))
+            };
+            match arg.as_str() {
+                "--save-baseline" => bench.save_baseline = Some(PathBuf::from(value())),
+                "--baseline" => bench.baseline = Some(read_baseline(Path::new(&value()))),
+                "--threshold" => {
+                    bench.threshold = value().parse().expect("--threshold must be a number")
+                }
+                // cargo bench passes --bench, and we've no use for other options it might.
+                _ if arg.starts_with("--") => {}
+                _ => bench.filter = Some(arg),
+            }
+        }
+        bench
+    }
+
+    /// Times `f`, which is run many times, under `name`, unless it's filtered out.
+    pub fn run<T, F>(&mut self, name: &str, mut f: F)
+    where
+        F: FnMut() -> T,
+    {
+        let name = format!("{}/{}", self.group, name);
+        if let Some(filter) = &self.filter {
+            if !name.contains(filter.as_str()) {
+                return;
+            }
+        }
+
+        let start = Instant::now();
+        let mut iterations = 0u32;
+        while start.elapsed() < WARM_UP {
+            black_box(f());
+            iterations += 1;
+        }
+        let per_sample = (SAMPLE_TIME.as_nanos() * u128::from(iterations)
+            / start.elapsed().as_nanos().max(1))
+        .max(1) as u32;
+
+        let mut samples: Vec<u64> = (0..SAMPLES)
+            .map(|_| {
+                let start = Instant::now();
+                for _ in 0..per_sample {
+                    black_box(f());
+                }
+                (start.elapsed().as_nanos() / u128::from(per_sample)) as u64
+            })
+            .collect();
+        samples.sort_unstable();
+        let median = samples[SAMPLES / 2];
+
+        let change = self
+            .baseline
+            .as_ref()
+            .and_then(|baseline| baseline.get(&name))
+            .map(|&before| (median as f64 - before as f64) / before as f64 * 100.0);
+        match change {
+            Some(change) if change > self.threshold => {
+                println!(
+                    "{:<48} {:>12?} ({:+.1}%, regressed)",
+                    name,
+                    Duration::from_nanos(median),
+                    change
+                );
+                self.regressions.push(name.clone());
+            }
+            Some(change) => println!(
+                "{:<48} {:>12?} ({:+.1}%)",
+                name,
+                Duration::from_nanos(median),
+                change
+            ),
+            None => println!("{:<48} {:>12?}", name, Duration::from_nanos(median)),
+        }
+        self.results.insert(name, median);
+    }
+
+    /// Saves the timings as a baseline if asked to, and exits with an error if any benchmark
+    /// regressed.
+    pub fn finish(self) {
+        if let Some(path) = &self.save_baseline {
+            // Each group adds its own timings to the baseline, keeping the other groups'.
+            let mut baseline = if path.exists() {
+                read_baseline(path)
+            } else {
+                BTreeMap::new()
+            };
+            baseline.extend(self.results);
+            let data = serde_json::to_vec_pretty(&baseline).expect("failed to serialize baseline");
+            fs::write(path, data)
+                .unwrap_or_else(|e| panic!("failed to write {}: {}", path.display(), e));
+        }
+        if !self.regressions.is_empty() {
+            eprintln!(
+                "{} got more than {}% slower: {}",
+                self.group,
+                self.threshold,
+                self.regressions.join(", ")
+            );
+            process::exit(1);
+        }
+    }
+}
+
+fn read_baseline(path: &Path) -> BTreeMap<String, u64> {
+    let data =
+        fs::read(path).unwrap_or_else(|e| panic!("failed to read {}: {}", path.display(), e));
+    serde_json::from_slice(&data)
+        .unwrap_or_else(|e| panic!("failed to parse {}: {}", path.display(), e))
+}
@@ -0,0 +1,98 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use semver::Version;
+use std::collections::BTreeMap;
+use update_metadata::{Images, Manifest, Update};
+
+const ARCHES: [&str; 2] = ["x86_64", "aarch64"];
+
+/// The seeds each wave ends at, like the default waves.
+const WAVE_BOUNDS: [u32; 4] = [512, 1024, 1536, 2048];
+
+/// The shape of a made-up fleet, and of the manifest for it: every variant has updates for
+/// every architecture, at each of a run of versions, each rolled out in four waves a day apart.
+#[derive(Debug, Clone, Copy)]
+pub struct Fleet {
+    pub name: &'static str,
+    pub variants: u64,
+    pub versions: u64,
+}
+
+impl Fleet {
+    /// A manifest like one for a handful of variants, with 40 updates.
+    pub const SMALL: Fleet = Fleet {
+        name: "small",
+        variants: 4,
+        versions: 5,
+    };
+
+    /// A manifest for many variants that's been published to for a long time, with 5000
+    /// updates.
+    pub const LARGE: Fleet = Fleet {
+        name: "large",
+        variants: 100,
+        versions: 25,
+    };
+
+    /// The fleets every benchmark is run against.
+    pub const ALL: [Fleet; 2] = [Fleet::SMALL, Fleet::LARGE];
+
+    /// The variant and arch of a host in the fleet, which has updates in every manifest.
+    pub const HOST: (&'static str, &'static str) = ("bottlerocket-variant-0", "x86_64");
+
+    /// When the first wave of the oldest update starts; each version's waves start a week after
+    /// the last one's.
+    pub fn start() -> DateTime<Utc> {
+        Utc.ymd(2020, 3, 1).and_hms(0, 0, 0)
+    }
+
+    /// Makes the fleet's manifest.
+    pub fn manifest(self) -> Manifest {
+        let mut updates = Vec::new();
+        for variant in 0..self.variants {
+            let variant = format!("bottlerocket-variant-{}", variant);
+            for arch in &ARCHES {
+                for minor in 0..self.versions {
+                    updates.push(update(&variant, arch, minor));
+                }
+            }
+        }
+        Manifest {
+            compatibility: Vec::new(),
+            updates,
+            migrations: BTreeMap::new(),
+            shards: BTreeMap::new(),
+        }
+    }
+
+    /// Makes the fleet's manifest, serialized the way updata writes it.
+    pub fn manifest_json(self) -> Vec<u8> {
+        serde_json::to_vec_pretty(&self.manifest()).expect("failed to serialize manifest")
+    }
+}
+
+fn update(variant: &str, arch: &str, minor: u64) -> Update {
+    let version = Version::new(1, minor, 0);
+    let start = Fleet::start() + Duration::weeks(minor as i64);
+    let waves = WAVE_BOUNDS
+        .iter()
+        .zip(0..)
+        .map(|(&bound, day)| (bound, start + Duration::days(day)))
+        .collect();
+    let image = |name: &str| format!("bottlerocket-{}-{}-v{}-{}", arch, variant, version, name);
+    Update {
+        images: Images {
+            boot: image("boot.ext4.lz4"),
+            root: image("root.ext4.lz4"),
+            hash: image("root.verity.lz4"),
+        },
+        variant: variant.to_string(),
+        arch: arch.to_string(),
+        max_version: Version::new(1, 99, 0),
+        version,
+        waves,
+        paused: None,
+        targeting: None,
+        provenance: None,
+        available: true,
+    }
+}
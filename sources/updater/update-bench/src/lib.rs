@@ -0,0 +1,43 @@
+/*!
+# Introduction
+
+update-bench holds benchmarks for the parts of the update path that every host runs on every
+check, so a change that slows them down is caught before it's released to a whole fleet:
+
+* `manifest`: parsing a manifest, in full as updata does, and for one host as updog does.
+* `selection`: finding the updates a host could apply, and the newest one that's ready for it.
+* `waves`: finding a host's wave, whether it's started, and the host's fetch window.
+
+Each is run against a [`Fleet::SMALL`] and a [`Fleet::LARGE`] manifest, made up by [`Fleet`].
+
+# Running
+
+```text
+cargo bench -p update-bench
+```
+
+Give a name, or part of one, to run only the benchmarks it matches, like
+`cargo bench -p update-bench -- large`.
+
+To catch regressions, save a baseline before a change, then compare against it after:
+
+```text
+cargo bench -p update-bench -- --save-baseline /tmp/base.json
+cargo bench -p update-bench -- --baseline /tmp/base.json
+```
+
+Comparing prints how each benchmark changed, and fails if any got slower by more than
+`--threshold` percent, 25 by default.  Timings are only comparable on the same machine, and
+are noisy on a busy one, so keep the threshold loose.
+
+The benchmarks use a small [`Bench`] runner of their own rather than the unstable built-in
+one, so they run on a stable toolchain.
+*/
+
+#![deny(rust_2018_idioms)]
+
+mod bench;
+mod fleet;
+
+pub use bench::{black_box, Bench};
+pub use fleet::Fleet;
@@ -0,0 +1,161 @@
+/*!
+Creates loop devices that look like a Bottlerocket OS disk to signpost and the update client.
+*/
+
+use gptman::{GPTPartitionEntry, GPT};
+use hex_literal::hex;
+use signpost::uuid_to_guid;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::thread;
+use std::time::Duration;
+
+// These match the partition types signpost looks for.
+const BOTTLEROCKET_BOOT: [u8; 16] = uuid_to_guid(hex!("6b636168 7420 6568 2070 6c616e657421"));
+const BOTTLEROCKET_ROOT: [u8; 16] = uuid_to_guid(hex!("5526016a 1a97 4ea4 b39a b7c8c6ca4502"));
+const BOTTLEROCKET_HASH: [u8; 16] = uuid_to_guid(hex!("598f10af c955 4456 6a99 7720068a6cea"));
+
+const SECTOR_SIZE: u64 = 512;
+/// Each partition is 1 MiB, and the first starts 1 MiB into the disk.
+const PARTITION_SECTORS: u64 = 2048;
+const DISK_SIZE: u64 = 8 * 1024 * 1024;
+
+// The gptprio bits of a boot partition that has booted successfully with priority 1, like the
+// first partition set of a fresh image.
+const GPTPRIO_PRIORITY_1: u64 = 1 << 48;
+const GPTPRIO_SUCCESSFUL: u64 = 1 << 56;
+
+/// Whether we can attach loop devices and see their partitions.  That needs root, and a kernel
+/// that understands GPT, so we find out by trying it once per process.
+pub fn loop_devices_available() -> bool {
+    static PROBE: Once = Once::new();
+    static AVAILABLE: AtomicBool = AtomicBool::new(false);
+    PROBE.call_once(|| {
+        let can_attach = OpenOptions::new()
+            .write(true)
+            .open("/dev/loop-control")
+            .is_ok();
+        let path = env::temp_dir().join(format!("update-harness-probe-{}.img", process::id()));
+        let available = can_attach && Disk::try_new(&path).is_some();
+        let _ = fs::remove_file(&path);
+        AVAILABLE.store(available, Ordering::SeqCst);
+    });
+    AVAILABLE.load(Ordering::SeqCst)
+}
+
+/// A disk image with two partition sets, attached to a loop device until dropped.
+///
+/// Partitions 1 to 3 are the boot, root, and hash partitions of set A, which is marked as
+/// successfully booted; partitions 4 to 6 are set B, which is empty.
+#[derive(Debug)]
+pub struct Disk {
+    device: PathBuf,
+}
+
+impl Disk {
+    /// Creates the disk image at `path` and attaches it.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self::try_new(path.as_ref()).expect("failed to attach disk image with partitions")
+    }
+
+    /// Like `new`, but returns `None` if the loop device or its partitions can't be set up.
+    fn try_new(path: &Path) -> Option<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .expect("failed to create disk image");
+        file.set_len(DISK_SIZE).expect("failed to size disk image");
+
+        let mut gpt = GPT::new_from(&mut file, SECTOR_SIZE, *b"bottlerocketdisk")
+            .expect("failed to create partition table");
+        let types = [
+            BOTTLEROCKET_BOOT,
+            BOTTLEROCKET_ROOT,
+            BOTTLEROCKET_HASH,
+            BOTTLEROCKET_BOOT,
+            BOTTLEROCKET_ROOT,
+            BOTTLEROCKET_HASH,
+        ];
+        for (i, partition_type) in types.iter().enumerate() {
+            let num = i as u64 + 1;
+            gpt[num as u32] = GPTPartitionEntry {
+                partition_type_guid: *partition_type,
+                unique_parition_guid: [num as u8; 16],
+                starting_lba: num * PARTITION_SECTORS,
+                ending_lba: (num + 1) * PARTITION_SECTORS - 1,
+                attribute_bits: if num == 1 {
+                    GPTPRIO_PRIORITY_1 | GPTPRIO_SUCCESSFUL
+                } else {
+                    0
+                },
+                partition_name: "".into(),
+            };
+        }
+        gpt.write_into(&mut file)
+            .expect("failed to write partition table");
+        GPT::write_protective_mbr_into(&mut file, SECTOR_SIZE)
+            .expect("failed to write protective MBR");
+        drop(file);
+
+        let output = Command::new("losetup")
+            .args(&["--find", "--show", "--partscan"])
+            .arg(path)
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let device = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+        let disk = Self { device };
+
+        // The partition devices show up asynchronously.
+        for _ in 0..50 {
+            if (1..=6).all(|num| disk.partition(num).exists()) {
+                return Some(disk);
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        None
+    }
+
+    /// The path to the loop device for the whole disk.
+    pub fn device(&self) -> &Path {
+        &self.device
+    }
+
+    /// The path to partition `num` of the disk.
+    pub fn partition(&self, num: u32) -> PathBuf {
+        PathBuf::from(format!("{}p{}", self.device.display(), num))
+    }
+
+    /// Reads the first `len` bytes of partition `num`.
+    pub fn read_partition(&self, num: u32, len: usize) -> Vec<u8> {
+        let mut data = vec![0; len];
+        File::open(self.partition(num))
+            .and_then(|mut f| f.read_exact(&mut data))
+            .expect("failed to read partition");
+        data
+    }
+
+    /// Writes `data` to the start of partition `num`.
+    pub fn write_partition(&self, num: u32, data: &[u8]) {
+        fs::write(self.partition(num), data).expect("failed to write partition");
+    }
+}
+
+impl Drop for Disk {
+    fn drop(&mut self) {
+        let _ = Command::new("losetup")
+            .arg("--detach")
+            .arg(&self.device)
+            .status();
+    }
+}
@@ -0,0 +1,30 @@
+/*!
+# Introduction
+
+update-harness holds the pieces needed to run the update client against something that looks
+like a real host and a real update repository, so the whole update path can be tested
+end-to-end:
+
+* [`RepoBuilder`] writes a TUF repository, signed with a test key, holding a manifest and the
+  images for each update in it.
+* [`Server`] serves a directory over HTTP on the loopback interface, and can inject a [`Fault`]
+  when serving chosen files.
+* [`Disk`] creates a disk image with two Bottlerocket partition sets and attaches it to a loop
+  device, so partition contents and flags can be checked with signpost.
+
+Setting up loop devices needs root and a kernel that can read GPT partition tables, so tests that
+use a `Disk` should call [`loop_devices_available`] first and skip themselves if it returns false.  Likewise, tests that talk to a `Server` should
+check [`loopback_http_available`], since some sandboxes block the client's connections.
+
+These helpers panic rather than returning errors, since they're only meant to be used in tests.
+*/
+
+#![deny(rust_2018_idioms)]
+
+mod disk;
+mod repo;
+mod server;
+
+pub use disk::{loop_devices_available, Disk};
+pub use repo::{image_data, RepoBuilder};
+pub use server::{loopback_http_available, Fault, Server};
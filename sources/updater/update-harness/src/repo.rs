@@ -0,0 +1,338 @@
+/*!
+Builds TUF repositories for tests.  The manifest can be built up here, with the same
+`update_metadata` calls that updata makes, or written by updata itself and loaded from a file.
+Every role is signed with the test key in `data/test-key.pem`.
+*/
+
+use chrono::{DateTime, Duration, Utc};
+use ring::digest::{digest, SHA256};
+use ring::rand::SystemRandom;
+use semver::Version;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+use tough::schema::{
+    Hashes, Role, RoleKeys, RoleType, Root, Signature, Signed, Snapshot, SnapshotMeta, Target,
+    Targets, Timestamp, TimestampMeta,
+};
+use tough::sign::{parse_keypair, Sign};
+use update_metadata::{Images, Manifest};
+
+const TEST_KEY: &[u8] = include_bytes!("../data/test-key.pem");
+
+/// The size of each generated image, before compression.
+const IMAGE_SIZE: usize = 64 * 1024;
+
+/// Returns the uncompressed contents of the test image of the given kind ("boot", "root", or
+/// "hash") for an update version, so tests can check what was written to a partition.
+pub fn image_data(version: &str, kind: &str) -> Vec<u8> {
+    format!("{} image for {}\n", kind, version)
+        .into_bytes()
+        .into_iter()
+        .cycle()
+        .take(IMAGE_SIZE)
+        .collect()
+}
+
+/// Collects the manifest and targets of a test repository, then signs and writes it out.
+#[derive(Debug)]
+pub struct RepoBuilder {
+    manifest: Manifest,
+    targets: BTreeMap<String, Vec<u8>>,
+    expires: DateTime<Utc>,
+}
+
+impl Default for RepoBuilder {
+    fn default() -> Self {
+        Self {
+            manifest: Manifest::default(),
+            targets: BTreeMap::new(),
+            expires: Utc::now() + Duration::days(7),
+        }
+    }
+}
+
+impl RepoBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the lz4-compressed images of an update for the given variant and version, built for
+    /// the architecture we're running on, and returns their target names for the manifest.
+    pub fn add_images(&mut self, variant: &str, version: &str) -> Images {
+        let arch = std::env::consts::ARCH;
+        let name = |kind: &str, ext: &str| {
+            format!("{}-{}-{}-{}.{}.lz4", variant, arch, version, kind, ext)
+        };
+        let images = Images {
+            boot: name("boot", "ext4"),
+            root: name("root", "ext4"),
+            hash: name("root", "verity"),
+        };
+        for (kind, target) in &[
+            ("boot", &images.boot),
+            ("root", &images.root),
+            ("hash", &images.hash),
+        ] {
+            self.add_target(target, compress(&image_data(version, kind)));
+        }
+        images
+    }
+
+    /// Adds the images of an update, as with `add_images`, and lists the update in the manifest.
+    pub fn add_update(&mut self, variant: &str, version: &str) -> &mut Self {
+        let images = self.add_images(variant, version);
+        self.manifest
+            .add_update(
+                Version::parse(version).expect("invalid update version"),
+                None,
+                std::env::consts::ARCH.to_string(),
+                variant.to_string(),
+                images,
+            )
+            .expect("failed to add update to manifest");
+        self
+    }
+
+    /// Records that no migrations are needed between two versions.
+    pub fn add_empty_migration(&mut self, from: &str, to: &str) -> &mut Self {
+        self.manifest
+            .add_migration(
+                false,
+                Version::parse(from).expect("invalid migration version"),
+                Version::parse(to).expect("invalid migration version"),
+                Vec::new(),
+            )
+            .expect("failed to add migration to manifest");
+        self
+    }
+
+    /// Replaces the manifest with one from a file, such as one written by updata.
+    pub fn manifest_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.manifest =
+            update_metadata::load_file(path.as_ref()).expect("failed to load manifest file");
+        self
+    }
+
+    /// Gives access to the manifest, for changes the other methods don't cover.
+    pub fn manifest_mut(&mut self) -> &mut Manifest {
+        &mut self.manifest
+    }
+
+    /// Adds a target file to the repository as-is.
+    pub fn add_target(&mut self, name: &str, data: Vec<u8>) -> &mut Self {
+        self.targets.insert(name.to_string(), data);
+        self
+    }
+
+    /// Signs the repository and writes it to `dir`, with metadata in `dir/metadata` and targets
+    /// in `dir/targets`.  Returns the path to the root.json that clients should trust.
+    pub fn write<P: AsRef<Path>>(&self, dir: P) -> PathBuf {
+        let dir = dir.as_ref();
+        let metadata_dir = dir.join("metadata");
+        let targets_dir = dir.join("targets");
+        fs::create_dir_all(&metadata_dir).expect("failed to create metadata directory");
+        fs::create_dir_all(&targets_dir).expect("failed to create targets directory");
+
+        let key = parse_keypair(TEST_KEY).expect("failed to parse test key");
+        let key_id = key.tuf_key().key_id().expect("failed to get test key ID");
+
+        let mut targets = self.targets.clone();
+        let (index, shards) = self
+            .manifest
+            .serialize_sharded()
+            .expect("failed to serialize manifest");
+        targets.insert("manifest.json".to_string(), index.into_bytes());
+        for (name, shard) in shards {
+            targets.insert(name, shard.into_bytes());
+        }
+        let mut target_meta = HashMap::new();
+        for (name, data) in &targets {
+            fs::write(targets_dir.join(name), data).expect("failed to write target");
+            target_meta.insert(
+                name.clone(),
+                Target {
+                    length: data.len() as u64,
+                    hashes: hashes(data),
+                    custom: HashMap::new(),
+                    _extra: HashMap::new(),
+                },
+            );
+        }
+
+        let one = NonZeroU64::new(1).unwrap();
+        let mut keys = HashMap::new();
+        keys.insert(key_id.clone(), key.tuf_key());
+        let mut roles = HashMap::new();
+        for role in &[
+            RoleType::Root,
+            RoleType::Snapshot,
+            RoleType::Targets,
+            RoleType::Timestamp,
+        ] {
+            roles.insert(
+                *role,
+                RoleKeys {
+                    keyids: vec![key_id.clone()],
+                    threshold: one,
+                    _extra: HashMap::new(),
+                },
+            );
+        }
+        write_role(
+            &metadata_dir,
+            "1.root.json",
+            &key,
+            Root {
+                spec_version: "1.0.0".to_string(),
+                consistent_snapshot: false,
+                version: one,
+                expires: self.expires,
+                keys,
+                roles,
+                _extra: HashMap::new(),
+            },
+        );
+
+        let targets = write_role(
+            &metadata_dir,
+            "targets.json",
+            &key,
+            Targets {
+                spec_version: "1.0.0".to_string(),
+                version: one,
+                expires: self.expires,
+                targets: target_meta,
+                _extra: HashMap::new(),
+            },
+        );
+
+        let mut snapshot_meta = HashMap::new();
+        snapshot_meta.insert(
+            "targets.json".to_string(),
+            SnapshotMeta {
+                length: Some(targets.len() as u64),
+                hashes: Some(hashes(&targets)),
+                version: one,
+                _extra: HashMap::new(),
+            },
+        );
+        let snapshot = write_role(
+            &metadata_dir,
+            "snapshot.json",
+            &key,
+            Snapshot {
+                spec_version: "1.0.0".to_string(),
+                version: one,
+                expires: self.expires,
+                meta: snapshot_meta,
+                _extra: HashMap::new(),
+            },
+        );
+
+        let mut timestamp_meta = HashMap::new();
+        timestamp_meta.insert(
+            "snapshot.json".to_string(),
+            TimestampMeta {
+                length: snapshot.len() as u64,
+                hashes: hashes(&snapshot),
+                version: one,
+                _extra: HashMap::new(),
+            },
+        );
+        write_role(
+            &metadata_dir,
+            "timestamp.json",
+            &key,
+            Timestamp {
+                spec_version: "1.0.0".to_string(),
+                version: one,
+                expires: self.expires,
+                meta: timestamp_meta,
+                _extra: HashMap::new(),
+            },
+        );
+
+        metadata_dir.join("1.root.json")
+    }
+}
+
+/// Signs a role with the test key and writes it to `dir/name`, returning the written bytes so
+/// they can be listed in the roles that refer to it.
+fn write_role<T: Role + Serialize>(dir: &Path, name: &str, key: &impl Sign, role: T) -> Vec<u8> {
+    let message = role.canonical_form().expect("failed to serialize role");
+    let sig = key
+        .sign(&message, &SystemRandom::new())
+        .expect("failed to sign role");
+    let signed = Signed {
+        signed: role,
+        signatures: vec![Signature {
+            keyid: key.tuf_key().key_id().expect("failed to get test key ID"),
+            sig: sig.into(),
+        }],
+    };
+    let data = serde_json::to_vec_pretty(&signed).expect("failed to serialize signed role");
+    fs::write(dir.join(name), &data).expect("failed to write role");
+    data
+}
+
+fn hashes(data: &[u8]) -> Hashes {
+    Hashes {
+        sha256: digest(&SHA256, data).as_ref().to_vec().into(),
+        _extra: HashMap::new(),
+    }
+}
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = lz4::EncoderBuilder::new()
+        .build(Vec::new())
+        .expect("failed to create lz4 encoder");
+    encoder.write_all(data).expect("failed to compress image");
+    let (compressed, result) = encoder.finish();
+    result.expect("failed to compress image");
+    compressed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempfile::TempDir;
+    use tough::{FilesystemTransport, Limits, Repository, Settings};
+
+    #[test]
+    fn written_repo_verifies() {
+        let dir = TempDir::new().unwrap();
+        let mut builder = RepoBuilder::new();
+        builder
+            .add_update("aws-k8s-1.15", "0.2.0")
+            .add_empty_migration("0.1.0", "0.2.0");
+        let root = builder.write(dir.path().join("repo"));
+        let datastore = dir.path().join("datastore");
+        fs::create_dir(&datastore).unwrap();
+
+        let metadata_base_url = format!("file://{}/", dir.path().join("repo/metadata").display());
+        let target_base_url = format!("file://{}/", dir.path().join("repo/targets").display());
+        let repo = Repository::load(
+            &FilesystemTransport,
+            Settings {
+                root: File::open(root).unwrap(),
+                datastore: &datastore,
+                metadata_base_url: &metadata_base_url,
+                target_base_url: &target_base_url,
+                limits: Limits::default(),
+            },
+        )
+        .unwrap();
+
+        let manifest: Manifest =
+            serde_json::from_reader(repo.read_target("manifest.json").unwrap().unwrap()).unwrap();
+        assert_eq!(manifest.updates.len(), 1);
+        let update = &manifest.updates[0];
+        assert_eq!(update.version, Version::parse("0.2.0").unwrap());
+        assert!(repo.read_target(&update.images.root).unwrap().is_some());
+    }
+}
@@ -0,0 +1,241 @@
+/*!
+A minimal HTTP server for update repositories.  It only knows how to answer GET requests for
+files, which is all the update client needs.  It can also be told to misbehave when serving
+particular files, so tests can check how the client handles an unreliable network.
+*/
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::thread;
+use std::time::Duration;
+
+/// How much of a file `Fault::Slow` sends at a time, and how long it waits in between.
+const SLOW_CHUNK: usize = 4;
+const SLOW_DELAY: Duration = Duration::from_millis(50);
+
+/// Whether the update client's HTTP stack can reach a `Server`.  Some sandboxes only allow the
+/// socket calls that std makes, so we find out by making a request with the same client, once per
+/// process.
+pub fn loopback_http_available() -> bool {
+    static PROBE: Once = Once::new();
+    static AVAILABLE: AtomicBool = AtomicBool::new(false);
+    PROBE.call_once(|| {
+        let server = Server::serve(env::temp_dir());
+        let available = reqwest::blocking::get(&format!("{}/", server.url())).is_ok();
+        AVAILABLE.store(available, Ordering::SeqCst);
+    });
+    AVAILABLE.load(Ordering::SeqCst)
+}
+
+/// Ways the server can fail to serve a file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    /// Close the connection without responding, as if the network dropped it.
+    Disconnect,
+    /// Respond with 404 Not Found, as if the file was missing from the repository.
+    NotFound,
+    /// Promise the whole file, but close the connection halfway through sending it.
+    Truncate,
+    /// Send the whole file, with some of its bytes changed.
+    Corrupt,
+    /// Send the whole file, a few bytes at a time, so downloading it takes a while.
+    Slow,
+}
+
+type Faults = Arc<Mutex<HashMap<String, Fault>>>;
+
+/// Serves files from a directory until the test process exits.
+pub struct Server {
+    url: String,
+    faults: Faults,
+}
+
+impl Server {
+    /// Starts serving `dir` on a free port on the loopback interface.
+    pub fn serve<P: AsRef<Path>>(dir: P) -> Self {
+        let dir = dir.as_ref().to_owned();
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind test server");
+        let url = format!(
+            "http://{}",
+            listener.local_addr().expect("test server has no address")
+        );
+        let faults = Faults::default();
+        let server_faults = Arc::clone(&faults);
+        thread::spawn(move || {
+            for stream in listener.incoming().filter_map(|stream| stream.ok()) {
+                let dir = dir.clone();
+                let faults = Arc::clone(&server_faults);
+                thread::spawn(move || handle(&dir, &faults, stream));
+            }
+        });
+        Self { url, faults }
+    }
+
+    /// Fails every request for `path`, relative to the served directory (for example
+    /// "metadata/timestamp.json"), in the given way until the fault is cleared.
+    pub fn inject(&self, path: &str, fault: Fault) {
+        self.faults
+            .lock()
+            .expect("test server faults poisoned")
+            .insert(path.trim_start_matches('/').to_string(), fault);
+    }
+
+    /// Goes back to serving every file normally.
+    pub fn clear_faults(&self) {
+        self.faults
+            .lock()
+            .expect("test server faults poisoned")
+            .clear();
+    }
+
+    /// The base URL of the server, without a trailing slash.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+fn handle(dir: &Path, faults: &Faults, mut stream: TcpStream) {
+    let mut reader = BufReader::new(stream.try_clone().expect("failed to clone stream"));
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    // Skip over the headers; we don't need any of them.
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) if line == "\r\n" || line == "\n" => break,
+            Ok(_) => {}
+        }
+    }
+
+    let mut words = request_line.split_whitespace();
+    let (body, fault) = match (words.next(), words.next()) {
+        (Some("GET"), Some(target)) => match request_path(target) {
+            Some(path) => (
+                fs::read(dir.join(path)).ok(),
+                faults
+                    .lock()
+                    .expect("test server faults poisoned")
+                    .get(path)
+                    .copied(),
+            ),
+            None => (None, None),
+        },
+        _ => (None, None),
+    };
+    let body = match fault {
+        Some(Fault::Disconnect) => {
+            let _ = stream.shutdown(Shutdown::Both);
+            return;
+        }
+        Some(Fault::NotFound) => None,
+        _ => body,
+    };
+    let _ = match body {
+        Some(mut body) => {
+            let len = body.len();
+            match fault {
+                Some(Fault::Truncate) => body.truncate(len / 2),
+                Some(Fault::Corrupt) => {
+                    for byte in body.iter_mut().step_by(64) {
+                        *byte = !*byte;
+                    }
+                }
+                _ => {}
+            }
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                len
+            )
+            .and_then(|()| {
+                if fault != Some(Fault::Slow) {
+                    return stream.write_all(&body);
+                }
+                for chunk in body.chunks(SLOW_CHUNK) {
+                    stream.write_all(chunk)?;
+                    stream.flush()?;
+                    thread::sleep(SLOW_DELAY);
+                }
+                Ok(())
+            })
+        }
+        None => write!(
+            stream,
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        ),
+    };
+}
+
+/// Maps a request target to a path relative to the served directory, ignoring any query string,
+/// and refusing anything that could point outside of it.
+fn request_path(target: &str) -> Option<&str> {
+    let path = target.split('?').next()?.trim_start_matches('/');
+    if path.split('/').any(|part| part == "..") {
+        return None;
+    }
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use tempfile::TempDir;
+
+    /// Requests `path` with a plain socket, returning the status line and body, so we can see what
+    /// the server sent even when it's wrong.
+    fn get(server: &Server, path: &str) -> (String, Vec<u8>) {
+        let mut stream = TcpStream::connect(server.url().trim_start_matches("http://")).unwrap();
+        write!(stream, "GET /{} HTTP/1.1\r\n\r\n", path).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let split = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .map_or(response.len(), |pos| pos + 4);
+        let head = String::from_utf8_lossy(&response[..split]);
+        let status = head.lines().next().unwrap_or_default().to_string();
+        (status, response[split..].to_vec())
+    }
+
+    #[test]
+    fn faults() {
+        let dir = TempDir::new().unwrap();
+        let data = vec![b'x'; 256];
+        fs::write(dir.path().join("file"), &data).unwrap();
+        let server = Server::serve(dir.path());
+
+        assert_eq!(
+            get(&server, "file"),
+            ("HTTP/1.1 200 OK".to_string(), data.clone())
+        );
+        assert_eq!(get(&server, "missing").0, "HTTP/1.1 404 Not Found");
+        assert_eq!(get(&server, "../file").0, "HTTP/1.1 404 Not Found");
+
+        server.inject("file", Fault::Disconnect);
+        assert_eq!(get(&server, "file"), (String::new(), Vec::new()));
+        server.inject("file", Fault::NotFound);
+        assert_eq!(get(&server, "file").0, "HTTP/1.1 404 Not Found");
+        server.inject("file", Fault::Truncate);
+        assert_eq!(get(&server, "file").1, &data[..128]);
+        server.inject("file", Fault::Corrupt);
+        let (status, body) = get(&server, "file");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body.len(), data.len());
+        assert_ne!(body, data);
+        server.inject("file", Fault::Slow);
+        assert_eq!(get(&server, "file").1, data);
+
+        server.clear_faults();
+        assert_eq!(get(&server, "file").1, data);
+    }
+}
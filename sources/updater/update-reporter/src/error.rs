@@ -0,0 +1,47 @@
+use snafu::Snafu;
+use std::path::PathBuf;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub(crate) enum Error {
+    #[snafu(display("Failed to set up logger: {}", source))]
+    Logger { source: simplelog::TermLogError },
+
+    #[snafu(display("Failed to read config file {}: {}", path.display(), source))]
+    ConfigRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to parse config file {}: {}", path.display(), source))]
+    ConfigParse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[snafu(display("Invalid report URL '{}': {}", url, source))]
+    InvalidUrl {
+        url: String,
+        source: url::ParseError,
+    },
+
+    #[snafu(display("Report URL '{}' must use HTTPS", url))]
+    InsecureUrl { url: String },
+
+    #[snafu(display("Unable to get OS version: {}", source))]
+    ReleaseVersion { source: bottlerocket_release::Error },
+
+    #[snafu(display("Failed to build HTTP client: {}", source))]
+    Client { source: reqwest::Error },
+
+    #[snafu(display("Error sending report to {}: {}", url, source))]
+    Send { url: String, source: reqwest::Error },
+
+    #[snafu(display("Error {} when sending report to {}", code, url))]
+    Response {
+        url: String,
+        code: reqwest::StatusCode,
+    },
+}
+
+pub(crate) type Result<T> = std::result::Result<T, Error>;
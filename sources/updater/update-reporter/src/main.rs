@@ -0,0 +1,283 @@
+/*!
+# Introduction
+
+update-reporter periodically sends a report of the host's update health to an HTTPS endpoint
+chosen by the operator, so a fleet's update progress can be followed from one place without
+instrumenting every host.
+
+Reporting is off unless `settings.updates.reporting.url` is set.
+Each report is a JSON object sent in a POST request:
+
+```json
+{
+  "variant": "aws-k8s-1.15",
+  "arch": "x86_64",
+  "version": "0.3.3",
+  "last-check": {
+    "time": "2020-03-26T20:00:00Z",
+    "command": "update-image",
+    "result": "failed",
+    "failure": "network"
+  }
+}
+```
+
+`last-check` comes from the status file updog saves after each run, and is null until updog has
+run.
+`result` is one of `no-update`, `update-available`, `update-scheduled`, `update-written`,
+`update-applied`, `update-prepared`, `activation-scheduled`, `update-activated`, `update-vetoed`,
+or `failed`.
+Failed runs include a broad `failure` class, like `network` or `disk`, rather than the error
+itself.
+`update-version` is included when the result concerns a particular update.
+
+Reports don't include anything that identifies the host, like its name, addresses, instance ID,
+or update seed, though the endpoint can still see the address that each report comes from.
+
+The first report is sent at a random point in the first interval, so that hosts started together
+don't all report at once.
+*/
+
+#![deny(rust_2018_idioms)]
+
+mod error;
+mod report;
+
+use crate::error::Result;
+use crate::report::{LastCheck, Report};
+use bottlerocket_release::BottlerocketRelease;
+use log::{debug, info, warn};
+use rand::{thread_rng, Rng};
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use simplelog::{Config as LogConfig, LevelFilter, TermLogger, TerminalMode};
+use snafu::{ensure, ResultExt};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+use url::Url;
+
+const DEFAULT_CONFIG_PATH: &str = "/etc/update-reporter.toml";
+const DEFAULT_STATUS_PATH: &str = "/var/lib/updog/status.json";
+const DEFAULT_INTERVAL: u64 = 60 * 60;
+
+/// The config file is written from settings; without a URL, reporting is off.
+#[derive(Debug, Default, Deserialize)]
+struct Config {
+    url: Option<String>,
+}
+
+/// Stores user-supplied arguments.
+struct Args {
+    config: PathBuf,
+    status: PathBuf,
+    os_release: Option<PathBuf>,
+    interval: Duration,
+    once: bool,
+    log_level: LevelFilter,
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+fn usage() -> ! {
+    let program_name = std::env::args()
+        .next()
+        .unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            [ --config PATH ]
+            [ --status-file PATH ]
+            [ --os-release PATH ]
+            [ --interval SECONDS ]
+            [ --once ]
+            [ --log-level trace|debug|info|warn|error ]
+
+    Config default: {}
+    Status file default: {}
+    Interval default: {}
+
+    With --once, a single report is sent right away.",
+        program_name, DEFAULT_CONFIG_PATH, DEFAULT_STATUS_PATH, DEFAULT_INTERVAL
+    );
+    process::exit(2);
+}
+
+/// Returns the value given to an option, or exits through usage() if there wasn't one.
+fn arg_value(arg: &str, value: Option<String>) -> String {
+    value.unwrap_or_else(|| usage_msg(format!("Did not give argument to {}", arg)))
+}
+
+/// Parses user arguments into an Args structure.
+fn parse_args(args: std::env::Args) -> Args {
+    let mut config = None;
+    let mut status = None;
+    let mut os_release = None;
+    let mut interval = None;
+    let mut once = false;
+    let mut log_level = None;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--config" => config = Some(PathBuf::from(arg_value(&arg, iter.next()))),
+
+            "--status-file" => status = Some(PathBuf::from(arg_value(&arg, iter.next()))),
+
+            "--os-release" => os_release = Some(PathBuf::from(arg_value(&arg, iter.next()))),
+
+            "--interval" => {
+                let value = arg_value(&arg, iter.next());
+                let seconds = u64::from_str(&value)
+                    .ok()
+                    .filter(|seconds| *seconds > 0)
+                    .unwrap_or_else(|| usage_msg(format!("Invalid interval '{}'", value)));
+                interval = Some(Duration::from_secs(seconds));
+            }
+
+            "--once" => once = true,
+
+            "--log-level" => {
+                let log_level_str = arg_value(&arg, iter.next());
+                log_level = Some(LevelFilter::from_str(&log_level_str).unwrap_or_else(|_| {
+                    usage_msg(format!("Invalid log level '{}'", log_level_str))
+                }));
+            }
+
+            _ => usage(),
+        }
+    }
+
+    Args {
+        config: config.unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH)),
+        status: status.unwrap_or_else(|| PathBuf::from(DEFAULT_STATUS_PATH)),
+        os_release,
+        interval: interval.unwrap_or_else(|| Duration::from_secs(DEFAULT_INTERVAL)),
+        once,
+        log_level: log_level.unwrap_or(LevelFilter::Info),
+    }
+}
+
+/// Loads the config file, returning the URL to send reports to, if reporting is on.  We only
+/// send reports over HTTPS.
+fn report_url(path: &Path) -> Result<Option<Url>> {
+    let data = fs::read_to_string(path).context(error::ConfigRead { path })?;
+    let config: Config = toml::from_str(&data).context(error::ConfigParse { path })?;
+    let url = match config.url {
+        Some(url) => url,
+        None => return Ok(None),
+    };
+    let parsed = Url::parse(&url).context(error::InvalidUrl { url: &url })?;
+    ensure!(parsed.scheme() == "https", error::InsecureUrl { url });
+    Ok(Some(parsed))
+}
+
+/// Sends one report, built from the current release data and updog's last status.
+fn send_report(client: &Client, url: &Url, args: &Args) -> Result<()> {
+    let release = match &args.os_release {
+        Some(path) => BottlerocketRelease::from_file(path),
+        None => BottlerocketRelease::new(),
+    }
+    .context(error::ReleaseVersion)?;
+    let report = Report::new(&release, LastCheck::load(&args.status));
+    debug!("Sending report: {:?}", report);
+
+    let response = client
+        .post(url.clone())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(serde_json::to_vec(&report).expect("report serialization can't fail"))
+        .send()
+        .context(error::Send { url: url.as_str() })?;
+    ensure!(
+        response.status().is_success(),
+        error::Response {
+            url: url.as_str(),
+            code: response.status(),
+        }
+    );
+    Ok(())
+}
+
+fn run() -> Result<()> {
+    let args = parse_args(std::env::args());
+
+    // TerminalMode::Mixed will send errors to stderr and anything less to stdout.
+    TermLogger::init(args.log_level, LogConfig::default(), TerminalMode::Mixed)
+        .context(error::Logger)?;
+
+    let url = match report_url(&args.config)? {
+        Some(url) => url,
+        None => {
+            info!("Update reporting is not enabled");
+            return Ok(());
+        }
+    };
+    let client = Client::builder().build().context(error::Client)?;
+
+    if args.once {
+        return send_report(&client, &url, &args);
+    }
+
+    info!("Reporting update status to {}", url);
+    // Start at a random point in the first interval, so hosts started together don't report
+    // together.
+    let delay = thread_rng().gen_range(0, args.interval.as_secs());
+    thread::sleep(Duration::from_secs(delay));
+    loop {
+        match send_report(&client, &url, &args) {
+            Ok(()) => debug!("Sent report"),
+            Err(e) => warn!("Failed to send report, will retry: {}", e),
+        }
+        thread::sleep(args.interval);
+    }
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn config(contents: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn report_url_from_config() {
+        let file = config("url = \"https://reports.example.com/bottlerocket\"\n");
+        assert_eq!(
+            report_url(file.path()).unwrap().unwrap().as_str(),
+            "https://reports.example.com/bottlerocket"
+        );
+    }
+
+    #[test]
+    fn reporting_off() {
+        let file = config("");
+        assert!(report_url(file.path()).unwrap().is_none());
+    }
+
+    #[test]
+    fn bad_urls() {
+        report_url(config("url = \"http://reports.example.com\"\n").path()).unwrap_err();
+        report_url(config("url = \"reports.example.com\"\n").path()).unwrap_err();
+    }
+}
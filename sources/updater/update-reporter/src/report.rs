@@ -0,0 +1,120 @@
+//! The report we send, built from the release data of the running OS and the status file updog
+//! saves after each run.  Only the fields named here are sent; nothing that identifies the host is
+//! included.
+
+use bottlerocket_release::BottlerocketRelease;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The parts of updog's status file that we pass along.  Anything else in the file stays on the
+/// host.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct LastCheck {
+    time: String,
+    command: String,
+    result: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    update_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    failure: Option<String>,
+}
+
+impl LastCheck {
+    /// Reads updog's status file.  Returns `None` if updog hasn't saved one yet, or if it can't
+    /// be read, since the rest of the report is still useful.
+    pub(crate) fn load(path: &Path) -> Option<Self> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                debug!("No update status at {}", path.display());
+                return None;
+            }
+            Err(e) => {
+                warn!("Failed to read update status {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        match serde_json::from_slice(&data) {
+            Ok(last_check) => Some(last_check),
+            Err(e) => {
+                warn!("Failed to parse update status {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Report {
+    variant: String,
+    arch: String,
+    version: String,
+    last_check: Option<LastCheck>,
+}
+
+impl Report {
+    pub(crate) fn new(release: &BottlerocketRelease, last_check: Option<LastCheck>) -> Self {
+        Self {
+            variant: release.variant_id.clone(),
+            arch: release.arch.clone(),
+            version: release.version_id.to_string(),
+            last_check,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use semver::Version;
+    use serde_json::json;
+
+    fn release() -> BottlerocketRelease {
+        BottlerocketRelease {
+            pretty_name: "Bottlerocket OS 0.3.3".to_string(),
+            variant_id: "aws-k8s-1.15".to_string(),
+            version_id: Version::parse("0.3.3").unwrap(),
+            build_id: "0123abcd".to_string(),
+            arch: "x86_64".to_string(),
+        }
+    }
+
+    #[test]
+    fn report_json() {
+        let last_check: LastCheck = serde_json::from_value(json!({
+            "time": "2020-03-26T20:00:00Z",
+            "command": "update-image",
+            "result": "failed",
+            "failure": "network",
+            "something-new": "stays on the host",
+        }))
+        .unwrap();
+        let report = serde_json::to_value(Report::new(&release(), Some(last_check))).unwrap();
+        assert_eq!(
+            report,
+            json!({
+                "variant": "aws-k8s-1.15",
+                "arch": "x86_64",
+                "version": "0.3.3",
+                "last-check": {
+                    "time": "2020-03-26T20:00:00Z",
+                    "command": "update-image",
+                    "result": "failed",
+                    "failure": "network",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn missing_status() {
+        assert_eq!(LastCheck::load(Path::new("/nonexistent/status.json")), None);
+        let report = serde_json::to_value(Report::new(&release(), None)).unwrap();
+        assert_eq!(report["last-check"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,27 @@
+//! Loads a manifest from files, the way updata does, with `load_file`.
+//!
+//! The input is split on NUL bytes: the first part is the index manifest, and the rest are
+//! written next to it as `shard-0.json`, `shard-1.json`, and so on, so an index that lists them
+//! as shards gets them loaded too.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use std::fs;
+use update_metadata::Manifest;
+
+fuzz_target!(|data: &[u8]| {
+    let dir = tempfile::tempdir().unwrap();
+    let mut parts = data.split(|&b| b == 0);
+    let path = dir.path().join("manifest.json");
+    fs::write(&path, parts.next().unwrap_or_default()).unwrap();
+    for (n, part) in parts.enumerate() {
+        fs::write(dir.path().join(format!("shard-{}.json", n)), part).unwrap();
+    }
+
+    if let Ok(manifest) = update_metadata::load_file(&path) {
+        // Whatever loads has to write out as something that loads back the same.
+        let written = serde_json::to_vec(&manifest).unwrap();
+        let reread: Manifest = serde_json::from_slice(&written).unwrap();
+        assert_eq!(serde_json::to_vec(&reread).unwrap(), written);
+    }
+});
@@ -0,0 +1,31 @@
+//! Parses a manifest for one host, the way updog does, with `from_slice_for`, and checks that it
+//! finds the same updates as a full parse.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use update_metadata::Manifest;
+
+const VARIANT: &str = "bottlerocket-aws-eks";
+const ARCH: &str = "x86_64";
+
+fuzz_target!(|data: &[u8]| {
+    let host = update_metadata::from_slice_for(data, VARIANT, ARCH);
+    let stream = update_metadata::from_reader_for(data, VARIANT, ARCH);
+    assert_eq!(host.is_ok(), stream.is_ok());
+
+    // Where a full parse, which parses every update rather than skipping over the ones for
+    // other hosts, also succeeds, the two have to agree.
+    if let (Ok(host), Ok(full)) = (host, serde_json::from_slice::<Manifest>(data)) {
+        let expected: Vec<_> = full
+            .updates
+            .iter()
+            .filter(|u| u.variant == VARIANT && u.arch == ARCH)
+            .collect();
+        assert_eq!(
+            serde_json::to_value(&host.updates).unwrap(),
+            serde_json::to_value(&expected).unwrap()
+        );
+        assert_eq!(host.migrations, full.migrations);
+        assert_eq!(host.shards, full.shards);
+    }
+});
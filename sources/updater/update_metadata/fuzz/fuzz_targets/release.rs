@@ -0,0 +1,20 @@
+//! Parses a Release.toml, the way updata does when it's given one to take migrations or the
+//! version from.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use update_metadata::Release;
+
+fuzz_target!(|data: &[u8]| {
+    let data = match std::str::from_utf8(data) {
+        Ok(data) => data,
+        Err(_) => return,
+    };
+    if let Ok(release) = toml::from_str::<Release>(data) {
+        // Whatever parses has to write out as something that parses back the same.
+        let written = toml::to_string(&release).unwrap();
+        let reread: Release = toml::from_str(&written).unwrap();
+        assert_eq!(reread.version, release.version);
+        assert_eq!(reread.migrations, release.migrations);
+    }
+});
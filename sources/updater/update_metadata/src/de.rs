@@ -1,11 +1,17 @@
 use crate::error;
+use crate::{compatible_variants, Compatibility, Manifest, Update};
 use chrono::{DateTime, Utc};
 use regex::Regex;
 use semver::Version;
-use serde::{de::Error as _, Deserializer};
+use serde::de::{DeserializeSeed, Error as _, IgnoredAny};
+use serde::{Deserialize, Deserializer};
+use serde_json::value::RawValue;
 use snafu::{ensure, ResultExt};
+use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Deref;
 
 /// Converts the bound key to an integer before insertion and catches duplicates
 pub(crate) fn deserialize_bound<'de, D>(
@@ -123,3 +129,154 @@ where
 
     deserializer.deserialize_map(Visitor)
 }
+
+/// Parses a manifest keeping only the updates for one variant and architecture.  Each update is
+/// first read as raw JSON, of type `R`, and only its variant and arch are parsed, borrowed from
+/// it; the versions, waves, and the rest are only parsed for the updates we keep.  So neither
+/// memory use nor parse time grow much with the updates for the rest of the fleet.
+///
+/// `R` is `Box<RawValue>` when reading from a stream, and `&RawValue` when parsing a slice, so
+/// the raw JSON is borrowed from the slice rather than copied.
+pub(crate) struct ManifestFor<'a, R> {
+    pub(crate) variant: &'a str,
+    pub(crate) arch: &'a str,
+    pub(crate) raw: PhantomData<R>,
+}
+
+impl<'de, 'a, R> DeserializeSeed<'de> for ManifestFor<'a, R>
+where
+    R: Deserialize<'de> + Deref<Target = RawValue>,
+{
+    type Value = Manifest;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 'a, R> serde::de::Visitor<'de> for ManifestFor<'a, R>
+where
+    R: Deserialize<'de> + Deref<Target = RawValue>,
+{
+    type Value = Manifest;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a manifest")
+    }
+
+    fn visit_map<M>(self, mut access: M) -> Result<Self::Value, M::Error>
+    where
+        M: serde::de::MapAccess<'de>,
+    {
+        let mut compatibility: Option<Vec<Compatibility>> = None;
+        let mut updates = None;
+        let mut migrations = None;
+        let mut shards = None;
+        while let Some(key) = access.next_key::<String>()? {
+            match key.as_str() {
+                "compatibility" => {
+                    let rules: Vec<Compatibility> = access.next_value()?;
+                    // The updates for other variants were dropped as they were read, so rules
+                    // that come after them can't let this host take some of them.
+                    if updates.is_some()
+                        && compatible_variants(&rules, self.variant, self.arch) != [self.variant]
+                    {
+                        return Err(M::Error::custom(
+                            "compatibility rules must come before the updates",
+                        ));
+                    }
+                    compatibility = Some(rules);
+                }
+                "updates" => {
+                    let rules = compatibility.as_deref().unwrap_or_default();
+                    updates = Some(access.next_value_seed(UpdatesFor::<R> {
+                        variants: compatible_variants(rules, self.variant, self.arch),
+                        arch: self.arch,
+                        raw: PhantomData,
+                    })?);
+                }
+                "migrations" => migrations = Some(access.next_value::<Migrations>()?.0),
+                "shards" => shards = Some(access.next_value()?),
+                _ => {
+                    access.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(Manifest {
+            compatibility: compatibility.unwrap_or_default(),
+            updates: updates.ok_or_else(|| M::Error::missing_field("updates"))?,
+            migrations: migrations.ok_or_else(|| M::Error::missing_field("migrations"))?,
+            shards: shards.unwrap_or_default(),
+        })
+    }
+}
+
+struct UpdatesFor<'a, R> {
+    variants: Vec<&'a str>,
+    arch: &'a str,
+    raw: PhantomData<R>,
+}
+
+impl<'de, 'a, R> DeserializeSeed<'de> for UpdatesFor<'a, R>
+where
+    R: Deserialize<'de> + Deref<Target = RawValue>,
+{
+    type Value = Vec<Update>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a, R> serde::de::Visitor<'de> for UpdatesFor<'a, R>
+where
+    R: Deserialize<'de> + Deref<Target = RawValue>,
+{
+    type Value = Vec<Update>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a list of updates")
+    }
+
+    fn visit_seq<S>(self, mut access: S) -> Result<Self::Value, S::Error>
+    where
+        S: serde::de::SeqAccess<'de>,
+    {
+        let mut updates = Vec::new();
+        while let Some(raw) = access.next_element::<R>()? {
+            let key: UpdateKey<'_> = serde_json::from_str(raw.get()).map_err(S::Error::custom)?;
+            if key.arch == self.arch && self.variants.contains(&key.variant.as_ref()) {
+                updates.push(serde_json::from_str(raw.get()).map_err(S::Error::custom)?);
+            }
+        }
+        Ok(updates)
+    }
+}
+
+/// The fields of an update that say which hosts it's for.  They're borrowed from the update's
+/// JSON unless they have escapes in them.
+#[derive(Deserialize)]
+struct UpdateKey<'a> {
+    #[serde(borrow)]
+    variant: Cow<'a, str>,
+    #[serde(borrow)]
+    arch: Cow<'a, str>,
+}
+
+/// The manifest's migrations, parsed like the `migrations` field of a `Manifest`.
+struct Migrations(BTreeMap<(Version, Version), Vec<String>>);
+
+impl<'de> Deserialize<'de> for Migrations {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_migration(deserializer).map(Migrations)
+    }
+}
@@ -58,6 +58,12 @@ pub enum Error {
     #[snafu(display("Duplicate version key: {}", key))]
     DuplicateVersionKey { backtrace: Backtrace, key: String },
 
+    #[snafu(display(
+        "Manifest file {} changed since it was loaded, so nothing was written; load it again and redo the change",
+        path.display()
+    ))]
+    ManifestChanged { path: PathBuf, backtrace: Backtrace },
+
     #[snafu(display("Failed to parse updates manifest: {}", source))]
     ManifestParse {
         source: serde_json::Error,
@@ -103,6 +109,45 @@ pub enum Error {
         to: Version,
     },
 
+    #[snafu(display(
+        "Failed to fetch manifest part after {} attempt(s): {}",
+        attempts,
+        source
+    ))]
+    PartFetch {
+        attempts: u32,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display("Failed to read manifest shard {}: {}", name, source))]
+    ShardRead {
+        name: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display("Invalid shard name '{}'; shards must be plain file names", name))]
+    ShardName { name: String, backtrace: Backtrace },
+
+    #[snafu(display("Failed to parse manifest shard {}: {}", path.display(), source))]
+    ShardParse {
+        path: PathBuf,
+        source: serde_json::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Manifest shard {} is for variant '{}' but has an update for '{}'",
+        path.display(),
+        expected,
+        found
+    ))]
+    ShardVariant {
+        path: PathBuf,
+        expected: String,
+        found: String,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Failed to serialize update information: {}", source))]
     UpdateSerialize {
         source: serde_json::Error,
@@ -112,9 +157,49 @@ pub enum Error {
     #[snafu(display("Waves are not ordered; percentages and dates must be in ascending order"))]
     WavesUnordered,
 
+    #[snafu(display("More than one compatibility rule for {} on {}", variant, arch))]
+    CompatibilityDuplicate { variant: String, arch: String },
+
+    #[snafu(display(
+        "The compatibility rule for {} on {} lists {} more than once",
+        variant,
+        arch,
+        target
+    ))]
+    CompatibilityRepeated {
+        variant: String,
+        arch: String,
+        target: String,
+    },
+
     #[snafu(display(
         "`fleet_percentage` must be a value between 1 - 100: value provided: {}",
         provided
     ))]
     InvalidFleetPercentage { provided: u32 },
+
+    #[snafu(display(
+        "A wave has to start before seed {}, the end of the fleet; seed provided: {}",
+        crate::MAX_SEED,
+        provided
+    ))]
+    InvalidWaveBound { provided: u32 },
+
+    #[snafu(display(
+        "Update has {} waves, but {} wave bounds were given",
+        expected,
+        provided
+    ))]
+    WaveBoundCount { expected: usize, provided: usize },
+
+    #[snafu(display(
+        "Invalid seed range {}..{}; the start must be less than the end, which can be at most {}",
+        start,
+        end,
+        crate::MAX_SEED
+    ))]
+    InvalidSeedRange { start: u32, end: u32 },
+
+    #[snafu(display("Provenance digest '{}' isn't a hex SHA-256 digest", digest))]
+    InvalidProvenanceDigest { digest: String },
 }
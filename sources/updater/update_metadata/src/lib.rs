@@ -6,22 +6,92 @@ mod se;
 
 use chrono::{DateTime, Duration, Utc};
 use migrator::MIGRATION_FILENAME_RE;
-use parse_datetime::parse_datetime;
+use parse_datetime::parse_datetime_at;
 use rand::{thread_rng, Rng};
+use ring::digest::{digest, SHA256};
 use semver::Version;
+use serde::de::DeserializeSeed;
 use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
 use snafu::{ensure, OptionExt, ResultExt};
+use std::cell::Cell;
 use std::collections::BTreeMap;
 use std::fs;
 use std::fs::File;
+use std::io::Read;
+use std::marker::PhantomData;
 use std::ops::Bound::{Excluded, Included};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::SystemTime;
 
 use crate::error::Result;
 
 pub const MAX_SEED: u32 = 2048;
 
+/// The seed a wave starts at to follow `percentage` of the fleet, the way `set_waves` turns each
+/// wave's share of the fleet into the seed the next wave starts at.  Percentages over 100 count as
+/// the whole fleet.
+pub fn percentage_seed(percentage: u32) -> u32 {
+    percentage.min(100) * MAX_SEED / 100
+}
+
+/// The share of the fleet with seeds before `seed`, as a percentage.
+pub fn seed_percentage(seed: u32) -> f64 {
+    f64::from(seed) * 100.0 / f64::from(MAX_SEED)
+}
+
+/// Where wave logic gets the current time.  Hosts don't always have the right time, so callers
+/// can give one that's checked against something they trust more than the system clock, like
+/// the repository's signed metadata, and tests can give a time of their choosing.
+pub trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> DateTime<Utc> {
+        (*self).now()
+    }
+}
+
+/// The system clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that reads whatever time it was last set to, for testing wave logic at any time.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Cell<DateTime<Utc>>,
+}
+
+impl MockClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Cell::new(now),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        self.now.set(now);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.now.get()
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum Wave {
     Initial {
@@ -37,18 +107,18 @@ pub enum Wave {
 }
 
 impl Wave {
-    pub fn has_started(&self) -> bool {
+    pub fn has_started(&self, clock: &dyn Clock) -> bool {
         match self {
             Self::Initial { .. } => true,
-            Self::General { start, .. } | Self::Last { start } => *start <= Utc::now(),
+            Self::General { start, .. } | Self::Last { start } => *start <= clock.now(),
         }
     }
 
-    pub fn has_passed(&self) -> bool {
+    pub fn has_passed(&self, clock: &dyn Clock) -> bool {
         match self {
-            Self::Initial { end } => *end <= Utc::now(),
-            Self::General { end, .. } => *end <= Utc::now(),
-            Self::Last { start } => *start <= Utc::now(),
+            Self::Initial { end } => *end <= clock.now(),
+            Self::General { end, .. } => *end <= clock.now(),
+            Self::Last { start } => *start <= clock.now(),
         }
     }
 }
@@ -66,14 +136,95 @@ pub struct UpdateWave {
     pub fleet_percentage: u32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A range of seeds, including `start` but not `end`, like the seeds of the first half of the
+/// fleet: `{ "start": 0, "end": 1024 }`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeedRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl SeedRange {
+    pub fn contains(&self, seed: u32) -> bool {
+        self.start <= seed && seed < self.end
+    }
+}
+
+/// Restricts an update to part of the fleet, so a fix can go only to the hosts that need it.  A
+/// host is targeted if its seed is in one of `seeds`, or `seeds` is empty, and it has every label
+/// in `labels` with the same value.  Hosts get their labels from `settings.updates.labels`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Targeting {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub seeds: Vec<SeedRange>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl Targeting {
+    pub fn matches(&self, seed: u32, labels: &BTreeMap<String, String>) -> bool {
+        (self.seeds.is_empty() || self.seeds.iter().any(|range| range.contains(seed)))
+            && self
+                .labels
+                .iter()
+                .all(|(key, value)| labels.get(key) == Some(value))
+    }
+}
+
+/// A provenance document for an update, like an in-toto statement with a SLSA predicate, that
+/// says how its images were built.  The document is a target in the repository, so it's signed
+/// like the images; the digest ties the update to the exact document it was published with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// The target name of the document.
+    pub target: String,
+    /// The hex SHA-256 digest of the document.
+    pub sha256: String,
+}
+
+/// Says which variants' updates the hosts of a variant may take, for moving hosts to another
+/// variant, or holding back an architecture.  Without a rule, hosts only take updates for their
+/// own variant.  A rule for the hosts' architecture takes precedence over one for every
+/// architecture.  Hosts always stay on their own architecture.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Compatibility {
+    pub variant: String,
+    /// The architecture of the hosts the rule is for, or every architecture if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+    /// The variants whose updates the hosts may take.  Their own variant has to be listed for them
+    /// to keep taking its updates, so an empty list holds them where they are.
+    pub updates_to: Vec<String>,
+}
+
+/// Returns the variants whose updates the hosts of `variant` and `arch` may take under `rules`.
+pub(crate) fn compatible_variants<'a>(
+    rules: &'a [Compatibility],
+    variant: &'a str,
+    arch: &str,
+) -> Vec<&'a str> {
+    let rule = rules
+        .iter()
+        .find(|rule| rule.variant == variant && rule.arch.as_deref() == Some(arch))
+        .or_else(|| {
+            rules
+                .iter()
+                .find(|rule| rule.variant == variant && rule.arch.is_none())
+        });
+    match rule {
+        Some(rule) => rule.updates_to.iter().map(String::as_str).collect(),
+        None => vec![variant],
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Images {
     pub boot: String,
     pub root: String,
     pub hash: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Update {
     pub variant: String,
     pub arch: String,
@@ -82,14 +233,47 @@ pub struct Update {
     #[serde(deserialize_with = "de::deserialize_bound")]
     pub waves: BTreeMap<u32, DateTime<Utc>>,
     pub images: Images,
+    /// When the rollout of this update was paused, if it's paused.  Hosts that haven't taken the
+    /// update aren't offered it until the rollout is resumed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paused: Option<DateTime<Utc>>,
+    /// The part of the fleet this update is for, if it's not for every host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub targeting: Option<Targeting>,
+    /// Where to find the provenance of the update's images, for hosts that check it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+    /// Whether hosts may take the update.  A deprecated update is kept, along with its
+    /// migrations, for the hosts already running it, but no host is offered it.
+    #[serde(default = "default_available", skip_serializing_if = "is_available")]
+    pub available: bool,
+}
+
+fn default_available() -> bool {
+    true
+}
+
+// serde's skip_serializing_if passes the field by reference.
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_available(available: &bool) -> bool {
+    *available
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Manifest {
+    /// Which variants' updates hosts may take, when it's not just their own.  The rules come
+    /// first, so hosts know which updates to keep as they read the rest.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub compatibility: Vec<Compatibility>,
     pub updates: Vec<Update>,
     #[serde(deserialize_with = "de::deserialize_migration")]
     #[serde(serialize_with = "se::serialize_migration")]
     pub migrations: BTreeMap<(Version, Version), Vec<String>>,
+    /// The shard files that hold the updates for some variants, by variant, so each host only
+    /// fetches the updates for its own variant.  The index manifest lists every other update and
+    /// all the migrations.  Shard files are kept next to the index, and are TUF targets like it.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub shards: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -103,17 +287,243 @@ pub struct Release {
     pub migrations: BTreeMap<(Version, Version), Vec<String>>,
 }
 
+/// Parses a manifest from `reader`, keeping only the updates for `variant` and `arch`, along with
+/// all the migrations and shards.  Updates for other variants are dropped as they're read, so
+/// hosts with little memory can read a manifest for a large fleet.
+pub fn from_reader_for<R: Read>(reader: R, variant: &str, arch: &str) -> Result<Manifest> {
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let manifest = de::ManifestFor::<Box<RawValue>> {
+        variant,
+        arch,
+        raw: PhantomData,
+    }
+    .deserialize(&mut deserializer)
+    .context(error::ManifestParse)?;
+    deserializer.end().context(error::ManifestParse)?;
+    Ok(manifest)
+}
+
+/// Parses a manifest from `data` like `from_reader_for`.  The other updates are skipped over in
+/// place rather than copied out, which makes this more than twice as fast as `from_reader_for`
+/// or a full parse, for callers that can hold the manifest in memory.
+pub fn from_slice_for(data: &[u8], variant: &str, arch: &str) -> Result<Manifest> {
+    let mut deserializer = serde_json::Deserializer::from_slice(data);
+    let manifest = de::ManifestFor::<&RawValue> {
+        variant,
+        arch,
+        raw: PhantomData,
+    }
+    .deserialize(&mut deserializer)
+    .context(error::ManifestParse)?;
+    deserializer.end().context(error::ManifestParse)?;
+    Ok(manifest)
+}
+
+/// Loads the manifest at `path`, along with the updates from any shard files it lists.
 pub fn load_file(path: &Path) -> Result<Manifest> {
     let file = File::open(path).context(error::ManifestRead { path })?;
-    serde_json::from_reader(file).context(error::ManifestParse)
+    let mut manifest: Manifest = serde_json::from_reader(file).context(error::ManifestParse)?;
+    for (variant, name) in &manifest.shards {
+        let path = shard_path(path, name)?;
+        let file = File::open(&path).context(error::ManifestRead { path: &path })?;
+        let shard: Manifest =
+            serde_json::from_reader(file).context(error::ShardParse { path: &path })?;
+        add_shard(&mut manifest.updates, variant, &path, shard)?;
+    }
+    Ok(manifest)
+}
+
+/// What the files of a manifest held when it was loaded with `load_file_versioned`, so it can be
+/// written back with `write_file_if_unchanged` only if nobody else has written it since.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestVersion {
+    files: BTreeMap<PathBuf, FileVersion>,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+struct FileVersion {
+    modified: Option<SystemTime>,
+    len: u64,
+    sha256: Vec<u8>,
+}
+
+impl FileVersion {
+    /// Reads the file at `path`, returning its contents and its version.
+    fn read(path: &Path) -> Result<(Vec<u8>, Self)> {
+        let modified = fs::metadata(path)
+            .context(error::ManifestRead { path })?
+            .modified()
+            .ok();
+        let data = fs::read(path).context(error::ManifestRead { path })?;
+        let version = Self {
+            modified,
+            len: data.len() as u64,
+            sha256: digest(&SHA256, &data).as_ref().to_vec(),
+        };
+        Ok((data, version))
+    }
+
+    /// Checks whether the file at `path` still has this version.  A file with the same contents
+    /// is unchanged even if it was written again; its modification time only lets us skip
+    /// reading it when that hasn't changed either.
+    fn is_current(&self, path: &Path) -> Result<bool> {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e).context(error::ManifestRead { path }),
+        };
+        if metadata.modified().ok() == self.modified && metadata.len() == self.len {
+            return Ok(true);
+        }
+        let (_, current) = Self::read(path)?;
+        Ok(current.sha256 == self.sha256)
+    }
+}
+
+/// Loads the manifest at `path` like `load_file`, along with the version of each file it was
+/// loaded from.
+pub fn load_file_versioned(path: &Path) -> Result<(Manifest, ManifestVersion)> {
+    let mut files = BTreeMap::new();
+    let (index, version) = FileVersion::read(path)?;
+    files.insert(path.to_path_buf(), version);
+    let manifest = from_stored(&index, |name| -> Result<Vec<u8>> {
+        let path = shard_path(path, name)?;
+        let (data, version) = FileVersion::read(&path)?;
+        files.insert(path, version);
+        Ok(data)
+    })?;
+    Ok((manifest, ManifestVersion { files }))
+}
+
+/// Writes the manifest to `path` like `write_file`, but only if none of the files it was loaded
+/// from have changed since `version` was taken, so a publisher can't silently discard changes
+/// someone else wrote in the meantime.  Returns the version of the files as written, for the
+/// next write.
+///
+/// Every file is checked before any is written, which leaves a moment between the check and the
+/// write rather than closing the window entirely; publishers that can't take turns should keep
+/// the manifest somewhere with conditional writes, like S3.
+pub fn write_file_if_unchanged(
+    path: &Path,
+    manifest: &Manifest,
+    version: &ManifestVersion,
+) -> Result<ManifestVersion> {
+    for (file, file_version) in &version.files {
+        ensure!(
+            file_version.is_current(file)?,
+            error::ManifestChanged { path: file }
+        );
+    }
+    write_file(path, manifest)?;
+
+    let mut files = BTreeMap::new();
+    files.insert(path.to_path_buf(), FileVersion::read(path)?.1);
+    for name in manifest.shards.values() {
+        let path = shard_path(path, name)?;
+        let (_, version) = FileVersion::read(&path)?;
+        files.insert(path, version);
+    }
+    Ok(ManifestVersion { files })
+}
+
+/// Parses a manifest as it's stored, from the index manifest and `read_shard`, which reads each
+/// shard file the index lists, by name.  This is for manifests that aren't kept in local files;
+/// see `load_file` for those.
+pub fn from_stored<F, E>(index: &[u8], mut read_shard: F) -> Result<Manifest>
+where
+    F: FnMut(&str) -> std::result::Result<Vec<u8>, E>,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let mut manifest: Manifest = serde_json::from_slice(index).context(error::ManifestParse)?;
+    for (variant, name) in &manifest.shards {
+        check_shard_name(name)?;
+        let data = read_shard(name)
+            .map_err(Into::into)
+            .context(error::ShardRead { name })?;
+        let path = Path::new(name);
+        let shard: Manifest = serde_json::from_slice(&data).context(error::ShardParse { path })?;
+        add_shard(&mut manifest.updates, variant, path, shard)?;
+    }
+    Ok(manifest)
+}
+
+/// Adds the updates from the shard file for `variant`, found at `path`, to `updates`, checking
+/// that they're all for that variant.
+fn add_shard(updates: &mut Vec<Update>, variant: &str, path: &Path, shard: Manifest) -> Result<()> {
+    for update in &shard.updates {
+        ensure!(
+            update.variant == variant,
+            error::ShardVariant {
+                path,
+                expected: variant,
+                found: &update.variant
+            }
+        );
+    }
+    updates.extend(shard.updates);
+    Ok(())
+}
+
+/// Writes the manifest to `path`, and the updates for sharded variants to their shard files.
 pub fn write_file(path: &Path, manifest: &Manifest) -> Result<()> {
-    let manifest = serde_json::to_string_pretty(&manifest).context(error::UpdateSerialize)?;
-    fs::write(path, &manifest).context(error::ManifestWrite { path })?;
+    let (index, shards) = manifest.serialize_sharded()?;
+    for (name, shard) in &shards {
+        let path = shard_path(path, name)?;
+        fs::write(&path, shard).context(error::ManifestWrite { path: &path })?;
+    }
+    fs::write(path, &index).context(error::ManifestWrite { path })?;
     Ok(())
 }
 
+/// Returns the path of the shard file `name` next to the index manifest at `index`.  Shard names
+/// are target names, so they must be plain file names.
+fn shard_path(index: &Path, name: &str) -> Result<PathBuf> {
+    check_shard_name(name)?;
+    Ok(index.parent().unwrap_or_else(|| Path::new("")).join(name))
+}
+
+fn check_shard_name(name: &str) -> Result<()> {
+    ensure!(
+        !name.is_empty() && !name.contains('/') && name != "." && name != "..",
+        error::ShardName { name }
+    );
+    Ok(())
+}
+
+/// Fetches part of a manifest with `fetch` and uses it to refresh the updates for `variant` and
+/// `arch` in `manifest`, returns the number of updates taken from it; see
+/// `Manifest::refresh_part`.  A failed fetch, or a part that doesn't parse, say because the
+/// connection dropped partway, is tried again, up to `attempts` times in all.  `fetch` is given
+/// the number of the attempt, starting at 1, so it can back off between them.  If every attempt
+/// fails, `manifest` is left as it was, so the caller can carry on with what it last fetched.
+pub fn refresh_part<F, R, E>(
+    manifest: &mut Manifest,
+    variant: &str,
+    arch: &str,
+    attempts: u32,
+    mut fetch: F,
+) -> Result<usize>
+where
+    F: FnMut(u32) -> std::result::Result<R, E>,
+    R: Read,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let part = fetch(attempt)
+            .map_err(Into::into)
+            .and_then(|reader| from_reader_for(reader, variant, arch).map_err(Into::into));
+        match part {
+            Ok(part) => return manifest.refresh_part(variant, arch, part),
+            Err(e) if attempt >= attempts => {
+                return Err(e).context(error::PartFetch { attempts: attempt })
+            }
+            Err(_) => {}
+        }
+    }
+}
+
 impl Manifest {
     pub fn add_migration(
         &mut self,
@@ -182,6 +592,10 @@ impl Manifest {
             max_version: max_version.clone(),
             images,
             waves: BTreeMap::new(),
+            paused: None,
+            targeting: None,
+            provenance: None,
+            available: true,
         };
         self.update_max_version(
             &update.max_version,
@@ -193,13 +607,14 @@ impl Manifest {
     }
 
     /// Update the maximum version for all updates that optionally match the
-    /// architecture and variant of some new update.
+    /// architecture and variant of some new update.  Returns the number of
+    /// updates that matched.
     pub fn update_max_version(
         &mut self,
         version: &Version,
         arch: Option<&str>,
         variant: Option<&str>,
-    ) {
+    ) -> usize {
         let matching: Vec<&mut Update> = self
             .updates
             .iter_mut()
@@ -210,9 +625,39 @@ impl Manifest {
                 _ => true,
             })
             .collect();
+        let num_matching = matching.len();
         for u in matching {
             u.max_version = version.clone();
         }
+        num_matching
+    }
+
+    /// Walks the migration chain from `from` towards the newer version `to`, the way hosts do.
+    /// Where more than one transition starts at a version, like (1.0.0, 1.1.0) and (1.0.0,
+    /// 1.2.0), the one that goes furthest without passing `to` is taken.  Returns the transitions
+    /// taken and the version the walk reached, which is `to` only if there's a complete path.
+    pub fn migration_path<'a>(
+        &'a self,
+        from: &'a Version,
+        to: &Version,
+    ) -> (Vec<&'a (Version, Version)>, &'a Version) {
+        let mut path = Vec::new();
+        let mut version = from;
+        while version != to {
+            let next = self
+                .migrations
+                .keys()
+                .filter(|(f, t)| f == version && t <= to)
+                .max_by(|(_, a), (_, b)| a.cmp(b));
+            match next {
+                Some(transition) => {
+                    path.push(transition);
+                    version = &transition.1;
+                }
+                None => break,
+            }
+        }
+        (path, version)
     }
 
     // Ensures wave dates and bounds are in ascending order.
@@ -280,6 +725,9 @@ impl Manifest {
     ) -> Result<usize> {
         let matching = self.get_matching_updates(variant, arch, image_version);
         let num_matching = matching.len();
+        // Relative start times all count from the same moment, so the waves keep the spacing
+        // they were given.
+        let now = Utc::now();
 
         for update in matching {
             update.waves.clear();
@@ -294,21 +742,379 @@ impl Manifest {
                     }
                 );
 
-                let start_time = parse_datetime(&wave.start_after).context(error::BadDateTime {
-                    datetime: &wave.start_after,
-                })?;
+                let start_time =
+                    parse_datetime_at(&wave.start_after, now).context(error::BadDateTime {
+                        datetime: &wave.start_after,
+                    })?;
                 update.waves.insert(seed, start_time);
 
-                // Get the appropriate seed from the percentage given
-                // First get the percentage as a decimal,
-                let percent = wave.fleet_percentage as f32 / 100 as f32;
-                // then, get seed from the percentage of MAX_SEED as a u32
-                seed = (percent * MAX_SEED as f32) as u32;
+                // The next wave starts where this one's share of the fleet ends
+                seed = percentage_seed(wave.fleet_percentage);
+            }
+        }
+        Self::validate_updates(&self.updates)?;
+        Ok(num_matching)
+    }
+
+    /// Moves the updates for `variants`, or for every variant if none are given, into their own
+    /// shard files the next time the manifest is written.  Returns the variants newly sharded.
+    pub fn shard(&mut self, variants: &[String]) -> Vec<String> {
+        let variants: Vec<String> = if variants.is_empty() {
+            self.updates
+                .iter()
+                .map(|update| update.variant.clone())
+                .collect()
+        } else {
+            variants.to_vec()
+        };
+        let mut sharded = Vec::new();
+        for variant in variants {
+            if !self.shards.contains_key(&variant) {
+                self.shards
+                    .insert(variant.clone(), format!("manifest-{}.json", variant));
+                sharded.push(variant);
+            }
+        }
+        sharded
+    }
+
+    /// Serializes the manifest the way it's stored: the index manifest, and the shard file for
+    /// each sharded variant, by name.
+    pub fn serialize_sharded(&self) -> Result<(String, BTreeMap<String, String>)> {
+        let mut index = serde_json::to_value(self).context(error::UpdateSerialize)?;
+        let unsharded: Vec<&Update> = self
+            .updates
+            .iter()
+            .filter(|update| !self.shards.contains_key(&update.variant))
+            .collect();
+        index["updates"] = serde_json::to_value(unsharded).context(error::UpdateSerialize)?;
+        let index = serde_json::to_string_pretty(&index).context(error::UpdateSerialize)?;
+
+        let mut shards = BTreeMap::new();
+        for (variant, name) in &self.shards {
+            let updates: Vec<&Update> = self
+                .updates
+                .iter()
+                .filter(|update| &update.variant == variant)
+                .collect();
+            let shard = serde_json::json!({ "updates": updates, "migrations": {} });
+            shards.insert(
+                name.clone(),
+                serde_json::to_string_pretty(&shard).context(error::UpdateSerialize)?,
+            );
+        }
+        Ok((index, shards))
+    }
+
+    /// Returns the updates for `variant` and `arch` that could be applied, newest first; updates
+    /// with a version above their own maximum version, and deprecated updates, are left out.
+    /// Returns the updates hosts of `variant` and `arch` may take, newest first: their own
+    /// variant's, or the ones the compatibility rules allow them.
+    pub fn applicable_updates(&self, variant: &str, arch: &str) -> Vec<&Update> {
+        let variants = self.compatible_variants(variant, arch);
+        let mut updates: Vec<&Update> = self
+            .updates
+            .iter()
+            .filter(|u| {
+                variants.contains(&u.variant.as_str())
+                    && u.arch == arch
+                    && u.version <= u.max_version
+                    && u.available
+            })
+            .collect();
+        // sort descending
+        updates.sort_unstable_by(|a, b| b.version.cmp(&a.version));
+        updates
+    }
+
+    /// Returns the update a host of `variant` and `arch` running `version` is offered, given its
+    /// seed and labels: the newest applicable update that's for the host, if it's newer than the
+    /// host's version, or the host is above its maximum version.  This is how updog picks an
+    /// update, so tools can tell what hosts will do with a manifest.
+    pub fn update_for(
+        &self,
+        variant: &str,
+        arch: &str,
+        version: &Version,
+        seed: u32,
+        labels: &BTreeMap<String, String>,
+    ) -> Option<&Update> {
+        for update in self.applicable_updates(variant, arch) {
+            // Hosts that haven't taken an update aren't offered it while its rollout is paused.
+            // Hosts already running it stay put, since they're not below its version or above the
+            // maximum version.
+            if update.paused.is_some() {
+                continue;
+            }
+            // Updates for part of the fleet are only offered to the hosts they target.
+            if !update.targets(seed, labels) {
+                continue;
+            }
+            // If the current running version is greater than the max version ever published,
+            // or moves us to a valid version <= the maximum version, update.
+            if *version < update.version || *version > update.max_version {
+                return Some(update);
+            }
+        }
+        None
+    }
+
+    /// Returns the variants whose updates the hosts of `variant` and `arch` may take.
+    pub fn compatible_variants<'a>(&'a self, variant: &'a str, arch: &str) -> Vec<&'a str> {
+        compatible_variants(&self.compatibility, variant, arch)
+    }
+
+    /// Sets the variants whose updates the hosts of `variant`, and of `arch` if given, may take,
+    /// replacing any rule for the same hosts, or removes the rule if `updates_to` is `None`.
+    /// Returns whether there was a rule for them before.
+    pub fn set_compatibility(
+        &mut self,
+        variant: String,
+        arch: Option<String>,
+        updates_to: Option<Vec<String>>,
+    ) -> Result<bool> {
+        let before = self.compatibility.len();
+        self.compatibility
+            .retain(|rule| rule.variant != variant || rule.arch != arch);
+        let existed = self.compatibility.len() != before;
+        if let Some(updates_to) = updates_to {
+            self.compatibility.push(Compatibility {
+                variant,
+                arch,
+                updates_to,
+            });
+        }
+        self.validate_compatibility()?;
+        Ok(existed)
+    }
+
+    /// Checks that no two compatibility rules are for the same hosts, and that no rule lists a
+    /// variant twice.
+    pub fn validate_compatibility(&self) -> Result<()> {
+        for (i, rule) in self.compatibility.iter().enumerate() {
+            let arch = rule.arch.as_deref().unwrap_or("any architecture");
+            ensure!(
+                !self.compatibility[..i]
+                    .iter()
+                    .any(|other| other.variant == rule.variant && other.arch == rule.arch),
+                error::CompatibilityDuplicate {
+                    variant: &rule.variant,
+                    arch
+                }
+            );
+            for (j, target) in rule.updates_to.iter().enumerate() {
+                ensure!(
+                    !rule.updates_to[..j].contains(target),
+                    error::CompatibilityRepeated {
+                        variant: &rule.variant,
+                        arch,
+                        target
+                    }
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the updates for `variant` and `arch` with the ones in `part`, a smaller manifest
+    /// fetched on its own, returns the number of updates taken from it.  Other updates in `part`
+    /// are ignored, and the rest of this manifest is left alone, apart from taking any migrations
+    /// `part` lists.  Nothing changes if the updates in `part` are invalid.
+    pub fn refresh_part(&mut self, variant: &str, arch: &str, part: Manifest) -> Result<usize> {
+        let updates: Vec<Update> = part
+            .updates
+            .into_iter()
+            .filter(|update| update.variant == variant && update.arch == arch)
+            .collect();
+        Self::validate_updates(&updates)?;
+        let num_updates = updates.len();
+        self.updates
+            .retain(|update| update.variant != variant || update.arch != arch);
+        self.updates.extend(updates);
+        self.migrations.extend(part.migrations);
+        Ok(num_updates)
+    }
+
+    /// Restricts matching updates to the hosts `targeting` describes, or opens them to every host
+    /// if it's None, returns number of matching updates.
+    pub fn set_targeting(
+        &mut self,
+        variant: String,
+        arch: String,
+        image_version: Version,
+        targeting: Option<&Targeting>,
+    ) -> Result<usize> {
+        if let Some(targeting) = targeting {
+            for range in &targeting.seeds {
+                ensure!(
+                    range.start < range.end && range.end <= MAX_SEED,
+                    error::InvalidSeedRange {
+                        start: range.start,
+                        end: range.end
+                    }
+                );
             }
         }
+        let matching = self.get_matching_updates(variant, arch, image_version);
+        let num_matching = matching.len();
+        for update in matching {
+            update.targeting = targeting.cloned();
+        }
+        Ok(num_matching)
+    }
+
+    /// Sets the provenance document of matching updates, or removes it if `provenance` is
+    /// `None`, returns number of matching updates.
+    pub fn set_provenance(
+        &mut self,
+        variant: String,
+        arch: String,
+        image_version: Version,
+        provenance: Option<&Provenance>,
+    ) -> Result<usize> {
+        if let Some(provenance) = provenance {
+            ensure!(
+                provenance.sha256.len() == 64
+                    && provenance.sha256.chars().all(|c| c.is_ascii_hexdigit()),
+                error::InvalidProvenanceDigest {
+                    digest: &provenance.sha256
+                }
+            );
+        }
+        let matching = self.get_matching_updates(variant, arch, image_version);
+        let num_matching = matching.len();
+        for update in matching {
+            update.provenance = provenance.cloned();
+        }
+        Ok(num_matching)
+    }
+
+    /// Moves the seed bounds of matching updates' waves to `bounds`, in order, keeping the time
+    /// each wave starts, returns number of matching updates.  This resizes waves when the fleet
+    /// has grown or shrunk, without changing the schedule.
+    pub fn set_wave_bounds(
+        &mut self,
+        variant: String,
+        arch: String,
+        image_version: Version,
+        bounds: &[u32],
+    ) -> Result<usize> {
+        ensure!(
+            bounds.windows(2).all(|pair| pair[0] < pair[1]),
+            error::WavesUnordered
+        );
+        let matching = self.get_matching_updates(variant, arch, image_version);
+        let num_matching = matching.len();
+        for update in matching {
+            ensure!(
+                bounds.len() == update.waves.len(),
+                error::WaveBoundCount {
+                    expected: update.waves.len(),
+                    provided: bounds.len()
+                }
+            );
+            let starts: Vec<DateTime<Utc>> = update.waves.values().copied().collect();
+            update.waves = bounds.iter().copied().zip(starts).collect();
+        }
         Self::validate_updates(&self.updates)?;
         Ok(num_matching)
     }
+
+    /// Adds a wave that starts at seed `bound` at `start` to matching updates, replacing any wave
+    /// that started at the same seed.  Returns number of matching updates.
+    pub fn add_wave(
+        &mut self,
+        variant: String,
+        arch: String,
+        image_version: Version,
+        bound: u32,
+        start: DateTime<Utc>,
+    ) -> Result<usize> {
+        ensure!(
+            bound < MAX_SEED,
+            error::InvalidWaveBound { provided: bound }
+        );
+        let matching = self.get_matching_updates(variant, arch, image_version);
+        let num_matching = matching.len();
+        for update in matching {
+            update.waves.insert(bound, start);
+        }
+        Self::validate_updates(&self.updates)?;
+        Ok(num_matching)
+    }
+
+    /// Removes every wave from matching updates, returns number of matching updates.  An update
+    /// without waves is offered to every host at once, so new waves should be set before the
+    /// manifest is published.
+    pub fn clear_waves(&mut self, variant: String, arch: String, image_version: Version) -> usize {
+        let matching = self.get_matching_updates(variant, arch, image_version);
+        let num_matching = matching.len();
+        for update in matching {
+            update.waves.clear();
+        }
+        num_matching
+    }
+
+    /// Pauses the rollout of matching updates as of `time`, returns number of matching updates.
+    /// An update that's already paused keeps its original pause time.
+    pub fn pause_rollout(
+        &mut self,
+        variant: String,
+        arch: String,
+        image_version: Version,
+        time: DateTime<Utc>,
+    ) -> usize {
+        let matching = self.get_matching_updates(variant, arch, image_version);
+        let num_matching = matching.len();
+        for update in matching {
+            update.paused.get_or_insert(time);
+        }
+        num_matching
+    }
+
+    /// Resumes the paused rollout of matching updates at `time`, returns number of matching
+    /// updates.  Waves that hadn't started when the rollout was paused are pushed back by the
+    /// length of the pause, so they're still spaced out the way the operator planned.
+    pub fn resume_rollout(
+        &mut self,
+        variant: String,
+        arch: String,
+        image_version: Version,
+        time: DateTime<Utc>,
+    ) -> Result<usize> {
+        let matching = self.get_matching_updates(variant, arch, image_version);
+        let num_matching = matching.len();
+        for update in matching {
+            if let Some(paused) = update.paused.take() {
+                let pause = std::cmp::max(time - paused, Duration::zero());
+                for start in update.waves.values_mut() {
+                    if *start > paused {
+                        *start = *start + pause;
+                    }
+                }
+            }
+        }
+        Self::validate_updates(&self.updates)?;
+        Ok(num_matching)
+    }
+
+    /// Deprecates matching updates, or reinstates them if `available` is true, returns number of
+    /// matching updates.  Hosts aren't offered deprecated updates, but the updates stay in the
+    /// manifest, so hosts already running them can still find their images and migrations.
+    pub fn set_available(
+        &mut self,
+        variant: String,
+        arch: String,
+        image_version: Version,
+        available: bool,
+    ) -> usize {
+        let matching = self.get_matching_updates(variant, arch, image_version);
+        let num_matching = matching.len();
+        for update in matching {
+            update.available = available;
+        }
+        num_matching
+    }
 }
 
 impl Update {
@@ -338,23 +1144,30 @@ impl Update {
         }
     }
 
-    pub fn update_ready(&self, seed: u32) -> bool {
+    /// Returns whether the host with the given seed and labels is one this update is for.
+    pub fn targets(&self, seed: u32, labels: &BTreeMap<String, String>) -> bool {
+        self.targeting
+            .as_ref()
+            .map_or(true, |targeting| targeting.matches(seed, labels))
+    }
+
+    pub fn update_ready(&self, seed: u32, clock: &dyn Clock) -> bool {
         // Has this client's wave started
         if let Some(wave) = self.update_wave(seed) {
-            return wave.has_started();
+            return wave.has_started(clock);
         }
 
         // Or there are no waves
         true
     }
 
-    pub fn jitter(&self, seed: u32) -> Option<DateTime<Utc>> {
+    pub fn jitter(&self, seed: u32, clock: &dyn Clock) -> Option<DateTime<Utc>> {
         if let Some(wave) = self.update_wave(seed) {
-            if wave.has_passed() {
+            if wave.has_passed(clock) {
                 return None;
             }
             let bounds = match self.update_wave(seed) {
-                Some(Wave::Initial { end }) => Some((Utc::now(), end)),
+                Some(Wave::Initial { end }) => Some((clock.now(), end)),
                 Some(Wave::General { start, end }) => Some((start, end)),
                 Some(Wave::Last { start: _ }) | None => None,
             };
@@ -367,4 +1180,40 @@ impl Update {
         }
         None
     }
+
+    /// Returns the part of its wave that the host with `seed` should fetch the update in, as of
+    /// `now`, so hosts in a wave spread their downloads over it rather than all fetching when it
+    /// starts.  The wave's time is split into one slot per seed, in seed order.  The first wave
+    /// is split from `now` until it ends, and the last wave, which has no end, is given as much
+    /// time as the wave before it.  Returns None if there are no waves to go by, or the host's
+    /// slot has already passed, since it may as well fetch right away.
+    pub fn fetch_window(
+        &self,
+        seed: u32,
+        now: DateTime<Utc>,
+    ) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let before = self.waves.range((Included(0), Excluded(seed))).last();
+        let after = self
+            .waves
+            .range((Included(seed), Included(MAX_SEED)))
+            .next();
+        // The seeds in the wave are those after the bound it starts at, up to its own bound.
+        let (first_seed, last_seed, start, end) = match (before, after) {
+            (None, Some((&bound, &end))) => (0, bound, now, end),
+            (Some((&from, &start)), Some((&bound, &end))) => (from + 1, bound, start, end),
+            (Some((&from, &start)), None) => {
+                let (_, &previous) = self.waves.range((Included(0), Excluded(from))).last()?;
+                (from + 1, MAX_SEED - 1, start, start + (start - previous))
+            }
+            (None, None) => return None,
+        };
+        let slots = i64::from(last_seed - first_seed + 1);
+        let slot = (end - start).num_seconds() / slots;
+        let window_start = start + Duration::seconds(slot * i64::from(seed - first_seed));
+        let window_end = window_start + Duration::seconds(slot);
+        if window_end <= now {
+            return None;
+        }
+        Some((window_start, window_end))
+    }
 }
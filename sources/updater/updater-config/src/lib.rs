@@ -0,0 +1,554 @@
+/*!
+# Background
+
+updog, the migrator, and signpost each need to know how verbose to be, and the ones that go over the network need a proxy and timeouts.
+This library is how they find out, so a setting means the same thing to all of them, and they agree on which value wins when it's set in more than one place.
+
+# Layers
+
+Settings are resolved in layers, each one overriding those before it:
+
+1. Defaults: no proxy, a 10 second connect timeout, a 30 second request timeout, and the `info` log level.
+2. The component's own config file, like `/etc/updog.toml`, for components that have one.
+3. `/etc/updater.toml`, which is generated from the settings API's `settings.network` so components share the host's proxy settings.
+4. The environment: `HTTPS_PROXY` and `NO_PROXY` (or their lowercase forms), `UPDATER_CONNECT_TIMEOUT_SECS`, `UPDATER_TIMEOUT_SECS`, and `UPDATER_LOG_LEVEL`.
+5. Options given on the component's command line, like `--log-level`.
+
+Missing files are skipped, since the settings file isn't generated until the settings API has run, which is after the migrator at boot.
+
+The files use the same keys, so a component's own file can set any of them:
+
+```toml
+https_proxy = "proxy.example.com:3128"
+no_proxy = ["localhost", "127.0.0.1"]
+connect_timeout_secs = 10
+timeout_secs = 30
+log_level = "debug"
+```
+
+# Showing the effective config
+
+Each component takes `--show-config` to print the settings it would run with and where each came from, rather than doing anything else.
+The output is TOML, so it can be used as a config file in turn:
+
+```toml
+https_proxy = "proxy.example.com:3128"  # settings /etc/updater.toml
+no_proxy = ["localhost", "127.0.0.1"]  # settings /etc/updater.toml
+connect_timeout_secs = 10  # default
+timeout_secs = 30  # default
+log_level = "debug"  # command line
+```
+*/
+
+#![deny(rust_2018_idioms)]
+
+use log::LevelFilter;
+use serde::{Deserialize, Deserializer};
+use snafu::{ensure, ResultExt};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The file generated from the settings API for all of the updater's components.
+pub const SETTINGS_FILE: &str = "/etc/updater.toml";
+
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+mod error {
+    use snafu::Snafu;
+    use std::io;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Unable to read config file '{}': {}", path.display(), source))]
+        Read { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Unable to parse config file '{}': {}", path.display(), source))]
+        Parse {
+            path: PathBuf,
+            source: toml::de::Error,
+        },
+
+        #[snafu(display("Invalid value '{}' for {}", value, name))]
+        EnvValue { name: String, value: String },
+
+        #[snafu(display("{} can't be 0, from {}", name, from))]
+        ZeroTimeout {
+            name: &'static str,
+            from: super::Source,
+        },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
+
+/// Where a setting's value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    /// The component's own config file.
+    File(PathBuf),
+    /// The file generated from the settings API.
+    Settings(PathBuf),
+    Environment,
+    CommandLine,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "file {}", path.display()),
+            Source::Settings(path) => write!(f, "settings {}", path.display()),
+            Source::Environment => write!(f, "environment"),
+            Source::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+/// The settings given by one layer; anything it leaves out is left to the layers before it.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Layer {
+    #[serde(default)]
+    pub https_proxy: Option<String>,
+    #[serde(default)]
+    pub no_proxy: Option<Vec<String>>,
+    #[serde(default)]
+    pub connect_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_level")]
+    pub log_level: Option<LevelFilter>,
+}
+
+fn deserialize_level<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<LevelFilter>, D::Error> {
+    let level = Option::<String>::deserialize(deserializer)?;
+    level
+        .map(|level| {
+            LevelFilter::from_str(&level)
+                .map_err(|_| serde::de::Error::custom(format!("invalid log level '{}'", level)))
+        })
+        .transpose()
+}
+
+impl Layer {
+    /// Reads a layer from a TOML file, ignoring keys that aren't shared settings, so it can be a
+    /// component's own config file.  A missing file gives no layer.
+    fn from_file(path: &Path) -> Result<Option<Self>> {
+        let s = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context(error::Read { path }),
+        };
+        toml::from_str(&s).map(Some).context(error::Parse { path })
+    }
+
+    /// Reads a layer from environment variables.  A proxy variable may be given in uppercase or
+    /// lowercase, like other tools take them; uppercase wins if both are set.
+    fn from_vars<I: IntoIterator<Item = (String, String)>>(vars: I) -> Result<Self> {
+        let vars: HashMap<String, String> = vars.into_iter().collect();
+        let var = |name: &str| {
+            vars.get(name)
+                .or_else(|| vars.get(&name.to_lowercase()))
+                .filter(|value| !value.is_empty())
+                .map(|value| (name.to_string(), value.clone()))
+        };
+        let secs = |name: &str| {
+            var(name)
+                .map(|(name, value)| {
+                    value
+                        .parse::<u64>()
+                        .ok()
+                        .ok_or(error::Error::EnvValue { name, value })
+                })
+                .transpose()
+        };
+        Ok(Self {
+            https_proxy: var("HTTPS_PROXY").map(|(_, value)| value),
+            no_proxy: var("NO_PROXY").map(|(_, value)| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|host| !host.is_empty())
+                    .map(String::from)
+                    .collect()
+            }),
+            connect_timeout_secs: secs("UPDATER_CONNECT_TIMEOUT_SECS")?,
+            timeout_secs: secs("UPDATER_TIMEOUT_SECS")?,
+            log_level: var("UPDATER_LOG_LEVEL")
+                .map(|(name, value)| {
+                    LevelFilter::from_str(&value)
+                        .ok()
+                        .ok_or(error::Error::EnvValue { name, value })
+                })
+                .transpose()?,
+        })
+    }
+}
+
+/// A setting's value, and the layer it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+impl<T> Setting<T> {
+    fn default(value: T) -> Self {
+        Self {
+            value,
+            source: Source::Default,
+        }
+    }
+
+    fn set(&mut self, value: Option<T>, source: &Source) {
+        if let Some(value) = value {
+            self.value = value;
+            self.source = source.clone();
+        }
+    }
+}
+
+/// How long network requests may take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// How long to wait to connect to a server.
+    pub connect: Duration,
+    /// How long to wait for a response, or for the next part of its body.
+    pub request: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            request: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+        }
+    }
+}
+
+/// The settings a component runs with, once all the layers are resolved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectiveConfig {
+    pub https_proxy: Setting<Option<String>>,
+    pub no_proxy: Setting<Vec<String>>,
+    pub connect_timeout_secs: Setting<u64>,
+    pub timeout_secs: Setting<u64>,
+    pub log_level: Setting<LevelFilter>,
+}
+
+impl Default for EffectiveConfig {
+    fn default() -> Self {
+        Self {
+            https_proxy: Setting::default(None),
+            no_proxy: Setting::default(Vec::new()),
+            connect_timeout_secs: Setting::default(DEFAULT_CONNECT_TIMEOUT_SECS),
+            timeout_secs: Setting::default(DEFAULT_TIMEOUT_SECS),
+            log_level: Setting::default(LevelFilter::Info),
+        }
+    }
+}
+
+impl EffectiveConfig {
+    pub fn timeouts(&self) -> Timeouts {
+        Timeouts {
+            connect: Duration::from_secs(self.connect_timeout_secs.value),
+            request: Duration::from_secs(self.timeout_secs.value),
+        }
+    }
+
+    fn apply(&mut self, layer: Layer, source: &Source) {
+        self.https_proxy.set(layer.https_proxy.map(Some), source);
+        self.no_proxy.set(layer.no_proxy, source);
+        self.connect_timeout_secs
+            .set(layer.connect_timeout_secs, source);
+        self.timeout_secs.set(layer.timeout_secs, source);
+        self.log_level.set(layer.log_level, source);
+    }
+}
+
+/// Quotes a string for TOML output.
+fn quoted(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl fmt::Display for EffectiveConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.https_proxy.value {
+            Some(proxy) => writeln!(
+                f,
+                "https_proxy = {}  # {}",
+                quoted(proxy),
+                self.https_proxy.source
+            )?,
+            None => writeln!(f, "# https_proxy is not set ({})", self.https_proxy.source)?,
+        }
+        let no_proxy: Vec<_> = self
+            .no_proxy
+            .value
+            .iter()
+            .map(|host| quoted(host))
+            .collect();
+        writeln!(
+            f,
+            "no_proxy = [{}]  # {}",
+            no_proxy.join(", "),
+            self.no_proxy.source
+        )?;
+        writeln!(
+            f,
+            "connect_timeout_secs = {}  # {}",
+            self.connect_timeout_secs.value, self.connect_timeout_secs.source
+        )?;
+        writeln!(
+            f,
+            "timeout_secs = {}  # {}",
+            self.timeout_secs.value, self.timeout_secs.source
+        )?;
+        write!(
+            f,
+            "log_level = {}  # {}",
+            quoted(&self.log_level.value.to_string().to_lowercase()),
+            self.log_level.source
+        )
+    }
+}
+
+/// Gathers layers in order, each overriding those added before it.
+#[derive(Debug, Default)]
+pub struct Loader {
+    layers: Vec<(Source, Layer)>,
+}
+
+impl Loader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the component's own config file, if it exists.
+    pub fn file<P: AsRef<Path>>(mut self, path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(layer) = Layer::from_file(path)? {
+            self.layers.push((Source::File(path.to_path_buf()), layer));
+        }
+        Ok(self)
+    }
+
+    /// Adds the file generated from the settings API, if it exists.
+    pub fn settings<P: AsRef<Path>>(mut self, path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(layer) = Layer::from_file(path)? {
+            self.layers
+                .push((Source::Settings(path.to_path_buf()), layer));
+        }
+        Ok(self)
+    }
+
+    /// Adds this process's environment.
+    pub fn env(self) -> Result<Self> {
+        self.vars(env::vars())
+    }
+
+    /// Adds the given environment variables, as if they were this process's environment.
+    pub fn vars<I: IntoIterator<Item = (String, String)>>(mut self, vars: I) -> Result<Self> {
+        self.layers
+            .push((Source::Environment, Layer::from_vars(vars)?));
+        Ok(self)
+    }
+
+    /// Adds the options given on the command line.
+    pub fn command_line(mut self, layer: Layer) -> Self {
+        self.layers.push((Source::CommandLine, layer));
+        self
+    }
+
+    /// Resolves the layers.
+    pub fn load(self) -> Result<EffectiveConfig> {
+        let mut config = EffectiveConfig::default();
+        for (source, layer) in self.layers {
+            config.apply(layer, &source);
+        }
+        // A timeout of 0 would fail every request, which is never what anyone means.
+        ensure!(
+            config.connect_timeout_secs.value > 0,
+            error::ZeroTimeout {
+                name: "connect_timeout_secs",
+                from: config.connect_timeout_secs.source.clone(),
+            }
+        );
+        ensure!(
+            config.timeout_secs.value > 0,
+            error::ZeroTimeout {
+                name: "timeout_secs",
+                from: config.timeout_secs.source.clone(),
+            }
+        );
+        Ok(config)
+    }
+}
+
+/// Loads the effective config in the usual order: defaults, the component's own config `file`,
+/// if it has one, the `settings` file, the environment, and the `command_line`.
+pub fn load(file: Option<&Path>, settings: &Path, command_line: Layer) -> Result<EffectiveConfig> {
+    let mut loader = Loader::new();
+    if let Some(file) = file {
+        loader = loader.file(file)?;
+    }
+    loader
+        .settings(settings)?
+        .env()?
+        .command_line(command_line)
+        .load()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn layers_override_in_order() {
+        let tmp = TempDir::new().unwrap();
+        let file = tmp.path().join("updog.toml");
+        let settings = tmp.path().join("updater.toml");
+        fs::write(
+            &file,
+            "seed = 1234\nhttps_proxy = \"file-proxy:3128\"\ntimeout_secs = 60\nlog_level = \"warn\"\n",
+        )
+        .unwrap();
+        fs::write(
+            &settings,
+            "https_proxy = \"settings-proxy:3128\"\nno_proxy = [\"localhost\"]\n",
+        )
+        .unwrap();
+
+        let config = Loader::new()
+            .file(&file)
+            .unwrap()
+            .settings(&settings)
+            .unwrap()
+            .vars(vars(&[("UPDATER_LOG_LEVEL", "debug")]))
+            .unwrap()
+            .command_line(Layer {
+                log_level: Some(LevelFilter::Trace),
+                ..Layer::default()
+            })
+            .load()
+            .unwrap();
+
+        assert_eq!(
+            config.https_proxy,
+            Setting {
+                value: Some("settings-proxy:3128".to_string()),
+                source: Source::Settings(settings.clone()),
+            }
+        );
+        assert_eq!(config.no_proxy.value, vec!["localhost".to_string()]);
+        assert_eq!(config.connect_timeout_secs.source, Source::Default);
+        assert_eq!(config.timeout_secs.source, Source::File(file));
+        assert_eq!(config.timeouts().request, Duration::from_secs(60));
+        assert_eq!(
+            config.log_level,
+            Setting {
+                value: LevelFilter::Trace,
+                source: Source::CommandLine,
+            }
+        );
+    }
+
+    #[test]
+    fn missing_files_are_skipped() {
+        let tmp = TempDir::new().unwrap();
+        let config = Loader::new()
+            .file(tmp.path().join("updog.toml"))
+            .unwrap()
+            .settings(tmp.path().join("updater.toml"))
+            .unwrap()
+            .load()
+            .unwrap();
+        assert_eq!(config, EffectiveConfig::default());
+
+        fs::write(tmp.path().join("updater.toml"), "log_level = \"loud\"\n").unwrap();
+        assert!(Loader::new()
+            .settings(tmp.path().join("updater.toml"))
+            .is_err());
+    }
+
+    #[test]
+    fn environment() {
+        let config = Loader::new()
+            .vars(vars(&[
+                ("https_proxy", "lower:3128"),
+                ("no_proxy", "example.com, .internal,"),
+                ("UPDATER_CONNECT_TIMEOUT_SECS", "5"),
+            ]))
+            .unwrap()
+            .load()
+            .unwrap();
+        assert_eq!(config.https_proxy.value.as_deref(), Some("lower:3128"));
+        assert_eq!(config.https_proxy.source, Source::Environment);
+        assert_eq!(config.no_proxy.value, vec!["example.com", ".internal"]);
+        assert_eq!(config.timeouts().connect, Duration::from_secs(5));
+
+        let config = Loader::new()
+            .vars(vars(&[
+                ("HTTPS_PROXY", "upper:3128"),
+                ("https_proxy", "lower:3128"),
+            ]))
+            .unwrap()
+            .load()
+            .unwrap();
+        assert_eq!(config.https_proxy.value.as_deref(), Some("upper:3128"));
+
+        assert!(Loader::new()
+            .vars(vars(&[("UPDATER_TIMEOUT_SECS", "soon")]))
+            .is_err());
+        assert!(Loader::new()
+            .vars(vars(&[("UPDATER_TIMEOUT_SECS", "0")]))
+            .unwrap()
+            .load()
+            .is_err());
+    }
+
+    #[test]
+    fn shown_as_toml() {
+        let mut config = EffectiveConfig::default();
+        config.apply(
+            Layer {
+                https_proxy: Some("proxy:3128".to_string()),
+                no_proxy: Some(vec!["localhost".to_string()]),
+                ..Layer::default()
+            },
+            &Source::Settings(PathBuf::from("/etc/updater.toml")),
+        );
+        let shown = config.to_string();
+        assert_eq!(
+            shown,
+            "https_proxy = \"proxy:3128\"  # settings /etc/updater.toml\n\
+             no_proxy = [\"localhost\"]  # settings /etc/updater.toml\n\
+             connect_timeout_secs = 10  # default\n\
+             timeout_secs = 30  # default\n\
+             log_level = \"info\"  # default"
+        );
+        // What's shown can be read back as a layer.
+        let layer: Layer = toml::from_str(&shown).unwrap();
+        assert_eq!(layer.log_level, Some(LevelFilter::Info));
+        assert_eq!(layer.https_proxy.as_deref(), Some("proxy:3128"));
+    }
+}
@@ -0,0 +1,168 @@
+//! Activates a written update, by setting the inactive partition set to boot next, and reboots
+//! into it.  Vetoes registered on the host and the operator's approval policy can hold either back.
+
+use crate::approval;
+use crate::cancel::Cancel;
+use crate::config::{load_config, Config, Paths};
+use crate::error::{self, Result};
+use crate::prepared::Prepared;
+use crate::repo::build_transport;
+use crate::report::{Output, Report};
+use crate::status::CheckResult;
+use crate::veto;
+use crate::{Arguments, TARGET_ARCH};
+use audit::{Action, Actor};
+use chrono::{SecondsFormat, Utc};
+use log::info;
+use semver::Version;
+use signal_hook::{iterator::Signals, SIGTERM};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::process;
+use std::thread;
+
+/// Marks the prepared update to boot next, unless it already is.  The update isn't activated
+/// before the time given with `--not-before`, here or in an earlier run; unless told to wait,
+/// updog leaves the update prepared and exits, and a later `activate` finishes the job.
+pub(crate) fn activate(paths: &Paths, arguments: &Arguments) -> Result<Output> {
+    let mut prepared = Prepared::load(&paths.prepared)
+        .context(error::PreparedRead {
+            path: &paths.prepared,
+        })?
+        .context(error::NotPrepared)?;
+    let config = load_config(paths)?;
+    let state = paths.partition_state()?;
+    let message = if prepared.is_activated(&state) {
+        String::from("Update already activated")
+    } else {
+        ensure!(prepared.is_staged(&state), error::NotPrepared);
+        if arguments.not_before.is_some() && arguments.not_before != prepared.not_before {
+            prepared.not_before = arguments.not_before;
+            prepared
+                .write(&paths.prepared)
+                .context(error::PreparedWrite {
+                    path: &paths.prepared,
+                })?;
+        }
+        let min_soak = if arguments.skip_soak {
+            chrono::Duration::zero()
+        } else {
+            config.min_soak()
+        };
+        if let Some(wait) = prepared.wait(Utc::now(), min_soak) {
+            let not_before = prepared
+                .activate_after(min_soak)
+                .to_rfc3339_opts(SecondsFormat::Secs, true);
+            if !arguments.wait {
+                let version = prepared.version.clone();
+                return Ok(Output::new(
+                    CheckResult::ActivationScheduled,
+                    Some(&version),
+                    Report::Prepared {
+                        message: format!("Update prepared, to be activated at {}", not_before),
+                        prepared,
+                    },
+                ));
+            }
+            info!("Waiting until {} to activate", not_before);
+            arguments.cancel.sleep(wait.to_std().unwrap_or_default())?;
+            // The update may have been cancelled or overwritten while we waited.
+            let state = paths.partition_state()?;
+            ensure!(prepared.is_staged(&state), error::NotPrepared);
+        }
+        check_vetoes(&config, Some(&prepared.version))?;
+        check_approval(arguments, &config, Some(&prepared.version))?;
+        update_flags(paths, &arguments.cancel)?;
+        String::from("Update activated")
+    };
+    if arguments.reboot {
+        check_vetoes(&config, Some(&prepared.version))?;
+        initiate_reboot()?;
+    }
+    let version = prepared.version.clone();
+    Ok(Output::new(
+        CheckResult::UpdateActivated,
+        Some(&version),
+        Report::Prepared { message, prepared },
+    ))
+}
+
+/// Fails if a veto registered on the host holds back activating or rebooting into an update.
+pub(crate) fn check_vetoes(config: &Config, version: Option<&Version>) -> Result<()> {
+    match veto::active(&config.vetoes, Utc::now()) {
+        Some(vetoes) => error::UpdateVetoed {
+            vetoes,
+            version: version.cloned(),
+        }
+        .fail(),
+        None => Ok(()),
+    }
+}
+
+/// Asks the configured approval policy, if there is one, whether the update to `version` may be
+/// activated.  A denial fails like a veto.
+pub(crate) fn check_approval(
+    arguments: &Arguments,
+    config: &Config,
+    version: Option<&Version>,
+) -> Result<()> {
+    let approval = match &config.approval {
+        Some(approval) => approval,
+        None => return Ok(()),
+    };
+    let paths = &arguments.paths;
+    let (running, variant) = paths.running_version()?;
+    let transport = build_transport(config)?;
+    approval::check(
+        transport.client(),
+        approval,
+        &paths.approval,
+        &approval::Context {
+            variant: &variant,
+            arch: TARGET_ARCH,
+            version: &running,
+            update_version: version,
+            seed: config.seed,
+            labels: &config.labels,
+            command: &arguments.command.to_string(),
+        },
+        Utc::now(),
+    )
+}
+
+/// Sets the inactive partition set to boot next, unless the run has been cancelled, recording it
+/// in the audit trail.
+pub(crate) fn update_flags(paths: &Paths, cancel: &Cancel) -> Result<()> {
+    cancel.check()?;
+    let mut gpt_state = paths.partition_state()?;
+    let inactive = gpt_state.inactive_set().to_string();
+    let result = gpt_state
+        .upgrade_to_inactive()
+        .context(error::InactivePartitionUpgrade)
+        .and_then(|()| gpt_state.write().context(error::PartitionTableWrite));
+    audit::record(Actor::Updog, Action::UpdateActivate, inactive, &result);
+    result
+}
+
+pub(crate) fn initiate_reboot() -> Result<()> {
+    // Set up signal handler for termination signals
+    let signals = Signals::new(&[SIGTERM]).context(error::Signal)?;
+    let signals_bg = signals.clone();
+    thread::spawn(move || {
+        for _sig in signals_bg.forever() {
+            // Ignore termination signals in case updog gets terminated
+            // before getting to exit normally by itself after invoking
+            // `shutdown -r` to complete the update.
+        }
+    });
+    let result = process::Command::new("shutdown")
+        .arg("-r")
+        .status()
+        .context(error::RebootFailure);
+    audit::record(Actor::Updog, Action::Reboot, "host", &result);
+    if let Err(err) = result {
+        // Kill the signal handling thread
+        signals.close();
+        return Err(err);
+    }
+    Ok(())
+}
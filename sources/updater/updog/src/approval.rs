@@ -0,0 +1,461 @@
+//! Asks an operator's policy endpoint whether an update may be activated, so activations can be
+//! gated centrally, like during a change freeze or until a canary group looks healthy, without
+//! running an agent on each host to register vetoes.
+//!
+//! The endpoint is sent the host's context in a POST request, wrapped in `input` the way the Open
+//! Policy Agent's data API expects, and answers with a decision wrapped in `result`:
+//!
+//! ```json
+//! {"result": {"allow": false, "reason": "change freeze", "ttl": 3600}}
+//! ```
+//!
+//! A decision with a `ttl`, in seconds, is kept in a local cache for that long, so hosts don't ask
+//! again on every run; it's only reused for the same endpoint and host context.  A denial holds
+//! back the update like a veto does, and a later run asks again.  If the endpoint can't be reached
+//! or its answer can't be read, the activation fails, unless the config says to fail open.
+
+use crate::error::{self, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::{info, warn};
+use reqwest::blocking::Client;
+use ring::digest::{digest, SHA256};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, ResultExt, Snafu};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use url::{Host, Url};
+
+/// How long to wait for the endpoint to answer.
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid approval URL '{}': {}", url, source))]
+    ParseUrl {
+        url: String,
+        source: url::ParseError,
+    },
+
+    #[snafu(display("Approval URL '{}' must use HTTPS unless it's on this host", url))]
+    InsecureUrl { url: String },
+
+    #[snafu(display("Error asking {} for approval: {}", url, source))]
+    Send { url: Url, source: reqwest::Error },
+
+    #[snafu(display("Invalid approval from {}: {}", url, source))]
+    Response { url: Url, source: serde_json::Error },
+}
+
+/// The policy endpoint, from settings.updates.approval.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct ApprovalConfig {
+    url: String,
+    /// Whether to activate anyway if the endpoint can't be reached, rather than fail.
+    #[serde(default)]
+    fail_open: bool,
+}
+
+/// What the endpoint is told about the host and the update it wants to activate.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Context<'a> {
+    pub(crate) variant: &'a str,
+    pub(crate) arch: &'a str,
+    /// The running version.
+    pub(crate) version: &'a Version,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) update_version: Option<&'a Version>,
+    pub(crate) seed: u32,
+    pub(crate) labels: &'a BTreeMap<String, String>,
+    pub(crate) command: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct Input<'a> {
+    input: InputContext<'a>,
+}
+
+#[derive(Debug, Serialize)]
+struct InputContext<'a> {
+    #[serde(flatten)]
+    context: &'a Context<'a>,
+    time: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Answer {
+    /// The Open Policy Agent leaves this out when the policy has no decision for the input.
+    #[serde(default)]
+    result: Option<Decision>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+struct Decision {
+    allow: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    /// How long the decision holds, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
+}
+
+/// A decision kept in the cache, along with what it was for.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Cached {
+    url: String,
+    /// The SHA-256 of the host context the decision was made for.
+    context: String,
+    expires: DateTime<Utc>,
+    decision: Decision,
+}
+
+/// Checks with the endpoint, or a cached decision, that the update in `context` may be activated.
+/// A denial fails as a veto, naming the policy's reason.
+pub(crate) fn check(
+    client: &Client,
+    config: &ApprovalConfig,
+    cache: &Path,
+    context: &Context<'_>,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    decide(config, cache, context, now, |body| {
+        ask(client, &config.url, body)
+    })
+}
+
+/// Makes the decision, getting it from `ask` if it isn't cached.
+fn decide<F>(
+    config: &ApprovalConfig,
+    cache: &Path,
+    context: &Context<'_>,
+    now: DateTime<Utc>,
+    ask: F,
+) -> Result<()>
+where
+    F: FnOnce(Vec<u8>) -> std::result::Result<Option<Decision>, Error>,
+{
+    let key = context_digest(context);
+    let decision = if let Some(decision) = load_cached(cache, &config.url, &key, now) {
+        decision
+    } else {
+        let input = Input {
+            input: InputContext { context, time: now },
+        };
+        let body = serde_json::to_vec(&input).expect("context serialization can't fail");
+        match ask(body) {
+            Ok(Some(decision)) => {
+                save_cached(cache, &config.url, key, now, &decision);
+                decision
+            }
+            Ok(None) => Decision {
+                allow: false,
+                reason: Some("the policy has no decision for this host".to_string()),
+                ttl: None,
+            },
+            Err(e) if config.fail_open => {
+                warn!("{}; activating anyway, since approval fails open", e);
+                return Ok(());
+            }
+            Err(e) => {
+                return error::ApprovalFailed {
+                    message: e.to_string(),
+                }
+                .fail()
+            }
+        }
+    };
+
+    if decision.allow {
+        info!("Activating {} was approved", describe(context));
+        return Ok(());
+    }
+    error::UpdateVetoed {
+        vetoes: match decision.reason {
+            Some(reason) => format!("approval policy ({})", reason),
+            None => "approval policy".to_string(),
+        },
+        version: context.update_version.cloned(),
+    }
+    .fail()
+}
+
+fn describe(context: &Context<'_>) -> String {
+    match context.update_version {
+        Some(version) => format!("the update to {}", version),
+        None => "the update".to_string(),
+    }
+}
+
+fn ask(client: &Client, url: &str, body: Vec<u8>) -> std::result::Result<Option<Decision>, Error> {
+    let url = Url::parse(url).context(ParseUrl { url })?;
+    let local = match url.host() {
+        Some(Host::Domain(domain)) => domain == "localhost",
+        Some(Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    };
+    ensure!(
+        url.scheme() == "https" || (url.scheme() == "http" && local),
+        InsecureUrl {
+            url: url.to_string()
+        }
+    );
+    let response = client
+        .post(url.clone())
+        .timeout(TIMEOUT)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .and_then(reqwest::blocking::Response::error_for_status)
+        .context(Send { url: url.clone() })?;
+    let answer: Answer = serde_json::from_reader(response).context(Response { url })?;
+    Ok(answer.result)
+}
+
+/// Identifies the context a decision was made for, apart from the time it was asked.
+fn context_digest(context: &Context<'_>) -> String {
+    let data = serde_json::to_vec(context).expect("context serialization can't fail");
+    digest(&SHA256, &data)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn load_cached(path: &Path, url: &str, context: &str, now: DateTime<Utc>) -> Option<Decision> {
+    let data = fs::read(path).ok()?;
+    let cached: Cached = serde_json::from_slice(&data).ok()?;
+    if cached.url == url && cached.context == context && cached.expires > now {
+        Some(cached.decision)
+    } else {
+        None
+    }
+}
+
+/// Caches a decision that has a TTL.  The cache only saves asking again, so failing to write it
+/// is logged and otherwise ignored.
+fn save_cached(path: &Path, url: &str, context: String, now: DateTime<Utc>, decision: &Decision) {
+    let ttl = match decision.ttl {
+        Some(ttl) if ttl > 0 => ttl,
+        _ => return,
+    };
+    let cached = Cached {
+        url: url.to_string(),
+        context,
+        expires: now + Duration::seconds(i64::from(ttl)),
+        decision: decision.clone(),
+    };
+    let write = || -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let data = serde_json::to_vec(&cached).map_err(io::Error::from)?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, path)
+    };
+    if let Err(e) = write() {
+        warn!("Failed to cache approval in {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error as UpdogError;
+    use std::cell::Cell;
+    use tempfile::TempDir;
+
+    const URL: &str = "https://policy.example.com/v1/data/bottlerocket/activate";
+
+    fn config(fail_open: bool) -> ApprovalConfig {
+        ApprovalConfig {
+            url: URL.to_string(),
+            fail_open,
+        }
+    }
+
+    fn decision(allow: bool, ttl: Option<u32>) -> Decision {
+        Decision {
+            allow,
+            reason: if allow {
+                None
+            } else {
+                Some("change freeze".to_string())
+            },
+            ttl,
+        }
+    }
+
+    struct Fixture {
+        _dir: TempDir,
+        cache: std::path::PathBuf,
+        version: Version,
+        update_version: Version,
+        labels: BTreeMap<String, String>,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let dir = TempDir::new().unwrap();
+            Self {
+                cache: dir.path().join("updog/approval.json"),
+                _dir: dir,
+                version: Version::parse("0.3.2").unwrap(),
+                update_version: Version::parse("0.3.4").unwrap(),
+                labels: BTreeMap::new(),
+            }
+        }
+
+        fn context(&self) -> Context<'_> {
+            Context {
+                variant: "aws-k8s-1.15",
+                arch: "x86_64",
+                version: &self.version,
+                update_version: Some(&self.update_version),
+                seed: 1234,
+                labels: &self.labels,
+                command: "activate",
+            }
+        }
+    }
+
+    #[test]
+    fn sends_context() {
+        let host = Fixture::new();
+        let now = Utc::now();
+        let mut sent = None;
+        decide(&config(false), &host.cache, &host.context(), now, |body| {
+            sent = Some(serde_json::from_slice::<serde_json::Value>(&body).unwrap());
+            Ok(Some(decision(true, None)))
+        })
+        .unwrap();
+        let sent = sent.unwrap();
+        assert_eq!(sent["input"]["variant"], "aws-k8s-1.15");
+        assert_eq!(sent["input"]["version"], "0.3.2");
+        assert_eq!(sent["input"]["update-version"], "0.3.4");
+        assert_eq!(sent["input"]["seed"], 1234);
+        assert!(sent["input"]["time"].is_string());
+    }
+
+    #[test]
+    fn denial_is_a_veto() {
+        let host = Fixture::new();
+        let err = decide(
+            &config(false),
+            &host.cache,
+            &host.context(),
+            Utc::now(),
+            |_| Ok(Some(decision(false, None))),
+        )
+        .unwrap_err();
+        match err {
+            UpdogError::UpdateVetoed {
+                vetoes, version, ..
+            } => {
+                assert_eq!(vetoes, "approval policy (change freeze)");
+                assert_eq!(version, Some(host.update_version.clone()));
+            }
+            e => panic!("wrong error: {}", e),
+        }
+
+        // A policy with no decision for the host doesn't approve it.
+        assert!(decide(
+            &config(true),
+            &host.cache,
+            &host.context(),
+            Utc::now(),
+            |_| Ok(None)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn caches_for_ttl() {
+        let host = Fixture::new();
+        let now = Utc::now();
+        let asked = Cell::new(0);
+        let ask = |_| {
+            asked.set(asked.get() + 1);
+            Ok(Some(decision(true, Some(600))))
+        };
+        decide(&config(false), &host.cache, &host.context(), now, ask).unwrap();
+        decide(&config(false), &host.cache, &host.context(), now, ask).unwrap();
+        assert_eq!(asked.get(), 1);
+
+        // It's asked again once the decision expires, or for another update.
+        let later = now + Duration::seconds(601);
+        decide(&config(false), &host.cache, &host.context(), later, ask).unwrap();
+        assert_eq!(asked.get(), 2);
+        let mut other = Fixture::new();
+        other.cache = host.cache.clone();
+        other.update_version = Version::parse("0.3.5").unwrap();
+        decide(&config(false), &other.cache, &other.context(), later, ask).unwrap();
+        assert_eq!(asked.get(), 3);
+
+        // Decisions without a TTL aren't cached.
+        let host = Fixture::new();
+        let ask = |_| {
+            asked.set(asked.get() + 1);
+            Ok(Some(decision(true, None)))
+        };
+        decide(&config(false), &host.cache, &host.context(), now, ask).unwrap();
+        decide(&config(false), &host.cache, &host.context(), now, ask).unwrap();
+        assert_eq!(asked.get(), 5);
+    }
+
+    #[test]
+    fn unreachable() {
+        let host = Fixture::new();
+        let unreachable = |_| {
+            InsecureUrl {
+                url: "http://policy.example.com/",
+            }
+            .fail()
+        };
+        match decide(
+            &config(false),
+            &host.cache,
+            &host.context(),
+            Utc::now(),
+            unreachable,
+        ) {
+            Err(UpdogError::ApprovalFailed { .. }) => {}
+            result => panic!("wrong result: {:?}", result),
+        }
+        decide(
+            &config(true),
+            &host.cache,
+            &host.context(),
+            Utc::now(),
+            unreachable,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn only_local_plain_http() {
+        let client = Client::new();
+        for url in &["http://policy.example.com/", "ftp://localhost/"] {
+            match ask(&client, url, Vec::new()) {
+                Err(Error::InsecureUrl { .. }) => {}
+                result => panic!("{}: wrong result: {:?}", url, result),
+            }
+        }
+    }
+
+    #[test]
+    fn answers() {
+        let answer: Answer = serde_json::from_str(
+            r#"{"result": {"allow": false, "reason": "change freeze", "ttl": 3600}}"#,
+        )
+        .unwrap();
+        assert_eq!(answer.result, Some(decision(false, Some(3600))));
+        let answer: Answer = serde_json::from_str("{}").unwrap();
+        assert_eq!(answer.result, None);
+    }
+}
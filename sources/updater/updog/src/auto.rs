@@ -0,0 +1,211 @@
+//! The policy `updog auto` follows, from settings.updates.auto, so a host can be left to update
+//! itself.  A timer runs `updog auto` about once an hour; each run goes as far through the update
+//! as the policy allows, and never ahead of the host's wave.
+
+use crate::activate::activate;
+use crate::config::load_config;
+use crate::error::{self, Result};
+use crate::messages::Message;
+use crate::prepared::Prepared;
+use crate::report::{Output, Report};
+use crate::status::CheckResult;
+use crate::{run_command, Arguments, Command};
+use chrono::{DateTime, Duration, NaiveTime, SecondsFormat, Utc};
+use serde::Deserialize;
+use snafu::{OptionExt, ResultExt};
+
+/// How far `updog auto` takes an update.  Each policy does everything the one before it does.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum AutoPolicy {
+    /// Do nothing; updates only happen when something else runs updog.
+    Off,
+    /// Check for an update and save what was found in the status file.
+    CheckOnly,
+    /// Prepare the update on the inactive partition set, without activating it.
+    Download,
+    /// Activate the prepared update, so it's booted at the next reboot, whenever that is.
+    Apply,
+    /// Prepare the update whenever it's available, but only activate it and reboot within the
+    /// reboot window.
+    ApplyAndRebootInWindow,
+}
+
+/// A daily window of time, in UTC, when automatic updates may reboot the host.  The window
+/// includes its start but not its end, and wraps past midnight if it ends before it starts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct RebootWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl RebootWindow {
+    /// Parses a window like "02:00-04:00", or returns None if it isn't one.
+    pub(crate) fn parse(window: &str) -> Option<Self> {
+        let mut times = window.splitn(2, '-');
+        let start = NaiveTime::parse_from_str(times.next()?, "%H:%M").ok()?;
+        let end = NaiveTime::parse_from_str(times.next()?, "%H:%M").ok()?;
+        if start == end {
+            return None;
+        }
+        Some(Self { start, end })
+    }
+
+    pub(crate) fn contains(&self, now: DateTime<Utc>) -> bool {
+        let time = now.time();
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+
+    /// The next time the window opens after `now`.
+    pub(crate) fn next_start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let today = DateTime::<Utc>::from_utc(now.date().naive_utc().and_time(self.start), Utc);
+        if today > now {
+            today
+        } else {
+            today + Duration::days(1)
+        }
+    }
+}
+
+/// Takes an update as far as the configured policy allows.  This runs on a timer whether or not
+/// there's an update, so finding none, or finding one for a later wave, isn't a failure.  An
+/// update that's already activated isn't prepared again; it's waiting for a reboot, which
+/// `apply-and-reboot-in-window` does the next time it runs within the window.
+pub(crate) fn run(arguments: &Arguments) -> Result<Output> {
+    let paths = &arguments.paths;
+    let config = load_config(paths)?;
+    let policy = config.auto;
+    match policy {
+        AutoPolicy::Off => {
+            return Ok(Output::report(Report::note(Message::AutoOff)));
+        }
+        AutoPolicy::CheckOnly => {
+            return match run_command(arguments, Command::CheckUpdate) {
+                Err(error::Error::UpdateNotAvailable { .. }) => Ok(Output::new(
+                    CheckResult::NoUpdate,
+                    None,
+                    Report::note(Message::NoUpdateRequired),
+                )),
+                Err(error::Error::UpdateNotReady { version, .. }) => Ok(Output::new(
+                    CheckResult::UpdateScheduled,
+                    Some(&version),
+                    Report::note(Message::LaterWave),
+                )),
+                result => result,
+            };
+        }
+        _ => {}
+    }
+    // Check the window before downloading anything, so a policy that can never reboot fails
+    // right away.
+    let window = if policy == AutoPolicy::ApplyAndRebootInWindow {
+        Some(
+            config
+                .reboot_window()?
+                .context(error::RebootWindowMissing)?,
+        )
+    } else {
+        None
+    };
+
+    // An update that's already activated is the one to reboot into.
+    let activated = match Prepared::load(&paths.prepared).context(error::PreparedRead {
+        path: &paths.prepared,
+    })? {
+        Some(prepared) if prepared.is_activated(&paths.partition_state()?) => Some(prepared),
+        _ => None,
+    };
+    let output = match &activated {
+        Some(prepared) => Output::new(
+            CheckResult::UpdateActivated,
+            Some(&prepared.version),
+            Report::Nothing,
+        ),
+        None => {
+            let output = run_command(arguments, Command::Prepare)?;
+            let result = output.outcome.as_ref().map(|outcome| outcome.result);
+            if result != Some(CheckResult::UpdatePrepared) {
+                return Ok(output);
+            }
+            output
+        }
+    };
+    if policy == AutoPolicy::Download {
+        return Ok(output);
+    }
+
+    let mut activate_arguments = arguments.clone();
+    activate_arguments.not_before = None;
+    activate_arguments.wait = false;
+    activate_arguments.skip_soak = false;
+    activate_arguments.reboot = false;
+    if let Some(window) = window {
+        let now = Utc::now();
+        if !window.contains(now) {
+            return Ok(Output {
+                outcome: output.outcome,
+                report: Report::Note {
+                    message: Message::WaitingForRebootWindow,
+                    detail: Some(
+                        window
+                            .next_start(now)
+                            .to_rfc3339_opts(SecondsFormat::Secs, true),
+                    ),
+                },
+            });
+        }
+        activate_arguments.reboot = true;
+    }
+    match activate(paths, &activate_arguments) {
+        // Vetoes lift on their own, and a later run activates the update, so this isn't a failure.
+        Err(error::Error::UpdateVetoed {
+            vetoes, version, ..
+        }) => Ok(Output::new(
+            CheckResult::UpdateVetoed,
+            version.as_ref(),
+            Report::Note {
+                message: Message::UpdateVetoed,
+                detail: Some(vetoes),
+            },
+        )),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(&format!("2020-03-26T{}:00Z", time))
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn reboot_window() {
+        let night = RebootWindow::parse("02:00-04:00").unwrap();
+        assert!(!night.contains(at("01:59")));
+        assert!(night.contains(at("02:00")));
+        assert!(night.contains(at("03:59")));
+        assert!(!night.contains(at("04:00")));
+        assert_eq!(night.next_start(at("01:00")), at("02:00"));
+        assert_eq!(
+            night.next_start(at("02:30")),
+            at("02:00") + Duration::days(1)
+        );
+
+        let wrapping = RebootWindow::parse("22:00-02:00").unwrap();
+        assert!(wrapping.contains(at("23:00")));
+        assert!(wrapping.contains(at("01:00")));
+        assert!(!wrapping.contains(at("12:00")));
+
+        for bad in &["", "02:00", "02:00-02:00", "2am-4am", "02:00-25:00"] {
+            assert_eq!(RebootWindow::parse(bad), None, "{}", bad);
+        }
+    }
+}
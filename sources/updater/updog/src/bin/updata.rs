@@ -1,31 +1,104 @@
 #![deny(rust_2018_idioms)]
 #![warn(clippy::pedantic)]
 
+// updata only makes bundles; updog unpacks them.
+#[path = "updata/adoption.rs"]
+mod adoption;
+#[path = "updata/backup.rs"]
+mod backup;
+#[allow(dead_code)]
+#[path = "../bundle.rs"]
+mod bundle;
+#[path = "updata/diff.rs"]
+mod diff;
+#[path = "updata/edit.rs"]
+mod edit;
 #[path = "../error.rs"]
 mod error;
+#[path = "updata/gc.rs"]
+mod gc;
+#[path = "updata/images.rs"]
+mod images;
+#[path = "updata/lint.rs"]
+mod lint;
+#[path = "../logging.rs"]
+mod logging;
+#[path = "updata/merge.rs"]
+mod merge;
+#[path = "updata/migration_path.rs"]
+mod migration_path;
+#[path = "updata/mirror.rs"]
+mod mirror;
+// updata only fetches with these to mirror repositories.
+#[allow(dead_code)]
+#[path = "../oci.rs"]
+mod oci;
+#[path = "updata/plan.rs"]
+mod plan;
+#[path = "updata/presets.rs"]
+mod presets;
+#[path = "updata/prune.rs"]
+mod prune;
+#[path = "updata/report.rs"]
+mod report;
+#[path = "updata/rotation.rs"]
+mod rotation;
+// updata only uses S3 to read and write manifests, not to fetch the way updog does.
+#[allow(dead_code)]
+#[path = "../s3.rs"]
+mod s3;
+#[path = "updata/simulate.rs"]
+mod simulate;
+#[path = "updata/stats.rs"]
+mod stats;
+#[path = "updata/store.rs"]
+mod store;
+#[path = "updata/strict.rs"]
+mod strict;
+#[allow(dead_code)]
+#[path = "../transport.rs"]
+mod transport;
 
 #[macro_use]
 extern crate log;
 
+use crate::adoption::Adoption;
+use crate::diff::ManifestDiff;
+use crate::edit::Editor;
 use crate::error::Result;
-use semver::Version;
-use simplelog::{Config as LogConfig, LevelFilter, TermLogger, TerminalMode};
-use snafu::{ErrorCompat, OptionExt, ResultExt};
+use crate::lint::{ReleaseFiles, Severity};
+use crate::logging::LogTarget;
+use crate::mirror::Selection;
+use crate::plan::Plan;
+use crate::presets::Presets;
+use crate::report::{Fleet, MetadataSizes, SizeReport};
+use crate::store::ManifestFile;
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::LevelFilter;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use semver::{Version, VersionReq};
+use snafu::{ensure, ErrorCompat, OptionExt, ResultExt};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
-use update_metadata::{Images, Manifest, Release, UpdateWaves};
+use tough::schema::decoded::{Decoded, Hex};
+use tough::sign::Sign;
+use update_metadata::{
+    Images, Manifest, Provenance, Release, SeedRange, Targeting, Update, UpdateWaves,
+};
+use url::Url;
 
 #[derive(Debug, StructOpt)]
 struct GeneralArgs {
     // metadata file to create/modify
-    file: PathBuf,
+    file: ManifestFile,
 }
 
 #[derive(Debug, StructOpt)]
 struct AddUpdateArgs {
     // metadata file to create/modify
-    file: PathBuf,
+    file: ManifestFile,
 
     // image 'variant', eg. 'aws-k8s-1.15'
     #[structopt(short = "f", long = "variant")]
@@ -35,54 +108,303 @@ struct AddUpdateArgs {
     #[structopt(short = "v", long = "version")]
     image_version: Version,
 
-    // architecture image is built for
-    #[structopt(short = "a", long = "arch")]
-    arch: String,
+    // architectures image is built for; repeat to add an update for each, or give 'all' for
+    // every architecture Bottlerocket is built for
+    #[structopt(short = "a", long = "arch", required = true, number_of_values = 1)]
+    arch: Vec<String>,
 
     // maximum valid version
     #[structopt(short = "m", long = "max-version")]
     max_version: Option<Version>,
 
-    // root image target name
+    // root image target name; '{arch}' is replaced with each update's architecture
     #[structopt(short = "r", long = "root")]
     root: String,
 
-    // boot image target name
+    // boot image target name; '{arch}' is replaced with each update's architecture
     #[structopt(short = "b", long = "boot")]
     boot: String,
 
-    // verity "hash" image target name
+    // verity "hash" image target name; '{arch}' is replaced with each update's architecture
     #[structopt(short = "h", long = "hash")]
     hash: String,
+
+    #[structopt(flatten)]
+    waves: PresetArgs,
+}
+
+/// How to give new updates waves from a preset, for the commands that add updates.
+#[derive(Debug, StructOpt)]
+struct PresetArgs {
+    // wave schedule to give each new update, like conservative-7d; see list-wave-presets
+    #[structopt(long = "wave-preset")]
+    wave_preset: Option<String>,
+
+    // when the first wave of the preset starts, like '2024-01-01 09:00 PST' or 'in 2 hours'
+    #[structopt(
+        long = "wave-start",
+        default_value = "now",
+        parse(try_from_str = parse_datetime::parse_datetime)
+    )]
+    wave_start: DateTime<Utc>,
+
+    // TOML file of more presets; one with a built-in preset's name replaces it
+    #[structopt(long = "presets")]
+    presets: Option<PathBuf>,
+}
+
+impl PresetArgs {
+    /// The waves of the chosen preset, if one was chosen.
+    fn waves(&self) -> Result<Option<UpdateWaves>> {
+        match &self.wave_preset {
+            Some(name) => {
+                let presets = Presets::load(self.presets.as_deref())?;
+                Ok(Some(presets.waves(name, self.wave_start)?))
+            }
+            None => Ok(None),
+        }
+    }
 }
 
+/// The architectures `--arch all` stands for.
+const ARCHES: &[&str] = &["x86_64", "aarch64"];
+/// Stands for the architecture in the target names given to add-update.
+const ARCH_PLACEHOLDER: &str = "{arch}";
+
 impl AddUpdateArgs {
     fn run(self) -> Result<()> {
-        let mut manifest: Manifest = match update_metadata::load_file(&self.file) {
+        let mut manifest: Manifest = match self.file.load() {
             Ok(m) => m,
             _ => Manifest::default(), // TODO only if EEXIST
         };
 
-        manifest.add_update(
-            self.image_version,
-            self.max_version,
-            self.arch,
-            self.variant,
-            Images {
-                root: self.root,
-                boot: self.boot,
-                hash: self.hash,
-            },
+        let mut arches: Vec<String> = Vec::new();
+        for arch in &self.arch {
+            let given = if arch == "all" {
+                ARCHES.iter().map(ToString::to_string).collect()
+            } else {
+                vec![arch.clone()]
+            };
+            for arch in given {
+                if !arches.contains(&arch) {
+                    arches.push(arch);
+                }
+            }
+        }
+        // Without the placeholder, every architecture's update would point at the same images.
+        if arches.len() > 1 {
+            for name in &[&self.root, &self.boot, &self.hash] {
+                ensure!(
+                    name.contains(ARCH_PLACEHOLDER),
+                    error::ArchTemplate {
+                        name: name.as_str()
+                    }
+                );
+            }
+        }
+
+        let waves = self.waves.waves()?;
+        for arch in arches {
+            let target = |name: &str| name.replace(ARCH_PLACEHOLDER, &arch);
+            let images = Images {
+                root: target(&self.root),
+                boot: target(&self.boot),
+                hash: target(&self.hash),
+            };
+            manifest.add_update(
+                self.image_version.clone(),
+                self.max_version.clone(),
+                arch.clone(),
+                self.variant.clone(),
+                images,
+            )?;
+            if let Some(waves) = &waves {
+                set_waves(
+                    &mut manifest,
+                    &self.variant,
+                    &arch,
+                    &self.image_version,
+                    waves,
+                )?;
+            }
+        }
+        self.file.write(&manifest)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct PlanArgs {
+    // metadata file to modify
+    file: ManifestFile,
+
+    // TOML file listing the steps to run
+    plan: PathBuf,
+}
+
+impl PlanArgs {
+    fn run(self) -> Result<()> {
+        let plan = Plan::load(&self.plan)?;
+        let mut manifest: Manifest = self.file.load()?;
+        plan.apply(&mut manifest)?;
+        self.file.write(&manifest)?;
+        info!("Applied {} steps from {}", plan.len(), self.plan.display());
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct CutReleaseArgs {
+    // metadata file to modify
+    file: ManifestFile,
+
+    // version being released
+    #[structopt(short = "v", long = "version")]
+    image_version: Version,
+
+    // directory the build wrote its images to
+    #[structopt(long = "from-build-dir")]
+    build_dir: PathBuf,
+
+    // file to get migrations from
+    #[structopt(short = "r", long = "release", default_value = "Release.toml")]
+    release: PathBuf,
+
+    // wave file, or the name of one in --waves-dir, like 'default' for default-waves.toml
+    #[structopt(
+        short = "w",
+        long = "wave-policy",
+        required_unless = "wave-preset",
+        conflicts_with = "wave-preset"
+    )]
+    wave_policy: Option<String>,
+
+    #[structopt(flatten)]
+    waves: PresetArgs,
+
+    // directory of wave files to find the wave policy in
+    #[structopt(long = "waves-dir", default_value = "sources/updater/waves")]
+    waves_dir: PathBuf,
+
+    // file that sets the lint rules to check; any finding fails the release, even a warning
+    #[structopt(short = "c", long = "lint-config")]
+    lint_config: Option<PathBuf>,
+
+    // changelog that should have release notes for the new version
+    #[structopt(short = "n", long = "changelog", default_value = "CHANGELOG.md")]
+    changelog: PathBuf,
+}
+
+impl CutReleaseArgs {
+    fn run(self) -> Result<()> {
+        let version = &self.image_version;
+        // A preset has no wave file to lint, but its waves are still linted in the new updates.
+        let (waves, wave_files) = match (&self.wave_policy, self.waves.waves()?) {
+            (Some(policy), _) => {
+                let wave_file = wave_policy(policy, &self.waves_dir)?;
+                (read_waves(&wave_file)?, vec![wave_file])
+            }
+            (None, Some(waves)) => (waves, Vec::new()),
+            (None, None) => return error::WavePolicyArg.fail(),
+        };
+        let release = read_release(&self.release)?;
+        ensure!(
+            release.version == *version,
+            error::ReleaseMismatch {
+                path: &self.release,
+                found: release.version.clone(),
+                version: version.clone(),
+            }
+        );
+        let built = images::find(&self.build_dir, version)?;
+
+        let old = self.file.load()?;
+        let mut manifest = self.file.load()?;
+        let max_version = manifest
+            .updates
+            .iter()
+            .map(|u| &u.max_version)
+            .chain(Some(version))
+            .max()
+            .cloned()
+            .unwrap_or_else(|| version.clone());
+        for build in built {
+            ensure!(
+                !manifest.updates.iter().any(|u| u.variant == build.variant
+                    && u.arch == build.arch
+                    && u.version == *version),
+                error::UpdateExists {
+                    arch: build.arch,
+                    variant: build.variant,
+                    version: version.clone(),
+                }
+            );
+            info!("Adding update {}-{}-{}", build.arch, build.variant, version);
+            manifest.add_update(
+                version.clone(),
+                Some(max_version.clone()),
+                build.arch.clone(),
+                build.variant.clone(),
+                build.images,
+            )?;
+            set_waves(&mut manifest, &build.variant, &build.arch, version, &waves)?;
+        }
+        manifest.update_max_version(&max_version, None, None);
+        manifest.migrations = release.migrations;
+
+        // Check the release strictly, before anything is written.
+        manifest.validate_compatibility()?;
+        let config = match &self.lint_config {
+            Some(path) => lint::Config::load(path)?,
+            None => lint::Config::default(),
+        };
+        let files = ReleaseFiles::new(
+            Some(manifest),
+            Some(version.clone()),
+            &wave_files,
+            Some(&self.changelog),
         )?;
-        update_metadata::write_file(&self.file, &manifest)?;
+        let findings = lint::lint(&config, &files);
+        for finding in &findings {
+            error!("{}", finding);
+        }
+        ensure!(
+            findings.is_empty(),
+            error::LintFailed {
+                errors: findings.len()
+            }
+        );
+        let manifest = files.into_manifest();
+
+        // A dry run prints the diff as it skips the write.
+        if !self.file.is_dry_run() {
+            print!("{}", ManifestDiff::new(&old, &manifest));
+        }
+        self.file.write(&manifest)?;
+        info!("Release {} cut", version);
         Ok(())
     }
 }
 
+/// Finds a wave policy: a wave file, or the name of one in `dir`, with or without its
+/// `-waves.toml` or `.toml` suffix.
+fn wave_policy(policy: &str, dir: &Path) -> Result<PathBuf> {
+    let candidates = [
+        PathBuf::from(policy),
+        dir.join(format!("{}-waves.toml", policy)),
+        dir.join(format!("{}.toml", policy)),
+    ];
+    candidates
+        .iter()
+        .find(|path| path.is_file())
+        .cloned()
+        .context(error::WavePolicy { policy, dir })
+}
+
 #[derive(Debug, StructOpt)]
 struct RemoveUpdateArgs {
     // metadata file to create/modify
-    file: PathBuf,
+    file: ManifestFile,
 
     // image 'variant', eg. 'aws-k8s-1.15'
     #[structopt(short = "l", long = "variant")]
@@ -99,7 +421,7 @@ struct RemoveUpdateArgs {
 
 impl RemoveUpdateArgs {
     fn run(&self) -> Result<()> {
-        let mut manifest: Manifest = update_metadata::load_file(&self.file)?;
+        let mut manifest: Manifest = self.file.load()?;
         // Remove any update that exactly matches the specified update
         manifest.updates.retain(|update| {
             update.arch != self.arch
@@ -107,7 +429,7 @@ impl RemoveUpdateArgs {
                 || update.version != self.image_version
         });
         // Note: We don't revert the maximum version on removal
-        update_metadata::write_file(&self.file, &manifest)?;
+        self.file.write(&manifest)?;
         if let Some(current) = manifest.updates.first() {
             info!(
                 "Update {}-{}-{} removed. Current maximum version: {}",
@@ -123,10 +445,535 @@ impl RemoveUpdateArgs {
     }
 }
 
+#[derive(Debug, StructOpt)]
+struct CopyUpdateArgs {
+    // metadata file to modify
+    file: ManifestFile,
+
+    // image 'variant' of the update to copy, eg. 'aws-dev'
+    #[structopt(long = "from-variant")]
+    from_variant: String,
+
+    // image 'variant' to add the copy for, eg. 'aws-k8s-1.15'
+    #[structopt(long = "to-variant")]
+    to_variant: String,
+
+    // image version
+    #[structopt(short = "v", long = "version")]
+    image_version: Version,
+
+    // architecture image is built for
+    #[structopt(short = "a", long = "arch")]
+    arch: String,
+
+    // maximum valid version, if not the copied update's
+    #[structopt(short = "m", long = "max-version")]
+    max_version: Option<Version>,
+
+    // root image target name, if not the copied update's
+    #[structopt(short = "r", long = "root")]
+    root: Option<String>,
+
+    // boot image target name, if not the copied update's
+    #[structopt(short = "b", long = "boot")]
+    boot: Option<String>,
+
+    // verity "hash" image target name, if not the copied update's
+    #[structopt(short = "h", long = "hash")]
+    hash: Option<String>,
+
+    // also copy the update's waves and targeting
+    #[structopt(long = "with-waves")]
+    with_waves: bool,
+}
+
+impl CopyUpdateArgs {
+    fn run(self) -> Result<()> {
+        let mut manifest: Manifest = self.file.load()?;
+        let find = |variant: &str| {
+            manifest.updates.iter().position(|update| {
+                update.variant == variant
+                    && update.arch == self.arch
+                    && update.version == self.image_version
+            })
+        };
+        ensure!(
+            find(&self.to_variant).is_none(),
+            error::UpdateExists {
+                arch: &self.arch,
+                variant: &self.to_variant,
+                version: self.image_version.clone(),
+            }
+        );
+        let from = &manifest.updates[find(&self.from_variant).context(error::UpdateNotFound {
+            arch: &self.arch,
+            variant: &self.from_variant,
+            version: self.image_version.clone(),
+        })?];
+
+        let images = Images {
+            root: self.root.as_ref().unwrap_or(&from.images.root).clone(),
+            boot: self.boot.as_ref().unwrap_or(&from.images.boot).clone(),
+            hash: self.hash.as_ref().unwrap_or(&from.images.hash).clone(),
+        };
+        // Provenance describes the images, so it only still applies if they're the same.
+        let provenance = if images == from.images {
+            from.provenance.clone()
+        } else {
+            None
+        };
+        let (waves, targeting) = if self.with_waves {
+            (from.waves.clone(), from.targeting.clone())
+        } else {
+            (BTreeMap::new(), None)
+        };
+        let max_version = self
+            .max_version
+            .clone()
+            .unwrap_or_else(|| from.max_version.clone());
+
+        manifest.add_update(
+            self.image_version.clone(),
+            Some(max_version),
+            self.arch.clone(),
+            self.to_variant.clone(),
+            images,
+        )?;
+        // add_update puts the new update last.
+        if let Some(copy) = manifest.updates.last_mut() {
+            copy.waves = waves;
+            copy.targeting = targeting;
+            copy.provenance = provenance;
+        }
+        self.file.write(&manifest)?;
+        info!(
+            "Copied update {}-{}-{} to {}",
+            self.arch, self.from_variant, self.image_version, self.to_variant
+        );
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct PruneArgs {
+    // metadata file to prune
+    file: ManifestFile,
+
+    // number of the newest updates to keep for each variant and architecture
+    #[structopt(short = "n", long = "keep-last", required_unless = "older-than")]
+    keep_last: Option<usize>,
+
+    // only prune updates whose first wave started before this, like '90 days ago', '-90d', or '2024-01-01'
+    #[structopt(long = "older-than", parse(try_from_str = prune::parse_cutoff))]
+    older_than: Option<DateTime<Utc>>,
+
+    // only prune updates for this image 'variant', eg. 'aws-k8s-1.15'
+    #[structopt(short = "f", long = "variant")]
+    variant: Option<String>,
+
+    // only prune updates for this architecture
+    #[structopt(short = "a", long = "arch")]
+    arch: Option<String>,
+}
+
+impl PruneArgs {
+    fn run(&self) -> Result<()> {
+        let mut manifest: Manifest = self.file.load()?;
+        let pruned = prune::prune(
+            &mut manifest,
+            &prune::Rules {
+                keep_last: self.keep_last,
+                older_than: self.older_than,
+                variant: self.variant.as_deref(),
+                arch: self.arch.as_deref(),
+            },
+        );
+        if pruned.is_empty() {
+            info!("Nothing to prune");
+            return Ok(());
+        }
+        for update in &pruned.updates {
+            println!("Removed update {}", update);
+        }
+        for (from, to) in &pruned.migrations {
+            println!("Removed migrations from {} to {}", from, to);
+        }
+        self.file.write(&manifest)
+    }
+}
+
 #[derive(Debug, StructOpt)]
 struct WaveArgs {
     // metadata file to create/modify
-    file: PathBuf,
+    file: ManifestFile,
+
+    // image 'variant', eg. 'aws-k8s-1.15'
+    #[structopt(short = "l", long = "variant")]
+    variant: String,
+
+    // image version
+    #[structopt(short = "v", long = "version")]
+    image_version: Version,
+
+    // architecture image is built for
+    #[structopt(short = "a", long = "arch")]
+    arch: String,
+
+    // file that contains wave structure
+    #[structopt(
+        short = "w",
+        long = "wave-file",
+        conflicts_with_all = &["bound", "fleet-percentage", "start"]
+    )]
+    wave_file: Option<PathBuf>,
+
+    // seed, out of 2048, where a single wave to add starts, rather than setting all the waves
+    // from a wave file
+    #[structopt(short = "b", long = "bound", conflicts_with = "fleet-percentage")]
+    bound: Option<u32>,
+
+    // share of the fleet a single wave to add follows, like 25 for a wave that starts after the
+    // first quarter of the fleet; converted to the wave's bound
+    #[structopt(short = "p", long = "fleet-percentage")]
+    fleet_percentage: Option<u32>,
+
+    // when the single wave starts, like '2024-01-01 09:00 PST' or 'in 2 hours'
+    #[structopt(short = "s", long = "start", parse(try_from_str = parse_datetime::parse_datetime))]
+    start: Option<DateTime<Utc>>,
+}
+
+impl WaveArgs {
+    fn set(self) -> Result<()> {
+        let mut manifest: Manifest = self.file.load()?;
+
+        if let Some(wave_file) = &self.wave_file {
+            let waves = read_waves(wave_file)?;
+            set_waves(
+                &mut manifest,
+                &self.variant,
+                &self.arch,
+                &self.image_version,
+                &waves,
+            )?;
+        } else {
+            let bound = match (self.bound, self.fleet_percentage) {
+                (Some(bound), _) => bound,
+                (None, Some(percentage)) => {
+                    ensure!(percentage < 100, error::WavePercentage { percentage });
+                    update_metadata::percentage_seed(percentage)
+                }
+                (None, None) => return error::WaveFileArg.fail(),
+            };
+            let start = self.start.context(error::WaveFileArg)?;
+            let num_matching = manifest.add_wave(
+                self.variant.clone(),
+                self.arch.clone(),
+                self.image_version.clone(),
+                bound,
+                start,
+            )?;
+            ensure!(
+                num_matching > 0,
+                error::UpdateNotFound {
+                    arch: &self.arch,
+                    variant: &self.variant,
+                    version: self.image_version.clone(),
+                }
+            );
+            info!(
+                "Wave starting at seed {}: {}",
+                fleet_share(bound),
+                start.to_rfc3339_opts(SecondsFormat::Secs, true)
+            );
+        }
+        self.file.write(&manifest)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct AddWaveArgs {
+    // metadata file to modify
+    file: ManifestFile,
+
+    // image 'variant', eg. 'aws-k8s-1.15'
+    #[structopt(short = "l", long = "variant")]
+    variant: String,
+
+    // image version
+    #[structopt(short = "v", long = "version")]
+    image_version: Version,
+
+    // architecture image is built for
+    #[structopt(short = "a", long = "arch")]
+    arch: String,
+
+    // wave schedule to set, like canary or conservative-7d; see list-wave-presets
+    #[structopt(short = "p", long = "preset")]
+    preset: String,
+
+    // when the first wave starts, like '2024-01-01 09:00 PST' or 'in 2 hours'
+    #[structopt(
+        short = "s",
+        long = "start",
+        default_value = "now",
+        parse(try_from_str = parse_datetime::parse_datetime)
+    )]
+    start: DateTime<Utc>,
+
+    // TOML file of more presets; one with a built-in preset's name replaces it
+    #[structopt(long = "presets")]
+    presets: Option<PathBuf>,
+}
+
+impl AddWaveArgs {
+    fn run(self) -> Result<()> {
+        let mut manifest: Manifest = self.file.load()?;
+        let presets = Presets::load(self.presets.as_deref())?;
+        let waves = presets.waves(&self.preset, self.start)?;
+        let num_matching = set_waves(
+            &mut manifest,
+            &self.variant,
+            &self.arch,
+            &self.image_version,
+            &waves,
+        )?;
+        ensure!(
+            num_matching > 0,
+            error::UpdateNotFound {
+                arch: &self.arch,
+                variant: &self.variant,
+                version: self.image_version.clone(),
+            }
+        );
+        self.file.write(&manifest)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct ListWavePresetsArgs {
+    // TOML file of more presets; one with a built-in preset's name replaces it
+    #[structopt(long = "presets")]
+    presets: Option<PathBuf>,
+}
+
+impl ListWavePresetsArgs {
+    fn run(self) -> Result<()> {
+        print!("{}", Presets::load(self.presets.as_deref())?);
+        Ok(())
+    }
+}
+
+/// A wave's seed, with the share of the fleet before it, like `512 (25.0% of the fleet)`.
+fn fleet_share(seed: u32) -> String {
+    format!(
+        "{} ({:.1}% of the fleet)",
+        seed,
+        update_metadata::seed_percentage(seed)
+    )
+}
+
+fn read_waves(path: &Path) -> Result<UpdateWaves> {
+    let wave_str = fs::read_to_string(path).context(error::ConfigRead { path })?;
+    toml::from_str(&wave_str).context(error::ConfigParse { path })
+}
+
+/// Sets the waves of an update, showing their start times in UTC, so a wrong timezone is caught
+/// before the manifest ships.  Returns the number of updates that matched.
+fn set_waves(
+    manifest: &mut Manifest,
+    variant: &str,
+    arch: &str,
+    image_version: &Version,
+    waves: &UpdateWaves,
+) -> Result<usize> {
+    let num_matching = manifest.set_waves(
+        variant.to_string(),
+        arch.to_string(),
+        image_version.clone(),
+        waves,
+    )?;
+
+    if num_matching > 1 {
+        warn!("Multiple matching updates for wave - this is weird but not a disaster");
+    }
+    for update in manifest
+        .updates
+        .iter()
+        .filter(|u| u.variant == variant && u.arch == arch && u.version == *image_version)
+    {
+        for (seed, start) in &update.waves {
+            info!(
+                "Wave starting at seed {}: {}",
+                fleet_share(*seed),
+                start.to_rfc3339_opts(SecondsFormat::Secs, true)
+            );
+        }
+    }
+    Ok(num_matching)
+}
+
+#[derive(Debug, StructOpt)]
+struct RebalanceArgs {
+    // metadata file to modify
+    file: ManifestFile,
+
+    // image 'variant', eg. 'aws-k8s-1.15'
+    #[structopt(short = "l", long = "variant")]
+    variant: String,
+
+    // image version
+    #[structopt(short = "v", long = "version")]
+    image_version: Version,
+
+    // architecture image is built for
+    #[structopt(short = "a", long = "arch")]
+    arch: String,
+
+    // number of hosts the waves are for
+    #[structopt(long = "fleet-hosts")]
+    fleet_hosts: u64,
+
+    // share of the fleet that should have started by the end of each wave but the last, like
+    // '0.1,5,25'; without it, each wave keeps the share it has now
+    #[structopt(long = "percentages", use_delimiter = true)]
+    percentages: Vec<f64>,
+
+    // fewest hosts each wave should expect to hold
+    #[structopt(long = "min-hosts", default_value = "1")]
+    min_hosts: u64,
+}
+
+impl RebalanceArgs {
+    #[allow(clippy::cast_precision_loss)]
+    fn run(self) -> Result<()> {
+        let mut manifest: Manifest = self.file.load()?;
+        let current: Vec<u32> = manifest
+            .updates
+            .iter()
+            .find(|u| {
+                u.variant == self.variant && u.arch == self.arch && u.version == self.image_version
+            })
+            .context(error::UpdateNotFound {
+                arch: &self.arch,
+                variant: &self.variant,
+                version: self.image_version.clone(),
+            })?
+            .waves
+            .keys()
+            .copied()
+            .collect();
+        let bounds = rebalance(
+            &current,
+            &self.percentages,
+            self.fleet_hosts,
+            self.min_hosts,
+        )?;
+        let num_matching = manifest.set_wave_bounds(
+            self.variant.clone(),
+            self.arch.clone(),
+            self.image_version.clone(),
+            &bounds,
+        )?;
+        if num_matching > 1 {
+            warn!("Multiple matching updates for wave - this is weird but not a disaster");
+        }
+        self.file.write(&manifest)?;
+
+        let seeds = f64::from(update_metadata::MAX_SEED);
+        let ends = bounds
+            .iter()
+            .skip(1)
+            .copied()
+            .chain(Some(update_metadata::MAX_SEED));
+        for (i, (start, end)) in bounds.iter().zip(ends).enumerate() {
+            info!(
+                "Wave {}: seeds {}..{}, about {} hosts",
+                i,
+                start,
+                end,
+                (f64::from(end - start) / seeds * self.fleet_hosts as f64).round()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Works out new seed bounds for waves starting at `current`, so each wave but the last ends
+/// with the given cumulative percentage of the fleet started, or the percentage it ends with now
+/// if none are given.  Every wave is kept large enough to expect `min_hosts` hosts out of
+/// `fleet_hosts`, since seeds are all a wave can be given and a small fleet leaves some seeds
+/// without any hosts.
+// Seeds and percentages are small enough that the casts are exact.
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn rebalance(
+    current: &[u32],
+    percentages: &[f64],
+    fleet_hosts: u64,
+    min_hosts: u64,
+) -> Result<Vec<u32>> {
+    let max_seed = f64::from(update_metadata::MAX_SEED);
+    let percentages: Vec<f64> = if percentages.is_empty() {
+        current
+            .iter()
+            .skip(1)
+            .map(|&bound| f64::from(bound) / max_seed * 100.0)
+            .collect()
+    } else {
+        percentages.to_vec()
+    };
+    ensure!(
+        percentages.len() + 1 == current.len(),
+        error::RebalanceCount {
+            waves: current.len(),
+            provided: percentages.len(),
+        }
+    );
+    ensure!(fleet_hosts > 0, error::RebalanceFleet);
+    // The fewest seeds that hold `min_hosts` hosts, on average.
+    let min_seeds = ((min_hosts as f64 * max_seed / fleet_hosts as f64).ceil() as u32).max(1);
+    // The cast saturates for a tiny fleet, so check the waves can fit before adding seeds up.
+    let fit = error::RebalanceFit {
+        waves: current.len(),
+        min_hosts,
+        fleet_hosts,
+    };
+    ensure!(min_seeds <= update_metadata::MAX_SEED, fit);
+
+    let mut bounds = Vec::with_capacity(current.len());
+    let mut previous = match current.first() {
+        Some(&first) => first,
+        None => return Ok(bounds),
+    };
+    bounds.push(previous);
+    let mut last_percentage = 0.0;
+    for &percentage in &percentages {
+        ensure!(
+            percentage > last_percentage && percentage < 100.0,
+            error::RebalancePercentage { percentage }
+        );
+        last_percentage = percentage;
+        let bound = ((percentage / 100.0 * max_seed).round() as u32)
+            .max(previous.saturating_add(min_seeds));
+        bounds.push(bound);
+        previous = bound;
+    }
+    ensure!(
+        previous.saturating_add(min_seeds) <= update_metadata::MAX_SEED,
+        fit
+    );
+    Ok(bounds)
+}
+
+#[derive(Debug, StructOpt)]
+struct TargetingArgs {
+    // metadata file to create/modify
+    file: ManifestFile,
 
     // image 'variant', eg. 'aws-k8s-1.15'
     #[structopt(short = "l", long = "variant")]
@@ -140,120 +987,1653 @@ struct WaveArgs {
     #[structopt(short = "a", long = "arch")]
     arch: String,
 
-    // file that contains wave structure
-    #[structopt(short = "w", long = "wave-file", conflicts_with_all = &["bound", "start"])]
-    wave_file: Option<PathBuf>,
+    // range of host seeds the update is for, like '0..1024'; may be given more than once
+    #[structopt(short = "s", long = "seeds", parse(try_from_str = parse_seed_range))]
+    seeds: Vec<SeedRange>,
+
+    // label hosts must have for the update, like 'gpu=true'; may be given more than once
+    #[structopt(short = "t", long = "label", parse(try_from_str = parse_label))]
+    labels: Vec<(String, String)>,
+}
+
+/// Parses a seed range like '0..1024', which includes 0 but not 1024.
+fn parse_seed_range(input: &str) -> Result<SeedRange> {
+    let mut parts = input.splitn(2, "..");
+    let start = parts.next().and_then(|s| s.parse().ok());
+    let end = parts.next().and_then(|s| s.parse().ok());
+    match (start, end) {
+        (Some(start), Some(end)) => Ok(SeedRange { start, end }),
+        _ => error::SeedRangeArg { input }.fail(),
+    }
+}
+
+/// Parses a label like 'gpu=true'.  Keys and values can only use the characters hosts can use in
+/// `settings.updates.labels`.
+fn parse_label(input: &str) -> Result<(String, String)> {
+    let valid = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    let mut parts = input.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(key), Some(value)) if valid(key) && valid(value) => {
+            Ok((key.to_string(), value.to_string()))
+        }
+        _ => error::LabelArg { input }.fail(),
+    }
+}
+
+impl TargetingArgs {
+    fn set(self) -> Result<()> {
+        let mut manifest: Manifest = self.file.load()?;
+        let targeting = if self.seeds.is_empty() && self.labels.is_empty() {
+            None
+        } else {
+            Some(Targeting {
+                seeds: self.seeds,
+                labels: self.labels.into_iter().collect(),
+            })
+        };
+        let num_matching = manifest.set_targeting(
+            self.variant.clone(),
+            self.arch.clone(),
+            self.image_version.clone(),
+            targeting.as_ref(),
+        )?;
+        ensure!(
+            num_matching > 0,
+            error::UpdateNotFound {
+                arch: &self.arch,
+                variant: &self.variant,
+                version: self.image_version.clone(),
+            }
+        );
+        self.file.write(&manifest)?;
+        if targeting.is_some() {
+            info!(
+                "Update {}-{}-{} restricted to targeted hosts",
+                self.arch, self.variant, self.image_version
+            );
+        } else {
+            info!(
+                "Update {}-{}-{} offered to every host",
+                self.arch, self.variant, self.image_version
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct CompatibilityArgs {
+    // metadata file to create/modify
+    file: ManifestFile,
+
+    // 'variant' of the hosts the rule is for, eg. 'aws-k8s-1.15'
+    #[structopt(short = "l", long = "variant")]
+    variant: String,
+
+    // architecture of the hosts the rule is for; every architecture if not given
+    #[structopt(short = "a", long = "arch")]
+    arch: Option<String>,
+
+    // variant whose updates the hosts may take, including their own to keep taking its updates;
+    // may be given more than once, or not at all to hold the hosts back
+    #[structopt(short = "u", long = "updates-to", conflicts_with = "remove")]
+    updates_to: Vec<String>,
+
+    // remove the rule, so the hosts only take their own variant's updates
+    #[structopt(long = "remove")]
+    remove: bool,
+}
+
+impl CompatibilityArgs {
+    fn run(self) -> Result<()> {
+        let mut manifest: Manifest = self.file.load()?;
+        let updates_to = if self.remove {
+            None
+        } else {
+            Some(self.updates_to.clone())
+        };
+        let hosts = match &self.arch {
+            Some(arch) => format!("{} on {}", self.variant, arch),
+            None => self.variant.clone(),
+        };
+        let existed = manifest.set_compatibility(self.variant, self.arch, updates_to)?;
+        self.file.write(&manifest)?;
+        if self.remove {
+            if existed {
+                info!("Hosts of {} only take their own variant's updates", hosts);
+            } else {
+                warn!("There was no compatibility rule for {}", hosts);
+            }
+        } else if self.updates_to.is_empty() {
+            info!("Hosts of {} held back from taking updates", hosts);
+        } else {
+            info!(
+                "Hosts of {} take updates for {}",
+                hosts,
+                self.updates_to.join(", ")
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct ValidateArgs {
+    // manifest to check
+    file: ManifestFile,
+
+    // also check waves, max versions, and migration paths, listing every problem found
+    #[structopt(long = "strict")]
+    strict: bool,
+
+    // print the problems --strict finds as JSON
+    #[structopt(long = "json", requires = "strict")]
+    json: bool,
+}
+
+impl ValidateArgs {
+    fn run(self) -> Result<()> {
+        let manifest = validate(&self.file)?;
+        if !self.strict {
+            return Ok(());
+        }
+        let problems = strict::check(&manifest);
+        if self.json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&problems).context(error::UpdateSerialize)?
+            );
+        } else {
+            for problem in &problems {
+                println!("{}", problem);
+            }
+        }
+        ensure!(
+            problems.is_empty(),
+            error::ValidateStrict {
+                problems: problems.len()
+            }
+        );
+        Ok(())
+    }
+}
+
+/// Loads a manifest to check it, warning about compatibility rules that point hosts at variants
+/// the manifest has no updates for.  Returns the manifest, for deeper checks.
+fn validate(file: &ManifestFile) -> Result<Manifest> {
+    let manifest = file.load()?;
+    manifest.validate_compatibility()?;
+    for update in &manifest.updates {
+        for (seed, start) in &update.waves {
+            info!(
+                "{}-{}-{}: wave starting at seed {}: {}",
+                update.arch,
+                update.variant,
+                update.version,
+                fleet_share(*seed),
+                start.to_rfc3339_opts(SecondsFormat::Secs, true)
+            );
+        }
+    }
+    for rule in &manifest.compatibility {
+        for variant in &rule.updates_to {
+            let arch = rule.arch.as_ref();
+            if !manifest
+                .updates
+                .iter()
+                .any(|u| &u.variant == variant && arch.map_or(true, |arch| &u.arch == arch))
+            {
+                warn!(
+                    "Hosts of {} may take updates for {}, but there are none",
+                    rule.variant, variant
+                );
+            }
+        }
+    }
+    Ok(manifest)
+}
+
+#[derive(Debug, StructOpt)]
+struct RolloutArgs {
+    // metadata file to create/modify
+    file: ManifestFile,
+
+    // image 'variant', eg. 'aws-k8s-1.15'
+    #[structopt(short = "l", long = "variant")]
+    variant: String,
+
+    // image version
+    #[structopt(short = "v", long = "version")]
+    image_version: Version,
+
+    // architecture image is built for
+    #[structopt(short = "a", long = "arch")]
+    arch: String,
+}
+
+impl RolloutArgs {
+    fn pause(self) -> Result<()> {
+        let mut manifest: Manifest = self.file.load()?;
+        let num_matching = manifest.pause_rollout(
+            self.variant.clone(),
+            self.arch.clone(),
+            self.image_version.clone(),
+            Utc::now(),
+        );
+        self.check_matching(num_matching)?;
+        self.file.write(&manifest)?;
+        info!(
+            "Rollout of {}-{}-{} paused",
+            self.arch, self.variant, self.image_version
+        );
+        Ok(())
+    }
+
+    fn resume(self) -> Result<()> {
+        let mut manifest: Manifest = self.file.load()?;
+        let num_matching = manifest.resume_rollout(
+            self.variant.clone(),
+            self.arch.clone(),
+            self.image_version.clone(),
+            Utc::now(),
+        )?;
+        self.check_matching(num_matching)?;
+        self.file.write(&manifest)?;
+        info!(
+            "Rollout of {}-{}-{} resumed",
+            self.arch, self.variant, self.image_version
+        );
+        Ok(())
+    }
+
+    fn clear_waves(self) -> Result<()> {
+        let mut manifest: Manifest = self.file.load()?;
+        let num_matching = manifest.clear_waves(
+            self.variant.clone(),
+            self.arch.clone(),
+            self.image_version.clone(),
+        );
+        self.check_matching(num_matching)?;
+        self.file.write(&manifest)?;
+        warn!(
+            "Cleared the waves of {}-{}-{}; until new waves are set, every host is offered it",
+            self.arch, self.variant, self.image_version
+        );
+        Ok(())
+    }
+
+    fn set_available(self, available: bool) -> Result<()> {
+        let mut manifest: Manifest = self.file.load()?;
+        let num_matching = manifest.set_available(
+            self.variant.clone(),
+            self.arch.clone(),
+            self.image_version.clone(),
+            available,
+        );
+        self.check_matching(num_matching)?;
+        self.file.write(&manifest)?;
+        info!(
+            "{}-{}-{} {}",
+            self.arch,
+            self.variant,
+            self.image_version,
+            if available {
+                "reinstated"
+            } else {
+                "deprecated"
+            }
+        );
+        Ok(())
+    }
+
+    /// Makes sure some update matched, since a pause that does nothing could go unnoticed.
+    fn check_matching(&self, num_matching: usize) -> Result<()> {
+        ensure!(
+            num_matching > 0,
+            error::UpdateNotFound {
+                arch: &self.arch,
+                variant: &self.variant,
+                version: self.image_version.clone(),
+            }
+        );
+        if num_matching > 1 {
+            warn!("Multiple matching updates for rollout - this is weird but not a disaster");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct MigrationArgs {
+    // file to get migrations from (probably Release.toml)
+    #[structopt(short = "f", long = "from")]
+    from: PathBuf,
+
+    // file to write migrations to (probably manifest.json)
+    #[structopt(short = "t", long = "to")]
+    to: ManifestFile,
+
+    // only replace the migrations for the version pairs in the input file, keeping the others
+    #[structopt(long = "append")]
+    append: bool,
+}
+
+impl MigrationArgs {
+    fn set(self) -> Result<()> {
+        // Load the file we will be writing to
+        let mut manifest: Manifest = self.to.load()?;
+
+        // Load the file we will be reading from
+        let release = read_release(&self.from)?;
+
+        if self.append {
+            // Merge the new data into the manifest 'migrations' section
+            manifest.migrations.extend(release.migrations);
+        } else {
+            // Replace the manifest 'migrations' section with the new data
+            manifest.migrations = release.migrations;
+        }
+
+        self.to.write(&manifest)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct MigrationEntryArgs {
+    // metadata file to modify
+    file: ManifestFile,
+
+    // datastore version the migrations start from
+    #[structopt(long = "from")]
+    from: Version,
+
+    // datastore version the migrations lead to
+    #[structopt(long = "to")]
+    to: Version,
+
+    // migration file name, like 'migrate_v1.1.0_foo'; repeat to give more than one, in the order
+    // they run
+    #[structopt(short = "n", long = "name", number_of_values = 1)]
+    names: Vec<String>,
+}
+
+impl MigrationEntryArgs {
+    /// Adds migrations to the end of the list for a pair of versions, creating it if needed.
+    /// Migrations already in the list are left where they are.
+    fn add(self) -> Result<()> {
+        ensure!(!self.names.is_empty(), error::MigrationNameArg);
+        let mut manifest: Manifest = self.file.load()?;
+        let key = (self.from.clone(), self.to.clone());
+        let existing = manifest.migrations.get(&key).cloned().unwrap_or_default();
+        let mut names = Vec::new();
+        for name in self.names {
+            if existing.contains(&name) || names.contains(&name) {
+                warn!(
+                    "{} is already in ({}, {}), skipping",
+                    name, self.from, self.to
+                );
+            } else {
+                names.push(name);
+            }
+        }
+        let added = names.len();
+        manifest.add_migration(true, self.from.clone(), self.to.clone(), names)?;
+        self.file.write(&manifest)?;
+        info!(
+            "Added {} migrations from {} to {}",
+            added, self.from, self.to
+        );
+        Ok(())
+    }
+
+    /// Removes the given migrations from the list for a pair of versions, or the whole list if
+    /// none are given.  A list left empty is removed too.
+    fn remove(self) -> Result<()> {
+        let mut manifest: Manifest = self.file.load()?;
+        let key = (self.from.clone(), self.to.clone());
+        let migrations = manifest
+            .migrations
+            .get_mut(&key)
+            .context(error::MigrationNotPresent {
+                from: self.from.clone(),
+                to: self.to.clone(),
+            })?;
+        for name in &self.names {
+            let index = migrations.iter().position(|m| m == name).context(
+                error::MigrationNameNotPresent {
+                    from: self.from.clone(),
+                    to: self.to.clone(),
+                    name: name.as_str(),
+                },
+            )?;
+            migrations.remove(index);
+        }
+        if self.names.is_empty() || migrations.is_empty() {
+            manifest.migrations.remove(&key);
+            info!("Removed migrations from {} to {}", self.from, self.to);
+        } else {
+            info!(
+                "Removed {} migrations from {} to {}",
+                self.names.len(),
+                self.from,
+                self.to
+            );
+        }
+        self.file.write(&manifest)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct ShowMigrationPathArgs {
+    // manifest to find the migrations in
+    file: ManifestFile,
+
+    // datastore version the host is moving from
+    #[structopt(long = "from")]
+    from: Version,
+
+    // datastore version the host is moving to; may be lower than --from
+    #[structopt(long = "to")]
+    to: Version,
+
+    // print the migrations as JSON
+    #[structopt(long = "json")]
+    json: bool,
+}
+
+impl ShowMigrationPathArgs {
+    fn run(self) -> Result<()> {
+        let manifest: Manifest = self.file.load()?;
+        let path = migration_path::migration_path(&manifest, &self.from, &self.to)?;
+        if self.json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&path).context(error::UpdateSerialize)?
+            );
+        } else {
+            println!("{}", path);
+        }
+        Ok(())
+    }
+}
+
+fn read_release(path: &Path) -> Result<Release> {
+    let release_data = fs::read_to_string(path).context(error::ConfigRead { path })?;
+    toml::from_str(&release_data).context(error::ReleaseParse { path })
+}
+
+#[derive(Debug, StructOpt)]
+struct MaxVersionArgs {
+    // metadata file to create/modify
+    file: ManifestFile,
+
+    // maximum valid version
+    #[structopt(short, long)]
+    max_version: Version,
+
+    // only set the max version of this variant's updates, eg. 'aws-k8s-1.15'
+    #[structopt(short = "l", long = "variant")]
+    variant: Option<String>,
+
+    // only set the max version of updates for this architecture
+    #[structopt(short = "a", long = "arch")]
+    arch: Option<String>,
+}
+
+impl MaxVersionArgs {
+    fn run(self) -> Result<()> {
+        let mut manifest: Manifest = self.file.load()?;
+        let num_matching = manifest.update_max_version(
+            &self.max_version,
+            self.arch.as_deref(),
+            self.variant.as_deref(),
+        );
+        // Without a filter, an empty manifest has nothing to set, which isn't a mistake.
+        ensure!(
+            num_matching > 0 || (self.variant.is_none() && self.arch.is_none()),
+            error::MaxVersionNotFound {
+                variant: self.variant.clone(),
+                arch: self.arch.clone(),
+            }
+        );
+        self.file.write(&manifest)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct ProvenanceArgs {
+    // metadata file to create/modify
+    file: ManifestFile,
+
+    // image 'variant', eg. 'aws-k8s-1.15'
+    #[structopt(short = "l", long = "variant")]
+    variant: String,
+
+    // image version
+    #[structopt(short = "v", long = "version")]
+    image_version: Version,
+
+    // architecture image is built for
+    #[structopt(short = "a", long = "arch")]
+    arch: String,
+
+    // target name of the provenance document; without it, the update's provenance is removed
+    #[structopt(short = "t", long = "target")]
+    target: Option<String>,
+
+    // hex SHA-256 digest of the document
+    #[structopt(long = "sha256", requires = "target", conflicts_with = "document")]
+    sha256: Option<String>,
+
+    // local copy of the document, to take the digest from
+    #[structopt(long = "document", requires = "target")]
+    document: Option<PathBuf>,
+}
+
+impl ProvenanceArgs {
+    fn set(self) -> Result<()> {
+        let mut manifest: Manifest = self.file.load()?;
+        let sha256 = match (&self.sha256, &self.document) {
+            (Some(sha256), _) => Some(sha256.to_ascii_lowercase()),
+            (None, Some(path)) => {
+                let data = fs::read(path).context(error::ProvenanceRead { path })?;
+                let digest = ring::digest::digest(&ring::digest::SHA256, &data);
+                Some(
+                    digest
+                        .as_ref()
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect(),
+                )
+            }
+            (None, None) => None,
+        };
+        let provenance = match (self.target.clone(), sha256) {
+            (Some(target), Some(sha256)) => Some(Provenance { target, sha256 }),
+            (Some(_), None) => return error::ProvenanceDigestMissing.fail(),
+            (None, _) => None,
+        };
+        let num_matching = manifest.set_provenance(
+            self.variant.clone(),
+            self.arch.clone(),
+            self.image_version.clone(),
+            provenance.as_ref(),
+        )?;
+        ensure!(
+            num_matching > 0,
+            error::UpdateNotFound {
+                arch: &self.arch,
+                variant: &self.variant,
+                version: self.image_version.clone(),
+            }
+        );
+        self.file.write(&manifest)?;
+        if let Some(provenance) = provenance {
+            info!(
+                "Update {}-{}-{} provenance set to {}",
+                self.arch, self.variant, self.image_version, provenance.target
+            );
+        } else {
+            info!(
+                "Update {}-{}-{} provenance removed",
+                self.arch, self.variant, self.image_version
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct ShardArgs {
+    // metadata file to create/modify
+    file: ManifestFile,
+
+    // image 'variant' to shard; may be given more than once, and all variants are sharded if
+    // none are given
+    #[structopt(short = "f", long = "variant")]
+    variants: Vec<String>,
+}
+
+impl ShardArgs {
+    fn run(self) -> Result<()> {
+        let mut manifest: Manifest = self.file.load()?;
+        for variant in manifest.shard(&self.variants) {
+            info!("Sharded {} into {}", variant, manifest.shards[&variant]);
+        }
+        self.file.write(&manifest)?;
+        Ok(())
+    }
+}
+
+impl GeneralArgs {
+    fn unshard(self) -> Result<()> {
+        let mut manifest: Manifest = self.file.load()?;
+        // The updates were loaded from the shards, so they're written back to the index.
+        for (variant, name) in std::mem::take(&mut manifest.shards) {
+            info!(
+                "Moved {} back from {}, which can now be removed",
+                variant, name
+            );
+        }
+        self.file.write(&manifest)?;
+        Ok(())
+    }
+
+    fn undo(self) -> Result<()> {
+        let restored = backup::undo(&self.file)?;
+        if !self.file.is_dry_run() {
+            info!("Restored {} from {}", self.file, restored.display());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct ExtractArgs {
+    // metadata file to copy updates from
+    file: ManifestFile,
+
+    // image 'variant' to keep updates for, eg. 'aws-k8s-1.15'
+    #[structopt(short = "f", long = "variant")]
+    variant: String,
+
+    // oldest image version to keep; hosts using the new manifest should run it or later
+    #[structopt(long = "min-version")]
+    min_version: Option<Version>,
+
+    // metadata file to write the matching updates to
+    #[structopt(short = "o", long = "output")]
+    output: ManifestFile,
+}
+
+impl ExtractArgs {
+    fn run(self) -> Result<()> {
+        let manifest: Manifest = self.file.load()?;
+        let subset = extract(manifest, &self.variant, self.min_version.as_ref())?;
+        info!(
+            "Extracted {} updates and {} migrations to {}",
+            subset.updates.len(),
+            subset.migrations.len(),
+            self.output
+        );
+        self.output.write(&subset)?;
+        Ok(())
+    }
+}
+
+/// Returns a manifest with just the updates for `variant`, starting at `min_version`, and the
+/// migrations hosts need to move between them.  Migrations from older versions are left out,
+/// since hosts using the new manifest won't be running them.  The result isn't sharded.
+fn extract(
+    mut manifest: Manifest,
+    variant: &str,
+    min_version: Option<&Version>,
+) -> Result<Manifest> {
+    let new_enough = |version: &Version| min_version.map_or(true, |min| version >= min);
+    manifest
+        .updates
+        .retain(|update| update.variant == variant && new_enough(&update.version));
+    let newest = manifest
+        .updates
+        .iter()
+        .map(|update| &update.version)
+        .max()
+        .cloned()
+        .context(error::ExtractEmpty {
+            variant,
+            min_version: min_version.cloned(),
+        })?;
+    manifest
+        .migrations
+        .retain(|(from, to), _| new_enough(from) && *to <= newest);
+    manifest.shards.clear();
+    Ok(manifest)
+}
+
+#[derive(Debug, StructOpt)]
+struct MergeArgs {
+    // metadata file to write the combined manifest to
+    output: ManifestFile,
+
+    // metadata files to combine, like one per architecture
+    #[structopt(required = true, min_values = 2)]
+    inputs: Vec<ManifestFile>,
+}
+
+impl MergeArgs {
+    fn run(self) -> Result<()> {
+        let mut inputs = Vec::new();
+        for input in &self.inputs {
+            inputs.push((input.to_string(), input.load()?));
+        }
+        let merged = match merge::merge(inputs) {
+            Ok(merged) => merged,
+            Err(conflicts) => {
+                for conflict in &conflicts {
+                    println!("{}", conflict);
+                }
+                return error::MergeConflicts {
+                    conflicts: conflicts.len(),
+                }
+                .fail();
+            }
+        };
+        info!(
+            "Merged {} updates and {} migrations into {}",
+            merged.updates.len(),
+            merged.migrations.len(),
+            self.output
+        );
+        self.output.write(&merged)
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct ListUpdatesArgs {
+    // metadata file to list updates from
+    file: ManifestFile,
+
+    // only list updates for this image 'variant', eg. 'aws-k8s-1.15'
+    #[structopt(short = "f", long = "variant")]
+    variant: Option<String>,
+
+    // only list updates for this architecture
+    #[structopt(short = "a", long = "arch")]
+    arch: Option<String>,
+
+    // only list updates at matching versions, like '>=1.4, <1.6'
+    #[structopt(short = "v", long = "version", default_value = "*")]
+    versions: VersionReq,
+
+    // print the updates as JSON, as they appear in the manifest
+    #[structopt(long = "json")]
+    json: bool,
+}
+
+impl ListUpdatesArgs {
+    fn run(self) -> Result<()> {
+        let manifest: Manifest = self.file.load()?;
+        let updates = list_updates(
+            &manifest,
+            self.variant.as_deref(),
+            self.arch.as_deref(),
+            &self.versions,
+        );
+        if self.json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&updates).context(error::UpdateSerialize)?
+            );
+            return Ok(());
+        }
+        for update in &updates {
+            let mut notes = vec![format!("max {}", update.max_version)];
+            if update.waves.is_empty() {
+                notes.push(String::from("0 waves"));
+            } else {
+                let seeds: Vec<_> = update
+                    .waves
+                    .keys()
+                    .map(|seed| {
+                        format!("{} ({:.1}%)", seed, update_metadata::seed_percentage(*seed))
+                    })
+                    .collect();
+                notes.push(format!(
+                    "{} waves at seeds {}",
+                    update.waves.len(),
+                    seeds.join(", ")
+                ));
+            }
+            if update.paused.is_some() {
+                notes.push(String::from("paused"));
+            }
+            if !update.available {
+                notes.push(String::from("deprecated"));
+            }
+            if update.targeting.is_some() {
+                notes.push(String::from("targeted"));
+            }
+            println!(
+                "{}\t{}\t{}\t{}",
+                update.variant,
+                update.arch,
+                update.version,
+                notes.join(", ")
+            );
+        }
+        info!(
+            "Listed {} of {} updates",
+            updates.len(),
+            manifest.updates.len()
+        );
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct StatsArgs {
+    // metadata file to summarize
+    file: ManifestFile,
+
+    // print the summary as JSON
+    #[structopt(long = "json")]
+    json: bool,
+}
+
+impl StatsArgs {
+    fn run(self) -> Result<()> {
+        let manifest: Manifest = self.file.load()?;
+        let stats = stats::stats(&manifest);
+        if self.json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&stats).context(error::UpdateSerialize)?
+            );
+        } else {
+            println!("{}", stats);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the updates in `manifest` that match the filters, by variant and architecture, newest
+/// first.
+fn list_updates<'a>(
+    manifest: &'a Manifest,
+    variant: Option<&str>,
+    arch: Option<&str>,
+    versions: &VersionReq,
+) -> Vec<&'a Update> {
+    let mut updates: Vec<&Update> = manifest
+        .updates
+        .iter()
+        .filter(|update| {
+            variant.map_or(true, |variant| update.variant == variant)
+                && arch.map_or(true, |arch| update.arch == arch)
+                && versions.matches(&update.version)
+        })
+        .collect();
+    updates.sort_by(|a, b| {
+        (&a.variant, &a.arch)
+            .cmp(&(&b.variant, &b.arch))
+            .then_with(|| b.version.cmp(&a.version))
+    });
+    updates
+}
+
+#[derive(Debug, StructOpt)]
+struct SimulateClientArgs {
+    // manifest the host would fetch
+    file: ManifestFile,
+
+    // the host's seed, from 0 to 2047, which places it in the update waves
+    #[structopt(short = "s", long = "seed", parse(try_from_str = parse_seed))]
+    seed: u32,
+
+    // the version the host is running
+    #[structopt(short = "c", long = "current-version")]
+    current_version: Version,
+
+    // the host's image 'variant', eg. 'aws-k8s-1.15'
+    #[structopt(short = "f", long = "variant")]
+    variant: String,
+
+    // the host's architecture
+    #[structopt(short = "a", long = "arch")]
+    arch: String,
+
+    // a label the host has, like 'gpu=true'; may be given more than once
+    #[structopt(short = "t", long = "label", parse(try_from_str = parse_label))]
+    labels: Vec<(String, String)>,
+
+    // when the host checks, like '2024-01-01 09:00 PST' or 'in 2 days'
+    #[structopt(
+        long = "at",
+        default_value = "now",
+        parse(try_from_str = parse_datetime::parse_datetime)
+    )]
+    at: DateTime<Utc>,
+
+    // print what the host would do as JSON
+    #[structopt(long = "json")]
+    json: bool,
+}
+
+/// Parses a host's seed, which has to be below the number of seeds.
+fn parse_seed(input: &str) -> Result<u32> {
+    let max = update_metadata::MAX_SEED - 1;
+    match input.parse() {
+        Ok(seed) if seed <= max => Ok(seed),
+        _ => error::SeedArg { input, max }.fail(),
+    }
+}
+
+impl SimulateClientArgs {
+    fn run(self) -> Result<()> {
+        let manifest: Manifest = self.file.load()?;
+        let host = simulate::Host {
+            variant: self.variant,
+            arch: self.arch,
+            version: self.current_version,
+            seed: self.seed,
+            labels: self.labels.into_iter().collect(),
+        };
+        let simulation = simulate::simulate(&manifest, &host, self.at);
+        if self.json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&simulation).context(error::UpdateSerialize)?
+            );
+            return Ok(());
+        }
+        match simulation {
+            Some(simulation) => println!("{}", simulation),
+            None => println!(
+                "No update for {} {} hosts on {} with seed {}",
+                host.variant, host.arch, host.version, host.seed
+            ),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct DiffArgs {
+    // the manifest as it was, like the one last published
+    old: ManifestFile,
+
+    // the manifest as it is now, like the one about to be published
+    new: ManifestFile,
+
+    // print the differences as JSON
+    #[structopt(long = "json")]
+    json: bool,
+}
+
+impl DiffArgs {
+    fn run(self) -> Result<()> {
+        let old: Manifest = self.old.load()?;
+        let new: Manifest = self.new.load()?;
+        let diff = ManifestDiff::new(&old, &new);
+        if self.json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&diff).context(error::UpdateSerialize)?
+            );
+        } else if diff.is_empty() {
+            info!("No differences");
+        } else {
+            print!("{}", diff);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct ReportSizeArgs {
+    // metadata file to report on
+    file: ManifestFile,
+
+    // image 'variant' of the hosts to report on, eg. 'aws-k8s-1.15'
+    #[structopt(short = "f", long = "variant")]
+    variant: String,
+
+    // architecture of the hosts to report on
+    #[structopt(short = "a", long = "arch")]
+    arch: String,
+
+    // the repository's signed metadata directory, with timestamp.json, snapshot.json, and
+    // targets.json; without it, only the manifest is counted
+    #[structopt(short = "d", long = "metadata-dir")]
+    metadata_dir: Option<PathBuf>,
+
+    // number of hosts in the fleet
+    #[structopt(long = "hosts", default_value = "1")]
+    hosts: u64,
+
+    // how many times a day each host checks for updates
+    #[structopt(long = "checks-per-day", default_value = "1")]
+    checks_per_day: u64,
+}
+
+impl ReportSizeArgs {
+    fn run(self) -> Result<()> {
+        let manifest: Manifest = self.file.load()?;
+        let metadata = match &self.metadata_dir {
+            Some(dir) => Some(MetadataSizes::load(dir)?),
+            None => None,
+        };
+        let fleet = Fleet {
+            hosts: self.hosts,
+            checks_per_day: self.checks_per_day,
+        };
+        let report = SizeReport::new(&manifest, &self.variant, &self.arch, metadata, fleet)?;
+        println!("{}", report);
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct GcTargetsArgs {
+    // the repository, with the signed metadata in metadata/ and the targets in targets/
+    #[structopt(long = "repo")]
+    repo: PathBuf,
+
+    // unreferenced targets changed within this many days are kept, since they may belong to a
+    // release that isn't signed yet
+    #[structopt(long = "grace-days", default_value = "7")]
+    grace_days: i64,
+
+    // delete the unreferenced targets, instead of only listing them
+    #[structopt(long = "delete")]
+    delete: bool,
+}
+
+impl GcTargetsArgs {
+    fn run(self) -> Result<()> {
+        let unreferenced = gc::unreferenced_targets(
+            &self.repo,
+            chrono::Duration::days(self.grace_days),
+            Utc::now(),
+        )?;
+        let mut total = 0;
+        for target in &unreferenced {
+            println!("{}\t{}", target.len, target.path.display());
+            total += target.len;
+        }
+        if self.delete {
+            for target in &unreferenced {
+                fs::remove_file(&target.path)
+                    .context(error::TargetDelete { path: &target.path })?;
+            }
+            info!(
+                "Deleted {} unreferenced targets, {} bytes",
+                unreferenced.len(),
+                total
+            );
+        } else {
+            info!(
+                "Found {} unreferenced targets, {} bytes; pass --delete to remove them",
+                unreferenced.len(),
+                total
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct MirrorArgs {
+    // URL of the repository to copy, with the signed metadata under metadata/ and the targets
+    // under targets/
+    #[structopt(long = "from")]
+    from: Url,
+
+    // directory to write the copy to, laid out the same way
+    #[structopt(long = "to")]
+    to: PathBuf,
+
+    // root.json to trust when checking the repository, as shipped on the hosts
+    #[structopt(long = "trusted-root")]
+    trusted_root: PathBuf,
+
+    // variants to copy updates for, comma-separated; a family like 'aws-k8s' includes each
+    // aws-k8s-* variant
+    #[structopt(long = "variants", required = true, use_delimiter = true)]
+    variants: Vec<String>,
+
+    // versions to copy updates for, like '>=1.4', along with the migrations to them
+    #[structopt(long = "versions", default_value = "*")]
+    versions: VersionReq,
+
+    // architecture to copy updates for; without it, every architecture is copied
+    #[structopt(short = "a", long = "arch")]
+    arch: Option<String>,
+}
+
+impl MirrorArgs {
+    fn run(self) -> Result<()> {
+        let selection = Selection {
+            variants: self.variants,
+            migrations: self.versions.clone(),
+            versions: self.versions,
+            arch: self.arch,
+        };
+        let copied = mirror::mirror(&self.from, &self.to, &self.trusted_root, &selection)?;
+        info!(
+            "Mirrored {} targets to {}, downloading {} bytes; {} were already there",
+            copied.targets,
+            self.to.display(),
+            copied.bytes,
+            copied.unchanged
+        );
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct BundleArgs {
+    // URL of the repository to take the update from, with the signed metadata under metadata/
+    // and the targets under targets/
+    #[structopt(long = "repo", alias = "from")]
+    repo: Url,
+
+    // root.json to trust when checking the repository, as shipped on the hosts
+    #[structopt(long = "trusted-root")]
+    trusted_root: PathBuf,
+
+    // image 'variant', eg. 'aws-k8s-1.15'
+    #[structopt(short = "f", long = "variant")]
+    variant: String,
+
+    // architecture image is built for
+    #[structopt(short = "a", long = "arch")]
+    arch: String,
+
+    // version of the update to bundle
+    #[structopt(short = "v", long = "version")]
+    version: Version,
+
+    // oldest version the hosts may be running; migrations from earlier versions are left out
+    #[structopt(long = "since")]
+    since: Option<Version>,
+
+    // Ed25519 private key in PKCS#8 DER form to sign the bundle's inventory with, for hosts
+    // that only take signed bundles; repeat to sign with each, like the old and new keys while
+    // rotating them
+    #[structopt(short = "k", long = "key", number_of_values = 1)]
+    key: Vec<PathBuf>,
+
+    // bundle file to write
+    #[structopt(short = "o", long = "output")]
+    output: PathBuf,
+}
+
+impl BundleArgs {
+    fn run(self) -> Result<()> {
+        let migrations = match &self.since {
+            Some(since) => format!(">{}, <={}", since, self.version),
+            None => format!("<={}", self.version),
+        };
+        let selection = Selection {
+            variants: vec![self.variant.clone()],
+            versions: VersionReq::exact(&self.version),
+            migrations: VersionReq::parse(&migrations).context(error::BundleVersions)?,
+            arch: Some(self.arch.clone()),
+        };
+        let mut keys = Vec::new();
+        for path in &self.key {
+            let pkcs8 = fs::read(path).context(error::BundleKeyRead { path })?;
+            keys.push(
+                Ed25519KeyPair::from_pkcs8_maybe_unchecked(&pkcs8)
+                    .context(error::BundleKeyParse { path })?,
+            );
+        }
+        let dir = tempfile::TempDir::new().context(error::MirrorDatastore)?;
+        let copied = mirror::mirror(&self.repo, dir.path(), &self.trusted_root, &selection)?;
+        let inventory = bundle::pack(
+            dir.path(),
+            &self.variant,
+            &self.arch,
+            &self.version,
+            &keys,
+            &self.output,
+        )?;
+        info!(
+            "Bundled {} {} for {} with {} targets in {}",
+            inventory.variant,
+            inventory.version,
+            inventory.arch,
+            copied.targets,
+            self.output.display()
+        );
+        for key in &keys {
+            info!(
+                "Signed the inventory with key {}",
+                base64::encode(key.public_key())
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct SignMetadataArgs {
+    // signed metadata files to add signatures to, like 2.root.json or timestamp.json
+    #[structopt(required = true)]
+    files: Vec<PathBuf>,
+
+    // RSA private key in PEM form to sign with; repeat to sign with each, like the old and new
+    // keys while rotating them
+    #[structopt(short = "k", long = "key", required = true, number_of_values = 1)]
+    key: Vec<PathBuf>,
+}
+
+impl SignMetadataArgs {
+    fn run(self) -> Result<()> {
+        let mut keys = Vec::new();
+        for path in &self.key {
+            let pem = fs::read(path).context(error::SigningKeyRead { path })?;
+            keys.push(tough::sign::parse_keypair(&pem).context(error::SigningKeyParse { path })?);
+        }
+        for file in &self.files {
+            let keys: Vec<&dyn Sign> = keys.iter().map(|key| key as &dyn Sign).collect();
+            let key_ids = rotation::sign(file, &keys)?;
+            info!(
+                "Signed {} with key(s) {}",
+                file.display(),
+                key_ids.join(", ")
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct CheckRotationArgs {
+    // directory of the repository's signed metadata, with each root as N.root.json
+    #[structopt(short = "m", long = "metadata-dir")]
+    metadata_dir: PathBuf,
+
+    // root.json the hosts trust, as shipped in each image still running in the fleet; repeat for
+    // each
+    #[structopt(
+        short = "t",
+        long = "trusted-root",
+        required = true,
+        number_of_values = 1
+    )]
+    trusted_root: Vec<PathBuf>,
+
+    // ID of the key to retire, as listed in root.json
+    #[structopt(short = "r", long = "retire")]
+    retire: Decoded<Hex>,
+
+    // print the checks as JSON
+    #[structopt(long = "json")]
+    json: bool,
+}
+
+impl CheckRotationArgs {
+    fn run(self) -> Result<()> {
+        let rotation = rotation::check(&self.metadata_dir, &self.trusted_root, &self.retire)?;
+        if self.json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&rotation).context(error::UpdateSerialize)?
+            );
+        } else {
+            print!("{}", rotation);
+        }
+        let problems = rotation.problems();
+        ensure!(problems == 0, error::RotationNotReady { problems });
+        Ok(())
+    }
+}
+
+/// Where the adoption of a manifest's waves is kept: the given file, or one next to a local
+/// manifest.
+fn adoption_path(file: &ManifestFile, given: Option<PathBuf>) -> Result<PathBuf> {
+    match (given, file.local_path()) {
+        (Some(path), _) => Ok(path),
+        (None, Some(manifest)) => Ok(manifest.with_extension("adoption.json")),
+        (None, None) => error::AdoptionPath.fail(),
+    }
 }
 
-impl WaveArgs {
-    fn set(self) -> Result<()> {
-        let mut manifest: Manifest = update_metadata::load_file(&self.file)?;
+#[derive(Debug, StructOpt)]
+struct ImportAdoptionArgs {
+    // manifest whose waves the telemetry is for
+    file: ManifestFile,
 
-        let wave_file = self.wave_file.context(error::WaveFileArg)?;
-        let wave_str =
-            fs::read_to_string(&wave_file).context(error::ConfigRead { path: &wave_file })?;
-        let waves: UpdateWaves =
-            toml::from_str(&wave_str).context(error::ConfigParse { path: &wave_file })?;
-        let num_matching =
-            manifest.set_waves(self.variant, self.arch, self.image_version, &waves)?;
+    // telemetry exported from the fleet, with the hosts and updated hosts in each wave
+    #[structopt(short = "f", long = "file")]
+    export: PathBuf,
 
-        if num_matching > 1 {
-            warn!("Multiple matching updates for wave - this is weird but not a disaster");
-        }
-        update_metadata::write_file(&self.file, &manifest)?;
+    // file to keep the adoption in; defaults to one next to the manifest, like
+    // manifest.adoption.json
+    #[structopt(long = "adoption")]
+    adoption: Option<PathBuf>,
+}
+
+impl ImportAdoptionArgs {
+    fn run(self) -> Result<()> {
+        let manifest: Manifest = self.file.load()?;
+        let path = adoption_path(&self.file, self.adoption)?;
+        let mut adoption = Adoption::load(&path)?;
+        let imported = adoption.import(&manifest, &self.export)?;
+        adoption.write(&path)?;
+        info!(
+            "Imported the adoption of {} updates into {}",
+            imported,
+            path.display()
+        );
         Ok(())
     }
 }
 
 #[derive(Debug, StructOpt)]
-struct MigrationArgs {
-    // file to get migrations from (probably Release.toml)
-    #[structopt(short = "f", long = "from")]
-    from: PathBuf,
+struct AdoptionReportArgs {
+    // manifest whose waves to check
+    file: ManifestFile,
 
-    // file to write migrations to (probably manifest.json)
-    #[structopt(short = "t", long = "to")]
-    to: PathBuf,
-}
+    // file the adoption was imported into; defaults to one next to the manifest
+    #[structopt(long = "adoption")]
+    adoption: Option<PathBuf>,
 
-impl MigrationArgs {
-    fn set(self) -> Result<()> {
-        // Load the file we will be writing to
-        let mut manifest: Manifest = update_metadata::load_file(&self.to)?;
+    // percentage of a wave's hosts that should have the update before the next wave opens
+    #[structopt(long = "min-adoption", default_value = "90")]
+    min_adoption: f64,
 
-        // Load the file we will be reading from
-        let release_data =
-            fs::read_to_string(&self.from).context(error::ConfigRead { path: &self.from })?;
-        let release: Release =
-            toml::from_str(&release_data).context(error::ReleaseParse { path: &self.from })?;
+    // hours a wave has to take the update before it can lag
+    #[structopt(long = "settle-hours", default_value = "24")]
+    settle_hours: i64,
 
-        // Replace the manifest 'migrations' section with the new data
-        manifest.migrations = release.migrations;
+    // print the waves as JSON
+    #[structopt(long = "json")]
+    json: bool,
+}
 
-        update_metadata::write_file(&self.to, &manifest)?;
+impl AdoptionReportArgs {
+    fn run(self) -> Result<()> {
+        let manifest: Manifest = self.file.load()?;
+        let adoption = Adoption::load(&adoption_path(&self.file, self.adoption)?)?;
+        let report = adoption.report(
+            &manifest,
+            self.min_adoption,
+            chrono::Duration::hours(self.settle_hours),
+        );
+        if self.json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report).context(error::UpdateSerialize)?
+            );
+        } else {
+            print!("{}", report);
+        }
+        let waves = report.lagging();
+        ensure!(waves == 0, error::AdoptionLagging { waves });
         Ok(())
     }
 }
 
 #[derive(Debug, StructOpt)]
-struct MaxVersionArgs {
-    // metadata file to create/modify
-    file: PathBuf,
+struct LintArgs {
+    // file that sets the severity of lint rules; rules not in it keep their defaults
+    #[structopt(short = "c", long = "config")]
+    config: Option<PathBuf>,
 
-    // maximum valid version
-    #[structopt(short, long)]
-    max_version: Version,
+    // manifest to check the new version's waves in
+    #[structopt(short = "m", long = "manifest")]
+    manifest: Option<PathBuf>,
+
+    // file to get the new version from (probably Release.toml); otherwise the newest version in
+    // the manifest is checked
+    #[structopt(short = "r", long = "release")]
+    release: Option<PathBuf>,
+
+    // file that contains wave structure; may be given more than once
+    #[structopt(short = "w", long = "wave-file")]
+    wave_files: Vec<PathBuf>,
+
+    // changelog that should have release notes for the new version
+    #[structopt(short = "n", long = "changelog")]
+    changelog: Option<PathBuf>,
 }
 
-impl MaxVersionArgs {
+impl LintArgs {
     fn run(self) -> Result<()> {
-        let mut manifest: Manifest = update_metadata::load_file(&self.file)?;
-        manifest.update_max_version(&self.max_version, None, None);
-        update_metadata::write_file(&self.file, &manifest)?;
+        let config = match &self.config {
+            Some(path) => lint::Config::load(path)?,
+            None => lint::Config::default(),
+        };
+        let files = ReleaseFiles::load(
+            self.manifest.as_deref(),
+            self.release.as_deref(),
+            &self.wave_files,
+            self.changelog.as_deref(),
+        )?;
+
+        let findings = lint::lint(&config, &files);
+        for finding in &findings {
+            if finding.severity == Severity::Error {
+                error!("{}", finding);
+            } else {
+                warn!("{}", finding);
+            }
+        }
+        let errors = findings
+            .iter()
+            .filter(|f| f.severity == Severity::Error)
+            .count();
+        ensure!(errors == 0, error::LintFailed { errors });
+        info!("Lint passed with {} warning(s)", findings.len());
         Ok(())
     }
 }
 
+#[derive(Debug, StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+struct Args {
+    /// Where to log: auto, terminal, stderr, journald, or syslog; auto logs to the journal if
+    /// run by systemd, and to the terminal otherwise
+    #[structopt(long = "log-target", default_value = "auto")]
+    log_target: LogTarget,
+    /// Check the changes and print a diff of them, rather than writing the manifest
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+    /// How many backups of a local manifest to keep, saving one before each change; 0 saves none
+    #[structopt(long = "backups", default_value = "10")]
+    backups: usize,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(rename_all = "kebab-case")]
 enum Command {
     /// Create an empty manifest
     Init(GeneralArgs),
-    /// Add a new update to the manifest, not including wave information
+    /// Add a new update to the manifest, with waves only if a preset is given
     AddUpdate(AddUpdateArgs),
     /// Set waves for an update
     SetWaves(WaveArgs),
-    /// Set the global maximum image version
+    /// Set an update's waves from a named schedule, like canary, starting at a given time
+    AddWave(AddWaveArgs),
+    /// Show the schedules add-wave, add-update, and cut-release can give an update's waves
+    ListWavePresets(ListWavePresetsArgs),
+    /// Move the seed bounds of an update's waves for a new fleet size, keeping their start times
+    RebalanceWaves(RebalanceArgs),
+    /// Set the maximum image version of every update, or of one variant's or architecture's
     SetMaxVersion(MaxVersionArgs),
     /// Remove an update from the manifest, including wave information
     RemoveUpdate(RemoveUpdateArgs),
-    /// Copy the migrations from an input file to an output file
+    /// Add an update for another variant with the same images as an existing one, changing only what's given
+    CopyUpdate(CopyUpdateArgs),
+    /// Remove old updates of each variant and architecture, and the migrations only they needed
+    Prune(PruneArgs),
+    /// Copy the migrations from an input file to an output file, or with --append, merge them in
     SetMigrations(MigrationArgs),
-    /// Validate a manifest file, but make no changes
-    Validate(GeneralArgs),
+    /// Add migrations to the end of the list for one pair of datastore versions
+    AddMigration(MigrationEntryArgs),
+    /// Remove some migrations, or all of them, from the list for one pair of datastore versions
+    RemoveMigration(MigrationEntryArgs),
+    /// List the migrations a host runs to move its data store between two versions, in the order they run
+    ShowMigrationPath(ShowMigrationPathArgs),
+    /// Add a version's updates from a build, with their migrations, waves, and max version, then check and show the changes
+    CutRelease(CutReleaseArgs),
+    /// Run the add-update, set-waves, and set-migrations steps in a plan file, writing the manifest only if all succeed
+    ApplyPlan(PlanArgs),
+    /// Validate a manifest file, but make no changes; with --strict, check that hosts can follow it
+    Validate(ValidateArgs),
+    /// Restrict an update to hosts with certain seeds or labels, or to every host if neither is given
+    SetTargeting(TargetingArgs),
+    /// Set which variants' updates hosts of a variant may take, or hold them back if none are given
+    SetCompatibility(CompatibilityArgs),
+    /// Attach a provenance document to an update, or remove it if no target is given
+    SetProvenance(ProvenanceArgs),
+    /// Pause the rollout of an update, so hosts that haven't taken it aren't offered it
+    PauseRollout(RolloutArgs),
+    /// Resume a paused rollout, pushing back waves that hadn't started by the length of the pause
+    ResumeRollout(RolloutArgs),
+    /// Remove every wave from an update, to set its waves again from scratch
+    ClearWaves(RolloutArgs),
+    /// Stop offering an update to hosts, keeping it and its migrations for hosts already running it
+    DeprecateUpdate(RolloutArgs),
+    /// Offer a deprecated update to hosts again
+    ReinstateUpdate(RolloutArgs),
+    /// Keep the adoption of each wave, from telemetry exported from the fleet, next to the manifest
+    ImportAdoption(ImportAdoptionArgs),
+    /// Show the imported adoption of each wave, and fail if any lag before the waves after them open
+    AdoptionReport(AdoptionReportArgs),
+    /// Check a release against lint rules, with severities set in a config file
+    Lint(LintArgs),
+    /// Move variants' updates into their own shard files, so hosts only fetch their own variant's
+    Shard(ShardArgs),
+    /// Move the updates in all shard files back into the manifest
+    Unshard(GeneralArgs),
+    /// Write a new manifest with only one variant's updates, optionally from a version onward
+    Extract(ExtractArgs),
+    /// Combine manifests, like one per architecture, into one, failing on any updates or migrations they disagree on
+    Merge(MergeArgs),
+    /// List the manifest's updates, optionally only some variants, architectures, or versions
+    ListUpdates(ListUpdatesArgs),
+    /// Count the manifest's updates, versions, and waves by variant and architecture, with the data store versions and targets it uses
+    Stats(StatsArgs),
+    /// Show the update a host would be offered at a given time, its wave, and the migrations it would fetch
+    SimulateClient(SimulateClientArgs),
+    /// Show the updates, waves, max versions, and migrations that differ between two manifests
+    Diff(DiffArgs),
+    /// Estimate how much hosts download to check for and take updates, and fleet-wide totals
+    ReportSize(ReportSizeArgs),
+    /// Browse and change a manifest interactively, seeing the changes before they're saved
+    Edit(GeneralArgs),
+    /// Restore a local manifest from its newest backup, taking back the last change to it
+    Undo(GeneralArgs),
+    /// List, or delete, the repository's target files that nothing refers to anymore
+    GcTargets(GcTargetsArgs),
+    /// Copy a repository's metadata and the targets some hosts need, for a network that can't reach it
+    Mirror(MirrorArgs),
+    /// Pack one update, with the signed metadata and migrations it needs, into a file for hosts to apply offline
+    Bundle(BundleArgs),
+    /// Add signatures to repository metadata, keeping its others, so it can be signed by old and new keys at once
+    SignMetadata(SignMetadataArgs),
+    /// Check that the fleet's trusted roots and the repository's signatures are ready for a key to be retired
+    CheckRotation(CheckRotationArgs),
 }
 
-fn main_inner() -> Result<()> {
-    // TerminalMode::Mixed will send errors to stderr and anything less to stdout.
-    TermLogger::init(LevelFilter::Info, LogConfig::default(), TerminalMode::Mixed)
-        .context(error::Logger)?;
+impl Command {
+    /// The manifests the command writes.
+    fn written_files(&self) -> Vec<&ManifestFile> {
+        match self {
+            Command::Init(args)
+            | Command::Unshard(args)
+            | Command::Edit(args)
+            | Command::Undo(args) => vec![&args.file],
+            Command::AddUpdate(args) => vec![&args.file],
+            Command::SetWaves(args) => vec![&args.file],
+            Command::AddWave(args) => vec![&args.file],
+            Command::RebalanceWaves(args) => vec![&args.file],
+            Command::SetMaxVersion(args) => vec![&args.file],
+            Command::RemoveUpdate(args) => vec![&args.file],
+            Command::CopyUpdate(args) => vec![&args.file],
+            Command::Prune(args) => vec![&args.file],
+            Command::SetMigrations(args) => vec![&args.to],
+            Command::AddMigration(args) | Command::RemoveMigration(args) => vec![&args.file],
+            Command::CutRelease(args) => vec![&args.file],
+            Command::ApplyPlan(args) => vec![&args.file],
+            Command::SetTargeting(args) => vec![&args.file],
+            Command::SetCompatibility(args) => vec![&args.file],
+            Command::SetProvenance(args) => vec![&args.file],
+            Command::PauseRollout(args)
+            | Command::ResumeRollout(args)
+            | Command::ClearWaves(args)
+            | Command::DeprecateUpdate(args)
+            | Command::ReinstateUpdate(args) => vec![&args.file],
+            Command::Shard(args) => vec![&args.file],
+            Command::Extract(args) => vec![&args.output],
+            Command::Merge(args) => vec![&args.output],
+            Command::Validate(_)
+            | Command::Lint(_)
+            | Command::ListUpdates(_)
+            | Command::Stats(_)
+            | Command::SimulateClient(_)
+            | Command::ShowMigrationPath(_)
+            | Command::Diff(_)
+            | Command::ReportSize(_)
+            | Command::GcTargets(_)
+            | Command::Mirror(_)
+            | Command::Bundle(_)
+            | Command::SignMetadata(_)
+            | Command::CheckRotation(_)
+            | Command::ImportAdoption(_)
+            | Command::AdoptionReport(_)
+            | Command::ListWavePresets(_) => Vec::new(),
+        }
+    }
+}
 
-    match Command::from_args() {
-        Command::Init(args) => {
-            match update_metadata::write_file(&args.file, &Manifest::default()) {
-                Ok(_) => Ok(()),
-                Err(e) => Err(error::Error::UpdateMetadata { source: e }),
-            }
+fn main_inner() -> Result<()> {
+    let args = Args::from_args();
+    logging::init(LevelFilter::Info, args.log_target, "updata", &[])?;
+    for file in args.command.written_files() {
+        if args.dry_run {
+            file.set_dry_run();
         }
+        file.set_backups(args.backups);
+    }
+
+    match args.command {
+        Command::Init(args) => args.file.write(&Manifest::default()),
         Command::AddUpdate(args) => args.run(),
         Command::SetWaves(args) => args.set(),
+        Command::AddWave(args) => args.run(),
+        Command::ListWavePresets(args) => args.run(),
+        Command::RebalanceWaves(args) => args.run(),
         Command::SetMaxVersion(args) => args.run(),
         Command::RemoveUpdate(args) => args.run(),
+        Command::CopyUpdate(args) => args.run(),
+        Command::Prune(args) => args.run(),
         Command::SetMigrations(args) => args.set(),
-        Command::Validate(args) => match update_metadata::load_file(&args.file) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(error::Error::UpdateMetadata { source: e }),
-        },
+        Command::AddMigration(args) => args.add(),
+        Command::RemoveMigration(args) => args.remove(),
+        Command::ShowMigrationPath(args) => args.run(),
+        Command::CutRelease(args) => args.run(),
+        Command::ApplyPlan(args) => args.run(),
+        Command::Validate(args) => args.run(),
+        Command::SetTargeting(args) => args.set(),
+        Command::SetCompatibility(args) => args.run(),
+        Command::SetProvenance(args) => args.set(),
+        Command::PauseRollout(args) => args.pause(),
+        Command::ResumeRollout(args) => args.resume(),
+        Command::ClearWaves(args) => args.clear_waves(),
+        Command::DeprecateUpdate(args) => args.set_available(false),
+        Command::ReinstateUpdate(args) => args.set_available(true),
+        Command::ImportAdoption(args) => args.run(),
+        Command::AdoptionReport(args) => args.run(),
+        Command::Lint(args) => args.run(),
+        Command::Shard(args) => args.run(),
+        Command::Unshard(args) => args.unshard(),
+        Command::Extract(args) => args.run(),
+        Command::Merge(args) => args.run(),
+        Command::ListUpdates(args) => args.run(),
+        Command::Stats(args) => args.run(),
+        Command::SimulateClient(args) => args.run(),
+        Command::Diff(args) => args.run(),
+        Command::ReportSize(args) => args.run(),
+        Command::Edit(args) => {
+            let stdin = std::io::stdin();
+            Editor::new(args.file, stdin.lock(), std::io::stdout())?.run()
+        }
+        Command::Undo(args) => args.undo(),
+        Command::GcTargets(args) => args.run(),
+        Command::Mirror(args) => args.run(),
+        Command::Bundle(args) => args.run(),
+        Command::SignMetadata(args) => args.run(),
+        Command::CheckRotation(args) => args.run(),
     }
 }
 
@@ -277,10 +2657,76 @@ fn main() -> ! {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::{DateTime, TimeZone};
     use std::fs::File;
-    use std::path::Path;
     use tempfile::NamedTempFile;
 
+    #[test]
+    fn rebalance_waves() {
+        // Waves for 1%, 5%, 15%, and 40% of the fleet, then the rest.
+        let current = [0, 20, 102, 307, 819];
+
+        // A big fleet keeps the waves as they are, unless it wants smaller ones.
+        assert_eq!(rebalance(&current, &[], 100_000, 1).unwrap(), current);
+        assert_eq!(
+            rebalance(&current, &[0.1, 1.0, 10.0, 40.0], 100_000, 1).unwrap(),
+            [0, 2, 20, 205, 819]
+        );
+
+        // In a fleet of 256 hosts, each wave needs 8 seeds to expect one host.
+        assert_eq!(
+            rebalance(&current, &[0.1, 0.2, 10.0, 40.0], 256, 1).unwrap(),
+            [0, 8, 16, 205, 819]
+        );
+        assert_eq!(
+            rebalance(&current, &[], 256, 20).unwrap(),
+            [0, 160, 320, 480, 819]
+        );
+        assert!(rebalance(&current, &[], 256, 60).is_err());
+
+        // Too few hosts for even one wave is refused, rather than overflowing the seed count.
+        for &(fleet_hosts, min_hosts) in &[(1, u64::MAX), (1, 1_000_000), (3, 2)] {
+            assert!(matches!(
+                rebalance(&current, &[], fleet_hosts, min_hosts),
+                Err(error::Error::RebalanceFit { .. })
+            ));
+        }
+
+        assert!(rebalance(&current, &[1.0, 5.0], 100_000, 1).is_err());
+        assert!(rebalance(&current, &[1.0, 5.0, 15.0, 100.0], 100_000, 1).is_err());
+        assert!(rebalance(&current, &[5.0, 1.0, 15.0, 40.0], 100_000, 1).is_err());
+    }
+
+    #[test]
+    fn prune_older_than() {
+        let parse = |older_than: &str| {
+            Args::from_iter_safe(&[
+                "updata",
+                "prune",
+                "manifest.json",
+                "--older-than",
+                older_than,
+            ])
+        };
+        let before = Utc::now();
+        let args = parse("90 days ago").unwrap();
+        let after = Utc::now();
+        match args.command {
+            Command::Prune(PruneArgs {
+                older_than: Some(cutoff),
+                ..
+            }) => {
+                assert!(cutoff >= before - chrono::Duration::days(90));
+                assert!(cutoff <= after - chrono::Duration::days(90));
+            }
+            other => panic!("expected prune with a cutoff, got {:?}", other),
+        }
+
+        // An amount of time without "ago" would be in the future, and prune everything.
+        assert!(parse("90 days").is_err());
+        assert!(parse("+90d").is_err());
+    }
+
     #[test]
     fn test_set_waves() {
         // A basic manifest with a single update, no migrations, and two
@@ -312,7 +2758,8 @@ mod tests {
         // Copy the migration data to the new manifest
         MigrationArgs {
             from: PathBuf::from(&release_path),
-            to: PathBuf::from(temp_manifest.path()),
+            to: ManifestFile::local(temp_manifest.path()),
+            append: false,
         }
         .set()
         .unwrap();
@@ -340,7 +2787,8 @@ mod tests {
         // Copy the migration data to the existing manifest
         MigrationArgs {
             from: PathBuf::from(&release_path),
-            to: PathBuf::from(&temp_manifest.path()),
+            to: ManifestFile::local(temp_manifest.path()),
+            append: false,
         }
         .set()
         .unwrap();
@@ -354,42 +2802,592 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    // Ensure that appending keeps the manifest's other migrations
+    fn test_migration_append() -> Result<()> {
+        let release_path = "tests/data/release.toml";
+        let temp_manifest = NamedTempFile::new().context(error::TmpFileCreate)?;
+        fs::copy("tests/data/example.json", temp_manifest.path()).unwrap();
+        let before: Manifest = update_metadata::load_file(temp_manifest.path()).unwrap();
+
+        MigrationArgs {
+            from: PathBuf::from(&release_path),
+            to: ManifestFile::local(temp_manifest.path()),
+            append: true,
+        }
+        .set()
+        .unwrap();
+
+        let manifest: Manifest = update_metadata::load_file(temp_manifest.path()).unwrap();
+        let release = read_release(Path::new(release_path)).unwrap();
+        assert_eq!(
+            manifest.migrations.len(),
+            before.migrations.len() + release.migrations.len()
+        );
+        for (versions, migrations) in before.migrations.iter().chain(&release.migrations) {
+            assert_eq!(&manifest.migrations[versions], migrations);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn add_and_remove_migration() {
+        let temp_manifest = NamedTempFile::new().unwrap();
+        fs::copy("tests/data/example.json", temp_manifest.path()).unwrap();
+        let entry = |from: &str, to: &str, names: &[&str]| MigrationEntryArgs {
+            file: ManifestFile::local(temp_manifest.path()),
+            from: Version::parse(from).unwrap(),
+            to: Version::parse(to).unwrap(),
+            names: names.iter().map(ToString::to_string).collect(),
+        };
+        let migrations = || {
+            let manifest: Manifest = update_metadata::load_file(temp_manifest.path()).unwrap();
+            manifest.migrations
+        };
+        let key =
+            |from: &str, to: &str| (Version::parse(from).unwrap(), Version::parse(to).unwrap());
+
+        // New migrations go at the end, and ones already there aren't added twice.
+        entry(
+            "1.12.0",
+            "1.13.0",
+            &["migrate_1.13.0_baz", "migrate_1.13.0_foo"],
+        )
+        .add()
+        .unwrap();
+        assert_eq!(
+            migrations()[&key("1.12.0", "1.13.0")],
+            vec![
+                "migrate_1.13.0_foo",
+                "migrate_1.13.0_bar",
+                "migrate_1.13.0_baz"
+            ]
+        );
+        entry("1.13.0", "1.14.0", &["migrate_v1.14.0_new"])
+            .add()
+            .unwrap();
+        assert_eq!(
+            migrations()[&key("1.13.0", "1.14.0")],
+            vec!["migrate_v1.14.0_new"]
+        );
+        // Names have to be for the version they lead to.
+        assert!(entry("1.13.0", "1.14.0", &["migrate_1.13.0_old"])
+            .add()
+            .is_err());
+        assert!(entry("1.13.0", "1.14.0", &[]).add().is_err());
+
+        entry("1.12.0", "1.13.0", &["migrate_1.13.0_foo"])
+            .remove()
+            .unwrap();
+        assert_eq!(
+            migrations()[&key("1.12.0", "1.13.0")],
+            vec!["migrate_1.13.0_bar", "migrate_1.13.0_baz"]
+        );
+        assert!(entry("1.12.0", "1.13.0", &["migrate_1.13.0_foo"])
+            .remove()
+            .is_err());
+        assert!(entry("1.0.0", "1.1.0", &[]).remove().is_err());
+
+        // Without names, or once the list is empty, the pair is removed.
+        entry("1.12.0", "1.13.0", &[]).remove().unwrap();
+        entry("1.13.0", "1.14.0", &["migrate_v1.14.0_new"])
+            .remove()
+            .unwrap();
+        assert_eq!(
+            migrations().keys().cloned().collect::<Vec<_>>(),
+            vec![key("1.11.0", "1.12.0")]
+        );
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        // One update, with waves starting on the 6th, 7th, and 8th
+        let path = "tests/data/example.json";
+        let mut manifest: Manifest = update_metadata::load_file(Path::new(path)).unwrap();
+        let variant = manifest.updates[0].variant.clone();
+        let arch = manifest.updates[0].arch.clone();
+        let image_version = manifest.updates[0].version.clone();
+        let time = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+
+        // Pausing again keeps the original pause time.
+        for paused in &["2019-10-06T20:00:00Z", "2019-10-07T20:00:00Z"] {
+            let num_matching = manifest.pause_rollout(
+                variant.clone(),
+                arch.clone(),
+                image_version.clone(),
+                time(paused),
+            );
+            assert_eq!(num_matching, 1);
+        }
+        assert_eq!(
+            manifest.updates[0].paused,
+            Some(time("2019-10-06T20:00:00Z"))
+        );
+
+        // Waves that hadn't started by the pause are pushed back by its two days.
+        manifest
+            .resume_rollout(variant, arch, image_version, time("2019-10-08T20:00:00Z"))
+            .unwrap();
+        let update = &manifest.updates[0];
+        assert_eq!(update.paused, None);
+        let waves: Vec<_> = update.waves.values().copied().collect();
+        assert_eq!(
+            waves,
+            vec![
+                time("2019-10-06T15:00:00Z"),
+                time("2019-10-09T15:00:00Z"),
+                time("2019-10-10T15:00:00Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn shard_and_unshard() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("manifest.json");
+        let mut manifest =
+            update_metadata::load_file(Path::new("tests/data/multiple.json")).unwrap();
+        let other = update_metadata::load_file(Path::new("tests/data/example_3.json")).unwrap();
+        manifest.updates.extend(other.updates);
+        let index_updates = |path: &Path| {
+            let index: serde_json::Value =
+                serde_json::from_slice(&fs::read(path).unwrap()).unwrap();
+            index["updates"].as_array().unwrap().len()
+        };
+
+        let variant = String::from("aws-k8s-1.15");
+        assert_eq!(manifest.shard(&[variant.clone()]), vec![variant.clone()]);
+        update_metadata::write_file(&path, &manifest).unwrap();
+        assert_eq!(index_updates(&path), 4);
+        assert_eq!(
+            index_updates(&dir.path().join("manifest-aws-k8s-1.15.json")),
+            2
+        );
+        // Loading the manifest brings the shard's updates back in.
+        let mut manifest = update_metadata::load_file(&path).unwrap();
+        assert_eq!(manifest.updates.len(), 6);
+        assert!(manifest.shard(&[variant]).is_empty());
+
+        GeneralArgs {
+            file: ManifestFile::local(path.clone()),
+        }
+        .unshard()
+        .unwrap();
+        assert_eq!(index_updates(&path), 6);
+        assert!(update_metadata::load_file(&path).unwrap().shards.is_empty());
+    }
+
+    #[test]
+    fn extract_subset() {
+        let path = Path::new("tests/data/migrations.json");
+        let mut manifest = update_metadata::load_file(path).unwrap();
+        let other = update_metadata::load_file(Path::new("tests/data/multiple.json")).unwrap();
+        manifest.updates.extend(other.updates);
+        manifest.updates[4].variant = String::from("aws-k8s-1.15");
+        let version = |v: &str| Version::parse(v).unwrap();
+
+        let subset = extract(manifest, "bottlerocket-aws-eks", Some(&version("1.5.0"))).unwrap();
+        let versions: Vec<_> = subset
+            .updates
+            .iter()
+            .map(|u| u.version.to_string())
+            .collect();
+        assert_eq!(versions, vec!["1.5.0", "1.13.0", "1.25.0", "1.15.0"]);
+        // No host using the subset runs 1.2.0 or older.
+        assert!(subset.migrations.is_empty());
+
+        let manifest = update_metadata::load_file(path).unwrap();
+        let subset = extract(manifest, "bottlerocket-aws-eks", Some(&version("1.1.0"))).unwrap();
+        assert_eq!(
+            subset.migrations.keys().cloned().collect::<Vec<_>>(),
+            vec![
+                (version("1.1.0"), version("1.2.0")),
+                (version("1.1.0"), version("1.5.0")),
+                (version("1.2.0"), version("1.5.0")),
+            ]
+        );
+
+        let manifest = update_metadata::load_file(path).unwrap();
+        assert!(extract(manifest, "aws-k8s-1.15", None).is_err());
+    }
+
+    #[test]
+    fn list_filtered_updates() {
+        let manifest = update_metadata::load_file(Path::new("tests/data/multiple.json")).unwrap();
+        let listed = |variant, arch, versions: &str| -> Vec<String> {
+            let versions = VersionReq::parse(versions).unwrap();
+            list_updates(&manifest, variant, arch, &versions)
+                .iter()
+                .map(|u| format!("{} {}", u.arch, u.version))
+                .collect()
+        };
+
+        assert_eq!(
+            listed(None, None, "*"),
+            vec![
+                "aarch64 1.16.0",
+                "x86_64 1.25.0",
+                "x86_64 1.15.0",
+                "x86_64 1.13.0"
+            ]
+        );
+        assert_eq!(
+            listed(
+                Some("bottlerocket-aws-eks"),
+                Some("x86_64"),
+                ">=1.14, <1.20"
+            ),
+            vec!["x86_64 1.15.0"]
+        );
+        assert!(listed(Some("aws-k8s-1.15"), None, "*").is_empty());
+    }
+
+    #[test]
+    fn copy_update() -> Result<()> {
+        let tmpfd = NamedTempFile::new().context(error::TmpFileCreate)?;
+        fs::copy("tests/data/example.json", tmpfd.path()).unwrap();
+        let args = |to_variant: &str, root: Option<&str>, with_waves| CopyUpdateArgs {
+            file: ManifestFile::local(tmpfd.path()),
+            from_variant: String::from("bottlerocket-aws-eks"),
+            to_variant: String::from(to_variant),
+            image_version: Version::new(1, 13, 0),
+            arch: String::from("x86_64"),
+            max_version: None,
+            root: root.map(String::from),
+            boot: None,
+            hash: None,
+            with_waves,
+        };
+
+        args("aws-dev", None, false).run()?;
+        args("aws-k8s-1.15", Some("root-1.13.img"), true).run()?;
+        // The copies can't be copied over.
+        assert!(args("aws-dev", None, false).run().is_err());
+
+        let manifest = update_metadata::load_file(tmpfd.path()).unwrap();
+        let (original, dev, k8s) = (
+            &manifest.updates[0],
+            &manifest.updates[1],
+            &manifest.updates[2],
+        );
+        assert_eq!(dev.variant, "aws-dev");
+        assert_eq!(dev.images, original.images);
+        assert_eq!(dev.max_version, original.max_version);
+        assert!(dev.waves.is_empty());
+        assert_eq!(k8s.images.root, "root-1.13.img");
+        assert_eq!(k8s.images.boot, original.images.boot);
+        assert_eq!(k8s.waves, original.waves);
+
+        // There's nothing to copy from a variant without the update.
+        let mut missing = args("aws-ecs-1", None, false);
+        missing.from_variant = String::from("aws-k8s-1.16");
+        assert!(missing.run().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn targeting_args() {
+        assert_eq!(
+            parse_seed_range("0..1024").unwrap(),
+            SeedRange {
+                start: 0,
+                end: 1024
+            }
+        );
+        assert!(parse_seed_range("0-1024").is_err());
+        assert!(parse_seed_range("..1024").is_err());
+        assert_eq!(
+            parse_label("gpu=true").unwrap(),
+            (String::from("gpu"), String::from("true"))
+        );
+        assert!(parse_label("gpu").is_err());
+        assert!(parse_label("gpu=").is_err());
+        assert!(parse_label("gpu=\"true\"").is_err());
+
+        let path = "tests/data/example.json";
+        let mut manifest: Manifest = update_metadata::load_file(Path::new(path)).unwrap();
+        let update = &manifest.updates[0];
+        let (variant, arch, version) = (
+            update.variant.clone(),
+            update.arch.clone(),
+            update.version.clone(),
+        );
+        let targeting = Targeting {
+            seeds: vec![parse_seed_range("1024..4096").unwrap()],
+            ..Targeting::default()
+        };
+        assert!(manifest
+            .set_targeting(variant, arch, version, Some(&targeting))
+            .is_err());
+    }
+
+    #[test]
+    fn set_compatibility() -> Result<()> {
+        let tmpfd = NamedTempFile::new().context(error::TmpFileCreate)?;
+        fs::copy("tests/data/multiple.json", tmpfd.path()).unwrap();
+        let args = |arch: Option<&str>, updates_to: &[&str], remove| CompatibilityArgs {
+            file: ManifestFile::local(tmpfd.path()),
+            variant: String::from("bottlerocket-aws-eks"),
+            arch: arch.map(String::from),
+            updates_to: updates_to.iter().map(ToString::to_string).collect(),
+            remove,
+        };
+        args(
+            None,
+            &["bottlerocket-aws-eks", "bottlerocket-aws-eks-2"],
+            false,
+        )
+        .run()?;
+        args(Some("aarch64"), &[], false).run()?;
+        validate(&ManifestFile::local(tmpfd.path()))?;
+        let m: Manifest = update_metadata::load_file(tmpfd.path())?;
+        assert_eq!(m.compatibility.len(), 2);
+        assert!(m
+            .applicable_updates("bottlerocket-aws-eks", "aarch64")
+            .is_empty());
+        assert_eq!(
+            m.applicable_updates("bottlerocket-aws-eks", "x86_64").len(),
+            2
+        );
+
+        args(Some("aarch64"), &[], true).run()?;
+        assert!(args(
+            None,
+            &["bottlerocket-aws-eks", "bottlerocket-aws-eks"],
+            false
+        )
+        .run()
+        .is_err());
+        let m: Manifest = update_metadata::load_file(tmpfd.path())?;
+        assert_eq!(m.compatibility.len(), 1);
+        assert_eq!(
+            m.applicable_updates("bottlerocket-aws-eks", "aarch64")
+                .len(),
+            1
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn apply_plan_atomically() -> Result<()> {
+        let tmpfd = NamedTempFile::new().context(error::TmpFileCreate)?;
+        fs::copy("tests/data/example.json", tmpfd.path()).unwrap();
+        let before = fs::read(tmpfd.path()).unwrap();
+        // The second step fails, since there's no such update, so the first isn't kept either.
+        let plan = NamedTempFile::new().context(error::TmpFileCreate)?;
+        fs::write(
+            plan.path(),
+            "[[step]]\n\
+             op = \"add-update\"\n\
+             variant = \"bottlerocket-aws-eks\"\n\
+             arch = \"x86_64\"\n\
+             version = \"1.14.0\"\n\
+             root = \"root\"\n\
+             boot = \"boot\"\n\
+             hash = \"hash\"\n\
+             [[step]]\n\
+             op = \"set-waves\"\n\
+             variant = \"bottlerocket-aws-eks\"\n\
+             arch = \"x86_64\"\n\
+             version = \"9.9.9\"\n\
+             waves = [{ start_after = \"1 hour\", fleet_percentage = 100 }]\n",
+        )
+        .unwrap();
+        let args = || PlanArgs {
+            file: ManifestFile::local(tmpfd.path()),
+            plan: plan.path().to_path_buf(),
+        };
+        assert!(args().run().is_err());
+        assert_eq!(fs::read(tmpfd.path()).unwrap(), before);
+
+        let fixed = fs::read_to_string(plan.path())
+            .unwrap()
+            .replace("9.9.9", "1.14.0");
+        fs::write(plan.path(), fixed).unwrap();
+        args().run()?;
+        let m: Manifest = update_metadata::load_file(tmpfd.path())?;
+        assert!(m
+            .updates
+            .iter()
+            .any(|u| u.version == Version::new(1, 14, 0) && !u.waves.is_empty()));
+        Ok(())
+    }
+
+    #[test]
+    fn cut_release() -> Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let build = dir.path().join("build");
+        fs::create_dir(&build).unwrap();
+        for arch in &["x86_64", "aarch64"] {
+            for kind in &["root.ext4.lz4", "boot.ext4.lz4", "root.verity.lz4"] {
+                let name = format!("bottlerocket-aws-k8s-1.15-{}-1.14.0-abc-{}", arch, kind);
+                fs::write(build.join(name), "").unwrap();
+            }
+        }
+        let release = dir.path().join("Release.toml");
+        fs::write(
+            &release,
+            "version = \"1.14.0\"\n[migrations]\n\"(1.13.0, 1.14.0)\" = [\"migrate_1.14.0_foo\"]\n",
+        )
+        .unwrap();
+        let changelog = dir.path().join("CHANGELOG.md");
+        fs::write(&changelog, "# v1.14.0 (2020-06-01)\n").unwrap();
+        let manifest = dir.path().join("manifest.json");
+        fs::copy("tests/data/example.json", &manifest).unwrap();
+
+        let args = |version: u64, policy: &str| CutReleaseArgs {
+            file: ManifestFile::local(&manifest),
+            image_version: Version::new(1, version, 0),
+            build_dir: build.clone(),
+            release: release.clone(),
+            wave_policy: Some(policy.to_string()),
+            waves: no_preset(),
+            waves_dir: PathBuf::from("../waves"),
+            lint_config: None,
+            changelog: changelog.clone(),
+        };
+        // The release file has to be for the version being cut, and the policy has to exist.
+        assert!(args(15, "default").run().is_err());
+        assert!(args(14, "standard").run().is_err());
+
+        args(14, "default").run()?;
+        let m: Manifest = update_metadata::load_file(&manifest)?;
+        let new: Vec<_> = m
+            .updates
+            .iter()
+            .filter(|u| u.version == Version::new(1, 14, 0))
+            .collect();
+        assert_eq!(new.len(), 2);
+        assert!(new.iter().all(|u| !u.waves.is_empty()));
+        // The max version is only ever raised, and example.json's is already past 1.14.0.
+        assert!(m
+            .updates
+            .iter()
+            .all(|u| u.max_version == Version::new(1, 20, 0)));
+        assert_eq!(m.migrations.len(), 1);
+
+        // Cutting the same release again would add the updates twice.
+        assert!(args(14, "default").run().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn pause_missing_update() -> Result<()> {
+        let tmpfd = NamedTempFile::new().context(error::TmpFileCreate)?;
+        fs::copy("tests/data/example.json", tmpfd.path()).unwrap();
+        let args = RolloutArgs {
+            file: ManifestFile::local(tmpfd.path()),
+            variant: String::from("bottlerocket-aws-eks"),
+            image_version: Version::parse("9.9.9").unwrap(),
+            arch: String::from("x86_64"),
+        };
+        assert!(args.pause().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn clear_waves() -> Result<()> {
+        let tmpfd = NamedTempFile::new().context(error::TmpFileCreate)?;
+        fs::copy("tests/data/example.json", tmpfd.path()).unwrap();
+        let args = |version: &str| RolloutArgs {
+            file: ManifestFile::local(tmpfd.path()),
+            variant: String::from("bottlerocket-aws-eks"),
+            image_version: Version::parse(version).unwrap(),
+            arch: String::from("x86_64"),
+        };
+        args("1.13.0").clear_waves()?;
+        let m: Manifest = update_metadata::load_file(tmpfd.path())?;
+        assert!(m.updates[0].waves.is_empty());
+        // Waves can be set from scratch afterward.
+        WaveArgs {
+            file: ManifestFile::local(tmpfd.path()),
+            variant: String::from("bottlerocket-aws-eks"),
+            image_version: Version::parse("1.13.0").unwrap(),
+            arch: String::from("x86_64"),
+            wave_file: None,
+            bound: None,
+            fleet_percentage: Some(10),
+            start: Some(Utc::now()),
+        }
+        .set()?;
+        let m: Manifest = update_metadata::load_file(tmpfd.path())?;
+        assert_eq!(m.updates[0].waves.len(), 1);
+
+        assert!(args("9.9.9").clear_waves().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn deprecate_and_reinstate() -> Result<()> {
+        let tmpfd = NamedTempFile::new().context(error::TmpFileCreate)?;
+        fs::copy("tests/data/example.json", tmpfd.path()).unwrap();
+        let args = |version: &str| RolloutArgs {
+            file: ManifestFile::local(tmpfd.path()),
+            variant: String::from("bottlerocket-aws-eks"),
+            image_version: Version::parse(version).unwrap(),
+            arch: String::from("x86_64"),
+        };
+
+        // A deprecated update stays in the manifest, but isn't applicable.
+        args("1.13.0").set_available(false)?;
+        let m: Manifest = update_metadata::load_file(tmpfd.path())?;
+        assert!(!m.updates[0].available);
+        assert!(m
+            .applicable_updates("bottlerocket-aws-eks", "x86_64")
+            .is_empty());
+        assert!(!m.migrations.is_empty());
+
+        // Reinstating it leaves the manifest as it was.
+        args("1.13.0").set_available(true)?;
+        let m: Manifest = update_metadata::load_file(tmpfd.path())?;
+        assert!(m.updates[0].available);
+        assert!(!fs::read_to_string(tmpfd.path())
+            .unwrap()
+            .contains("available"));
+
+        assert!(args("9.9.9").set_available(false).is_err());
+        Ok(())
+    }
+
     #[test]
     fn max_versions() -> Result<()> {
         let tmpfd = NamedTempFile::new().context(error::TmpFileCreate)?;
         AddUpdateArgs {
-            file: PathBuf::from(tmpfd.path()),
+            file: ManifestFile::local(tmpfd.path()),
             variant: String::from("yum"),
-            arch: String::from("x86_64"),
+            arch: vec![String::from("x86_64")],
             image_version: Version::parse("1.2.3").unwrap(),
             max_version: Some(Version::parse("1.2.3").unwrap()),
             boot: String::from("boot"),
             root: String::from("root"),
             hash: String::from("hash"),
+            waves: no_preset(),
         }
         .run()
         .unwrap();
         AddUpdateArgs {
-            file: PathBuf::from(tmpfd.path()),
+            file: ManifestFile::local(tmpfd.path()),
             variant: String::from("yum"),
-            arch: String::from("x86_64"),
+            arch: vec![String::from("x86_64")],
             image_version: Version::parse("1.2.5").unwrap(),
             max_version: Some(Version::parse("1.2.3").unwrap()),
             boot: String::from("boot"),
             root: String::from("root"),
             hash: String::from("hash"),
+            waves: no_preset(),
         }
         .run()
         .unwrap();
         AddUpdateArgs {
-            file: PathBuf::from(tmpfd.path()),
+            file: ManifestFile::local(tmpfd.path()),
             variant: String::from("yum"),
-            arch: String::from("x86_64"),
+            arch: vec![String::from("x86_64")],
             image_version: Version::parse("1.2.4").unwrap(),
             max_version: Some(Version::parse("1.2.4").unwrap()),
             boot: String::from("boot"),
             root: String::from("root"),
             hash: String::from("hash"),
+            waves: no_preset(),
         }
         .run()
         .unwrap();
@@ -400,4 +3398,176 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn scoped_max_version() -> Result<()> {
+        let tmpfd = NamedTempFile::new().context(error::TmpFileCreate)?;
+        for variant in &["aws-k8s-1.15", "aws-dev"] {
+            AddUpdateArgs {
+                file: ManifestFile::local(tmpfd.path()),
+                variant: variant.to_string(),
+                arch: vec![String::from("all")],
+                image_version: Version::new(1, 2, 3),
+                max_version: None,
+                boot: String::from("{arch}-boot"),
+                root: String::from("{arch}-root"),
+                hash: String::from("{arch}-hash"),
+                waves: no_preset(),
+            }
+            .run()?;
+        }
+        let args = |version: u64, variant: Option<&str>, arch: Option<&str>| MaxVersionArgs {
+            file: ManifestFile::local(tmpfd.path()),
+            max_version: Version::new(1, version, 0),
+            variant: variant.map(String::from),
+            arch: arch.map(String::from),
+        };
+        let max_versions = || -> Result<Vec<String>> {
+            let m: Manifest = update_metadata::load_file(tmpfd.path())?;
+            Ok(m.updates
+                .iter()
+                .map(|u| format!("{} {} {}", u.variant, u.arch, u.max_version))
+                .collect())
+        };
+
+        args(3, None, None).run()?;
+        args(4, Some("aws-dev"), None).run()?;
+        args(5, Some("aws-dev"), Some("aarch64")).run()?;
+        assert_eq!(
+            max_versions()?,
+            vec![
+                "aws-k8s-1.15 x86_64 1.3.0",
+                "aws-k8s-1.15 aarch64 1.3.0",
+                "aws-dev x86_64 1.4.0",
+                "aws-dev aarch64 1.5.0",
+            ]
+        );
+
+        // A filter that matches nothing is probably a typo, so nothing is written.
+        assert!(args(6, Some("aws-devv"), None).run().is_err());
+        assert!(args(6, Some("aws-k8s-1.15"), Some("arm64")).run().is_err());
+        assert_eq!(max_versions()?[0], "aws-k8s-1.15 x86_64 1.3.0");
+        Ok(())
+    }
+
+    #[test]
+    fn add_update_arches() -> Result<()> {
+        let tmpfd = NamedTempFile::new().context(error::TmpFileCreate)?;
+        let args = |arch: &[&str], root: &str| AddUpdateArgs {
+            file: ManifestFile::local(tmpfd.path()),
+            variant: String::from("yum"),
+            arch: arch.iter().map(ToString::to_string).collect(),
+            image_version: Version::parse("1.2.3").unwrap(),
+            max_version: None,
+            boot: String::from("bottlerocket-yum-{arch}-1.2.3-boot.ext4.lz4"),
+            root: String::from(root),
+            hash: String::from("bottlerocket-yum-{arch}-1.2.3-root.verity.lz4"),
+            waves: no_preset(),
+        };
+
+        // Every target name needs the placeholder, or the updates would share images.
+        assert!(args(&["x86_64", "aarch64"], "root").run().is_err());
+        assert!(fs::read(tmpfd.path()).unwrap().is_empty());
+
+        let root = "bottlerocket-yum-{arch}-1.2.3-root.ext4.lz4";
+        args(&["x86_64", "all"], root).run()?;
+        let m: Manifest = update_metadata::load_file(tmpfd.path())?;
+        let added: Vec<_> = m
+            .updates
+            .iter()
+            .map(|u| (u.arch.as_str(), u.images.root.as_str()))
+            .collect();
+        assert_eq!(
+            added,
+            vec![
+                ("x86_64", "bottlerocket-yum-x86_64-1.2.3-root.ext4.lz4"),
+                ("aarch64", "bottlerocket-yum-aarch64-1.2.3-root.ext4.lz4"),
+            ]
+        );
+        assert_eq!(
+            m.updates[1].images.hash,
+            "bottlerocket-yum-aarch64-1.2.3-root.verity.lz4"
+        );
+        Ok(())
+    }
+
+    fn no_preset() -> PresetArgs {
+        PresetArgs {
+            wave_preset: None,
+            wave_start: Utc::now(),
+            presets: None,
+        }
+    }
+
+    #[test]
+    fn add_update_preset() -> Result<()> {
+        let tmpfd = NamedTempFile::new().context(error::TmpFileCreate)?;
+        let start = Utc.ymd(2020, 6, 1).and_hms(9, 0, 0);
+        let args = |preset: &str| AddUpdateArgs {
+            file: ManifestFile::local(tmpfd.path()),
+            variant: String::from("yum"),
+            arch: vec![String::from("x86_64"), String::from("aarch64")],
+            image_version: Version::parse("1.2.3").unwrap(),
+            max_version: None,
+            boot: String::from("bottlerocket-yum-{arch}-1.2.3-boot.ext4.lz4"),
+            root: String::from("bottlerocket-yum-{arch}-1.2.3-root.ext4.lz4"),
+            hash: String::from("bottlerocket-yum-{arch}-1.2.3-root.verity.lz4"),
+            waves: PresetArgs {
+                wave_preset: Some(preset.to_string()),
+                wave_start: start,
+                presets: None,
+            },
+        };
+
+        // An unknown preset is caught before any update is added.
+        assert!(args("slow").run().is_err());
+        assert!(fs::read(tmpfd.path()).unwrap().is_empty());
+
+        args("fast-24h").run()?;
+        let m: Manifest = update_metadata::load_file(tmpfd.path())?;
+        assert_eq!(m.updates.len(), 2);
+        for update in &m.updates {
+            let waves: Vec<_> = update.waves.iter().map(|(k, v)| (*k, *v)).collect();
+            assert_eq!(
+                waves,
+                vec![
+                    (0, start),
+                    (204, start + chrono::Duration::hours(6)),
+                    (1024, start + chrono::Duration::hours(24)),
+                ]
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn add_single_wave() -> Result<()> {
+        let tmpfd = NamedTempFile::new().context(error::TmpFileCreate)?;
+        fs::copy("tests/data/single_wave.json", tmpfd.path()).context(error::TmpFileCreate)?;
+        let start = |day| Some(Utc.ymd(2019, 10, day).and_hms(15, 0, 0));
+        let args = |bound, fleet_percentage, start| WaveArgs {
+            file: ManifestFile::local(tmpfd.path()),
+            variant: String::from("bottlerocket-aws-eks"),
+            image_version: Version::parse("1.13.0").unwrap(),
+            arch: String::from("x86_64"),
+            wave_file: None,
+            bound,
+            fleet_percentage,
+            start,
+        };
+
+        // A percentage of the fleet becomes the seed the wave starts at.
+        args(None, Some(25), start(8)).set()?;
+        args(Some(20), None, start(7)).set()?;
+        let m: Manifest = update_metadata::load_file(tmpfd.path())?;
+        let seeds: Vec<_> = m.updates[0].waves.keys().copied().collect();
+        assert_eq!(seeds, vec![0, 20, 512]);
+
+        // Waves have to start in order, and before the end of the fleet.
+        assert!(args(None, Some(50), start(7)).set().is_err());
+        assert!(args(None, Some(100), start(9)).set().is_err());
+        assert!(args(Some(2048), None, start(9)).set().is_err());
+        assert!(args(None, Some(50), None).set().is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,408 @@
+//! How far each wave of an update has actually got, from the fleet's own telemetry, so a rollout
+//! whose early waves are stuck can be held before the later ones open.  Telemetry is exported as
+//! JSON, with the hosts in each wave and how many of them have taken the update, and imported into
+//! a sidecar file kept next to the manifest; hosts never fetch it, so the manifest stays the same
+//! size.  Waves are named by the seed they start at, as in the manifest:
+//!
+//! ```json
+//! {
+//!   "collected": "2020-04-03T12:00:00Z",
+//!   "updates": [
+//!     {
+//!       "variant": "aws-k8s-1.15",
+//!       "arch": "x86_64",
+//!       "version": "1.0.6",
+//!       "waves": { "20": { "hosts": 120, "updated": 118 }, "102": { "hosts": 480, "updated": 95 } }
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! An update can give its own `collected` time, if it was taken apart from the rest.
+
+use crate::error::{self, Result};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, ResultExt};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use update_metadata::{Manifest, Update};
+
+/// The hosts seen in a wave, and how many of them have taken the update.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Counts {
+    pub(crate) hosts: u64,
+    pub(crate) updated: u64,
+}
+
+impl Counts {
+    /// The share of the wave's hosts that have taken the update, as a percentage.
+    fn percentage(self) -> Option<f64> {
+        if self.hosts == 0 {
+            return None;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        Some(self.updated as f64 * 100.0 / self.hosts as f64)
+    }
+}
+
+/// What was seen of one update, by the seed each wave starts at.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UpdateAdoption {
+    variant: String,
+    arch: String,
+    version: Version,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    collected: Option<DateTime<Utc>>,
+    waves: BTreeMap<u32, Counts>,
+}
+
+impl UpdateAdoption {
+    fn is_for(&self, update: &Update) -> bool {
+        self.variant == update.variant && self.arch == update.arch && self.version == update.version
+    }
+
+    fn name(&self) -> String {
+        format!("{}-{}-{}", self.arch, self.variant, self.version)
+    }
+}
+
+/// Telemetry as exported from the fleet.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Export {
+    collected: DateTime<Utc>,
+    updates: Vec<UpdateAdoption>,
+}
+
+/// The adoption last imported for each update, as kept in the sidecar file.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Adoption {
+    updates: Vec<UpdateAdoption>,
+}
+
+impl Adoption {
+    /// Loads the sidecar file, or nothing if there isn't one yet.
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = fs::read(path).context(error::AdoptionRead { path })?;
+        serde_json::from_slice(&data).context(error::AdoptionParse { path })
+    }
+
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self).context(error::UpdateSerialize)?;
+        fs::write(path, data).context(error::AdoptionWrite { path })
+    }
+
+    /// Imports the telemetry exported to `path`, replacing what was kept for each update it has
+    /// unless what was kept is newer.  Updates that aren't in the manifest are skipped, since they
+    /// may have been removed since the telemetry was taken, but waves the update doesn't have, or
+    /// counts that can't be right, are errors.  Returns how many updates were imported.
+    pub(crate) fn import(&mut self, manifest: &Manifest, path: &Path) -> Result<usize> {
+        let data = fs::read(path).context(error::AdoptionRead { path })?;
+        let export: Export =
+            serde_json::from_slice(&data).context(error::AdoptionParse { path })?;
+
+        let mut imported = 0;
+        for mut update in export.updates {
+            let manifest_update =
+                if let Some(manifest_update) = manifest.updates.iter().find(|u| update.is_for(u)) {
+                    manifest_update
+                } else {
+                    warn!("Skipping {}, which isn't in the manifest", update.name());
+                    continue;
+                };
+            for (seed, counts) in &update.waves {
+                ensure!(
+                    manifest_update.waves.contains_key(seed),
+                    error::AdoptionWave {
+                        update: update.name(),
+                        seed: *seed,
+                    }
+                );
+                ensure!(
+                    counts.updated <= counts.hosts,
+                    error::AdoptionCounts {
+                        update: update.name(),
+                        seed: *seed,
+                    }
+                );
+            }
+            let collected = *update.collected.get_or_insert(export.collected);
+
+            match self
+                .updates
+                .iter_mut()
+                .find(|kept| kept.is_for(manifest_update))
+            {
+                Some(kept) if kept.collected > Some(collected) => {
+                    warn!(
+                        "Keeping the adoption of {} collected at {}, which is newer",
+                        update.name(),
+                        format_time(kept.collected.unwrap_or(collected))
+                    );
+                    continue;
+                }
+                Some(kept) => *kept = update,
+                None => self.updates.push(update),
+            }
+            imported += 1;
+        }
+        self.updates.sort_by(|a, b| {
+            (&a.variant, &a.arch, &a.version).cmp(&(&b.variant, &b.arch, &b.version))
+        });
+        Ok(imported)
+    }
+
+    /// Checks each wave with adoption data against the manifest.  A wave lags if it started at
+    /// least `settle` before the data was collected, fewer than `min_percentage` of its hosts have
+    /// taken the update, and a later wave is due to follow it.
+    pub(crate) fn report(
+        &self,
+        manifest: &Manifest,
+        min_percentage: f64,
+        settle: Duration,
+    ) -> AdoptionReport {
+        let mut waves = Vec::new();
+        for adoption in &self.updates {
+            let update = match manifest.updates.iter().find(|u| adoption.is_for(u)) {
+                Some(update) => update,
+                None => continue,
+            };
+            let collected = adoption.collected.unwrap_or_else(Utc::now);
+            for (seed, counts) in &adoption.waves {
+                let start = match update.waves.get(seed) {
+                    Some(start) => *start,
+                    None => continue,
+                };
+                let next_opens = update
+                    .waves
+                    .values()
+                    .filter(|opens| **opens > start)
+                    .min()
+                    .copied();
+                let lagging = start + settle <= collected
+                    && next_opens.is_some()
+                    && counts
+                        .percentage()
+                        .map_or(false, |percentage| percentage < min_percentage);
+                waves.push(WaveAdoption {
+                    variant: update.variant.clone(),
+                    arch: update.arch.clone(),
+                    version: update.version.clone(),
+                    seed: *seed,
+                    start,
+                    collected,
+                    counts: *counts,
+                    next_opens,
+                    lagging,
+                });
+            }
+        }
+        AdoptionReport { waves }
+    }
+}
+
+/// The adoption of one wave, and whether it lags.
+#[derive(Debug, Serialize)]
+pub(crate) struct WaveAdoption {
+    variant: String,
+    arch: String,
+    version: Version,
+    seed: u32,
+    start: DateTime<Utc>,
+    collected: DateTime<Utc>,
+    #[serde(flatten)]
+    counts: Counts,
+    next_opens: Option<DateTime<Utc>>,
+    lagging: bool,
+}
+
+impl fmt::Display for WaveAdoption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}-{}-{} wave {} (started {}): ",
+            if self.lagging { "LAGGING " } else { "" },
+            self.arch,
+            self.variant,
+            self.version,
+            self.seed,
+            format_time(self.start)
+        )?;
+        match self.counts.percentage() {
+            Some(percentage) => write!(
+                f,
+                "{} of {} hosts updated ({:.1}%)",
+                self.counts.updated, self.counts.hosts, percentage
+            )?,
+            None => write!(f, "no hosts seen")?,
+        }
+        match self.next_opens {
+            Some(opens) if opens <= self.collected => {
+                write!(f, "; the next wave opened at {}", format_time(opens))
+            }
+            Some(opens) => write!(f, "; the next wave opens at {}", format_time(opens)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct AdoptionReport {
+    waves: Vec<WaveAdoption>,
+}
+
+impl AdoptionReport {
+    /// The number of waves that lag.
+    pub(crate) fn lagging(&self) -> usize {
+        self.waves.iter().filter(|wave| wave.lagging).count()
+    }
+}
+
+impl fmt::Display for AdoptionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.waves.is_empty() {
+            return writeln!(f, "No adoption data for the manifest's updates");
+        }
+        for wave in &self.waves {
+            writeln!(f, "{}", wave)?;
+        }
+        Ok(())
+    }
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use update_metadata::Images;
+
+    fn manifest() -> Manifest {
+        let mut manifest = Manifest::default();
+        manifest
+            .add_update(
+                Version::new(1, 0, 6),
+                None,
+                "x86_64".to_string(),
+                "aws-k8s-1.15".to_string(),
+                Images {
+                    root: "root".to_string(),
+                    boot: "boot".to_string(),
+                    hash: "hash".to_string(),
+                },
+            )
+            .unwrap();
+        let day = |d| Utc.ymd(2020, 4, d).and_hms(0, 0, 0);
+        let update = &mut manifest.updates[0];
+        update.waves.insert(20, day(1));
+        update.waves.insert(102, day(2));
+        update.waves.insert(2048, day(5));
+        manifest
+    }
+
+    fn export(dir: &Path, collected: &str, waves: &str) -> std::path::PathBuf {
+        let path = dir.join("export.json");
+        fs::write(
+            &path,
+            format!(
+                r#"{{"collected": "{}", "updates": [
+                    {{"variant": "aws-k8s-1.15", "arch": "x86_64", "version": "1.0.6", "waves": {}}},
+                    {{"variant": "aws-k8s-1.15", "arch": "x86_64", "version": "0.9.0", "waves": {{}}}}
+                ]}}"#,
+                collected, waves
+            ),
+        )
+        .unwrap();
+        path
+    }
+
+    #[test]
+    fn import() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = manifest();
+        let sidecar = dir.path().join("manifest.adoption.json");
+        let mut adoption = Adoption::load(&sidecar).unwrap();
+
+        // The update that isn't in the manifest is skipped.
+        let path = export(
+            dir.path(),
+            "2020-04-03T00:00:00Z",
+            r#"{"20": {"hosts": 10, "updated": 9}}"#,
+        );
+        assert_eq!(adoption.import(&manifest, &path).unwrap(), 1);
+        adoption.write(&sidecar).unwrap();
+        assert_eq!(Adoption::load(&sidecar).unwrap(), adoption);
+
+        // Older telemetry doesn't replace newer.
+        let path = export(
+            dir.path(),
+            "2020-04-02T00:00:00Z",
+            r#"{"20": {"hosts": 10, "updated": 1}}"#,
+        );
+        assert_eq!(adoption.import(&manifest, &path).unwrap(), 0);
+        assert_eq!(adoption.updates[0].waves[&20].updated, 9);
+
+        let path = export(
+            dir.path(),
+            "2020-04-04T00:00:00Z",
+            r#"{"30": {"hosts": 10, "updated": 1}}"#,
+        );
+        assert!(adoption.import(&manifest, &path).is_err());
+        let path = export(
+            dir.path(),
+            "2020-04-04T00:00:00Z",
+            r#"{"20": {"hosts": 10, "updated": 11}}"#,
+        );
+        assert!(adoption.import(&manifest, &path).is_err());
+    }
+
+    #[test]
+    fn lagging_waves() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = manifest();
+        let mut adoption = Adoption::default();
+        let path = export(
+            dir.path(),
+            "2020-04-03T00:00:00Z",
+            r#"{"20": {"hosts": 100, "updated": 40},
+                "102": {"hosts": 400, "updated": 20},
+                "2048": {"hosts": 0, "updated": 0}}"#,
+        );
+        adoption.import(&manifest, &path).unwrap();
+
+        // The first wave has had two days and lags; the second has only had one.
+        let report = adoption.report(&manifest, 90.0, Duration::days(1));
+        let lagging: Vec<_> = report.waves.iter().map(|w| (w.seed, w.lagging)).collect();
+        assert_eq!(lagging, vec![(20, true), (102, true), (2048, false)]);
+        assert_eq!(report.lagging(), 2);
+        let report = adoption.report(&manifest, 90.0, Duration::days(2));
+        assert_eq!(report.lagging(), 1);
+        let text = report.to_string();
+        assert!(text.starts_with(
+            "LAGGING x86_64-aws-k8s-1.15-1.0.6 wave 20 (started 2020-04-01T00:00:00Z): \
+             40 of 100 hosts updated (40.0%); the next wave opened at 2020-04-02T00:00:00Z\n"
+        ));
+        assert!(text.contains("the next wave opens at 2020-04-05T00:00:00Z"));
+        assert!(text.contains("no hosts seen"));
+
+        assert_eq!(
+            adoption
+                .report(&manifest, 30.0, Duration::days(1))
+                .lagging(),
+            1
+        );
+    }
+}
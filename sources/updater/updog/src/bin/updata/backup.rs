@@ -0,0 +1,150 @@
+//! Backups of local manifests, so a bad change can be taken back with `updata undo`.  Before a
+//! manifest is written, it's saved as it's stored now next to itself, as
+//! `manifest.json.bak.<time>` with the time in UTC.  The updates from any shard files are
+//! included, so each backup is a whole manifest in one file.  Only the newest backups are kept.
+
+use crate::error::{self, Result};
+use crate::store::ManifestFile;
+use chrono::Utc;
+use snafu::{OptionExt, ResultExt};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use update_metadata::Manifest;
+
+/// Saves a backup of the manifest at `path`, if there is one, and removes all but the newest
+/// `keep` backups.  Returns the path of the new backup.
+pub(crate) fn save(path: &Path, keep: usize) -> Result<Option<PathBuf>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data = match update_metadata::load_file(path) {
+        Ok(manifest) => serde_json::to_string_pretty(&manifest)
+            .context(error::UpdateSerialize)?
+            .into_bytes(),
+        // Keep what's there even if it can't be loaded, rather than refusing to replace it.
+        Err(_) => fs::read(path).context(error::BackupRead { path })?,
+    };
+    let mut name = prefix(path);
+    name.push(Utc::now().format("%Y%m%dT%H%M%S%.9fZ").to_string());
+    let backup = path.with_file_name(name);
+    fs::write(&backup, data).context(error::BackupWrite { path: &backup })?;
+
+    let backups = list(path)?;
+    for old in &backups[..backups.len().saturating_sub(keep)] {
+        fs::remove_file(old).context(error::BackupRemove { path: old })?;
+    }
+    Ok(Some(backup))
+}
+
+/// Returns the backups of the manifest at `path`, oldest first.
+pub(crate) fn list(path: &Path) -> Result<Vec<PathBuf>> {
+    let prefix = prefix(path);
+    let prefix = prefix.to_string_lossy();
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir).context(error::BackupList { path: dir })? {
+        let entry = entry.context(error::BackupList { path: dir })?;
+        if entry.file_name().to_string_lossy().starts_with(&*prefix) {
+            backups.push(path.with_file_name(entry.file_name()));
+        }
+    }
+    // The times in the names sort in the order they were taken.
+    backups.sort();
+    Ok(backups)
+}
+
+/// Writes the newest backup of `file` over it, and removes the backup, so undoing again goes
+/// back another step.  Returns the backup that was restored.
+pub(crate) fn undo(file: &ManifestFile) -> Result<PathBuf> {
+    let path = file.local_path().context(error::UndoRemote {
+        file: file.to_string(),
+    })?;
+    let backup = list(path)?.pop().context(error::NoBackup { path })?;
+    let data = fs::read(&backup).context(error::BackupRead { path: &backup })?;
+    let manifest: Manifest =
+        serde_json::from_slice(&data).context(error::BackupParse { path: &backup })?;
+
+    // Loading the manifest makes the write fail if it changes in the meantime; if it can't be
+    // loaded, it's replaced all the same, since that's likely what's being undone.
+    if let Err(e) = file.load() {
+        warn!("Replacing {}, which can't be loaded: {}", file, e);
+    }
+    // What's undone isn't backed up, or the next undo would restore it.
+    file.set_backups(0);
+    file.write(&manifest)?;
+    if !file.is_dry_run() {
+        fs::remove_file(&backup).context(error::BackupRemove { path: &backup })?;
+    }
+    Ok(backup)
+}
+
+/// Backup names are the manifest's name followed by `.bak.`.
+fn prefix(path: &Path) -> OsString {
+    let mut prefix = path.file_name().map(OsString::from).unwrap_or_default();
+    prefix.push(".bak.");
+    prefix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use semver::Version;
+    use tempfile::TempDir;
+
+    #[test]
+    fn write_and_undo() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("manifest.json");
+        fs::copy("tests/data/example_3.json", &path).unwrap();
+
+        // Each write saves what was there before it, keeping only the newest two.
+        let file = ManifestFile::local(&path);
+        file.set_backups(2);
+        let mut manifest = file.load().unwrap();
+        for max_version in &["9.0.0", "9.1.0", "9.2.0"] {
+            manifest.updates[0].max_version = Version::parse(max_version).unwrap();
+            file.write(&manifest).unwrap();
+        }
+        assert_eq!(list(&path).unwrap().len(), 2);
+
+        // Undoing steps back through the writes, until the backups run out.
+        let max_version = || {
+            update_metadata::load_file(&path).unwrap().updates[0]
+                .max_version
+                .to_string()
+        };
+        let file = ManifestFile::local(&path);
+        undo(&file).unwrap();
+        assert_eq!(max_version(), "9.1.0");
+        undo(&file).unwrap();
+        assert_eq!(max_version(), "9.0.0");
+        assert!(list(&path).unwrap().is_empty());
+        match undo(&file) {
+            Err(error::Error::NoBackup { .. }) => {}
+            other => panic!("expected no backup, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unreadable() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("manifest.json");
+        fs::write(&path, "not a manifest").unwrap();
+
+        // A manifest that can't be loaded is backed up as it was.
+        let file = ManifestFile::local(&path);
+        file.set_backups(1);
+        file.write(&Manifest::default()).unwrap();
+        let backups = list(&path).unwrap();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(fs::read(&backups[0]).unwrap(), b"not a manifest");
+
+        // Other files in the directory aren't backups.
+        fs::write(dir.path().join("manifest.json.old"), "").unwrap();
+        assert_eq!(list(&path).unwrap(), backups);
+    }
+}
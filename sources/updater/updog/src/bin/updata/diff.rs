@@ -0,0 +1,267 @@
+//! Compares two manifests, so what a release changes can be reviewed before it's published: the
+//! updates added and removed, changes to the waves and max version of the updates in both, and
+//! changes to the migrations between versions.  Updates are matched by variant, architecture, and
+//! version.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use semver::Version;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use update_metadata::{Manifest, Update};
+
+/// Names an update in the manifests.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub(crate) struct UpdateId {
+    variant: String,
+    arch: String,
+    version: Version,
+}
+
+impl UpdateId {
+    fn of(update: &Update) -> Self {
+        Self {
+            variant: update.variant.clone(),
+            arch: update.arch.clone(),
+            version: update.version.clone(),
+        }
+    }
+}
+
+impl fmt::Display for UpdateId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.variant, self.arch, self.version)
+    }
+}
+
+/// A value in the old manifest and what it is in the new one.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct Change<T> {
+    old: T,
+    new: T,
+}
+
+/// How an update in both manifests changed; fields that didn't change are left out.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct UpdateChange {
+    #[serde(flatten)]
+    update: UpdateId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_version: Option<Change<Version>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    waves: Option<Change<BTreeMap<u32, DateTime<Utc>>>>,
+}
+
+/// The migrations between two versions, in either manifest.  A migration entry that was added has
+/// no `old`, and one that was removed has no `new`.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct MigrationChange {
+    from: Version,
+    to: Version,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    new: Option<Vec<String>>,
+}
+
+/// What changed from one manifest to another.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub(crate) struct ManifestDiff {
+    added: Vec<UpdateId>,
+    removed: Vec<UpdateId>,
+    changed: Vec<UpdateChange>,
+    migrations: Vec<MigrationChange>,
+}
+
+impl ManifestDiff {
+    pub(crate) fn new(old: &Manifest, new: &Manifest) -> Self {
+        let old_updates = by_id(old);
+        let new_updates = by_id(new);
+
+        let mut diff = Self::default();
+        for (id, old_update) in &old_updates {
+            let new_update = if let Some(update) = new_updates.get(id) {
+                update
+            } else {
+                diff.removed.push(id.clone());
+                continue;
+            };
+            let change = UpdateChange {
+                update: id.clone(),
+                max_version: changed(&old_update.max_version, &new_update.max_version),
+                waves: changed(&old_update.waves, &new_update.waves),
+            };
+            if change.max_version.is_some() || change.waves.is_some() {
+                diff.changed.push(change);
+            }
+        }
+        diff.added = new_updates
+            .keys()
+            .filter(|id| !old_updates.contains_key(id))
+            .cloned()
+            .collect();
+
+        let versions: BTreeSet<_> = old.migrations.keys().chain(new.migrations.keys()).collect();
+        for (from, to) in versions {
+            let old = old.migrations.get(&(from.clone(), to.clone()));
+            let new = new.migrations.get(&(from.clone(), to.clone()));
+            if old != new {
+                diff.migrations.push(MigrationChange {
+                    from: from.clone(),
+                    to: to.clone(),
+                    old: old.cloned(),
+                    new: new.cloned(),
+                });
+            }
+        }
+        diff
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+fn by_id(manifest: &Manifest) -> BTreeMap<UpdateId, &Update> {
+    manifest
+        .updates
+        .iter()
+        .map(|update| (UpdateId::of(update), update))
+        .collect()
+}
+
+fn changed<T: Clone + PartialEq>(old: &T, new: &T) -> Option<Change<T>> {
+    if old == new {
+        None
+    } else {
+        Some(Change {
+            old: old.clone(),
+            new: new.clone(),
+        })
+    }
+}
+
+fn time(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Shows the diff a line per change, marked `+` for added, `-` for removed, and `~` for changed.
+impl fmt::Display for ManifestDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for id in &self.added {
+            writeln!(f, "+ update {}", id)?;
+        }
+        for id in &self.removed {
+            writeln!(f, "- update {}", id)?;
+        }
+        for change in &self.changed {
+            writeln!(f, "~ update {}", change.update)?;
+            if let Some(max) = &change.max_version {
+                writeln!(f, "    max version {} -> {}", max.old, max.new)?;
+            }
+            if let Some(waves) = &change.waves {
+                let bounds: BTreeSet<_> = waves.old.keys().chain(waves.new.keys()).collect();
+                for bound in bounds {
+                    match (waves.old.get(bound), waves.new.get(bound)) {
+                        (Some(old), Some(new)) if old != new => writeln!(
+                            f,
+                            "    wave to seed {}: {} -> {}",
+                            bound,
+                            time(old),
+                            time(new)
+                        )?,
+                        (Some(old), None) => {
+                            writeln!(f, "    wave to seed {} removed, was {}", bound, time(old))?;
+                        }
+                        (None, Some(new)) => {
+                            writeln!(f, "    wave to seed {} added at {}", bound, time(new))?;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        for change in &self.migrations {
+            let (mark, migrations) = match (&change.old, &change.new) {
+                (None, Some(new)) => ("+", new.join(", ")),
+                (Some(old), None) => ("-", old.join(", ")),
+                (Some(old), Some(new)) => {
+                    ("~", format!("{} -> {}", old.join(", "), new.join(", ")))
+                }
+                (None, None) => continue,
+            };
+            writeln!(
+                f,
+                "{} migrations {} to {}: {}",
+                mark, change.from, change.to, migrations
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn diff_manifests() {
+        let load = || update_metadata::load_file(Path::new("tests/data/multiple.json")).unwrap();
+        let old = load();
+        assert!(ManifestDiff::new(&old, &load()).is_empty());
+
+        let mut new = load();
+        let removed = new.updates.remove(0);
+        new.updates[0].version = Version::new(1, 26, 0);
+        new.updates[1].max_version = Version::new(1, 21, 0);
+        let waves = &mut new.updates[1].waves;
+        let first = waves[&0];
+        waves.insert(0, first + chrono::Duration::hours(1));
+        waves.remove(&858_993_459);
+        waves.insert(1_000_000_000, first + chrono::Duration::days(2));
+        new.migrations.insert(
+            (Version::new(1, 25, 0), Version::new(1, 26, 0)),
+            vec![String::from("migrate_1.26.0_new-setting")],
+        );
+
+        let diff = ManifestDiff::new(&old, &new);
+        assert_eq!(
+            diff.added
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec!["bottlerocket-aws-eks x86_64 1.26.0"]
+        );
+        assert_eq!(
+            diff.removed
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "bottlerocket-aws-eks x86_64 1.13.0",
+                "bottlerocket-aws-eks x86_64 1.25.0"
+            ]
+        );
+        assert_eq!(diff.removed[0], UpdateId::of(&removed));
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(
+            diff.to_string(),
+            "+ update bottlerocket-aws-eks x86_64 1.26.0\n\
+             - update bottlerocket-aws-eks x86_64 1.13.0\n\
+             - update bottlerocket-aws-eks x86_64 1.25.0\n\
+             ~ update bottlerocket-aws-eks x86_64 1.15.0\n\
+            \x20   max version 1.20.0 -> 1.21.0\n\
+            \x20   wave to seed 0: 2019-10-06T15:00:00Z -> 2019-10-06T16:00:00Z\n\
+            \x20   wave to seed 858993459 removed, was 2019-10-07T15:00:00Z\n\
+            \x20   wave to seed 1000000000 added at 2019-10-08T15:00:00Z\n\
+             + migrations 1.25.0 to 1.26.0: migrate_1.26.0_new-setting\n"
+        );
+
+        let json = serde_json::to_value(&diff).unwrap();
+        assert_eq!(json["added"][0]["version"], "1.26.0");
+        assert_eq!(json["changed"][0]["version"], "1.15.0");
+        assert_eq!(json["changed"][0]["max_version"]["new"], "1.21.0");
+        assert!(json["migrations"][0].get("old").is_none());
+    }
+}
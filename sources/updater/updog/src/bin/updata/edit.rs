@@ -0,0 +1,620 @@
+//! An interactive editor for manifests, for operators who only touch a manifest now and then and
+//! would rather be asked for each value than remember which flags go with which command.  Edits
+//! are made in memory, each one is checked the same way the matching updata command checks it,
+//! and nothing is written until the operator has seen the diff and confirmed it.
+
+use crate::error::{self, Result};
+use crate::store::ManifestFile;
+use semver::Version;
+use snafu::ResultExt;
+use std::cmp;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use update_metadata::{Images, Manifest, UpdateWaves};
+
+/// Lines of unchanged context shown around changes in a diff.
+const DIFF_CONTEXT: usize = 2;
+
+const HELP: &str = "\
+Commands:
+    list                    List the updates, numbered
+    show N                  Show update N's images, waves, and targeting
+    migrations              List the migrations between versions
+    add                     Add an update, asking for each value
+    remove N                Remove update N
+    max-version VERSION     Set the maximum version of every update
+    waves N WAVE-FILE       Set update N's waves from a wave file
+    pause N                 Pause the rollout of update N
+    resume N                Resume the paused rollout of update N
+    deprecate N             Stop offering update N to hosts, keeping it for hosts running it
+    reinstate N             Offer deprecated update N to hosts again
+    undo                    Undo the last edit
+    diff                    Show the changes that haven't been saved
+    save                    Show the changes, then write them if you confirm
+    quit                    Stop editing, asking first if there are unsaved changes";
+
+pub(crate) struct Editor<R, W> {
+    file: ManifestFile,
+    manifest: Manifest,
+    /// The manifest as it was last loaded or saved, to diff against.
+    saved: String,
+    /// The manifest before each edit, most recent last, for undo.
+    history: Vec<String>,
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Editor<R, W> {
+    pub(crate) fn new(file: ManifestFile, input: R, output: W) -> Result<Self> {
+        let manifest = file.load()?;
+        let saved = serialize(&manifest)?;
+        Ok(Self {
+            file,
+            manifest,
+            saved,
+            history: Vec::new(),
+            input,
+            output,
+        })
+    }
+
+    /// Reads and runs commands until the operator quits or the input ends.
+    pub(crate) fn run(&mut self) -> Result<()> {
+        self.say(&format!("Editing {}; type 'help' for commands", self.file))?;
+        while let Some(line) = self.prompt("updata> ")? {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => {}
+                ["help"] => self.say(HELP)?,
+                ["list"] => self.list()?,
+                ["show", n] => self.show(n)?,
+                ["migrations"] => self.migrations()?,
+                ["add"] => self.add()?,
+                ["remove", n] => self.remove(n)?,
+                ["max-version", version] => self.max_version(version)?,
+                ["waves", n, wave_file] => self.waves(n, Path::new(wave_file))?,
+                ["pause", n] => self.pause(n, true)?,
+                ["resume", n] => self.pause(n, false)?,
+                ["deprecate", n] => self.deprecate(n, true)?,
+                ["reinstate", n] => self.deprecate(n, false)?,
+                ["undo"] => self.undo()?,
+                ["diff"] => self.diff()?,
+                ["save"] => self.save()?,
+                ["quit"] => {
+                    if self.unsaved()? && !self.confirm("Quit without saving your changes?")? {
+                        continue;
+                    }
+                    return Ok(());
+                }
+                _ => self.say(&format!(
+                    "Unknown command '{}'; type 'help' for commands",
+                    line.trim()
+                ))?,
+            }
+        }
+        if self.unsaved()? {
+            self.say("Input ended; your changes weren't saved")?;
+        }
+        Ok(())
+    }
+
+    fn say(&mut self, message: &str) -> Result<()> {
+        writeln!(self.output, "{}", message).context(error::EditIo)
+    }
+
+    /// Asks for a line of input, returning None once the input ends.
+    fn prompt(&mut self, prompt: &str) -> Result<Option<String>> {
+        write!(self.output, "{}", prompt).context(error::EditIo)?;
+        self.output.flush().context(error::EditIo)?;
+        let mut line = String::new();
+        if self.input.read_line(&mut line).context(error::EditIo)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(line.trim().to_string()))
+    }
+
+    /// Asks a yes or no question; anything but yes is no.
+    fn confirm(&mut self, question: &str) -> Result<bool> {
+        let answer = self.prompt(&format!("{} [y/N] ", question))?;
+        Ok(answer.map_or(false, |a| a == "y" || a == "yes"))
+    }
+
+    /// Asks for a value, offering `default` if there is one, until one parses.
+    fn ask<T, F>(&mut self, name: &str, default: Option<&str>, parse: F) -> Result<Option<T>>
+    where
+        F: Fn(&str) -> std::result::Result<T, String>,
+    {
+        let prompt = match default {
+            Some(default) => format!("{} [{}]: ", name, default),
+            None => format!("{}: ", name),
+        };
+        loop {
+            let answer = match self.prompt(&prompt)? {
+                Some(answer) => answer,
+                None => return Ok(None),
+            };
+            let answer = match (answer.is_empty(), default) {
+                (true, Some(default)) => default.to_string(),
+                _ => answer,
+            };
+            match parse(&answer) {
+                Ok(value) => return Ok(Some(value)),
+                Err(e) => self.say(&format!("Invalid {}: {}", name, e))?,
+            }
+        }
+    }
+
+    fn unsaved(&self) -> Result<bool> {
+        Ok(serialize(&self.manifest)? != self.saved)
+    }
+
+    /// Finds update N, as numbered by `list`, telling the operator if there isn't one.
+    fn pick(&mut self, n: &str) -> Result<Option<usize>> {
+        match n.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= self.manifest.updates.len() => Ok(Some(n - 1)),
+            _ => {
+                self.say(&format!("No update {}; 'list' shows the updates", n))?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Makes an edit, keeping what the manifest was before for undo.  If the edit fails
+    /// validation, the manifest goes back to how it was.
+    fn edit<F>(&mut self, edit: F) -> Result<()>
+    where
+        F: FnOnce(&mut Manifest) -> update_metadata::error::Result<String>,
+    {
+        let before = serialize(&self.manifest)?;
+        match edit(&mut self.manifest) {
+            Ok(message) => {
+                self.history.push(before);
+                self.say(&message)
+            }
+            Err(e) => {
+                self.manifest = deserialize(&before)?;
+                self.say(&format!("Not changed: {}", e))
+            }
+        }
+    }
+
+    fn list(&mut self) -> Result<()> {
+        if self.manifest.updates.is_empty() {
+            return self.say("No updates; 'add' adds one");
+        }
+        let lines: Vec<String> = self
+            .manifest
+            .updates
+            .iter()
+            .enumerate()
+            .map(|(i, u)| {
+                format!(
+                    "{:>3}  {} {} {} (max {}, {} wave(s){}{})",
+                    i + 1,
+                    u.variant,
+                    u.arch,
+                    u.version,
+                    u.max_version,
+                    u.waves.len(),
+                    if u.paused.is_some() { ", paused" } else { "" },
+                    if u.available { "" } else { ", deprecated" }
+                )
+            })
+            .collect();
+        self.say(&lines.join("\n"))
+    }
+
+    fn show(&mut self, n: &str) -> Result<()> {
+        let i = match self.pick(n)? {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        let update = &self.manifest.updates[i];
+        let mut lines = vec![
+            format!("{} {} {}", update.variant, update.arch, update.version),
+            format!("  max version: {}", update.max_version),
+            format!("  boot image:  {}", update.images.boot),
+            format!("  root image:  {}", update.images.root),
+            format!("  hash image:  {}", update.images.hash),
+        ];
+        if update.waves.is_empty() {
+            lines.push("  no waves; every host can take it right away".to_string());
+        }
+        for (seed, start) in &update.waves {
+            lines.push(format!("  wave from seed {:>4}: {}", seed, start));
+        }
+        if let Some(paused) = update.paused {
+            lines.push(format!("  paused at {}", paused));
+        }
+        if !update.available {
+            lines.push("  deprecated; no host is offered it".to_string());
+        }
+        if let Some(targeting) = &update.targeting {
+            for range in &targeting.seeds {
+                lines.push(format!("  for seeds {}..{}", range.start, range.end));
+            }
+            for (key, value) in &targeting.labels {
+                lines.push(format!("  for hosts labeled {}={}", key, value));
+            }
+        }
+        self.say(&lines.join("\n"))
+    }
+
+    fn migrations(&mut self) -> Result<()> {
+        if self.manifest.migrations.is_empty() {
+            return self.say("No migrations");
+        }
+        let lines: Vec<String> = self
+            .manifest
+            .migrations
+            .iter()
+            .map(|((from, to), migrations)| {
+                if migrations.is_empty() {
+                    format!("{} -> {}: none needed", from, to)
+                } else {
+                    format!("{} -> {}: {}", from, to, migrations.join(", "))
+                }
+            })
+            .collect();
+        self.say(&lines.join("\n"))
+    }
+
+    fn add(&mut self) -> Result<()> {
+        let text = |s: &str| -> std::result::Result<String, String> {
+            if s.is_empty() {
+                Err("a value is needed".to_string())
+            } else {
+                Ok(s.to_string())
+            }
+        };
+        let version = |s: &str| Version::parse(s).map_err(|e| e.to_string());
+        // Offer the values of the last update, since a release usually adds several alike.
+        let last = self
+            .manifest
+            .updates
+            .last()
+            .map(|u| (u.variant.clone(), u.arch.clone()));
+        let (last_variant, last_arch) = match &last {
+            Some((variant, arch)) => (Some(variant.as_str()), Some(arch.as_str())),
+            None => (None, None),
+        };
+
+        macro_rules! ask {
+            ($name:expr, $default:expr, $parse:expr) => {
+                match self.ask($name, $default, $parse)? {
+                    Some(value) => value,
+                    None => return Ok(()),
+                }
+            };
+        }
+        let variant: String = ask!("variant", last_variant, text);
+        let arch: String = ask!("arch", last_arch, text);
+        let image_version: Version = ask!("version", None, version);
+        let max_version: String = ask!("max version, or 'default'", Some("default"), text);
+        let max_version = if max_version == "default" {
+            None
+        } else {
+            match Version::parse(&max_version) {
+                Ok(v) => Some(v),
+                Err(e) => return self.say(&format!("Invalid max version: {}", e)),
+            }
+        };
+        let image = |kind: &str| {
+            format!(
+                "bottlerocket-{}-{}-v{}-{}.lz4",
+                arch, variant, image_version, kind
+            )
+        };
+        let boot: String = ask!("boot image", Some(&image("boot.ext4")), text);
+        let root: String = ask!("root image", Some(&image("root.ext4")), text);
+        let hash: String = ask!("hash image", Some(&image("root.verity")), text);
+
+        if !self.confirm(&format!("Add {} {} {}?", variant, arch, image_version))? {
+            return self.say("Not added");
+        }
+        self.edit(|manifest| {
+            let message = format!("Added {} {} {}", variant, arch, image_version);
+            manifest.add_update(
+                image_version,
+                max_version,
+                arch,
+                variant,
+                Images { boot, root, hash },
+            )?;
+            Ok(message)
+        })
+    }
+
+    fn remove(&mut self, n: &str) -> Result<()> {
+        let i = match self.pick(n)? {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        let update = &self.manifest.updates[i];
+        let name = format!("{} {} {}", update.variant, update.arch, update.version);
+        if !self.confirm(&format!("Remove {}?", name))? {
+            return self.say("Not removed");
+        }
+        self.edit(|manifest| {
+            manifest.updates.remove(i);
+            Ok(format!("Removed {}", name))
+        })
+    }
+
+    fn max_version(&mut self, version: &str) -> Result<()> {
+        let version = match Version::parse(version) {
+            Ok(version) => version,
+            Err(e) => return self.say(&format!("Invalid version: {}", e)),
+        };
+        if !self.confirm(&format!(
+            "Set the maximum version of all {} update(s) to {}?",
+            self.manifest.updates.len(),
+            version
+        ))? {
+            return self.say("Not changed");
+        }
+        self.edit(|manifest| {
+            manifest.update_max_version(&version, None, None);
+            Ok(format!("Maximum version set to {}", version))
+        })
+    }
+
+    fn waves(&mut self, n: &str, wave_file: &Path) -> Result<()> {
+        let i = match self.pick(n)? {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        let waves = fs::read_to_string(wave_file)
+            .map_err(|e| e.to_string())
+            .and_then(|data| toml::from_str::<UpdateWaves>(&data).map_err(|e| e.to_string()));
+        let waves = match waves {
+            Ok(waves) => waves,
+            Err(e) => {
+                return self.say(&format!(
+                    "Failed to read wave file {}: {}",
+                    wave_file.display(),
+                    e
+                ))
+            }
+        };
+        let update = &self.manifest.updates[i];
+        let (variant, arch, version) = (
+            update.variant.clone(),
+            update.arch.clone(),
+            update.version.clone(),
+        );
+        self.edit(|manifest| {
+            manifest.set_waves(variant, arch, version, &waves)?;
+            Ok(format!(
+                "Set {} wave(s); 'show {}' shows them",
+                waves.waves.len(),
+                i + 1
+            ))
+        })
+    }
+
+    fn pause(&mut self, n: &str, pause: bool) -> Result<()> {
+        let i = match self.pick(n)? {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        let update = &self.manifest.updates[i];
+        let (variant, arch, version) = (
+            update.variant.clone(),
+            update.arch.clone(),
+            update.version.clone(),
+        );
+        let now = chrono::Utc::now();
+        self.edit(|manifest| {
+            let name = format!("{} {} {}", variant, arch, version);
+            if pause {
+                manifest.pause_rollout(variant, arch, version, now);
+                Ok(format!("Paused the rollout of {}", name))
+            } else {
+                manifest.resume_rollout(variant, arch, version, now)?;
+                Ok(format!("Resumed the rollout of {}", name))
+            }
+        })
+    }
+
+    fn deprecate(&mut self, n: &str, deprecate: bool) -> Result<()> {
+        let i = match self.pick(n)? {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        let update = &self.manifest.updates[i];
+        let (variant, arch, version) = (
+            update.variant.clone(),
+            update.arch.clone(),
+            update.version.clone(),
+        );
+        self.edit(|manifest| {
+            let name = format!("{} {} {}", variant, arch, version);
+            manifest.set_available(variant, arch, version, !deprecate);
+            if deprecate {
+                Ok(format!("Deprecated {}", name))
+            } else {
+                Ok(format!("Reinstated {}", name))
+            }
+        })
+    }
+
+    fn undo(&mut self) -> Result<()> {
+        match self.history.pop() {
+            Some(before) => {
+                self.manifest = deserialize(&before)?;
+                self.say("Undid the last edit")
+            }
+            None => self.say("Nothing to undo"),
+        }
+    }
+
+    fn diff(&mut self) -> Result<()> {
+        let diff = diff_lines(&self.saved, &serialize(&self.manifest)?);
+        if diff.is_empty() {
+            self.say("No unsaved changes")
+        } else {
+            self.say(&diff.join("\n"))
+        }
+    }
+
+    fn save(&mut self) -> Result<()> {
+        let current = serialize(&self.manifest)?;
+        let diff = diff_lines(&self.saved, &current);
+        if diff.is_empty() {
+            return self.say("No unsaved changes");
+        }
+        self.say(&diff.join("\n"))?;
+        if !self.confirm(&format!("Write these changes to {}?", self.file))? {
+            return self.say("Not saved");
+        }
+        self.file.write(&self.manifest)?;
+        self.saved = current;
+        self.say(&format!("Saved {}", self.file))
+    }
+}
+
+fn serialize(manifest: &Manifest) -> Result<String> {
+    serde_json::to_string_pretty(manifest).context(error::UpdateSerialize)
+}
+
+fn deserialize(data: &str) -> Result<Manifest> {
+    serde_json::from_str(data).context(error::ManifestParse)
+}
+
+/// Compares two texts line by line, returning the changed lines marked with "-" or "+", along
+/// with a little unchanged context around them.  Returns nothing if the texts are the same.
+fn diff_lines(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    // Edits are usually small, so only the part between the common start and end is compared.
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_changed = &old[prefix..old.len() - suffix];
+    let new_changed = &new[prefix..new.len() - suffix];
+    if old_changed.is_empty() && new_changed.is_empty() {
+        return Vec::new();
+    }
+
+    // lcs[i][j] is the length of the longest common subsequence of old_changed[i..] and
+    // new_changed[j..].
+    let mut lcs = vec![vec![0_usize; new_changed.len() + 1]; old_changed.len() + 1];
+    for i in (0..old_changed.len()).rev() {
+        for j in (0..new_changed.len()).rev() {
+            lcs[i][j] = if old_changed[i] == new_changed[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff: Vec<String> = old[prefix.saturating_sub(DIFF_CONTEXT)..prefix]
+        .iter()
+        .map(|line| format!("  {}", line))
+        .collect();
+    let (mut i, mut j) = (0, 0);
+    while i < old_changed.len() || j < new_changed.len() {
+        if i < old_changed.len() && j < new_changed.len() && old_changed[i] == new_changed[j] {
+            diff.push(format!("  {}", old_changed[i]));
+            i += 1;
+            j += 1;
+        } else if i < old_changed.len()
+            && (j == new_changed.len() || lcs[i + 1][j] >= lcs[i][j + 1])
+        {
+            diff.push(format!("- {}", old_changed[i]));
+            i += 1;
+        } else {
+            diff.push(format!("+ {}", new_changed[j]));
+            j += 1;
+        }
+    }
+    let after = old.len() - suffix;
+    diff.extend(
+        old[after..cmp::min(after + DIFF_CONTEXT, old.len())]
+            .iter()
+            .map(|line| format!("  {}", line)),
+    );
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    /// Runs an editing session on a copy of `example_3.json` with the given input, returning the
+    /// path to the copy and what the editor printed.
+    fn session(input: &str) -> (TempDir, PathBuf, String) {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("manifest.json");
+        fs::copy("tests/data/example_3.json", &path).unwrap();
+        let mut output = Vec::new();
+        Editor::new(
+            ManifestFile::local(path.clone()),
+            Cursor::new(input),
+            &mut output,
+        )
+        .unwrap()
+        .run()
+        .unwrap();
+        (dir, path, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn remove_and_save() {
+        let (_dir, path, output) = session("list\nremove 1\ny\nsave\ny\nquit\n");
+        assert!(output.contains("  1  aws-k8s-1.15 x86_64 0.1.1"));
+        assert!(output.contains("- "));
+        let manifest = update_metadata::load_file(&path).unwrap();
+        assert_eq!(manifest.updates.len(), 1);
+        assert_eq!(
+            manifest.updates[0].version,
+            Version::parse("0.1.2").unwrap()
+        );
+    }
+
+    #[test]
+    fn guarded_edits() {
+        // Declining to remove, undoing, and quitting without saving all leave the file alone.
+        let before = fs::read_to_string("tests/data/example_3.json").unwrap();
+        let (_dir, path, output) =
+            session("remove 1\nn\nremove 2\ny\nundo\nremove 5\nremove 1\ny\nquit\ny\n");
+        assert!(output.contains("Not removed"));
+        assert!(output.contains("Undid the last edit"));
+        assert!(output.contains("No update 5"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), before);
+    }
+
+    #[test]
+    fn add_with_prompts() {
+        // Blank answers take the defaults, and an invalid version is asked for again.
+        let (_dir, path, output) = session("add\n\n\nnope\n0.1.3\n\n\n\n\ny\nsave\ny\n");
+        assert!(output.contains("Invalid version"));
+        let manifest = update_metadata::load_file(&path).unwrap();
+        let update = manifest.updates.last().unwrap();
+        assert_eq!(update.version, Version::parse("0.1.3").unwrap());
+        assert_eq!(update.variant, "aws-k8s-1.15");
+        assert_eq!(
+            update.images.root,
+            "bottlerocket-x86_64-aws-k8s-1.15-v0.1.3-root.ext4.lz4"
+        );
+    }
+
+    #[test]
+    fn diff() {
+        assert!(diff_lines("a\nb\n", "a\nb\n").is_empty());
+        assert_eq!(
+            diff_lines("a\nb\nc\nd\ne\nf\n", "a\nb\nc\nX\ne\nf\n"),
+            vec!["  b", "  c", "- d", "+ X", "  e", "  f"]
+        );
+        assert_eq!(diff_lines("a\n", "a\nb\n"), vec!["  a", "+ b"]);
+    }
+}
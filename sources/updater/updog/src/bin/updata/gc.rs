@@ -0,0 +1,170 @@
+//! Finds target files in a repository that nothing refers to anymore, so a self-hosted repository
+//! doesn't keep every image it has ever published.  A target is still in use if the signed
+//! targets metadata lists it, or if the repository's manifest, or one of its shards, names it as
+//! an image, a migration, or a shard.  Files changed recently are kept either way, since they may
+//! be for a release that's been copied in but not signed yet.
+
+use crate::error::{self, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use snafu::ResultExt;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The parts of targets.json we need.
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    signed: Signed,
+}
+
+#[derive(Debug, Deserialize)]
+struct Signed {
+    #[serde(default)]
+    targets: HashMap<String, serde_json::Value>,
+}
+
+/// A file in the repository's targets directory that nothing refers to.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Unreferenced {
+    pub(crate) path: PathBuf,
+    pub(crate) len: u64,
+}
+
+/// Lists the files in `repo`'s targets directory that aren't referenced, and weren't modified
+/// within `grace` of `now`.
+pub(crate) fn unreferenced_targets(
+    repo: &Path,
+    grace: Duration,
+    now: DateTime<Utc>,
+) -> Result<Vec<Unreferenced>> {
+    let referenced = referenced_targets(repo)?;
+    let dir = repo.join("targets");
+    let entries = fs::read_dir(&dir).context(error::TargetsDirRead { path: &dir })?;
+    let mut unreferenced = Vec::new();
+    for entry in entries {
+        let entry = entry.context(error::TargetsDirRead { path: &dir })?;
+        let path = entry.path();
+        let metadata = entry
+            .metadata()
+            .context(error::TargetsDirRead { path: &path })?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if referenced.contains(&name) || referenced.contains(unprefixed(&name)) {
+            continue;
+        }
+        // A file whose age can't be told is kept, like a recent one.
+        let recent = metadata.modified().map_or(true, |modified| {
+            DateTime::<Utc>::from(modified) > now - grace
+        });
+        if recent {
+            debug!("Keeping unreferenced target {}, changed recently", name);
+            continue;
+        }
+        unreferenced.push(Unreferenced {
+            path,
+            len: metadata.len(),
+        });
+    }
+    unreferenced.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(unreferenced)
+}
+
+/// Returns the names of the targets that the targets metadata or the manifest refers to.
+fn referenced_targets(repo: &Path) -> Result<HashSet<String>> {
+    let path = repo.join("metadata/targets.json");
+    let data = fs::read(&path).context(error::RepoMetadataRead { path: &path })?;
+    let metadata: Metadata =
+        serde_json::from_slice(&data).context(error::RepoMetadataParse { path: &path })?;
+    let mut referenced: HashSet<String> = metadata.signed.targets.keys().cloned().collect();
+
+    let manifest_path = repo.join("targets/manifest.json");
+    if manifest_path.exists() {
+        let manifest = update_metadata::load_file(&manifest_path)?;
+        referenced.insert("manifest.json".to_string());
+        referenced.extend(manifest.shards.values().cloned());
+        for update in &manifest.updates {
+            referenced.insert(update.images.boot.clone());
+            referenced.insert(update.images.root.clone());
+            referenced.insert(update.images.hash.clone());
+        }
+        referenced.extend(manifest.migrations.values().flatten().cloned());
+    }
+    Ok(referenced)
+}
+
+/// With consistent snapshots, target files are stored with the hex SHA-256 digest of their
+/// contents in front of their names, like `<digest>.manifest.json`; this returns the name without
+/// it.
+fn unprefixed(name: &str) -> &str {
+    match name.find('.') {
+        Some(64) if name[..64].chars().all(|c| c.is_ascii_hexdigit()) => &name[65..],
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[test]
+    fn finds_unreferenced() {
+        let repo = TempDir::new().unwrap();
+        fs::create_dir_all(repo.path().join("metadata")).unwrap();
+        fs::create_dir_all(repo.path().join("targets")).unwrap();
+        let targets = json!({"signed": {"targets": {"signed-only.img": {}}}});
+        fs::write(
+            repo.path().join("metadata/targets.json"),
+            targets.to_string(),
+        )
+        .unwrap();
+        fs::copy(
+            "tests/data/example_3.json",
+            repo.path().join("targets/manifest.json"),
+        )
+        .unwrap();
+        let manifest =
+            update_metadata::load_file(&repo.path().join("targets/manifest.json")).unwrap();
+        let image = manifest.updates[0].images.root.clone();
+        for name in &[
+            "signed-only.img",
+            image.as_str(),
+            &format!("{}.{}", "ab".repeat(32), image),
+            "old.img",
+        ] {
+            fs::write(repo.path().join("targets").join(name), "data").unwrap();
+        }
+
+        // Everything is too new to collect at first.
+        let now = Utc::now();
+        let grace = Duration::days(7);
+        assert_eq!(
+            unreferenced_targets(repo.path(), grace, now).unwrap(),
+            vec![]
+        );
+
+        let later = now + Duration::days(8);
+        assert_eq!(
+            unreferenced_targets(repo.path(), grace, later).unwrap(),
+            vec![Unreferenced {
+                path: repo.path().join("targets/old.img"),
+                len: 4,
+            }]
+        );
+    }
+
+    #[test]
+    fn digest_prefix() {
+        let digest = "0123456789abcdef".repeat(4);
+        assert_eq!(
+            unprefixed(&format!("{}.manifest.json", digest)),
+            "manifest.json"
+        );
+        assert_eq!(unprefixed("manifest.json"), "manifest.json");
+        assert_eq!(unprefixed("not-a-digest.img"), "not-a-digest.img");
+    }
+}
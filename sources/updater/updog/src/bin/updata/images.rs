@@ -0,0 +1,138 @@
+//! Finds the images a build wrote for a version, so a release can add an update for each variant
+//! and architecture that was built.  Each image is named the way the build names it, like
+//! `bottlerocket-aws-k8s-1.15-x86_64-1.5.0-a1b2c3d4-root.ext4.lz4`: the variant, architecture,
+//! version, and build ID, then the kind of image.  The file names are used as the target names.
+
+use crate::error::{self, Result};
+use semver::Version;
+use snafu::{ensure, ResultExt};
+use std::fs;
+use std::path::Path;
+use update_metadata::Images;
+
+const PREFIX: &str = "bottlerocket-";
+const ROOT: &str = "-root.ext4.lz4";
+const BOOT: &str = "-boot.ext4.lz4";
+const HASH: &str = "-root.verity.lz4";
+
+/// The images of one variant and architecture.
+#[derive(Debug)]
+pub(crate) struct Built {
+    pub(crate) variant: String,
+    pub(crate) arch: String,
+    pub(crate) images: Images,
+}
+
+/// Finds the images for `version` in `dir`, sorted by variant and architecture.  Each root image
+/// needs its boot and hash images next to it.
+pub(crate) fn find(dir: &Path, version: &Version) -> Result<Vec<Built>> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir).context(error::BuildDirRead { path: dir })? {
+        let entry = entry.context(error::BuildDirRead { path: dir })?;
+        let name = entry.file_name();
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => continue,
+        };
+        let (variant, arch, image_version, stem) = match parse(name) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+        if image_version != *version {
+            continue;
+        }
+        let sibling = |suffix: &str| -> Result<String> {
+            let image = format!("{}{}", stem, suffix);
+            let path = dir.join(&image);
+            ensure!(path.is_file(), error::BuildImageMissing { path });
+            Ok(image)
+        };
+        found.push(Built {
+            variant: variant.to_string(),
+            arch: arch.to_string(),
+            images: Images {
+                root: name.to_string(),
+                boot: sibling(BOOT)?,
+                hash: sibling(HASH)?,
+            },
+        });
+    }
+    ensure!(
+        !found.is_empty(),
+        error::BuildImagesMissing {
+            path: dir,
+            version: version.clone(),
+        }
+    );
+    found.sort_by(|a, b| (&a.variant, &a.arch).cmp(&(&b.variant, &b.arch)));
+    Ok(found)
+}
+
+/// Reads the variant, architecture, and version from the name of a root image, along with the
+/// part of the name its other images share.  Variants can have dashes in them, but the rest
+/// can't, so the name is split from the end.
+fn parse(name: &str) -> Option<(&str, &str, Version, &str)> {
+    if !name.starts_with(PREFIX) || !name.ends_with(ROOT) || name.len() < PREFIX.len() + ROOT.len()
+    {
+        return None;
+    }
+    let stem = &name[..name.len() - ROOT.len()];
+    let rest = &stem[PREFIX.len()..];
+    let mut parts = rest.rsplitn(4, '-');
+    let _build = parts.next()?;
+    let version = parts.next()?.parse().ok()?;
+    let arch = parts.next()?;
+    let variant = parts.next()?;
+    Some((variant, arch, version, stem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_names() {
+        let (variant, arch, version, stem) =
+            parse("bottlerocket-aws-k8s-1.15-x86_64-1.5.0-a1b2c3d4-root.ext4.lz4").unwrap();
+        assert_eq!(variant, "aws-k8s-1.15");
+        assert_eq!(arch, "x86_64");
+        assert_eq!(version, Version::new(1, 5, 0));
+        assert_eq!(stem, "bottlerocket-aws-k8s-1.15-x86_64-1.5.0-a1b2c3d4");
+
+        assert!(parse("bottlerocket-aws-k8s-1.15-x86_64-1.5.0-a1b2c3d4-boot.ext4.lz4").is_none());
+        assert!(parse("bottlerocket-aws-k8s-1.15-x86_64-latest-root.ext4.lz4").is_none());
+        assert!(parse("bottlerocket-x86_64-root.ext4.lz4").is_none());
+    }
+
+    #[test]
+    fn find_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let touch = |name: &str| fs::write(dir.path().join(name), "").unwrap();
+        for (variant, arch) in &[("aws-k8s-1.15", "x86_64"), ("aws-dev", "aarch64")] {
+            for kind in &["root.ext4.lz4", "boot.ext4.lz4", "root.verity.lz4"] {
+                touch(&format!(
+                    "bottlerocket-{}-{}-1.5.0-abc-{}",
+                    variant, arch, kind
+                ));
+            }
+        }
+        touch("bottlerocket-aws-dev-aarch64-1.4.0-abc-root.ext4.lz4");
+        touch("bottlerocket-aws-dev-aarch64-1.5.0-abc.img.lz4");
+
+        let found = find(dir.path(), &Version::new(1, 5, 0)).unwrap();
+        let names: Vec<_> = found
+            .iter()
+            .map(|b| format!("{} {}", b.variant, b.arch))
+            .collect();
+        assert_eq!(names, vec!["aws-dev aarch64", "aws-k8s-1.15 x86_64"]);
+        assert_eq!(
+            found[1].images.hash,
+            "bottlerocket-aws-k8s-1.15-x86_64-1.5.0-abc-root.verity.lz4"
+        );
+        assert!(find(dir.path(), &Version::new(1, 6, 0)).is_err());
+
+        // A root image without the others is an error, rather than an update left out.
+        touch("bottlerocket-aws-ecs-1-x86_64-1.5.0-abc-root.ext4.lz4");
+        assert!(find(dir.path(), &Version::new(1, 5, 0)).is_err());
+    }
+}
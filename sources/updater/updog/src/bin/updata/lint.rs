@@ -0,0 +1,688 @@
+//! Lint rules for releases.  `validate` only checks that a manifest can be loaded; these rules
+//! catch releases that are valid but probably not what the operator meant, like waves that never
+//! reach the whole fleet.  Each rule's severity, and any options it has, can be set in a TOML
+//! config file:
+//!
+//! ```toml
+//! [min-wave-span]
+//! severity = "error"
+//! hours = 48
+//!
+//! [release-notes]
+//! severity = "off"
+//! ```
+//!
+//! Rules left out of the config file keep their defaults.
+
+use crate::error::{self, Result};
+use chrono::{DateTime, Duration, Utc};
+use parse_datetime::parse_datetime_at;
+use semver::Version;
+use serde::Deserialize;
+use snafu::ResultExt;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use update_metadata::{Manifest, Release, Update, UpdateWaves};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Severity {
+    Off,
+    Warn,
+    Error,
+}
+
+/// The settings for each rule.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Config {
+    last_wave_full: LastWaveFull,
+    waves_increase: WavesIncrease,
+    min_wave_span: MinWaveSpan,
+    release_notes: ReleaseNotes,
+    shared_images: SharedImages,
+}
+
+impl Config {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let data = fs::read_to_string(path).context(error::ConfigRead { path })?;
+        toml::from_str(&data).context(error::ConfigParse { path })
+    }
+
+    fn rules(&self) -> [&dyn Rule; 5] {
+        [
+            &self.last_wave_full,
+            &self.waves_increase,
+            &self.min_wave_span,
+            &self.release_notes,
+            &self.shared_images,
+        ]
+    }
+}
+
+/// A wave from a wave file, with its start time resolved.
+#[derive(Debug)]
+struct PlannedWave {
+    start: DateTime<Utc>,
+    fleet_percentage: u32,
+}
+
+#[derive(Debug)]
+struct WaveFile {
+    path: PathBuf,
+    waves: Vec<PlannedWave>,
+}
+
+/// The files that make up a release.  Each rule checks whichever of them it was given.
+#[derive(Debug, Default)]
+pub(crate) struct ReleaseFiles {
+    /// The version being released, from the release file, or else the newest in the manifest.
+    version: Option<Version>,
+    manifest: Option<Manifest>,
+    wave_files: Vec<WaveFile>,
+    changelog: Option<(PathBuf, String)>,
+}
+
+impl ReleaseFiles {
+    pub(crate) fn load(
+        manifest: Option<&Path>,
+        release: Option<&Path>,
+        wave_files: &[PathBuf],
+        changelog: Option<&Path>,
+    ) -> Result<Self> {
+        let manifest = match manifest {
+            Some(path) => Some(update_metadata::load_file(path)?),
+            None => None,
+        };
+
+        let version = match release {
+            Some(path) => {
+                let data = fs::read_to_string(path).context(error::ConfigRead { path })?;
+                let release: Release =
+                    toml::from_str(&data).context(error::ReleaseParse { path })?;
+                Some(release.version)
+            }
+            None => manifest
+                .as_ref()
+                .and_then(|m| m.updates.iter().map(|u| &u.version).max().cloned()),
+        };
+        Self::new(manifest, version, wave_files, changelog)
+    }
+
+    /// Gathers a release from a manifest that's already loaded, like one about to be written.
+    pub(crate) fn new(
+        manifest: Option<Manifest>,
+        version: Option<Version>,
+        wave_files: &[PathBuf],
+        changelog: Option<&Path>,
+    ) -> Result<Self> {
+        let now = Utc::now();
+        let mut files = Vec::new();
+        for path in wave_files {
+            let data = fs::read_to_string(path).context(error::ConfigRead { path })?;
+            let waves: UpdateWaves = toml::from_str(&data).context(error::ConfigParse { path })?;
+            let mut planned = Vec::new();
+            for wave in waves.waves {
+                let start =
+                    parse_datetime_at(&wave.start_after, now).context(error::WaveStart {
+                        path,
+                        start_after: &wave.start_after,
+                    })?;
+                planned.push(PlannedWave {
+                    start,
+                    fleet_percentage: wave.fleet_percentage,
+                });
+            }
+            files.push(WaveFile {
+                path: path.clone(),
+                waves: planned,
+            });
+        }
+
+        let changelog = match changelog {
+            Some(path) => {
+                let data = fs::read_to_string(path).context(error::ChangelogRead { path })?;
+                Some((path.to_path_buf(), data))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            version,
+            manifest,
+            wave_files: files,
+            changelog,
+        })
+    }
+
+    /// Takes back the manifest, or an empty one if there wasn't one.
+    pub(crate) fn into_manifest(self) -> Manifest {
+        self.manifest.unwrap_or_default()
+    }
+
+    /// The updates in the manifest for the version being released.
+    fn new_updates(&self) -> impl Iterator<Item = &Update> {
+        let version = self.version.as_ref();
+        self.manifest
+            .iter()
+            .flat_map(|m| m.updates.iter())
+            .filter(move |u| Some(&u.version) == version)
+    }
+}
+
+/// Something a rule found wrong with a release.
+#[derive(Debug)]
+pub(crate) struct Finding {
+    pub(crate) rule: &'static str,
+    pub(crate) severity: Severity,
+    pub(crate) message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.rule, self.message)
+    }
+}
+
+trait Rule {
+    /// The rule's name, as used in the config file.
+    fn name(&self) -> &'static str;
+    fn severity(&self) -> Severity;
+    /// Returns a message for each problem the rule finds.
+    fn check(&self, files: &ReleaseFiles) -> Vec<String>;
+}
+
+/// Runs each rule that isn't turned off against the release.
+pub(crate) fn lint(config: &Config, files: &ReleaseFiles) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for rule in &config.rules() {
+        if rule.severity() == Severity::Off {
+            continue;
+        }
+        for message in rule.check(files) {
+            findings.push(Finding {
+                rule: rule.name(),
+                severity: rule.severity(),
+                message,
+            });
+        }
+    }
+    findings
+}
+
+/// The last wave in each wave file must reach the whole fleet; otherwise the rest of the fleet
+/// updates whenever the last wave starts, which is rarely what was meant.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LastWaveFull {
+    severity: Severity,
+}
+
+impl Default for LastWaveFull {
+    fn default() -> Self {
+        Self {
+            severity: Severity::Error,
+        }
+    }
+}
+
+impl Rule for LastWaveFull {
+    fn name(&self) -> &'static str {
+        "last-wave-full"
+    }
+
+    fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    fn check(&self, files: &ReleaseFiles) -> Vec<String> {
+        let mut messages = Vec::new();
+        for file in &files.wave_files {
+            match file.waves.last() {
+                None => messages.push(format!("{} has no waves", file.path.display())),
+                Some(wave) if wave.fleet_percentage < 100 => messages.push(format!(
+                    "the last wave in {} only reaches {}% of the fleet",
+                    file.path.display(),
+                    wave.fleet_percentage
+                )),
+                Some(_) => {}
+            }
+        }
+        messages
+    }
+}
+
+/// Each wave in a wave file must start later, and reach more of the fleet, than the one before.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct WavesIncrease {
+    severity: Severity,
+}
+
+impl Default for WavesIncrease {
+    fn default() -> Self {
+        Self {
+            severity: Severity::Error,
+        }
+    }
+}
+
+impl Rule for WavesIncrease {
+    fn name(&self) -> &'static str {
+        "waves-increase"
+    }
+
+    fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    fn check(&self, files: &ReleaseFiles) -> Vec<String> {
+        let mut messages = Vec::new();
+        for file in &files.wave_files {
+            for (i, pair) in file.waves.windows(2).enumerate() {
+                // Waves are numbered from 1 for people.
+                let num = i + 2;
+                if pair[1].start <= pair[0].start {
+                    messages.push(format!(
+                        "wave {} in {} doesn't start after wave {}",
+                        num,
+                        file.path.display(),
+                        num - 1
+                    ));
+                }
+                if pair[1].fleet_percentage <= pair[0].fleet_percentage {
+                    messages.push(format!(
+                        "wave {} in {} doesn't reach more of the fleet than wave {}",
+                        num,
+                        file.path.display(),
+                        num - 1
+                    ));
+                }
+            }
+        }
+        messages
+    }
+}
+
+/// The waves for a release must take at least `hours` from the first to the last, so there's time
+/// to notice problems before the whole fleet has updated.  Checked for each wave file, and for the
+/// new version's updates in the manifest.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct MinWaveSpan {
+    severity: Severity,
+    hours: u32,
+}
+
+impl Default for MinWaveSpan {
+    fn default() -> Self {
+        Self {
+            severity: Severity::Warn,
+            hours: 24,
+        }
+    }
+}
+
+impl MinWaveSpan {
+    /// Returns a message if the given start times span less than the minimum.
+    fn check_span<'a, I>(&self, what: &str, starts: I) -> Option<String>
+    where
+        I: Iterator<Item = &'a DateTime<Utc>> + Clone,
+    {
+        let first = starts.clone().min()?;
+        let last = starts.max()?;
+        let span = *last - *first;
+        if span < Duration::hours(i64::from(self.hours)) {
+            Some(format!(
+                "waves in {} span {} hours, less than {}",
+                what,
+                span.num_hours(),
+                self.hours
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+impl Rule for MinWaveSpan {
+    fn name(&self) -> &'static str {
+        "min-wave-span"
+    }
+
+    fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    fn check(&self, files: &ReleaseFiles) -> Vec<String> {
+        let mut messages = Vec::new();
+        for file in &files.wave_files {
+            let what = file.path.display().to_string();
+            messages.extend(self.check_span(&what, file.waves.iter().map(|w| &w.start)));
+        }
+        for update in files.new_updates() {
+            let what = format!(
+                "the manifest for {} {} {}",
+                update.variant, update.arch, update.version
+            );
+            messages.extend(self.check_span(&what, update.waves.values()));
+        }
+        messages
+    }
+}
+
+/// The changelog must have a section for the version being released, with a heading like
+/// "# v0.3.3".
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ReleaseNotes {
+    severity: Severity,
+}
+
+impl Default for ReleaseNotes {
+    fn default() -> Self {
+        Self {
+            severity: Severity::Error,
+        }
+    }
+}
+
+impl Rule for ReleaseNotes {
+    fn name(&self) -> &'static str {
+        "release-notes"
+    }
+
+    fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    fn check(&self, files: &ReleaseFiles) -> Vec<String> {
+        let version = match &files.version {
+            Some(version) => version,
+            None => return Vec::new(),
+        };
+        let (path, changelog) = match &files.changelog {
+            Some(changelog) => changelog,
+            None => {
+                return vec![format!(
+                    "no changelog given to check for notes on {}",
+                    version
+                )]
+            }
+        };
+
+        let heading = format!("# v{}", version);
+        let has_notes = changelog.lines().any(|line| {
+            line == heading
+                || (line.starts_with(&heading) && line[heading.len()..].starts_with(' '))
+        });
+        if has_notes {
+            Vec::new()
+        } else {
+            vec![format!("{} has no '{}' section", path.display(), heading)]
+        }
+    }
+}
+
+/// Updates that share image targets must have the same version and max version.  Variants can
+/// share images, but the version is also the datastore version the update brings, so two
+/// updates with the same images and different versions almost always means one was copied from
+/// the other and only partly edited.  Checks every update in the manifest, not just the new
+/// version's, since the update copied from is usually an old one.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SharedImages {
+    severity: Severity,
+}
+
+impl Default for SharedImages {
+    fn default() -> Self {
+        Self {
+            severity: Severity::Error,
+        }
+    }
+}
+
+impl Rule for SharedImages {
+    fn name(&self) -> &'static str {
+        "shared-images"
+    }
+
+    fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    fn check(&self, files: &ReleaseFiles) -> Vec<String> {
+        fn images(u: &Update) -> [&str; 3] {
+            [&u.images.boot, &u.images.root, &u.images.hash]
+        }
+
+        let updates = match &files.manifest {
+            Some(manifest) => &manifest.updates,
+            None => return Vec::new(),
+        };
+        let name = |u: &Update| format!("{} {} {}", u.variant, u.arch, u.version);
+
+        let mut messages = Vec::new();
+        for (i, first) in updates.iter().enumerate() {
+            let first_images = images(first);
+            for second in &updates[i + 1..] {
+                let shared = match images(second)
+                    .iter()
+                    .find(|image| first_images.contains(image))
+                {
+                    Some(image) => *image,
+                    None => continue,
+                };
+                if first.version != second.version {
+                    messages.push(format!(
+                        "{} and {} share image {} but have different versions",
+                        name(first),
+                        name(second),
+                        shared
+                    ));
+                } else if first.max_version != second.max_version {
+                    messages.push(format!(
+                        "{} and {} share image {} but have different max versions, {} and {}",
+                        name(first),
+                        name(second),
+                        shared,
+                        first.max_version,
+                        second.max_version
+                    ));
+                }
+            }
+        }
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wave_file(waves: &[(i64, u32)]) -> WaveFile {
+        let now = Utc::now();
+        WaveFile {
+            path: PathBuf::from("waves.toml"),
+            waves: waves
+                .iter()
+                .map(|&(hours, fleet_percentage)| PlannedWave {
+                    start: now + Duration::hours(hours),
+                    fleet_percentage,
+                })
+                .collect(),
+        }
+    }
+
+    fn rules_found(findings: &[Finding]) -> Vec<&'static str> {
+        findings.iter().map(|f| f.rule).collect()
+    }
+
+    #[test]
+    fn shipped_wave_files() {
+        let files = ReleaseFiles::load(
+            None,
+            None,
+            &[
+                PathBuf::from("../waves/default-waves.toml"),
+                PathBuf::from("../waves/accelerated-waves.toml"),
+                PathBuf::from("../waves/ohno.toml"),
+            ],
+            None,
+        )
+        .unwrap();
+        let findings = lint(&Config::default(), &files);
+        // The accelerated and emergency waves are meant to be quicker than a day.
+        assert_eq!(
+            rules_found(&findings),
+            vec!["min-wave-span", "min-wave-span"]
+        );
+        assert!(findings.iter().all(|f| f.severity == Severity::Warn));
+        assert!(findings[0].message.contains("accelerated-waves.toml"));
+        assert!(findings[1].message.contains("ohno.toml"));
+    }
+
+    #[test]
+    fn bad_waves() {
+        let files = ReleaseFiles {
+            wave_files: vec![wave_file(&[(1, 10), (1, 5), (2, 50)])],
+            ..ReleaseFiles::default()
+        };
+        let findings = lint(&Config::default(), &files);
+        assert_eq!(
+            rules_found(&findings),
+            vec![
+                "last-wave-full",
+                "waves-increase",
+                "waves-increase",
+                "min-wave-span"
+            ]
+        );
+    }
+
+    #[test]
+    fn configured_severity() {
+        let config: Config = toml::from_str(
+            r#"
+            [last-wave-full]
+            severity = "warn"
+
+            [waves-increase]
+            severity = "off"
+
+            [min-wave-span]
+            hours = 1
+            "#,
+        )
+        .unwrap();
+        let files = ReleaseFiles {
+            wave_files: vec![wave_file(&[(1, 10), (1, 5), (2, 50)])],
+            ..ReleaseFiles::default()
+        };
+        let findings = lint(&config, &files);
+        assert_eq!(rules_found(&findings), vec!["last-wave-full"]);
+        assert_eq!(findings[0].severity, Severity::Warn);
+
+        assert!(toml::from_str::<Config>("[no-such-rule]\nseverity = \"warn\"").is_err());
+        assert!(toml::from_str::<Config>("[last-wave-full]\nhours = 1").is_err());
+    }
+
+    #[test]
+    fn manifest_waves() {
+        // The waves for both updates here take an hour or less, but only the newest is checked.
+        let files = ReleaseFiles::load(
+            Some(Path::new("tests/data/example_3.json")),
+            None,
+            &[],
+            None,
+        )
+        .unwrap();
+        let findings = lint(&Config::default(), &files);
+        assert_eq!(
+            rules_found(&findings),
+            vec!["min-wave-span", "release-notes"]
+        );
+        assert!(findings[0].message.contains("aws-k8s-1.15 x86_64 0.1.2"));
+    }
+
+    #[test]
+    fn shared_images() {
+        // Each update's images are named for the build they came from.
+        let update = |variant: &str, version: &str, max_version: &str, build: &str| {
+            serde_json::json!({
+                "variant": variant,
+                "arch": "x86_64",
+                "version": version,
+                "max_version": max_version,
+                "waves": {},
+                "images": {
+                    "boot": format!("boot-{}.ext4.lz4", build),
+                    "root": format!("root-{}.ext4.lz4", build),
+                    "hash": format!("root-{}.verity.lz4", build),
+                },
+            })
+        };
+        let manifest = |updates: Vec<serde_json::Value>| {
+            let manifest: Manifest =
+                serde_json::from_value(serde_json::json!({"updates": updates, "migrations": {}}))
+                    .unwrap();
+            ReleaseFiles {
+                manifest: Some(manifest),
+                ..ReleaseFiles::default()
+            }
+        };
+        let config = Config::default();
+
+        // Variants can share images if everything else about the updates matches.
+        let files = manifest(vec![
+            update("aws-k8s-1.15", "0.3.3", "0.3.3", "0.3.3"),
+            update("aws-k8s-1.16", "0.3.3", "0.3.3", "0.3.3"),
+            update("aws-k8s-1.16", "0.3.4", "0.3.4", "0.3.4"),
+        ]);
+        assert!(lint(&config, &files).is_empty());
+
+        // The new update kept an old update's image, or its max version.
+        let files = manifest(vec![
+            update("aws-k8s-1.15", "0.3.3", "0.3.3", "0.3.3"),
+            update("aws-k8s-1.16", "0.3.4", "0.3.4", "0.3.3"),
+            update("aws-dev", "0.3.4", "0.3.4", "0.3.4"),
+            update("aws-k8s-1.15", "0.3.4", "0.3.3", "0.3.4"),
+        ]);
+        let findings = lint(&config, &files);
+        assert_eq!(
+            rules_found(&findings),
+            vec!["shared-images", "shared-images"]
+        );
+        assert_eq!(
+            findings[0].message,
+            "aws-k8s-1.15 x86_64 0.3.3 and aws-k8s-1.16 x86_64 0.3.4 share image \
+             boot-0.3.3.ext4.lz4 but have different versions"
+        );
+        assert_eq!(
+            findings[1].message,
+            "aws-dev x86_64 0.3.4 and aws-k8s-1.15 x86_64 0.3.4 share image \
+             boot-0.3.4.ext4.lz4 but have different max versions, 0.3.4 and 0.3.3"
+        );
+    }
+
+    #[test]
+    fn release_notes() {
+        let version = Version::parse("0.3.3").unwrap();
+        let mut files = ReleaseFiles {
+            version: Some(version),
+            ..ReleaseFiles::default()
+        };
+        let config = Config::default();
+        assert_eq!(rules_found(&lint(&config, &files)), vec!["release-notes"]);
+
+        let changelog = |text: &str| Some((PathBuf::from("CHANGELOG.md"), text.to_string()));
+        files.changelog = changelog("# v0.3.3 (2020-05-01)\n\n* Things\n\n# v0.3.2 (2020-04-20)\n");
+        assert!(lint(&config, &files).is_empty());
+        files.changelog = changelog("# v0.3.2 (2020-04-20)\n\n* Mentions v0.3.3\n");
+        assert_eq!(rules_found(&lint(&config, &files)), vec!["release-notes"]);
+        files.changelog = changelog("# v0.3.33 (2020-05-01)\n");
+        assert_eq!(rules_found(&lint(&config, &files)), vec!["release-notes"]);
+    }
+}
@@ -0,0 +1,302 @@
+//! Combines manifests built separately, like one per architecture, into one.  Updates are matched
+//! by variant, architecture, and version; an update in more than one input has to have the same
+//! images and max version in each, and its waves are combined.  Migrations, shards, and
+//! compatibility rules are combined the same way.  Anything the inputs disagree on is a conflict,
+//! and every conflict is reported, rather than one input quietly winning.
+
+use semver::Version;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fmt;
+use update_metadata::{Compatibility, Manifest, Update};
+
+/// Something two inputs disagree on.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Conflict {
+    /// What differs, like `the images of aws-k8s-1.15 x86_64 1.0.6`.
+    what: String,
+    first: String,
+    second: String,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} differ between {} and {}",
+            self.what, self.first, self.second
+        )
+    }
+}
+
+/// Builds the merged manifest, remembering which input each part came from, for conflicts.
+#[derive(Default)]
+struct Merger {
+    manifest: Manifest,
+    /// The input each update came from, by its index in the merged manifest.
+    update_sources: Vec<String>,
+    migration_sources: BTreeMap<(Version, Version), String>,
+    shard_sources: BTreeMap<String, String>,
+    /// The input each compatibility rule came from, by its index in the merged manifest.
+    rule_sources: Vec<String>,
+    conflicts: Vec<Conflict>,
+}
+
+impl Merger {
+    fn conflict(&mut self, what: String, first: &str, second: &str) {
+        self.conflicts.push(Conflict {
+            what,
+            first: first.to_string(),
+            second: second.to_string(),
+        });
+    }
+
+    fn add(&mut self, source: &str, manifest: Manifest) {
+        for update in manifest.updates {
+            self.add_update(source, update);
+        }
+
+        for (versions, migrations) in manifest.migrations {
+            match self.manifest.migrations.get(&versions) {
+                None => {
+                    self.migration_sources
+                        .insert(versions.clone(), source.to_string());
+                    self.manifest.migrations.insert(versions, migrations);
+                }
+                Some(existing) if *existing != migrations => {
+                    let first = self.migration_sources[&versions].clone();
+                    let what = format!("the migrations from {} to {}", versions.0, versions.1);
+                    self.conflict(what, &first, source);
+                }
+                Some(_) => {}
+            }
+        }
+
+        for (variant, shard) in manifest.shards {
+            match self.manifest.shards.get(&variant) {
+                None => {
+                    self.shard_sources
+                        .insert(variant.clone(), source.to_string());
+                    self.manifest.shards.insert(variant, shard);
+                }
+                Some(existing) if *existing != shard => {
+                    let first = self.shard_sources[&variant].clone();
+                    self.conflict(format!("the shard files of {}", variant), &first, source);
+                }
+                Some(_) => {}
+            }
+        }
+
+        for rule in manifest.compatibility {
+            self.add_rule(source, rule);
+        }
+    }
+
+    fn add_update(&mut self, source: &str, update: Update) {
+        let index = self.manifest.updates.iter().position(|existing| {
+            existing.variant == update.variant
+                && existing.arch == update.arch
+                && existing.version == update.version
+        });
+        let index = if let Some(index) = index {
+            index
+        } else {
+            self.manifest.updates.push(update);
+            self.update_sources.push(source.to_string());
+            return;
+        };
+
+        let name = format!("{} {} {}", update.variant, update.arch, update.version);
+        let existing = &mut self.manifest.updates[index];
+        let mut differ = Vec::new();
+        if existing.images != update.images {
+            differ.push(format!("the images of {}", name));
+        }
+        if existing.max_version != update.max_version {
+            differ.push(format!("the max versions of {}", name));
+        }
+        for (bound, start) in update.waves {
+            match existing.waves.entry(bound) {
+                Entry::Vacant(entry) => {
+                    entry.insert(start);
+                }
+                Entry::Occupied(entry) => {
+                    if *entry.get() != start {
+                        differ.push(format!("the starts of wave {} of {}", bound, name));
+                    }
+                }
+            }
+        }
+        if !merge_option(&mut existing.paused, update.paused) {
+            differ.push(format!("the pause times of {}", name));
+        }
+        if !merge_option(&mut existing.targeting, update.targeting) {
+            differ.push(format!("the targeting of {}", name));
+        }
+        if !merge_option(&mut existing.provenance, update.provenance) {
+            differ.push(format!("the provenance documents of {}", name));
+        }
+        if existing.available != update.available {
+            differ.push(format!("whether {} is deprecated", name));
+        }
+
+        let first = self.update_sources[index].clone();
+        for what in differ {
+            self.conflict(what, &first, source);
+        }
+    }
+
+    fn add_rule(&mut self, source: &str, rule: Compatibility) {
+        let index =
+            self.manifest.compatibility.iter().position(|existing| {
+                existing.variant == rule.variant && existing.arch == rule.arch
+            });
+        match index {
+            None => {
+                self.manifest.compatibility.push(rule);
+                self.rule_sources.push(source.to_string());
+            }
+            Some(index) if self.manifest.compatibility[index] != rule => {
+                let first = self.rule_sources[index].clone();
+                let what = format!(
+                    "the compatibility rules of {} on {}",
+                    rule.variant,
+                    rule.arch.as_deref().unwrap_or("every architecture")
+                );
+                self.conflict(what, &first, source);
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+/// Sets `existing` to `new` if it isn't set yet.  Returns false if both are set and differ.
+fn merge_option<T: PartialEq>(existing: &mut Option<T>, new: Option<T>) -> bool {
+    match (existing.as_ref(), new) {
+        (_, None) => true,
+        (None, new) => {
+            *existing = new;
+            true
+        }
+        (Some(existing), Some(new)) => *existing == new,
+    }
+}
+
+/// Merges manifests, each named by where it was read from, in order.  Updates and rules keep the
+/// order they're first seen in.  Returns every conflict found if the inputs disagree.
+pub(crate) fn merge<I>(inputs: I) -> std::result::Result<Manifest, Vec<Conflict>>
+where
+    I: IntoIterator<Item = (String, Manifest)>,
+{
+    let mut merger = Merger::default();
+    for (source, manifest) in inputs {
+        merger.add(&source, manifest);
+    }
+    if merger.conflicts.is_empty() {
+        Ok(merger.manifest)
+    } else {
+        Err(merger.conflicts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(arch: &str, version: &str, root: &str, waves: &str) -> String {
+        format!(
+            r#"{{"variant": "aws-k8s-1.15", "arch": "{}", "version": "{}", "max_version": "1.1.0",
+                "waves": {{{}}}, "images": {{"boot": "boot", "root": "{}", "hash": "hash"}}}}"#,
+            arch, version, waves, root
+        )
+    }
+
+    fn manifest(updates: &[String], migrations: &str) -> Manifest {
+        serde_json::from_str(&format!(
+            r#"{{"updates": [{}], "migrations": {{{}}}}}"#,
+            updates.join(","),
+            migrations
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn per_arch_manifests() {
+        let x86_64 = manifest(
+            &[
+                update("x86_64", "1.0.0", "x86-root-1.0.0", ""),
+                update(
+                    "x86_64",
+                    "1.1.0",
+                    "x86-root-1.1.0",
+                    r#""0": "2020-06-01T09:00:00Z""#,
+                ),
+            ],
+            r#""(1.0.0, 1.1.0)": ["a"]"#,
+        );
+        let aarch64 = manifest(
+            &[
+                update("aarch64", "1.1.0", "arm-root-1.1.0", ""),
+                // The same update, with a wave the other input doesn't have yet.
+                update(
+                    "x86_64",
+                    "1.1.0",
+                    "x86-root-1.1.0",
+                    r#""1024": "2020-06-02T09:00:00Z""#,
+                ),
+            ],
+            r#""(1.0.0, 1.1.0)": ["a"], "(1.1.0, 1.2.0)": ["b"]"#,
+        );
+        let merged = merge(vec![
+            ("x86_64.json".to_string(), x86_64),
+            ("aarch64.json".to_string(), aarch64),
+        ])
+        .unwrap();
+
+        let names: Vec<_> = merged
+            .updates
+            .iter()
+            .map(|update| format!("{}-{}", update.arch, update.version))
+            .collect();
+        assert_eq!(names, vec!["x86_64-1.0.0", "x86_64-1.1.0", "aarch64-1.1.0"]);
+        assert_eq!(merged.updates[1].waves.len(), 2);
+        assert_eq!(merged.migrations.len(), 2);
+    }
+
+    #[test]
+    fn conflicts() {
+        let first = manifest(
+            &[update(
+                "x86_64",
+                "1.1.0",
+                "root",
+                r#""0": "2020-06-01T09:00:00Z""#,
+            )],
+            r#""(1.0.0, 1.1.0)": ["a"]"#,
+        );
+        let second = manifest(
+            &[update(
+                "x86_64",
+                "1.1.0",
+                "rebuilt-root",
+                r#""0": "2020-06-03T09:00:00Z""#,
+            )],
+            r#""(1.0.0, 1.1.0)": ["a", "b"]"#,
+        );
+        let conflicts = merge(vec![
+            ("first.json".to_string(), first),
+            ("second.json".to_string(), second),
+        ])
+        .unwrap_err();
+
+        let messages: Vec<_> = conflicts.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "the images of aws-k8s-1.15 x86_64 1.1.0 differ between first.json and second.json",
+                "the starts of wave 0 of aws-k8s-1.15 x86_64 1.1.0 differ between first.json and second.json",
+                "the migrations from 1.0.0 to 1.1.0 differ between first.json and second.json",
+            ]
+        );
+    }
+}
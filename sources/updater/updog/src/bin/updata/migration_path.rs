@@ -0,0 +1,228 @@
+//! The migrations a host runs to move its data store from one version to another, found the way
+//! updog finds them, in the order the migrator runs them.
+
+use crate::error::{self, Result};
+use migrator::MIGRATION_FILENAME_RE;
+use semver::Version;
+use serde::Serialize;
+use snafu::ensure;
+use std::fmt;
+use update_metadata::Manifest;
+
+/// One transition in the manifest's migrations, in the direction the host takes it.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct Hop {
+    pub(crate) from: Version,
+    pub(crate) to: Version,
+    /// The hop's migrations, in the order they run.
+    pub(crate) migrations: Vec<String>,
+}
+
+/// The hops a host takes between two data store versions.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct MigrationPath {
+    pub(crate) from: Version,
+    pub(crate) to: Version,
+    pub(crate) hops: Vec<Hop>,
+}
+
+/// Returns the hops from `from` to `to`, or an error saying where the path stops if it doesn't
+/// reach `to`.  Like updog, going down a path is taken to need the same migrations as going up
+/// it; the migrator runs them in reverse.
+pub(crate) fn migration_path(
+    manifest: &Manifest,
+    from: &Version,
+    to: &Version,
+) -> Result<MigrationPath> {
+    let low = from.min(to);
+    let high = from.max(to);
+    let (path, reached) = manifest.migration_path(low, high);
+    ensure!(
+        reached == high,
+        error::MigrationPathIncomplete {
+            low: low.clone(),
+            high: high.clone(),
+            reached: reached.clone(),
+            detail: stop_detail(manifest, reached, high),
+        }
+    );
+
+    let mut hops: Vec<Hop> = path
+        .into_iter()
+        .map(|transition| {
+            let mut migrations = manifest
+                .migrations
+                .get(transition)
+                .cloned()
+                .unwrap_or_default();
+            migrations.sort_by_key(|name| run_order(name));
+            Hop {
+                from: transition.0.clone(),
+                to: transition.1.clone(),
+                migrations,
+            }
+        })
+        .collect();
+    if from > to {
+        hops.reverse();
+        for hop in &mut hops {
+            std::mem::swap(&mut hop.from, &mut hop.to);
+            hop.migrations.reverse();
+        }
+    }
+
+    Ok(MigrationPath {
+        from: from.clone(),
+        to: to.clone(),
+        hops,
+    })
+}
+
+/// The migrator runs migrations in order of the version in their name, then the rest of their
+/// name.  It skips files it can't parse a version from; those are put first here, so they stand
+/// out.
+fn run_order(name: &str) -> (Option<Version>, String) {
+    MIGRATION_FILENAME_RE
+        .captures(name)
+        .and_then(|captures| {
+            let version = Version::parse(captures.name("version")?.as_str()).ok()?;
+            Some((Some(version), captures.name("name")?.as_str().to_string()))
+        })
+        .unwrap_or_else(|| (None, name.to_string()))
+}
+
+/// Describes what the manifest has near where a path stopped, to help find the missing hop.
+fn stop_detail(manifest: &Manifest, reached: &Version, high: &Version) -> String {
+    let past: Vec<String> = manifest
+        .migrations
+        .keys()
+        .filter(|(f, t)| f == reached && t > high)
+        .map(|(f, t)| format!("({}, {})", f, t))
+        .collect();
+    match past.len() {
+        0 => {}
+        1 => return format!("{} goes past {}", past[0], high),
+        _ => return format!("{} go past {}", past.join(", "), high),
+    }
+    match manifest
+        .migrations
+        .keys()
+        .map(|(f, _)| f)
+        .filter(|f| *f > reached && *f < high)
+        .min()
+    {
+        Some(next) => format!("the next migrations start from {}", next),
+        None => format!("no migrations start between {} and {}", reached, high),
+    }
+}
+
+impl fmt::Display for MigrationPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.hops.is_empty() {
+            return write!(f, "No migrations from {} to {}", self.from, self.to);
+        }
+        for (i, hop) in self.hops.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{} to {}", hop.from, hop.to)?;
+            if hop.migrations.is_empty() {
+                write!(f, "\n  (none)")?;
+            }
+            for migration in &hop.migrations {
+                write!(f, "\n  {}", migration)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest() -> Manifest {
+        serde_json::from_str(
+            r#"{
+                "updates": [],
+                "migrations": {
+                    "(1.0.0, 1.1.0)": ["migrate_v1.1.0_b", "migrate_v1.1.0_a"],
+                    "(1.1.0, 1.2.0)": [],
+                    "(1.1.0, 1.3.0)": ["migrate_v1.3.0_c"],
+                    "(1.3.0, 1.5.0)": ["migrate_v1.5.0_d"],
+                    "(1.4.0, 1.5.0)": ["migrate_v1.5.0_e"]
+                }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn hop(from: &str, to: &str, migrations: &[&str]) -> Hop {
+        Hop {
+            from: Version::parse(from).unwrap(),
+            to: Version::parse(to).unwrap(),
+            migrations: migrations.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn forward_and_back() {
+        let manifest = manifest();
+        let v = |s: &str| Version::parse(s).unwrap();
+
+        // The furthest hop that doesn't pass the target is taken, and migrations run in name
+        // order.
+        let path = migration_path(&manifest, &v("1.0.0"), &v("1.3.0")).unwrap();
+        assert_eq!(
+            path.hops,
+            vec![
+                hop("1.0.0", "1.1.0", &["migrate_v1.1.0_a", "migrate_v1.1.0_b"]),
+                hop("1.1.0", "1.3.0", &["migrate_v1.3.0_c"]),
+            ]
+        );
+        assert_eq!(
+            path.to_string(),
+            "1.0.0 to 1.1.0\n  migrate_v1.1.0_a\n  migrate_v1.1.0_b\n1.1.0 to 1.3.0\n  migrate_v1.3.0_c"
+        );
+
+        // Going down runs the same migrations in reverse.
+        let path = migration_path(&manifest, &v("1.3.0"), &v("1.0.0")).unwrap();
+        assert_eq!(
+            path.hops,
+            vec![
+                hop("1.3.0", "1.1.0", &["migrate_v1.3.0_c"]),
+                hop("1.1.0", "1.0.0", &["migrate_v1.1.0_b", "migrate_v1.1.0_a"]),
+            ]
+        );
+
+        let path = migration_path(&manifest, &v("1.1.0"), &v("1.2.0")).unwrap();
+        assert_eq!(path.to_string(), "1.1.0 to 1.2.0\n  (none)");
+        let path = migration_path(&manifest, &v("1.1.0"), &v("1.1.0")).unwrap();
+        assert!(path.hops.is_empty());
+    }
+
+    #[test]
+    fn missing_hop() {
+        let manifest = manifest();
+        let v = |s: &str| Version::parse(s).unwrap();
+
+        let err = migration_path(&manifest, &v("1.0.0"), &v("1.4.0")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No migration path between 1.0.0 and 1.4.0: nothing leads on from 1.3.0, \
+             and (1.3.0, 1.5.0) goes past 1.4.0"
+        );
+        let err = migration_path(&manifest, &v("1.5.0"), &v("1.2.0")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No migration path between 1.2.0 and 1.5.0: nothing leads on from 1.2.0, \
+             and the next migrations start from 1.3.0"
+        );
+        let err = migration_path(&manifest, &v("0.9.0"), &v("1.0.0")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No migration path between 0.9.0 and 1.0.0: nothing leads on from 0.9.0, \
+             and no migrations start between 0.9.0 and 1.0.0"
+        );
+    }
+}
@@ -0,0 +1,366 @@
+//! Copies a repository, or the part of it some hosts need, to a directory that can be carried
+//! into a network with no route to the original, like a USB drive for an air-gapped cluster.
+//! The metadata is copied as-is, since it can't be signed again, along with the manifest and the
+//! images and migrations for the chosen variants and versions; everything else is left out.
+//!
+//! Targets are checked against the source's signed metadata as they're downloaded, and once the
+//! copy is done, it's loaded again as a repository of its own and every copied target is checked
+//! against it, so a mirror that's written successfully is one hosts can update from.
+
+use crate::error::{self, Result};
+use crate::transport::HttpQueryTransport;
+use ring::digest::{Context, SHA256};
+use semver::VersionReq;
+use snafu::{OptionExt, ResultExt};
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+use tough::{Limits, Repository, Settings, Transport};
+use update_metadata::Manifest;
+use url::Url;
+
+/// Which updates to copy.
+#[derive(Debug)]
+pub(crate) struct Selection {
+    /// Variants, or variant families: "aws-k8s" picks aws-k8s-1.15 and aws-k8s-1.16.
+    pub(crate) variants: Vec<String>,
+    pub(crate) versions: VersionReq,
+    /// Versions to copy the migrations to; hosts moving to a version need the migrations to each
+    /// version in between, even if those versions' images aren't copied.
+    pub(crate) migrations: VersionReq,
+    pub(crate) arch: Option<String>,
+}
+
+impl Selection {
+    fn wants_variant(&self, variant: &str) -> bool {
+        self.variants.iter().any(|wanted| {
+            variant == wanted
+                || (variant.starts_with(wanted.as_str())
+                    && variant[wanted.len()..].starts_with('-'))
+        })
+    }
+
+    /// Returns the names of the targets the selected updates need: their images, and the
+    /// selected migrations.
+    fn targets(&self, manifest: &Manifest) -> BTreeSet<String> {
+        let mut targets = BTreeSet::new();
+        for update in &manifest.updates {
+            if self.wants_variant(&update.variant)
+                && self.versions.matches(&update.version)
+                && self.arch.as_ref().map_or(true, |arch| *arch == update.arch)
+            {
+                targets.insert(update.images.boot.clone());
+                targets.insert(update.images.root.clone());
+                targets.insert(update.images.hash.clone());
+            }
+        }
+        for ((_, to), migrations) in &manifest.migrations {
+            if self.migrations.matches(to) {
+                targets.extend(migrations.iter().cloned());
+            }
+        }
+        targets
+    }
+}
+
+/// What a mirror run copied.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Copied {
+    /// The targets the mirror holds.
+    pub(crate) targets: usize,
+    /// Targets that were already in the mirror from an earlier run, and weren't downloaded.
+    pub(crate) unchanged: usize,
+    /// The size of the targets that were downloaded.
+    pub(crate) bytes: u64,
+}
+
+/// Copies the repository at `from` into `to`, trusting the root.json at `trusted_root`.  Both
+/// hold the metadata in `metadata/` and the targets in `targets/`.
+pub(crate) fn mirror(
+    from: &Url,
+    to: &Path,
+    trusted_root: &Path,
+    selection: &Selection,
+) -> Result<Copied> {
+    let transport = HttpQueryTransport::new();
+    let source_metadata = base_url(from, "metadata/")?;
+    let source_targets = base_url(from, "targets/")?;
+    let datastore = TempDir::new().context(error::MirrorDatastore)?;
+    let repository = load(
+        &transport,
+        trusted_root,
+        datastore.path(),
+        &source_metadata,
+        &source_targets,
+    )?;
+
+    let metadata_dir = to.join("metadata");
+    let targets_dir = to.join("targets");
+    for dir in &[&metadata_dir, &targets_dir] {
+        fs::create_dir_all(dir).context(error::MirrorWrite { path: *dir })?;
+    }
+
+    copy_metadata(&transport, &repository, &source_metadata, &metadata_dir)?;
+
+    let consistent = repository.root().signed.consistent_snapshot;
+    let mut copied = Copied::default();
+    let mut copy = |name: &str| -> Result<PathBuf> {
+        let target = repository
+            .targets()
+            .signed
+            .targets
+            .get(name)
+            .context(error::TargetNotFound { target: name })?;
+        let path = if consistent {
+            targets_dir.join(format!("{}.{}", hex(&target.hashes.sha256), name))
+        } else {
+            targets_dir.join(name)
+        };
+        copied.targets += 1;
+        if let Ok(sha256) = sha256_file(&path) {
+            if sha256 == target.hashes.sha256.as_ref() {
+                copied.unchanged += 1;
+                return Ok(path);
+            }
+        }
+        let mut reader = repository
+            .read_target(name)
+            .context(error::Metadata)?
+            .context(error::TargetNotFound { target: name })?;
+        let mut file = File::create(&path).context(error::MirrorWrite { path: &path })?;
+        copied.bytes +=
+            io::copy(&mut reader, &mut file).context(error::MirrorWrite { path: &path })?;
+        Ok(path)
+    };
+
+    // The manifest is needed to know which targets to copy; the shards of variants that aren't
+    // wanted are read to load it, but not copied.
+    let index_path = copy("manifest.json")?;
+    let index = fs::read(&index_path).context(error::MirrorWrite { path: &index_path })?;
+    let manifest = update_metadata::from_stored(&index, |name| read_target(&repository, name))?;
+    for (variant, shard) in &manifest.shards {
+        if selection.wants_variant(variant) {
+            copy(shard)?;
+        }
+    }
+    let mut targets = selection.targets(&manifest);
+    for name in &targets {
+        copy(name)?;
+    }
+
+    // Check the copy the way a host would see it.
+    targets.insert("manifest.json".to_string());
+    for (variant, shard) in &manifest.shards {
+        if selection.wants_variant(variant) {
+            targets.insert(shard.clone());
+        }
+    }
+    let datastore = TempDir::new().context(error::MirrorDatastore)?;
+    // File URLs need absolute paths.
+    let mirror_url = |dir: &Path| -> Result<Url> {
+        let dir = fs::canonicalize(dir).context(error::MirrorWrite { path: dir })?;
+        Url::from_directory_path(&dir)
+            .ok()
+            .context(error::MirrorPath { path: &dir })
+    };
+    let mirror_metadata = mirror_url(&metadata_dir)?;
+    let mirror_targets = mirror_url(&targets_dir)?;
+    let mirrored = load(
+        &transport,
+        trusted_root,
+        datastore.path(),
+        &mirror_metadata,
+        &mirror_targets,
+    )?;
+    for name in &targets {
+        let mut reader = mirrored
+            .read_target(name)
+            .context(error::Metadata)?
+            .context(error::TargetNotFound {
+                target: name.as_str(),
+            })?;
+        io::copy(&mut reader, &mut io::sink()).context(error::MirrorVerify {
+            target: name.as_str(),
+        })?;
+    }
+    Ok(copied)
+}
+
+/// Copies the metadata files hosts fetch, byte for byte, since the snapshot and timestamp sign
+/// the exact files.  Every root.json is copied, so hosts that trust an older one can still walk
+/// up to the newest.
+fn copy_metadata(
+    transport: &HttpQueryTransport,
+    repository: &Repository<'_, HttpQueryTransport>,
+    source_metadata: &Url,
+    metadata_dir: &Path,
+) -> Result<()> {
+    let mut metadata = vec!["timestamp.json".to_string()];
+    for version in 1..=repository.root().signed.version.get() {
+        metadata.push(format!("{}.root.json", version));
+    }
+    if repository.root().signed.consistent_snapshot {
+        metadata.push(format!(
+            "{}.snapshot.json",
+            repository.snapshot().signed.version
+        ));
+        metadata.push(format!(
+            "{}.targets.json",
+            repository.targets().signed.version
+        ));
+    } else {
+        metadata.push("snapshot.json".to_string());
+        metadata.push("targets.json".to_string());
+    }
+    for name in &metadata {
+        let url = source_metadata
+            .join(name)
+            .context(error::ManifestUrl { url: name.as_str() })?;
+        let mut reader = transport
+            .fetch(url.clone())
+            .context(error::MirrorFetch { url })?;
+        let path = metadata_dir.join(name);
+        let mut file = File::create(&path).context(error::MirrorWrite { path: &path })?;
+        io::copy(&mut reader, &mut file).context(error::MirrorWrite { path: &path })?;
+    }
+    Ok(())
+}
+
+fn load<'a>(
+    transport: &'a HttpQueryTransport,
+    trusted_root: &Path,
+    datastore: &'a Path,
+    metadata_base_url: &'a Url,
+    target_base_url: &'a Url,
+) -> Result<Repository<'a, HttpQueryTransport>> {
+    Repository::load(
+        transport,
+        Settings {
+            root: File::open(trusted_root).context(error::OpenRoot { path: trusted_root })?,
+            datastore,
+            metadata_base_url: metadata_base_url.as_str(),
+            target_base_url: target_base_url.as_str(),
+            limits: Limits {
+                max_root_size: 1024 * 1024,         // 1 MiB
+                max_targets_size: 1024 * 1024 * 10, // 10 MiB
+                max_timestamp_size: 1024 * 1024,    // 1 MiB
+                max_root_updates: 1024,
+            },
+        },
+    )
+    .context(error::Metadata)
+}
+
+fn read_target(repository: &Repository<'_, HttpQueryTransport>, name: &str) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut reader = repository
+        .read_target(name)
+        .context(error::Metadata)?
+        .context(error::TargetNotFound { target: name })?;
+    io::copy(&mut reader, &mut data).context(error::MirrorVerify { target: name })?;
+    Ok(data)
+}
+
+/// Returns the URL of the directory `dir` under the repository at `base`, whether or not `base`
+/// ends with a slash.
+fn base_url(base: &Url, dir: &str) -> Result<Url> {
+    let mut base = base.clone();
+    if !base.path().ends_with('/') {
+        base.set_path(&format!("{}/", base.path()));
+    }
+    base.join(dir).context(error::ManifestUrl { url: dir })
+}
+
+fn sha256_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut context = Context::new(&SHA256);
+    let mut buf = [0; 8192];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        context.update(&buf[..n]);
+    }
+    Ok(context.finish().as_ref().to_vec())
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use update_harness::RepoBuilder;
+
+    #[test]
+    fn selection() {
+        let selection = Selection {
+            variants: vec!["aws-k8s".to_string()],
+            versions: VersionReq::parse(">=0.3").unwrap(),
+            migrations: VersionReq::parse(">=0.3").unwrap(),
+            arch: None,
+        };
+        assert!(selection.wants_variant("aws-k8s"));
+        assert!(selection.wants_variant("aws-k8s-1.15"));
+        assert!(!selection.wants_variant("aws-k8sx"));
+        assert!(!selection.wants_variant("aws-ecs-1"));
+    }
+
+    #[test]
+    fn copies_what_hosts_need() {
+        let dir = TempDir::new().unwrap();
+        let mut repo = RepoBuilder::new();
+        repo.add_update("aws-k8s-1.15", "0.2.0")
+            .add_update("aws-k8s-1.15", "0.3.0")
+            .add_update("aws-ecs-1", "0.3.0");
+        repo.add_target("migrate_v0.3.0_a.lz4", b"a".to_vec())
+            .add_target("migrate_v0.2.0_b.lz4", b"b".to_vec());
+        let manifest = repo.manifest_mut();
+        manifest.migrations.insert(
+            (
+                semver::Version::parse("0.2.0").unwrap(),
+                semver::Version::parse("0.3.0").unwrap(),
+            ),
+            vec!["migrate_v0.3.0_a.lz4".to_string()],
+        );
+        manifest.migrations.insert(
+            (
+                semver::Version::parse("0.1.0").unwrap(),
+                semver::Version::parse("0.2.0").unwrap(),
+            ),
+            vec!["migrate_v0.2.0_b.lz4".to_string()],
+        );
+        let trusted_root = repo.write(dir.path().join("repo"));
+        let from = Url::from_directory_path(dir.path().join("repo")).unwrap();
+        let to = dir.path().join("mirror");
+        let selection = Selection {
+            variants: vec!["aws-k8s".to_string()],
+            versions: VersionReq::parse(">=0.3").unwrap(),
+            migrations: VersionReq::parse(">=0.3").unwrap(),
+            arch: None,
+        };
+
+        let copied = mirror(&from, &to, &trusted_root, &selection).unwrap();
+        // The manifest, three images, and one migration.
+        assert_eq!(copied.targets, 5);
+        assert_eq!(copied.unchanged, 0);
+        let mut names: Vec<_> = fs::read_dir(to.join("targets"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), 5);
+        assert!(names.contains(&"migrate_v0.3.0_a.lz4".to_string()));
+        assert!(names.iter().all(|name| !name.contains("0.2.0")));
+        assert!(names.iter().all(|name| !name.starts_with("aws-ecs")));
+        assert!(to.join("metadata/1.root.json").exists());
+
+        // Another sync leaves what's already there.
+        let copied = mirror(&from, &to, &trusted_root, &selection).unwrap();
+        assert_eq!(copied.unchanged, 5);
+    }
+}
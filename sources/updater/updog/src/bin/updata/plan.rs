@@ -0,0 +1,264 @@
+//! Plans for building a release in one go, rather than running updata once per change.  A plan
+//! is a TOML file listing steps, each named after the updata command it stands for, that are run
+//! in order against the manifest in memory:
+//!
+//! ```toml
+//! [[step]]
+//! op = "add-update"
+//! variant = "aws-k8s-1.15"
+//! arch = "x86_64"
+//! version = "1.0.6"
+//! root = "bottlerocket-aws-k8s-1.15-x86_64-1.0.6-root.ext4.lz4"
+//! boot = "bottlerocket-aws-k8s-1.15-x86_64-1.0.6-boot.ext4.lz4"
+//! hash = "bottlerocket-aws-k8s-1.15-x86_64-1.0.6-root.verity.lz4"
+//!
+//! [[step]]
+//! op = "set-waves"
+//! variant = "aws-k8s-1.15"
+//! arch = "x86_64"
+//! version = "1.0.6"
+//! wave-file = "waves/default-waves.toml"
+//!
+//! [[step]]
+//! op = "set-migrations"
+//! from = "Release.toml"
+//! ```
+//!
+//! Paths are relative to the plan file.  The manifest is only written if every step succeeds.
+
+use crate::error::{self, Result};
+use semver::Version;
+use serde::Deserialize;
+use snafu::{ensure, ResultExt};
+use std::fs;
+use std::path::{Path, PathBuf};
+use update_metadata::{Images, Manifest, UpdateWave, UpdateWaves};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Plan {
+    #[serde(default, rename = "step")]
+    steps: Vec<Step>,
+    /// The directory paths in the plan are relative to.
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+/// A change to the manifest, like the updata command with the same name.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "kebab-case", deny_unknown_fields)]
+enum Step {
+    #[serde(rename_all = "kebab-case")]
+    AddUpdate {
+        variant: String,
+        arch: String,
+        version: Version,
+        max_version: Option<Version>,
+        root: String,
+        boot: String,
+        hash: String,
+    },
+    /// Waves are given either from a wave file, or in the plan itself.
+    #[serde(rename_all = "kebab-case")]
+    SetWaves {
+        variant: String,
+        arch: String,
+        version: Version,
+        wave_file: Option<PathBuf>,
+        waves: Option<Vec<UpdateWave>>,
+    },
+    SetMigrations {
+        from: PathBuf,
+    },
+}
+
+impl Step {
+    fn op(&self) -> &'static str {
+        match self {
+            Step::AddUpdate { .. } => "add-update",
+            Step::SetWaves { .. } => "set-waves",
+            Step::SetMigrations { .. } => "set-migrations",
+        }
+    }
+
+    fn apply(&self, manifest: &mut Manifest, dir: &Path) -> Result<()> {
+        match self {
+            Step::AddUpdate {
+                variant,
+                arch,
+                version,
+                max_version,
+                root,
+                boot,
+                hash,
+            } => {
+                manifest.add_update(
+                    version.clone(),
+                    max_version.clone(),
+                    arch.clone(),
+                    variant.clone(),
+                    Images {
+                        root: root.clone(),
+                        boot: boot.clone(),
+                        hash: hash.clone(),
+                    },
+                )?;
+                info!("Added update {}-{}-{}", arch, variant, version);
+            }
+            Step::SetWaves {
+                variant,
+                arch,
+                version,
+                wave_file,
+                waves,
+            } => {
+                let waves = match (wave_file, waves) {
+                    (Some(path), None) => crate::read_waves(&dir.join(path))?,
+                    (None, Some(waves)) => UpdateWaves {
+                        waves: waves
+                            .iter()
+                            .map(|wave| UpdateWave {
+                                start_after: wave.start_after.clone(),
+                                fleet_percentage: wave.fleet_percentage,
+                            })
+                            .collect(),
+                    },
+                    _ => return error::PlanWaves.fail(),
+                };
+                // Unlike `updata set-waves`, a plan shouldn't go on as if waves were set.
+                let num_matching = crate::set_waves(manifest, variant, arch, version, &waves)?;
+                ensure!(
+                    num_matching > 0,
+                    error::UpdateNotFound {
+                        arch,
+                        variant,
+                        version: version.clone(),
+                    }
+                );
+            }
+            Step::SetMigrations { from } => {
+                manifest.migrations = crate::read_release(&dir.join(from))?.migrations;
+                info!("Set migrations from {}", from.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Plan {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let data = fs::read_to_string(path).context(error::ConfigRead { path })?;
+        let mut plan: Self = toml::from_str(&data).context(error::PlanParse { path })?;
+        plan.dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(plan)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Runs the steps in order, stopping at the first that fails.
+    pub(crate) fn apply(&self, manifest: &mut Manifest) -> Result<()> {
+        for (i, step) in self.steps.iter().enumerate() {
+            step.apply(manifest, &self.dir)
+                .map_err(Box::new)
+                .context(error::PlanStep {
+                    step: i + 1,
+                    op: step.op(),
+                })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_plan() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("Release.toml"),
+            "version = \"1.14.0\"\n\
+             [migrations]\n\
+             \"(1.13.0, 1.14.0)\" = [\"migrate_1.14.0_foo\"]\n",
+        )
+        .unwrap();
+        let path = dir.path().join("plan.toml");
+        fs::write(
+            &path,
+            r#"
+            [[step]]
+            op = "add-update"
+            variant = "bottlerocket-aws-eks"
+            arch = "x86_64"
+            version = "1.14.0"
+            max-version = "1.14.0"
+            root = "root"
+            boot = "boot"
+            hash = "hash"
+
+            [[step]]
+            op = "set-waves"
+            variant = "bottlerocket-aws-eks"
+            arch = "x86_64"
+            version = "1.14.0"
+            waves = [
+                { start_after = "1 hour", fleet_percentage = 10 },
+                { start_after = "1 day", fleet_percentage = 100 },
+            ]
+
+            [[step]]
+            op = "set-migrations"
+            from = "Release.toml"
+            "#,
+        )
+        .unwrap();
+
+        let plan = Plan::load(&path).unwrap();
+        assert_eq!(plan.len(), 3);
+        let mut manifest =
+            update_metadata::load_file(Path::new("tests/data/example.json")).unwrap();
+        plan.apply(&mut manifest).unwrap();
+        let update = manifest
+            .updates
+            .iter()
+            .find(|u| u.version == Version::new(1, 14, 0))
+            .unwrap();
+        assert_eq!(update.waves.len(), 2);
+        assert_eq!(manifest.migrations.len(), 1);
+        assert!(manifest
+            .updates
+            .iter()
+            .all(|u| u.max_version == Version::new(1, 14, 0)));
+    }
+
+    #[test]
+    fn bad_steps() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("plan.toml");
+        let plan = |steps: &str| {
+            fs::write(&path, steps).unwrap();
+            Plan::load(&path)
+        };
+        let mut manifest = Manifest::default();
+
+        // Waves need either a wave file or a list, and the failing step is named.
+        let plan_waves = plan(
+            "[[step]]\n\
+             op = \"set-waves\"\n\
+             variant = \"v\"\n\
+             arch = \"x86_64\"\n\
+             version = \"1.0.0\"\n",
+        )
+        .unwrap();
+        let err = plan_waves.apply(&mut manifest).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Step 1 of the plan (set-waves)"));
+
+        assert!(plan("[[step]]\nop = \"remove-everything\"\n").is_err());
+        assert!(plan("[[step]]\nop = \"set-migrations\"\nfrom = \"a\"\nto = \"b\"\n").is_err());
+    }
+}
@@ -0,0 +1,251 @@
+//! Named wave schedules, so an update's waves can be set from a start time and a preset rather
+//! than a wave file written by hand.  Each wave of a preset starts an offset after the start, like
+//! `1 day`, and takes the rollout to a share of the fleet.  A few presets are built in, and more
+//! can be given in a TOML file, where a preset with a built-in name replaces it:
+//!
+//! ```toml
+//! [preset.cautious]
+//! waves = [
+//!     { after = "now", fleet_percentage = 1 },
+//!     { after = "2 days", fleet_percentage = 20 },
+//!     { after = "7 days", fleet_percentage = 100 },
+//! ]
+//! ```
+
+use crate::error::{self, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use parse_datetime::parse_datetime_at;
+use serde::Deserialize;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use update_metadata::{UpdateWave, UpdateWaves};
+
+/// The presets every updata knows, as each wave's offset from the start and the share of the
+/// fleet it takes the rollout to.
+const BUILT_IN: &[(&str, &[(&str, u32)])] = &[
+    // A few hosts first, to catch problems before most of the fleet has the update.
+    (
+        "canary",
+        &[("now", 1), ("1 day", 10), ("2 days", 50), ("4 days", 100)],
+    ),
+    // The same share of the fleet each day.
+    (
+        "linear",
+        &[("now", 25), ("1 day", 50), ("2 days", 75), ("3 days", 100)],
+    ),
+    // The whole fleet within a day, for fixes that can't wait.
+    (
+        "aggressive",
+        &[("now", 10), ("4 hours", 50), ("12 hours", 100)],
+    ),
+    // A week, with most of the fleet held back until the first few days have gone well.
+    (
+        "conservative-7d",
+        &[
+            ("now", 1),
+            ("1 day", 5),
+            ("2 days", 15),
+            ("4 days", 40),
+            ("7 days", 100),
+        ],
+    ),
+    // A day, for routine releases to fleets that can take some risk.
+    (
+        "fast-24h",
+        &[("now", 10), ("6 hours", 50), ("24 hours", 100)],
+    ),
+    // Every host at once, for internal and test fleets that exist to find problems first.
+    ("internal-immediate", &[("now", 100)]),
+];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Preset {
+    waves: Vec<PresetWave>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PresetWave {
+    /// When the wave starts, as an offset from the start of the rollout.
+    after: String,
+    fleet_percentage: u32,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PresetFile {
+    #[serde(default, rename = "preset")]
+    presets: BTreeMap<String, Preset>,
+}
+
+/// The built-in presets, along with any from a file.
+#[derive(Debug)]
+pub(crate) struct Presets {
+    presets: BTreeMap<String, Preset>,
+}
+
+impl Presets {
+    pub(crate) fn load(path: Option<&Path>) -> Result<Self> {
+        let mut presets: BTreeMap<_, _> = BUILT_IN
+            .iter()
+            .map(|(name, waves)| {
+                let waves = waves
+                    .iter()
+                    .map(|(after, fleet_percentage)| PresetWave {
+                        after: (*after).to_string(),
+                        fleet_percentage: *fleet_percentage,
+                    })
+                    .collect();
+                ((*name).to_string(), Preset { waves })
+            })
+            .collect();
+        if let Some(path) = path {
+            let data = fs::read_to_string(path).context(error::ConfigRead { path })?;
+            let file: PresetFile = toml::from_str(&data).context(error::ConfigParse { path })?;
+            presets.extend(file.presets);
+        }
+        Ok(Self { presets })
+    }
+
+    /// Expands the preset called `name` into waves starting at `start`, in the form set-waves
+    /// takes from wave files.
+    pub(crate) fn waves(&self, name: &str, start: DateTime<Utc>) -> Result<UpdateWaves> {
+        let preset = self.presets.get(name).context(error::WavePreset {
+            preset: name,
+            known: self
+                .presets
+                .keys()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(", "),
+        })?;
+        ensure!(
+            !preset.waves.is_empty(),
+            error::PresetEmpty { preset: name }
+        );
+
+        let mut waves = Vec::new();
+        let mut last: Option<(DateTime<Utc>, u32)> = None;
+        for wave in &preset.waves {
+            let at = parse_datetime_at(&wave.after, start).context(error::PresetOffset {
+                preset: name,
+                after: &wave.after,
+            })?;
+            // Waves have to take the rollout further, and can't start before the ones they follow.
+            if let Some((last_at, last_percentage)) = last {
+                ensure!(
+                    at >= last_at && wave.fleet_percentage > last_percentage,
+                    error::PresetOrder {
+                        preset: name,
+                        after: &wave.after,
+                    }
+                );
+            }
+            last = Some((at, wave.fleet_percentage));
+            waves.push(UpdateWave {
+                start_after: at.to_rfc3339_opts(SecondsFormat::Secs, true),
+                fleet_percentage: wave.fleet_percentage,
+            });
+        }
+        Ok(UpdateWaves { waves })
+    }
+}
+
+/// Lists each preset's schedule, one per line, like `linear: 25% now, 50% after 1 day, ...`.
+impl fmt::Display for Presets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, preset) in &self.presets {
+            let waves: Vec<_> = preset
+                .waves
+                .iter()
+                .map(|wave| {
+                    if wave.after == "now" {
+                        format!("{}% now", wave.fleet_percentage)
+                    } else {
+                        format!("{}% after {}", wave.fleet_percentage, wave.after)
+                    }
+                })
+                .collect();
+            writeln!(f, "{}: {}", name, waves.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    #[test]
+    fn built_in() {
+        let presets = Presets::load(None).unwrap();
+        let start = Utc.ymd(2020, 4, 1).and_hms(17, 0, 0);
+        for (name, _) in BUILT_IN {
+            presets.waves(name, start).unwrap();
+        }
+
+        let waves = presets.waves("canary", start).unwrap().waves;
+        let times: Vec<_> = waves.iter().map(|w| w.start_after.as_str()).collect();
+        assert_eq!(
+            times,
+            vec![
+                "2020-04-01T17:00:00Z",
+                "2020-04-02T17:00:00Z",
+                "2020-04-03T17:00:00Z",
+                "2020-04-05T17:00:00Z"
+            ]
+        );
+        assert_eq!(waves.last().unwrap().fleet_percentage, 100);
+
+        let err = presets.waves("slow", start).unwrap_err().to_string();
+        assert!(
+            err.contains("aggressive, canary, conservative-7d"),
+            "{}",
+            err
+        );
+
+        let listed = presets.to_string();
+        assert_eq!(listed.lines().count(), BUILT_IN.len());
+        assert!(listed.contains("\nfast-24h: 10% now, 50% after 6 hours, 100% after 24 hours\n"));
+    }
+
+    #[test]
+    fn from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("presets.toml");
+        fs::write(
+            &path,
+            r#"
+            [preset.canary]
+            waves = [
+                { after = "now", fleet_percentage = 5 },
+                { after = "+12h", fleet_percentage = 100 },
+            ]
+
+            [preset.backwards]
+            waves = [
+                { after = "2 days", fleet_percentage = 10 },
+                { after = "1 day", fleet_percentage = 100 },
+            ]
+            "#,
+        )
+        .unwrap();
+        let presets = Presets::load(Some(&path)).unwrap();
+        let start = Utc::now();
+
+        // A preset in the file replaces the built-in one with its name.
+        let waves = presets.waves("canary", start).unwrap().waves;
+        assert_eq!(waves.len(), 2);
+        assert_eq!(
+            waves[1].start_after,
+            (start + Duration::hours(12)).to_rfc3339_opts(SecondsFormat::Secs, true)
+        );
+        assert!(presets.waves("linear", start).is_ok());
+        assert!(presets.waves("backwards", start).is_err());
+    }
+}
@@ -0,0 +1,280 @@
+//! Drops old updates from a manifest, so it doesn't grow with every release.  Updates are pruned
+//! per variant and architecture: the newest ones are kept, along with any update a kept one holds
+//! hosts at through its max version.  An update's age is when its first wave starts; updates
+//! without waves have no age, so `--older-than` keeps them.
+//!
+//! Migrations are keyed by version alone, so they're shared by every variant.  Once nothing in the
+//! manifest is older than a version, the migrations that only lead up to it are dropped too.  The
+//! ones into the oldest remaining version are kept, so hosts on the release just before it can
+//! still update, and every path between remaining versions is left as it was.
+
+use crate::error::{self, Result};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use semver::Version;
+use snafu::{ensure, ResultExt};
+use std::collections::{BTreeMap, HashSet};
+use update_metadata::{Manifest, Update};
+
+/// Which updates to prune.  An update has to match every rule that's given to be pruned.
+#[derive(Debug, Default)]
+pub(crate) struct Rules<'a> {
+    /// How many of the newest updates of each variant and architecture to keep.
+    pub(crate) keep_last: Option<usize>,
+    /// Only updates whose first wave started before this are pruned.
+    pub(crate) older_than: Option<DateTime<Utc>>,
+    pub(crate) variant: Option<&'a str>,
+    pub(crate) arch: Option<&'a str>,
+}
+
+/// Parses the cutoff for `--older-than`.  It has to be in the past, since a cutoff in the future
+/// would make every update old enough, so amounts of time are counted back from now: "90 days
+/// ago", "-90d", or "now-90d".  A date on its own, like "2024-01-01", is midnight UTC, and
+/// anything else is parsed like the other times updata takes, then refused if it's still to come.
+pub(crate) fn parse_cutoff(input: &str) -> Result<DateTime<Utc>> {
+    parse_cutoff_at(input, Utc::now())
+}
+
+/// Parses a cutoff like `parse_cutoff`, with amounts of time counted back from `now`.
+pub(crate) fn parse_cutoff_at(input: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let text = input.trim();
+    let ago = match text.strip_suffix("ago") {
+        Some(interval) => Some(interval),
+        None => text
+            .strip_prefix("now")
+            .unwrap_or(text)
+            .trim_start()
+            .strip_prefix('-'),
+    };
+    let cutoff = if let Some(interval) = ago {
+        let interval = parse_datetime::parse_interval(interval.trim())
+            .context(error::PruneCutoff { input })?;
+        // Further back than chrono can count is older than anything in a manifest.
+        now.checked_sub_signed(interval)
+            .unwrap_or_else(|| chrono::MIN_DATE.and_hms(0, 0, 0))
+    } else if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        Utc.from_utc_date(&date).and_hms(0, 0, 0)
+    } else {
+        parse_datetime::parse_datetime_at(text, now).context(error::PruneCutoff { input })?
+    };
+    ensure!(cutoff <= now, error::PruneCutoffFuture { input, cutoff });
+    Ok(cutoff)
+}
+
+/// What was pruned from a manifest.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Pruned {
+    /// The updates removed, like `x86_64-aws-k8s-1.15-1.0.6`.
+    pub(crate) updates: Vec<String>,
+    pub(crate) migrations: Vec<(Version, Version)>,
+}
+
+impl Pruned {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.updates.is_empty() && self.migrations.is_empty()
+    }
+}
+
+fn update_name(update: &Update) -> String {
+    format!("{}-{}-{}", update.arch, update.variant, update.version)
+}
+
+/// Removes the updates that match the rules, and the migrations no remaining update needs.
+pub(crate) fn prune(manifest: &mut Manifest, rules: &Rules<'_>) -> Pruned {
+    // Each variant and architecture's updates, newest first.
+    let mut groups: BTreeMap<(&str, &str), Vec<&Update>> = BTreeMap::new();
+    for update in &manifest.updates {
+        if rules
+            .variant
+            .map_or(false, |variant| update.variant != variant)
+            || rules.arch.map_or(false, |arch| update.arch != arch)
+        {
+            continue;
+        }
+        groups
+            .entry((&update.variant, &update.arch))
+            .or_default()
+            .push(update);
+    }
+
+    let mut remove = HashSet::new();
+    for updates in groups.values_mut() {
+        updates.sort_by(|a, b| b.version.cmp(&a.version));
+        // The newest update is always kept, so hosts have something to update to.
+        let keep_last = rules.keep_last.unwrap_or(0).max(1);
+        let kept = &updates[..keep_last.min(updates.len())];
+        let held_at: HashSet<_> = kept.iter().map(|update| &update.max_version).collect();
+        for update in updates.iter().skip(keep_last) {
+            let old_enough = rules.older_than.map_or(true, |cutoff| {
+                update
+                    .waves
+                    .values()
+                    .next()
+                    .map_or(false, |start| *start < cutoff)
+            });
+            if old_enough && !held_at.contains(&update.version) {
+                remove.insert((
+                    update.variant.clone(),
+                    update.arch.clone(),
+                    update.version.clone(),
+                ));
+            }
+        }
+    }
+
+    let mut pruned = Pruned::default();
+    manifest.updates.retain(|update| {
+        let key = (
+            update.variant.clone(),
+            update.arch.clone(),
+            update.version.clone(),
+        );
+        if remove.contains(&key) {
+            pruned.updates.push(update_name(update));
+            return false;
+        }
+        true
+    });
+
+    if let Some(oldest) = manifest
+        .updates
+        .iter()
+        .map(|update| update.version.clone())
+        .min()
+    {
+        manifest.migrations.retain(|(from, to), _| {
+            if *to < oldest {
+                pruned.migrations.push((from.clone(), to.clone()));
+                return false;
+            }
+            true
+        });
+    }
+    pruned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn update(variant: &str, version: &str, max_version: &str, day: Option<u32>) -> String {
+        let waves = day.map_or(String::new(), |day| {
+            format!(r#""0": "2020-06-{:02}T09:00:00Z""#, day)
+        });
+        format!(
+            r#"{{"variant": "{}", "arch": "x86_64", "version": "{}", "max_version": "{}",
+                "waves": {{{}}}, "images": {{"boot": "boot", "root": "root", "hash": "hash"}}}}"#,
+            variant, version, max_version, waves
+        )
+    }
+
+    fn manifest() -> Manifest {
+        let updates = [
+            update("aws-k8s-1.15", "1.0.0", "1.3.0", Some(1)),
+            update("aws-k8s-1.15", "1.1.0", "1.3.0", None),
+            update("aws-k8s-1.15", "1.2.0", "1.3.0", Some(10)),
+            update("aws-k8s-1.15", "1.3.0", "1.3.0", Some(20)),
+            update("aws-dev", "1.1.0", "1.1.0", Some(5)),
+            update("aws-dev", "1.2.0", "1.1.0", Some(12)),
+        ];
+        serde_json::from_str(&format!(
+            r#"{{"updates": [{}], "migrations": {{
+                "(0.9.0, 1.0.0)": ["a"], "(1.0.0, 1.1.0)": ["b"],
+                "(1.1.0, 1.2.0)": ["c"], "(1.2.0, 1.3.0)": ["d"]}}}}"#,
+            updates.join(",")
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn keep_last() {
+        let mut manifest = manifest();
+        let pruned = prune(
+            &mut manifest,
+            &Rules {
+                keep_last: Some(1),
+                ..Rules::default()
+            },
+        );
+        // aws-dev's 1.2.0 holds hosts at 1.1.0, so that one stays.
+        assert_eq!(
+            pruned.updates,
+            vec![
+                "x86_64-aws-k8s-1.15-1.0.0",
+                "x86_64-aws-k8s-1.15-1.1.0",
+                "x86_64-aws-k8s-1.15-1.2.0"
+            ]
+        );
+        // aws-dev still has 1.1.0, so only the migrations up to 1.0.0 go.
+        assert_eq!(
+            pruned.migrations,
+            vec![(Version::new(0, 9, 0), Version::new(1, 0, 0))]
+        );
+        assert_eq!(manifest.updates.len(), 3);
+    }
+
+    #[test]
+    fn cutoffs() {
+        let now = Utc.ymd(2020, 6, 15).and_hms(12, 0, 0);
+        for (input, expected) in &[
+            ("90 days ago", Utc.ymd(2020, 3, 17).and_hms(12, 0, 0)),
+            ("2w ago", Utc.ymd(2020, 6, 1).and_hms(12, 0, 0)),
+            ("-90d", Utc.ymd(2020, 3, 17).and_hms(12, 0, 0)),
+            ("now-4h", Utc.ymd(2020, 6, 15).and_hms(8, 0, 0)),
+            ("now", now),
+            ("2020-01-01", Utc.ymd(2020, 1, 1).and_hms(0, 0, 0)),
+            (
+                "2020-01-01 09:00 PST",
+                Utc.ymd(2020, 1, 1).and_hms(17, 0, 0),
+            ),
+            ("2020-01-01T09:00:00Z", Utc.ymd(2020, 1, 1).and_hms(9, 0, 0)),
+            // Further back than chrono can count.
+            ("4294967295 weeks ago", chrono::MIN_DATE.and_hms(0, 0, 0)),
+        ] {
+            assert_eq!(parse_cutoff_at(input, now).unwrap(), *expected, "{}", input);
+        }
+
+        // Amounts of time on their own count forward, so they're refused, like any future time.
+        for input in &[
+            "90 days",
+            "90d",
+            "in 90 days",
+            "+90d",
+            "2020-07-01",
+            "ago",
+            "-",
+        ] {
+            assert!(parse_cutoff_at(input, now).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn older_than_one_variant() {
+        let mut manifest = manifest();
+        let pruned = prune(
+            &mut manifest,
+            &Rules {
+                older_than: Some(Utc.ymd(2020, 6, 15).and_hms(0, 0, 0)),
+                variant: Some("aws-k8s-1.15"),
+                ..Rules::default()
+            },
+        );
+        // 1.1.0 has no waves, so it has no age; the newest is always kept.
+        assert_eq!(
+            pruned.updates,
+            vec!["x86_64-aws-k8s-1.15-1.0.0", "x86_64-aws-k8s-1.15-1.2.0"]
+        );
+        assert_eq!(
+            pruned.migrations,
+            vec![(Version::new(0, 9, 0), Version::new(1, 0, 0))]
+        );
+
+        // Nothing matches an architecture without updates.
+        let rules = Rules {
+            keep_last: Some(1),
+            arch: Some("aarch64"),
+            ..Rules::default()
+        };
+        assert!(prune(&mut manifest, &rules).is_empty());
+    }
+}
@@ -0,0 +1,306 @@
+//! Estimates how much hosts download from a repository, so decisions like sharding the manifest
+//! can be based on numbers.  Metadata and target sizes come from the repository's signed metadata,
+//! so the images don't need to be present; the manifest sizes are what updata would publish.
+
+use crate::error::{self, Result};
+use semver::Version;
+use serde::Deserialize;
+use snafu::ResultExt;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use update_metadata::Manifest;
+
+/// The parts of a TUF metadata file the report needs.
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    signed: Signed,
+}
+
+#[derive(Debug, Deserialize)]
+struct Signed {
+    #[serde(default)]
+    targets: HashMap<String, TargetInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TargetInfo {
+    length: u64,
+}
+
+/// The sizes of the metadata files a host fetches when checking for updates.  The timestamp is
+/// fetched on every check, while the snapshot and targets are only fetched when they've changed.
+#[derive(Debug)]
+pub(crate) struct MetadataSizes {
+    timestamp: u64,
+    snapshot: u64,
+    targets: u64,
+    target_lengths: HashMap<String, u64>,
+}
+
+impl MetadataSizes {
+    /// Reads the sizes of the metadata files in `dir`, and the target lengths listed in
+    /// targets.json.
+    pub(crate) fn load(dir: &Path) -> Result<Self> {
+        let size = |name: &str| -> Result<u64> {
+            let path = dir.join(name);
+            Ok(fs::metadata(&path)
+                .context(error::RepoMetadataRead { path: &path })?
+                .len())
+        };
+        let path = dir.join("targets.json");
+        let data = fs::read(&path).context(error::RepoMetadataRead { path: &path })?;
+        let targets: Metadata =
+            serde_json::from_slice(&data).context(error::RepoMetadataParse { path: &path })?;
+        Ok(Self {
+            timestamp: size("timestamp.json")?,
+            snapshot: size("snapshot.json")?,
+            targets: data.len() as u64,
+            target_lengths: targets
+                .signed
+                .targets
+                .into_iter()
+                .map(|(name, info)| (name, info.length))
+                .collect(),
+        })
+    }
+}
+
+/// Fleet details for projecting total downloads.
+#[derive(Debug)]
+pub(crate) struct Fleet {
+    pub(crate) hosts: u64,
+    pub(crate) checks_per_day: u64,
+}
+
+/// The download needed to take an update, as far as the metadata says.
+#[derive(Debug)]
+struct UpdateSize {
+    version: Version,
+    images: u64,
+    migrations: u64,
+    num_migrations: usize,
+    /// Targets the metadata doesn't list, which aren't counted.
+    unknown: Vec<String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct SizeReport {
+    variant: String,
+    index: u64,
+    shard: Option<u64>,
+    metadata: Option<MetadataSizes>,
+    update: Option<UpdateSize>,
+    fleet: Fleet,
+}
+
+impl SizeReport {
+    /// Builds the report for hosts of `variant` and `arch` checking `manifest`, and taking the
+    /// newest update for them.
+    pub(crate) fn new(
+        manifest: &Manifest,
+        variant: &str,
+        arch: &str,
+        metadata: Option<MetadataSizes>,
+        fleet: Fleet,
+    ) -> Result<Self> {
+        let (index, shards) = manifest.serialize_sharded()?;
+        let shard = manifest
+            .shards
+            .get(variant)
+            .and_then(|name| shards.get(name))
+            .map(|shard| shard.len() as u64);
+
+        let latest = manifest
+            .updates
+            .iter()
+            .filter(|u| u.variant == variant && u.arch == arch)
+            .max_by(|a, b| a.version.cmp(&b.version));
+        let update = match (latest, &metadata) {
+            (Some(update), Some(metadata)) => {
+                let mut size = UpdateSize {
+                    version: update.version.clone(),
+                    images: 0,
+                    migrations: 0,
+                    num_migrations: 0,
+                    unknown: Vec::new(),
+                };
+                let images = &update.images;
+                for target in &[&images.boot, &images.root, &images.hash] {
+                    match metadata.target_lengths.get(*target) {
+                        Some(length) => size.images += length,
+                        None => size.unknown.push((*target).clone()),
+                    }
+                }
+                // The migrations into this version, which a host on the version before needs.
+                for target in manifest
+                    .migrations
+                    .iter()
+                    .filter(|((_, to), _)| *to == update.version)
+                    .flat_map(|(_, migrations)| migrations)
+                {
+                    size.num_migrations += 1;
+                    match metadata.target_lengths.get(target) {
+                        Some(length) => size.migrations += length,
+                        None => size.unknown.push(target.clone()),
+                    }
+                }
+                Some(size)
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            variant: variant.to_string(),
+            index: index.len() as u64,
+            shard,
+            metadata,
+            update,
+            fleet,
+        })
+    }
+
+    /// The least and most a host downloads for one check: the timestamp and manifest every time,
+    /// and the snapshot and targets when they've changed since the last check.
+    fn per_check(&self) -> (u64, u64) {
+        let manifest = self.index + self.shard.unwrap_or(0);
+        match &self.metadata {
+            Some(m) => (
+                manifest + m.timestamp,
+                manifest + m.timestamp + m.snapshot + m.targets,
+            ),
+            None => (manifest, manifest),
+        }
+    }
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Manifest:          {}", Size(self.index))?;
+        if let Some(shard) = self.shard {
+            writeln!(f, "Shard for {}: {}", self.variant, Size(shard))?;
+        }
+        if let Some(m) = &self.metadata {
+            writeln!(f, "timestamp.json:    {}", Size(m.timestamp))?;
+            writeln!(f, "snapshot.json:     {}", Size(m.snapshot))?;
+            writeln!(f, "targets.json:      {}", Size(m.targets))?;
+        }
+
+        let (least, most) = self.per_check();
+        if least == most {
+            writeln!(f, "Per check:         {} (without metadata)", Size(least))?;
+        } else {
+            writeln!(
+                f,
+                "Per check:         {} to {} (when the snapshot and targets changed)",
+                Size(least),
+                Size(most)
+            )?;
+        }
+        if let Some(update) = &self.update {
+            writeln!(
+                f,
+                "Per update to {}: {} ({} of images, {} of {} migration(s))",
+                update.version,
+                Size(update.images + update.migrations),
+                Size(update.images),
+                Size(update.migrations),
+                update.num_migrations
+            )?;
+            for target in &update.unknown {
+                writeln!(
+                    f,
+                    "  not counted, since it isn't in targets.json: {}",
+                    target
+                )?;
+            }
+        }
+
+        let checks = self.fleet.hosts * self.fleet.checks_per_day;
+        writeln!(
+            f,
+            "Fleet of {} host(s) checking {} time(s) a day:",
+            self.fleet.hosts, self.fleet.checks_per_day
+        )?;
+        write!(
+            f,
+            "  checks:          {} to {} a day",
+            Size(least * checks),
+            Size(most * checks)
+        )?;
+        if let Some(update) = &self.update {
+            write!(
+                f,
+                "\n  update:          {} in all",
+                Size((update.images + update.migrations) * self.fleet.hosts)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Shows a number of bytes in binary units, like "3.5 MiB".
+struct Size(u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let mut size = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(Size(512).to_string(), "512 B");
+        assert_eq!(Size(1536).to_string(), "1.5 KiB");
+        assert_eq!(Size(3 * 1024 * 1024 * 1024).to_string(), "3.0 GiB");
+    }
+
+    #[test]
+    fn report() {
+        let manifest = update_metadata::load_file(Path::new("tests/data/example_3.json")).unwrap();
+        let update = &manifest.updates[1];
+        let mut target_lengths = HashMap::new();
+        target_lengths.insert(update.images.boot.clone(), 10 * 1024 * 1024);
+        target_lengths.insert(update.images.root.clone(), 500 * 1024 * 1024);
+        let metadata = MetadataSizes {
+            timestamp: 1024,
+            snapshot: 1024,
+            targets: 100 * 1024,
+            target_lengths,
+        };
+        let fleet = Fleet {
+            hosts: 1000,
+            checks_per_day: 4,
+        };
+        let report =
+            SizeReport::new(&manifest, "aws-k8s-1.15", "x86_64", Some(metadata), fleet).unwrap();
+
+        let (least, most) = report.per_check();
+        assert_eq!(least, report.index + 1024);
+        assert_eq!(most, report.index + 102 * 1024);
+        let update = report.update.as_ref().unwrap();
+        assert_eq!(update.version, Version::parse("0.1.2").unwrap());
+        assert_eq!(update.images, 510 * 1024 * 1024);
+        // The hash image isn't in the metadata, so it's called out rather than counted.
+        assert_eq!(update.unknown.len(), 1);
+        assert!(report
+            .to_string()
+            .contains("update:          498.0 GiB in all"));
+    }
+}
@@ -0,0 +1,381 @@
+//! Rotates the keys that sign the repository's metadata without leaving hosts behind.  For a
+//! while, the metadata is signed with both the old and the new keys, by `updata sign-metadata`,
+//! and a new root lists both, so a host accepts the metadata whichever root it's reached; TUF only
+//! asks that each role's signatures from the keys its root lists meet the role's threshold.
+//!
+//! Before the old key is retired, `updata check-rotation` checks that the trusted roots the fleet
+//! ships with can each walk the repository's roots to the latest one, and that each role of the
+//! latest metadata is signed by enough keys besides the old one to meet its threshold.
+
+use crate::error::{self, Result};
+use ring::rand::SystemRandom;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt};
+use std::fmt;
+use std::fs;
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+use tough::schema::decoded::{Decoded, Hex};
+use tough::schema::{Role, RoleType, Root, Signature, Signed, Snapshot, Targets, Timestamp};
+use tough::sign::Sign;
+
+/// Each version of the root is kept as `N.root.json`.
+const ROOT_SUFFIX: &str = ".root.json";
+
+/// Just enough of a metadata file to tell which role it is.
+#[derive(Deserialize)]
+struct RoleOf {
+    signed: RoleKind,
+}
+
+#[derive(Deserialize)]
+struct RoleKind {
+    #[serde(rename = "_type")]
+    role: RoleType,
+}
+
+/// Adds a signature by each of `keys` to the metadata file at `path`, replacing any earlier
+/// signature by the same key and keeping the others, and returns the IDs of the keys.
+pub(crate) fn sign(path: &Path, keys: &[&dyn Sign]) -> Result<Vec<String>> {
+    let data = fs::read(path).context(error::RepoMetadataRead { path })?;
+    let kind: RoleOf = serde_json::from_slice(&data).context(error::RepoMetadataParse { path })?;
+    let (data, key_ids) = match kind.signed.role {
+        RoleType::Root => add_signatures::<Root>(path, &data, keys),
+        RoleType::Snapshot => add_signatures::<Snapshot>(path, &data, keys),
+        RoleType::Targets => add_signatures::<Targets>(path, &data, keys),
+        RoleType::Timestamp => add_signatures::<Timestamp>(path, &data, keys),
+    }?;
+    fs::write(path, data).context(error::RepoMetadataWrite { path })?;
+    Ok(key_ids)
+}
+
+fn add_signatures<T>(path: &Path, data: &[u8], keys: &[&dyn Sign]) -> Result<(Vec<u8>, Vec<String>)>
+where
+    T: Role + Serialize + DeserializeOwned,
+{
+    let mut signed: Signed<T> =
+        serde_json::from_slice(data).context(error::RepoMetadataParse { path })?;
+    let message = signed
+        .signed
+        .canonical_form()
+        .context(error::RepoMetadataCanonical { path })?;
+    let mut key_ids = Vec::new();
+    for key in keys {
+        let keyid = key
+            .tuf_key()
+            .key_id()
+            .context(error::RepoMetadataCanonical { path })?;
+        let sig = key
+            .sign(&message, &SystemRandom::new())
+            .context(error::RepoMetadataSign { path })?;
+        signed
+            .signatures
+            .retain(|signature| signature.keyid != keyid);
+        key_ids.push(crate::mirror::hex(&keyid));
+        signed.signatures.push(Signature {
+            keyid,
+            sig: sig.into(),
+        });
+    }
+    let data = serde_json::to_vec_pretty(&signed).context(error::UpdateSerialize)?;
+    Ok((data, key_ids))
+}
+
+/// Whether one of the fleet's trusted roots reaches the latest root.
+#[derive(Debug, Serialize)]
+struct RootCheck {
+    path: PathBuf,
+    /// The version of the trusted root.
+    version: u64,
+    /// The last root it could walk to.
+    reached: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// How many keys besides the retired one signed a role of the latest metadata.
+#[derive(Debug, Serialize)]
+struct RoleCheck {
+    role: RoleType,
+    file: String,
+    threshold: u64,
+    signed: u64,
+}
+
+/// What `check` found.
+#[derive(Debug, Serialize)]
+pub(crate) struct Rotation {
+    retired: String,
+    latest_root: u64,
+    /// Whether the latest root still lists the retired key.  Once the check passes, the next root
+    /// can drop it.
+    retired_listed: bool,
+    roots: Vec<RootCheck>,
+    roles: Vec<RoleCheck>,
+}
+
+impl Rotation {
+    /// How many of the checks failed.
+    pub(crate) fn problems(&self) -> usize {
+        let roots = self
+            .roots
+            .iter()
+            .filter(|root| root.reached != self.latest_root)
+            .count();
+        let roles = self
+            .roles
+            .iter()
+            .filter(|role| role.signed < role.threshold)
+            .count();
+        roots + roles
+    }
+}
+
+impl fmt::Display for Rotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mark = |ok: bool| if ok { "ok  " } else { "FAIL" };
+        for root in &self.roots {
+            write!(
+                f,
+                "{} trusted root {} (version {}) reaches root {}",
+                mark(root.reached == self.latest_root),
+                root.path.display(),
+                root.version,
+                root.reached
+            )?;
+            match &root.error {
+                Some(error) => writeln!(f, ": {}", error)?,
+                None => writeln!(f)?,
+            }
+        }
+        for role in &self.roles {
+            writeln!(
+                f,
+                "{} {} ({}) is signed by {} key(s) besides {}, and needs {}",
+                mark(role.signed >= role.threshold),
+                role.role,
+                role.file,
+                role.signed,
+                self.retired,
+                role.threshold
+            )?;
+        }
+        if !self.retired_listed {
+            writeln!(
+                f,
+                "note {} isn't listed in root {}",
+                self.retired, self.latest_root
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks whether the key `retired` can stop signing the repository metadata in `dir`, for hosts
+/// that trust the roots at `trusted_roots`.
+pub(crate) fn check(
+    dir: &Path,
+    trusted_roots: &[PathBuf],
+    retired: &Decoded<Hex>,
+) -> Result<Rotation> {
+    let latest_version = latest_root(dir)?;
+    let root_file = format!("{}{}", latest_version, ROOT_SUFFIX);
+    let latest: Signed<Root> = read(&dir.join(&root_file))?;
+
+    let mut roots = Vec::new();
+    for path in trusted_roots {
+        let trusted: Signed<Root> = read(path)?;
+        let (reached, error) = walk(dir, trusted.clone());
+        roots.push(RootCheck {
+            path: path.clone(),
+            version: trusted.signed.version.get(),
+            reached,
+            error,
+        });
+    }
+
+    let root = &latest.signed;
+    let timestamp: Signed<Timestamp> = read(&dir.join("timestamp.json"))?;
+    let snapshot_file = versioned(root, "snapshot.json", |name| {
+        timestamp.signed.meta.get(name).map(|meta| meta.version)
+    });
+    let snapshot: Signed<Snapshot> = read(&dir.join(&snapshot_file))?;
+    let targets_file = versioned(root, "targets.json", |name| {
+        snapshot.signed.meta.get(name).map(|meta| meta.version)
+    });
+    let targets: Signed<Targets> = read(&dir.join(&targets_file))?;
+    let roles = vec![
+        role_check(root, &latest, root_file, retired),
+        role_check(root, &timestamp, "timestamp.json".to_string(), retired),
+        role_check(root, &snapshot, snapshot_file, retired),
+        role_check(root, &targets, targets_file, retired),
+    ];
+
+    Ok(Rotation {
+        retired: crate::mirror::hex(retired),
+        latest_root: latest_version,
+        retired_listed: root
+            .roles
+            .values()
+            .any(|keys| keys.keyids.contains(retired)),
+        roots,
+        roles,
+    })
+}
+
+fn read<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let data = fs::read(path).context(error::RepoMetadataRead { path })?;
+    serde_json::from_slice(&data).context(error::RepoMetadataParse { path })
+}
+
+/// Finds the version of the latest `N.root.json` in `dir`.
+fn latest_root(dir: &Path) -> Result<u64> {
+    let entries = fs::read_dir(dir).context(error::RepoMetadataRead { path: dir })?;
+    let mut latest = None;
+    for entry in entries {
+        let name = entry
+            .context(error::RepoMetadataRead { path: dir })?
+            .file_name();
+        let version = name
+            .to_str()
+            .filter(|name| name.ends_with(ROOT_SUFFIX))
+            .and_then(|name| name[..name.len() - ROOT_SUFFIX.len()].parse::<u64>().ok());
+        latest = latest.max(version);
+    }
+    latest.context(error::RootMissing { path: dir })
+}
+
+/// The file name hosts fetch a role from: `name`, or with consistent snapshots, `name` prefixed by
+/// the version the role that lists it gives.
+fn versioned<F>(root: &Root, name: &str, version: F) -> String
+where
+    F: Fn(&str) -> Option<NonZeroU64>,
+{
+    match version(name) {
+        Some(version) if root.consistent_snapshot => format!("{}.{}", version, name),
+        _ => name.to_string(),
+    }
+}
+
+/// Walks from `root` through the roots in `dir` the way a host does: each root has to be signed
+/// by enough of the keys the one before it lists, and by enough of its own.  Returns the version
+/// reached, and why the walk stopped short if it did.
+fn walk(dir: &Path, mut root: Signed<Root>) -> (u64, Option<String>) {
+    loop {
+        let version = root.signed.version.get();
+        let path = dir.join(format!("{}{}", version + 1, ROOT_SUFFIX));
+        if !path.exists() {
+            return (version, None);
+        }
+        let next: Signed<Root> = match read(&path) {
+            Ok(next) => next,
+            Err(e) => return (version, Some(e.to_string())),
+        };
+        let verified = root
+            .signed
+            .verify_role(&next)
+            .and_then(|()| next.signed.verify_role(&next));
+        if let Err(e) = verified {
+            return (
+                version,
+                Some(format!("root {} doesn't verify: {}", version + 1, e)),
+            );
+        }
+        root = next;
+    }
+}
+
+/// Counts the keys `root` lists for a role, other than `retired`, that signed `role`.
+fn role_check<T: Role + Serialize>(
+    root: &Root,
+    role: &Signed<T>,
+    file: String,
+    retired: &Decoded<Hex>,
+) -> RoleCheck {
+    let (keyids, threshold) = match root.roles.get(&T::TYPE) {
+        Some(keys) => (keys.keyids.clone(), keys.threshold.get()),
+        None => (Vec::new(), 1),
+    };
+    let mut signed = 0;
+    for keyid in keyids.into_iter().filter(|keyid| keyid != retired) {
+        // verify_role only says whether the threshold was met, so check one key at a time.
+        let mut single = root.clone();
+        if let Some(keys) = single.roles.get_mut(&T::TYPE) {
+            keys.keyids = vec![keyid];
+            keys.threshold = NonZeroU64::new(1).unwrap();
+        }
+        if single.verify_role(role).is_ok() {
+            signed += 1;
+        }
+    }
+    RoleCheck {
+        role: T::TYPE,
+        file,
+        threshold,
+        signed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tough::sign::parse_keypair;
+    use update_harness::RepoBuilder;
+
+    fn key(path: &str) -> impl Sign {
+        parse_keypair(&fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn rotate_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let trusted_root = RepoBuilder::new().write(dir.path());
+        let metadata = dir.path().join("metadata");
+        let old = key("../update-harness/data/test-key.pem");
+        let new = key("tests/data/rotation-key.pem");
+        let old_id = old.tuf_key().key_id().unwrap();
+        let new_id = new.tuf_key().key_id().unwrap();
+
+        // Everything is only signed with the old key so far.
+        let rotation = check(&metadata, std::slice::from_ref(&trusted_root), &old_id).unwrap();
+        assert!(rotation.retired_listed);
+        assert_eq!(rotation.problems(), 4);
+
+        // The next root lists both keys for every role.  Signed with only the new key, hosts
+        // that trust the old root can't move to it.
+        let mut root: Signed<Root> = read(&trusted_root).unwrap();
+        root.signed.version = NonZeroU64::new(2).unwrap();
+        root.signed.keys.insert(new_id.clone(), new.tuf_key());
+        for keys in root.signed.roles.values_mut() {
+            keys.keyids.push(new_id.clone());
+        }
+        root.signatures.clear();
+        let next_root = metadata.join("2.root.json");
+        fs::write(&next_root, serde_json::to_vec(&root).unwrap()).unwrap();
+        assert_eq!(
+            sign(&next_root, &[&new]).unwrap(),
+            vec![crate::mirror::hex(&new_id)]
+        );
+        let rotation = check(&metadata, std::slice::from_ref(&trusted_root), &old_id).unwrap();
+        assert_eq!(rotation.latest_root, 2);
+        assert_eq!(rotation.roots[0].reached, 1);
+        assert!(rotation.roots[0].error.is_some());
+        assert_eq!(rotation.problems(), 4);
+
+        // Signed with both, they can, and once the other roles are signed with the new key too,
+        // the old one can be retired.
+        sign(&next_root, &[&old, &new]).unwrap();
+        let signed: Signed<Root> = read(&next_root).unwrap();
+        assert_eq!(signed.signatures.len(), 2);
+        for file in &["timestamp.json", "snapshot.json", "targets.json"] {
+            sign(&metadata.join(file), &[&new]).unwrap();
+        }
+        let rotation = check(&metadata, &[trusted_root, next_root], &old_id).unwrap();
+        assert_eq!(rotation.problems(), 0, "{}", rotation);
+        assert!(rotation.roots.iter().all(|root| root.reached == 2));
+
+        // The old key's signatures were kept, so until they're dropped, either key could go.
+        assert_eq!(check(&metadata, &[], &new_id).unwrap().problems(), 0);
+    }
+}
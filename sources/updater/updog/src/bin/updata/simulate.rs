@@ -0,0 +1,242 @@
+//! What a host would do with a manifest, found the way updog finds it, so a manifest can be
+//! checked against the hosts it's for before it's published.  The host is described by what
+//! updog goes by: its variant, architecture, version, seed, and labels.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use semver::Version;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use update_metadata::{Manifest, MockClock, Wave};
+
+/// A host to simulate.
+pub(crate) struct Host {
+    pub(crate) variant: String,
+    pub(crate) arch: String,
+    pub(crate) version: Version,
+    pub(crate) seed: u32,
+    pub(crate) labels: BTreeMap<String, String>,
+}
+
+/// The time between two points, either of which may be open.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct Window {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) start: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) end: Option<DateTime<Utc>>,
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = |time: Option<DateTime<Utc>>| {
+            time.map_or(String::from("..."), |time| {
+                time.to_rfc3339_opts(SecondsFormat::Secs, true)
+            })
+        };
+        write!(f, "{} to {}", time(self.start), time(self.end))
+    }
+}
+
+/// What the host would do at the simulated time.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Simulation {
+    /// The update the host would be offered.
+    pub(crate) variant: String,
+    pub(crate) arch: String,
+    pub(crate) version: Version,
+    pub(crate) max_version: Version,
+    /// The wave the host's seed falls into, if the update has waves.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) wave: Option<Window>,
+    /// Whether the host's wave has started, so it would take the update.
+    pub(crate) ready: bool,
+    /// The part of its wave the host would fetch the update in, if it's still to come.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) fetch_window: Option<Window>,
+    /// The migrations the host would fetch, in the order it fetches them.
+    pub(crate) migrations: Vec<String>,
+    /// Where the migration path stops, if it doesn't reach the version the host is moving to;
+    /// updog fails to take the update if so.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) migrations_stop_at: Option<Version>,
+}
+
+/// Returns what `host` would do with the manifest at time `at`, or None if it wouldn't be
+/// offered an update.
+pub(crate) fn simulate(manifest: &Manifest, host: &Host, at: DateTime<Utc>) -> Option<Simulation> {
+    let update = manifest.update_for(
+        &host.variant,
+        &host.arch,
+        &host.version,
+        host.seed,
+        &host.labels,
+    )?;
+    let clock = MockClock::new(at);
+    let (migrations, migrations_stop_at) = migrations(manifest, &host.version, &update.version);
+    Some(Simulation {
+        variant: update.variant.clone(),
+        arch: update.arch.clone(),
+        version: update.version.clone(),
+        max_version: update.max_version.clone(),
+        wave: update.update_wave(host.seed).map(|wave| match wave {
+            Wave::Initial { end } => Window {
+                start: None,
+                end: Some(end),
+            },
+            Wave::General { start, end } => Window {
+                start: Some(start),
+                end: Some(end),
+            },
+            Wave::Last { start } => Window {
+                start: Some(start),
+                end: None,
+            },
+        }),
+        ready: update.update_ready(host.seed, &clock),
+        fetch_window: update
+            .fetch_window(host.seed, at)
+            .map(|(start, end)| Window {
+                start: Some(start),
+                end: Some(end),
+            }),
+        migrations,
+        migrations_stop_at,
+    })
+}
+
+/// The migrations a host moving between `current` and `target` fetches, and where the path stops
+/// if it's incomplete.  Like updog, the migrations for going back down a path are taken to be the
+/// same as for going up it, and are fetched in name order.
+fn migrations(
+    manifest: &Manifest,
+    current: &Version,
+    target: &Version,
+) -> (Vec<String>, Option<Version>) {
+    let from = current.min(target);
+    let to = current.max(target);
+    let (path, reached) = manifest.migration_path(from, to);
+    let mut migrations: Vec<String> = path
+        .into_iter()
+        .filter_map(|transition| manifest.migrations.get(transition))
+        .flatten()
+        .cloned()
+        .collect();
+    migrations.sort();
+    let stop = if reached == to {
+        None
+    } else {
+        Some(reached.clone())
+    };
+    (migrations, stop)
+}
+
+impl fmt::Display for Simulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Update: {} {} {} (max version {})",
+            self.variant, self.arch, self.version, self.max_version
+        )?;
+        match &self.wave {
+            Some(wave) => writeln!(f, "Wave: {}", wave)?,
+            None => writeln!(f, "Wave: none, the update has no waves")?,
+        }
+        writeln!(
+            f,
+            "Ready: {}",
+            if self.ready {
+                "yes"
+            } else {
+                "no, the wave hasn't started"
+            }
+        )?;
+        if let Some(window) = &self.fetch_window {
+            writeln!(f, "Fetch window: {}", window)?;
+        }
+        if self.migrations.is_empty() {
+            write!(f, "Migrations: none")?;
+        } else {
+            write!(f, "Migrations: {}", self.migrations.join(", "))?;
+        }
+        if let Some(reached) = &self.migrations_stop_at {
+            write!(
+                f,
+                "\nMissing migrations: the path stops at {}, so the update would fail",
+                reached
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn host_view() {
+        let manifest: Manifest = serde_json::from_str(
+            r#"{
+                "updates": [
+                    {"variant": "aws-k8s-1.15", "arch": "x86_64", "version": "1.2.0",
+                     "max_version": "1.2.0", "paused": "2020-06-01T00:00:00Z",
+                     "waves": {}, "images": {"boot": "b", "root": "r", "hash": "h"}},
+                    {"variant": "aws-k8s-1.15", "arch": "x86_64", "version": "1.1.0",
+                     "max_version": "1.2.0",
+                     "waves": {"512": "2020-06-01T09:00:00Z", "1024": "2020-06-02T09:00:00Z"},
+                     "images": {"boot": "b", "root": "r", "hash": "h"}}
+                ],
+                "migrations": {
+                    "(1.0.0, 1.0.1)": ["migrate_1.0.1_b", "migrate_1.0.1_a"],
+                    "(1.0.1, 1.1.0)": ["migrate_1.1.0_c"]
+                }
+            }"#,
+        )
+        .unwrap();
+        let mut host = Host {
+            variant: "aws-k8s-1.15".to_string(),
+            arch: "x86_64".to_string(),
+            version: Version::new(1, 0, 0),
+            seed: 700,
+            labels: BTreeMap::new(),
+        };
+        let at = Utc.ymd(2020, 6, 1).and_hms(12, 0, 0);
+
+        // 1.2.0 is paused, so the host is offered 1.1.0, in the wave that started at 09:00.
+        let simulation = simulate(&manifest, &host, at).unwrap();
+        assert_eq!(simulation.version, Version::new(1, 1, 0));
+        assert_eq!(
+            simulation.wave,
+            Some(Window {
+                start: Some(Utc.ymd(2020, 6, 1).and_hms(9, 0, 0)),
+                end: Some(Utc.ymd(2020, 6, 2).and_hms(9, 0, 0)),
+            })
+        );
+        assert!(simulation.ready);
+        assert_eq!(
+            simulation.migrations,
+            vec!["migrate_1.0.1_a", "migrate_1.0.1_b", "migrate_1.1.0_c"]
+        );
+        assert_eq!(simulation.migrations_stop_at, None);
+
+        // The first wave has no start, and later waves wait.
+        host.seed = 100;
+        let simulation = simulate(&manifest, &host, at).unwrap();
+        assert_eq!(simulation.wave.unwrap().start, None);
+        host.seed = 2000;
+        let simulation = simulate(&manifest, &host, at).unwrap();
+        assert!(!simulation.ready);
+
+        // A host on a version the migrations don't start from can't get there.
+        host.version = Version::new(0, 9, 0);
+        let simulation = simulate(&manifest, &host, at).unwrap();
+        assert_eq!(simulation.migrations_stop_at, Some(Version::new(0, 9, 0)));
+
+        // Hosts already on the newest update they can take aren't offered anything.
+        host.version = Version::new(1, 1, 0);
+        assert_eq!(simulate(&manifest, &host, at), None);
+    }
+}
@@ -0,0 +1,223 @@
+//! A summary of what a manifest holds, as a quick check that a release changed what it meant to,
+//! or for dashboards with `--json`.
+
+use semver::Version;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use update_metadata::Manifest;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct Stats {
+    pub(crate) updates: usize,
+    pub(crate) oldest: Option<Version>,
+    pub(crate) newest: Option<Version>,
+    /// The updates for each variant and architecture.
+    pub(crate) variants: Vec<VariantStats>,
+    /// How many waves each update has.
+    pub(crate) waves: Vec<UpdateWaves>,
+    /// The data store versions the manifest's migrations move hosts between.
+    pub(crate) datastore_versions: BTreeSet<Version>,
+    pub(crate) migrations: usize,
+    /// How many different targets the manifest names, as images, provenance documents,
+    /// migrations, or shard files.
+    pub(crate) targets: usize,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct VariantStats {
+    pub(crate) variant: String,
+    pub(crate) arch: String,
+    pub(crate) updates: usize,
+    pub(crate) oldest: Version,
+    pub(crate) newest: Version,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct UpdateWaves {
+    pub(crate) variant: String,
+    pub(crate) arch: String,
+    pub(crate) version: Version,
+    pub(crate) waves: usize,
+}
+
+pub(crate) fn stats(manifest: &Manifest) -> Stats {
+    let mut variants: BTreeMap<(&str, &str), VariantStats> = BTreeMap::new();
+    let mut waves = Vec::new();
+    let mut targets = BTreeSet::new();
+    for update in &manifest.updates {
+        variants
+            .entry((update.variant.as_str(), update.arch.as_str()))
+            .and_modify(|stats| {
+                stats.updates += 1;
+                if update.version < stats.oldest {
+                    stats.oldest = update.version.clone();
+                }
+                if update.version > stats.newest {
+                    stats.newest = update.version.clone();
+                }
+            })
+            .or_insert_with(|| VariantStats {
+                variant: update.variant.clone(),
+                arch: update.arch.clone(),
+                updates: 1,
+                oldest: update.version.clone(),
+                newest: update.version.clone(),
+            });
+        waves.push(UpdateWaves {
+            variant: update.variant.clone(),
+            arch: update.arch.clone(),
+            version: update.version.clone(),
+            waves: update.waves.len(),
+        });
+        targets.insert(&update.images.boot);
+        targets.insert(&update.images.root);
+        targets.insert(&update.images.hash);
+        if let Some(provenance) = &update.provenance {
+            targets.insert(&provenance.target);
+        }
+    }
+    waves.sort_by(|a, b| (&a.variant, &a.arch, &a.version).cmp(&(&b.variant, &b.arch, &b.version)));
+    targets.extend(manifest.migrations.values().flatten());
+    targets.extend(manifest.shards.values());
+
+    Stats {
+        updates: manifest.updates.len(),
+        oldest: manifest.updates.iter().map(|u| &u.version).min().cloned(),
+        newest: manifest.updates.iter().map(|u| &u.version).max().cloned(),
+        variants: variants.into_iter().map(|(_, stats)| stats).collect(),
+        waves,
+        datastore_versions: manifest
+            .migrations
+            .keys()
+            .flat_map(|(from, to)| vec![from, to])
+            .cloned()
+            .collect(),
+        migrations: manifest.migrations.values().map(Vec::len).sum(),
+        targets: targets.len(),
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.oldest, &self.newest) {
+            (Some(oldest), Some(newest)) => writeln!(
+                f,
+                "{} update(s), from {} to {}",
+                self.updates, oldest, newest
+            )?,
+            _ => writeln!(f, "No updates")?,
+        }
+        for variant in &self.variants {
+            writeln!(
+                f,
+                "  {} {}: {} update(s), from {} to {}",
+                variant.variant, variant.arch, variant.updates, variant.oldest, variant.newest
+            )?;
+        }
+        if !self.waves.is_empty() {
+            writeln!(f, "Waves per update:")?;
+        }
+        for update in &self.waves {
+            writeln!(
+                f,
+                "  {} {} {}: {}",
+                update.variant, update.arch, update.version, update.waves
+            )?;
+        }
+        let versions: Vec<String> = self
+            .datastore_versions
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        if versions.is_empty() {
+            writeln!(f, "No data store versions with migrations")?;
+        } else {
+            writeln!(
+                f,
+                "Data store versions: {} ({} migration(s))",
+                versions.join(", "),
+                self.migrations
+            )?;
+        }
+        write!(f, "Targets referenced: {}", self.targets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(variant: &str, arch: &str, version: &str, waves: &str) -> String {
+        format!(
+            r#"{{
+                "variant": "{variant}",
+                "arch": "{arch}",
+                "version": "{version}",
+                "max_version": "{version}",
+                "waves": {waves},
+                "images": {{
+                    "boot": "{arch}-{version}-boot.ext4.lz4",
+                    "root": "{arch}-{version}-root.ext4.lz4",
+                    "hash": "{arch}-{version}-root.verity.lz4"
+                }}
+            }}"#,
+            variant = variant,
+            arch = arch,
+            version = version,
+            waves = waves
+        )
+    }
+
+    #[test]
+    fn summary() {
+        let two_waves = r#"{"0": "2020-06-01T00:00:00Z", "2047": "2020-06-02T00:00:00Z"}"#;
+        let updates = vec![
+            update("aws-k8s-1.15", "x86_64", "0.3.2", two_waves),
+            update("aws-k8s-1.15", "x86_64", "0.3.4", "{}"),
+            update("aws-k8s-1.15", "aarch64", "0.3.4", two_waves),
+            update("aws-dev", "x86_64", "0.3.1", two_waves),
+        ];
+        let manifest: Manifest = serde_json::from_str(&format!(
+            r#"{{
+                "updates": [{}],
+                "migrations": {{
+                    "(0.3.1, 0.3.2)": ["migrate_v0.3.2_a"],
+                    "(0.3.2, 0.3.4)": ["migrate_v0.3.4_b", "migrate_v0.3.4_c"]
+                }}
+            }}"#,
+            updates.join(",")
+        ))
+        .unwrap();
+
+        let stats = stats(&manifest);
+        assert_eq!(stats.updates, 4);
+        assert_eq!(stats.oldest, Some(Version::new(0, 3, 1)));
+        assert_eq!(stats.newest, Some(Version::new(0, 3, 4)));
+        // 3 images for each update, and 3 migrations.
+        assert_eq!(stats.targets, 15);
+        assert_eq!(
+            stats.to_string(),
+            "4 update(s), from 0.3.1 to 0.3.4
+  aws-dev x86_64: 1 update(s), from 0.3.1 to 0.3.1
+  aws-k8s-1.15 aarch64: 1 update(s), from 0.3.4 to 0.3.4
+  aws-k8s-1.15 x86_64: 2 update(s), from 0.3.2 to 0.3.4
+Waves per update:
+  aws-dev x86_64 0.3.1: 2
+  aws-k8s-1.15 aarch64 0.3.4: 2
+  aws-k8s-1.15 x86_64 0.3.2: 2
+  aws-k8s-1.15 x86_64 0.3.4: 0
+Data store versions: 0.3.1, 0.3.2, 0.3.4 (3 migration(s))
+Targets referenced: 15"
+        );
+    }
+
+    #[test]
+    fn empty() {
+        let stats = stats(&Manifest::default());
+        assert_eq!(
+            stats.to_string(),
+            "No updates\nNo data store versions with migrations\nTargets referenced: 0"
+        );
+    }
+}
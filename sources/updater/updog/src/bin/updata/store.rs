@@ -0,0 +1,356 @@
+//! Where updata reads and writes manifests: a local file, or an object in S3, given as
+//! `s3://bucket/path/manifest.json`.  A manifest in S3 is only written back if it hasn't changed
+//! since it was read, using the `ETag` S3 gave for each file, so two releases editing the same
+//! manifest at once can't lose each other's changes; the one that writes second fails, and can
+//! run the command again.  Local files get the same check, using `update_metadata`'s versioned
+//! loads.
+//!
+//! A file marked for a dry run is never written; writing it prints what would have changed
+//! instead, as a diff from the manifest as it's stored now.
+//!
+//! Local files can be set to keep backups, which are saved before each write; see `backup`.
+//! Manifests in S3 aren't backed up, since buckets can keep old versions of objects themselves.
+
+use crate::backup;
+use crate::diff::ManifestDiff;
+use crate::error::{self, Result};
+use crate::s3::S3Source;
+use reqwest::blocking::Client;
+use reqwest::header::ETAG;
+use reqwest::{Method, StatusCode};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use update_metadata::{Manifest, ManifestVersion};
+use url::Url;
+
+#[derive(Debug)]
+pub(crate) struct ManifestFile {
+    location: Location,
+    dry_run: Cell<bool>,
+    /// How many backups to keep when writing a local file; none are saved if it's 0.
+    backups: Cell<usize>,
+}
+
+#[derive(Debug)]
+enum Location {
+    Local(LocalFile),
+    S3(Box<S3File>),
+}
+
+#[derive(Debug)]
+pub(crate) struct LocalFile {
+    path: PathBuf,
+    /// The version of the manifest's files as they were last read or written.
+    version: RefCell<Option<ManifestVersion>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct S3File {
+    url: Url,
+    source: S3Source,
+    /// The `ETag` of each file as it was last read or written, by URL.
+    etags: RefCell<BTreeMap<Url, String>>,
+}
+
+impl FromStr for ManifestFile {
+    type Err = error::Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        if input.starts_with("s3://") {
+            let url = Url::parse(input).context(error::ManifestUrl { url: input })?;
+            Ok(Self::at(Location::S3(Box::new(S3File {
+                url,
+                source: S3Source::new(Client::new()),
+                etags: RefCell::default(),
+            }))))
+        } else {
+            Ok(Self::local(input))
+        }
+    }
+}
+
+impl ManifestFile {
+    pub(crate) fn local<P: Into<PathBuf>>(path: P) -> Self {
+        Self::at(Location::Local(LocalFile {
+            path: path.into(),
+            version: RefCell::default(),
+        }))
+    }
+
+    fn at(location: Location) -> Self {
+        Self {
+            location,
+            dry_run: Cell::new(false),
+            backups: Cell::new(0),
+        }
+    }
+
+    /// The path of the manifest, if it's a local file.
+    pub(crate) fn local_path(&self) -> Option<&Path> {
+        match &self.location {
+            Location::Local(file) => Some(&file.path),
+            Location::S3(_) => None,
+        }
+    }
+
+    /// Marks the file for a dry run, so it's never written.
+    pub(crate) fn set_dry_run(&self) {
+        self.dry_run.set(true);
+    }
+
+    pub(crate) fn is_dry_run(&self) -> bool {
+        self.dry_run.get()
+    }
+
+    /// Sets how many backups of a local file to keep, saving one before each write.
+    pub(crate) fn set_backups(&self, count: usize) {
+        self.backups.set(count);
+    }
+
+    /// Loads the manifest, along with the updates from any shard files it lists.
+    pub(crate) fn load(&self) -> Result<Manifest> {
+        match &self.location {
+            Location::Local(file) => {
+                let (manifest, version) = update_metadata::load_file_versioned(&file.path)?;
+                *file.version.borrow_mut() = Some(version);
+                Ok(manifest)
+            }
+            Location::S3(file) => file.load(),
+        }
+    }
+
+    /// Writes the manifest, and the updates for sharded variants to their shard files.  Files
+    /// that were read are only written if they're unchanged since; in S3, files that weren't read
+    /// are only written if they don't exist yet.
+    pub(crate) fn write(&self, manifest: &Manifest) -> Result<()> {
+        if self.is_dry_run() {
+            return self.show_write(manifest);
+        }
+        match &self.location {
+            Location::Local(file) => {
+                if self.backups.get() > 0 {
+                    backup::save(&file.path, self.backups.get())?;
+                }
+                let mut version = file.version.borrow_mut();
+                match &*version {
+                    Some(read) => {
+                        *version = Some(update_metadata::write_file_if_unchanged(
+                            &file.path, manifest, read,
+                        )?);
+                    }
+                    None => update_metadata::write_file(&file.path, manifest)?,
+                }
+                Ok(())
+            }
+            Location::S3(file) => file.write(manifest),
+        }
+    }
+
+    /// Prints what writing `manifest` would change, after checking it can be written.
+    fn show_write(&self, manifest: &Manifest) -> Result<()> {
+        manifest.serialize_sharded()?;
+        let stored = match &self.location {
+            Location::Local(file) if !file.path.exists() => Manifest::default(),
+            Location::Local(file) => update_metadata::load_file(&file.path)?,
+            Location::S3(file) => file.load_existing()?.unwrap_or_default(),
+        };
+        let diff = ManifestDiff::new(&stored, manifest);
+        if diff.is_empty() {
+            info!("Dry run: no changes to {}", self);
+        } else {
+            print!("{}", diff);
+            info!("Dry run: {} not written", self);
+        }
+        Ok(())
+    }
+}
+
+impl S3File {
+    fn load(&self) -> Result<Manifest> {
+        self.load_existing()?.context(error::S3ManifestMissing {
+            url: self.url.clone(),
+        })
+    }
+
+    /// Loads the manifest, or returns `None` if there isn't one yet.
+    fn load_existing(&self) -> Result<Option<Manifest>> {
+        let index = match self.get(&self.url)? {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let manifest = update_metadata::from_stored(&index, |name| {
+            let url = shard_url(&self.url, name)?;
+            self.get(&url)?.context(error::S3ManifestMissing { url })
+        })?;
+        Ok(Some(manifest))
+    }
+
+    fn write(&self, manifest: &Manifest) -> Result<()> {
+        let (index, shards) = manifest.serialize_sharded()?;
+        // Shards go first, like they do locally, so the index never lists a shard that isn't
+        // there.
+        for (name, shard) in shards {
+            self.put(&shard_url(&self.url, &name)?, shard)?;
+        }
+        self.put(&self.url, index)
+    }
+
+    /// Reads the object at `url`, remembering its `ETag`, or returns `None` if there isn't one.
+    fn get(&self, url: &Url) -> Result<Option<Vec<u8>>> {
+        let response = self
+            .source
+            .send(Method::GET, url, None, &[])
+            .context(error::S3ManifestRequest { url: url.clone() })?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = response
+            .error_for_status()
+            .context(error::S3ManifestResponse { url: url.clone() })?;
+        if let Some(etag) = etag(&response) {
+            self.etags.borrow_mut().insert(url.clone(), etag);
+        }
+        let body = response
+            .bytes()
+            .context(error::S3ManifestResponse { url: url.clone() })?;
+        Ok(Some(body.to_vec()))
+    }
+
+    /// Writes `data` to the object at `url`, if it still has the `ETag` it was read with, or if it
+    /// doesn't exist and wasn't read.
+    fn put(&self, url: &Url, data: String) -> Result<()> {
+        let read_etag = self.etags.borrow().get(url).cloned();
+        let condition = match &read_etag {
+            Some(etag) => ("if-match", etag.as_str()),
+            None => ("if-none-match", "*"),
+        };
+        let response = self
+            .source
+            .send(Method::PUT, url, Some(data.into_bytes()), &[condition])
+            .context(error::S3ManifestRequest { url: url.clone() })?;
+        // S3 answers 409 instead of 412 when another write to the object is still in progress.
+        ensure!(
+            response.status() != StatusCode::PRECONDITION_FAILED
+                && response.status() != StatusCode::CONFLICT,
+            error::S3ManifestConflict { url: url.clone() }
+        );
+        let response = response
+            .error_for_status()
+            .context(error::S3ManifestResponse { url: url.clone() })?;
+        // Keep the new `ETag`, so the editor can save again.
+        if let Some(etag) = etag(&response) {
+            self.etags.borrow_mut().insert(url.clone(), etag);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ManifestFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Location::Local(file) => write!(f, "{}", file.path.display()),
+            Location::S3(file) => write!(f, "{}", file.url),
+        }
+    }
+}
+
+/// Returns the URL of the shard file `name` next to the index manifest at `index`; see
+/// `update_metadata::load_file`, which checks that shard names are plain file names.
+fn shard_url(index: &Url, name: &str) -> Result<Url> {
+    index.join(name).context(error::ManifestUrl { url: name })
+}
+
+fn etag(response: &reqwest::blocking::Response) -> Option<String> {
+    response
+        .headers()
+        .get(ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(ToString::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use semver::Version;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn parse() {
+        let file: ManifestFile = "s3://releases/bottlerocket/manifest.json".parse().unwrap();
+        match file.location {
+            Location::S3(file) => assert_eq!(
+                shard_url(&file.url, "manifest-aws-k8s-1.15.json")
+                    .unwrap()
+                    .as_str(),
+                "s3://releases/bottlerocket/manifest-aws-k8s-1.15.json"
+            ),
+            Location::Local(_) => panic!("S3 URL parsed as a path"),
+        }
+        let file: ManifestFile = "build/manifest.json".parse().unwrap();
+        match file.location {
+            Location::Local(file) => {
+                assert_eq!(file.path, PathBuf::from("build/manifest.json"));
+            }
+            Location::S3(_) => panic!("path parsed as an S3 URL"),
+        }
+    }
+
+    #[test]
+    fn dry_run() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("manifest.json");
+        fs::copy("tests/data/example_3.json", &path).unwrap();
+        let before = fs::read(&path).unwrap();
+
+        let file = ManifestFile::local(&path);
+        file.set_dry_run();
+        let mut manifest = file.load().unwrap();
+        manifest.updates.pop();
+        file.write(&manifest).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), before);
+
+        // A manifest that doesn't exist yet isn't created either.
+        let new = ManifestFile::local(dir.path().join("new.json"));
+        new.set_dry_run();
+        new.write(&manifest).unwrap();
+        assert!(!dir.path().join("new.json").exists());
+    }
+
+    #[test]
+    fn local_conflict() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("manifest.json");
+        fs::copy("tests/data/example_3.json", &path).unwrap();
+
+        let ours = ManifestFile::local(&path);
+        let mut manifest = ours.load().unwrap();
+        // Writing what we loaded works, and so does writing again after that.
+        manifest.updates.pop();
+        ours.write(&manifest).unwrap();
+        manifest.migrations.clear();
+        ours.write(&manifest).unwrap();
+
+        // Someone else writes the manifest after we load it.
+        let theirs = ManifestFile::local(&path);
+        let mut their_manifest = theirs.load().unwrap();
+        ours.load().unwrap();
+        let max_version = Version::parse("9.9.9").unwrap();
+        their_manifest.updates[0].max_version = max_version.clone();
+        theirs.write(&their_manifest).unwrap();
+        match ours.write(&manifest) {
+            Err(error::Error::UpdateMetadata {
+                source: update_metadata::error::Error::ManifestChanged { .. },
+            }) => {}
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert_eq!(
+            update_metadata::load_file(&path).unwrap().updates[0].max_version,
+            max_version
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! The deeper checks of `validate --strict`.  Loading a manifest only checks that it parses; these
+//! find manifests that parse but would leave hosts stuck, like waves that go back in time or
+//! versions with no migration path between them.  Every problem is reported, rather than just the
+//! first, so a manifest can be fixed in one pass.
+
+use chrono::SecondsFormat;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use update_metadata::{Manifest, Update, MAX_SEED};
+
+/// One problem found in a manifest.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Problem {
+    /// Which check found it, like `wave-order`, so tooling can group problems.
+    pub(crate) check: &'static str,
+    /// The update it's in, like `x86_64-aws-k8s-1.15-1.0.6`, if it's about one update.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) update: Option<String>,
+    pub(crate) message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.update {
+            Some(update) => write!(f, "{} [{}]: {}", update, self.check, self.message),
+            None => write!(f, "[{}]: {}", self.check, self.message),
+        }
+    }
+}
+
+/// Checks a manifest, returning every problem found.
+pub(crate) fn check(manifest: &Manifest) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for update in &manifest.updates {
+        check_waves(update, &mut problems);
+        check_max_version(manifest, update, &mut problems);
+    }
+    check_migration_paths(manifest, &mut problems);
+    problems
+}
+
+fn update_name(update: &Update) -> String {
+    format!("{}-{}-{}", update.arch, update.variant, update.version)
+}
+
+/// Each wave has to start at a seed hosts can have, and after the wave before it.  The waves are
+/// kept in seed order, so a wave that starts earlier than the one before it means the bounds and
+/// start times disagree about which comes first.
+fn check_waves(update: &Update, problems: &mut Vec<Problem>) {
+    let problem = |check, message| Problem {
+        check,
+        update: Some(update_name(update)),
+        message,
+    };
+    let mut previous = None;
+    for (seed, start) in &update.waves {
+        if *seed >= MAX_SEED {
+            problems.push(problem(
+                "wave-bound",
+                format!(
+                    "wave starts at seed {}, but seeds only go up to {}",
+                    seed,
+                    MAX_SEED - 1
+                ),
+            ));
+        }
+        if let Some((previous_seed, previous_start)) = previous {
+            if start <= previous_start {
+                problems.push(problem(
+                    "wave-order",
+                    format!(
+                        "wave at seed {} starts at {}, no later than the wave at seed {} ({})",
+                        seed,
+                        start.to_rfc3339_opts(SecondsFormat::Secs, true),
+                        previous_seed,
+                        previous_start.to_rfc3339_opts(SecondsFormat::Secs, true)
+                    ),
+                ));
+            }
+        }
+        previous = Some((seed, start));
+    }
+}
+
+/// Hosts stop at an update's max version, so it should be a version they can update to.
+fn check_max_version(manifest: &Manifest, update: &Update, problems: &mut Vec<Problem>) {
+    let exists = manifest.updates.iter().any(|u| {
+        u.variant == update.variant && u.arch == update.arch && u.version == update.max_version
+    });
+    if !exists {
+        problems.push(Problem {
+            check: "max-version",
+            update: Some(update_name(update)),
+            message: format!(
+                "max version {} isn't an update for {} {}",
+                update.max_version, update.variant, update.arch
+            ),
+        });
+    }
+}
+
+/// A host can be on any of its variant's versions and update, or roll back, to any other, so
+/// there has to be a migration path between every pair of them.  Paths are found the way hosts
+/// find them, so one that exists but that hosts wouldn't take still counts as missing.
+fn check_migration_paths(manifest: &Manifest, problems: &mut Vec<Problem>) {
+    let mut versions: BTreeMap<&str, BTreeSet<_>> = BTreeMap::new();
+    for update in &manifest.updates {
+        versions
+            .entry(&update.variant)
+            .or_default()
+            .insert(&update.version);
+    }
+    for (variant, versions) in versions {
+        for (i, from) in versions.iter().enumerate() {
+            for to in versions.iter().skip(i + 1) {
+                let (_, reached) = manifest.migration_path(from, to);
+                if reached != *to {
+                    problems.push(Problem {
+                        check: "migration-path",
+                        update: None,
+                        message: format!(
+                            "no migration path for {} from {} to {}; the migrations stop at {}",
+                            variant, from, to, reached
+                        ),
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(version: &str, max_version: &str, waves: &str) -> String {
+        format!(
+            r#"{{"variant": "aws-k8s-1.15", "arch": "x86_64", "version": "{}", "max_version": "{}",
+                "waves": {{{}}}, "images": {{"boot": "boot", "root": "root", "hash": "hash"}}}}"#,
+            version, max_version, waves
+        )
+    }
+
+    #[test]
+    fn problems() {
+        let manifest = |updates: &[String], migrations: &str| -> Manifest {
+            serde_json::from_str(&format!(
+                r#"{{"updates": [{}], "migrations": {{{}}}}}"#,
+                updates.join(","),
+                migrations
+            ))
+            .unwrap()
+        };
+        let waves = r#""0": "2020-06-01T09:00:00Z", "512": "2020-06-02T09:00:00Z""#;
+        let good = manifest(
+            &[
+                update("1.0.0", "1.1.0", waves),
+                update("1.1.0", "1.1.0", ""),
+            ],
+            r#""(1.0.0, 1.1.0)": []"#,
+        );
+        assert_eq!(check(&good), Vec::new());
+
+        let bad = manifest(
+            &[
+                update("1.0.0", "1.3.0", waves),
+                update(
+                    "1.1.0",
+                    "1.2.0",
+                    r#""0": "2020-06-02T09:00:00Z", "512": "2020-06-01T09:00:00Z", "2048": "2020-06-03T09:00:00Z""#,
+                ),
+                update("1.2.0", "1.2.0", ""),
+            ],
+            r#""(1.0.0, 1.1.0)": []"#,
+        );
+        let problems: Vec<_> = check(&bad).iter().map(ToString::to_string).collect();
+        assert_eq!(
+            problems,
+            vec![
+                "x86_64-aws-k8s-1.15-1.0.0 [max-version]: max version 1.3.0 isn't an update for aws-k8s-1.15 x86_64",
+                "x86_64-aws-k8s-1.15-1.1.0 [wave-order]: wave at seed 512 starts at 2020-06-01T09:00:00Z, no later than the wave at seed 0 (2020-06-02T09:00:00Z)",
+                "x86_64-aws-k8s-1.15-1.1.0 [wave-bound]: wave starts at seed 2048, but seeds only go up to 2047",
+                "[migration-path]: no migration path for aws-k8s-1.15 from 1.0.0 to 1.2.0; the migrations stop at 1.1.0",
+                "[migration-path]: no migration path for aws-k8s-1.15 from 1.1.0 to 1.2.0; the migrations stop at 1.1.0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,116 @@
+//! The smallest hosts, like t3.nano instances, don't have much memory to spare for updog, and an
+//! update that needs more than the host has gets its workloads killed along with it.  So updog
+//! streams what it can, and keeps what it has to hold in memory within a budget.
+//!
+//! Images and migrations are never held in memory: they're decompressed, hashed, and written to
+//! disk as they're downloaded, through fixed-size buffers.  What's read whole is the repository's
+//! metadata, which tough parses in memory, and the manifest, though only this host's updates are
+//! parsed out of it; updates for other variants are skipped over.  With `memory_limit_mib` set,
+//! the metadata and manifest are refused if they're larger than their share of the budget, and
+//! updog's data segment is capped at the budget, so if it does need more it fails on its own
+//! rather than leaving the kernel to pick something to kill.
+
+use crate::error::{self, Result};
+use snafu::{ensure, ResultExt};
+use std::io;
+use tough::Limits;
+
+/// The smallest budget updog can work in; its HTTP client and TLS alone need several MiB.
+pub(crate) const MIN_MEMORY_LIMIT_MIB: u32 = 32;
+
+/// Limits for the repository's metadata when there's no budget.
+const DEFAULT_LIMITS: Limits = Limits {
+    max_root_size: 1024 * 1024,         // 1 MiB
+    max_targets_size: 1024 * 1024 * 10, // 10 MiB
+    max_timestamp_size: 1024 * 1024,    // 1 MiB
+    max_root_updates: 1024,
+};
+
+/// The largest manifest updog reads when there's no budget.
+const DEFAULT_MAX_MANIFEST_SIZE: u64 = 1024 * 1024 * 64; // 64 MiB
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct MemoryBudget {
+    /// The budget in bytes, if there is one.
+    bytes: Option<u64>,
+}
+
+impl MemoryBudget {
+    pub(crate) fn new(limit_mib: Option<u32>) -> Result<Self> {
+        if let Some(limit) = limit_mib {
+            ensure!(
+                limit >= MIN_MEMORY_LIMIT_MIB,
+                error::ConfigMemoryLimit {
+                    limit,
+                    min: MIN_MEMORY_LIMIT_MIB
+                }
+            );
+        }
+        Ok(Self {
+            bytes: limit_mib.map(|limit| u64::from(limit) * 1024 * 1024),
+        })
+    }
+
+    /// The size limits for the repository's metadata.  Targets metadata, which lists every image
+    /// in the repository, gets an eighth of the budget, and root and timestamp metadata, which
+    /// are small, a thirty-second each.
+    pub(crate) fn limits(self) -> Limits {
+        match self.bytes {
+            None => DEFAULT_LIMITS,
+            Some(bytes) => Limits {
+                max_root_size: DEFAULT_LIMITS.max_root_size.min(bytes / 32),
+                max_targets_size: DEFAULT_LIMITS.max_targets_size.min(bytes / 8),
+                max_timestamp_size: DEFAULT_LIMITS.max_timestamp_size.min(bytes / 32),
+                max_root_updates: DEFAULT_LIMITS.max_root_updates,
+            },
+        }
+    }
+
+    /// The largest manifest, or manifest shard, to read.  A manifest is held in memory while this
+    /// host's updates are parsed out of it, so it gets a quarter of the budget.
+    pub(crate) fn max_manifest_size(self) -> u64 {
+        self.bytes
+            .map_or(DEFAULT_MAX_MANIFEST_SIZE, |bytes| bytes / 4)
+    }
+
+    /// Caps updog's data segment, which holds its heap and anonymous memory, at the budget, if
+    /// there is one.
+    pub(crate) fn enforce(self) -> Result<()> {
+        let bytes = match self.bytes {
+            Some(bytes) => bytes,
+            None => return Ok(()),
+        };
+        let limit = libc::rlimit {
+            rlim_cur: bytes as libc::rlim_t,
+            rlim_max: bytes as libc::rlim_t,
+        };
+        // Safe because we pass a valid rlimit, which setrlimit only reads.
+        let ret = unsafe { libc::setrlimit(libc::RLIMIT_DATA, &limit) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error()).context(error::MemoryLimit { limit: bytes });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        let unlimited = MemoryBudget::new(None).unwrap();
+        assert_eq!(unlimited.limits().max_targets_size, 10 * 1024 * 1024);
+        assert_eq!(unlimited.max_manifest_size(), DEFAULT_MAX_MANIFEST_SIZE);
+
+        // A small budget shrinks what's read whole; a large one keeps the usual limits.
+        let small = MemoryBudget::new(Some(32)).unwrap();
+        assert_eq!(small.limits().max_targets_size, 4 * 1024 * 1024);
+        assert_eq!(small.limits().max_root_size, 1024 * 1024);
+        assert_eq!(small.max_manifest_size(), 8 * 1024 * 1024);
+        let large = MemoryBudget::new(Some(1024)).unwrap();
+        assert_eq!(large.limits().max_targets_size, 10 * 1024 * 1024);
+
+        assert!(MemoryBudget::new(Some(MIN_MEMORY_LIMIT_MIB - 1)).is_err());
+    }
+}
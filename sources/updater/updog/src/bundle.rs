@@ -0,0 +1,361 @@
+//! Bundles carry an update to hosts that can't reach its repository, like edge sites that are
+//! provisioned with a USB drive.  A bundle is a tar file made by `updata bundle`: a copy of the
+//! repository's signed metadata under `metadata/`, the manifest and the targets one update needs
+//! under `targets/`, and an inventory, `inventory.json`, that says which update that is and lists
+//! each file with its size and SHA-256 digest.
+//!
+//! `updog apply-bundle` unpacks a bundle and updates from it the way it would from the repository,
+//! so nothing in it is trusted until it's checked against the host's trusted root.  The inventory
+//! is checked first, so a bundle that was damaged on its way to the host is refused before
+//! anything is written.  It can also be signed, in `inventory.sig`, with one or more Ed25519 keys;
+//! hosts configured with bundle keys only take bundles signed by one of them, so an operator can
+//! vouch for which update a site should take, not just that it came from the repository.  Signing
+//! with both the old and new keys while rotating them lets hosts configured with either take it.
+
+use crate::error::{self, Result};
+use ring::digest::{Context, SHA256};
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path};
+
+const INVENTORY_FILE: &str = "inventory.json";
+const SIGNATURE_FILE: &str = "inventory.sig";
+const PARTS: &[&str] = &["metadata", "targets"];
+
+/// What a bundle holds: the update, and each of the bundle's files, by path.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub(crate) struct Inventory {
+    pub(crate) variant: String,
+    pub(crate) arch: String,
+    pub(crate) version: Version,
+    pub(crate) files: BTreeMap<String, FileInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub(crate) struct FileInfo {
+    pub(crate) length: u64,
+    pub(crate) sha256: String,
+}
+
+/// A signature over the exact bytes of `inventory.json`.
+#[derive(Debug, Deserialize, Serialize)]
+struct Signature {
+    /// The base64 Ed25519 public key that made the signature.
+    key: String,
+    /// The hex signature.
+    sig: String,
+}
+
+/// Writes a bundle to `output` holding the repository copy in `dir`, which has the metadata in
+/// `metadata/` and the targets in `targets/`, along with its inventory, signed by each of `keys`.
+/// Returns the inventory.
+pub(crate) fn pack(
+    dir: &Path,
+    variant: &str,
+    arch: &str,
+    version: &Version,
+    keys: &[Ed25519KeyPair],
+    output: &Path,
+) -> Result<Inventory> {
+    let mut files = BTreeMap::new();
+    for part in PARTS {
+        let part_dir = dir.join(part);
+        let entries = fs::read_dir(&part_dir).context(error::BundleWrite { path: &part_dir })?;
+        for entry in entries {
+            let path = entry
+                .context(error::BundleWrite { path: &part_dir })?
+                .path();
+            let name = format!("{}/{}", part, path_name(&path)?);
+            files.insert(
+                name,
+                file_info(&path).context(error::BundleWrite { path: &path })?,
+            );
+        }
+    }
+    let inventory = Inventory {
+        variant: variant.to_string(),
+        arch: arch.to_string(),
+        version: version.clone(),
+        files,
+    };
+    let data = serde_json::to_vec_pretty(&inventory).context(error::UpdateSerialize)?;
+
+    let file = File::create(output).context(error::BundleWrite { path: output })?;
+    let mut builder = tar::Builder::new(file);
+    let mut append = |name: &str, data: &[u8]| -> Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, name, data)
+            .context(error::BundleWrite { path: output })
+    };
+    append(INVENTORY_FILE, &data)?;
+    if !keys.is_empty() {
+        let signatures: Vec<_> = keys
+            .iter()
+            .map(|key| Signature {
+                key: base64::encode(key.public_key()),
+                sig: hex(key.sign(&data).as_ref()),
+            })
+            .collect();
+        let signatures = serde_json::to_vec_pretty(&signatures).context(error::UpdateSerialize)?;
+        append(SIGNATURE_FILE, &signatures)?;
+    }
+    for part in PARTS {
+        builder
+            .append_dir_all(part, dir.join(part))
+            .context(error::BundleWrite { path: output })?;
+    }
+    builder
+        .into_inner()
+        .and_then(|file| file.sync_all())
+        .context(error::BundleWrite { path: output })?;
+    Ok(inventory)
+}
+
+/// Unpacks the bundle at `path` into `dir`, checks its files against its inventory, and returns
+/// the inventory.  If any `keys` are given, the inventory has to be signed by one of them.
+///
+/// Only directories and regular files are unpacked, and only the inventory and what's under
+/// `metadata/` and `targets/`, so a bundle can't write anywhere else.
+pub(crate) fn unpack(path: &Path, dir: &Path, keys: &[Vec<u8>]) -> Result<Inventory> {
+    let file = File::open(path).context(error::BundleRead { path })?;
+    let mut archive = tar::Archive::new(file);
+    let mut unpacked = BTreeSet::new();
+    for entry in archive.entries().context(error::BundleRead { path })? {
+        let mut entry = entry.context(error::BundleRead { path })?;
+        let name = entry
+            .path()
+            .context(error::BundleRead { path })?
+            .into_owned();
+        let kind = entry.header().entry_type();
+        ensure!(
+            allowed(&name, kind),
+            error::BundleEntry { path, entry: name }
+        );
+        entry.unpack_in(dir).context(error::BundleRead { path })?;
+        if kind.is_file() {
+            unpacked.insert(name.to_string_lossy().into_owned());
+        }
+    }
+
+    let data = fs::read(dir.join(INVENTORY_FILE)).context(error::BundleRead { path })?;
+    if !keys.is_empty() {
+        let signatures = fs::read(dir.join(SIGNATURE_FILE))
+            .ok()
+            .context(error::BundleUnsigned { path })?;
+        let signatures: Vec<Signature> =
+            serde_json::from_slice(&signatures).context(error::BundleInventoryParse { path })?;
+        ensure!(
+            signatures
+                .iter()
+                .any(|signature| verify(signature, keys, &data)),
+            error::BundleSignature { path }
+        );
+    }
+    let inventory: Inventory =
+        serde_json::from_slice(&data).context(error::BundleInventoryParse { path })?;
+
+    unpacked.remove(INVENTORY_FILE);
+    unpacked.remove(SIGNATURE_FILE);
+    for (name, expected) in &inventory.files {
+        let file_path = dir.join(name);
+        let found = file_info(&file_path).context(error::BundleRead { path })?;
+        ensure!(
+            unpacked.remove(name)
+                && found.length == expected.length
+                && found.sha256.eq_ignore_ascii_case(&expected.sha256),
+            error::BundleFileMismatch { path, file: name }
+        );
+    }
+    if let Some(extra) = unpacked.into_iter().next() {
+        return error::BundleFileMismatch { path, file: extra }.fail();
+    }
+    Ok(inventory)
+}
+
+/// Whether `signature` is a valid signature of `data` by one of `keys`.
+fn verify(signature: &Signature, keys: &[Vec<u8>], data: &[u8]) -> bool {
+    let key = match base64::decode(&signature.key) {
+        Ok(key) => key,
+        Err(_) => return false,
+    };
+    let sig = match unhex(&signature.sig) {
+        Some(sig) => sig,
+        None => return false,
+    };
+    keys.contains(&key)
+        && UnparsedPublicKey::new(&ED25519, &key)
+            .verify(data, &sig)
+            .is_ok()
+}
+
+/// Whether a bundle may hold an entry called `name` of the given kind.
+fn allowed(name: &Path, kind: tar::EntryType) -> bool {
+    let parts: Option<Vec<&str>> = name
+        .components()
+        .map(|component| match component {
+            Component::Normal(part) => part.to_str(),
+            _ => None,
+        })
+        .collect();
+    let parts = match parts {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let in_repo = parts.first().map_or(false, |first| PARTS.contains(first));
+    if kind.is_dir() {
+        in_repo
+    } else if kind.is_file() {
+        (in_repo && parts.len() > 1) || parts == [INVENTORY_FILE] || parts == [SIGNATURE_FILE]
+    } else {
+        false
+    }
+}
+
+fn path_name(path: &Path) -> Result<&str> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .context(error::BundlePath { path })
+}
+
+fn file_info(path: &Path) -> io::Result<FileInfo> {
+    let mut file = File::open(path)?;
+    let mut context = Context::new(&SHA256);
+    let mut buf = [0; 8192];
+    let mut length = 0;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        context.update(&buf[..n]);
+        length += n as u64;
+    }
+    Ok(FileInfo {
+        length,
+        sha256: hex(context.finish().as_ref()),
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use tempfile::TempDir;
+
+    fn repo(dir: &Path) -> std::path::PathBuf {
+        let repo = dir.join("repo");
+        fs::create_dir_all(repo.join("metadata")).unwrap();
+        fs::create_dir_all(repo.join("targets")).unwrap();
+        fs::write(repo.join("metadata/timestamp.json"), "{}").unwrap();
+        fs::write(repo.join("targets/manifest.json"), "{}").unwrap();
+        repo
+    }
+
+    #[test]
+    fn pack_unpack() {
+        let dir = TempDir::new().unwrap();
+        let repo = repo(dir.path());
+        let version = Version::parse("0.3.4").unwrap();
+        let bundle = dir.path().join("bundle.tar");
+        let inventory = pack(&repo, "aws-k8s-1.15", "x86_64", &version, &[], &bundle).unwrap();
+        assert_eq!(
+            inventory.files.keys().collect::<Vec<_>>(),
+            vec!["metadata/timestamp.json", "targets/manifest.json"]
+        );
+        assert_eq!(inventory.files["targets/manifest.json"].length, 2);
+
+        let unpacked = dir.path().join("unpacked");
+        assert_eq!(unpack(&bundle, &unpacked, &[]).unwrap(), inventory);
+        assert_eq!(
+            fs::read_to_string(unpacked.join("targets/manifest.json")).unwrap(),
+            "{}"
+        );
+
+        // An unsigned bundle isn't taken by hosts that want signed ones.
+        let key = vec![0; 32];
+        let unpacked = dir.path().join("unsigned");
+        assert!(unpack(&bundle, &unpacked, &[key]).is_err());
+    }
+
+    #[test]
+    fn signed() {
+        let dir = TempDir::new().unwrap();
+        let repo = repo(dir.path());
+        let version = Version::parse("0.3.4").unwrap();
+        let key = || {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+            Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap()
+        };
+        let keys = vec![key(), key()];
+        let public: Vec<_> = keys
+            .iter()
+            .map(|key| key.public_key().as_ref().to_vec())
+            .collect();
+        let bundle = dir.path().join("bundle.tar");
+        pack(&repo, "aws-k8s-1.15", "x86_64", &version, &keys, &bundle).unwrap();
+
+        // Hosts configured with either key take a bundle signed with both.
+        for (i, public) in public.iter().enumerate() {
+            let unpacked = dir.path().join(format!("good-{}", i));
+            unpack(&bundle, &unpacked, std::slice::from_ref(public)).unwrap();
+        }
+        let unpacked = dir.path().join("other-key");
+        assert!(unpack(&bundle, &unpacked, &[vec![1; 32]]).is_err());
+    }
+
+    #[test]
+    fn damaged() {
+        let dir = TempDir::new().unwrap();
+        let repo = repo(dir.path());
+        let version = Version::parse("0.3.4").unwrap();
+        let bundle = dir.path().join("bundle.tar");
+        pack(&repo, "aws-k8s-1.15", "x86_64", &version, &[], &bundle).unwrap();
+
+        // Change a file's contents without changing its length or the tar's checksums.
+        let mut data = fs::read(&bundle).unwrap();
+        let at = data.windows(2).rposition(|w| w == b"{}").unwrap();
+        data[at] = b'[';
+        fs::write(&bundle, data).unwrap();
+        let unpacked = dir.path().join("unpacked");
+        assert!(unpack(&bundle, &unpacked, &[]).is_err());
+    }
+
+    #[test]
+    fn allowed_entries() {
+        let file = tar::EntryType::Regular;
+        assert!(allowed(Path::new("inventory.json"), file));
+        assert!(allowed(Path::new("targets/manifest.json"), file));
+        assert!(allowed(Path::new("metadata"), tar::EntryType::Directory));
+        assert!(!allowed(Path::new("targets"), file));
+        assert!(!allowed(Path::new("etc/passwd"), file));
+        assert!(!allowed(Path::new("targets/../../etc/passwd"), file));
+        assert!(!allowed(Path::new("/targets/manifest.json"), file));
+        assert!(!allowed(
+            Path::new("targets/manifest.json"),
+            tar::EntryType::Symlink
+        ));
+    }
+}
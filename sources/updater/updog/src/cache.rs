@@ -0,0 +1,161 @@
+//! Fetches update images from a cache inside the cluster before going to the repository, so a
+//! wave of thousands of hosts doesn't download the same images over the WAN thousands of times.
+//!
+//! The protocol is plain HTTP: the cache serves each target at its name under the configured URL,
+//! like the repository's targets directory does, and anything other than a successful response is
+//! a miss.  A static server with copies of the targets works, and so does a pull-through proxy in
+//! front of the repository.  The cache isn't trusted; what it sends is checked against the length
+//! and SHA-256 digest in the repository's signed targets metadata, and if it doesn't match, or the
+//! cache can't be reached, the image is fetched from the repository instead.
+
+use crate::error::{self, Result};
+use crate::transport::HttpQueryRepo;
+use reqwest::blocking::{Client, Response};
+use ring::digest::{self, Context};
+use snafu::{OptionExt, ResultExt};
+use std::io::{self, Read};
+use std::time::Duration;
+use url::Url;
+
+/// How long to wait to connect to the cache before falling back to the repository.  The cache is
+/// nearby, so it shouldn't take long.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub(crate) struct TargetCache {
+    client: Client,
+    base_url: Url,
+}
+
+impl TargetCache {
+    /// Creates a cache client for `base_url`.  Requests don't go through the configured proxy,
+    /// since the cache is in the cluster.
+    pub(crate) fn new(base_url: Url) -> Result<Self> {
+        let client = Client::builder()
+            .no_proxy()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .build()
+            .context(error::TransportBuild)?;
+        Ok(Self { client, base_url })
+    }
+
+    /// Requests `target` from the cache.  The reader fails at the end of the target if it doesn't
+    /// match what the repository's metadata says it should be.
+    pub(crate) fn read_target(
+        &self,
+        repository: &HttpQueryRepo<'_>,
+        target: &str,
+    ) -> Result<CheckedReader<Response>> {
+        let expected = repository
+            .targets()
+            .signed
+            .targets
+            .get(target)
+            .context(error::TargetNotFound { target })?;
+        let url = self
+            .base_url
+            .join(target)
+            .context(error::TargetCacheUrl { target })?;
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .and_then(Response::error_for_status)
+            .context(error::TargetCacheFetch { target })?;
+        Ok(CheckedReader::new(
+            response,
+            expected.length,
+            &expected.hashes.sha256,
+        ))
+    }
+}
+
+/// Passes through a target's contents, returning an error instead of the end of the stream if
+/// they don't have the expected length and SHA-256 digest.
+pub(crate) struct CheckedReader<R> {
+    inner: io::Take<R>,
+    digest: Context,
+    len: u64,
+    expected_len: u64,
+    expected_digest: Vec<u8>,
+}
+
+impl<R: Read> CheckedReader<R> {
+    fn new(inner: R, expected_len: u64, expected_digest: &[u8]) -> Self {
+        Self {
+            // Read one byte past the expected length, so a target that's too long is caught
+            // without reading all of it.
+            inner: inner.take(expected_len + 1),
+            digest: Context::new(&digest::SHA256),
+            len: 0,
+            expected_len,
+            expected_digest: expected_digest.to_vec(),
+        }
+    }
+}
+
+impl<R: Read> Read for CheckedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.len += read as u64;
+        if self.len > self.expected_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("target is longer than {} bytes", self.expected_len),
+            ));
+        }
+        self.digest.update(&buf[..read]);
+        if read == 0 {
+            if self.len != self.expected_len {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "target is {} bytes, not {} bytes",
+                        self.len, self.expected_len
+                    ),
+                ));
+            }
+            if self.digest.clone().finish().as_ref() != self.expected_digest.as_slice() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "target doesn't match its digest",
+                ));
+            }
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all<R: Read>(mut reader: R) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    #[test]
+    fn checked_reader() {
+        let data = b"bottlerocket".to_vec();
+        let sha256 = digest::digest(&digest::SHA256, &data);
+        let len = data.len() as u64;
+
+        let reader = CheckedReader::new(&data[..], len, sha256.as_ref());
+        assert_eq!(read_all(reader).unwrap(), data);
+
+        let mut corrupt = data.clone();
+        corrupt[0] = b'B';
+        let reader = CheckedReader::new(&corrupt[..], len, sha256.as_ref());
+        assert!(read_all(reader).is_err());
+
+        let reader = CheckedReader::new(&data[..4], len, sha256.as_ref());
+        assert!(read_all(reader).is_err());
+
+        let mut long = data.clone();
+        long.push(b'!');
+        let reader = CheckedReader::new(&long[..], len, sha256.as_ref());
+        assert!(read_all(reader).is_err());
+    }
+}
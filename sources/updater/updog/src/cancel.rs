@@ -0,0 +1,128 @@
+//! Lets a run be cancelled partway through, by SIGINT or SIGTERM at the command line or by a
+//! caller of the library from another thread.  Cancelling doesn't stop updog on the spot; it stops
+//! at the next point it can stop cleanly, such as the next read of a download, or before the
+//! partition table is changed, so nothing is left half-done for the next run to trip over.
+//!
+//! Updates are written so a cancelled write needs no undoing: the inactive partition set is marked
+//! invalid and the record of any prepared update is removed before anything is written to it, and
+//! it's only marked valid once every image is written.  A cancelled run is saved in the status
+//! file as `cancelled`.
+
+use crate::error::{self, Result};
+use signal_hook::{SIGINT, SIGTERM};
+use snafu::{ensure, ResultExt};
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a wait checks whether it's been cancelled.
+const WAIT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Cancels a run.  Clones share the same state, so one can be kept to cancel a run that's been
+/// given another.
+#[derive(Debug, Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    /// Asks the run to stop at the next point it can.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Cancels the run on SIGINT or SIGTERM, rather than letting them kill the process.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the signal handlers can't be set up.
+    pub fn on_signals(&self) -> Result<()> {
+        for signal in &[SIGINT, SIGTERM] {
+            signal_hook::flag::register(*signal, Arc::clone(&self.0)).context(error::Signal)?;
+        }
+        Ok(())
+    }
+
+    /// Fails with `Error::Cancelled` if the run has been cancelled.
+    pub(crate) fn check(&self) -> Result<()> {
+        ensure!(!self.is_cancelled(), error::Cancelled);
+        Ok(())
+    }
+
+    /// Waits for `duration`, or until the run is cancelled.
+    pub(crate) fn sleep(&self, duration: Duration) -> Result<()> {
+        let end = Instant::now() + duration;
+        loop {
+            self.check()?;
+            let now = Instant::now();
+            if now >= end {
+                return Ok(());
+            }
+            thread::sleep(std::cmp::min(end - now, WAIT_INTERVAL));
+        }
+    }
+
+    /// Wraps `reader` so reads fail once the run is cancelled, which stops a download between
+    /// reads rather than at its end.
+    pub(crate) fn reader<R: Read>(&self, reader: R) -> CancelRead<R> {
+        CancelRead {
+            inner: reader,
+            cancel: self.clone(),
+        }
+    }
+}
+
+pub(crate) struct CancelRead<R> {
+    inner: R,
+    cancel: Cancel,
+}
+
+impl<R: Read> Read for CancelRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancel.is_cancelled() {
+            return Err(io::Error::new(io::ErrorKind::Other, "cancelled"));
+        }
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn clones_share_state() {
+        let cancel = Cancel::default();
+        let run = cancel.clone();
+        assert!(run.check().is_ok());
+        cancel.cancel();
+        assert!(run.is_cancelled());
+        assert!(matches!(run.check(), Err(Error::Cancelled { .. })));
+    }
+
+    #[test]
+    fn reads_stop() {
+        let cancel = Cancel::default();
+        let mut reader = cancel.reader(&b"update"[..]);
+        let mut buf = [0; 3];
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        cancel.cancel();
+        assert!(reader.read(&mut buf).is_err());
+    }
+
+    #[test]
+    fn waits_stop() {
+        let cancel = Cancel::default();
+        let waiting = cancel.clone();
+        let start = Instant::now();
+        let wait = thread::spawn(move || waiting.sleep(Duration::from_secs(60)));
+        cancel.cancel();
+        assert!(wait.join().unwrap().is_err());
+        assert!(start.elapsed() < Duration::from_secs(30));
+    }
+}
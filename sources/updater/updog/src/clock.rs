@@ -0,0 +1,93 @@
+//! Waves are only as fair as the hosts' clocks: a host whose clock is behind waits past its wave,
+//! and one whose clock is ahead takes an update before its wave starts.  So rather than go by
+//! the local clock alone, updog checks it against the repository, whose signed metadata it trusts
+//! more.
+//!
+//! The repository's timestamp metadata is signed again on a schedule, and each copy expires a
+//! fixed time after it's signed.  If updog is told that time, as `timestamp_lifetime_hours`, it
+//! knows when the copy it fetched was signed, and so that the time is at least that.  Waves are
+//! evaluated against the local clock, but never earlier than the signing time, so a host whose
+//! clock is behind, like one whose clock reset at boot, is still offered its wave on time.
+//!
+//! A clock that's ahead can't run past the metadata's expiry: the metadata would look expired,
+//! and updog refuses expired metadata, so the host takes no update at all until its clock is
+//! fixed.  That leaves a clock ahead by less than the time the metadata has left, which updog
+//! can't tell from the right time; repositories that sign timestamp metadata often, with a short
+//! lifetime, keep that small.
+//!
+//! Activation times, vetoes, and the reboot window go by the local clock, since they're about
+//! the host rather than the fleet.
+
+use chrono::{DateTime, Duration, Utc};
+use update_metadata::Clock;
+
+/// The clock updog evaluates waves with: the local clock, but never earlier than the time the
+/// repository's timestamp metadata was signed, if that's known.
+#[derive(Debug)]
+pub(crate) struct WaveClock<C: Clock> {
+    local: C,
+    signed_at: Option<DateTime<Utc>>,
+}
+
+impl<C: Clock> WaveClock<C> {
+    /// Makes a clock from the local clock and the expiry of the repository's timestamp
+    /// metadata.  `lifetime` is how long after it's signed the metadata expires, if known.
+    pub(crate) fn new(local: C, expires: DateTime<Utc>, lifetime: Option<Duration>) -> Self {
+        Self {
+            local,
+            signed_at: lifetime.map(|lifetime| expires - lifetime),
+        }
+    }
+
+    /// Returns how far the local clock is behind the time the repository's timestamp metadata
+    /// was signed, if it is.
+    pub(crate) fn skew(&self) -> Option<Duration> {
+        let signed_at = self.signed_at?;
+        let local = self.local.now();
+        if local < signed_at {
+            Some(signed_at - local)
+        } else {
+            None
+        }
+    }
+}
+
+impl<C: Clock> Clock for WaveClock<C> {
+    fn now(&self) -> DateTime<Utc> {
+        let local = self.local.now();
+        match self.signed_at {
+            Some(signed_at) if signed_at > local => signed_at,
+            _ => local,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use update_metadata::MockClock;
+
+    #[test]
+    fn bounded_by_signing_time() {
+        let expires = DateTime::parse_from_rfc3339("2020-03-27T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let signed_at = expires - Duration::days(7);
+
+        // Without the lifetime, the local clock is all there is.
+        let local = MockClock::new(signed_at - Duration::days(30));
+        let clock = WaveClock::new(&local, expires, None);
+        assert_eq!(clock.now(), signed_at - Duration::days(30));
+        assert_eq!(clock.skew(), None);
+
+        // A clock that's behind is held at the signing time.
+        let clock = WaveClock::new(&local, expires, Some(Duration::days(7)));
+        assert_eq!(clock.now(), signed_at);
+        assert_eq!(clock.skew(), Some(Duration::days(30)));
+
+        // A clock that's past it is trusted as it is.
+        local.set(signed_at + Duration::hours(1));
+        assert_eq!(clock.now(), signed_at + Duration::hours(1));
+        assert_eq!(clock.skew(), None);
+    }
+}
@@ -0,0 +1,395 @@
+//! updog's configuration, generated from settings.updates into `/etc/updog.toml`, and the paths
+//! where updog finds it and the rest of the state of the host.
+
+use crate::approval::ApprovalConfig;
+use crate::auto::{AutoPolicy, RebootWindow};
+use crate::budget::MemoryBudget;
+use crate::error::{self, Result};
+use crate::identity::HostIdentity;
+use crate::logging::LogTarget;
+use crate::veto::Veto;
+use crate::webhook::WebhookConfig;
+use bottlerocket_release::{VersionInfo, DEFAULT_DATASTORE_DIR, DEFAULT_RELEASE_FILE};
+use semver::Version;
+use serde::Deserialize;
+use signpost::State;
+use snafu::{OptionExt, ResultExt};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use updater_config::{EffectiveConfig, Layer, Timeouts, SETTINGS_FILE};
+use url::Url;
+
+const CONFIG_PATH: &str = "/etc/updog.toml";
+const TRUSTED_ROOT_PATH: &str = "/usr/share/updog/root.json";
+const MIGRATION_PATH: &str = "/var/lib/bottlerocket-migrations";
+const METADATA_PATH: &str = "/var/cache/bottlerocket-metadata";
+const STATUS_PATH: &str = "/var/lib/updog/status.json";
+const HISTORY_PATH: &str = "/var/lib/updog/history.jsonl";
+const PREPARED_PATH: &str = "/var/lib/updog/prepared.json";
+const APPROVAL_PATH: &str = "/var/lib/updog/approval.json";
+const BUNDLE_PATH: &str = "/var/lib/updog/bundles";
+const LOCK_PATH: &str = "/run/updog/lock";
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Config {
+    pub(crate) metadata_base_url: String,
+    pub(crate) targets_base_url: String,
+    pub(crate) seed: u32,
+    // "latest", or the version to update to and stay on, with an optional "v" prefix.
+    #[serde(default = "default_version_lock")]
+    pub(crate) version_lock: String,
+    #[serde(default)]
+    pub(crate) ignore_waves: bool,
+    // Passed along to the update repository so it can serve the chosen channel.
+    #[serde(default)]
+    pub(crate) channel: Option<String>,
+    // HTTPS requests go through this proxy, unless the host matches an entry in no_proxy.  These
+    // and the timeouts are resolved with the other updater components' settings when loaded.
+    #[serde(default)]
+    pub(crate) https_proxy: Option<String>,
+    #[serde(default)]
+    pub(crate) no_proxy: Vec<String>,
+    #[serde(skip)]
+    pub(crate) timeouts: Timeouts,
+    // Updates can be restricted to hosts with certain labels.
+    #[serde(default)]
+    pub(crate) labels: BTreeMap<String, String>,
+    // Credentials for OCI registries, in the format of Docker's config.json.
+    #[serde(default)]
+    pub(crate) registry_auth_file: Option<PathBuf>,
+    // Update images are fetched from this in-cluster cache first, if it has them.
+    #[serde(default)]
+    pub(crate) target_cache_url: Option<String>,
+    // About how many hosts share the update repository, so check-update can say how many are
+    // expected to fetch an update at the same time as this one.
+    #[serde(default)]
+    pub(crate) fleet_size_hint: Option<u64>,
+    // How far `updog auto` takes an update on its own.
+    #[serde(default = "default_auto_policy")]
+    pub(crate) auto: AutoPolicy,
+    // When `updog auto` may reboot the host, like "02:00-04:00" in UTC.
+    #[serde(default)]
+    pub(crate) reboot_window: Option<String>,
+    // Endpoints to tell when the host's update state changes, by name.
+    #[serde(default)]
+    pub(crate) webhooks: BTreeMap<String, WebhookConfig>,
+    // Updates are only written if their provenance document vouches for their images.
+    #[serde(default)]
+    pub(crate) require_provenance: bool,
+    // How long a prepared update has to stay staged before it can be activated, in minutes.
+    #[serde(default)]
+    pub(crate) min_soak_minutes: u32,
+    // Conditions registered on the host that hold back activating and rebooting, by name.
+    #[serde(default)]
+    pub(crate) vetoes: BTreeMap<String, Veto>,
+    // Base64 Ed25519 public keys; if any are given, `apply-bundle` only takes bundles signed by
+    // one of them.
+    #[serde(default)]
+    pub(crate) bundle_keys: Vec<String>,
+    // How long after it's signed the repository's timestamp metadata expires, so waves can be
+    // checked against the time it was signed when the host's clock is behind.
+    #[serde(default)]
+    pub(crate) timestamp_lifetime_hours: Option<u32>,
+    // The most memory updog should use, in MiB, for hosts with little to spare.
+    #[serde(default)]
+    pub(crate) memory_limit_mib: Option<u32>,
+    // "aws-instance-identity", or a file holding the host's identity, to send to a private
+    // repository that only serves hosts it knows.
+    #[serde(default)]
+    pub(crate) host_identity: Option<String>,
+    // An operator's policy endpoint to ask before activating an update.
+    #[serde(default)]
+    pub(crate) approval: Option<ApprovalConfig>,
+    // TODO API sourced configuration, eg.
+    // blacklist: Option<Vec<Version>>,
+    // mode: Option<{Automatic, Managed, Disabled}>
+}
+
+/// Where updog finds its configuration and the state of the host.  Tests change these to files
+/// of their own, and a container that has the host's root filesystem mounted somewhere other
+/// than `/` can find them under it with `Paths::under`.
+#[derive(Debug, Clone)]
+pub struct Paths {
+    pub config: PathBuf,
+    pub shared_config: PathBuf,
+    pub trusted_root: PathBuf,
+    pub metadata: PathBuf,
+    pub migrations: PathBuf,
+    /// Where the result of each run is saved for the update reporter.
+    pub status: PathBuf,
+    /// Where the results of recent runs are kept.
+    pub history: PathBuf,
+    /// Where the update staged by `prepare` is recorded for `activate`.
+    pub prepared: PathBuf,
+    /// Where the approval policy's last decision is cached.
+    pub approval: PathBuf,
+    /// Where `apply-bundle` unpacks bundles.
+    pub bundles: PathBuf,
+    /// The lock held by runs that change the host, which says which process holds it.
+    pub lock: PathBuf,
+    pub os_release: PathBuf,
+    /// The directory of versioned data stores, which says what version the settings are at.
+    pub datastore_dir: PathBuf,
+    /// The host's root filesystem, whose backing partition says which partition set is active.
+    pub root: PathBuf,
+    /// The partition backing the root filesystem, if not the one `root` is on.
+    pub active_partition: Option<PathBuf>,
+}
+
+impl Default for Paths {
+    fn default() -> Self {
+        Self::under("/")
+    }
+}
+
+impl Paths {
+    /// The host's usual paths, under `root` rather than `/`, like `/proc/1/root` for a container
+    /// that shares the host's PID namespace.
+    pub fn under<P: AsRef<Path>>(root: P) -> Self {
+        let root = root.as_ref();
+        let path = |path: &str| root.join(path.trim_start_matches('/'));
+        Self {
+            config: path(CONFIG_PATH),
+            shared_config: path(SETTINGS_FILE),
+            trusted_root: path(TRUSTED_ROOT_PATH),
+            metadata: path(METADATA_PATH),
+            migrations: path(MIGRATION_PATH),
+            status: path(STATUS_PATH),
+            history: path(HISTORY_PATH),
+            prepared: path(PREPARED_PATH),
+            approval: path(APPROVAL_PATH),
+            bundles: path(BUNDLE_PATH),
+            lock: path(LOCK_PATH),
+            os_release: path(DEFAULT_RELEASE_FILE),
+            datastore_dir: path(DEFAULT_DATASTORE_DIR),
+            root: root.to_path_buf(),
+            active_partition: None,
+        }
+    }
+
+    pub(crate) fn version_info(&self) -> Result<VersionInfo> {
+        VersionInfo::from_paths(&self.os_release, &self.datastore_dir)
+            .context(error::ReleaseVersion)
+    }
+
+    pub(crate) fn running_version(&self) -> Result<(Version, String)> {
+        let info = self.version_info()?;
+        Ok((info.release.version_id, info.release.variant_id))
+    }
+
+    pub(crate) fn partition_state(&self) -> Result<State> {
+        match &self.active_partition {
+            Some(partition) => State::load_from_partition(partition),
+            None => State::load_from_root(&self.root),
+        }
+        .context(error::PartitionTableRead)
+    }
+}
+
+fn default_auto_policy() -> AutoPolicy {
+    AutoPolicy::Off
+}
+
+fn default_version_lock() -> String {
+    String::from("latest")
+}
+
+impl Config {
+    /// Returns the version we're locked to, if any.
+    pub(crate) fn locked_version(&self) -> Result<Option<Version>> {
+        if self.version_lock == "latest" {
+            return Ok(None);
+        }
+        let mut chars = self.version_lock.chars();
+        let version = match chars.next() {
+            Some('v') => chars.as_str(),
+            _ => &self.version_lock,
+        };
+        Version::parse(version)
+            .map(Some)
+            .context(error::ConfigVersionLock {
+                version: &self.version_lock,
+            })
+    }
+
+    /// Returns the HTTPS proxy to use, if any.  Like other tools, we assume "http://" if the
+    /// proxy is given without a scheme.
+    pub(crate) fn proxy_url(&self) -> Result<Option<Url>> {
+        let proxy = match &self.https_proxy {
+            Some(proxy) => proxy,
+            None => return Ok(None),
+        };
+        let url = if proxy.contains("://") {
+            proxy.clone()
+        } else {
+            format!("http://{}", proxy)
+        };
+        Url::parse(&url)
+            .map(Some)
+            .context(error::ConfigProxy { proxy })
+    }
+
+    /// Returns the URL of the target cache, if one is configured.  Like the base URLs, it should
+    /// end with a slash, so target names are joined under it.
+    pub(crate) fn target_cache_url(&self) -> Result<Option<Url>> {
+        match &self.target_cache_url {
+            Some(url) => Url::parse(url)
+                .map(Some)
+                .context(error::ConfigTargetCache { url }),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn host_identity(&self) -> Result<Option<HostIdentity>> {
+        self.host_identity
+            .as_ref()
+            .map(|identity| HostIdentity::parse(identity))
+            .transpose()
+    }
+
+    pub(crate) fn reboot_window(&self) -> Result<Option<RebootWindow>> {
+        match &self.reboot_window {
+            Some(window) => RebootWindow::parse(window)
+                .map(Some)
+                .context(error::ConfigRebootWindow { window }),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn bundle_keys(&self) -> Result<Vec<Vec<u8>>> {
+        self.bundle_keys
+            .iter()
+            .map(|key| base64::decode(key).context(error::ConfigBundleKey { key }))
+            .collect()
+    }
+
+    pub(crate) fn min_soak(&self) -> chrono::Duration {
+        chrono::Duration::minutes(i64::from(self.min_soak_minutes))
+    }
+
+    pub(crate) fn memory_budget(&self) -> Result<MemoryBudget> {
+        MemoryBudget::new(self.memory_limit_mib)
+    }
+
+    pub(crate) fn timestamp_lifetime(&self) -> Option<chrono::Duration> {
+        self.timestamp_lifetime_hours
+            .map(|hours| chrono::Duration::hours(i64::from(hours)))
+    }
+}
+
+pub(crate) fn load_config(paths: &Paths) -> Result<Config> {
+    let path = &paths.config;
+    let s = fs::read_to_string(path).context(error::ConfigRead { path })?;
+    let mut config: Config = toml::from_str(&s).context(error::ConfigParse { path })?;
+    // updog has no command line options for the network, so only the other layers matter here.
+    let shared = effective_config(paths, Layer::default())?;
+    config.timeouts = shared.timeouts();
+    config.https_proxy = shared.https_proxy.value;
+    config.no_proxy = shared.no_proxy.value;
+    // Everything updog does needs the config first, so this is where the memory limit starts.
+    config.memory_budget()?.enforce()?;
+    Ok(config)
+}
+
+/// Reads just where to log from the config at `path`, since logging is set up before the config
+/// is loaded.  Any problem with the config is left for `load_config` to report, once it can be
+/// logged.
+pub(crate) fn configured_log_target(path: &Path) -> Option<LogTarget> {
+    #[derive(Deserialize)]
+    struct LogSettings {
+        log_target: Option<LogTarget>,
+    }
+    let s = fs::read_to_string(path).ok()?;
+    toml::from_str::<LogSettings>(&s).ok()?.log_target
+}
+
+/// Resolves the settings updog shares with the other updater components, like the proxy and log
+/// level, from its config file, the settings file, the environment, and `command_line`.  This is
+/// what `updog --show-config` prints.
+///
+/// # Errors
+///
+/// Fails with `Error::SharedConfig` if a layer can't be read or has an invalid value.
+pub fn effective_config(paths: &Paths, command_line: Layer) -> Result<EffectiveConfig> {
+    updater_config::load(Some(&paths.config), &paths.shared_config, command_line)
+        .context(error::SharedConfig)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_under_root() {
+        let paths = Paths::under("/proc/1/root");
+        assert_eq!(paths.config, Path::new("/proc/1/root/etc/updog.toml"));
+        assert_eq!(paths.lock, Path::new("/proc/1/root/run/updog/lock"));
+        assert_eq!(paths.root, Path::new("/proc/1/root"));
+        assert_eq!(Paths::default().config, Path::new(CONFIG_PATH));
+    }
+
+    #[test]
+    fn config_version_lock() {
+        let config: Config = toml::from_str(
+            r#"
+            metadata_base_url = "foo"
+            targets_base_url = "bar"
+            seed = 123
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.version_lock, "latest");
+        assert!(!config.ignore_waves);
+        assert!(config.channel.is_none());
+        assert!(config.locked_version().unwrap().is_none());
+
+        let mut config: Config = toml::from_str(
+            r#"
+            metadata_base_url = "foo"
+            targets_base_url = "bar"
+            seed = 123
+            version_lock = "v1.1.1"
+            ignore_waves = true
+            channel = "beta"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.locked_version().unwrap(),
+            Some(Version::parse("1.1.1").unwrap())
+        );
+        assert!(config.ignore_waves);
+        assert_eq!(config.channel.as_deref(), Some("beta"));
+
+        config.version_lock = String::from("1.1");
+        config.locked_version().unwrap_err();
+    }
+
+    #[test]
+    fn config_proxy() {
+        let mut config: Config = toml::from_str(
+            r#"
+            metadata_base_url = "foo"
+            targets_base_url = "bar"
+            seed = 123
+        "#,
+        )
+        .unwrap();
+        assert!(config.proxy_url().unwrap().is_none());
+        assert!(config.no_proxy.is_empty());
+
+        config.https_proxy = Some(String::from("proxy.example.com:3128"));
+        assert_eq!(
+            config.proxy_url().unwrap().unwrap().as_str(),
+            "http://proxy.example.com:3128/"
+        );
+        config.https_proxy = Some(String::from("https://proxy.example.com"));
+        assert_eq!(
+            config.proxy_url().unwrap().unwrap().as_str(),
+            "https://proxy.example.com/"
+        );
+        config.https_proxy = Some(String::from("http://"));
+        config.proxy_url().unwrap_err();
+    }
+}
@@ -0,0 +1,437 @@
+//! Checks the things updog needs in order to update the host, so an operator can find out why
+//! updates aren't happening without reading through logs.  Each check says what it found and, if
+//! something's wrong, what to do about it.  The checks don't change anything on the host, apart
+//! from refreshing the cached TUF metadata the way any other updog run would.
+
+use crate::config::{load_config, Config, Paths};
+use crate::error::{self, Result};
+use crate::messages::Message;
+use crate::recover;
+use crate::repo::{
+    applicable_updates, build_transport, load_manifest, load_repository, set_common_query_params,
+};
+use crate::status::FailureClass;
+use chrono::{DateTime, Duration, Utc};
+use semver::Version;
+use serde::Serialize;
+use signpost::State;
+use snafu::ensure;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use tough::schema::{Root, Signed};
+
+/// Warn when the repository's root metadata expires within this many days; once it expires, the
+/// host can't verify any update.
+const ROOT_EXPIRY_WARNING_DAYS: i64 = 30;
+/// Warn when a directory updog downloads into has less free space than this.  Migrations and
+/// metadata for an update only take a few MiB, so this leaves room for several.
+const MIN_FREE_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Health {
+    Ok,
+    /// Updates can still work, but something may need attention.
+    Warn,
+    /// Updates can't work until this is fixed.
+    Fail,
+    /// The check couldn't run because an earlier one failed.
+    Skip,
+}
+
+/// One of the doctor's checks, and what it found.
+#[derive(Debug, Serialize)]
+pub struct Check {
+    name: &'static str,
+    health: Health,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<&'static str>,
+    /// The hint's ID, for tooling that shouldn't depend on its wording.
+    #[serde(rename = "hint-id", skip_serializing_if = "Option::is_none")]
+    hint_id: Option<Message>,
+}
+
+impl Check {
+    fn ok<S: Into<String>>(name: &'static str, message: S) -> Self {
+        Self {
+            name,
+            health: Health::Ok,
+            message: message.into(),
+            hint: None,
+            hint_id: None,
+        }
+    }
+
+    fn warn<S: Into<String>>(name: &'static str, message: S, hint: Message) -> Self {
+        Self {
+            name,
+            health: Health::Warn,
+            message: message.into(),
+            hint: Some(hint.text()),
+            hint_id: Some(hint),
+        }
+    }
+
+    fn fail<S: Into<String>>(name: &'static str, message: S, hint: Option<Message>) -> Self {
+        Self {
+            name,
+            health: Health::Fail,
+            message: message.into(),
+            hint: hint.map(Message::text),
+            hint_id: hint,
+        }
+    }
+
+    fn skip(name: &'static str, needs: &str) -> Self {
+        Self {
+            name,
+            health: Health::Skip,
+            message: format!("skipped, since the {} check failed", needs),
+            hint: None,
+            hint_id: None,
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let health = match self.health {
+            Health::Ok => "ok",
+            Health::Warn => "warn",
+            Health::Fail => "FAIL",
+            Health::Skip => "skip",
+        };
+        write!(f, "{:<5} {}: {}", health, self.name, self.message)?;
+        if let Some(hint) = self.hint {
+            write!(f, "\n      {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fails if any of `checks` failed.
+pub(crate) fn ensure_passed(checks: &[Check]) -> Result<()> {
+    let failed = checks
+        .iter()
+        .filter(|check| check.health == Health::Fail)
+        .count();
+    ensure!(failed == 0, error::DoctorFailed { failed });
+    Ok(())
+}
+
+/// Runs every check, returning what each found.
+pub(crate) fn run(paths: &Paths) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let config = match check_config(paths) {
+        Ok(config) => {
+            checks.push(Check::ok(
+                "config",
+                format!("{} is valid", paths.config.display()),
+            ));
+            Some(config)
+        }
+        Err(e) => {
+            checks.push(Check::fail(
+                "config",
+                e.to_string(),
+                Some(Message::DoctorConfig),
+            ));
+            None
+        }
+    };
+
+    let release = match paths.version_info() {
+        Ok(info) => {
+            let version = info.release.version_id;
+            let variant = info.release.variant_id;
+            checks.push(match info.datastore_version {
+                // Migrations bring the data store to the running version at boot; if they
+                // didn't, settings may be missing or stale.
+                Some(datastore) if datastore != version => Check::warn(
+                    "release",
+                    format!(
+                        "running {} {}, but the data store is at {}",
+                        variant, version, datastore
+                    ),
+                    Message::DoctorDatastoreVersion,
+                ),
+                _ => Check::ok("release", format!("running {} {}", variant, version)),
+            });
+            Some((version, variant))
+        }
+        Err(e) => {
+            checks.push(Check::fail(
+                "release",
+                e.to_string(),
+                Some(Message::DoctorRelease),
+            ));
+            None
+        }
+    };
+
+    checks.push(check_trusted_root(&paths.trusted_root));
+
+    match (&config, &release) {
+        (Some(config), Some((version, variant))) => {
+            checks.extend(check_repository(paths, config, version, variant));
+        }
+        (None, _) => {
+            checks.push(Check::skip("repository", "config"));
+            checks.push(Check::skip("root-expiry", "config"));
+        }
+        (_, None) => {
+            checks.push(Check::skip("repository", "release"));
+            checks.push(Check::skip("root-expiry", "release"));
+        }
+    }
+
+    let state = match paths.partition_state() {
+        Ok(state) => {
+            checks.push(Check::ok(
+                "partitions",
+                format!(
+                    "found both partition sets; booted from {}",
+                    state.active_set().root.display()
+                ),
+            ));
+            Some(state)
+        }
+        Err(e) => {
+            checks.push(Check::fail(
+                "partitions",
+                e.to_string(),
+                Some(Message::DoctorPartitions),
+            ));
+            None
+        }
+    };
+
+    checks.push(check_free_space(&paths.migrations));
+    checks.push(check_free_space(&paths.metadata));
+
+    match config.as_ref().map(Config::locked_version) {
+        Some(Ok(Some(version))) => checks.push(Check::warn(
+            "version-lock",
+            format!(
+                "locked to {}, so updog only updates to that version",
+                version
+            ),
+            Message::DoctorVersionLock,
+        )),
+        Some(_) => checks.push(Check::ok(
+            "version-lock",
+            "not locked, so updog takes the latest update",
+        )),
+        None => checks.push(Check::skip("version-lock", "config")),
+    }
+
+    checks.push(check_leftovers(paths, state.as_ref()));
+
+    match state {
+        Some(state) if state.upgrade_pending() => checks.push(Check::warn(
+            "pending-update",
+            "an update has been applied, but the host hasn't rebooted into it",
+            Message::DoctorPendingUpdate,
+        )),
+        Some(_) => checks.push(Check::ok(
+            "pending-update",
+            "no update is waiting for a reboot",
+        )),
+        None => checks.push(Check::skip("pending-update", "partitions")),
+    }
+
+    checks
+}
+
+/// Looks for files left by a run that didn't finish.  The doctor doesn't hold the run lock, so it
+/// only reports them; the next run that changes the host cleans them up.
+fn check_leftovers(paths: &Paths, state: Option<&State>) -> Check {
+    let leftovers = recover::scan(paths, state);
+    if leftovers.is_empty() {
+        return Check::ok("leftovers", "no files left from an interrupted run");
+    }
+    let found: Vec<_> = leftovers.iter().map(ToString::to_string).collect();
+    Check::warn(
+        "leftovers",
+        format!(
+            "found files left from an interrupted run: {}",
+            found.join(", ")
+        ),
+        Message::DoctorLeftovers,
+    )
+}
+
+/// Loads the config and checks the settings updog only parses when it uses them.
+fn check_config(paths: &Paths) -> Result<Config> {
+    let config = load_config(paths)?;
+    config.locked_version()?;
+    config.proxy_url()?;
+    config.target_cache_url()?;
+    config.host_identity()?;
+    Ok(config)
+}
+
+/// Checks that the trusted root shipped with the OS can be read.  It may well have expired; the
+/// repository is expected to have newer roots signed with it, which `check_repository` checks.
+fn check_trusted_root(path: &Path) -> Check {
+    let root = fs::read(path)
+        .map_err(|e| e.to_string())
+        .and_then(|data| serde_json::from_slice::<Signed<Root>>(&data).map_err(|e| e.to_string()));
+    match root {
+        Ok(root) => Check::ok(
+            "trusted-root",
+            format!(
+                "{} holds version {} of the root metadata",
+                path.display(),
+                root.signed.version
+            ),
+        ),
+        Err(e) => Check::fail(
+            "trusted-root",
+            format!("Failed to read {}: {}", path.display(), e),
+            Some(Message::DoctorTrustedRoot),
+        ),
+    }
+}
+
+/// Loads the repository and its manifest the way an update check does, then checks how long the
+/// latest root metadata is good for.
+fn check_repository(
+    paths: &Paths,
+    config: &Config,
+    version: &Version,
+    variant: &str,
+) -> Vec<Check> {
+    let loaded = build_transport(config).and_then(|transport| {
+        set_common_query_params(&transport, version, config)?;
+        let repository = load_repository(&transport, config, paths)?;
+        let manifest = load_manifest(&repository, variant, config.memory_budget()?)?;
+        Ok((
+            repository.root().signed.expires,
+            applicable_updates(&manifest, variant).len(),
+        ))
+    });
+    match loaded {
+        Ok((expires, updates)) => vec![
+            Check::ok(
+                "repository",
+                format!(
+                    "loaded {}, which lists {} update(s) for {}",
+                    config.metadata_base_url, updates, variant
+                ),
+            ),
+            check_root_expiry(expires, Utc::now()),
+        ],
+        Err(e) => vec![
+            Check::fail(
+                "repository",
+                e.to_string(),
+                Some(FailureClass::from(&e).remediation()),
+            ),
+            Check::skip("root-expiry", "repository"),
+        ],
+    }
+}
+
+fn check_root_expiry(expires: DateTime<Utc>, now: DateTime<Utc>) -> Check {
+    if expires <= now {
+        Check::fail(
+            "root-expiry",
+            format!("the repository's root metadata expired at {}", expires),
+            Some(Message::DoctorRootExpired),
+        )
+    } else if expires - now < Duration::days(ROOT_EXPIRY_WARNING_DAYS) {
+        Check::warn(
+            "root-expiry",
+            format!("the repository's root metadata expires at {}", expires),
+            Message::DoctorRootExpiring,
+        )
+    } else {
+        Check::ok(
+            "root-expiry",
+            format!("the repository's root metadata is good until {}", expires),
+        )
+    }
+}
+
+/// Checks the free space where updog will write into `dir`, which may not exist until the first
+/// update.
+fn check_free_space(dir: &Path) -> Check {
+    let existing = dir.ancestors().find(|path| path.exists()).unwrap_or(dir);
+    match free_bytes(existing) {
+        Ok(free) if free < MIN_FREE_BYTES => Check::warn(
+            "free-space",
+            format!("{} has only {} MiB free", dir.display(), free / 1024 / 1024),
+            Message::DoctorFreeSpace,
+        ),
+        Ok(free) => Check::ok(
+            "free-space",
+            format!("{} has {} MiB free", dir.display(), free / 1024 / 1024),
+        ),
+        Err(e) => Check::fail(
+            "free-space",
+            format!("Failed to check free space for {}: {}", dir.display(), e),
+            None,
+        ),
+    }
+}
+
+fn free_bytes(path: &Path) -> nix::Result<u64> {
+    let stat = nix::sys::statvfs::statvfs(path)?;
+    // Counts the space available to unprivileged users, like df does.
+    Ok(stat.blocks_available() * stat.fragment_size())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn root_expiry() {
+        let now = Utc::now();
+        assert_eq!(
+            check_root_expiry(now + Duration::days(365), now).health,
+            Health::Ok
+        );
+        assert_eq!(
+            check_root_expiry(now + Duration::days(7), now).health,
+            Health::Warn
+        );
+        assert_eq!(
+            check_root_expiry(now - Duration::days(1), now).health,
+            Health::Fail
+        );
+    }
+
+    #[test]
+    fn free_space_of_missing_dir() {
+        // Not existing yet is fine; the space is checked where the directory will be created.
+        let tmp = TempDir::new().unwrap();
+        let check = check_free_space(&tmp.path().join("not/there/yet"));
+        assert_ne!(check.health, Health::Fail, "{}", check);
+    }
+
+    #[test]
+    fn display() {
+        let check = Check::warn("free-space", "low", Message::DoctorFreeSpace);
+        assert_eq!(
+            check.to_string(),
+            format!(
+                "warn  free-space: low\n      {}",
+                Message::DoctorFreeSpace.text()
+            )
+        );
+        let value = serde_json::to_value(&check).unwrap();
+        assert_eq!(value["hint-id"], "doctor.free-space");
+        assert_eq!(value["hint"], Message::DoctorFreeSpace.text());
+        let check = Check::skip("repository", "config");
+        assert_eq!(
+            check.to_string(),
+            "skip  repository: skipped, since the config check failed"
+        );
+    }
+}
@@ -5,11 +5,24 @@ use snafu::{Backtrace, Snafu};
 use std::path::PathBuf;
 use update_metadata::error::Error as update_metadata_error;
 
-pub(crate) type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, Snafu)]
 #[snafu(visibility = "pub(crate)")]
-pub(crate) enum Error {
+pub enum Error {
+    #[snafu(display("Failed to get approval to activate the update: {}", message))]
+    ApprovalFailed {
+        message: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read changelog {}: {}", path.display(), source))]
+    ChangelogRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Failed to parse config file {}: {}", path.display(), source))]
     ConfigParse {
         path: PathBuf,
@@ -17,6 +30,48 @@ pub(crate) enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Invalid version lock '{}' in config: {}", version, source))]
+    ConfigVersionLock {
+        version: String,
+        source: semver::SemVerError,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Invalid HTTPS proxy '{}' in config: {}", proxy, source))]
+    ConfigProxy {
+        proxy: String,
+        source: url::ParseError,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Invalid target cache URL '{}' in config: {}", url, source))]
+    ConfigTargetCache {
+        url: String,
+        source: url::ParseError,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Invalid host identity '{}' in config, expected 'aws-instance-identity' or an absolute path",
+        identity
+    ))]
+    ConfigHostIdentity {
+        identity: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Invalid reboot window '{}' in config, expected like '02:00-04:00'",
+        window
+    ))]
+    ConfigRebootWindow {
+        window: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("The apply-and-reboot-in-window update policy needs a reboot window"))]
+    RebootWindowMissing { backtrace: Backtrace },
+
     #[snafu(display("Failed to read config file {}: {}", path.display(), source))]
     ConfigRead {
         path: PathBuf,
@@ -24,6 +79,12 @@ pub(crate) enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Failed to load the shared updater config: {}", source))]
+    SharedConfig {
+        source: updater_config::Error,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Failed to serialize config file {}: {}", path.display(), source))]
     ConfigSerialize {
         path: PathBuf,
@@ -44,8 +105,53 @@ pub(crate) enum Error {
         path: PathBuf,
     },
 
+    #[snafu(display("{} of updog's checks failed", failed))]
+    DoctorFailed { failed: usize, backtrace: Backtrace },
+
+    #[snafu(display("Failed to read or write the terminal: {}", source))]
+    EditIo {
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read history file {}: {}", path.display(), source))]
+    HistoryRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to get the host's identity: {}", source))]
+    HostIdentity {
+        source: crate::s3::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read host identity file {}: {}", path.display(), source))]
+    HostIdentityRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Invalid label '{}', expected KEY=VALUE using letters, digits, and '-'",
+        input
+    ))]
+    LabelArg { input: String, backtrace: Backtrace },
+
+    #[snafu(display("Lint found {} error(s)", errors))]
+    LintFailed { errors: usize, backtrace: Backtrace },
+
     #[snafu(display("Logger setup error: {}", source))]
-    Logger { source: simplelog::TermLogError },
+    Logger { source: log::SetLoggerError },
+
+    #[snafu(display("Failed to connect to log socket {}: {}", path.display(), source))]
+    LogSocket {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
 
     #[snafu(display("Could not mark inactive partition for boot: {}", source))]
     InactivePartitionUpgrade { source: signpost::Error },
@@ -78,12 +184,73 @@ pub(crate) enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Failed to load manifest: {}", source))]
+    ManifestLoad {
+        source: update_metadata::error::Error,
+    },
+
+    #[snafu(display(
+        "{} is {} bytes, more than the {} bytes updog's memory limit allows for it",
+        target,
+        size,
+        limit
+    ))]
+    ManifestTooLarge {
+        target: String,
+        size: u64,
+        limit: u64,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read manifest {}: {}", target, source))]
+    ManifestRead {
+        target: String,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to load manifest shard {}: {}", shard, source))]
+    ManifestShard {
+        shard: String,
+        source: update_metadata::error::Error,
+    },
+
     #[snafu(display("Failed to parse updates manifest: {}", source))]
     ManifestParse {
         source: serde_json::Error,
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Invalid manifest URL '{}': {}", url, source))]
+    ManifestUrl {
+        url: String,
+        source: url::ParseError,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("No manifest file at {}", url))]
+    S3ManifestMissing { url: url::Url, backtrace: Backtrace },
+
+    #[snafu(display("Failed to reach {}: {}", url, source))]
+    S3ManifestRequest {
+        url: url::Url,
+        source: crate::s3::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read or write {}: {}", url, source))]
+    S3ManifestResponse {
+        url: url::Url,
+        source: reqwest::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "{} changed since it was read, so it wasn't written; run the command again to make the change to the new version",
+        url
+    ))]
+    S3ManifestConflict { url: url::Url, backtrace: Backtrace },
+
     #[snafu(display("Metadata error: {}", source))]
     Metadata {
         source: tough::error::Error,
@@ -100,6 +267,12 @@ pub(crate) enum Error {
     #[snafu(display("Migration not found in image: {:?}", name))]
     MigrationNotLocal { backtrace: Backtrace, name: PathBuf },
 
+    #[snafu(display("Failed to move downloaded migration into place at '{}': {}", path.display(), source))]
+    MigrationRename {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
     #[snafu(display("Migration ({},{}) not present in manifest", from, to))]
     MigrationNotPresent {
         backtrace: Backtrace,
@@ -107,6 +280,32 @@ pub(crate) enum Error {
         to: Version,
     },
 
+    #[snafu(display("--name required to give the migrations to add"))]
+    MigrationNameArg { backtrace: Backtrace },
+
+    #[snafu(display("Migration {} not present in ({},{}) in manifest", name, from, to))]
+    MigrationNameNotPresent {
+        backtrace: Backtrace,
+        from: Version,
+        to: Version,
+        name: String,
+    },
+
+    #[snafu(display(
+        "No migration path between {} and {}: nothing leads on from {}, and {}",
+        low,
+        high,
+        reached,
+        detail
+    ))]
+    MigrationPathIncomplete {
+        backtrace: Backtrace,
+        low: Version,
+        high: Version,
+        reached: Version,
+        detail: String,
+    },
+
     #[snafu(display(
         "Reached end of migration chain at {} but target is {}",
         current,
@@ -133,6 +332,9 @@ pub(crate) enum Error {
     #[snafu(display("No update available"))]
     NoUpdate { backtrace: Backtrace },
 
+    #[snafu(display("No update is prepared; run `updog prepare` first"))]
+    NotPrepared { backtrace: Backtrace },
+
     #[snafu(display("Failed to open partition {}: {}", path.display(), source))]
     OpenPartition {
         path: PathBuf,
@@ -163,12 +365,522 @@ pub(crate) enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Failed to read prepared update record {}: {}", path.display(), source))]
+    PreparedRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read status file {}: {}", path.display(), source))]
+    StatusRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to save prepared update record {}: {}", path.display(), source))]
+    PreparedWrite {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Update has {} waves, so it needs {} percentages, not {}", waves, waves.saturating_sub(1), provided))]
+    RebalanceCount {
+        waves: usize,
+        provided: usize,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("--fleet-hosts must be at least 1"))]
+    RebalanceFleet { backtrace: Backtrace },
+
+    #[snafu(display(
+        "{} waves of at least {} hosts each don't fit in a fleet of {} hosts",
+        waves,
+        min_hosts,
+        fleet_hosts
+    ))]
+    RebalanceFit {
+        waves: usize,
+        min_hosts: u64,
+        fleet_hosts: u64,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Wave percentage {} must be above the one before it and below 100",
+        percentage
+    ))]
+    RebalancePercentage {
+        percentage: f64,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read registry credentials {}: {}", path.display(), source))]
+    RegistryAuthRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to parse registry credentials {}: {}", path.display(), source))]
+    RegistryAuthParse {
+        path: PathBuf,
+        source: serde_json::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("{} of the running OS's images don't match the repository", mismatched))]
+    RunningDrift {
+        mismatched: usize,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Running version {} of {} isn't in the manifest", version, variant))]
+    RunningNotInManifest {
+        version: Version,
+        variant: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read repository metadata {}: {}", path.display(), source))]
+    RepoMetadataRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to parse repository metadata {}: {}", path.display(), source))]
+    RepoMetadataParse {
+        path: PathBuf,
+        source: serde_json::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to get the canonical form of {}: {}", path.display(), source))]
+    RepoMetadataCanonical {
+        path: PathBuf,
+        source: tough::schema::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to sign repository metadata {}: {}", path.display(), source))]
+    RepoMetadataSign {
+        path: PathBuf,
+        source: tough::error::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to write repository metadata {}: {}", path.display(), source))]
+    RepoMetadataWrite {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("No N.root.json in {}", path.display()))]
+    RootMissing { path: PathBuf, backtrace: Backtrace },
+
+    #[snafu(display(
+        "{} rotation check(s) failed; keep signing with the key until they pass",
+        problems
+    ))]
+    RotationNotReady {
+        problems: usize,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read signing key {}: {}", path.display(), source))]
+    SigningKeyRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Signing key {} isn't an RSA key in PEM form: {}", path.display(), source))]
+    SigningKeyParse {
+        path: PathBuf,
+        source: tough::error::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read targets directory {}: {}", path.display(), source))]
+    TargetsDirRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to delete target {}: {}", path.display(), source))]
+    TargetDelete {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to create a metadata cache for mirroring: {}", source))]
+    MirrorDatastore {
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to fetch {} for the mirror: {}", url, source))]
+    MirrorFetch {
+        url: url::Url,
+        source: crate::transport::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to write {} in the mirror: {}", path.display(), source))]
+    MirrorWrite {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Mirror directory {} can't be used as a URL", path.display()))]
+    MirrorPath { path: PathBuf, backtrace: Backtrace },
+
+    #[snafu(display("Failed to verify target {}: {}", target, source))]
+    MirrorVerify {
+        target: String,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to pick the versions to bundle migrations for: {}", source))]
+    BundleVersions {
+        source: semver::ReqParseError,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to write bundle {}: {}", path.display(), source))]
+    BundleWrite {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read bundle {}: {}", path.display(), source))]
+    BundleRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to make a directory to unpack the bundle in {}: {}", path.display(), source))]
+    BundleDir {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Did not give a bundle to apply-bundle"))]
+    BundleMissing { backtrace: Backtrace },
+
+    #[snafu(display("Bundle directory {} can't be used as a URL", path.display()))]
+    BundlePath { path: PathBuf, backtrace: Backtrace },
+
+    #[snafu(display("Bundle {} holds {}, which bundles can't hold", path.display(), entry.display()))]
+    BundleEntry {
+        path: PathBuf,
+        entry: PathBuf,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to parse the inventory in bundle {}: {}", path.display(), source))]
+    BundleInventoryParse {
+        path: PathBuf,
+        source: serde_json::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Bundle {} isn't signed, but this host only takes signed bundles", path.display()))]
+    BundleUnsigned { path: PathBuf, backtrace: Backtrace },
+
+    #[snafu(display("Bundle {} isn't signed by any of this host's bundle keys", path.display()))]
+    BundleSignature { path: PathBuf, backtrace: Backtrace },
+
+    #[snafu(display("{} in bundle {} doesn't match the bundle's inventory", file, path.display()))]
+    BundleFileMismatch {
+        path: PathBuf,
+        file: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Memory limit of {} MiB in config is too small; updog needs at least {} MiB",
+        limit,
+        min
+    ))]
+    ConfigMemoryLimit {
+        limit: u32,
+        min: u32,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to limit updog's memory to {} bytes: {}", limit, source))]
+    MemoryLimit {
+        limit: u64,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Invalid bundle key '{}' in config: {}", key, source))]
+    ConfigBundleKey {
+        key: String,
+        source: base64::DecodeError,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read bundle signing key {}: {}", path.display(), source))]
+    BundleKeyRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Bundle signing key {} isn't an Ed25519 key in PKCS#8 form: {}", path.display(), source))]
+    BundleKeyParse {
+        path: PathBuf,
+        source: ring::error::KeyRejected,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Bundle holds {} {} for {}, but this host runs {} {} on {}",
+        bundle_variant,
+        bundle_version,
+        bundle_arch,
+        variant,
+        version,
+        arch
+    ))]
+    BundleNotApplicable {
+        bundle_variant: String,
+        bundle_version: Version,
+        bundle_arch: String,
+        variant: String,
+        version: Version,
+        arch: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Bundle's update {} isn't in the repository's manifest for this host",
+        version
+    ))]
+    BundleUpdateMissing {
+        version: Version,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Failed to reboot: {}", source))]
     RebootFailure {
         source: std::io::Error,
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Failed to read build directory {}: {}", path.display(), source))]
+    BuildDirRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Image {} is missing from the build", path.display()))]
+    BuildImageMissing { path: PathBuf, backtrace: Backtrace },
+
+    #[snafu(display("No images for version {} in {}", version, path.display()))]
+    BuildImagesMissing {
+        path: PathBuf,
+        version: Version,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Release {} is for version {}, not {}", path.display(), found, version))]
+    ReleaseMismatch {
+        path: PathBuf,
+        found: Version,
+        version: Version,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "No wave policy '{}'; give a wave file, or a policy in {}",
+        policy,
+        dir.display()
+    ))]
+    WavePolicy {
+        policy: String,
+        dir: PathBuf,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Target name '{}' needs '{{arch}}' in it to add updates for more than one architecture",
+        name
+    ))]
+    ArchTemplate { name: String, backtrace: Backtrace },
+
+    #[snafu(display("No wave preset '{}'; the presets are {}", preset, known))]
+    WavePreset {
+        preset: String,
+        known: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Wave preset '{}' has no waves", preset))]
+    PresetEmpty {
+        preset: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Invalid offset '{}' in wave preset '{}': {}", after, preset, source))]
+    PresetOffset {
+        preset: String,
+        after: String,
+        source: parse_datetime::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Wave '{}' in preset '{}' starts before the wave it follows, or doesn't take more of the fleet",
+        after,
+        preset
+    ))]
+    PresetOrder {
+        preset: String,
+        after: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read adoption file {}: {}", path.display(), source))]
+    AdoptionRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to parse adoption file {}: {}", path.display(), source))]
+    AdoptionParse {
+        path: PathBuf,
+        source: serde_json::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to write adoption file {}: {}", path.display(), source))]
+    AdoptionWrite {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Adoption of {} is for wave {}, which the update doesn't have",
+        update,
+        seed
+    ))]
+    AdoptionWave {
+        update: String,
+        seed: u32,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Adoption of {} says wave {} has more hosts updated than it has hosts",
+        update,
+        seed
+    ))]
+    AdoptionCounts {
+        update: String,
+        seed: u32,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "The manifest is in S3, so the adoption file needs to be given with --adoption"
+    ))]
+    AdoptionPath { backtrace: Backtrace },
+
+    #[snafu(display("Problems found in the manifest: {}", problems))]
+    ValidateStrict {
+        problems: usize,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Conflicts between the manifests to merge: {}", conflicts))]
+    MergeConflicts {
+        conflicts: usize,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Waves lagging behind their rollout: {}", waves))]
+    AdoptionLagging { waves: usize, backtrace: Backtrace },
+
+    #[snafu(display("Failed to parse plan file '{}': {}", path.display(), source))]
+    PlanParse {
+        path: PathBuf,
+        source: toml::de::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Step {} of the plan ({}) failed: {}", step, op, source))]
+    PlanStep {
+        step: usize,
+        op: String,
+        source: Box<Error>,
+    },
+
+    #[snafu(display("A set-waves step needs either a wave-file or waves, but not both"))]
+    PlanWaves { backtrace: Backtrace },
+
+    #[snafu(display("Failed to list backups in '{}': {}", path.display(), source))]
+    BackupList {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read '{}' to back it up or restore it: {}", path.display(), source))]
+    BackupRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to parse backup '{}': {}", path.display(), source))]
+    BackupParse {
+        path: PathBuf,
+        source: serde_json::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to remove backup '{}': {}", path.display(), source))]
+    BackupRemove {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to write backup '{}': {}", path.display(), source))]
+    BackupWrite {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("No backups of '{}' to restore", path.display()))]
+    NoBackup { path: PathBuf, backtrace: Backtrace },
+
+    #[snafu(display(
+        "{} is in S3, which updata doesn't back up; restore an old version of the object instead",
+        file
+    ))]
+    UndoRemote { file: String, backtrace: Backtrace },
+
     #[snafu(display("Failed to parse release metadata file '{}': {}", path.display(), source))]
     ReleaseParse {
         path: PathBuf,
@@ -179,6 +891,38 @@ pub(crate) enum Error {
     #[snafu(display("Unable to get OS version: {}", source))]
     ReleaseVersion { source: bottlerocket_release::Error },
 
+    #[snafu(display(
+        "Invalid --older-than '{}', expected a time in the past like '90 days ago', '-90d', or '2024-01-01': {}",
+        input,
+        source
+    ))]
+    PruneCutoff {
+        input: String,
+        source: parse_datetime::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "--older-than '{}' is {}, in the future, which would prune every update; for a time in the past, use something like '90 days ago' or '-90d'",
+        input,
+        cutoff
+    ))]
+    PruneCutoffFuture {
+        input: String,
+        cutoff: chrono::DateTime<chrono::Utc>,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Invalid seed '{}', expected a number from 0 to {}", input, max))]
+    SeedArg {
+        input: String,
+        max: u32,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Invalid seed range '{}', expected START..END", input))]
+    SeedRangeArg { input: String, backtrace: Backtrace },
+
     #[snafu(display("Failed setting permissions of '{}': {}", path.display(), source))]
     SetPermissions {
         path: PathBuf,
@@ -191,27 +935,137 @@ pub(crate) enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Failed to fetch {} from the target cache: {}", target, source))]
+    TargetCacheFetch {
+        target: String,
+        source: reqwest::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Target name {} can't be joined to the target cache URL: {}",
+        target,
+        source
+    ))]
+    TargetCacheUrl {
+        target: String,
+        source: url::ParseError,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Failed to create tmpfile for root mount"))]
     TmpFileCreate {
         backtrace: Backtrace,
         source: std::io::Error,
     },
 
+    #[snafu(display("Failed to build HTTP client: {}", source))]
+    TransportBuild {
+        backtrace: Backtrace,
+        source: reqwest::Error,
+    },
+
     #[snafu(display("2Borrow2Fast"))]
     TransportBorrow {
         backtrace: Backtrace,
         source: std::cell::BorrowMutError,
     },
 
+    #[snafu(display("No updates{}{} in the manifest to set the max version of",
+        match variant {
+            Some(variant) => format!(" for {}", variant),
+            None => String::new(),
+        },
+        match arch {
+            Some(arch) => format!(" on {}", arch),
+            None => String::new(),
+        }
+    ))]
+    MaxVersionNotFound {
+        variant: Option<String>,
+        arch: Option<String>,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("No updates for {} in the manifest{}", variant, match min_version {
+        Some(version) => format!(" at version {} or later", version),
+        None => String::new(),
+    }))]
+    ExtractEmpty {
+        variant: String,
+        min_version: Option<Version>,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read provenance document {}: {}", path.display(), source))]
+    ProvenanceRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Update {} has no provenance, which the host requires", version))]
+    ProvenanceMissing {
+        version: Version,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Provenance document {} doesn't match the manifest's digest", target))]
+    ProvenanceMismatch {
+        target: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to parse provenance document {}: {}", target, source))]
+    ProvenanceParse {
+        target: String,
+        source: serde_json::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Provenance doesn't vouch for image {} with its signed digest", image))]
+    ProvenanceSubject { image: String, backtrace: Backtrace },
+
+    #[snafu(display("A provenance target needs its digest, from --sha256 or --document"))]
+    ProvenanceDigestMissing { backtrace: Backtrace },
+
     #[snafu(display("No update available"))]
     UpdateNotAvailable { backtrace: Backtrace },
 
+    #[snafu(display("No update for {}-{}-{} in the manifest", arch, variant, version))]
+    UpdateNotFound {
+        arch: String,
+        variant: String,
+        version: Version,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "An update for {}-{}-{} is already in the manifest",
+        arch,
+        variant,
+        version
+    ))]
+    UpdateExists {
+        arch: String,
+        variant: String,
+        version: Version,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Update {} exists but wave in the future", version))]
     UpdateNotReady {
         backtrace: Backtrace,
         version: Version,
     },
 
+    #[snafu(display("Update held back by vetoes: {}", vetoes))]
+    UpdateVetoed {
+        vetoes: String,
+        version: Option<Version>,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Failed to serialize update information: {}", source))]
     UpdateSerialize {
         source: serde_json::Error,
@@ -227,9 +1081,50 @@ pub(crate) enum Error {
         backtrace: Backtrace,
     },
 
-    #[snafu(display("--wave-file <path> required to add waves to update"))]
+    #[snafu(display(
+        "--wave-file <path>, or --start with --bound or --fleet-percentage, required to add waves to update"
+    ))]
     WaveFileArg { backtrace: Backtrace },
 
+    #[snafu(display(
+        "--wave-policy or --wave-preset required to give the release's updates waves"
+    ))]
+    WavePolicyArg { backtrace: Backtrace },
+
+    #[snafu(display(
+        "--fleet-percentage must be less than 100, since a wave can't start after the whole fleet: {}",
+        percentage
+    ))]
+    WavePercentage {
+        percentage: u32,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Invalid start_after '{}' in wave file {}: {}", start_after, path.display(), source))]
+    WaveStart {
+        path: PathBuf,
+        start_after: String,
+        source: parse_datetime::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Update data read back from {} doesn't match what was written", path.display()))]
+    VerifyMismatch { path: PathBuf, backtrace: Backtrace },
+
+    #[snafu(display("Failed to download target {} to compare it: {}", target, source))]
+    VerifyTarget {
+        target: String,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read back update data from {}: {}", path.display(), source))]
+    VerifyRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Failed writing update data to disk: {}", source))]
     WriteUpdate {
         source: std::io::Error,
@@ -241,6 +1136,26 @@ pub(crate) enum Error {
         source: update_metadata::error::Error,
     },
 
+    #[snafu(display("Cancelled"))]
+    Cancelled { backtrace: Backtrace },
+
+    #[snafu(display("Another updog run is changing the host (process {})", pid))]
+    Busy { pid: String, backtrace: Backtrace },
+
+    #[snafu(display("Failed to open lock file {}: {}", path.display(), source))]
+    LockOpen {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to lock {}: {}", path.display(), source))]
+    Lock {
+        path: PathBuf,
+        source: nix::Error,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Failed to set up signal handler: {}", source))]
     Signal {
         source: std::io::Error,
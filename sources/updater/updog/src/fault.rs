@@ -0,0 +1,62 @@
+//! Hooks that let tests crash updog partway through writing an update, to check that the host is
+//! left in a state it can recover from.  They're only built with the `fault-injection` feature;
+//! otherwise they pass everything through untouched.
+//!
+//! Set `UPDOG_CRASH_WRITING` to the name of a target, for example the root image of an update, and
+//! updog aborts once it has written part of that target to disk.
+
+use std::io::Write;
+
+#[cfg(feature = "fault-injection")]
+const CRASH_WRITING_ENV: &str = "UPDOG_CRASH_WRITING";
+
+/// How many bytes of the target we write before crashing, so the crash happens with the
+/// destination partly written.
+#[cfg(feature = "fault-injection")]
+const CRASH_AFTER_BYTES: usize = 4096;
+
+/// Wraps the writer for `target`, so that updog crashes while writing it if a test asked for that.
+#[cfg(not(feature = "fault-injection"))]
+pub(crate) fn writer<W: Write>(_target: &str, writer: W) -> W {
+    writer
+}
+
+/// Wraps the writer for `target`, so that updog crashes while writing it if a test asked for that.
+#[cfg(feature = "fault-injection")]
+pub(crate) fn writer<W: Write>(target: &str, writer: W) -> CrashWriter<W> {
+    let crash = std::env::var(CRASH_WRITING_ENV).ok().as_deref() == Some(target);
+    CrashWriter {
+        inner: writer,
+        written: 0,
+        crash,
+    }
+}
+
+#[cfg(feature = "fault-injection")]
+pub(crate) struct CrashWriter<W> {
+    inner: W,
+    written: usize,
+    crash: bool,
+}
+
+#[cfg(feature = "fault-injection")]
+impl<W: Write> Write for CrashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.written += count;
+        if self.crash && self.written >= CRASH_AFTER_BYTES {
+            // Make sure what we wrote reaches the disk, like it might have before a real crash.
+            self.inner.flush()?;
+            eprintln!(
+                "Crashing while writing target, as requested by {}",
+                CRASH_WRITING_ENV
+            );
+            std::process::abort();
+        }
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
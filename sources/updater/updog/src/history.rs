@@ -0,0 +1,99 @@
+//! Keeps a rolling history of what updog found or did, so what happened leading up to an incident
+//! can be pieced together after the journal has rotated away.  Each line of the history file is
+//! the status saved from one run, oldest first.
+
+use crate::status::Status;
+use log::warn;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+/// The most runs kept in the history.  updog checks for updates a few times a day, so this covers
+/// a few months of checks, and much longer than the journal.
+pub(crate) const MAX_ENTRIES: usize = 512;
+
+/// Adds `status` to the end of the history at `path`, dropping the oldest entries past
+/// `max_entries`.  The file is replaced all at once, like the status file, so a crash can't
+/// leave half an entry behind.
+pub(crate) fn record(path: &Path, status: &Status, max_entries: usize) -> io::Result<()> {
+    let mut lines = match fs::File::open(path) {
+        Ok(file) => BufReader::new(file)
+            .lines()
+            .collect::<io::Result<Vec<_>>>()?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    lines.push(serde_json::to_string(status).map_err(io::Error::from)?);
+    let keep_from = lines.len().saturating_sub(max_entries);
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut data = lines[keep_from..].join("\n");
+    data.push('\n');
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)
+}
+
+/// Reads the history at `path`, oldest first.  Entries that can't be read, say from a newer
+/// updog, are skipped rather than hiding the rest of the history.
+pub(crate) fn load(path: &Path) -> io::Result<Vec<Status>> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        match serde_json::from_str(&line) {
+            Ok(status) => entries.push(status),
+            Err(e) => warn!("Skipping unreadable history entry '{}': {}", line, e),
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::{CheckResult, Outcome};
+    use tempfile::TempDir;
+
+    #[test]
+    fn rolls_over() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("updog/history.jsonl");
+        assert!(load(&path).unwrap().is_empty());
+
+        for result in &[
+            CheckResult::NoUpdate,
+            CheckResult::UpdateAvailable,
+            CheckResult::UpdateWritten,
+        ] {
+            let status = Status::new("update", Ok(&Outcome::new(*result, None)));
+            record(&path, &status, 2).unwrap();
+        }
+        let history = load(&path).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].result, CheckResult::UpdateAvailable);
+        assert_eq!(history[1].result, CheckResult::UpdateWritten);
+    }
+
+    #[test]
+    fn skips_unreadable_entries() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("history.jsonl");
+        fs::write(&path, "not json\n").unwrap();
+
+        let status = Status::new(
+            "check-update",
+            Ok(&Outcome::new(CheckResult::NoUpdate, None)),
+        );
+        record(&path, &status, MAX_ENTRIES).unwrap();
+        let history = load(&path).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].command, "check-update");
+    }
+}
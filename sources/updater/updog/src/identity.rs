@@ -0,0 +1,126 @@
+//! Tells a private update repository which host is asking, so it can hold to policies like "only
+//! registered hosts may download".  The identity is sent in headers with each HTTPS request for
+//! the repository's metadata and targets; it's never sent over plain HTTP, to the target cache,
+//! or to S3 and OCI sources, which have their own credentials.
+//!
+//! The identity is either the instance identity document from the AWS instance metadata
+//! service, signed by AWS, or the contents of a file kept up to date by something else on the
+//! host, like an attestation agent that writes a TPM quote.  Either way, the repository is the one
+//! checking it; updog just passes it along.
+
+use crate::error::{self, Result};
+use crate::s3::Imds;
+use snafu::{ensure, ResultExt};
+use std::fs;
+use std::path::PathBuf;
+
+/// Says where the identity came from, so the repository knows how to check it.
+pub(crate) const TYPE_HEADER: &str = "x-bottlerocket-identity-type";
+/// The identity itself, base64-encoded.
+pub(crate) const IDENTITY_HEADER: &str = "x-bottlerocket-identity";
+/// The signature over the identity, for types that have one apart from it.
+pub(crate) const SIGNATURE_HEADER: &str = "x-bottlerocket-identity-signature";
+
+/// Where to get the host's identity.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum HostIdentity {
+    /// The AWS instance identity document and its PKCS #7 signature.
+    AwsInstanceIdentity,
+    /// A file holding the identity, like a TPM quote.
+    File(PathBuf),
+}
+
+impl HostIdentity {
+    /// Parses the identity setting: `aws-instance-identity`, or the absolute path of a file.
+    pub(crate) fn parse(identity: &str) -> Result<Self> {
+        if identity == "aws-instance-identity" {
+            return Ok(Self::AwsInstanceIdentity);
+        }
+        let path = PathBuf::from(identity);
+        ensure!(path.is_absolute(), error::ConfigHostIdentity { identity });
+        Ok(Self::File(path))
+    }
+
+    /// Gets the identity and returns the headers to send it in.  It's read once per run, so a file
+    /// that's replaced while updog runs is picked up the next time.
+    pub(crate) fn headers(&self) -> Result<Vec<(String, String)>> {
+        match self {
+            Self::AwsInstanceIdentity => {
+                let (document, signature) = Imds::new()
+                    .and_then(|imds| imds.instance_identity())
+                    .context(error::HostIdentity)?;
+                Ok(aws_headers(&document, &signature))
+            }
+            Self::File(path) => {
+                let identity = fs::read(path).context(error::HostIdentityRead { path })?;
+                Ok(vec![
+                    (TYPE_HEADER.to_string(), "file".to_string()),
+                    (IDENTITY_HEADER.to_string(), base64::encode(&identity)),
+                ])
+            }
+        }
+    }
+}
+
+/// The document is JSON over several lines, so it's base64-encoded to fit in a header; the
+/// signature is already base64, but split over lines, which are joined.
+fn aws_headers(document: &str, signature: &str) -> Vec<(String, String)> {
+    let signature: String = signature.split_whitespace().collect();
+    vec![
+        (TYPE_HEADER.to_string(), "aws-instance-identity".to_string()),
+        (IDENTITY_HEADER.to_string(), base64::encode(document)),
+        (SIGNATURE_HEADER.to_string(), signature),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            HostIdentity::parse("aws-instance-identity").unwrap(),
+            HostIdentity::AwsInstanceIdentity
+        );
+        assert_eq!(
+            HostIdentity::parse("/run/attestation/quote").unwrap(),
+            HostIdentity::File(PathBuf::from("/run/attestation/quote"))
+        );
+        assert!(HostIdentity::parse("tpm-quote").is_err());
+        assert!(HostIdentity::parse("run/attestation/quote").is_err());
+    }
+
+    #[test]
+    fn aws() {
+        let headers = aws_headers("{\n  \"instanceId\" : \"i-1234\"\n}", "MIAG\nCSqG\n");
+        assert_eq!(headers[0].1, "aws-instance-identity");
+        assert_eq!(
+            base64::decode(&headers[1].1).unwrap(),
+            b"{\n  \"instanceId\" : \"i-1234\"\n}"
+        );
+        assert_eq!(
+            headers[2],
+            (SIGNATURE_HEADER.to_string(), "MIAGCSqG".to_string())
+        );
+    }
+
+    #[test]
+    fn file() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("quote");
+        fs::write(&path, b"\x00quote").unwrap();
+        let headers = HostIdentity::File(path.clone()).headers().unwrap();
+        assert_eq!(
+            headers,
+            vec![
+                (TYPE_HEADER.to_string(), "file".to_string()),
+                (IDENTITY_HEADER.to_string(), base64::encode(b"\x00quote")),
+            ]
+        );
+
+        fs::remove_file(&path).unwrap();
+        assert!(HostIdentity::File(path).headers().is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! Writes update images to the inactive partition set, and reads them back to check them.
+
+use crate::cache::TargetCache;
+use crate::cancel::Cancel;
+use crate::config::Paths;
+use crate::error::{self, Result};
+use crate::fault;
+use crate::prepared::Prepared;
+use crate::transport::HttpQueryRepo;
+use audit::{Action, Actor};
+use log::{info, warn};
+use ring::digest::{self, Digest};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use update_metadata::Update;
+
+/// What was written for a target, so it can be read back and checked.
+pub(crate) struct Written {
+    pub(crate) len: u64,
+    digest: Digest,
+}
+
+impl Written {
+    pub(crate) fn matches(&self, other: &Self) -> bool {
+        self.len == other.len && self.digest.as_ref() == other.digest.as_ref()
+    }
+}
+
+/// Passes writes through to `inner`, keeping count and a digest of what was written.
+pub(crate) struct DigestWriter<W> {
+    inner: W,
+    len: u64,
+    digest: digest::Context,
+}
+
+impl<W: Write> DigestWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            len: 0,
+            digest: digest::Context::new(&digest::SHA256),
+        }
+    }
+
+    pub(crate) fn finish(self) -> Written {
+        Written {
+            len: self.len,
+            digest: self.digest.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.len += count as u64;
+        self.digest.update(&buf[..count]);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub(crate) fn write_target_to_disk<P: AsRef<Path>>(
+    repository: &HttpQueryRepo<'_>,
+    cache: Option<&TargetCache>,
+    target: &str,
+    disk_path: P,
+    cancel: &Cancel,
+) -> Result<Written> {
+    if let Some(cache) = cache {
+        let written = cache
+            .read_target(repository, target)
+            .and_then(|reader| write_reader(reader, target, disk_path.as_ref(), cancel));
+        match written {
+            Ok(written) => {
+                info!("Fetched {} from the target cache", target);
+                return Ok(written);
+            }
+            Err(e @ error::Error::Cancelled { .. }) => return Err(e),
+            Err(e) => warn!(
+                "Failed to fetch {} from the target cache, using the repository: {}",
+                target, e
+            ),
+        }
+    }
+    let reader = repository
+        .read_target(target)
+        .context(error::Metadata)?
+        .context(error::TargetNotFound { target })?;
+    write_reader(reader, target, disk_path.as_ref(), cancel)
+}
+
+/// Decompresses `target` from `reader` onto `disk_path`, stopping partway if the run is
+/// cancelled.
+fn write_reader<R: Read>(
+    reader: R,
+    target: &str,
+    disk_path: &Path,
+    cancel: &Cancel,
+) -> Result<Written> {
+    // Note: the file extension for the compression type we're using should be removed in
+    // `repo::retrieve_migrations`.
+    let mut reader =
+        lz4::Decoder::new(cancel.reader(reader)).context(error::Lz4Decode { target })?;
+    let f = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .open(disk_path)
+        .context(error::OpenPartition { path: disk_path })?;
+    let mut f = DigestWriter::new(fault::writer(target, f));
+    let copied = io::copy(&mut reader, &mut f);
+    cancel.check()?;
+    copied.context(error::WriteUpdate)?;
+    f.flush().context(error::WriteUpdate)?;
+    // Read to the end of the target, so its digest is checked even if the compressed data ends
+    // early.
+    let (mut reader, finished) = reader.finish();
+    finished.context(error::Lz4Decode { target })?;
+    io::copy(&mut reader, &mut io::sink()).context(error::WriteUpdate)?;
+    Ok(f.finish())
+}
+
+/// Reads back what was written to `disk_path`, checking that it matches.
+fn verify_written(disk_path: &Path, written: &Written) -> Result<()> {
+    ensure!(
+        read_back(disk_path, written.len)?.matches(written),
+        error::VerifyMismatch { path: disk_path }
+    );
+    Ok(())
+}
+
+/// Reads up to `len` bytes from the start of `disk_path`, to compare with what belongs there.
+pub(crate) fn read_back(disk_path: &Path, len: u64) -> Result<Written> {
+    let f = File::open(disk_path).context(error::OpenPartition { path: disk_path })?;
+    let mut read = DigestWriter::new(io::sink());
+    io::copy(&mut f.take(len), &mut read).context(error::VerifyRead { path: disk_path })?;
+    Ok(read.finish())
+}
+
+/// Writes the update's images to the inactive partition set, and marks the set valid, recording
+/// the write in the audit trail.  If `verify` is set, the images are read back and checked before
+/// the set is marked.  If the run is cancelled, or fails, partway through, the set is left marked
+/// invalid, so nothing boots it or takes it for a prepared update.
+pub(crate) fn update_image(
+    update: &Update,
+    repository: &HttpQueryRepo<'_>,
+    cache: Option<&TargetCache>,
+    paths: &Paths,
+    verify: bool,
+    cancel: &Cancel,
+) -> Result<()> {
+    let result = write_images(update, repository, cache, paths, verify, cancel);
+    audit::record(
+        Actor::Updog,
+        Action::ImageWrite,
+        update.version.to_string(),
+        &result,
+    );
+    result
+}
+
+/// Does the work of `update_image`.
+fn write_images(
+    update: &Update,
+    repository: &HttpQueryRepo<'_>,
+    cache: Option<&TargetCache>,
+    paths: &Paths,
+    verify: bool,
+    cancel: &Cancel,
+) -> Result<()> {
+    let mut gpt_state = paths.partition_state()?;
+    // Whatever was prepared on the inactive partition set is about to be overwritten.
+    Prepared::clear(&paths.prepared).context(error::PreparedWrite {
+        path: &paths.prepared,
+    })?;
+    gpt_state.clear_inactive();
+    // Write out the clearing of the inactive partition immediately, because we're about to
+    // overwrite the partition set with update data and don't want it to be used until we
+    // know we're done with all components.
+    gpt_state.write().context(error::PartitionTableWrite)?;
+
+    let inactive = gpt_state.inactive_set();
+
+    // TODO Do we want to recover the inactive side on an error?
+    let images = [
+        (&update.images.root, &inactive.root),
+        (&update.images.boot, &inactive.boot),
+        (&update.images.hash, &inactive.hash),
+    ];
+    for (target, partition) in &images {
+        let written = write_target_to_disk(repository, cache, target, partition, cancel)?;
+        if verify {
+            verify_written(partition, &written)?;
+        }
+    }
+    cancel.check()?;
+
+    gpt_state.mark_inactive_valid();
+    gpt_state.write().context(error::PartitionTableWrite)?;
+    Ok(())
+}
+
+/// Returns the record of `update` if it's already prepared and still staged on the inactive
+/// partition set, so it doesn't need writing again.
+pub(crate) fn already_prepared(
+    paths: &Paths,
+    variant: &str,
+    update: &Update,
+) -> Result<Option<Prepared>> {
+    let prepared = Prepared::load(&paths.prepared).context(error::PreparedRead {
+        path: &paths.prepared,
+    })?;
+    let prepared = match prepared {
+        Some(prepared) if prepared.variant == variant && prepared.version == update.version => {
+            prepared
+        }
+        _ => return Ok(None),
+    };
+    let state = paths.partition_state()?;
+    Ok(if prepared.is_staged(&state) {
+        Some(prepared)
+    } else {
+        None
+    })
+}
@@ -0,0 +1,313 @@
+//! updog finds, stages, and activates updates for a Bottlerocket host.  This library does the
+//! work, and the `updog` binary is a thin command line over it, so the API server, a fleet
+//! orchestration agent, and tests can run updates in the same process and get typed results
+//! instead of spawning updog and reading its output.
+//!
+//! A command is given as `Arguments`, like the options on updog's command line:
+//!
+//! ```no_run
+//! use updog::{Arguments, CheckResult, Command, Output};
+//!
+//! let arguments = Arguments::new(Command::Prepare);
+//! let run = updog::run(&arguments);
+//! // Save the status file and history, and tell webhooks, like the command line does.
+//! updog::record(&arguments, &run);
+//! match run {
+//!     Ok(Output {
+//!         outcome: Some(outcome),
+//!         ..
+//!     }) if outcome.result == CheckResult::UpdatePrepared => {
+//!         println!("prepared {:?}", outcome.update_version)
+//!     }
+//!     Ok(output) => println!("{}", output.report),
+//!     Err(e) => eprintln!("{} ({:?})", e, updog::FailureClass::from(&e)),
+//! }
+//! ```
+//!
+//! The library doesn't print anything; a run returns a `Report` of what it found or did, which
+//! the command line shows as text or JSON.  updog doesn't set up logging as a library unless
+//! asked to with `init_logging`, so it logs wherever its caller does.
+
+#![deny(rust_2018_idioms)]
+#![warn(clippy::pedantic)]
+
+mod activate;
+mod approval;
+mod auto;
+mod budget;
+// updog only unpacks bundles; updata makes them.
+#[allow(dead_code)]
+mod bundle;
+mod cache;
+mod cancel;
+mod clock;
+mod config;
+mod doctor;
+mod error;
+mod fault;
+mod history;
+mod identity;
+mod image;
+mod lock;
+mod logging;
+mod messages;
+mod oci;
+mod prepared;
+mod provenance;
+mod recover;
+mod repo;
+mod report;
+mod s3;
+mod status;
+mod transport;
+mod update;
+mod verify;
+mod veto;
+mod webhook;
+
+use crate::activate::activate;
+pub use crate::cancel::Cancel;
+pub use crate::config::{effective_config, Paths};
+pub use crate::doctor::Check;
+pub use crate::error::{Error, Result};
+use crate::lock::RunLock;
+pub use crate::logging::LogTarget;
+pub use crate::messages::Message;
+pub use crate::prepared::Prepared;
+pub use crate::report::{CheckOutput, FetchWindow, Output, PreparedStatus, Report, StatusReport};
+pub use crate::status::{CheckResult, FailureClass, Outcome, Status};
+pub use crate::verify::Verification;
+use chrono::{DateTime, Utc};
+use log::{warn, LevelFilter};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[cfg(target_arch = "x86_64")]
+const TARGET_ARCH: &str = "x86_64";
+#[cfg(target_arch = "aarch64")]
+const TARGET_ARCH: &str = "aarch64";
+
+/// A command, named like updog's subcommands, such as `check-update`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Command {
+    /// Shows whether an update is available.
+    CheckUpdate,
+    /// Like `CheckUpdate`.
+    Whats,
+    /// Writes an update to the inactive partition set and checks it, without activating it.
+    Prepare,
+    /// Sets the update staged by `Prepare` to boot next.
+    Activate,
+    /// Writes an update and sets it to boot next.
+    Update,
+    /// Writes an update without setting it to boot.
+    UpdateImage,
+    /// Sets an update written by `UpdateImage` to boot next.
+    UpdateApply,
+    /// Checks that updog has what it needs to update the host.
+    Doctor,
+    /// Shows the results of recent runs.
+    History,
+    /// Shows the result of the last run and the prepared update.
+    Status,
+    /// Checks the running OS against the repository.
+    VerifyCurrent,
+    /// Takes an update as far as the configured policy allows.
+    Auto,
+    /// Applies an update from a bundle.
+    ApplyBundle,
+}
+
+impl FromStr for Command {
+    type Err = serde_plain::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        serde_plain::from_str(s)
+    }
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&serde_plain::to_string(self).map_err(|_| std::fmt::Error)?)
+    }
+}
+
+/// A command to run, and how, like the options given on updog's command line.
+#[derive(Debug, Clone)]
+pub struct Arguments {
+    pub command: Command,
+    /// Ignore the release schedule, and update right away.
+    pub ignore_waves: bool,
+    /// Update to this version rather than the latest.
+    pub force_version: Option<Version>,
+    /// For `CheckUpdate`, show every applicable update; for `VerifyCurrent`, compare the root
+    /// image too.
+    pub all: bool,
+    /// Reboot into the update once it's set to boot.
+    pub reboot: bool,
+    /// Update at this time rather than the host's time in its wave.
+    pub timestamp: Option<DateTime<Utc>>,
+    /// For `Activate`, don't activate before this time.
+    pub not_before: Option<DateTime<Utc>>,
+    /// For `Activate`, wait until the update may be activated rather than schedule it.
+    pub wait: bool,
+    /// For `Activate`, don't wait for the configured soak time.
+    pub skip_soak: bool,
+    /// The bundle given to `ApplyBundle`.
+    pub bundle: Option<PathBuf>,
+    pub paths: Paths,
+    /// Stops the run at the next point it can stop cleanly, once cancelled.
+    pub cancel: Cancel,
+}
+
+impl Arguments {
+    /// Arguments for `command` with the defaults, and the host's usual paths.
+    pub fn new(command: Command) -> Self {
+        Self {
+            command,
+            ignore_waves: false,
+            force_version: None,
+            all: false,
+            reboot: false,
+            timestamp: None,
+            not_before: None,
+            wait: false,
+            skip_soak: false,
+            bundle: None,
+            paths: Paths::default(),
+            cancel: Cancel::default(),
+        }
+    }
+}
+
+/// Sets up logging at `level` the way the command line does: to `target` if it's given, or else
+/// where updog's config says, or else the journal when run by systemd and the terminal otherwise.
+/// Only call this if nothing else in the process sets up the `log` crate.
+///
+/// # Errors
+///
+/// Fails if a logger is already set up, or the journal or syslog can't be reached.
+pub fn init_logging(
+    level: LevelFilter,
+    target: Option<LogTarget>,
+    arguments: &Arguments,
+) -> Result<()> {
+    let target = target
+        .or_else(|| config::configured_log_target(&arguments.paths.config))
+        .unwrap_or_default();
+    logging::init(
+        level,
+        target,
+        "updog",
+        &[("UPDOG_COMMAND", &arguments.command.to_string())],
+    )
+}
+
+/// Runs the command, returning what it found or did: what's worth saving in the status file, if
+/// anything, and a report to show.  A run that doesn't find an update ready for the host fails
+/// with an error, like `Error::UpdateNotAvailable`; `Status::new` counts those as results rather
+/// than failures.  `doctor` and `verify-current` succeed with what they found even if it's a
+/// problem, so it can be shown; `Report::ensure_passed` says whether it is.
+///
+/// Commands that write or activate an update hold a lock while they run, so only one at a time
+/// changes the host.  The run stops early with `Error::Cancelled` if `arguments.cancel` is
+/// cancelled.
+///
+/// # Errors
+///
+/// Fails if the command does, for any of the reasons in `Error`, or with `Error::Busy` if another
+/// run holds the lock.
+pub fn run(arguments: &Arguments) -> Result<Output> {
+    let _lock = if changes_host(arguments.command) {
+        let lock = RunLock::acquire(&arguments.paths.lock)?;
+        // With the lock held, anything a run was writing was left by one that didn't finish.
+        recover::recover(&arguments.paths);
+        Some(lock)
+    } else {
+        None
+    };
+    run_command(arguments, arguments.command)
+}
+
+/// Returns whether `command` can write or activate an update, so it needs the run lock.
+fn changes_host(command: Command) -> bool {
+    match command {
+        Command::Prepare
+        | Command::Activate
+        | Command::Update
+        | Command::UpdateImage
+        | Command::UpdateApply
+        | Command::Auto
+        | Command::ApplyBundle => true,
+        Command::CheckUpdate
+        | Command::Whats
+        | Command::Doctor
+        | Command::History
+        | Command::Status
+        | Command::VerifyCurrent => false,
+    }
+}
+
+/// Saves the status of a run of `arguments.command` to the status file and history, and tells
+/// the configured webhooks if the host's update state changed, returning the status.  Runs that
+/// didn't check for or act on an update, like listing updates, checking updog's health, or
+/// verifying the running OS, aren't saved.  Anything that goes wrong is logged, since the run is
+/// already done.
+pub fn record(arguments: &Arguments, run: &Result<Output>) -> Option<Status> {
+    let command = arguments.command.to_string();
+    let status = match run {
+        _ if arguments.command == Command::Doctor
+            || arguments.command == Command::VerifyCurrent =>
+        {
+            return None
+        }
+        Ok(Output {
+            outcome: Some(outcome),
+            ..
+        }) => Status::new(&command, Ok(outcome)),
+        Ok(_) => return None,
+        Err(e) => Status::new(&command, Err(e)),
+    };
+    // Events are worked out from the previous status, so this comes before the history is
+    // updated.
+    webhook::notify(&arguments.paths, &status);
+    if let Err(e) = status.write(&arguments.paths.status) {
+        warn!("Failed to save status: {}", e);
+    }
+    if let Err(e) = history::record(&arguments.paths.history, &status, history::MAX_ENTRIES) {
+        warn!("Failed to add status to history: {}", e);
+    }
+    Some(status)
+}
+
+/// Does the work of `run`.  `auto` runs other commands through this.
+fn run_command(arguments: &Arguments, command: Command) -> Result<Output> {
+    let paths = &arguments.paths;
+    match command {
+        Command::Auto => auto::run(arguments),
+        Command::ApplyBundle => update::apply_bundle(arguments),
+        Command::Doctor => Ok(Output::report(Report::Doctor(doctor::run(paths)))),
+        Command::Activate => activate(paths, arguments),
+        Command::History => {
+            let history = history::load(&paths.history).context(error::HistoryRead {
+                path: &paths.history,
+            })?;
+            Ok(Output::report(Report::History(history)))
+        }
+        Command::Status => Ok(Output::report(Report::Status(StatusReport::load(
+            paths,
+            Utc::now(),
+        )?))),
+        Command::CheckUpdate
+        | Command::Whats
+        | Command::Prepare
+        | Command::Update
+        | Command::UpdateImage
+        | Command::UpdateApply
+        | Command::VerifyCurrent => update::run(arguments, command),
+    }
+}
@@ -0,0 +1,79 @@
+//! Keeps two runs from changing the host at once, like a timer's `updog auto` and an operator's
+//! `updog update`.  Runs that write or activate an update hold an exclusive lock on the lock file
+//! for as long as they run; the lock goes with the file descriptor, so it's released when the
+//! run ends for any reason, including a crash, and never needs cleaning up.  The file holds the
+//! process ID of the run that last took the lock, so the API server can ask a running update to
+//! cancel; it only counts while the file is locked.
+
+use crate::error::{self, Result};
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use snafu::ResultExt;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::process;
+
+/// An exclusive lock on the lock file, held until dropped.
+#[derive(Debug)]
+pub(crate) struct RunLock {
+    _file: File,
+}
+
+impl RunLock {
+    /// Takes the lock at `path`, failing right away with `Error::Busy` if another run holds it.
+    pub(crate) fn acquire(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context(error::LockOpen { path })?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .context(error::LockOpen { path })?;
+        match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+            Ok(()) => {}
+            Err(nix::Error::Sys(Errno::EAGAIN)) => {
+                let pid = fs::read_to_string(path).unwrap_or_default();
+                return error::Busy {
+                    pid: pid.trim().to_string(),
+                }
+                .fail();
+            }
+            Err(e) => return Err(e).context(error::Lock { path }),
+        }
+        file.set_len(0)
+            .and_then(|()| write!(file, "{}", process::id()))
+            .context(error::LockOpen { path })?;
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use tempfile::TempDir;
+
+    #[test]
+    fn one_run_at_a_time() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("updog").join("lock");
+
+        let lock = RunLock::acquire(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            process::id().to_string()
+        );
+        match RunLock::acquire(&path) {
+            Err(Error::Busy { pid, .. }) => assert_eq!(pid, process::id().to_string()),
+            other => panic!("took a held lock: {:?}", other),
+        }
+
+        drop(lock);
+        RunLock::acquire(&path).unwrap();
+    }
+}
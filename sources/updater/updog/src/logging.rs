@@ -0,0 +1,333 @@
+//! updog and updata log through the `log` crate, and this is where the log goes.  At a terminal,
+//! that's the terminal, with errors on stderr and the rest on stdout.  Run by systemd at boot,
+//! it's the journal, with entries sent over journald's native protocol so they keep their
+//! priority and carry fields to search by, like `journalctl UPDOG_COMMAND=auto`, rather than
+//! being lines scraped from stderr.  Hosts that forward syslog can log there instead, and
+//! anything else can take plain lines on stderr.
+
+use crate::error::{self, Result};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+use simplelog::{Config as LogConfig, TermLogger, TerminalMode, WriteLogger};
+use snafu::ResultExt;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// The syslog facility for system daemons.
+const LOG_DAEMON: u8 = 3;
+
+/// Where to send the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogTarget {
+    /// The journal if stderr is connected to it, like when run by systemd, and otherwise the
+    /// terminal.
+    Auto,
+    /// The terminal, or plain lines on stderr if there's no terminal.
+    Terminal,
+    /// Plain lines on stderr.
+    Stderr,
+    /// The journal, over journald's native protocol.
+    Journald,
+    /// The local syslog socket.
+    Syslog,
+}
+
+impl Default for LogTarget {
+    fn default() -> Self {
+        LogTarget::Auto
+    }
+}
+
+impl FromStr for LogTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        serde_plain::from_str(s).map_err(|_| {
+            format!(
+                "Invalid log target '{}', expected auto, terminal, stderr, journald, or syslog",
+                s
+            )
+        })
+    }
+}
+
+/// Sets up logging at `level` to `target`.  `identifier` names the program in the journal and
+/// syslog, and every journal entry gets `fields` as well.
+pub(crate) fn init(
+    level: LevelFilter,
+    target: LogTarget,
+    identifier: &'static str,
+    fields: &[(&str, &str)],
+) -> Result<()> {
+    let logger: Box<dyn Log> = match resolve(target) {
+        LogTarget::Terminal => {
+            // TerminalMode::Mixed will send errors to stderr and anything less to stdout.
+            match TermLogger::new(level, LogConfig::default(), TerminalMode::Mixed) {
+                Some(logger) => logger,
+                None => WriteLogger::new(level, LogConfig::default(), io::stderr()),
+            }
+        }
+        LogTarget::Stderr => WriteLogger::new(level, LogConfig::default(), io::stderr()),
+        LogTarget::Journald => Box::new(Journald::connect(
+            Path::new(JOURNALD_SOCKET),
+            level,
+            identifier,
+            fields,
+        )?),
+        LogTarget::Syslog => Box::new(Syslog::connect(
+            Path::new(SYSLOG_SOCKET),
+            level,
+            identifier,
+        )?),
+        LogTarget::Auto => unreachable!("auto is resolved to another target"),
+    };
+    log::set_max_level(level);
+    log::set_boxed_logger(logger).context(error::Logger)
+}
+
+/// Picks the target for `Auto`.
+fn resolve(target: LogTarget) -> LogTarget {
+    match target {
+        LogTarget::Auto if stderr_is_journal() => LogTarget::Journald,
+        LogTarget::Auto => LogTarget::Terminal,
+        target => target,
+    }
+}
+
+/// Returns whether stderr is connected to the journal.  systemd sets `JOURNAL_STREAM` to the
+/// device and inode of the journal's stream, like `8:12345`, for services whose output goes to
+/// it; if stderr isn't that stream, it's been redirected.
+fn stderr_is_journal() -> bool {
+    let stream = match env::var("JOURNAL_STREAM") {
+        Ok(stream) => stream,
+        Err(_) => return false,
+    };
+    let mut parts = stream.splitn(2, ':');
+    let (dev, ino) = match (parts.next(), parts.next()) {
+        (Some(dev), Some(ino)) => (dev.parse::<u64>(), ino.parse::<u64>()),
+        _ => return false,
+    };
+    match (dev, ino, fs::metadata("/proc/self/fd/2")) {
+        (Ok(dev), Ok(ino), Ok(stderr)) => stderr.dev() == dev && stderr.ino() == ino,
+        _ => false,
+    }
+}
+
+/// The syslog severity for a log level.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Logs to the journal over its native protocol, where each entry is a datagram of fields.
+struct Journald {
+    socket: UnixDatagram,
+    level: LevelFilter,
+    /// The fields every entry has, already encoded.
+    common: Vec<u8>,
+}
+
+impl Journald {
+    fn connect(
+        path: &Path,
+        level: LevelFilter,
+        identifier: &str,
+        fields: &[(&str, &str)],
+    ) -> Result<Self> {
+        let socket = UnixDatagram::unbound()
+            .and_then(|socket| socket.connect(path).map(|()| socket))
+            .context(error::LogSocket { path })?;
+        let mut common = Vec::new();
+        add_field(&mut common, "SYSLOG_IDENTIFIER", identifier);
+        add_field(&mut common, "SYSLOG_PID", &process::id().to_string());
+        for (name, value) in fields {
+            add_field(&mut common, name, value);
+        }
+        Ok(Self {
+            socket,
+            level,
+            common,
+        })
+    }
+}
+
+/// Adds a field to a journal entry.  Values with newlines in them are sent with their length
+/// rather than ending at a newline.
+fn add_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+impl Log for Journald {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut entry = self.common.clone();
+        add_field(&mut entry, "MESSAGE", &record.args().to_string());
+        add_field(
+            &mut entry,
+            "PRIORITY",
+            &severity(record.level()).to_string(),
+        );
+        add_field(&mut entry, "TARGET", record.target());
+        if let Some(file) = record.file() {
+            add_field(&mut entry, "CODE_FILE", file);
+        }
+        if let Some(line) = record.line() {
+            add_field(&mut entry, "CODE_LINE", &line.to_string());
+        }
+        // An entry too big for a datagram, or a journal that's gone away, shouldn't lose the
+        // message, least of all an error.
+        if self.socket.send(&entry).is_err() {
+            eprintln!("{}: {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Logs to the local syslog socket, in the short form local daemons take.
+struct Syslog {
+    socket: UnixDatagram,
+    level: LevelFilter,
+    identifier: String,
+}
+
+impl Syslog {
+    fn connect(path: &Path, level: LevelFilter, identifier: &str) -> Result<Self> {
+        let socket = UnixDatagram::unbound()
+            .and_then(|socket| socket.connect(path).map(|()| socket))
+            .context(error::LogSocket { path })?;
+        Ok(Self {
+            socket,
+            level,
+            identifier: identifier.to_string(),
+        })
+    }
+}
+
+impl Log for Syslog {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!(
+            "<{}>{}[{}]: {}",
+            LOG_DAEMON * 8 + severity(record.level()),
+            self.identifier,
+            process::id(),
+            record.args()
+        );
+        if self.socket.send(line.as_bytes()).is_err() {
+            eprintln!("{}: {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Record;
+
+    fn record(log: &dyn Log, level: Level, message: &str) {
+        log.log(
+            &Record::builder()
+                .level(level)
+                .target("updog")
+                .args(format_args!("{}", message))
+                .file(Some("src/main.rs"))
+                .line(Some(42))
+                .build(),
+        );
+    }
+
+    #[test]
+    fn parse_target() {
+        assert_eq!("journald".parse(), Ok(LogTarget::Journald));
+        assert_eq!("auto".parse(), Ok(LogTarget::Auto));
+        assert!("journal".parse::<LogTarget>().is_err());
+    }
+
+    #[test]
+    fn journald_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("socket");
+        let journal = UnixDatagram::bind(&path).unwrap();
+        let logger = Journald::connect(
+            &path,
+            LevelFilter::Info,
+            "updog",
+            &[("UPDOG_COMMAND", "auto")],
+        )
+        .unwrap();
+
+        record(&logger, Level::Warn, "first\nsecond");
+        let mut buf = [0; 1024];
+        let len = journal.recv(&mut buf).unwrap();
+        let entry = &buf[..len];
+        let text = String::from_utf8_lossy(entry);
+        assert!(text.starts_with("SYSLOG_IDENTIFIER=updog\nSYSLOG_PID="));
+        assert!(text.contains("\nUPDOG_COMMAND=auto\n"));
+        assert!(text.contains("\nPRIORITY=4\nTARGET=updog\nCODE_FILE=src/main.rs\nCODE_LINE=42\n"));
+        // The multi-line message is sent with its length.
+        let mut message = b"MESSAGE\n".to_vec();
+        message.extend_from_slice(&12u64.to_le_bytes());
+        message.extend_from_slice(b"first\nsecond\n");
+        assert!(entry
+            .windows(message.len())
+            .any(|w| w == message.as_slice()));
+
+        // Entries below the level aren't sent.
+        record(&logger, Level::Debug, "quiet");
+        journal.set_nonblocking(true).unwrap();
+        assert!(journal.recv(&mut buf).is_err());
+    }
+
+    #[test]
+    fn syslog_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("socket");
+        let syslog = UnixDatagram::bind(&path).unwrap();
+        let logger = Syslog::connect(&path, LevelFilter::Info, "updata").unwrap();
+
+        record(&logger, Level::Error, "failed");
+        let mut buf = [0; 1024];
+        let len = syslog.recv(&mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buf[..len]),
+            format!("<27>updata[{}]: failed", process::id())
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! The hints and notes updog shows operators, each with a stable ID.  The English text can be
+//! reworded from one release to the next, but an ID is never changed or reused once released, so
+//! tooling that reads updog's JSON output or status file should key off the ID.  IDs are grouped
+//! by where they're used, like `remediation.network` for the hint saved with a network failure.
+
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+
+macro_rules! catalog {
+    ($($(#[$attr:meta])* $name:ident = $id:literal => $text:literal,)*) => {
+        /// A message from the catalog.  It's serialized as its ID.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Message {
+            $($(#[$attr])* $name,)*
+        }
+
+        impl Message {
+            pub const ALL: &'static [Message] = &[$(Message::$name,)*];
+
+            /// The message's stable ID.
+            pub fn id(self) -> &'static str {
+                match self {
+                    $(Message::$name => $id,)*
+                }
+            }
+
+            /// The message's English text.
+            pub fn text(self) -> &'static str {
+                match self {
+                    $(Message::$name => $text,)*
+                }
+            }
+        }
+    };
+}
+
+catalog! {
+    // Hints saved with each class of failure; see `status::FailureClass`.
+    RemediationConfig = "remediation.config"
+        => "Check updog's config and the host's os-release and trusted root",
+    RemediationNetwork = "remediation.network"
+        => "Check that the host can reach settings.updates.metadata-base-url and targets-base-url, and settings.network.https-proxy and no-proxy if it needs a proxy",
+    RemediationTrust = "remediation.trust"
+        => "Check that the host's clock is right and that the repository is signed for this host's trusted root",
+    RemediationMetadata = "remediation.metadata"
+        => "Check that the repository's manifest lists updates for this host's variant",
+    RemediationDisk = "remediation.disk"
+        => "Check the journal for I/O errors from the host's disk",
+    RemediationVerification = "remediation.verification"
+        => "The image didn't match the repository's signed digest; check any target cache or proxy in the way, then try again",
+    RemediationMigration = "remediation.migration"
+        => "Check that the update's migrations are in the repository, and the journal for migrator errors",
+    RemediationPartition = "remediation.partition"
+        => "Check the host's partition table with `signpost status`",
+    RemediationReboot = "remediation.reboot"
+        => "Reboot the host yourself to boot the update",
+    RemediationOther = "remediation.other"
+        => "Check the journal for updog's full error",
+
+    // Hints for the doctor's checks.
+    DoctorConfig = "doctor.config"
+        => "updog's config is generated from settings.updates and settings.network; check those with `apiclient -u /settings`",
+    DoctorRelease = "doctor.release"
+        => "updog reads the running version and variant from the os-release file",
+    DoctorDatastoreVersion = "doctor.datastore-version"
+        => "Check the journal for migrator failures with `journalctl -u migrator`",
+    DoctorTrustedRoot = "doctor.trusted-root"
+        => "The trusted root ships with the OS image, so if it can't be read, the image is damaged",
+    DoctorRootExpired = "doctor.root-expired"
+        => "Ask the repository's owner to sign a new root, or check that the host's clock is right",
+    DoctorRootExpiring = "doctor.root-expiring"
+        => "Ask the repository's owner to sign a new root before then; the host can't update once it expires",
+    DoctorPartitions = "doctor.partitions"
+        => "updog writes updates to the OS disk's inactive partition set, so it can only update a Bottlerocket host",
+    DoctorFreeSpace = "doctor.free-space"
+        => "Free up space on the data partition, or updates may fail to download",
+    DoctorVersionLock = "doctor.version-lock"
+        => "Set settings.updates.version-lock to \"latest\" to take new updates",
+    DoctorPendingUpdate = "doctor.pending-update"
+        => "Reboot to finish the update, or run `signpost cancel-upgrade` to keep the running version",
+    DoctorLeftovers = "doctor.leftovers"
+        => "An update run was interrupted; the next one cleans up after it, and logs what it removed",
+
+    // Notes on what a run found or did, printed to stderr.
+    AutoOff = "note.auto-off" => "Automatic updates are off",
+    NoUpdateRequired = "note.no-update-required" => "No update required",
+    LaterWave = "note.later-wave" => "Update available in later wave",
+    StartingUpdate = "note.starting-update" => "Starting update",
+    UpdatingImmediately = "note.updating-immediately" => "** Updating immediately **",
+    UpdateAlreadyPrepared = "note.update-already-prepared" => "Update already prepared",
+    UpdatePrepared = "note.update-prepared" => "Update prepared",
+    UpdateApplied = "note.update-applied" => "Update applied",
+    UpdateVetoed = "note.update-vetoed" => "Update held back by vetoes",
+    WaitingForRebootWindow = "note.waiting-for-reboot-window"
+        => "Waiting for the reboot window to activate the update",
+}
+
+impl Message {
+    /// Finds the message with the given ID.
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|message| message.id() == id)
+    }
+}
+
+impl Serialize for Message {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.id())
+    }
+}
+
+impl<'de> Deserialize<'de> for Message {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        Self::from_id(&id).ok_or_else(|| de::Error::custom(format!("unknown message ID '{}'", id)))
+    }
+}
+
+/// Reads an optional message ID, taking one this updog doesn't know, such as from a status
+/// written by a later release, as no message rather than failing to read the rest.
+pub(crate) fn deserialize_lenient<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Message>, D::Error> {
+    let id = Option::<String>::deserialize(deserializer)?;
+    Ok(id.and_then(|id| Message::from_id(&id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn ids_are_unique() {
+        let mut seen = HashSet::new();
+        for message in Message::ALL {
+            assert!(seen.insert(message.id()), "{} is used twice", message.id());
+            assert!(!message.text().is_empty());
+        }
+    }
+
+    #[test]
+    fn serialized_as_id() {
+        assert_eq!(
+            serde_json::to_value(Message::RemediationNetwork).unwrap(),
+            "remediation.network"
+        );
+        let message: Message = serde_json::from_str("\"doctor.free-space\"").unwrap();
+        assert_eq!(message, Message::DoctorFreeSpace);
+        assert!(serde_json::from_str::<Message>("\"doctor.nonexistent\"").is_err());
+    }
+}
@@ -0,0 +1,391 @@
+//! Fetches repository files from an OCI registry, so fleets can use the registries, mirrors, and
+//! credentials they already have for containers instead of running a web server for updates.
+//! URLs look like `oci://registry.example.com/bottlerocket/updates/FILE`: each file, metadata or
+//! target, is an artifact in the `bottlerocket/updates` repository, tagged with its file name and
+//! holding the file as its only layer.  Files are still verified by TUF like from any other source.
+
+use log::warn;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{ACCEPT, WWW_AUTHENTICATE};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use url::Url;
+
+/// Manifests we know how to find the layer in.
+const MANIFEST_TYPES: &str =
+    "application/vnd.oci.image.manifest.v1+json, application/vnd.docker.distribution.manifest.v2+json";
+/// The longest tag registries accept.
+const MAX_TAG_LEN: usize = 128;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("OCI URL {} doesn't name a registry, repository, and file", url))]
+    BadReference { url: Url },
+
+    #[snafu(display("File name '{}' can't be used as a tag in an OCI registry", tag))]
+    BadTag { tag: String },
+
+    #[snafu(display("Failed to fetch {}: {}", url, source))]
+    Fetch { url: String, source: reqwest::Error },
+
+    #[snafu(display(
+        "Registry for {} asked for authentication we don't support: {}",
+        url,
+        challenge
+    ))]
+    Challenge { url: String, challenge: String },
+
+    #[snafu(display("Failed to parse response from {}: {}", url, source))]
+    Parse {
+        url: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Artifact {} should have one layer, but has {}", url, layers))]
+    Layers { url: String, layers: usize },
+}
+
+/// Credentials for registries, by registry host.
+#[derive(Debug, Default, Clone)]
+pub struct RegistryAuth(HashMap<String, (String, String)>);
+
+#[derive(Debug, Deserialize)]
+struct DockerConfig {
+    #[serde(default)]
+    auths: HashMap<String, DockerAuth>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DockerAuth {
+    #[serde(default)]
+    auth: Option<String>,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+}
+
+impl RegistryAuth {
+    /// Reads credentials in the format of Docker's config.json, which other container tools and
+    /// credential helpers write too.  Entries that can't be used are skipped, so one bad entry
+    /// doesn't keep updog from reaching other registries.
+    pub fn from_docker_config(data: &[u8]) -> serde_json::Result<Self> {
+        let config: DockerConfig = serde_json::from_slice(data)?;
+        let mut auth = HashMap::new();
+        for (registry, entry) in config.auths {
+            // Entries can be keyed by URL, like "https://index.docker.io/v1/".
+            let host = Url::parse(&registry)
+                .ok()
+                .and_then(|url| url.host_str().map(ToString::to_string))
+                .unwrap_or_else(|| registry.clone());
+            let credentials = match (entry.auth, entry.username, entry.password) {
+                (_, Some(username), Some(password)) => (username, password),
+                (Some(encoded), _, _) => {
+                    if let Some(credentials) = decode_auth(&encoded) {
+                        credentials
+                    } else {
+                        warn!(
+                            "Skipping credentials for {} that aren't base64 'user:password'",
+                            registry
+                        );
+                        continue;
+                    }
+                }
+                _ => continue,
+            };
+            auth.insert(host, credentials);
+        }
+        Ok(Self(auth))
+    }
+}
+
+fn decode_auth(encoded: &str) -> Option<(String, String)> {
+    let decoded = String::from_utf8(base64::decode(encoded).ok()?).ok()?;
+    let mut parts = decoded.splitn(2, ':');
+    Some((parts.next()?.to_string(), parts.next()?.to_string()))
+}
+
+/// Where a file lives in a registry.
+#[derive(Debug, PartialEq)]
+struct Reference {
+    /// The registry's host, and port if given.
+    registry: String,
+    repository: String,
+    tag: String,
+}
+
+impl Reference {
+    fn parse(url: &Url) -> Result<Self, Error> {
+        let host = url.host_str().context(BadReference { url: url.clone() })?;
+        let registry = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        let path = url.path().trim_start_matches('/');
+        let split = path.rfind('/').context(BadReference { url: url.clone() })?;
+        let (repository, tag) = (&path[..split], &path[split + 1..]);
+        ensure!(
+            !repository.is_empty() && !tag.is_empty(),
+            BadReference { url: url.clone() }
+        );
+        ensure!(valid_tag(tag), BadTag { tag });
+        Ok(Self {
+            registry,
+            repository: repository.to_string(),
+            tag: tag.to_string(),
+        })
+    }
+
+    fn url(&self, kind: &str, name: &str) -> String {
+        format!(
+            "https://{}/v2/{}/{}/{}",
+            self.registry, self.repository, kind, name
+        )
+    }
+}
+
+/// Whether `tag` follows the rules registries have for tags.
+fn valid_tag(tag: &str) -> bool {
+    let allowed = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-';
+    tag.len() <= MAX_TAG_LEN
+        && tag.chars().all(allowed)
+        && !tag.starts_with('.')
+        && !tag.starts_with('-')
+}
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    layers: Vec<Descriptor>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Descriptor {
+    digest: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    #[serde(alias = "access_token")]
+    token: String,
+}
+
+#[derive(Debug)]
+pub struct OciSource {
+    client: Client,
+    auth: RegistryAuth,
+    /// Bearer tokens, by registry and repository, kept for the rest of the run.
+    tokens: RefCell<HashMap<(String, String), String>>,
+}
+
+impl OciSource {
+    pub fn new(client: Client, auth: RegistryAuth) -> Self {
+        Self {
+            client,
+            auth,
+            tokens: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn fetch(&self, url: &Url) -> Result<Response, Error> {
+        let reference = Reference::parse(url)?;
+        let manifest_url = reference.url("manifests", &reference.tag);
+        let manifest = self
+            .get(&reference, &manifest_url, |request| {
+                request.header(ACCEPT, MANIFEST_TYPES)
+            })?
+            .text()
+            .context(Fetch { url: &manifest_url })?;
+        let manifest: Manifest =
+            serde_json::from_str(&manifest).context(Parse { url: &manifest_url })?;
+        ensure!(
+            manifest.layers.len() == 1,
+            Layers {
+                url: manifest_url,
+                layers: manifest.layers.len()
+            }
+        );
+        let blob_url = reference.url("blobs", &manifest.layers[0].digest);
+        self.get(&reference, &blob_url, |request| request)
+    }
+
+    /// Sends a GET to the registry, authenticating if the registry asks.
+    fn get<F>(&self, reference: &Reference, url: &str, build: F) -> Result<Response, Error>
+    where
+        F: Fn(RequestBuilder) -> RequestBuilder,
+    {
+        let key = (reference.registry.clone(), reference.repository.clone());
+        let send = |authorize: &dyn Fn(RequestBuilder) -> RequestBuilder| {
+            authorize(build(self.client.get(url)))
+                .send()
+                .context(Fetch { url })
+        };
+
+        let token = self.tokens.borrow().get(&key).cloned();
+        let response = match &token {
+            Some(token) => send(&|request| request.bearer_auth(token))?,
+            None => send(&|request| request)?,
+        };
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return response.error_for_status().context(Fetch { url });
+        }
+
+        let challenge = response
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let credentials = self.auth.0.get(&reference.registry);
+        let response = if challenge.starts_with("Bearer ") {
+            let token = self.token(reference, &challenge, credentials)?;
+            let response = send(&|request| request.bearer_auth(&token))?;
+            self.tokens.borrow_mut().insert(key, token);
+            response
+        } else if let (true, Some((username, password))) =
+            (challenge.starts_with("Basic "), credentials)
+        {
+            send(&|request| request.basic_auth(username, Some(password)))?
+        } else {
+            return Challenge { url, challenge }.fail();
+        };
+        response.error_for_status().context(Fetch { url })
+    }
+
+    /// Gets a token to pull from the repository, from the auth service named in `challenge`.
+    fn token(
+        &self,
+        reference: &Reference,
+        challenge: &str,
+        credentials: Option<&(String, String)>,
+    ) -> Result<String, Error> {
+        let params = challenge_params(&challenge["Bearer ".len()..]);
+        let realm = params.get("realm").context(Challenge {
+            url: reference.url("manifests", &reference.tag),
+            challenge,
+        })?;
+        let scope = params
+            .get("scope")
+            .cloned()
+            .unwrap_or_else(|| format!("repository:{}:pull", reference.repository));
+        let mut query = vec![("scope", scope.as_str())];
+        if let Some(service) = params.get("service") {
+            query.push(("service", service));
+        }
+        let mut request = self.client.get(realm.as_str()).query(&query);
+        if let Some((username, password)) = credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+        let response = request
+            .send()
+            .and_then(Response::error_for_status)
+            .and_then(Response::text)
+            .context(Fetch {
+                url: realm.as_str(),
+            })?;
+        let response: TokenResponse = serde_json::from_str(&response).context(Parse {
+            url: realm.as_str(),
+        })?;
+        Ok(response.token)
+    }
+}
+
+/// Parses the parameters of an authentication challenge, like
+/// `realm="https://auth.example.com/token",service="registry.example.com"`.
+fn challenge_params(params: &str) -> HashMap<String, String> {
+    let mut parsed = HashMap::new();
+    let mut rest = params.trim();
+    while let Some(eq) = rest.find('=') {
+        let name = rest[..eq].trim().to_lowercase();
+        rest = &rest[eq + 1..];
+        let value = if rest.starts_with('"') {
+            let end = rest[1..].find('"').map_or(rest.len(), |end| end + 1);
+            let value = rest[1..end].to_string();
+            rest = rest.get(end + 1..).unwrap_or("");
+            value
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            let value = rest[..end].trim().to_string();
+            rest = &rest[end..];
+            value
+        };
+        parsed.insert(name, value);
+        rest = rest.trim_start_matches(&[',', ' '][..]);
+    }
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn references() {
+        let url =
+            Url::parse("oci://registry.example.com:5000/bottlerocket/updates/1.root.json").unwrap();
+        assert_eq!(
+            Reference::parse(&url).unwrap(),
+            Reference {
+                registry: "registry.example.com:5000".to_string(),
+                repository: "bottlerocket/updates".to_string(),
+                tag: "1.root.json".to_string(),
+            }
+        );
+        assert_eq!(
+            Reference::parse(&url)
+                .unwrap()
+                .url("manifests", "1.root.json"),
+            "https://registry.example.com:5000/v2/bottlerocket/updates/manifests/1.root.json"
+        );
+
+        for bad in &[
+            "oci://registry.example.com/1.root.json",
+            "oci://registry.example.com/updates/",
+            "oci://registry.example.com/updates/v1.0+build.json",
+        ] {
+            assert!(
+                Reference::parse(&Url::parse(bad).unwrap()).is_err(),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn challenges() {
+        let params = challenge_params(
+            r#"realm="https://auth.example.com/token",service="registry.example.com",scope="repository:a/b:pull""#,
+        );
+        assert_eq!(params["realm"], "https://auth.example.com/token");
+        assert_eq!(params["service"], "registry.example.com");
+        assert_eq!(params["scope"], "repository:a/b:pull");
+
+        let params = challenge_params("realm=https://auth.example.com/token, service=example");
+        assert_eq!(params["realm"], "https://auth.example.com/token");
+        assert_eq!(params["service"], "example");
+    }
+
+    #[test]
+    fn docker_config() {
+        let auth = RegistryAuth::from_docker_config(
+            br#"{"auths": {
+                "registry.example.com": {"auth": "dXNlcjpwYXNzOndvcmQ="},
+                "https://index.docker.io/v1/": {"username": "me", "password": "secret"},
+                "helper.example.com": {},
+                "bad.example.com": {"auth": "not base64"}
+            }}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            auth.0["registry.example.com"],
+            ("user".to_string(), "pass:word".to_string())
+        );
+        assert_eq!(
+            auth.0["index.docker.io"],
+            ("me".to_string(), "secret".to_string())
+        );
+        assert_eq!(auth.0.len(), 2);
+    }
+}
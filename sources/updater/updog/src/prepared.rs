@@ -0,0 +1,190 @@
+//! Remembers the update that `prepare` wrote to the inactive partition set, so orchestrators can
+//! stage an update ahead of time and `activate` it later.  Both commands can be run again safely:
+//! `prepare` skips writing an update that's still staged, and `activate` does nothing if the
+//! staged update is already set to boot next.
+
+use chrono::{DateTime, Duration, Utc};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use signpost::State;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Prepared {
+    pub variant: String,
+    pub version: Version,
+    /// The root partition the update was written to, so a record left from before the partition
+    /// sets swapped isn't mistaken for the current one.
+    pub root: PathBuf,
+    pub time: DateTime<Utc>,
+    /// The update isn't activated before this time, even if `activate` is run sooner.  Set by
+    /// `activate --not-before`, so an orchestrator can stage the same time across a fleet and each
+    /// host keeps to it on its own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<DateTime<Utc>>,
+}
+
+impl Prepared {
+    pub(crate) fn new(variant: &str, version: &Version, state: &State) -> Self {
+        Self {
+            variant: variant.to_string(),
+            version: version.clone(),
+            root: state.inactive_set().root.clone(),
+            time: Utc::now(),
+            not_before: None,
+        }
+    }
+
+    /// When the update may be activated: once it's been staged for `min_soak`, so local health
+    /// checks have time to veto it, and not before `not_before`.
+    pub(crate) fn activate_after(&self, min_soak: Duration) -> DateTime<Utc> {
+        let soaked = self.time + min_soak;
+        match self.not_before {
+            Some(not_before) if not_before > soaked => not_before,
+            _ => soaked,
+        }
+    }
+
+    /// How much longer activation has to wait at `now`, if at all.
+    pub(crate) fn wait(&self, now: DateTime<Utc>, min_soak: Duration) -> Option<Duration> {
+        let after = self.activate_after(min_soak);
+        if after > now {
+            Some(after - now)
+        } else {
+            None
+        }
+    }
+
+    /// How much of the soak time is left at `now`, if any.
+    pub(crate) fn soak_remaining(
+        &self,
+        now: DateTime<Utc>,
+        min_soak: Duration,
+    ) -> Option<Duration> {
+        let soaked = self.time + min_soak;
+        if soaked > now {
+            Some(soaked - now)
+        } else {
+            None
+        }
+    }
+
+    /// Reads the record of the prepared update, if there is one.
+    pub(crate) fn load(path: &Path) -> io::Result<Option<Self>> {
+        match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map(Some)
+                .map_err(io::Error::from),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Saves the record, replacing the file all at once like the status file.
+    pub(crate) fn write(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let data = serde_json::to_vec(self).map_err(io::Error::from)?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Removes the record, if there is one, before the inactive partition set is overwritten.
+    pub(crate) fn clear(path: &Path) -> io::Result<()> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the update is still staged on the inactive partition set, and hasn't since been
+    /// cleared, overwritten, or booted.
+    pub(crate) fn is_staged(&self, state: &State) -> bool {
+        self.root == state.inactive_set().root && state.inactive_valid()
+    }
+
+    /// Whether the update has been activated, and is set to boot next.
+    pub(crate) fn is_activated(&self, state: &State) -> bool {
+        self.root == state.inactive_set().root && state.upgrade_pending()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn load_write_clear() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("updog/prepared.json");
+        assert_eq!(Prepared::load(&path).unwrap(), None);
+
+        let prepared = Prepared {
+            variant: "aws-k8s-1.15".to_string(),
+            version: Version::parse("0.2.0").unwrap(),
+            root: PathBuf::from("/dev/nvme0n1p5"),
+            time: Utc::now(),
+            not_before: None,
+        };
+        prepared.write(&path).unwrap();
+        assert_eq!(Prepared::load(&path).unwrap(), Some(prepared));
+
+        Prepared::clear(&path).unwrap();
+        assert_eq!(Prepared::load(&path).unwrap(), None);
+        // Clearing again is fine.
+        Prepared::clear(&path).unwrap();
+    }
+
+    #[test]
+    fn wait() {
+        let now = Utc::now();
+        let mut prepared = Prepared {
+            variant: "aws-k8s-1.15".to_string(),
+            version: Version::parse("0.2.0").unwrap(),
+            root: PathBuf::from("/dev/nvme0n1p5"),
+            time: now,
+            not_before: None,
+        };
+        let none = Duration::zero();
+        assert_eq!(prepared.wait(now, none), None);
+        prepared.not_before = Some(now + Duration::hours(2));
+        assert_eq!(prepared.wait(now, none), Some(Duration::hours(2)));
+        assert_eq!(prepared.wait(now + Duration::hours(3), none), None);
+    }
+
+    #[test]
+    fn soak() {
+        let now = Utc::now();
+        let mut prepared = Prepared {
+            variant: "aws-k8s-1.15".to_string(),
+            version: Version::parse("0.2.0").unwrap(),
+            root: PathBuf::from("/dev/nvme0n1p5"),
+            time: now,
+            not_before: None,
+        };
+        let soak = Duration::hours(4);
+        assert_eq!(prepared.wait(now, soak), Some(soak));
+        assert_eq!(
+            prepared.soak_remaining(now + Duration::hours(1), soak),
+            Some(Duration::hours(3))
+        );
+        assert_eq!(prepared.wait(now + soak, soak), None);
+        assert_eq!(prepared.soak_remaining(now + soak, soak), None);
+
+        // Whichever of the soak and --not-before ends later holds activation back.
+        prepared.not_before = Some(now + Duration::hours(2));
+        assert_eq!(prepared.activate_after(soak), now + soak);
+        prepared.not_before = Some(now + Duration::hours(6));
+        assert_eq!(prepared.activate_after(soak), now + Duration::hours(6));
+        assert_eq!(
+            prepared.soak_remaining(now + Duration::hours(5), soak),
+            None
+        );
+    }
+}
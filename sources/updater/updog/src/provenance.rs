@@ -0,0 +1,120 @@
+//! Checks an update's provenance before it's written, for fleets that only take images with a
+//! known build history.  The manifest names the update's provenance document, a target in the
+//! repository, and its digest.  The document is an in-toto statement, usually with a SLSA
+//! predicate; it has to list each of the update's images as a subject, with the same SHA-256
+//! digest the repository's signed targets metadata has for it.
+//!
+//! Trust in the document comes from the repository: it's a signed target like the images, so it
+//! doesn't carry signatures of its own.  What the predicate says about the build isn't checked
+//! here; it's kept with the update for auditing.
+
+use crate::error::{self, Result};
+use crate::transport::HttpQueryRepo;
+use ring::digest::{digest, SHA256};
+use serde::Deserialize;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::HashMap;
+use std::io::Read;
+use update_metadata::Update;
+
+/// The most we read of a provenance document; they're small JSON files.
+const MAX_SIZE: u64 = 1024 * 1024;
+
+/// The parts of an in-toto statement we check.
+#[derive(Debug, Deserialize)]
+struct Statement {
+    subject: Vec<Subject>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Subject {
+    name: String,
+    digest: HashMap<String, String>,
+}
+
+/// Fetches the provenance document for `update` and checks that it vouches for the update's
+/// images, failing if the update has none.
+pub(crate) fn verify(repository: &HttpQueryRepo<'_>, update: &Update) -> Result<()> {
+    let provenance = update
+        .provenance
+        .as_ref()
+        .context(error::ProvenanceMissing {
+            version: update.version.clone(),
+        })?;
+    let target = provenance.target.as_str();
+    let mut data = Vec::new();
+    repository
+        .read_target(target)
+        .context(error::Metadata)?
+        .context(error::TargetNotFound { target })?
+        .take(MAX_SIZE)
+        .read_to_end(&mut data)
+        .context(error::ProvenanceRead { path: target })?;
+    ensure!(
+        hex(digest(&SHA256, &data).as_ref()) == provenance.sha256.to_ascii_lowercase(),
+        error::ProvenanceMismatch { target }
+    );
+
+    let statement: Statement =
+        serde_json::from_slice(&data).context(error::ProvenanceParse { target })?;
+    let targets = &repository.targets().signed.targets;
+    for image in &[
+        &update.images.root,
+        &update.images.boot,
+        &update.images.hash,
+    ] {
+        let expected = hex(&targets
+            .get(image.as_str())
+            .context(error::TargetNotFound {
+                target: image.as_str(),
+            })?
+            .hashes
+            .sha256);
+        check_subject(&statement, image, &expected)?;
+    }
+    Ok(())
+}
+
+/// Checks that the statement lists `image` with the SHA-256 digest `expected`.
+fn check_subject(statement: &Statement, image: &str, expected: &str) -> Result<()> {
+    let vouched = statement.subject.iter().any(|subject| {
+        subject.name == image
+            && subject
+                .digest
+                .get("sha256")
+                .map_or(false, |sha256| sha256.eq_ignore_ascii_case(expected))
+    });
+    ensure!(vouched, error::ProvenanceSubject { image });
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn subjects() {
+        let root_digest = "ab".repeat(32);
+        let statement: Statement = serde_json::from_value(json!({
+            "_type": "https://in-toto.io/Statement/v0.1",
+            "predicateType": "https://slsa.dev/provenance/v0.2",
+            "subject": [
+                {"name": "root.ext4.lz4", "digest": {"sha256": root_digest}},
+                {"name": "boot.ext4.lz4", "digest": {"sha512": "00"}},
+            ],
+            "predicate": {"builder": {"id": "https://ci.example.com"}},
+        }))
+        .unwrap();
+
+        assert!(check_subject(&statement, "root.ext4.lz4", &root_digest.to_uppercase()).is_ok());
+        assert!(check_subject(&statement, "root.ext4.lz4", &"cd".repeat(32)).is_err());
+        // A subject without a SHA-256 digest doesn't count.
+        assert!(check_subject(&statement, "boot.ext4.lz4", &"00".repeat(32)).is_err());
+        assert!(check_subject(&statement, "root.verity.lz4", &root_digest).is_err());
+    }
+}
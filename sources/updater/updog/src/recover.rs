@@ -0,0 +1,246 @@
+//! Cleans up after a run that crashed or lost power partway, before the next run that changes the
+//! host gets going.  The run lock can't go stale, since it's released with the process, but the
+//! files a run was writing can be left behind: temporary files it meant to rename into place,
+//! migrations it was still downloading, and bundles it was still unpacking.  None of these are
+//! read as they are, so they're removed.  A status or prepared record that can't be read is
+//! quarantined instead, moved aside so it can be looked at, and a prepared record for an update
+//! that's no longer staged is removed so `activate` doesn't trust it.
+//!
+//! Each repair is logged.  Recovery never fails a run; if a repair doesn't work, the run goes on
+//! and whatever trips over the leftover reports its own error.
+
+use crate::config::Paths;
+use crate::prepared::Prepared;
+use crate::status::Status;
+use log::{error, warn};
+use serde::Serialize;
+use signpost::State;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The extension of migrations that are still downloading.
+const PARTIAL_EXTENSION: &str = "partial";
+/// Appended to the name of a record that's moved aside.
+const QUARANTINE_SUFFIX: &str = ".quarantined";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Problem {
+    /// A file written to be renamed over another, that never was.
+    TempFile,
+    /// A migration that was still downloading.
+    PartialMigration,
+    /// A bundle that was still being unpacked or applied.
+    BundleDir,
+    /// A record of a prepared update that's no longer on the inactive partition set.
+    StalePrepared,
+    /// A status or prepared record that can't be read.
+    Unreadable,
+}
+
+impl Problem {
+    fn describe(self) -> &'static str {
+        match self {
+            Problem::TempFile => "an unfinished write",
+            Problem::PartialMigration => "a partly downloaded migration",
+            Problem::BundleDir => "a partly applied bundle",
+            Problem::StalePrepared => "a prepared update that's no longer staged",
+            Problem::Unreadable => "a record that can't be read",
+        }
+    }
+}
+
+/// Something a previous run left behind.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Leftover {
+    pub(crate) path: PathBuf,
+    pub(crate) problem: Problem,
+}
+
+impl Leftover {
+    /// Where the leftover is moved to, if it's kept rather than removed.
+    fn quarantine_path(&self) -> Option<PathBuf> {
+        if self.problem != Problem::Unreadable {
+            return None;
+        }
+        Some(with_suffix(&self.path, QUARANTINE_SUFFIX))
+    }
+
+    fn repair(&self) -> std::io::Result<()> {
+        match (self.problem, self.quarantine_path()) {
+            (_, Some(quarantine)) => fs::rename(&self.path, quarantine),
+            (Problem::BundleDir, _) => fs::remove_dir_all(&self.path),
+            _ => fs::remove_file(&self.path),
+        }
+    }
+}
+
+impl fmt::Display for Leftover {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.path.display(), self.problem.describe())
+    }
+}
+
+/// Where a migration is downloaded before it's moved to `path`.  Migration names have dots in
+/// them, so the extension is added rather than replacing what looks like one.
+pub(crate) fn partial_path(path: &Path) -> PathBuf {
+    with_suffix(path, &format!(".{}", PARTIAL_EXTENSION))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Finds what previous runs left behind.  `state` is the host's partition state, if it could be
+/// read; without it, prepared records aren't checked against the partitions.
+pub(crate) fn scan(paths: &Paths, state: Option<&State>) -> Vec<Leftover> {
+    let mut found = Vec::new();
+    let mut add = |path: PathBuf, problem| found.push(Leftover { path, problem });
+
+    for path in &[
+        &paths.status,
+        &paths.history,
+        &paths.prepared,
+        &paths.approval,
+    ] {
+        let tmp = path.with_extension("tmp");
+        if tmp.is_file() {
+            add(tmp, Problem::TempFile);
+        }
+    }
+
+    for path in entries(&paths.migrations) {
+        if path.is_file() && path.extension() == Some(PARTIAL_EXTENSION.as_ref()) {
+            add(path, Problem::PartialMigration);
+        }
+    }
+
+    // apply-bundle unpacks into a temporary directory that's only removed if the run finishes.
+    for path in entries(&paths.bundles) {
+        let unpacking = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| name.starts_with(".tmp"));
+        if unpacking && path.is_dir() {
+            add(path, Problem::BundleDir);
+        }
+    }
+
+    if paths.status.is_file() && Status::load(&paths.status).is_err() {
+        add(paths.status.clone(), Problem::Unreadable);
+    }
+
+    match Prepared::load(&paths.prepared) {
+        Ok(Some(prepared)) => {
+            if let Some(state) = state {
+                if !prepared.is_staged(state) && !prepared.is_activated(state) {
+                    add(paths.prepared.clone(), Problem::StalePrepared);
+                }
+            }
+        }
+        Ok(None) => {}
+        Err(_) => add(paths.prepared.clone(), Problem::Unreadable),
+    }
+
+    found
+}
+
+/// The paths in `dir`, or none if it can't be read.
+fn entries(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Finds and repairs what previous runs left behind, returning what was repaired.  Only runs
+/// holding the run lock should call this, so nothing it removes is in use.
+pub(crate) fn recover(paths: &Paths) -> Vec<Leftover> {
+    let state = paths.partition_state().ok();
+    let mut repaired = Vec::new();
+    for leftover in scan(paths, state.as_ref()) {
+        match leftover.repair() {
+            Ok(()) => {
+                if let Some(quarantine) = leftover.quarantine_path() {
+                    warn!(
+                        "Moved {} to {} after an interrupted run",
+                        leftover,
+                        quarantine.display()
+                    );
+                } else {
+                    warn!("Removed {} after an interrupted run", leftover);
+                }
+                repaired.push(leftover);
+            }
+            Err(e) => error!("Failed to clean up {}: {}", leftover, e),
+        }
+    }
+    repaired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn paths(dir: &Path) -> Paths {
+        Paths {
+            status: dir.join("status.json"),
+            history: dir.join("history.jsonl"),
+            prepared: dir.join("prepared.json"),
+            approval: dir.join("approval.json"),
+            migrations: dir.join("migrations"),
+            bundles: dir.join("bundles"),
+            ..Paths::default()
+        }
+    }
+
+    #[test]
+    fn nothing_left() {
+        let dir = TempDir::new().unwrap();
+        assert!(scan(&paths(dir.path()), None).is_empty());
+    }
+
+    #[test]
+    fn repair_leftovers() {
+        let dir = TempDir::new().unwrap();
+        let paths = paths(dir.path());
+        fs::write(dir.path().join("history.tmp"), "{}").unwrap();
+        fs::create_dir(&paths.migrations).unwrap();
+        fs::write(paths.migrations.join("migrate_v1.1.0_foo"), "").unwrap();
+        fs::write(
+            partial_path(&paths.migrations.join("migrate_v1.1.0_bar")),
+            "",
+        )
+        .unwrap();
+        fs::create_dir_all(paths.bundles.join(".tmpAbC123/targets")).unwrap();
+        fs::write(&paths.prepared, "{\"variant\":").unwrap();
+
+        let found = scan(&paths, None);
+        let problems: Vec<_> = found.iter().map(|leftover| leftover.problem).collect();
+        assert_eq!(
+            problems,
+            vec![
+                Problem::TempFile,
+                Problem::PartialMigration,
+                Problem::BundleDir,
+                Problem::Unreadable
+            ]
+        );
+
+        let repaired = recover(&paths);
+        assert_eq!(repaired, found);
+        assert!(scan(&paths, None).is_empty());
+        // Finished migrations are kept, and the unreadable record is moved aside.
+        assert!(paths.migrations.join("migrate_v1.1.0_foo").is_file());
+        assert!(!paths.prepared.exists());
+        assert!(dir.path().join("prepared.json.quarantined").is_file());
+    }
+}
@@ -0,0 +1,1126 @@
+//! Finds updates for the host in the update repository: loading the repository's signed metadata
+//! and the manifest, picking the update the host should take, and fetching the migrations it
+//! needs.
+
+use crate::budget::MemoryBudget;
+use crate::cancel::Cancel;
+use crate::config::{Config, Paths};
+use crate::error::{self, Result};
+use crate::image::write_target_to_disk;
+use crate::oci::RegistryAuth;
+use crate::recover;
+use crate::transport::{HttpQueryRepo, HttpQueryTransport};
+use crate::TARGET_ARCH;
+use semver::Version;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::convert::TryFrom;
+use std::fs::{self, File, Permissions};
+use std::io::{BufReader, Read};
+use std::os::unix::fs::PermissionsExt;
+use tough::{Repository, Settings};
+use update_metadata::{Manifest, Update};
+
+// How many times to try fetching this host's manifest shard, which can be cut off like any download.
+const SHARD_FETCH_ATTEMPTS: u32 = 3;
+
+pub(crate) fn load_repository<'a>(
+    transport: &'a HttpQueryTransport,
+    config: &'a Config,
+    paths: &'a Paths,
+) -> Result<HttpQueryRepo<'a>> {
+    fs::create_dir_all(&paths.metadata).context(error::CreateMetadataCache)?;
+    Repository::load(
+        transport,
+        Settings {
+            root: File::open(&paths.trusted_root).context(error::OpenRoot {
+                path: &paths.trusted_root,
+            })?,
+            datastore: &paths.metadata,
+            metadata_base_url: &config.metadata_base_url,
+            target_base_url: &config.targets_base_url,
+            limits: config.memory_budget()?.limits(),
+        },
+    )
+    .context(error::Metadata)
+}
+
+/// Loads the manifest, fetching just this host's updates if the manifest is sharded.  The manifest
+/// is read whole, since it's quicker to find this host's updates in memory, but only they are
+/// parsed; its size was checked against the memory budget first.
+pub(crate) fn load_manifest(
+    repository: &HttpQueryRepo<'_>,
+    variant: &str,
+    budget: MemoryBudget,
+) -> Result<Manifest> {
+    let target = "manifest.json";
+    let size = check_manifest_size(repository, target, budget)?;
+    let mut data = Vec::with_capacity(usize::try_from(size).unwrap_or_default());
+    repository
+        .read_target(target)
+        .context(error::Metadata)?
+        .context(error::TargetNotFound { target })?
+        .read_to_end(&mut data)
+        .context(error::ManifestRead { target })?;
+    let mut manifest = update_metadata::from_slice_for(&data, variant, TARGET_ARCH)
+        .context(error::ManifestLoad)?;
+    drop(data);
+
+    // The updates for each variant this host may take can be kept in that variant's shard.
+    let variants: Vec<String> = manifest
+        .compatible_variants(variant, TARGET_ARCH)
+        .into_iter()
+        .map(String::from)
+        .collect();
+    for variant in variants {
+        let shard = match manifest.shards.get(&variant) {
+            Some(shard) => shard.clone(),
+            None => continue,
+        };
+        check_manifest_size(repository, &shard, budget)?;
+        update_metadata::refresh_part(
+            &mut manifest,
+            &variant,
+            TARGET_ARCH,
+            SHARD_FETCH_ATTEMPTS,
+            |_| -> Result<_> {
+                repository
+                    .read_target(&shard)
+                    .context(error::Metadata)?
+                    .context(error::TargetNotFound { target: &shard })
+                    .map(BufReader::new)
+            },
+        )
+        .context(error::ManifestShard { shard })?;
+    }
+    Ok(manifest)
+}
+
+/// Checks that the signed size of a manifest `target` fits in the memory budget, and returns it.
+fn check_manifest_size(
+    repository: &HttpQueryRepo<'_>,
+    target: &str,
+    budget: MemoryBudget,
+) -> Result<u64> {
+    let size = repository
+        .targets()
+        .signed
+        .targets
+        .get(target)
+        .context(error::TargetNotFound { target })?
+        .length;
+    let limit = budget.max_manifest_size();
+    ensure!(
+        size <= limit,
+        error::ManifestTooLarge {
+            target,
+            size,
+            limit
+        }
+    );
+    Ok(size)
+}
+
+pub(crate) fn applicable_updates<'a>(manifest: &'a Manifest, variant: &str) -> Vec<&'a Update> {
+    manifest.applicable_updates(variant, TARGET_ARCH)
+}
+
+// TODO use config if there is api-sourced configuration that could affect this
+// TODO updog.toml may include settings that cause us to ignore/delay
+// certain/any updates;
+//  Ignore Specific Target Version
+//  Ingore Any Target
+//  ...
+pub(crate) fn update_required<'a>(
+    config: &Config,
+    manifest: &'a Manifest,
+    version: &Version,
+    variant: &str,
+    force_version: Option<Version>,
+) -> Option<&'a Update> {
+    if let Some(forced_version) = force_version {
+        return applicable_updates(manifest, variant)
+            .into_iter()
+            .find(|u| u.version == forced_version);
+    }
+
+    manifest.update_for(variant, TARGET_ARCH, version, config.seed, &config.labels)
+}
+
+fn migration_targets(from: &Version, to: &Version, manifest: &Manifest) -> Result<Vec<String>> {
+    let (path, reached) = manifest.migration_path(from, to);
+    ensure!(
+        reached == to,
+        error::MissingMigration {
+            current: reached.clone(),
+            target: to.clone(),
+        }
+    );
+    let mut targets = Vec::new();
+    for transition in path {
+        // If a transition doesn't require a migration the array will be empty
+        if let Some(migrations) = manifest.migrations.get(transition) {
+            targets.extend_from_slice(migrations);
+        }
+    }
+    Ok(targets)
+}
+
+/// Store required migrations for an update in persistent storage. All intermediate migrations
+/// between the current version and the target version must be retrieved.
+pub(crate) fn retrieve_migrations(
+    repository: &HttpQueryRepo<'_>,
+    transport: &HttpQueryTransport,
+    manifest: &Manifest,
+    update: &Update,
+    version_current: &Version,
+    paths: &Paths,
+    cancel: &Cancel,
+) -> Result<()> {
+    // the migrations required for foo to bar and bar to foo are
+    // the same; we can pretend we're always upgrading from foo to
+    // bar and use the same logic to obtain the migrations
+    let target = std::cmp::max(&update.version, version_current);
+    let start = std::cmp::min(&update.version, version_current);
+
+    let dir = &paths.migrations;
+    if !dir.exists() {
+        fs::create_dir(&dir).context(error::DirCreate { path: &dir })?;
+    }
+
+    // download each migration, making sure they are executable and removing
+    // known extensions from our compression, e.g. .lz4
+    let mut targets = migration_targets(start, target, &manifest)?;
+    targets.sort();
+    for name in &targets {
+        let mut destination = dir.join(&name);
+        if destination.extension() == Some("lz4".as_ref()) {
+            destination.set_extension("");
+        }
+        // A migration that's only partly written would fail when the migrator runs it, so it's
+        // downloaded next to where it goes, and only moved there once it's whole.  If the run
+        // doesn't get that far, the next one cleans up the partial file.
+        let partial = recover::partial_path(&destination);
+        if let Err(e) = write_target_to_disk(repository, None, name, &partial, cancel) {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+        fs::set_permissions(&partial, Permissions::from_mode(0o755))
+            .context(error::SetPermissions { path: &partial })?;
+        fs::rename(&partial, &destination).context(error::MigrationRename { path: destination })?;
+    }
+
+    // Set a query parameter listing the required migrations
+    transport
+        .queries_get_mut()
+        .context(error::TransportBorrow)?
+        .push(("migrations".to_owned(), targets.join(",")));
+
+    Ok(())
+}
+
+/// Builds the transport for the update repository, sending requests through the configured proxy.
+pub(crate) fn build_transport(config: &Config) -> Result<HttpQueryTransport> {
+    let transport = HttpQueryTransport::configured(
+        config.proxy_url()?,
+        config.no_proxy.clone(),
+        config.timeouts,
+    )
+    .context(error::TransportBuild)?;
+    let transport = match &config.registry_auth_file {
+        Some(path) => {
+            let data = fs::read(path).context(error::RegistryAuthRead { path })?;
+            let auth = RegistryAuth::from_docker_config(&data)
+                .context(error::RegistryAuthParse { path })?;
+            transport.with_registry_auth(auth)
+        }
+        None => transport,
+    };
+    Ok(match config.host_identity()? {
+        Some(identity) => transport.with_https_headers(identity.headers()?),
+        None => transport,
+    })
+}
+
+pub(crate) fn set_common_query_params(
+    transport: &HttpQueryTransport,
+    current_version: &Version,
+    config: &Config,
+) -> Result<()> {
+    let mut transport_borrow = transport
+        .queries_get_mut()
+        .context(error::TransportBorrow)?;
+
+    transport_borrow.push((String::from("version"), current_version.to_string()));
+    transport_borrow.push((String::from("seed"), config.seed.to_string()));
+    if let Some(channel) = &config.channel {
+        transport_borrow.push((String::from("channel"), channel.clone()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auto::AutoPolicy;
+    use chrono::{Duration as TestDuration, Utc};
+    use std::collections::BTreeMap;
+    use std::io;
+    use update_metadata::{Clock, Images, MockClock, SeedRange, Targeting, Wave};
+    use updater_config::Timeouts;
+
+    #[test]
+    fn test_manifest_json() {
+        // Loads a general example of a manifest that includes an update with waves,
+        // a set of migrations, and some datastore mappings.
+        // This tests checks that it parses and the following properties are correct:
+        // - the (1.0, 1.1) migrations exist with the migration "migrate_1.1_foo"
+        // - the image:datastore mappings exist
+        // - there is a mapping between 1.11.0 and 1.0
+        let path = "tests/data/example.json";
+        let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        assert!(
+            manifest.updates.len() > 0,
+            "Failed to parse update manifest"
+        );
+
+        assert!(manifest.migrations.len() > 0, "Failed to parse migrations");
+        let from = Version::parse("1.11.0").unwrap();
+        let to = Version::parse("1.12.0").unwrap();
+        assert!(manifest
+            .migrations
+            .contains_key(&(from.clone(), to.clone())));
+        let migration = manifest.migrations.get(&(from, to)).unwrap();
+        assert!(migration[0] == "migrate_1.12.0_foo");
+    }
+
+    #[test]
+    fn refresh_manifest_part() {
+        // The part only has an x86_64 update, so the aarch64 update is left alone.
+        let path = "tests/data/multiple.json";
+        let mut manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let part = fs::read("tests/data/example_2.json").unwrap();
+        let variant = "bottlerocket-aws-eks";
+        let versions = |manifest: &Manifest, arch: &str| -> Vec<String> {
+            manifest
+                .updates
+                .iter()
+                .filter(|u| u.arch == arch)
+                .map(|u| u.version.to_string())
+                .collect()
+        };
+
+        let mut attempts = Vec::new();
+        let refreshed = update_metadata::refresh_part(&mut manifest, variant, "x86_64", 3, |n| {
+            attempts.push(n);
+            if n == 1 {
+                Err(io::Error::new(io::ErrorKind::ConnectionReset, "dropped"))
+            } else {
+                Ok(part.as_slice())
+            }
+        })
+        .unwrap();
+        assert_eq!(refreshed, 1);
+        assert_eq!(attempts, vec![1, 2]);
+        assert_eq!(versions(&manifest, "x86_64"), vec!["1.1.0"]);
+        assert_eq!(versions(&manifest, "aarch64"), vec!["1.16.0"]);
+
+        // A part that never arrives whole leaves the manifest as it was.
+        let result = update_metadata::refresh_part(&mut manifest, variant, "aarch64", 2, |_| {
+            Ok::<_, io::Error>(&part[..10])
+        });
+        assert!(result.is_err());
+        assert_eq!(versions(&manifest, "aarch64"), vec!["1.16.0"]);
+    }
+
+    #[test]
+    fn manifest_for_host() {
+        // Only the host's updates are kept, but they come out just as they would from a full
+        // parse, migrations and all.
+        let path = "tests/data/multiple.json";
+        let full: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let manifest = update_metadata::from_reader_for(
+            BufReader::new(File::open(path).unwrap()),
+            "bottlerocket-aws-eks",
+            "aarch64",
+        )
+        .unwrap();
+        assert_eq!(manifest.updates.len(), 1);
+        assert_eq!(
+            serde_json::to_value(&manifest.updates[0]).unwrap(),
+            serde_json::to_value(&full.updates[3]).unwrap()
+        );
+
+        let path = "tests/data/migrations.json";
+        let full: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let manifest = update_metadata::from_reader_for(
+            BufReader::new(File::open(path).unwrap()),
+            "bottlerocket-aws-k8s",
+            "x86_64",
+        )
+        .unwrap();
+        assert!(manifest.updates.is_empty());
+        assert_eq!(manifest.migrations, full.migrations);
+
+        // Parsing in place finds the same updates, even when their fields have escapes in them.
+        let versions = |manifest: Manifest| -> Vec<String> {
+            manifest
+                .updates
+                .iter()
+                .map(|u| u.version.to_string())
+                .collect()
+        };
+        let data = fs::read("tests/data/multiple.json").unwrap();
+        let manifest =
+            update_metadata::from_slice_for(&data, "bottlerocket-aws-eks", "x86_64").unwrap();
+        assert_eq!(versions(manifest), vec!["1.13.0", "1.25.0", "1.15.0"]);
+        let escaped = String::from_utf8(data)
+            .unwrap()
+            .replace("\"aarch64\"", "\"aarch\\u0036\\u0034\"");
+        let manifest =
+            update_metadata::from_slice_for(escaped.as_bytes(), "bottlerocket-aws-eks", "aarch64")
+                .unwrap();
+        assert_eq!(versions(manifest), vec!["1.16.0"]);
+
+        // Trailing data is still an error.
+        let mut data = fs::read(path).unwrap();
+        data.extend_from_slice(b"{}");
+        assert!(update_metadata::from_reader_for(
+            data.as_slice(),
+            "bottlerocket-aws-eks",
+            "x86_64"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_serde_reader() {
+        // A basic manifest with a single update, no migrations, and two
+        // image:datastore mappings
+        let path = "tests/data/example_2.json";
+        let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        assert!(manifest.updates.len() > 0);
+    }
+
+    #[test]
+    fn test_update_ready() {
+        let clock = MockClock::new(Utc::now());
+        let mut update = Update {
+            variant: String::from("bottlerocket"),
+            arch: String::from("test"),
+            version: Version::parse("1.0.0").unwrap(),
+            max_version: Version::parse("1.1.0").unwrap(),
+            waves: BTreeMap::new(),
+            images: Images {
+                boot: String::from("boot"),
+                root: String::from("root"),
+                hash: String::from("hash"),
+            },
+            paused: None,
+            targeting: None,
+            provenance: None,
+            available: true,
+        };
+
+        let seed = 123;
+        assert!(
+            update.update_ready(seed, &clock),
+            "No waves specified but no update"
+        );
+
+        update
+            .waves
+            .insert(1024, clock.now() + TestDuration::hours(1));
+
+        assert!(update.update_ready(seed, &clock), "0th wave not ready");
+
+        update
+            .waves
+            .insert(100, clock.now() + TestDuration::minutes(30));
+
+        assert!(
+            !update.update_ready(seed, &clock),
+            "1st wave scheduled early"
+        );
+        clock.advance(TestDuration::minutes(31));
+        assert!(
+            update.update_ready(seed, &clock),
+            "1st wave not ready once started"
+        );
+
+        let early_seed = 50;
+        update
+            .waves
+            .insert(49, clock.now() - TestDuration::minutes(30));
+
+        assert!(
+            update.update_ready(early_seed, &clock),
+            "Update wave missed"
+        );
+    }
+
+    #[test]
+    fn test_final_wave() {
+        let clock = MockClock::new(Utc::now());
+        let mut update = Update {
+            variant: String::from("bottlerocket"),
+            arch: String::from("test"),
+            version: Version::parse("1.0.0").unwrap(),
+            max_version: Version::parse("1.1.0").unwrap(),
+            waves: BTreeMap::new(),
+            images: Images {
+                boot: String::from("boot"),
+                root: String::from("root"),
+                hash: String::from("hash"),
+            },
+            paused: None,
+            targeting: None,
+            provenance: None,
+            available: true,
+        };
+        let seed = 1024;
+
+        update.waves.insert(0, clock.now() - TestDuration::hours(3));
+        update
+            .waves
+            .insert(256, clock.now() - TestDuration::hours(2));
+        update
+            .waves
+            .insert(512, clock.now() - TestDuration::hours(1));
+
+        assert!(
+            update.update_ready(seed, &clock),
+            "All waves passed but no update"
+        );
+    }
+
+    #[test]
+    fn test_versions() {
+        // A manifest with a single update whose version exceeds the max version.
+        // update in manifest has
+        // - version: 1.25.0
+        // - max_version: 1.20.0
+        let path = "tests/data/regret.json";
+        let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let config = Config {
+            metadata_base_url: String::from("foo"),
+            targets_base_url: String::from("bar"),
+            seed: 123,
+            version_lock: String::from("latest"),
+            ignore_waves: false,
+            channel: None,
+            https_proxy: None,
+            no_proxy: vec![],
+            labels: BTreeMap::new(),
+            registry_auth_file: None,
+            target_cache_url: None,
+            fleet_size_hint: None,
+            auto: AutoPolicy::Off,
+            reboot_window: None,
+            webhooks: BTreeMap::new(),
+            require_provenance: false,
+            min_soak_minutes: 0,
+            vetoes: BTreeMap::new(),
+            bundle_keys: Vec::new(),
+            timestamp_lifetime_hours: None,
+            memory_limit_mib: None,
+            host_identity: None,
+            approval: None,
+            timeouts: Timeouts::default(),
+        };
+        let version = Version::parse("1.18.0").unwrap();
+        let variant = String::from("bottlerocket-aws-eks");
+
+        assert!(
+            update_required(&config, &manifest, &version, &variant, None).is_none(),
+            "Updog tried to exceed max_version"
+        );
+    }
+
+    #[test]
+    fn older_versions() {
+        // A manifest with two updates, both less than 0.1.3
+        let path = "tests/data/example_3.json";
+        let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let config = Config {
+            metadata_base_url: String::from("foo"),
+            targets_base_url: String::from("bar"),
+            seed: 1487,
+            version_lock: String::from("latest"),
+            ignore_waves: false,
+            channel: None,
+            https_proxy: None,
+            no_proxy: vec![],
+            labels: BTreeMap::new(),
+            registry_auth_file: None,
+            target_cache_url: None,
+            fleet_size_hint: None,
+            auto: AutoPolicy::Off,
+            reboot_window: None,
+            webhooks: BTreeMap::new(),
+            require_provenance: false,
+            min_soak_minutes: 0,
+            vetoes: BTreeMap::new(),
+            bundle_keys: Vec::new(),
+            timestamp_lifetime_hours: None,
+            memory_limit_mib: None,
+            host_identity: None,
+            approval: None,
+            timeouts: Timeouts::default(),
+        };
+
+        let version = Version::parse("0.1.3").unwrap();
+        let variant = String::from("aws-k8s-1.15");
+        let update = update_required(&config, &manifest, &version, &variant, None);
+
+        assert!(update.is_some(), "Updog ignored max version");
+        assert!(
+            update.unwrap().version == Version::parse("0.1.2").unwrap(),
+            "Updog didn't choose the most recent valid version"
+        );
+    }
+
+    #[test]
+    fn test_multiple() {
+        // A manifest with four updates; two valid, one which exceeds the max
+        // version, and one which is for an aarch64 target. This asserts that
+        // upgrading from the version 1.10.0 results in updating to 1.15.0
+        // instead of 1.13.0 (lower), 1.25.0 (too high), or 1.16.0 (wrong arch).
+        let path = "tests/data/multiple.json";
+        let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let config = Config {
+            metadata_base_url: String::from("foo"),
+            targets_base_url: String::from("bar"),
+            seed: 123,
+            version_lock: String::from("latest"),
+            ignore_waves: false,
+            channel: None,
+            https_proxy: None,
+            no_proxy: vec![],
+            labels: BTreeMap::new(),
+            registry_auth_file: None,
+            target_cache_url: None,
+            fleet_size_hint: None,
+            auto: AutoPolicy::Off,
+            reboot_window: None,
+            webhooks: BTreeMap::new(),
+            require_provenance: false,
+            min_soak_minutes: 0,
+            vetoes: BTreeMap::new(),
+            bundle_keys: Vec::new(),
+            timestamp_lifetime_hours: None,
+            memory_limit_mib: None,
+            host_identity: None,
+            approval: None,
+            timeouts: Timeouts::default(),
+        };
+
+        let version = Version::parse("1.10.0").unwrap();
+        let variant = String::from("bottlerocket-aws-eks");
+        let result = update_required(&config, &manifest, &version, &variant, None);
+
+        assert!(result.is_some(), "Updog failed to find an update");
+
+        if let Some(u) = result {
+            assert!(
+                u.version == Version::parse("1.15.0").unwrap(),
+                "Incorrect version: {}, should be 1.15.0",
+                u.version
+            );
+        }
+    }
+
+    #[test]
+    fn variant_compatibility() {
+        // Move 1.15.0 to another variant; without a rule, hosts only take their own variant's.
+        let path = "tests/data/multiple.json";
+        let mut manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        manifest.updates[2].variant = String::from("bottlerocket-aws-eks-2");
+        let versions = |manifest: &Manifest, arch: &str| -> Vec<String> {
+            manifest
+                .applicable_updates("bottlerocket-aws-eks", arch)
+                .iter()
+                .map(|u| u.version.to_string())
+                .collect()
+        };
+        assert_eq!(versions(&manifest, "x86_64"), vec!["1.13.0"]);
+
+        // A rule lets them take the other variant's updates, on their own architecture.
+        let eks = || {
+            vec![
+                String::from("bottlerocket-aws-eks"),
+                String::from("bottlerocket-aws-eks-2"),
+            ]
+        };
+        manifest
+            .set_compatibility(String::from("bottlerocket-aws-eks"), None, Some(eks()))
+            .unwrap();
+        assert_eq!(versions(&manifest, "x86_64"), vec!["1.15.0", "1.13.0"]);
+        assert_eq!(versions(&manifest, "aarch64"), vec!["1.16.0"]);
+
+        // A rule for one architecture holds it back, and doesn't change the others.
+        manifest
+            .set_compatibility(
+                String::from("bottlerocket-aws-eks"),
+                Some(String::from("x86_64")),
+                Some(vec![]),
+            )
+            .unwrap();
+        assert!(versions(&manifest, "x86_64").is_empty());
+        assert_eq!(versions(&manifest, "aarch64"), vec!["1.16.0"]);
+        assert!(manifest
+            .set_compatibility(
+                String::from("bottlerocket-aws-eks"),
+                Some(String::from("x86_64")),
+                None,
+            )
+            .unwrap());
+
+        // Rules for the same hosts, or listing a variant twice, are rejected.
+        let mut repeated = eks();
+        repeated.push(String::from("bottlerocket-aws-eks"));
+        assert!(manifest
+            .set_compatibility(String::from("bottlerocket-aws-eks"), None, Some(repeated))
+            .is_err());
+        manifest
+            .compatibility
+            .push(manifest.compatibility[0].clone());
+        assert!(manifest.validate_compatibility().is_err());
+        manifest.compatibility.pop();
+
+        // Parsing for a host keeps the updates of every variant it may take, since the rules
+        // are serialized first; rules after the updates that would change that are an error.
+        let data = serde_json::to_vec(&manifest).unwrap();
+        let parsed =
+            update_metadata::from_slice_for(&data, "bottlerocket-aws-eks", "x86_64").unwrap();
+        assert_eq!(versions(&parsed, "x86_64"), vec!["1.15.0", "1.13.0"]);
+        assert_eq!(parsed.compatibility, manifest.compatibility);
+        let late = format!(
+            r#"{{"updates": {}, "migrations": {{}}, "compatibility": {}}}"#,
+            serde_json::to_string(&manifest.updates).unwrap(),
+            serde_json::to_string(&manifest.compatibility).unwrap()
+        );
+        assert!(
+            update_metadata::from_slice_for(late.as_bytes(), "bottlerocket-aws-eks", "x86_64")
+                .is_err()
+        );
+        assert!(
+            update_metadata::from_slice_for(late.as_bytes(), "bottlerocket-aws-ecs", "x86_64")
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn paused_rollout() {
+        // With 1.15.0 paused, hosts that haven't taken it get the next best update, and hosts
+        // already running it stay put, unless they ask for it by version.
+        let path = "tests/data/multiple.json";
+        let mut manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let config = Config {
+            metadata_base_url: String::from("foo"),
+            targets_base_url: String::from("bar"),
+            seed: 123,
+            version_lock: String::from("latest"),
+            ignore_waves: false,
+            channel: None,
+            https_proxy: None,
+            no_proxy: vec![],
+            labels: BTreeMap::new(),
+            registry_auth_file: None,
+            target_cache_url: None,
+            fleet_size_hint: None,
+            auto: AutoPolicy::Off,
+            reboot_window: None,
+            webhooks: BTreeMap::new(),
+            require_provenance: false,
+            min_soak_minutes: 0,
+            vetoes: BTreeMap::new(),
+            bundle_keys: Vec::new(),
+            timestamp_lifetime_hours: None,
+            memory_limit_mib: None,
+            host_identity: None,
+            approval: None,
+            timeouts: Timeouts::default(),
+        };
+        let variant = String::from("bottlerocket-aws-eks");
+        let paused = Version::parse("1.15.0").unwrap();
+        assert_eq!(
+            manifest.pause_rollout(
+                variant.clone(),
+                String::from("x86_64"),
+                paused.clone(),
+                Utc::now()
+            ),
+            1
+        );
+
+        let version = Version::parse("1.10.0").unwrap();
+        let result = update_required(&config, &manifest, &version, &variant, None).unwrap();
+        assert_eq!(result.version, Version::parse("1.13.0").unwrap());
+
+        assert!(update_required(&config, &manifest, &paused, &variant, None).is_none());
+
+        let result =
+            update_required(&config, &manifest, &version, &variant, Some(paused.clone())).unwrap();
+        assert_eq!(result.version, paused);
+    }
+
+    #[test]
+    fn deprecated_update() {
+        // With 1.15.0 deprecated, no host is offered it, even by version, but hosts already
+        // running it stay put.  Once it's reinstated, hosts can ask for it again.
+        let path = "tests/data/multiple.json";
+        let mut manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let config = Config {
+            metadata_base_url: String::from("foo"),
+            targets_base_url: String::from("bar"),
+            seed: 123,
+            version_lock: String::from("latest"),
+            ignore_waves: false,
+            channel: None,
+            https_proxy: None,
+            no_proxy: vec![],
+            labels: BTreeMap::new(),
+            registry_auth_file: None,
+            target_cache_url: None,
+            fleet_size_hint: None,
+            auto: AutoPolicy::Off,
+            reboot_window: None,
+            webhooks: BTreeMap::new(),
+            require_provenance: false,
+            min_soak_minutes: 0,
+            vetoes: BTreeMap::new(),
+            bundle_keys: Vec::new(),
+            timestamp_lifetime_hours: None,
+            memory_limit_mib: None,
+            host_identity: None,
+            approval: None,
+            timeouts: Timeouts::default(),
+        };
+        let variant = String::from("bottlerocket-aws-eks");
+        let arch = String::from("x86_64");
+        let deprecated = Version::parse("1.15.0").unwrap();
+        assert_eq!(
+            manifest.set_available(variant.clone(), arch.clone(), deprecated.clone(), false),
+            1
+        );
+
+        let version = Version::parse("1.10.0").unwrap();
+        let result = update_required(&config, &manifest, &version, &variant, None).unwrap();
+        assert_eq!(result.version, Version::parse("1.13.0").unwrap());
+        assert!(update_required(&config, &manifest, &deprecated, &variant, None).is_none());
+        assert!(update_required(
+            &config,
+            &manifest,
+            &version,
+            &variant,
+            Some(deprecated.clone())
+        )
+        .is_none());
+
+        manifest.set_available(variant.clone(), arch, deprecated.clone(), true);
+        let result = update_required(
+            &config,
+            &manifest,
+            &version,
+            &variant,
+            Some(deprecated.clone()),
+        )
+        .unwrap();
+        assert_eq!(result.version, deprecated);
+    }
+
+    #[test]
+    fn targeted_update() {
+        // With 1.15.0 only for GPU hosts in the first half of the fleet, other hosts get the next
+        // best update.
+        let path = "tests/data/multiple.json";
+        let mut manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let mut config: Config = toml::from_str(
+            r#"
+            metadata_base_url = "foo"
+            targets_base_url = "bar"
+            seed = 123
+
+            [labels]
+            gpu = "true"
+            zone = "a"
+        "#,
+        )
+        .unwrap();
+        let variant = String::from("bottlerocket-aws-eks");
+        let mut labels = BTreeMap::new();
+        labels.insert(String::from("gpu"), String::from("true"));
+        let targeting = Targeting {
+            seeds: vec![SeedRange {
+                start: 0,
+                end: 1024,
+            }],
+            labels,
+        };
+        manifest
+            .set_targeting(
+                variant.clone(),
+                String::from("x86_64"),
+                Version::parse("1.15.0").unwrap(),
+                Some(&targeting),
+            )
+            .unwrap();
+
+        let version = Version::parse("1.10.0").unwrap();
+        let selected = |config: &Config| {
+            update_required(config, &manifest, &version, &variant, None)
+                .unwrap()
+                .version
+                .to_string()
+        };
+        assert_eq!(selected(&config), "1.15.0");
+        config.seed = 1024;
+        assert_eq!(selected(&config), "1.13.0");
+        config.seed = 123;
+        config
+            .labels
+            .insert(String::from("gpu"), String::from("false"));
+        assert_eq!(selected(&config), "1.13.0");
+        config.labels.clear();
+        assert_eq!(selected(&config), "1.13.0");
+    }
+
+    #[test]
+    fn force_update_version() {
+        // A manifest with four updates; two valid, one which exceeds the max
+        // version, and one which is for an aarch64 target. This tests forces
+        // a downgrade to 1.13.0, instead of 1.15.0 like it would be in the
+        // above test, test_multiple.
+        let path = "tests/data/multiple.json";
+        let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let config = Config {
+            metadata_base_url: String::from("foo"),
+            targets_base_url: String::from("bar"),
+            seed: 123,
+            version_lock: String::from("latest"),
+            ignore_waves: false,
+            channel: None,
+            https_proxy: None,
+            no_proxy: vec![],
+            labels: BTreeMap::new(),
+            registry_auth_file: None,
+            target_cache_url: None,
+            fleet_size_hint: None,
+            auto: AutoPolicy::Off,
+            reboot_window: None,
+            webhooks: BTreeMap::new(),
+            require_provenance: false,
+            min_soak_minutes: 0,
+            vetoes: BTreeMap::new(),
+            bundle_keys: Vec::new(),
+            timestamp_lifetime_hours: None,
+            memory_limit_mib: None,
+            host_identity: None,
+            approval: None,
+            timeouts: Timeouts::default(),
+        };
+
+        let version = Version::parse("1.10.0").unwrap();
+        let forced = Version::parse("1.13.0").unwrap();
+        let variant = String::from("bottlerocket-aws-eks");
+        let result = update_required(&config, &manifest, &version, &variant, Some(forced));
+
+        assert!(result.is_some(), "Updog failed to find an update");
+
+        if let Some(u) = result {
+            assert!(
+                u.version == Version::parse("1.13.0").unwrap(),
+                "Incorrect version: {}, should be forced to 1.13.0",
+                u.version
+            );
+        }
+    }
+
+    #[test]
+    fn bad_bound() {
+        // This manifest has an invalid key for one of the update's waves
+        assert!(
+            serde_json::from_str::<Manifest>(include_str!("../tests/data/bad-bound.json")).is_err()
+        );
+    }
+
+    #[test]
+    fn duplicate_bound() {
+        // This manifest has two waves with a bound id of 0
+        assert!(serde_json::from_str::<Manifest>(include_str!(
+            "../tests/data/duplicate-bound.json"
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_migrations() {
+        // A manifest with four migration tuples starting at 1.0 and ending at 1.3.
+        // There is a shortcut from 1.1 to 1.3, skipping 1.2
+        let path = "tests/data/migrations.json";
+        let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let from = Version::parse("1.0.0").unwrap();
+        let to = Version::parse("1.5.0").unwrap();
+        let targets = migration_targets(&from, &to, &manifest).unwrap();
+
+        assert!(targets.len() == 3);
+        let mut i = targets.iter();
+        assert!(i.next().unwrap() == "migration_1.1.0_a");
+        assert!(i.next().unwrap() == "migration_1.1.0_b");
+        assert!(i.next().unwrap() == "migration_1.5.0_shortcut");
+    }
+
+    #[test]
+    fn serialize_metadata() {
+        // A basic manifest with a single update
+        let path = "tests/data/example_2.json";
+        let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        assert!(serde_json::to_string_pretty(&manifest)
+            .context(error::UpdateSerialize)
+            .is_ok());
+    }
+
+    #[test]
+    fn early_wave() {
+        let clock = MockClock::new(Utc::now());
+        let mut u = Update {
+            variant: String::from("bottlerocket"),
+            arch: String::from("test"),
+            version: Version::parse("1.0.0").unwrap(),
+            max_version: Version::parse("1.1.0").unwrap(),
+            waves: BTreeMap::new(),
+            images: Images {
+                boot: String::from("boot"),
+                root: String::from("root"),
+                hash: String::from("hash"),
+            },
+            paused: None,
+            targeting: None,
+            provenance: None,
+            available: true,
+        };
+
+        // | ---- (100, "now") ---
+        let first_bound = clock.now();
+        u.waves.insert(100, first_bound);
+        assert!(
+            u.update_wave(1).unwrap() == Wave::Initial { end: first_bound },
+            "Expected to be 0th wave"
+        );
+        assert!(u.jitter(1, &clock).is_none(), "Expected immediate update");
+        assert!(
+            u.update_wave(101).unwrap() == Wave::Last { start: first_bound },
+            "Expected to be final wave"
+        );
+        assert!(u.jitter(101, &clock).is_none(), "Expected immediate update");
+
+        // | ---- (100, "now") ---- (200, "+1hr") ---
+        let second_bound = clock.now() + TestDuration::hours(1);
+        u.waves.insert(200, second_bound);
+        assert!(
+            u.update_wave(1).unwrap() == Wave::Initial { end: first_bound },
+            "Expected to be 0th wave"
+        );
+        assert!(u.jitter(1, &clock).is_none(), "Expected immediate update");
+
+        assert!(
+            u.update_wave(100).unwrap() == Wave::Initial { end: first_bound },
+            "Expected to be 0th wave (just!)"
+        );
+        assert!(u.jitter(100, &clock).is_none(), "Expected immediate update");
+
+        assert!(
+            u.update_wave(150).unwrap()
+                == Wave::General {
+                    start: first_bound,
+                    end: second_bound
+                },
+            "Expected to be some bounded wave"
+        );
+        assert!(
+            u.jitter(150, &clock).is_some(),
+            "Expected to have to wait for update"
+        );
+
+        assert!(
+            u.update_wave(201).unwrap()
+                == Wave::Last {
+                    start: second_bound
+                },
+            "Expected to be final wave"
+        );
+        assert!(u.jitter(201, &clock).is_none(), "Expected immediate update");
+    }
+
+    #[test]
+    /// Make sure that update_ready() doesn't return true unless the client's
+    /// wave is also ready.
+    fn check_update_waves() {
+        let clock = MockClock::new(Utc::now());
+        let mut manifest = Manifest::default();
+        let mut update = Update {
+            variant: String::from("aws-k8s-1.15"),
+            arch: String::from(TARGET_ARCH),
+            version: Version::parse("1.1.1").unwrap(),
+            max_version: Version::parse("1.1.1").unwrap(),
+            waves: BTreeMap::new(),
+            images: Images {
+                boot: String::from("boot"),
+                root: String::from("boot"),
+                hash: String::from("boot"),
+            },
+            paused: None,
+            targeting: None,
+            provenance: None,
+            available: true,
+        };
+
+        let current_version = Version::parse("1.0.0").unwrap();
+        let variant = String::from("aws-k8s-1.15");
+        let config = Config {
+            metadata_base_url: String::from("foo"),
+            targets_base_url: String::from("bar"),
+            seed: 512,
+            version_lock: String::from("latest"),
+            ignore_waves: false,
+            channel: None,
+            https_proxy: None,
+            no_proxy: vec![],
+            labels: BTreeMap::new(),
+            registry_auth_file: None,
+            target_cache_url: None,
+            fleet_size_hint: None,
+            auto: AutoPolicy::Off,
+            reboot_window: None,
+            webhooks: BTreeMap::new(),
+            require_provenance: false,
+            min_soak_minutes: 0,
+            vetoes: BTreeMap::new(),
+            bundle_keys: Vec::new(),
+            timestamp_lifetime_hours: None,
+            memory_limit_mib: None,
+            host_identity: None,
+            approval: None,
+            timeouts: Timeouts::default(),
+        };
+
+        // Two waves; the 0th wave, and the final wave which starts in one hour
+        update
+            .waves
+            .insert(1024, clock.now() + TestDuration::hours(1));
+        manifest.updates.push(update);
+
+        let potential_update =
+            update_required(&config, &manifest, &current_version, &variant, None).unwrap();
+
+        assert!(
+            potential_update.update_ready(512, &clock),
+            "0th wave doesn't appear ready"
+        );
+        assert!(
+            !potential_update.update_ready(2000, &clock),
+            "Later wave incorrectly sees update"
+        );
+    }
+}
@@ -0,0 +1,443 @@
+//! What a run found or did, for whoever asked for it.  The library hands this back instead of
+//! printing it; the command line prints it as text, or as JSON with `--json`.
+
+use crate::config::{load_config, Paths};
+use crate::doctor::{self, Check};
+use crate::error::{self, Result};
+use crate::messages::Message;
+use crate::prepared::Prepared;
+use crate::status::{CheckResult, Outcome, Status};
+use crate::verify::Verification;
+use crate::veto;
+use chrono::{DateTime, SecondsFormat, Utc};
+use semver::Version;
+use serde::{Serialize, Serializer};
+use snafu::ResultExt;
+use std::fmt;
+use update_metadata::Update;
+
+/// What a successful run found or did.
+#[derive(Debug)]
+pub struct Output {
+    /// What to save in the status file, for runs that check for or act on an update.
+    pub outcome: Option<Outcome>,
+    /// What to show whoever asked for the run.
+    pub report: Report,
+}
+
+impl Output {
+    pub(crate) fn new(
+        result: CheckResult,
+        update_version: Option<&Version>,
+        report: Report,
+    ) -> Self {
+        Self {
+            outcome: Some(Outcome::new(result, update_version)),
+            report,
+        }
+    }
+
+    /// Output for runs that don't check for or act on an update, like showing the history.
+    pub(crate) fn report(report: Report) -> Self {
+        Self {
+            outcome: None,
+            report,
+        }
+    }
+}
+
+/// What to show for a run.  As JSON, each report is what it's about, like the update or the list
+/// of checks; notes are only shown as text.
+#[derive(Debug)]
+pub enum Report {
+    /// Nothing to show, like after `update-apply`.
+    Nothing,
+    /// Why the run didn't go further, like there being no update yet, with any detail, like the
+    /// time it's waiting for.
+    Note {
+        message: Message,
+        detail: Option<String>,
+    },
+    /// The update `check-update` found, and when to fetch it.
+    Check(CheckOutput),
+    /// Every update that applies to the host, whatever its wave, from `check-update` with `all`.
+    Updates(Vec<Update>),
+    /// When the host's wave reaches the update it found.
+    Scheduled(DateTime<Utc>),
+    /// The update that was written, prepared, or applied.
+    Update {
+        message: Message,
+        update: Update,
+    },
+    /// The prepared update, and what `prepare` or `activate` did with it.
+    Prepared {
+        message: String,
+        prepared: Prepared,
+    },
+    /// The results of recent runs, oldest first.
+    History(Vec<Status>),
+    Status(StatusReport),
+    /// What `doctor` found.
+    Doctor(Vec<Check>),
+    /// What `verify-current` found.
+    Verification(Verification),
+}
+
+impl Report {
+    pub(crate) fn note(message: Message) -> Self {
+        Report::Note {
+            message,
+            detail: None,
+        }
+    }
+
+    /// Fails if the report is of checks that found problems: `doctor` checks that failed, or
+    /// images that don't match in `verify-current`.  Those runs succeed with what they found, so
+    /// it can be shown; the command line fails with this once it's shown it.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `Error::DoctorFailed` or `Error::RunningDrift`.
+    pub fn ensure_passed(&self) -> Result<()> {
+        match self {
+            Report::Doctor(checks) => doctor::ensure_passed(checks),
+            Report::Verification(verification) => verification.ensure_matched(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Serialize for Report {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            Report::Nothing | Report::Note { .. } => serializer.serialize_none(),
+            Report::Check(check) => check.serialize(serializer),
+            Report::Updates(updates) => updates.serialize(serializer),
+            Report::Scheduled(time) => time.serialize(serializer),
+            Report::Update { update, .. } => update.serialize(serializer),
+            Report::Prepared { prepared, .. } => prepared.serialize(serializer),
+            Report::History(history) => history.serialize(serializer),
+            Report::Status(status) => status.serialize(serializer),
+            Report::Doctor(checks) => checks.serialize(serializer),
+            Report::Verification(verification) => verification.serialize(serializer),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Report::Nothing => Ok(()),
+            Report::Note {
+                message,
+                detail: Some(detail),
+            } => write!(f, "{}: {}", message.text(), detail),
+            Report::Note {
+                message,
+                detail: None,
+            } => f.write_str(message.text()),
+            Report::Check(check) => f.write_str(&full_version(&check.update)),
+            Report::Updates(updates) => {
+                let lines: Vec<String> = updates.iter().map(full_version).collect();
+                f.write_str(&lines.join("\n"))
+            }
+            Report::Scheduled(time) => write!(f, "Update available at {}", time),
+            Report::Update { message, update } => {
+                write!(f, "{}: {}", message.text(), full_version(update))
+            }
+            Report::Prepared { message, prepared } => {
+                write!(f, "{}: {} {}", message, prepared.variant, prepared.version)
+            }
+            Report::History(history) => write_lines(f, history),
+            Report::Status(status) => status.fmt(f),
+            Report::Doctor(checks) => write_lines(f, checks),
+            Report::Verification(verification) => verification.fmt(f),
+        }
+    }
+}
+
+/// Writes each of `items` on a line of its own.
+fn write_lines<T: fmt::Display>(f: &mut fmt::Formatter<'_>, items: &[T]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            f.write_str("\n")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+fn full_version(update: &Update) -> String {
+    format!("{} {}", update.variant, update.version)
+}
+
+/// What `updog status` shows: the status saved by the last run, and the prepared update, if any.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct StatusReport {
+    pub last_run: Option<Status>,
+    pub prepared: Option<PreparedStatus>,
+    /// The vetoes holding back activating and rebooting, if any.
+    pub vetoes: Option<String>,
+}
+
+/// The prepared update, and where it stands.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PreparedStatus {
+    #[serde(flatten)]
+    pub update: Prepared,
+    /// Whether the update is still on the inactive partition set, ready to activate.
+    pub staged: bool,
+    pub activated: bool,
+    pub activate_after: DateTime<Utc>,
+    /// Seconds left of the configured soak time, or zero once it's over.
+    pub soak_remaining: i64,
+}
+
+impl StatusReport {
+    pub(crate) fn load(paths: &Paths, now: DateTime<Utc>) -> Result<Self> {
+        let last_run = Status::load(&paths.status).context(error::StatusRead {
+            path: &paths.status,
+        })?;
+        let config = load_config(paths)?;
+        let prepared = match Prepared::load(&paths.prepared).context(error::PreparedRead {
+            path: &paths.prepared,
+        })? {
+            Some(update) => {
+                let state = paths.partition_state()?;
+                let min_soak = config.min_soak();
+                Some(PreparedStatus {
+                    staged: update.is_staged(&state),
+                    activated: update.is_activated(&state),
+                    activate_after: update.activate_after(min_soak),
+                    soak_remaining: update
+                        .soak_remaining(now, min_soak)
+                        .map_or(0, |left| left.num_seconds()),
+                    update,
+                })
+            }
+            None => None,
+        };
+        Ok(Self {
+            last_run,
+            prepared,
+            vetoes: veto::active(&config.vetoes, now),
+        })
+    }
+}
+
+impl fmt::Display for StatusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.last_run {
+            Some(status) => write!(f, "Last run: {}", status)?,
+            None => write!(f, "Last run: none recorded")?,
+        }
+        if let Some(vetoes) = &self.vetoes {
+            write!(f, "\nHeld back by vetoes: {}", vetoes)?;
+        }
+        let prepared = match &self.prepared {
+            Some(prepared) => prepared,
+            None => return Ok(()),
+        };
+        let update = &prepared.update;
+        let state = if prepared.activated {
+            "activated"
+        } else if prepared.staged {
+            "prepared"
+        } else {
+            "no longer staged"
+        };
+        write!(
+            f,
+            "\nUpdate {}: {} {}, written at {}",
+            state,
+            update.variant,
+            update.version,
+            update.time.to_rfc3339_opts(SecondsFormat::Secs, true)
+        )?;
+        if prepared.staged && !prepared.activated {
+            write!(
+                f,
+                "\nCan be activated at {}",
+                prepared
+                    .activate_after
+                    .to_rfc3339_opts(SecondsFormat::Secs, true)
+            )?;
+            if prepared.soak_remaining > 0 {
+                let minutes = (prepared.soak_remaining + 59) / 60;
+                write!(f, " ({} minutes of soak time left)", minutes)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What check-update prints: the update, and when this host should fetch it.
+#[derive(Debug, Serialize)]
+pub struct CheckOutput {
+    #[serde(flatten)]
+    pub update: Update,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fetch_window: Option<FetchWindow>,
+}
+
+/// When to fetch an update, so orchestrators can spread downloads over the host's wave instead of
+/// having every host in it fetch as soon as it starts.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FetchWindow {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// About how many hosts are expected to fetch in the same window, if we know the fleet size.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_hosts: Option<u64>,
+}
+
+impl FetchWindow {
+    pub(crate) fn new(
+        update: &Update,
+        seed: u32,
+        fleet_size: Option<u64>,
+        now: DateTime<Utc>,
+    ) -> Option<Self> {
+        let (start, end) = update.fetch_window(seed, now)?;
+        Some(Self {
+            start,
+            end,
+            // Each window belongs to one seed, and hosts pick their seeds evenly.
+            expected_hosts: fleet_size.map(|hosts| {
+                let seeds = u64::from(update_metadata::MAX_SEED);
+                (hosts + seeds - 1) / seeds
+            }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration as TestDuration;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use update_metadata::Images;
+
+    #[test]
+    fn fetch_window() {
+        let mut update = Update {
+            variant: String::from("bottlerocket"),
+            arch: String::from("test"),
+            version: Version::parse("1.0.0").unwrap(),
+            max_version: Version::parse("1.1.0").unwrap(),
+            waves: BTreeMap::new(),
+            images: Images {
+                boot: String::from("boot"),
+                root: String::from("root"),
+                hash: String::from("hash"),
+            },
+            paused: None,
+            targeting: None,
+            provenance: None,
+            available: true,
+        };
+        let now = Utc::now();
+        assert_eq!(FetchWindow::new(&update, 100, None, now), None);
+
+        // Seeds 0-99 get an hour from now, seeds 100-1099 the hour after that, and the rest start
+        // after two hours and are given another hour.
+        update.waves.insert(99, now + TestDuration::hours(1));
+        update.waves.insert(1099, now + TestDuration::hours(2));
+        let window = |seed| update.fetch_window(seed, now).unwrap();
+        assert_eq!(window(0), (now, now + TestDuration::seconds(36)));
+        assert_eq!(
+            window(99),
+            (
+                now + TestDuration::seconds(3564),
+                now + TestDuration::seconds(3600)
+            )
+        );
+        assert_eq!(
+            window(100),
+            (
+                now + TestDuration::hours(1),
+                now + TestDuration::hours(1) + TestDuration::seconds(3)
+            )
+        );
+        let last = window(update_metadata::MAX_SEED - 1);
+        assert!(last.0 > now + TestDuration::hours(2) && last.1 <= now + TestDuration::hours(3));
+
+        // A window that's passed means fetching now.
+        assert_eq!(
+            update.fetch_window(100, now + TestDuration::minutes(61)),
+            None
+        );
+
+        let window = FetchWindow::new(&update, 100, Some(10_000), now).unwrap();
+        assert_eq!(window.expected_hosts, Some(5));
+    }
+
+    #[test]
+    fn status_report() {
+        let time = DateTime::parse_from_rfc3339("2020-03-26T20:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let version = Version::parse("0.1.4").unwrap();
+        let report = StatusReport {
+            last_run: None,
+            prepared: Some(PreparedStatus {
+                update: Prepared {
+                    variant: "aws-k8s-1.15".to_string(),
+                    version,
+                    root: PathBuf::from("/dev/nvme0n1p5"),
+                    time,
+                    not_before: None,
+                },
+                staged: true,
+                activated: false,
+                activate_after: time + TestDuration::hours(4),
+                soak_remaining: 8580,
+            }),
+            vetoes: None,
+        };
+        assert_eq!(
+            report.to_string(),
+            "Last run: none recorded\n\
+             Update prepared: aws-k8s-1.15 0.1.4, written at 2020-03-26T20:00:00Z\n\
+             Can be activated at 2020-03-27T00:00:00Z (143 minutes of soak time left)"
+        );
+        let value = serde_json::to_value(&report).unwrap();
+        assert_eq!(value["prepared"]["version"], "0.1.4");
+        assert_eq!(value["prepared"]["soak-remaining"], 8580);
+    }
+
+    #[test]
+    fn update_report() {
+        let update = Update {
+            variant: String::from("aws-k8s-1.15"),
+            arch: String::from("x86_64"),
+            version: Version::parse("0.3.3").unwrap(),
+            max_version: Version::parse("0.3.3").unwrap(),
+            waves: BTreeMap::new(),
+            images: Images {
+                boot: String::from("boot"),
+                root: String::from("root"),
+                hash: String::from("hash"),
+            },
+            paused: None,
+            targeting: None,
+            provenance: None,
+            available: true,
+        };
+        let report = Report::Update {
+            message: Message::UpdateApplied,
+            update,
+        };
+        assert_eq!(report.to_string(), "Update applied: aws-k8s-1.15 0.3.3");
+        let value = serde_json::to_value(&report).unwrap();
+        assert_eq!(value["version"], "0.3.3");
+        report.ensure_passed().unwrap();
+
+        let report = Report::note(Message::NoUpdateRequired);
+        assert_eq!(report.to_string(), "No update required");
+        assert!(serde_json::to_value(&report).unwrap().is_null());
+    }
+}
@@ -0,0 +1,449 @@
+//! Records what updog found or did the last time it ran, so the update reporter can tell the
+//! operator how updates are going on the host without running updog itself.  Failures are only
+//! saved as a broad class, never as the full error, since errors can include URLs and paths; the
+//! class comes with a short hint at what to look at, so a fleet's failures can be counted by class
+//! and acted on without reading each host's journal.  The migrator saves its failures with the
+//! same classes.
+//! Each status is also added to updog's history; see the `history` module.
+
+use crate::error::Error;
+use crate::messages::{self, Message};
+use chrono::{DateTime, SecondsFormat, Utc};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The result of an updog run, as saved in the status file.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CheckResult {
+    NoUpdate,
+    /// An update is available to this host now.
+    UpdateAvailable,
+    /// An update exists, but this host's wave hasn't started yet.
+    UpdateScheduled,
+    UpdateWritten,
+    UpdateApplied,
+    /// An update was written and checked by `prepare`, and is waiting to be activated.
+    UpdatePrepared,
+    /// The prepared update is waiting for the time given to `activate --not-before`.
+    ActivationScheduled,
+    /// The prepared update was set to boot next by `activate`.
+    UpdateActivated,
+    /// A veto registered on the host held back activating the update or rebooting into it.
+    UpdateVetoed,
+    /// The run was cancelled before it finished, leaving the host as it would have been had the
+    /// run not started, apart from anything it no longer needed.
+    Cancelled,
+    Failed,
+}
+
+/// The broad reason an updog run failed.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailureClass {
+    /// The config, os-release, or trusted root couldn't be read.
+    Config,
+    /// The update repository couldn't be reached.
+    Network,
+    /// The repository's metadata didn't verify against the trusted root, or had expired.
+    Trust,
+    /// The repository's metadata or manifest was missing or invalid.
+    Metadata,
+    /// An update image couldn't be written to, or read back from, the disk.
+    // Statuses saved before the disk and verification classes were split out call this "image".
+    #[serde(alias = "image")]
+    Disk,
+    /// A downloaded or written image didn't match the digest in the repository's metadata.
+    Verification,
+    /// The migrations for an update couldn't be found, saved, or run.
+    Migration,
+    /// The partition table couldn't be read or changed.
+    Partition,
+    /// The host couldn't be rebooted.
+    Reboot,
+    Other,
+}
+
+impl FailureClass {
+    /// A short hint at what to check for this class of failure.
+    pub fn remediation(self) -> Message {
+        match self {
+            Self::Config => Message::RemediationConfig,
+            Self::Network => Message::RemediationNetwork,
+            Self::Trust => Message::RemediationTrust,
+            Self::Metadata => Message::RemediationMetadata,
+            Self::Disk => Message::RemediationDisk,
+            Self::Verification => Message::RemediationVerification,
+            Self::Migration => Message::RemediationMigration,
+            Self::Partition => Message::RemediationPartition,
+            Self::Reboot => Message::RemediationReboot,
+            Self::Other => Message::RemediationOther,
+        }
+    }
+
+    /// Classifies an I/O error from reading a download, which tough fails when the contents don't
+    /// match the metadata and the transport fails when the connection drops.
+    fn from_download(err: &io::Error, otherwise: Self) -> Self {
+        let inner = match err.get_ref() {
+            Some(inner) => inner,
+            None => return otherwise,
+        };
+        if let Some(err) = inner.downcast_ref::<tough::error::Error>() {
+            match err {
+                tough::error::Error::HashMismatch { .. }
+                | tough::error::Error::MaxSizeExceeded { .. } => Self::Verification,
+                tough::error::Error::Transport { .. } => Self::Network,
+                _ => otherwise,
+            }
+        } else if inner.is::<reqwest::Error>() {
+            Self::Network
+        } else {
+            otherwise
+        }
+    }
+}
+
+impl From<&Error> for FailureClass {
+    fn from(err: &Error) -> Self {
+        match err {
+            Error::ConfigParse { .. }
+            | Error::ConfigVersionLock { .. }
+            | Error::ConfigProxy { .. }
+            | Error::ConfigTargetCache { .. }
+            | Error::ConfigRebootWindow { .. }
+            | Error::BundleMissing { .. }
+            | Error::ConfigBundleKey { .. }
+            | Error::ConfigMemoryLimit { .. }
+            | Error::ConfigHostIdentity { .. }
+            | Error::HostIdentityRead { .. }
+            | Error::MemoryLimit { .. }
+            | Error::RebootWindowMissing { .. }
+            | Error::ConfigRead { .. }
+            | Error::SharedConfig { .. }
+            | Error::OpenRoot { .. }
+            | Error::RegistryAuthParse { .. }
+            | Error::RegistryAuthRead { .. }
+            | Error::ReleaseVersion { .. } => Self::Config,
+
+            Error::Metadata {
+                source: tough::error::Error::Transport { .. },
+                ..
+            }
+            | Error::TargetCacheFetch { .. }
+            | Error::HostIdentity { .. }
+            | Error::TransportBuild { .. } => Self::Network,
+
+            Error::Metadata {
+                source:
+                    tough::error::Error::VerifyMetadata { .. }
+                    | tough::error::Error::VerifyTrustedMetadata { .. }
+                    | tough::error::Error::ExpiredMetadata { .. }
+                    | tough::error::Error::OlderMetadata { .. }
+                    | tough::error::Error::VersionMismatch { .. }
+                    | tough::error::Error::HashMismatch { .. }
+                    | tough::error::Error::SystemTimeSteppedBackward { .. },
+                ..
+            }
+            | Error::ProvenanceMissing { .. }
+            | Error::ProvenanceMismatch { .. }
+            | Error::ProvenanceParse { .. }
+            | Error::ProvenanceSubject { .. }
+            | Error::BundleUnsigned { .. }
+            | Error::BundleSignature { .. } => Self::Trust,
+
+            Error::Metadata { .. }
+            | Error::ManifestParse { .. }
+            | Error::ManifestLoad { .. }
+            | Error::ManifestRead { .. }
+            | Error::ManifestTooLarge { .. }
+            | Error::ManifestShard { .. }
+            | Error::TargetNotFound { .. }
+            | Error::MissingVersion { .. }
+            | Error::BundleEntry { .. }
+            | Error::BundleInventoryParse { .. }
+            | Error::BundleNotApplicable { .. }
+            | Error::BundleUpdateMissing { .. } => Self::Metadata,
+
+            Error::WriteUpdate { source, .. } => Self::from_download(source, Self::Disk),
+            Error::Lz4Decode { source, .. } => Self::from_download(source, Self::Verification),
+            Error::VerifyTarget { source, .. } => Self::from_download(source, Self::Network),
+
+            Error::CreateMetadataCache { .. }
+            | Error::BundleDir { .. }
+            | Error::BundleRead { .. }
+            | Error::LockOpen { .. }
+            | Error::Lock { .. }
+            | Error::OpenPartition { .. }
+            | Error::PreparedWrite { .. }
+            | Error::VerifyRead { .. } => Self::Disk,
+
+            Error::VerifyMismatch { .. }
+            | Error::RunningDrift { .. }
+            | Error::BundleFileMismatch { .. } => Self::Verification,
+
+            Error::DirCreate { .. }
+            | Error::MissingMigration { .. }
+            | Error::MigrationCopyFailed { .. }
+            | Error::MigrationNotLocal { .. }
+            | Error::MigrationNotPresent { .. }
+            | Error::MigrationRename { .. }
+            | Error::SetPermissions { .. } => Self::Migration,
+
+            Error::PartitionTableRead { .. }
+            | Error::PartitionTableWrite { .. }
+            | Error::InactivePartitionUpgrade { .. }
+            | Error::UnknownPartition { .. } => Self::Partition,
+
+            Error::RebootFailure { .. } | Error::Signal { .. } => Self::Reboot,
+
+            _ => Self::Other,
+        }
+    }
+}
+
+/// What a successful updog run found or did, along with the update it concerns, if any.
+#[derive(Debug)]
+pub struct Outcome {
+    pub result: CheckResult,
+    pub update_version: Option<Version>,
+}
+
+impl Outcome {
+    pub fn new(result: CheckResult, update_version: Option<&Version>) -> Self {
+        Self {
+            result,
+            update_version: update_version.cloned(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Status {
+    pub time: DateTime<Utc>,
+    pub command: String,
+    pub result: CheckResult,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub update_version: Option<Version>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<FailureClass>,
+    /// The failure class's hint, saved with it so readers of the status don't need to know them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remediation: Option<String>,
+    /// The hint's ID, for tooling that shouldn't depend on its wording.
+    #[serde(
+        default,
+        deserialize_with = "messages::deserialize_lenient",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub remediation_id: Option<Message>,
+}
+
+impl Status {
+    /// Builds the status for a run of `command`.  Not finding an update ready for this host is an
+    /// error for some commands, but for the status it's just a result.
+    pub fn new(command: &str, run: Result<&Outcome, &Error>) -> Self {
+        let (result, update_version, failure) = match run {
+            Ok(outcome) => (outcome.result, outcome.update_version.clone(), None),
+            Err(Error::UpdateNotAvailable { .. }) => (CheckResult::NoUpdate, None, None),
+            Err(Error::UpdateNotReady { version, .. }) => {
+                (CheckResult::UpdateScheduled, Some(version.clone()), None)
+            }
+            Err(Error::UpdateVetoed { version, .. }) => {
+                (CheckResult::UpdateVetoed, version.clone(), None)
+            }
+            Err(Error::Cancelled { .. }) => (CheckResult::Cancelled, None, None),
+            Err(e) => (CheckResult::Failed, None, Some(FailureClass::from(e))),
+        };
+        Self {
+            time: Utc::now(),
+            command: command.to_string(),
+            result,
+            update_version,
+            failure,
+            remediation: failure.map(|class| class.remediation().text().to_string()),
+            remediation_id: failure.map(FailureClass::remediation),
+        }
+    }
+
+    /// Reads the status saved by the last run, if there is one.
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map(Some)
+                .map_err(io::Error::from),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the status to `path`, replacing the file all at once so readers never see part of
+    /// it.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let data = serde_json::to_vec(self).map_err(io::Error::from)?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+/// Shows the status on one line, like `2020-03-26T20:00:00Z check-update: update-available 0.3.4`.
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}: {}",
+            self.time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            self.command,
+            serde_plain::to_string(&self.result).map_err(|_| fmt::Error)?
+        )?;
+        if let Some(version) = &self.update_version {
+            write!(f, " {}", version)?;
+        }
+        if let Some(failure) = self.failure {
+            write!(
+                f,
+                " ({})",
+                serde_plain::to_string(&failure).map_err(|_| fmt::Error)?
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error;
+    use snafu::{GenerateBacktrace, IntoError, NoneError};
+
+    #[test]
+    fn status_json() {
+        let version = Version::parse("0.3.4").unwrap();
+        let outcome = Outcome::new(CheckResult::UpdateWritten, Some(&version));
+        let value = serde_json::to_value(Status::new("update-image", Ok(&outcome))).unwrap();
+        assert_eq!(value["command"], "update-image");
+        assert_eq!(value["result"], "update-written");
+        assert_eq!(value["update-version"], "0.3.4");
+        assert!(value.get("failure").is_none());
+    }
+
+    #[test]
+    fn display() {
+        let version = Version::parse("0.3.4").unwrap();
+        let outcome = Outcome::new(CheckResult::UpdateAvailable, Some(&version));
+        let mut status = Status::new("check-update", Ok(&outcome));
+        status.time = DateTime::parse_from_rfc3339("2020-03-26T20:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            status.to_string(),
+            "2020-03-26T20:00:00Z check-update: update-available 0.3.4"
+        );
+
+        let err = error::TargetNotFound {
+            target: "manifest.json",
+        }
+        .into_error(NoneError);
+        let mut status = Status::new("update", Err(&err));
+        status.time = DateTime::parse_from_rfc3339("2020-03-26T20:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            status.to_string(),
+            "2020-03-26T20:00:00Z update: failed (metadata)"
+        );
+    }
+
+    #[test]
+    fn results_from_errors() {
+        let err = error::UpdateNotAvailable.into_error(NoneError);
+        let status = Status::new("check-update", Err(&err));
+        assert_eq!(status.result, CheckResult::NoUpdate);
+        assert_eq!(status.failure, None);
+
+        let version = Version::parse("0.3.4").unwrap();
+        let err = error::UpdateNotReady { version }.into_error(NoneError);
+        let status = Status::new("check-update", Err(&err));
+        assert_eq!(status.result, CheckResult::UpdateScheduled);
+        assert_eq!(status.update_version, Version::parse("0.3.4").ok());
+
+        let err = error::UpdateVetoed {
+            vetoes: "batch-job until 2020-03-27T02:00:00Z",
+            version: Version::parse("0.3.4").ok(),
+        }
+        .into_error(NoneError);
+        let status = Status::new("activate", Err(&err));
+        assert_eq!(status.result, CheckResult::UpdateVetoed);
+        assert_eq!(status.failure, None);
+
+        let err = error::Cancelled.into_error(NoneError);
+        let status = Status::new("prepare", Err(&err));
+        assert_eq!(status.result, CheckResult::Cancelled);
+        assert_eq!(status.failure, None);
+        assert_eq!(
+            serde_json::to_value(&status).unwrap()["result"],
+            "cancelled"
+        );
+
+        let err = error::TargetNotFound {
+            target: "manifest.json",
+        }
+        .into_error(NoneError);
+        let status = Status::new("check-update", Err(&err));
+        assert_eq!(status.result, CheckResult::Failed);
+        assert_eq!(status.failure, Some(FailureClass::Metadata));
+        let value = serde_json::to_value(&status).unwrap();
+        assert_eq!(value["failure"], "metadata");
+        assert_eq!(
+            value["remediation"],
+            FailureClass::Metadata.remediation().text()
+        );
+        assert_eq!(value["remediation-id"], "remediation.metadata");
+    }
+
+    #[test]
+    fn download_failures() {
+        let mismatch: io::Error = tough::error::Error::HashMismatch {
+            context: "root.ext4.lz4".to_string(),
+            calculated: "00".to_string(),
+            expected: "01".to_string(),
+            backtrace: GenerateBacktrace::generate(),
+        }
+        .into();
+        let err = error::WriteUpdate.into_error(mismatch);
+        assert_eq!(FailureClass::from(&err), FailureClass::Verification);
+
+        let full = io::Error::from_raw_os_error(28);
+        let err = error::WriteUpdate.into_error(full);
+        assert_eq!(FailureClass::from(&err), FailureClass::Disk);
+
+        // Statuses saved before the disk class was split out still load.
+        let old: FailureClass = serde_json::from_str("\"image\"").unwrap();
+        assert_eq!(old, FailureClass::Disk);
+    }
+
+    #[test]
+    fn remediation_ids() {
+        // Statuses saved before hints had IDs, and ones with IDs this updog doesn't know, still
+        // load.
+        let old: Status = serde_json::from_str(
+            r#"{"time":"2020-03-26T20:05:00Z","command":"update","result":"failed","failure":"disk","remediation":"Check the disk"}"#,
+        )
+        .unwrap();
+        assert_eq!(old.remediation_id, None);
+        let newer: Status = serde_json::from_str(
+            r#"{"time":"2020-03-26T20:05:00Z","command":"update","result":"failed","failure":"disk","remediation-id":"remediation.disk-full"}"#,
+        )
+        .unwrap();
+        assert_eq!(newer.failure, Some(FailureClass::Disk));
+        assert_eq!(newer.remediation_id, None);
+    }
+}
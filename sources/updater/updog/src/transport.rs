@@ -1,22 +1,198 @@
+//! Fetches files from the update repository.  Where they're fetched from depends on the scheme
+//! of the repository's URLs: `https` (or `http`) URLs are fetched from a web server, `s3` URLs
+//! straight from a bucket, `oci` URLs from a container registry, and `file` URLs from the local
+//! filesystem.  Another kind of repository only needs a `Source` and a scheme to pick it by; the update logic just sees a `Transport`.
+
+use crate::oci::{self, OciSource, RegistryAuth};
+use crate::s3::{self, S3Source};
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::Proxy;
+use snafu::{ResultExt, Snafu};
 use std::cell::{BorrowMutError, RefCell};
-use tough::{HttpTransport, Repository, Transport};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use tough::{Repository, Transport};
+use updater_config::Timeouts;
 use url::Url;
 
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("{}", source))]
+    Http { source: reqwest::Error },
+
+    #[snafu(display("{}", source))]
+    S3 { source: s3::Error },
+
+    #[snafu(display("{}", source))]
+    Oci { source: oci::Error },
+
+    #[snafu(display("Failed to open {}: {}", path.display(), source))]
+    FileOpen {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Can't fetch {}: no transport for '{}' URLs", url, scheme))]
+    UnsupportedScheme { url: Url, scheme: String },
+}
+
+/// A place files can be fetched from, picked by URL scheme.
+pub trait Source: fmt::Debug {
+    /// The URL schemes this source fetches.
+    fn schemes(&self) -> &[&str];
+
+    fn fetch(&self, url: &Url) -> Result<Box<dyn Read>, Error>;
+}
+
+#[derive(Debug)]
+struct HttpSource {
+    client: Client,
+    /// Sent with each HTTPS request, but never over plain HTTP, since they can identify the host.
+    headers: Vec<(String, String)>,
+}
+
+impl HttpSource {
+    fn request(&self, url: &Url) -> RequestBuilder {
+        let mut request = self.client.get(url.as_str());
+        if url.scheme() == "https" {
+            for (name, value) in &self.headers {
+                request = request.header(name.as_str(), value.as_str());
+            }
+        }
+        request
+    }
+}
+
+impl Source for HttpSource {
+    fn schemes(&self) -> &[&str] {
+        &["https", "http"]
+    }
+
+    fn fetch(&self, url: &Url) -> Result<Box<dyn Read>, Error> {
+        let response = self
+            .request(url)
+            .send()
+            .and_then(reqwest::blocking::Response::error_for_status)
+            .context(Http)?;
+        Ok(Box::new(response))
+    }
+}
+
+impl Source for S3Source {
+    fn schemes(&self) -> &[&str] {
+        &["s3"]
+    }
+
+    fn fetch(&self, url: &Url) -> Result<Box<dyn Read>, Error> {
+        Ok(Box::new(S3Source::fetch(self, url).context(S3)?))
+    }
+}
+
+impl Source for OciSource {
+    fn schemes(&self) -> &[&str] {
+        &["oci"]
+    }
+
+    fn fetch(&self, url: &Url) -> Result<Box<dyn Read>, Error> {
+        Ok(Box::new(OciSource::fetch(self, url).context(Oci)?))
+    }
+}
+
+/// Reads local files, for repositories copied onto the host, like in an air-gapped network.
+#[derive(Debug)]
+struct FileSource;
+
+impl Source for FileSource {
+    fn schemes(&self) -> &[&str] {
+        &["file"]
+    }
+
+    fn fetch(&self, url: &Url) -> Result<Box<dyn Read>, Error> {
+        let path = PathBuf::from(url.path());
+        let file = File::open(&path).context(FileOpen { path })?;
+        Ok(Box::new(file))
+    }
+}
+
 #[derive(Debug)]
 #[allow(clippy::module_name_repetitions)]
 pub struct HttpQueryTransport {
-    pub inner: HttpTransport,
+    client: Client,
+    sources: Vec<Box<dyn Source>>,
     parameters: RefCell<Vec<(String, String)>>,
 }
 
 impl HttpQueryTransport {
     pub fn new() -> Self {
+        Self::with_client(Client::new())
+    }
+
+    /// Create a transport that sends any requests that go over the network with `client`.
+    fn with_client(client: Client) -> Self {
         Self {
-            inner: HttpTransport::new(),
+            sources: vec![
+                Box::new(HttpSource {
+                    client: client.clone(),
+                    headers: Vec::new(),
+                }),
+                Box::new(S3Source::new(client.clone())),
+                Box::new(OciSource::new(client.clone(), RegistryAuth::default())),
+                Box::new(FileSource),
+            ],
+            client,
             parameters: RefCell::new(vec![]),
         }
     }
 
+    /// The client used for requests over the network, which goes through the configured proxy.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Use `auth` to log in to OCI registries.
+    pub fn with_registry_auth(mut self, auth: RegistryAuth) -> Self {
+        self.sources
+            .retain(|source| !source.schemes().contains(&"oci"));
+        self.sources
+            .push(Box::new(OciSource::new(self.client.clone(), auth)));
+        self
+    }
+
+    /// Send `headers` with each HTTPS request to the repository, like the host's identity.
+    pub fn with_https_headers(mut self, headers: Vec<(String, String)>) -> Self {
+        self.sources
+            .retain(|source| !source.schemes().contains(&"https"));
+        self.sources.push(Box::new(HttpSource {
+            client: self.client.clone(),
+            headers,
+        }));
+        self
+    }
+
+    /// Create a transport whose requests give up after `timeouts`, and that sends HTTPS requests
+    /// through `proxy`, if given, except for requests to hosts matching an entry in `no_proxy`.
+    pub fn configured(
+        proxy: Option<Url>,
+        no_proxy: Vec<String>,
+        timeouts: Timeouts,
+    ) -> reqwest::Result<Self> {
+        let mut builder = Client::builder()
+            .connect_timeout(timeouts.connect)
+            .timeout(timeouts.request);
+        if let Some(proxy) = proxy {
+            builder = builder.proxy(Proxy::custom(move |url| match url.host_str() {
+                Some(host) if url.scheme() == "https" && !no_proxy_matches(host, &no_proxy) => {
+                    Some(proxy.clone())
+                }
+                _ => None,
+            }));
+        }
+        Ok(Self::with_client(builder.build()?))
+    }
+
     /// Try to borrow a mutable reference to parameters; returns an error if
     /// a borrow is already active
     pub fn queries_get_mut(
@@ -49,13 +225,133 @@ impl HttpQueryTransport {
     }
 }
 
+/// Checks whether `host` matches an entry in a `no_proxy` list, following the usual conventions:
+/// "*" matches every host, IP addresses match exactly, and domains match themselves and their
+/// subdomains, with or without a leading ".".
+fn no_proxy_matches(host: &str, no_proxy: &[String]) -> bool {
+    // IPv6 hosts are bracketed in URLs.
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let host_ip = host.parse::<IpAddr>().ok();
+    no_proxy.iter().any(|entry| {
+        let entry = entry.trim();
+        if entry == "*" {
+            return true;
+        }
+        if let Ok(ip) = entry.parse::<IpAddr>() {
+            return host_ip == Some(ip);
+        }
+        let domain = entry.trim_start_matches('.');
+        !domain.is_empty()
+            && (host.eq_ignore_ascii_case(domain)
+                || (host.len() > domain.len()
+                    && host
+                        .to_ascii_lowercase()
+                        .ends_with(&format!(".{}", domain.to_ascii_lowercase()))))
+    })
+}
+
 pub type HttpQueryRepo<'a> = Repository<'a, HttpQueryTransport>;
 
 impl Transport for HttpQueryTransport {
-    type Stream = reqwest::blocking::Response;
-    type Error = reqwest::Error;
+    type Stream = Box<dyn Read>;
+    type Error = Error;
 
     fn fetch(&self, url: Url) -> Result<Self::Stream, Self::Error> {
-        self.inner.fetch(self.set_query_string(url))
+        let source = self
+            .sources
+            .iter()
+            .find(|source| source.schemes().contains(&url.scheme()));
+        match source {
+            // The query string tells a repository's web server about the host, like its version
+            // and the channel it follows; other sources can't use it.
+            Some(source) if url.scheme().starts_with("http") => {
+                source.fetch(&self.set_query_string(url))
+            }
+            Some(source) => source.fetch(&url),
+            None => UnsupportedScheme {
+                scheme: url.scheme().to_string(),
+                url,
+            }
+            .fail(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn picks_source_by_scheme() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("timestamp.json"), "{}").unwrap();
+        let transport = HttpQueryTransport::new();
+
+        let base = Url::from_directory_path(dir.path()).unwrap();
+        let mut data = String::new();
+        transport
+            .fetch(base.join("timestamp.json").unwrap())
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "{}");
+
+        let ftp = Url::parse("ftp://ftp.example.com/repo/timestamp.json").unwrap();
+        match transport.fetch(ftp) {
+            Err(Error::UnsupportedScheme { scheme, .. }) => assert_eq!(scheme, "ftp"),
+            Err(e) => panic!("wrong error: {}", e),
+            Ok(_) => panic!("fetched a URL with no transport"),
+        }
+
+        // Repository URLs are joined with file names the same way for every scheme.
+        let s3 = Url::parse("s3://bucket/metadata/").unwrap();
+        assert_eq!(
+            s3.join("1.root.json").unwrap().as_str(),
+            "s3://bucket/metadata/1.root.json"
+        );
+    }
+
+    #[test]
+    fn headers_only_over_https() {
+        let source = HttpSource {
+            client: Client::new(),
+            headers: vec![("x-host".to_string(), "i-1234".to_string())],
+        };
+        let https = Url::parse("https://updates.example.com/timestamp.json").unwrap();
+        let request = source.request(&https).build().unwrap();
+        assert_eq!(request.headers()["x-host"], "i-1234");
+
+        let http = Url::parse("http://updates.example.com/timestamp.json").unwrap();
+        let request = source.request(&http).build().unwrap();
+        assert!(request.headers().get("x-host").is_none());
+    }
+
+    #[test]
+    fn no_proxy() {
+        let no_proxy: Vec<String> = ["example.com", ".internal", "10.0.0.1", "::1"]
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        for host in &[
+            "example.com",
+            "updates.EXAMPLE.com",
+            "repo.internal",
+            "10.0.0.1",
+            "[::1]",
+        ] {
+            assert!(no_proxy_matches(host, &no_proxy), "{}", host);
+        }
+        for host in &[
+            "notexample.com",
+            "example.com.evil",
+            "internal.net",
+            "10.0.0.2",
+        ] {
+            assert!(!no_proxy_matches(host, &no_proxy), "{}", host);
+        }
+        assert!(no_proxy_matches("anything", &["*".to_string()]));
+        assert!(!no_proxy_matches("anything", &[]));
     }
 }
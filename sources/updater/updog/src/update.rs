@@ -0,0 +1,343 @@
+//! The commands that check the repository for an update and write it to the host, and
+//! `apply-bundle`, which does the same from a bundle.
+
+use crate::activate::{check_approval, check_vetoes, initiate_reboot, update_flags};
+use crate::bundle;
+use crate::cache::TargetCache;
+use crate::clock::WaveClock;
+use crate::config::load_config;
+use crate::error::{self, Result};
+use crate::image::{already_prepared, update_image};
+use crate::messages::Message;
+use crate::prepared::Prepared;
+use crate::provenance;
+use crate::repo::{
+    applicable_updates, build_transport, load_manifest, load_repository, retrieve_migrations,
+    set_common_query_params, update_required,
+};
+use crate::report::{CheckOutput, FetchWindow, Output, Report};
+use crate::status::CheckResult;
+use crate::transport::HttpQueryTransport;
+use crate::verify;
+use crate::{Arguments, Command, TARGET_ARCH};
+use log::{info, warn};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::fs;
+use update_metadata::{Clock, SystemClock};
+use url::Url;
+
+/// Runs one of the commands that needs the repository's manifest, which is loaded first.
+#[allow(clippy::too_many_lines)]
+pub(crate) fn run(arguments: &Arguments, command: Command) -> Result<Output> {
+    let paths = &arguments.paths;
+    let config = load_config(paths)?;
+    let (current_version, variant) = paths.running_version()?;
+    // A version given on the command line takes precedence over the configured version lock.
+    let force_version = match &arguments.force_version {
+        Some(version) => Some(version.clone()),
+        None => config.locked_version()?,
+    };
+    let ignore_waves = arguments.ignore_waves || config.ignore_waves;
+    let transport = build_transport(&config)?;
+    set_common_query_params(&transport, &current_version, &config)?;
+    let repository = load_repository(&transport, &config, paths)?;
+    let manifest = load_manifest(&repository, &variant, config.memory_budget()?)?;
+    let clock = WaveClock::new(
+        SystemClock,
+        repository.timestamp().signed.expires,
+        config.timestamp_lifetime(),
+    );
+    if let Some(skew) = clock.skew() {
+        warn!(
+            "The system clock is {} minutes behind the repository's timestamp metadata; \
+             going by the metadata's signing time for waves",
+            skew.num_minutes()
+        );
+    }
+
+    match command {
+        Command::CheckUpdate | Command::Whats => {
+            if arguments.all {
+                let updates = applicable_updates(&manifest, &variant)
+                    .into_iter()
+                    .cloned()
+                    .collect();
+                return Ok(Output::report(Report::Updates(updates)));
+            }
+
+            let update = update_required(
+                &config,
+                &manifest,
+                &current_version,
+                &variant,
+                force_version,
+            )
+            .context(error::UpdateNotAvailable)?;
+
+            if !ignore_waves {
+                ensure!(
+                    update.update_ready(config.seed, &clock),
+                    error::UpdateNotReady {
+                        version: update.version.clone()
+                    }
+                );
+            }
+            let check = CheckOutput {
+                fetch_window: if ignore_waves {
+                    None
+                } else {
+                    FetchWindow::new(update, config.seed, config.fleet_size_hint, clock.now())
+                },
+                update: update.clone(),
+            };
+            Ok(Output::new(
+                CheckResult::UpdateAvailable,
+                Some(&update.version),
+                Report::Check(check),
+            ))
+        }
+        Command::Update | Command::UpdateImage | Command::Prepare => {
+            if let Some(u) = update_required(
+                &config,
+                &manifest,
+                &current_version,
+                &variant,
+                force_version,
+            ) {
+                if u.update_ready(config.seed, &clock) || ignore_waves {
+                    info!("{}: {}", Message::StartingUpdate.text(), u.version);
+
+                    if ignore_waves {
+                        info!("{}", Message::UpdatingImmediately.text());
+                    } else {
+                        let jitter = match arguments.timestamp {
+                            Some(t) => Some(t),
+                            _ => u.jitter(config.seed, &clock),
+                        };
+
+                        if let Some(j) = jitter {
+                            if j > clock.now() {
+                                // not yet!
+                                return Ok(Output::new(
+                                    CheckResult::UpdateScheduled,
+                                    Some(&u.version),
+                                    Report::Scheduled(j),
+                                ));
+                            }
+                        }
+                    }
+
+                    if command == Command::Prepare {
+                        if let Some(prepared) = already_prepared(paths, &variant, u)? {
+                            return Ok(Output::new(
+                                CheckResult::UpdatePrepared,
+                                Some(&u.version),
+                                Report::Prepared {
+                                    message: Message::UpdateAlreadyPrepared.text().to_string(),
+                                    prepared,
+                                },
+                            ));
+                        }
+                    }
+
+                    transport
+                        .queries_get_mut()
+                        .context(error::TransportBorrow)?
+                        .push((String::from("target"), u.version.to_string()));
+
+                    if config.require_provenance {
+                        provenance::verify(&repository, u)?;
+                    }
+                    retrieve_migrations(
+                        &repository,
+                        &transport,
+                        &manifest,
+                        u,
+                        &current_version,
+                        paths,
+                        &arguments.cancel,
+                    )?;
+                    let cache = match config.target_cache_url()? {
+                        Some(url) => Some(TargetCache::new(url)?),
+                        None => None,
+                    };
+                    update_image(
+                        u,
+                        &repository,
+                        cache.as_ref(),
+                        paths,
+                        command == Command::Prepare,
+                        &arguments.cancel,
+                    )?;
+                    let result = match command {
+                        Command::Update => {
+                            check_vetoes(&config, Some(&u.version))?;
+                            check_approval(arguments, &config, Some(&u.version))?;
+                            update_flags(paths, &arguments.cancel)?;
+                            if arguments.reboot {
+                                initiate_reboot()?;
+                            }
+                            CheckResult::UpdateApplied
+                        }
+                        Command::Prepare => {
+                            let state = paths.partition_state()?;
+                            Prepared::new(&variant, &u.version, &state)
+                                .write(&paths.prepared)
+                                .context(error::PreparedWrite {
+                                    path: &paths.prepared,
+                                })?;
+                            CheckResult::UpdatePrepared
+                        }
+                        _ => CheckResult::UpdateWritten,
+                    };
+                    let message = if result == CheckResult::UpdatePrepared {
+                        Message::UpdatePrepared
+                    } else {
+                        Message::UpdateApplied
+                    };
+                    Ok(Output::new(
+                        result,
+                        Some(&u.version),
+                        Report::Update {
+                            message,
+                            update: u.clone(),
+                        },
+                    ))
+                } else if let Some(wave) = u.jitter(config.seed, &clock) {
+                    // return the jittered time of our wave in the update
+                    Ok(Output::new(
+                        CheckResult::UpdateScheduled,
+                        Some(&u.version),
+                        Report::Scheduled(wave),
+                    ))
+                } else {
+                    Ok(Output::new(
+                        CheckResult::UpdateScheduled,
+                        Some(&u.version),
+                        Report::note(Message::LaterWave),
+                    ))
+                }
+            } else {
+                Ok(Output::new(
+                    CheckResult::NoUpdate,
+                    None,
+                    Report::note(Message::NoUpdateRequired),
+                ))
+            }
+        }
+        Command::UpdateApply => {
+            check_vetoes(&config, None)?;
+            check_approval(arguments, &config, None)?;
+            update_flags(paths, &arguments.cancel)?;
+            if arguments.reboot {
+                initiate_reboot()?;
+            }
+            Ok(Output::new(
+                CheckResult::UpdateApplied,
+                None,
+                Report::Nothing,
+            ))
+        }
+        Command::VerifyCurrent => Ok(Output::report(Report::Verification(verify::run(
+            &repository,
+            &manifest,
+            paths,
+            &current_version,
+            &variant,
+            arguments.all,
+        )?))),
+        // Handled by `run_command` before loading the config and repository, which these don't
+        // need or check themselves.
+        Command::Activate
+        | Command::Doctor
+        | Command::History
+        | Command::Status
+        | Command::Auto
+        | Command::ApplyBundle => Ok(Output::report(Report::Nothing)),
+    }
+}
+
+/// Applies the update in a bundle made by `updata bundle`, for hosts that can't reach the
+/// repository.  The bundle's files are checked against its inventory, which has to be signed if
+/// the host has bundle keys, and then its metadata is checked against the trusted root like the
+/// repository's; the metadata cache keeps a bundle from rolling back metadata the host has
+/// already seen.  Waves don't apply, but vetoes do, and the update has to be newer than the
+/// running version.
+pub(crate) fn apply_bundle(arguments: &Arguments) -> Result<Output> {
+    let paths = &arguments.paths;
+    let path = arguments.bundle.as_ref().context(error::BundleMissing)?;
+    let mut config = load_config(paths)?;
+    let (current_version, variant) = paths.running_version()?;
+
+    fs::create_dir_all(&paths.bundles).context(error::BundleDir {
+        path: &paths.bundles,
+    })?;
+    let dir = tempfile::TempDir::new_in(&paths.bundles).context(error::BundleDir {
+        path: &paths.bundles,
+    })?;
+    // File URLs need absolute paths.
+    let root = fs::canonicalize(dir.path()).context(error::BundleDir {
+        path: &paths.bundles,
+    })?;
+    let inventory = bundle::unpack(path, &root, &config.bundle_keys()?)?;
+    ensure!(
+        inventory.variant == variant
+            && inventory.arch == TARGET_ARCH
+            && inventory.version > current_version,
+        error::BundleNotApplicable {
+            bundle_variant: inventory.variant,
+            bundle_version: inventory.version,
+            bundle_arch: inventory.arch,
+            variant,
+            version: current_version,
+            arch: TARGET_ARCH,
+        }
+    );
+
+    // Load the bundle like a repository of its own.
+    let dir_url = |part: &str| -> Result<String> {
+        let part_dir = root.join(part);
+        Url::from_directory_path(&part_dir)
+            .map(|url| url.to_string())
+            .ok()
+            .context(error::BundlePath { path: part_dir })
+    };
+    config.metadata_base_url = dir_url("metadata")?;
+    config.targets_base_url = dir_url("targets")?;
+    let transport = HttpQueryTransport::new();
+    let repository = load_repository(&transport, &config, paths)?;
+    let manifest = load_manifest(&repository, &variant, config.memory_budget()?)?;
+    let update = applicable_updates(&manifest, &variant)
+        .into_iter()
+        .find(|u| u.version == inventory.version)
+        .context(error::BundleUpdateMissing {
+            version: inventory.version.clone(),
+        })?;
+
+    if config.require_provenance {
+        provenance::verify(&repository, update)?;
+    }
+    retrieve_migrations(
+        &repository,
+        &transport,
+        &manifest,
+        update,
+        &current_version,
+        paths,
+        &arguments.cancel,
+    )?;
+    update_image(update, &repository, None, paths, true, &arguments.cancel)?;
+    check_vetoes(&config, Some(&update.version))?;
+    update_flags(paths, &arguments.cancel)?;
+    if arguments.reboot {
+        initiate_reboot()?;
+    }
+    Ok(Output::new(
+        CheckResult::UpdateApplied,
+        Some(&update.version),
+        Report::Update {
+            message: Message::UpdateApplied,
+            update: update.clone(),
+        },
+    ))
+}
@@ -0,0 +1,166 @@
+//! Checks that the running OS is what the update repository says it should be, for compliance
+//! scans that need to show a host hasn't drifted from, or been tampered with since, the release it
+//! claims to run.  The running version must be listed in the manifest, and the active partition
+//! set must hold that version's images, as verified through the repository's TUF metadata.
+
+use crate::config::Paths;
+use crate::error::{self, Result};
+use crate::image::{read_back, DigestWriter, Written};
+use crate::transport::HttpQueryRepo;
+use crate::TARGET_ARCH;
+use semver::Version;
+use serde::{Serialize, Serializer};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use update_metadata::Manifest;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct ImageCheck {
+    image: &'static str,
+    target: String,
+    partition: PathBuf,
+    matches: bool,
+}
+
+impl fmt::Display for ImageCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.matches {
+            write!(
+                f,
+                "ok    {}: {} matches {}",
+                self.image,
+                self.partition.display(),
+                self.target
+            )
+        } else {
+            write!(
+                f,
+                "DRIFT {}: {} doesn't match {}",
+                self.image,
+                self.partition.display(),
+                self.target
+            )
+        }
+    }
+}
+
+/// What `verify-current` found: the running version, which is in the manifest, and whether each
+/// of its images matches the active partition set.
+#[derive(Debug)]
+pub struct Verification {
+    variant: String,
+    version: Version,
+    checks: Vec<ImageCheck>,
+}
+
+/// As JSON, a verification is just the images' checks.
+impl Serialize for Verification {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.checks.serialize(serializer)
+    }
+}
+
+impl Verification {
+    /// Fails if any of the images don't match.
+    pub(crate) fn ensure_matched(&self) -> Result<()> {
+        let mismatched = self.checks.iter().filter(|check| !check.matches).count();
+        ensure!(mismatched == 0, error::RunningDrift { mismatched });
+        Ok(())
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Running {} {}, which is in the manifest",
+            self.variant, self.version
+        )?;
+        for check in &self.checks {
+            write!(f, "\n{}", check)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares the active partition set with the running version's images.  The root image is only
+/// compared if `root` is set, since it's much larger than the others and means downloading all of
+/// it; the kernel already checks the root filesystem against the hash partition as it's read.
+pub(crate) fn run(
+    repository: &HttpQueryRepo<'_>,
+    manifest: &Manifest,
+    paths: &Paths,
+    version: &Version,
+    variant: &str,
+    root: bool,
+) -> Result<Verification> {
+    let update = manifest
+        .updates
+        .iter()
+        .find(|u| u.variant == variant && u.arch == TARGET_ARCH && u.version == *version)
+        .context(error::RunningNotInManifest {
+            version: version.clone(),
+            variant,
+        })?;
+    let state = paths.partition_state()?;
+    let active = state.active_set();
+
+    let mut images = vec![
+        ("boot", &update.images.boot, &active.boot),
+        ("hash", &update.images.hash, &active.hash),
+    ];
+    if root {
+        images.push(("root", &update.images.root, &active.root));
+    }
+    let mut checks = Vec::new();
+    for (image, target, partition) in images {
+        let expected = target_digest(repository, target)?;
+        let found = read_back(partition, expected.len)?;
+        checks.push(ImageCheck {
+            image,
+            target: target.clone(),
+            partition: partition.clone(),
+            matches: found.matches(&expected),
+        });
+    }
+    Ok(Verification {
+        variant: variant.to_string(),
+        version: version.clone(),
+        checks,
+    })
+}
+
+/// Downloads `target` and returns the length and digest of its contents, as they'd be written to
+/// disk.
+fn target_digest(repository: &HttpQueryRepo<'_>, target: &str) -> Result<Written> {
+    let reader = repository
+        .read_target(target)
+        .context(error::Metadata)?
+        .context(error::TargetNotFound { target })?;
+    let mut reader = lz4::Decoder::new(reader).context(error::Lz4Decode { target })?;
+    let mut digest = DigestWriter::new(io::sink());
+    io::copy(&mut reader, &mut digest).context(error::VerifyTarget { target })?;
+    Ok(digest.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let check = ImageCheck {
+            image: "hash",
+            target: "bottlerocket-x86_64-aws-k8s-1.15-v0.3.2-root.verity.lz4".to_string(),
+            partition: PathBuf::from("/dev/nvme0n1p4"),
+            matches: false,
+        };
+        assert_eq!(
+            check.to_string(),
+            "DRIFT hash: /dev/nvme0n1p4 doesn't match bottlerocket-x86_64-aws-k8s-1.15-v0.3.2-root.verity.lz4"
+        );
+    }
+}
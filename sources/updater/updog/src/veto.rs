@@ -0,0 +1,85 @@
+//! Vetoes let software on the host, like a host container or an agent, hold back activating an
+//! update and rebooting into one while it's doing something that shouldn't be interrupted, such
+//! as a critical batch job, instead of each of them wrapping updog with a guard of its own.
+//!
+//! A veto is registered through the API under settings.updates.vetoes, by name, with a reason and
+//! the time it expires.  Every veto has to expire, so an agent that dies can't hold back updates
+//! forever; to lift one early, its owner sets the expiry to a time that's already passed.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) struct Veto {
+    /// Why the update is held back, to show the operator.
+    #[serde(default)]
+    pub(crate) reason: Option<String>,
+    pub(crate) expires: DateTime<Utc>,
+}
+
+/// Describes the vetoes that haven't expired at `now`, like
+/// `batch-job (critical batch job running) until 2020-03-27T02:00:00Z`, or returns None if there
+/// aren't any.
+pub(crate) fn active(vetoes: &BTreeMap<String, Veto>, now: DateTime<Utc>) -> Option<String> {
+    let active: Vec<String> = vetoes
+        .iter()
+        .filter(|(_, veto)| veto.expires > now)
+        .map(|(name, veto)| {
+            let until = veto.expires.to_rfc3339_opts(SecondsFormat::Secs, true);
+            match &veto.reason {
+                Some(reason) => format!("{} ({}) until {}", name, reason, until),
+                None => format!("{} until {}", name, until),
+            }
+        })
+        .collect();
+    if active.is_empty() {
+        None
+    } else {
+        Some(active.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn active_vetoes() {
+        let now = DateTime::parse_from_rfc3339("2020-03-26T20:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut vetoes = BTreeMap::new();
+        assert_eq!(active(&vetoes, now), None);
+
+        vetoes.insert(
+            "lifted".to_string(),
+            Veto {
+                reason: None,
+                expires: now - Duration::minutes(1),
+            },
+        );
+        assert_eq!(active(&vetoes, now), None);
+
+        vetoes.insert(
+            "batch-job".to_string(),
+            Veto {
+                reason: Some("critical batch job running".to_string()),
+                expires: now + Duration::hours(6),
+            },
+        );
+        vetoes.insert(
+            "backup".to_string(),
+            Veto {
+                reason: None,
+                expires: now + Duration::hours(1),
+            },
+        );
+        assert_eq!(
+            active(&vetoes, now).unwrap(),
+            "backup until 2020-03-26T21:00:00Z, \
+             batch-job (critical batch job running) until 2020-03-27T02:00:00Z"
+        );
+    }
+}
@@ -0,0 +1,338 @@
+//! Tells operator-chosen HTTPS endpoints when an update reaches a new stage on the host, so
+//! chat and ticketing systems hear about it without polling every host.  Events come from the
+//! status of each updog run, compared with the run before it: an event is only sent when the
+//! host's update state changes, not every time a timer finds the same update again.
+//!
+//! Each event is a JSON object sent in a POST request, signed with the webhook's secret using
+//! HMAC-SHA256 so the endpoint can check it came from a host that knows the secret.  Sending is
+//! best effort; a webhook that can't be reached doesn't fail the run, and isn't retried.
+
+use crate::config::{load_config, Paths};
+use crate::history;
+use crate::repo::build_transport;
+use crate::status::{CheckResult, FailureClass, Status};
+use chrono::{DateTime, TimeZone, Utc};
+use log::{debug, warn};
+use reqwest::blocking::Client;
+use ring::hmac;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, ResultExt, Snafu};
+use std::fs;
+use std::time::Duration;
+use url::Url;
+
+/// How long to wait for a webhook to answer, so a slow endpoint doesn't hold up updog.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// The header holding the hex HMAC-SHA256 of the request body, prefixed with "sha256=".
+const SIGNATURE_HEADER: &str = "Bottlerocket-Signature";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid webhook URL '{}': {}", url, source))]
+    ParseUrl {
+        url: String,
+        source: url::ParseError,
+    },
+
+    #[snafu(display("Webhook URL '{}' must use HTTPS", url))]
+    InsecureUrl { url: String },
+
+    #[snafu(display("Webhook secret for {} isn't valid base64: {}", url, source))]
+    Secret {
+        url: Url,
+        source: base64::DecodeError,
+    },
+
+    #[snafu(display("Error sending event to {}: {}", url, source))]
+    Send { url: Url, source: reqwest::Error },
+}
+
+/// An endpoint to send events to, from settings.updates.webhooks.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct WebhookConfig {
+    url: String,
+    /// The key events are signed with, in base64.
+    secret: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[allow(clippy::enum_variant_names)]
+pub(crate) enum EventKind {
+    UpdateAvailable,
+    /// The update was written to the inactive partition set, but isn't set to boot.
+    UpdateStaged,
+    /// The update is set to boot next.
+    UpdateActivated,
+    UpdateFailed,
+    /// The host rebooted after activating an update, but isn't running it.
+    UpdateRolledBack,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Event {
+    #[serde(rename = "event")]
+    kind: EventKind,
+    time: DateTime<Utc>,
+    variant: String,
+    arch: String,
+    /// The running version.
+    version: Version,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    update_version: Option<Version>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failure: Option<FailureClass>,
+}
+
+/// The event a status stands for, if any.
+fn kind(status: &Status) -> Option<EventKind> {
+    match status.result {
+        CheckResult::UpdateAvailable => Some(EventKind::UpdateAvailable),
+        CheckResult::UpdateWritten | CheckResult::UpdatePrepared => Some(EventKind::UpdateStaged),
+        CheckResult::UpdateApplied | CheckResult::UpdateActivated => {
+            Some(EventKind::UpdateActivated)
+        }
+        CheckResult::Failed => Some(EventKind::UpdateFailed),
+        _ => None,
+    }
+}
+
+/// Works out the events to send for `current`, given the status of the run before it.  `booted`
+/// is when the host last booted, so we can tell that an activated update didn't stick.
+pub(crate) fn events(
+    previous: Option<&Status>,
+    current: &Status,
+    variant: &str,
+    running: &Version,
+    booted: DateTime<Utc>,
+) -> Vec<Event> {
+    let event = |kind, update_version: Option<&Version>, failure| Event {
+        kind,
+        time: current.time,
+        variant: variant.to_string(),
+        arch: crate::TARGET_ARCH.to_string(),
+        version: running.clone(),
+        update_version: update_version.cloned(),
+        failure,
+    };
+    let mut events = Vec::new();
+
+    if let Some(previous) = previous {
+        if let (Some(EventKind::UpdateActivated), Some(version)) =
+            (kind(previous), &previous.update_version)
+        {
+            if previous.time < booted && version != running {
+                events.push(event(EventKind::UpdateRolledBack, Some(version), None));
+            }
+        }
+    }
+
+    let unchanged = previous.map_or(false, |previous| {
+        kind(previous) == kind(current)
+            && previous.update_version == current.update_version
+            && previous.failure == current.failure
+    });
+    if let Some(kind) = kind(current) {
+        if !unchanged {
+            events.push(event(
+                kind,
+                current.update_version.as_ref(),
+                current.failure,
+            ));
+        }
+    }
+    events
+}
+
+/// Sends the events for a run's status to the configured webhooks.  Anything that goes wrong is
+/// logged and otherwise ignored, since the run itself is already done.
+pub(crate) fn notify(paths: &Paths, status: &Status) {
+    // A config that can't be loaded already failed the run, and its status says so.
+    let config = match load_config(paths) {
+        Ok(config) => config,
+        Err(_) => return,
+    };
+    if config.webhooks.is_empty() {
+        return;
+    }
+    let (running, variant) = match paths.running_version() {
+        Ok(release) => release,
+        Err(e) => {
+            warn!("Not sending update events: {}", e);
+            return;
+        }
+    };
+    let previous = match history::load(&paths.history) {
+        Ok(mut history) => history.pop(),
+        Err(e) => {
+            warn!("Not sending update events: failed to read history: {}", e);
+            return;
+        }
+    };
+    let events = events(
+        previous.as_ref(),
+        status,
+        &variant,
+        &running,
+        boot_time().unwrap_or_else(Utc::now),
+    );
+    if events.is_empty() {
+        return;
+    }
+    let transport = match build_transport(&config) {
+        Ok(transport) => transport,
+        Err(e) => {
+            warn!("Not sending update events: {}", e);
+            return;
+        }
+    };
+    for event in &events {
+        for (name, webhook) in &config.webhooks {
+            match send(transport.client(), webhook, event) {
+                Ok(()) => debug!("Sent {:?} event to webhook '{}'", event.kind, name),
+                Err(e) => warn!("Failed to send event to webhook '{}': {}", name, e),
+            }
+        }
+    }
+}
+
+fn send(client: &Client, webhook: &WebhookConfig, event: &Event) -> Result<(), Error> {
+    let url = Url::parse(&webhook.url).context(ParseUrl { url: &webhook.url })?;
+    ensure!(url.scheme() == "https", InsecureUrl { url: &webhook.url });
+    let secret = base64::decode(&webhook.secret).context(Secret { url: url.clone() })?;
+    let body = serde_json::to_vec(event).expect("event serialization can't fail");
+    client
+        .post(url.clone())
+        .timeout(TIMEOUT)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, signature(&secret, &body))
+        .body(body)
+        .send()
+        .and_then(reqwest::blocking::Response::error_for_status)
+        .context(Send { url })?;
+    Ok(())
+}
+
+fn signature(secret: &[u8], body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+    let tag = hmac::sign(&key, body);
+    let hex: String = tag.as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", hex)
+}
+
+/// When the host booted, from the kernel's `btime` in /proc/stat.
+fn boot_time() -> Option<DateTime<Utc>> {
+    let stat = fs::read_to_string("/proc/stat").ok()?;
+    let btime = stat.lines().find(|line| line.starts_with("btime "))?["btime ".len()..]
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Utc.timestamp(btime, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn status(result: CheckResult, version: Option<&str>, time: DateTime<Utc>) -> Status {
+        Status {
+            time,
+            command: "auto".to_string(),
+            result,
+            update_version: version.map(|v| Version::parse(v).unwrap()),
+            failure: None,
+            remediation: None,
+            remediation_id: None,
+        }
+    }
+
+    fn kinds(events: &[Event]) -> Vec<EventKind> {
+        events.iter().map(|event| event.kind).collect()
+    }
+
+    #[test]
+    fn events_on_change() {
+        let now = Utc::now();
+        let booted = now - Duration::days(1);
+        let running = Version::parse("0.3.3").unwrap();
+        let check = |previous: Option<&Status>, current: &Status| {
+            kinds(&events(previous, current, "aws-k8s-1.15", &running, booted))
+        };
+
+        let available = status(CheckResult::UpdateAvailable, Some("0.3.4"), now);
+        assert_eq!(check(None, &available), vec![EventKind::UpdateAvailable]);
+        // Finding the same update again isn't news.
+        assert_eq!(check(Some(&available), &available), vec![]);
+        let newer = status(CheckResult::UpdateAvailable, Some("0.3.5"), now);
+        assert_eq!(
+            check(Some(&available), &newer),
+            vec![EventKind::UpdateAvailable]
+        );
+
+        let staged = status(CheckResult::UpdatePrepared, Some("0.3.4"), now);
+        assert_eq!(
+            check(Some(&available), &staged),
+            vec![EventKind::UpdateStaged]
+        );
+        let mut failed = status(CheckResult::Failed, None, now);
+        failed.failure = Some(FailureClass::Network);
+        assert_eq!(check(Some(&staged), &failed), vec![EventKind::UpdateFailed]);
+        assert_eq!(
+            check(None, &status(CheckResult::NoUpdate, None, now)),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn rolled_back() {
+        let now = Utc::now();
+        let running = Version::parse("0.3.3").unwrap();
+        let activated = status(
+            CheckResult::UpdateActivated,
+            Some("0.3.4"),
+            now - Duration::hours(2),
+        );
+        let available = status(CheckResult::UpdateAvailable, Some("0.3.4"), now);
+
+        // Rebooted since activating, but still on the old version.
+        let booted = now - Duration::hours(1);
+        let sent = events(
+            Some(&activated),
+            &available,
+            "aws-k8s-1.15",
+            &running,
+            booted,
+        );
+        assert_eq!(
+            kinds(&sent),
+            vec![EventKind::UpdateRolledBack, EventKind::UpdateAvailable]
+        );
+        assert_eq!(
+            sent[0].update_version,
+            Some(Version::parse("0.3.4").unwrap())
+        );
+
+        // Not rebooted yet.
+        let booted = now - Duration::hours(3);
+        let sent = events(
+            Some(&activated),
+            &available,
+            "aws-k8s-1.15",
+            &running,
+            booted,
+        );
+        assert_eq!(kinds(&sent), vec![EventKind::UpdateAvailable]);
+    }
+
+    #[test]
+    fn signed() {
+        // From RFC 4231, test case 2.
+        assert_eq!(
+            signature(b"Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -0,0 +1,271 @@
+//! Sets up hosts for updog's end-to-end tests: each has its own config, os-release, and trusted
+//! root, pointed at a signed TUF repository published with updata and served over HTTP, and
+//! optionally a loop device with two partition sets.
+
+// Each test file uses a different subset of these helpers.
+#![allow(dead_code)]
+
+use signpost::State;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use tempfile::TempDir;
+use update_harness::{
+    image_data, loop_devices_available, loopback_http_available, Disk, RepoBuilder, Server,
+};
+
+pub const VARIANT: &str = "aws-k8s-1.15";
+/// The number of bytes of each partition we compare against the image written to it.
+const COMPARE_LEN: usize = 4096;
+
+/// A host running `version`, with its own config and os-release, pointed at a repository holding
+/// an update to 0.2.0.
+pub struct Host {
+    dir: TempDir,
+    disk: Option<Disk>,
+    server: Server,
+    trusted_root: PathBuf,
+    root_image: String,
+}
+
+impl Host {
+    pub fn new(version: &str) -> Self {
+        Self::build(version, false)
+    }
+
+    /// Like `new`, but with the repository's manifest sharded by variant.
+    pub fn sharded(version: &str) -> Self {
+        Self::build(version, true)
+    }
+
+    fn build(version: &str, shard: bool) -> Self {
+        let dir = TempDir::new().unwrap();
+
+        // Publish the manifest with updata, the way a real repository is built.
+        let mut repo = RepoBuilder::new();
+        let images = repo.add_images(VARIANT, "0.2.0");
+        let manifest = dir.path().join("manifest.json");
+        let release = dir.path().join("Release.toml");
+        fs::write(
+            &release,
+            "version = \"0.2.0\"\n\
+             [migrations]\n\
+             \"(0.1.0, 0.2.0)\" = []\n",
+        )
+        .unwrap();
+        updata(&["init".as_ref(), manifest.as_os_str()]);
+        updata(&[
+            "add-update".as_ref(),
+            manifest.as_os_str(),
+            "--variant".as_ref(),
+            VARIANT.as_ref(),
+            "--version".as_ref(),
+            "0.2.0".as_ref(),
+            "--arch".as_ref(),
+            std::env::consts::ARCH.as_ref(),
+            "--root".as_ref(),
+            images.root.as_ref(),
+            "--boot".as_ref(),
+            images.boot.as_ref(),
+            "--hash".as_ref(),
+            images.hash.as_ref(),
+        ]);
+        updata(&[
+            "set-migrations".as_ref(),
+            "--from".as_ref(),
+            release.as_os_str(),
+            "--to".as_ref(),
+            manifest.as_os_str(),
+        ]);
+        if shard {
+            updata(&["shard".as_ref(), manifest.as_os_str()]);
+        }
+        repo.manifest_file(&manifest);
+        let trusted_root = repo.write(dir.path().join("repo"));
+        let server = Server::serve(dir.path().join("repo"));
+
+        fs::write(
+            dir.path().join("updog.toml"),
+            format!(
+                "metadata_base_url = \"{url}/metadata/\"\n\
+                 targets_base_url = \"{url}/targets/\"\n\
+                 seed = 1234\n",
+                url = server.url()
+            ),
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("os-release"),
+            format!(
+                "PRETTY_NAME=\"Bottlerocket OS {version}\"\n\
+                 VARIANT_ID={variant}\n\
+                 VERSION_ID={version}\n\
+                 BUILD_ID=0123abcd\n",
+                version = version,
+                variant = VARIANT
+            ),
+        )
+        .unwrap();
+
+        Self {
+            dir,
+            disk: None,
+            server,
+            trusted_root,
+            root_image: images.root,
+        }
+    }
+
+    /// Like `new`, but with an OS disk, so updates can be written.
+    pub fn with_disk(version: &str) -> Self {
+        let mut host = Self::new(version);
+        host.disk = Some(Disk::new(host.path("disk.img")));
+        host
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    /// The server for the host's repository, which can be told to misbehave.
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    /// Adds a line to the host's updog config.
+    pub fn add_config(&self, line: &str) {
+        let path = self.path("updog.toml");
+        let mut config = fs::read_to_string(&path).unwrap();
+        config.push_str(line);
+        config.push('\n');
+        fs::write(path, config).unwrap();
+    }
+
+    pub fn trusted_root(&self) -> &PathBuf {
+        &self.trusted_root
+    }
+
+    /// The target name of the root image for 0.2.0.
+    pub fn root_image(&self) -> &str {
+        &self.root_image
+    }
+
+    pub fn disk(&self) -> &Disk {
+        self.disk.as_ref().expect("host has no disk")
+    }
+
+    /// The root partition of set A, which the host is running from.
+    pub fn active_partition(&self) -> PathBuf {
+        self.disk().partition(2)
+    }
+
+    pub fn updog(&self, args: &[&str]) -> Output {
+        self.updog_with_root(args, &self.trusted_root)
+    }
+
+    pub fn updog_with_root(&self, args: &[&str], trusted_root: &PathBuf) -> Output {
+        self.updog_command(args, trusted_root).output().unwrap()
+    }
+
+    /// Builds the command to run updog with the host's files, so tests can change how it runs.
+    pub fn updog_command(&self, args: &[&str], trusted_root: &PathBuf) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_updog"));
+        command
+            .args(args)
+            .arg("--config")
+            .arg(self.path("updog.toml"))
+            .arg("--trusted-root")
+            .arg(trusted_root)
+            .arg("--metadata-dir")
+            .arg(self.path("metadata"))
+            .arg("--migration-dir")
+            .arg(self.path("migrations"))
+            .arg("--os-release")
+            .arg(self.path("os-release"))
+            .arg("--status-file")
+            .arg(self.path("status.json"))
+            .arg("--history-file")
+            .arg(self.path("history.jsonl"))
+            .arg("--prepared-file")
+            .arg(self.path("prepared.json"))
+            .arg("--approval-file")
+            .arg(self.path("approval.json"))
+            .arg("--bundle-dir")
+            .arg(self.path("bundles"))
+            .arg("--lock-file")
+            .arg(self.path("lock"));
+        if self.disk.is_some() {
+            command
+                .arg("--active-partition")
+                .arg(self.active_partition());
+        }
+        command
+    }
+
+    /// The status updog saved from its last run.
+    pub fn status(&self) -> serde_json::Value {
+        serde_json::from_slice(&fs::read(self.path("status.json")).unwrap()).unwrap()
+    }
+
+    /// The statuses from updog's recent runs, oldest first.
+    pub fn history(&self) -> Vec<serde_json::Value> {
+        let output = self.updog(&["history", "--json"]);
+        assert_success(&output);
+        serde_json::from_slice(&output.stdout).unwrap()
+    }
+
+    pub fn partition_state(&self) -> String {
+        State::load_from_partition(self.active_partition())
+            .unwrap()
+            .to_string()
+    }
+}
+
+/// Runs updata, failing the test if it fails.
+pub fn updata(args: &[&OsStr]) {
+    assert_success(
+        &Command::new(env!("CARGO_BIN_EXE_updata"))
+            .args(args)
+            .output()
+            .unwrap(),
+    );
+}
+
+pub fn assert_success(output: &Output) {
+    assert!(
+        output.status.success(),
+        "command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+pub fn assert_partition_holds(disk: &Disk, num: u32, version: &str, kind: &str) {
+    assert!(
+        disk.read_partition(num, COMPARE_LEN) == image_data(version, kind)[..COMPARE_LEN],
+        "partition {} doesn't hold the {} image for {}",
+        num,
+        kind,
+        version
+    );
+}
+
+pub fn skip_without_network() -> bool {
+    if loopback_http_available() {
+        false
+    } else {
+        eprintln!("Skipping: can't reach a loopback HTTP server here");
+        true
+    }
+}
+
+pub fn skip_without_disk() -> bool {
+    if skip_without_network() {
+        true
+    } else if loop_devices_available() {
+        false
+    } else {
+        eprintln!("Skipping: can't set up partitioned loop devices here");
+        true
+    }
+}
@@ -0,0 +1,155 @@
+//! Runs updog end-to-end while things go wrong: the repository server drops connections, truncates
+//! or corrupts what it sends, or updog crashes partway through writing an update.  Whatever
+//! happens, the host must keep booting the set it's running, and a later update must succeed.
+//!
+//! These need updog built with the `fault-injection` feature:
+//!
+//!     cargo test -p updog --features fault-injection --test faults
+
+mod common;
+
+use common::*;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::thread;
+use std::time::Duration;
+use update_harness::Fault;
+
+/// Checks that an update that failed to write can't be applied, and that the host recovers once
+/// the repository behaves again.
+fn assert_recovers(host: &Host) {
+    assert!(!host.updog(&["update-apply"]).status.success());
+    assert!(host.partition_state().ends_with("Next:    Set A"));
+
+    host.server().clear_faults();
+    assert_success(&host.updog(&["update-image"]));
+    assert_partition_holds(host.disk(), 5, "0.2.0", "root");
+    assert_success(&host.updog(&["update-apply"]));
+    assert!(host.partition_state().ends_with("Next:    Set B"));
+}
+
+/// Writes the update while the server fails to serve the root image in the given way.
+fn update_with_image_fault(fault: Fault) {
+    if skip_without_disk() {
+        return;
+    }
+    let host = Host::with_disk("0.1.0");
+    host.server()
+        .inject(&format!("targets/{}", host.root_image()), fault);
+
+    assert!(!host.updog(&["update-image"]).status.success());
+    assert_eq!(host.status()["result"], "failed");
+    assert_recovers(&host);
+}
+
+#[test]
+fn metadata_unreachable() {
+    if skip_without_network() {
+        return;
+    }
+    let host = Host::new("0.1.0");
+    host.server()
+        .inject("metadata/timestamp.json", Fault::Disconnect);
+    assert!(!host.updog(&["check-update"]).status.success());
+    assert_eq!(host.status()["failure"], "network");
+
+    host.server().clear_faults();
+    assert_success(&host.updog(&["check-update"]));
+}
+
+#[test]
+fn metadata_corrupted() {
+    if skip_without_network() {
+        return;
+    }
+    let host = Host::new("0.1.0");
+    host.server()
+        .inject("metadata/targets.json", Fault::Corrupt);
+    assert!(!host.updog(&["check-update"]).status.success());
+
+    host.server().clear_faults();
+    assert_success(&host.updog(&["check-update"]));
+}
+
+#[test]
+fn manifest_missing() {
+    if skip_without_network() {
+        return;
+    }
+    let host = Host::new("0.1.0");
+    host.server()
+        .inject("targets/manifest.json", Fault::NotFound);
+    assert!(!host.updog(&["check-update"]).status.success());
+}
+
+#[test]
+fn shard_truncated() {
+    if skip_without_network() {
+        return;
+    }
+    // Without its shard, the host can't tell what updates there are.
+    let host = Host::sharded("0.1.0");
+    host.server().inject(
+        &format!("targets/manifest-{}.json", VARIANT),
+        Fault::Truncate,
+    );
+    assert!(!host.updog(&["check-update"]).status.success());
+    assert_eq!(host.status()["failure"], "metadata");
+
+    host.server().clear_faults();
+    assert_success(&host.updog(&["check-update"]));
+}
+
+#[test]
+fn image_unreachable() {
+    update_with_image_fault(Fault::Disconnect);
+}
+
+#[test]
+fn image_truncated() {
+    update_with_image_fault(Fault::Truncate);
+}
+
+#[test]
+fn image_corrupted() {
+    update_with_image_fault(Fault::Corrupt);
+}
+
+#[test]
+fn crash_while_writing_image() {
+    if skip_without_disk() {
+        return;
+    }
+    let host = Host::with_disk("0.1.0");
+
+    let output = host
+        .updog_command(&["update-image"], host.trusted_root())
+        .env("UPDOG_CRASH_WRITING", host.root_image())
+        .output()
+        .unwrap();
+    // A crash leaves no exit code, just the signal that killed updog.
+    assert_eq!(output.status.code(), None);
+    assert_recovers(&host);
+}
+
+#[test]
+fn cancel_while_writing_image() {
+    if skip_without_disk() {
+        return;
+    }
+    let host = Host::with_disk("0.1.0");
+    host.server()
+        .inject(&format!("targets/{}", host.root_image()), Fault::Slow);
+
+    let mut updog = host
+        .updog_command(&["update-image"], host.trusted_root())
+        .spawn()
+        .unwrap();
+    // The root image takes seconds to download at this speed, so updog is partway through it.
+    thread::sleep(Duration::from_secs(1));
+    #[allow(clippy::cast_possible_wrap)]
+    kill(Pid::from_raw(updog.id() as i32), Signal::SIGTERM).unwrap();
+    assert_eq!(updog.wait().unwrap().code(), Some(1));
+    assert_eq!(host.status()["result"], "cancelled");
+    assert_recovers(&host);
+}
@@ -0,0 +1,69 @@
+//! Drives updog as a library, the way the API server or an orchestration agent would, checking
+//! that results come back typed and are recorded like the command line records them.
+
+use tempfile::TempDir;
+use updog::{Arguments, CheckResult, Command, Error, FailureClass, Message, Status};
+
+/// Arguments for `command` on a host whose files are all under `dir`, none of which exist yet.
+fn arguments(dir: &TempDir, command: Command) -> Arguments {
+    let mut arguments = Arguments::new(command);
+    let paths = &mut arguments.paths;
+    paths.config = dir.path().join("updog.toml");
+    paths.status = dir.path().join("status.json");
+    paths.history = dir.path().join("history.jsonl");
+    paths.prepared = dir.path().join("prepared.json");
+    paths.approval = dir.path().join("approval.json");
+    paths.lock = dir.path().join("lock");
+    paths.os_release = dir.path().join("os-release");
+    arguments
+}
+
+#[test]
+fn commands_are_named_like_subcommands() {
+    assert_eq!(Command::CheckUpdate.to_string(), "check-update");
+    assert_eq!(
+        "apply-bundle".parse::<Command>().unwrap(),
+        Command::ApplyBundle
+    );
+    assert!("check".parse::<Command>().is_err());
+}
+
+#[test]
+fn failures_are_typed_and_recorded() {
+    let dir = TempDir::new().unwrap();
+    let arguments = arguments(&dir, Command::Prepare);
+
+    let run = updog::run(&arguments);
+    let err = run.as_ref().unwrap_err();
+    assert!(matches!(err, Error::ConfigRead { .. }), "{}", err);
+    assert_eq!(FailureClass::from(err), FailureClass::Config);
+
+    let status = updog::record(&arguments, &run).unwrap();
+    assert_eq!(status.command, "prepare");
+    assert_eq!(status.result, CheckResult::Failed);
+    assert_eq!(status.remediation_id, Some(Message::RemediationConfig));
+
+    let saved = Status::load(&arguments.paths.status).unwrap().unwrap();
+    assert_eq!(saved.failure, Some(FailureClass::Config));
+    assert!(arguments.paths.history.exists());
+}
+
+#[test]
+fn checks_of_updog_itself_are_not_recorded() {
+    let dir = TempDir::new().unwrap();
+    let arguments = arguments(&dir, Command::VerifyCurrent);
+
+    let run = updog::run(&arguments);
+    assert!(run.is_err());
+    assert!(updog::record(&arguments, &run).is_none());
+    assert!(Status::load(&arguments.paths.status).unwrap().is_none());
+}
+
+#[test]
+fn apply_bundle_needs_a_bundle() {
+    let dir = TempDir::new().unwrap();
+    let arguments = arguments(&dir, Command::ApplyBundle);
+
+    let err = updog::run(&arguments).unwrap_err();
+    assert!(matches!(err, Error::BundleMissing { .. }), "{}", err);
+}
@@ -0,0 +1,359 @@
+//! Runs updog end-to-end: checking for updates, and writing and applying them.  Tests skip
+//! themselves if the system can't give us a loop device or let updog reach the repository.
+
+mod common;
+
+use chrono::{Duration, Utc};
+use common::*;
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use std::fs;
+use update_harness::{image_data, Fault, Server};
+
+#[test]
+fn check_update() {
+    if skip_without_network() {
+        return;
+    }
+    let host = Host::new("0.1.0");
+    let output = host.updog(&["check-update", "--json"]);
+    assert_success(&output);
+    let update: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(update["version"], "0.2.0");
+    assert_eq!(update["variant"], VARIANT);
+    assert_eq!(host.status()["result"], "update-available");
+    assert_eq!(host.status()["update-version"], "0.2.0");
+
+    assert_success(&host.updog(&["check-update"]));
+    let history = host.history();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[1]["command"], "check-update");
+    assert_eq!(history[1]["result"], "update-available");
+}
+
+#[test]
+fn check_update_sharded() {
+    if skip_without_network() {
+        return;
+    }
+    let host = Host::sharded("0.1.0");
+    let output = host.updog(&["check-update", "--json"]);
+    assert_success(&output);
+    let update: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(update["version"], "0.2.0");
+}
+
+#[test]
+fn no_update_when_current() {
+    if skip_without_network() {
+        return;
+    }
+    let host = Host::new("0.2.0");
+    let output = host.updog(&["check-update"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No update available"));
+    assert_eq!(host.status()["result"], "no-update");
+}
+
+#[test]
+fn untrusted_repository() {
+    if skip_without_network() {
+        return;
+    }
+    let host = Host::new("0.1.0");
+    // Break the signature on the root.json we trust, so nothing from the repository verifies.
+    let mut root: serde_json::Value =
+        serde_json::from_slice(&fs::read(host.trusted_root()).unwrap()).unwrap();
+    root["signatures"][0]["sig"] = serde_json::json!("00".repeat(256));
+    let bad_root = host.path("bad-root.json");
+    fs::write(&bad_root, serde_json::to_vec(&root).unwrap()).unwrap();
+
+    let output = host.updog_with_root(&["check-update"], &bad_root);
+    assert!(!output.status.success());
+    assert_eq!(host.status()["result"], "failed");
+    assert_eq!(host.status()["failure"], "trust");
+    assert!(host.status()["remediation"].is_string());
+    assert!(host.status()["remediation-id"].is_string());
+}
+
+#[test]
+fn update_image_and_apply() {
+    if skip_without_disk() {
+        return;
+    }
+    let host = Host::with_disk("0.1.0");
+
+    let output = host.updog(&["update-image", "--json"]);
+    assert_success(&output);
+    assert_partition_holds(host.disk(), 4, "0.2.0", "boot");
+    assert_partition_holds(host.disk(), 5, "0.2.0", "root");
+    assert_partition_holds(host.disk(), 6, "0.2.0", "hash");
+    // The image is written, but set B isn't prioritized until the update is applied.
+    assert!(host.partition_state().ends_with("Next:    Set A"));
+    assert!(host.path("migrations").is_dir());
+    assert_eq!(host.status()["result"], "update-written");
+
+    let output = host.updog(&["update-apply"]);
+    assert_success(&output);
+    assert!(host.partition_state().ends_with("Next:    Set B"));
+    assert_eq!(host.status()["result"], "update-applied");
+}
+
+#[test]
+fn apply_bundle() {
+    if skip_without_disk() {
+        return;
+    }
+    let host = Host::with_disk("0.1.0");
+    let bundle = host.path("bundle.tar");
+    let repo = url::Url::from_directory_path(host.path("repo")).unwrap();
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+    let key = host.path("bundle-key.der");
+    fs::write(&key, pkcs8.as_ref()).unwrap();
+    let public_key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+        .unwrap()
+        .public_key()
+        .as_ref()
+        .to_vec();
+    updata(&[
+        "bundle".as_ref(),
+        "--repo".as_ref(),
+        repo.as_str().as_ref(),
+        "--trusted-root".as_ref(),
+        host.trusted_root().as_os_str(),
+        "--variant".as_ref(),
+        VARIANT.as_ref(),
+        "--arch".as_ref(),
+        std::env::consts::ARCH.as_ref(),
+        "--version".as_ref(),
+        "0.2.0".as_ref(),
+        "--key".as_ref(),
+        key.as_os_str(),
+        "--output".as_ref(),
+        bundle.as_os_str(),
+    ]);
+
+    // The host can't reach the repository; everything comes from the bundle, which has to be
+    // signed by the host's bundle key.
+    fs::write(
+        host.path("updog.toml"),
+        format!(
+            "metadata_base_url = \"http://127.0.0.1:9/metadata/\"\n\
+             targets_base_url = \"http://127.0.0.1:9/targets/\"\n\
+             seed = 1234\n\
+             bundle_keys = [\"{}\"]\n",
+            base64::encode(&public_key)
+        ),
+    )
+    .unwrap();
+    let output = host.updog(&["apply-bundle", bundle.to_str().unwrap()]);
+    assert_success(&output);
+    assert_partition_holds(host.disk(), 5, "0.2.0", "root");
+    assert!(host.partition_state().ends_with("Next:    Set B"));
+    assert_eq!(host.status()["result"], "update-applied");
+    assert_eq!(host.status()["update-version"], "0.2.0");
+    assert_eq!(fs::read_dir(host.path("bundles")).unwrap().count(), 0);
+}
+
+#[test]
+fn update_image_from_target_cache() {
+    if skip_without_disk() {
+        return;
+    }
+    let host = Host::with_disk("0.1.0");
+    let cache = Server::serve(host.path("repo"));
+    host.add_config(&format!("target_cache_url = \"{}/targets/\"", cache.url()));
+    let root_image = format!("targets/{}", host.root_image());
+
+    // The root image only comes from the cache.
+    host.server().inject(&root_image, Fault::NotFound);
+    assert_success(&host.updog(&["update-image"]));
+    assert_partition_holds(host.disk(), 5, "0.2.0", "root");
+
+    // A corrupt image from the cache is replaced with the repository's.
+    host.server().clear_faults();
+    cache.inject(&root_image, Fault::Corrupt);
+    assert_success(&host.updog(&["update-image"]));
+    assert_partition_holds(host.disk(), 5, "0.2.0", "root");
+
+    // Without either, the update fails.
+    host.server().inject(&root_image, Fault::NotFound);
+    assert!(!host.updog(&["update-image"]).status.success());
+    assert_eq!(host.status()["failure"], "network");
+}
+
+#[test]
+fn prepare_and_activate() {
+    if skip_without_disk() {
+        return;
+    }
+    let host = Host::with_disk("0.1.0");
+
+    // Nothing to activate yet.
+    assert!(!host.updog(&["activate"]).status.success());
+
+    let output = host.updog(&["prepare"]);
+    assert_success(&output);
+    assert_partition_holds(host.disk(), 5, "0.2.0", "root");
+    assert!(host.partition_state().ends_with("Next:    Set A"));
+    assert_eq!(host.status()["result"], "update-prepared");
+    assert_eq!(host.status()["update-version"], "0.2.0");
+
+    // Preparing again doesn't write the images again.
+    host.disk().write_partition(5, b"untouched");
+    let output = host.updog(&["prepare"]);
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("already prepared"));
+    assert_eq!(host.disk().read_partition(5, 9), b"untouched");
+
+    // The barrier is kept for later runs, even ones that don't give it.
+    let output = host.updog(&["activate", "--not-before", "2999-01-01T00:00:00Z"]);
+    assert_success(&output);
+    assert_eq!(host.status()["result"], "activation-scheduled");
+    let output = host.updog(&["activate"]);
+    assert_success(&output);
+    assert_eq!(host.status()["result"], "activation-scheduled");
+    assert!(host.partition_state().ends_with("Next:    Set A"));
+
+    for _ in 0..2 {
+        let output = host.updog(&["activate", "--not-before", "2000-01-01T00:00:00Z"]);
+        assert_success(&output);
+        assert!(host.partition_state().ends_with("Next:    Set B"));
+        assert_eq!(host.status()["result"], "update-activated");
+        assert_eq!(host.status()["update-version"], "0.2.0");
+    }
+}
+
+#[test]
+fn auto_update() {
+    if skip_without_disk() {
+        return;
+    }
+    let host = Host::with_disk("0.1.0");
+
+    // Nothing happens, or is saved, without a policy.
+    assert_success(&host.updog(&["auto"]));
+    assert!(host.history().is_empty());
+    assert!(host.partition_state().ends_with("Next:    Set A"));
+
+    // With a reboot window that hasn't started, the update is only prepared.
+    host.add_config("auto = \"apply-and-reboot-in-window\"");
+    let later = Utc::now() + Duration::hours(2);
+    host.add_config(&format!(
+        "reboot_window = \"{}-{}\"",
+        later.format("%H:00"),
+        (later + Duration::hours(1)).format("%H:00")
+    ));
+    assert_success(&host.updog(&["auto"]));
+    assert_eq!(host.status()["command"], "auto");
+    assert_eq!(host.status()["result"], "update-prepared");
+    assert_partition_holds(host.disk(), 5, "0.2.0", "root");
+    assert!(host.partition_state().ends_with("Next:    Set A"));
+}
+
+#[test]
+fn auto_update_apply() {
+    if skip_without_disk() {
+        return;
+    }
+    let host = Host::with_disk("0.1.0");
+    host.add_config("auto = \"apply\"");
+    assert_success(&host.updog(&["auto"]));
+    assert_eq!(host.status()["result"], "update-activated");
+    assert!(host.partition_state().ends_with("Next:    Set B"));
+
+    // Once it's activated, the update isn't written again while it waits for a reboot.
+    host.disk().write_partition(5, b"untouched");
+    assert_success(&host.updog(&["auto"]));
+    assert_eq!(host.status()["result"], "update-activated");
+    assert_eq!(host.disk().read_partition(5, 9), b"untouched");
+    assert!(host.partition_state().ends_with("Next:    Set B"));
+}
+
+#[test]
+fn update_image_when_current() {
+    if skip_without_disk() {
+        return;
+    }
+    let host = Host::with_disk("0.2.0");
+    host.disk().write_partition(5, b"untouched");
+
+    let output = host.updog(&["update-image"]);
+    assert_success(&output);
+    assert_eq!(host.disk().read_partition(5, 9), b"untouched");
+    assert!(host.partition_state().ends_with("Next:    Set A"));
+}
+
+#[test]
+fn verify_current() {
+    if skip_without_disk() {
+        return;
+    }
+    let host = Host::with_disk("0.2.0");
+    // Set A, which the host is running from, holds 0.2.0's images.
+    host.disk().write_partition(1, &image_data("0.2.0", "boot"));
+    host.disk().write_partition(2, &image_data("0.2.0", "root"));
+    host.disk().write_partition(3, &image_data("0.2.0", "hash"));
+
+    let output = host.updog(&["verify-current", "--all"]);
+    assert_success(&output);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("DRIFT"));
+
+    host.disk().write_partition(3, b"tampered");
+    let output = host.updog(&["verify-current"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("DRIFT hash"));
+}
+
+/// Runs the doctor, returning whether it passed and the health of each check by name.
+fn doctor(host: &Host) -> (bool, serde_json::Map<String, serde_json::Value>) {
+    let output = host.updog(&["doctor", "--json"]);
+    let checks: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    let health = checks
+        .into_iter()
+        .map(|check| {
+            (
+                check["name"].as_str().unwrap().to_string(),
+                check["health"].clone(),
+            )
+        })
+        .collect();
+    (output.status.success(), health)
+}
+
+#[test]
+fn doctor_finds_pending_update() {
+    if skip_without_disk() {
+        return;
+    }
+    let host = Host::with_disk("0.1.0");
+    let (passed, health) = doctor(&host);
+    assert!(passed);
+    assert_eq!(health["repository"], "ok");
+    assert_eq!(health["partitions"], "ok");
+    assert_eq!(health["pending-update"], "ok");
+
+    assert_success(&host.updog(&["update"]));
+    let (passed, health) = doctor(&host);
+    assert!(passed);
+    assert_eq!(health["pending-update"], "warn");
+}
+
+#[test]
+fn doctor_finds_bad_config() {
+    if skip_without_network() {
+        return;
+    }
+    let host = Host::new("0.1.0");
+    fs::write(host.path("updog.toml"), "seed = \"not a number\"\n").unwrap();
+
+    let (passed, health) = doctor(&host);
+    assert!(!passed);
+    assert_eq!(health["config"], "fail");
+    assert_eq!(health["release"], "ok");
+    assert_eq!(health["trusted-root"], "ok");
+    assert_eq!(health["repository"], "skip");
+    // The doctor's checks aren't an update run, so they're not saved as updog's status.
+    assert!(!host.path("status.json").exists());
+    assert!(host.history().is_empty());
+}